@@ -0,0 +1,82 @@
+// Example bot built on the headless ChatClient
+// Welcomes everyone who lands and repeats whatever follows "!echo ". It stays quiet until the
+// history replay is over (the user count frame comes right after it) so old messages don't
+// set it off, and it reconnects the same way the TUI does when the connection drops.
+//
+// cargo run --example greeter_bot -- [--addr 127.0.0.1:8082] [--name GreeterBot] [--password <server password>]
+
+use std::env;
+use std::error::Error;
+
+use retro_chat_project::client::connection::{ChatClient, DEFAULT_ADDR};
+use retro_chat_project::client::reconnect::{ReconnectAction, ReconnectPolicy};
+use retro_chat_project::protocol::{ChatMessage, Handshake, MessageType};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut handshake = Handshake { username: "GreeterBot".to_string(), ..Default::default() };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().ok_or("--addr needs a value")?,
+            "--name" => handshake.username = args.next().ok_or("--name needs a value")?,
+            "--password" => handshake.password = Some(args.next().ok_or("--password needs a value")?),
+            other => return Err(format!("unknown argument: {}", other).into()),
+        }
+    }
+
+    let mut client = ChatClient::connect(&addr, handshake).await?;
+    println!("🤖 Connected to {} as {}", addr, client.handshake().username);
+    let mut policy = ReconnectPolicy::default();
+    loop {
+        let rejection = run(&mut client, &mut policy).await;
+        loop {
+            match policy.next_action(rejection.as_ref()) {
+                ReconnectAction::Retry(delay) => {
+                    println!("🤖 Connection lost, reconnecting in {}s", delay.as_secs().max(1));
+                    tokio::time::sleep(delay).await;
+                    if client.reconnect().await.is_ok() {
+                        break;
+                    }
+                }
+                ReconnectAction::PromptPassword => return Err("the server wants a password, pass --password".into()),
+                ReconnectAction::GiveUp(reason) => return Err(format!("disconnected: {}", reason).into()),
+            }
+        }
+    }
+}
+
+// Answers frames until the connection drops, returns the error frame that explains why (if any)
+async fn run(client: &mut ChatClient, policy: &mut ReconnectPolicy) -> Option<ChatMessage> {
+    let mut caught_up = false;
+    let mut rejection = None;
+    while let Some(msg) = client.recv().await {
+        if msg.message_type == MessageType::Error || msg.error.is_some() {
+            rejection = Some(msg);
+            continue;
+        }
+        rejection = None;
+        policy.connected();
+
+        let me = client.handshake().username.clone();
+        let reply = match msg.message_type {
+            MessageType::UserCount => {
+                caught_up = true;
+                None
+            }
+            _ if !caught_up || msg.username == me => None,
+            MessageType::SystemNotification if msg.content == "has landed" => {
+                Some(format!("Welcome aboard, {}! 🚀", msg.username))
+            }
+            MessageType::UserMessage => msg.content.strip_prefix("!echo ").map(str::to_string),
+            _ => None,
+        };
+        if let Some(reply) = reply
+            && let Err(e) = client.send(&reply).await
+        {
+            eprintln!("🤖 Could not send: {}", e);
+        }
+    }
+    rejection
+}
@@ -1,310 +1,679 @@
 // Importing from cursive library to create a UI 
-use cursive::{
-    align::HAlign, event::Key, theme::{BaseColor, BorderStyle, Color, ColorStyle, Palette, PaletteColor, Theme}, traits::*, utils::markup::StyledString, views::{Dialog, DummyView, EditView, LinearLayout, Panel, ScrollView, TextView}, Cursive // Main Cursive application object
-};
-
-// Importing Serde for serialization and deserialization for JSON handling 
-use serde::{Deserialize, Serialize};
+use cursive::{Cursive, CursiveRunnable}; // Main Cursive application object, and one that runs on the terminal
 
 //imporitng models for error handling and shared ownership of data 
-use std::{env, error::Error, sync::Arc};
-
-// Importing Tokio async utilities
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, 
-    net::TcpStream, 
-    sync::Mutex, 
-};
-
-
-// Chrono for date and time 
-use chrono::Local;
-
-// Structutre of a chat message 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChatMessage {
-    username: String, 
-    content: String, 
-    timestamp: String, 
-    message_type: MessageType, 
+use std::{collections::BTreeMap, env, error::Error, path::PathBuf, sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
+
+use tokio::task::JoinHandle;
+
+// Chat message frames are shared with the server through the protocol module,
+// the connection itself is the headless ChatClient bots use too
+use retro_chat_project::protocol::{ChatMessage, Handshake, MessageType};
+use retro_chat_project::client::connection::{check_addr, ChatClient, Transport, DEFAULT_ADDR, UNIX_PREFIX};
+use retro_chat_project::client::chatlog::ChatLog;
+use retro_chat_project::client::e2e::E2e;
+use retro_chat_project::client::signing::Signer;
+use retro_chat_project::client::socks::Proxy;
+use retro_chat_project::client::emoji::Emoji;
+use retro_chat_project::client::ignore::IgnoreList;
+use retro_chat_project::client::keymap::Keymap;
+use retro_chat_project::client::markup::Markup;
+use retro_chat_project::client::outbox::Outbox;
+use retro_chat_project::client::notify::Notifier;
+use retro_chat_project::client::settings::{Profile, Settings, SettingsFile, Times, DEFAULT_THEME};
+use retro_chat_project::client::reconnect::{ReconnectAction, ReconnectPolicy};
+use retro_chat_project::client::session::Sessions;
+use retro_chat_project::client::{config_dir, themes, ui};
+use retro_chat_project::client::ui::{Connection, ToastKind, UnsentChoice};
+use retro_chat_project::client::writer::{Outgoing, Report, Writer};
+use retro_chat_project::trace::WireTracer;
+
+// How often the round trip in the status bar is measured again
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// How often the UI is asked for drafts and read marks that are due to go out
+const DRAFT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// Client-side state the UI callbacks and the connection tasks share
+#[derive(Clone)]
+struct Shared {
+    // Emoji, markup, the ignore list and the rest the UI works with
+    ui: ui::Context,
+    // The server we're on, /connect puts another one in
+    server: Arc<RwLock<Arc<Server>>>,
+    // Everything we send goes through it, whichever connection is up
+    writer: Writer,
+    // The task reading from the server, stopped when /connect moves us to another one
+    connection: Arc<Mutex<Option<JoinHandle<()>>>>,
+    // Our whisper key pair and everyone else's public keys
+    e2e: Arc<E2e>,
+    // Signs what we send, None when sign_messages is off
+    signer: Option<Arc<Signer>>,
+    // Session tokens and saved servers' passwords go in the OS keychain unless --no-keyring
+    keyring: bool,
+}
+
+impl Shared {
+    fn server(&self) -> Arc<Server> {
+        self.server.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
 }
 
-// Enum to represent different types of messages
-#[derive(Debug, Clone, Serialize, Deserialize)]
-enum MessageType {
-    UserMessage, 
-    SystemNotification, 
+// Where the server is (host:port, quic://host:port or unix:<path>) and how to get there (--proxy, --quic-cert),
+// for reconnecting with a new password too, who we are there and the lines that haven't gone out to it
+struct Server {
+    addr: String,
+    transport: Transport,
+    username: String,
+    outbox: Arc<Outbox>,
+    // One of the servers in config.toml, whose password is kept once it gets us in
+    saved: bool,
+}
+
+impl Server {
+    fn new(addr: String, transport: Transport, username: String, saved: bool) -> Arc<Server> {
+        let outbox = Arc::new(Outbox::open(&addr, &username));
+        Arc::new(Server { addr, transport, username, outbox, saved })
+    }
 }
 
 // Main asynchronous function to run the chat client
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Creates username from command line argument, and orders user to give one if they fail to do so
-    let username = env::args()
-        .nth(1) 
-        .expect("Please provide a username as an argument"); 
+    // Preferences from config.toml first, they fill in what the command line leaves out
+    let mut load_errors = Vec::new();
+    let settings = Settings::load().unwrap_or_else(|e| {
+        load_errors.push(format!("Settings not loaded: {}", e));
+        Settings::default()
+    });
 
-    // UI framework initialized 
+    // Creates username from command line argument (or username in config.toml), the connect dialog asks for one if neither is there
+    // An optional "--password <password>" can follow for password protected servers,
+    // "--trace-wire <file>" logs every frame for protocol debugging,
+    // "--proxy socks5://host:port" connects through a SOCKS5 proxy (Tor's is socks5://127.0.0.1:9050),
+    // "--server tcp://host:port" or "--server quic://host:port" picks the server and the way to it (QUIC needs the
+    // quic feature, "--quic-cert <pem>" trusts a server's own certificate),
+    // "--unix-socket <path>" connects to a server on this machine through its Unix socket
+    // and "--no-keyring" keeps session tokens in a file rather than the OS keychain
+    let mut args = env::args().skip(1).peekable();
+    let named = args.peek().is_some_and(|first| !first.starts_with("--"));
+    let mut username = if named { args.next() } else { settings.username.clone() };
+    let mut server_given = false;
+    let mut password = None;
+    let mut trace_wire = None;
+    let mut keyring = true;
+    let mut transport = Transport::default();
+    // Address of the chat server, server in config.toml or the default unless given here
+    let mut server_addr = settings.server.clone().unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--password" => password = Some(args.next().expect("--password needs a value")),
+            "--trace-wire" => trace_wire = Some(args.next().expect("--trace-wire needs a file path")),
+            "--proxy" => match Proxy::parse(&args.next().expect("--proxy needs a socks5://host:port URL")) {
+                Ok(parsed) => transport.proxy = Some(parsed),
+                Err(e) => panic!("{}", e),
+            },
+            "--server" => {
+                server_addr = args.next().expect("--server needs a tcp://host:port or quic://host:port URL");
+                server_given = true;
+            }
+            "--quic-cert" => transport.quic_cert = Some(PathBuf::from(args.next().expect("--quic-cert needs a PEM file"))),
+            "--unix-socket" => {
+                server_addr = format!("{}{}", UNIX_PREFIX, args.next().expect("--unix-socket needs a path"));
+                server_given = true;
+            }
+            "--no-keyring" => keyring = false,
+            other => panic!("Unknown argument: {}", other),
+        }
+    }
+    let password_from_cli = password.clone();
+
+    // Passwords config.toml still keeps for saved servers move to the keychain (sessions.json with --no-keyring)
+    let sessions = Sessions::user(keyring);
+    for (name, old) in SettingsFile::user().old_passwords() {
+        let Some(profile) = settings.servers.get(&name) else { continue };
+        let moved = sessions.save_password(&profile.address, &old).map_err(|e| e.to_string());
+        if let Err(e) = moved.and_then(|()| SettingsFile::user().forget_password(&name)) {
+            load_errors.push(format!("Couldn't move the password for {} out of config.toml: {}", name, e));
+        }
+    }
+    let transport_from_cli = transport.clone();
+
+    // Wire tracing can also be switched on later with "/debug wire on"
+    let tracer = Arc::new(WireTracer::new(
+        trace_wire.clone().map(PathBuf::from).unwrap_or_else(default_trace_file),
+        trace_wire.is_some(),
+    ));
+
+    // The bundled :shortcodes: plus the user's own from emoji.toml
+    let emoji = Emoji::load().unwrap_or_else(|e| {
+        load_errors.push(format!("Custom emoji not loaded: {}", e));
+        Emoji::bundled()
+    });
+    emoji.set_enabled(settings.emojify);
+    let e2e = Arc::new(E2e::load().unwrap_or_else(|e| {
+        load_errors.push(format!("Whisper key not loaded, using one for this run only: {}", e));
+        E2e::generate()
+    }));
+    let signer = settings.sign_messages.then(|| {
+        Arc::new(Signer::load().unwrap_or_else(|e| {
+            load_errors.push(format!("Signing key not loaded, using one for this run only: {}", e));
+            Signer::generate()
+        }))
+    });
+
+    let markup = Markup::new();
+    markup.set_absolute_times(settings.times == Times::Absolute);
+    let keymap = Keymap::new(&settings.keys).unwrap_or_else(|e| {
+        load_errors.push(format!("Keys not loaded, using the defaults: {}", e));
+        Keymap::default()
+    });
+
+    // UI framework initialized, see client::ui for the layout
     let mut siv = cursive::default();
-    siv.set_theme(create_space_theme()); //"space" theme for chat room 
-
-    // Header of the chat room 
-    let header = TextView::new(format!(r#"== CHATBOX == Chatterer: {} == {} =="#,
-        username, 
-        Local::now().format("%D:%H:%M:%S") 
-    ))
-    .style(Color::Light(BaseColor::Cyan)) 
-    .h_align(HAlign::Center); 
-
-    // Message area that is scrollable 
-    let messages = TextView::new("") 
-        .with_name("messages") 
-        .min_height(50) 
-        .scrollable(); 
-
-
-
-    // Setting up the scroll view for messages
-
-    let messages = ScrollView::new(messages)
-        .scroll_strategy(cursive::view::ScrollStrategy::StickToTop) // Keep the scroll at the bottom 
-        .min_width(30) 
-        .full_width(); 
-
-    // Creating an input area for typing messages
-    let input = EditView::new()
-        .on_submit(move |s, text| send_message(s, text.to_string())) 
-        .with_name("input") 
-        .min_width(50) 
-        .max_height(5) 
-        .full_width(); 
-
-    // Creating help text for user commands
-    let help_text = TextView::new("Ctrl+C:quit | Enter:send | Commands: /help, /clear, /quit, /funface")
-        .style(Color::Dark(BaseColor::Green));
-
-    // Creating the main layout of the chat application
-    let layout = LinearLayout::vertical()
-        .child(Panel::new(header))
-        .child(
-            Dialog::around(messages) 
-                .title("Chattering") // Title 
-                .title_position(HAlign::Center) // Center-align 
-                .full_width()
-        )
-        .child( 
-            Dialog::around(input) 
-                .title("Chit Chat") 
-                .title_position(HAlign::Left) 
-                .full_width()
-        )
-        .child(Panel::new(help_text).full_width()); 
-
-    // Wrapping layout for centering
-    let centered_layout = LinearLayout::horizontal()
-        .child(DummyView.full_width()) 
-        .child(layout)
-        .child(DummyView.full_width());
-
-    // Adding the centered layout to the Cursive root
-    siv.add_fullscreen_layer(centered_layout);
-
-    // Adding global key bindings
-    siv.add_global_callback(Key::Esc, |s| s.quit()); 
-    siv.add_global_callback('/', |s| {
-        s.call_on_name("input", |view: &mut EditView| {
-            view.set_content("/"); 
-        });
+    let theme = themes::load(&settings.theme).unwrap_or_else(|e| {
+        load_errors.push(format!("Couldn't load the theme, {}", e));
+        themes::built_in(DEFAULT_THEME).unwrap_or_default()
     });
+    siv.set_theme(theme.clone());
 
     // Establishing a connection to the chat server, inbound to port 8082
     // This is where the client connects to the server
-    let stream = TcpStream::connect("127.0.0.1:8082").await?;
-    let (reader, mut writer) = stream.into_split(); 
+    // A session token saved by an earlier run signs us straight back in
+    // When the command line doesn't say who or where, the servers saved in config.toml are offered first.
+    // With no username from anywhere (started from a launcher, say), a dialog asks for it and the server.
+    // Either is asked again for as long as connecting fails
+    let picking = !named && !server_given && !settings.servers.is_empty();
+    let mut asking = !picking && username.is_none();
+    let mut typed = String::new();
+    let mut error = None;
+    let mut saved = false;
+    let (username, client) = loop {
+        if picking {
+            match pick_server(&mut siv, &settings.servers, error.take()) {
+                Some(Some(name)) => {
+                    let profile = &settings.servers[&name];
+                    server_addr = profile.address.clone();
+                    username = profile.username.clone().or_else(|| settings.username.clone());
+                    password = sessions.load_password(&profile.address).or_else(|| password_from_cli.clone());
+                    asking = username.is_none();
+                    saved = true;
+                    match transport_for(profile, &transport_from_cli) {
+                        Ok(picked) => transport = picked,
+                        Err(e) => {
+                            error = Some(format!("Can't connect to {}: {}", name, e));
+                            continue;
+                        }
+                    }
+                }
+                Some(None) => asking = true, // Another server, the dialog asks which
+                None => return Ok(()),      // Quit instead
+            }
+        }
+        if asking {
+            match ask_where_to(&mut siv, &typed, &server_addr, error.take()) {
+                Some((name, server)) => {
+                    saved = saved && server == server_addr;
+                    server_addr = server;
+                    username = Some(name);
+                }
+                None => return Ok(()), // Quit instead
+            }
+        }
+        let name = username.take().ok_or("Please provide a username as an argument, or set username in config.toml")?;
+        let session_token = sessions.load_token(&server_addr, &name);
+        let public_key = Some(e2e.public_key());
+        let signing_key = signer.as_ref().map(|signer| signer.public_key());
+        let handshake = Handshake { username: name.clone(), password: password.clone(), session_token, public_key, signing_key, ..Default::default() };
+        match ChatClient::connect_via(&server_addr, transport.clone(), handshake, tracer.clone()).await {
+            Ok(client) => break (name, client),
+            Err(e) if picking || asking => {
+                error = Some(format!("Couldn't connect to {}: {}", server_addr, e));
+                typed = name; // filled in when we ask again
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    // What the dialog was told is remembered, so the next start goes straight in
+    if asking {
+        for (key, value) in [("username", &username), ("server", &server_addr)] {
+            if let Err(e) = SettingsFile::user().save(key, value.as_str()) {
+                load_errors.push(format!("Couldn't keep the {} in config.toml: {}", key, e));
+            }
+        }
+    }
+    let password_given = password.is_some();
+
+    // Lines that didn't go out last time are still in the outbox
+    let server = Arc::new(RwLock::new(Server::new(server_addr, transport, username.clone(), saved)));
+    let writer = Writer::spawn(report_to(siv.cb_sink().clone(), server.clone()));
+
+    let shared = Shared {
+        ui: ui::Context {
+            tracer,
+            emoji: Arc::new(emoji),
+            markup: Arc::new(markup),
+            ignore: Arc::new(IgnoreList::load(&settings)),
+            notifier: Arc::new(Notifier::new(&username, &settings.notifications)),
+            settings: SettingsFile::user(),
+            keymap: Arc::new(keymap),
+            server_commands: Arc::default(),
+            chat_log: Arc::new(ChatLog::new(settings.log.dir(), settings.log.enabled)),
+        },
+        server,
+        writer,
+        connection: Arc::new(Mutex::new(None)),
+        e2e,
+        signer,
+        keyring,
+    };
+
+    ui::build(&mut siv, &username, &shared.ui, {
+        let shared = shared.clone();
+        move |s, text| send_message(s, text, &shared)
+    });
+    siv.set_theme(theme);
+    for error in load_errors {
+        ui::toast(&mut siv, ToastKind::Error, &error);
+    }
 
-    writer.write_all(format!("{}\n", username).as_bytes()).await?; 
+    connected(&shared, &client);
+    ui::set_connection(&mut siv, Connection::Connected);
+    offer_unsent(&mut siv, &shared);
 
-    let writer = Arc::new(Mutex::new(writer)); 
-    let writer_clone = Arc::clone(&writer); // Clone writer for later use
-    siv.set_user_data(writer); // Store writer in the Cursive app data
+    // Spawn an async task to handle incoming messages (and reconnects)
+    spawn_connection(client, siv.cb_sink().clone(), shared.clone(), password_given);
 
-    let reader = BufReader::new(reader); // Create a buffered reader for the stream
-    let mut lines = reader.lines(); // Create an iterator over the lines of the stream
-    let sink = siv.cb_sink().clone(); // Get a callback sink to update the UI
+    siv.run(); // Run cursive events 
+    shared.writer.close().await;
+    Ok(()) 
+}
 
-    // Spawn an async task to handle incoming messages
+// Shows the connect dialog on its own and waits for it: who to sign in as and where, or None for Quit
+fn ask_where_to(siv: &mut CursiveRunnable, username: &str, server: &str, error: Option<String>) -> Option<(String, String)> {
+    let answer = Arc::new(Mutex::new(None));
+    ui::show_connect_dialog(siv, username, server, error.as_deref(), {
+        let answer = answer.clone();
+        move |s, username, server| {
+            *answer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((username.to_string(), server.to_string()));
+            s.quit();
+        }
+    });
+    siv.run();
+    answer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take()
+}
+
+// Offers the saved servers on their own and waits: Some(Some(name)) for one of them,
+// Some(None) for another server and None for Quit
+fn pick_server(siv: &mut CursiveRunnable, servers: &BTreeMap<String, Profile>, error: Option<String>) -> Option<Option<String>> {
+    let picked = Arc::new(Mutex::new(None));
+    ui::show_server_picker(siv, servers, error.as_deref(), true, {
+        let picked = picked.clone();
+        move |s, name| {
+            *picked.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(name.map(str::to_string));
+            s.quit();
+        }
+    });
+    siv.run();
+    picked.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take()
+}
+
+// How to get to a saved server: its proxy and certificate, else those from the command line
+fn transport_for(profile: &Profile, from_cli: &Transport) -> Result<Transport, String> {
+    check_addr(&profile.address)?;
+    let proxy = match &profile.proxy {
+        Some(url) => Some(Proxy::parse(url).map_err(|e| e.to_string())?),
+        None => from_cli.proxy.clone(),
+    };
+    Ok(Transport { proxy, quic_cert: profile.quic_cert.clone().or_else(|| from_cli.quic_cert.clone()) })
+}
+
+// "/connect <name>": connects to the saved server in the background, and only once that worked lets go of the
+// one we're on and starts over in the window. Until then (and if it fails) we stay where we are
+fn switch_server(siv: &mut Cursive, name: &str, shared: &Shared) {
+    let settings = match shared.ui.settings.load() {
+        Ok(settings) => settings,
+        Err(e) => return ui::toast(siv, ToastKind::Error, &format!("Settings not loaded: {}", e)),
+    };
+    let Some(profile) = settings.servers.get(name) else {
+        let saved: Vec<&str> = settings.servers.keys().map(String::as_str).collect();
+        let hint = if saved.is_empty() { "there are none in config.toml".to_string() } else { format!("try {}", saved.join(", ")) };
+        return ui::toast(siv, ToastKind::Error, &format!("There's no saved server called {:?}, {}", name, hint));
+    };
+    let current = shared.server();
+    let transport = match transport_for(profile, &current.transport) {
+        Ok(transport) => transport,
+        Err(e) => return ui::toast(siv, ToastKind::Error, &format!("Can't connect to {}: {}", name, e)),
+    };
+    let username = profile.username.clone().or(settings.username).unwrap_or_else(|| current.username.clone());
+    let addr = profile.address.clone();
+    let password = Sessions::user(shared.keyring).load_password(&addr);
+    let handshake = Handshake {
+        username: username.clone(),
+        password: password.clone(),
+        session_token: Sessions::user(shared.keyring).load_token(&addr, &username),
+        public_key: Some(shared.e2e.public_key()),
+        signing_key: shared.signer.as_ref().map(|signer| signer.public_key()),
+        ..Default::default()
+    };
+    ui::toast(siv, ToastKind::Info, &format!("Connecting to {} ({})...", name, addr));
+
+    let (sink, shared, name) = (siv.cb_sink().clone(), shared.clone(), name.to_string());
     tokio::spawn(async move {
-        while let Ok(Some(line)) = lines.next_line().await {
-            if let Ok(msg) = serde_json::from_str::<ChatMessage>(&line) {
-                // Format incoming message based on type
-                let formatted_msg = match msg.message_type {
-    MessageType::UserMessage => {
-        let mut styled = StyledString::plain(format!("┌─[{}]\n└─ ", msg.timestamp));
-        styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
-        styled.append_plain(format!(" --> {}\n", msg.content));
-        styled
-    }
-    MessageType::SystemNotification => {
-        let mut styled = StyledString::plain("\n[");
-        styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
-        styled.append_plain(format!(" {}]\n", msg.content));
-        styled
-    }
-};
-                // Update UI with the new message
-                if sink.send(Box::new(move |siv: &mut Cursive| {
-                    siv.call_on_name("messages", |view: &mut TextView| {
-                        view.append(formatted_msg); // Append the message
-                    });
-                })).is_err() {
-                    break; 
-                }
+        let client = match ChatClient::connect_via(&addr, transport.clone(), handshake, shared.ui.tracer.clone()).await {
+            Ok(client) => client,
+            Err(e) => {
+                toast(&sink, ToastKind::Error, &format!("Couldn't connect to {}, still on {}: {}", name, current.addr, e));
+                return;
             }
+        };
+        // The old connection goes quietly, without the reconnects a dropped one would get
+        if let Some(task) = shared.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            task.abort();
         }
+        shared.writer.close().await;
+        connected(&shared, &client);
+        *shared.server.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Server::new(addr, transport, username.clone(), true);
+        shared.ui.notifier.set_name(&username);
+        let offered = shared.clone();
+        let _ = sink.send(Box::new(move |siv: &mut Cursive| {
+            // The new server sends its own commands, if it knows to
+            offered.ui.server_commands.write().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+            ui::switch_server(siv, &username, &name);
+            ui::set_connection(siv, Connection::Connected);
+            offer_unsent(siv, &offered);
+        }));
+        spawn_connection(client, sink, shared, password.is_some());
     });
+}
 
-    siv.run(); // Run cursive events 
-    let _ = writer_clone.lock().await.shutdown().await; 
-    Ok(()) 
+// Reads frames from the server into the message view and reconnects whenever the connection drops
+// How long to wait (or whether to retry at all) depends on the rejection the server sent, if any
+// The client's handshake follows the session token, newest message id and rooms, so a reconnect resumes where we left off
+fn spawn_connection(
+    mut client: ChatClient,
+    sink: cursive::CbSink,
+    shared: Shared,
+    password_given: bool,
+) {
+    let connection = shared.connection.clone();
+    let task = tokio::spawn(async move {
+        let mut policy = ReconnectPolicy::default();
+        loop {
+            let rejection = read_frames(&mut client, &sink, &mut policy, &shared).await;
+            shared.writer.disconnected();
+            let mut action = policy.next_action(rejection.as_ref());
+            set_connection(&sink, Connection::Disconnected);
+
+            loop {
+                match action {
+                    ReconnectAction::PromptPassword if !password_given => {
+                        let handshake = client.handshake().clone();
+                        let shared = shared.clone();
+                        let _ = sink.send(Box::new(move |siv: &mut Cursive| show_password_dialog(siv, handshake, shared)));
+                        return;
+                    }
+                    ReconnectAction::PromptPassword => {
+                        notify(&sink, "Disconnected: the password given with --password (or saved with the server) was rejected");
+                        return;
+                    }
+                    ReconnectAction::GiveUp(reason) => {
+                        notify(&sink, &format!("Disconnected: {}", reason));
+                        return;
+                    }
+                    ReconnectAction::Retry(delay) => {
+                        if !toast(&sink, ToastKind::Error, &format!("Connection lost, reconnecting in {}s...", delay.as_secs().max(1))) {
+                            return; // UI is gone
+                        }
+                        set_connection(&sink, Connection::Reconnecting);
+                        tokio::time::sleep(delay).await;
+                        match client.reconnect().await {
+                            Ok(()) => {
+                                connected(&shared, &client);
+                                let offered = shared.clone();
+                                let _ = sink.send(Box::new(move |siv: &mut Cursive| {
+                                    ui::set_connection(siv, Connection::Connected);
+                                    offer_unsent(siv, &offered); // Whatever failed while we were away
+                                }));
+                                toast(&sink, ToastKind::Info, "Reconnected");
+                                break;
+                            }
+                            Err(_) => action = policy.next_action(None),
+                        }
+                    }
+                }
+            }
+        }
+    });
+    *connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task);
 }
 
-// Function to handle sending messages
-fn send_message(siv: &mut Cursive, msg: String) {
-    if msg.is_empty() { 
-        return
-    }
+// Appends incoming frames to the message view until the connection closes, timing a ping now and then
+// Returns the error frame the server sent last, which explains why it closed us (if it did)
+async fn read_frames(
+    client: &mut ChatClient,
+    sink: &cursive::CbSink,
+    policy: &mut ReconnectPolicy,
+    shared: &Shared,
+) -> Option<ChatMessage> {
+    let mut rejection = None;
+    let mut let_in = false;
+    let mut pings = tokio::time::interval(PING_INTERVAL);
+    let mut drafts = tokio::time::interval(DRAFT_CHECK_INTERVAL);
+    loop {
+        let mut msg = tokio::select! {
+            msg = client.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = pings.tick() => {
+                shared.writer.send(Outgoing::Quiet(ui::ping_line())); // a dead connection shows up in recv()
+                continue;
+            }
+            _ = drafts.tick() => {
+                let writer = shared.writer.clone();
+                let _ = sink.send(Box::new(move |siv: &mut Cursive| send_due(siv, &writer)));
+                continue;
+            }
+        };
+        if msg.message_type == MessageType::Error || msg.error.is_some() {
+            rejection = Some(msg.clone());
+        } else {
+            rejection = None;
+            policy.connected(); // Anything but an error means the server let us in
+            let server = shared.server();
+            if !let_in
+                && server.saved
+                && let Some(password) = &client.handshake().password
+                && let Err(e) = Sessions::user(shared.keyring).save_password(&server.addr, password)
+            {
+                toast(sink, ToastKind::Error, &format!("Could not save the server's password: {}", e));
+            }
+            let_in = true;
+        }
 
-    // extra commands 
-    match msg.as_str() {
-        "/help" => {
-            siv.call_on_name("messages", |view: &mut TextView| {
-                view.append("\n=== Commands ===\n/help - Show this help\n/clear - Clear messages\n/quit - Exit chat\n\n");
-            });
-            siv.call_on_name("input", |view: &mut EditView| {
-                view.set_content("");
-            });
-            return;
+        // Session tokens are kept (in memory and on disk) rather than shown, the name they're for is ours now
+        if msg.message_type == MessageType::Session {
+            shared.ui.notifier.set_name(&msg.username);
+            if let Err(e) = Sessions::user(shared.keyring).save_token(&shared.server().addr, &msg.username, &msg.content) {
+                toast(sink, ToastKind::Error, &format!("Could not save session token: {}", e));
+            }
+            continue;
         }
-        "/clear" => {
-            siv.call_on_name("messages", |view: &mut TextView| {
-                view.set_content(""); // Clear messages
-            });
-            siv.call_on_name("input", |view: &mut EditView| {
-                view.set_content(""); // Clear input
-            });
-            return;
+        // Shortcodes made on the server expand like our own from now on
+        if msg.message_type == MessageType::ShortcodeSync {
+            shared.ui.emoji.set_shared(msg.shortcodes.into_iter().map(|shortcode| (shortcode.code, shortcode.text)));
+            continue;
         }
-        "/quit" => {
-            siv.quit();
-            return;
-}
-        "/funface" => {
-            siv.call_on_name("messages", |view: &mut TextView| {
-                view.append(
-"                        $$$$$$$$$$$$$$$$$$$$
-                       $$$$$$$$$$$$$$$$$$$$$$$$$$$
-                    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$         $$   $$$$$
-    $$$$$$        $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$       $$$$$$$$$$
- $$ $$$$$$      $$$$$$$$$$    $$$$$$$$$$$$$    $$$$$$$$$$       $$$$$$$$
- $$$$$$$$$     $$$$$$$$$$      $$$$$$$$$$$      $$$$$$$$$$$    $$$$$$$$
-   $$$$$$$    $$$$$$$$$$$      $$$$$$$$$$$      $$$$$$$$$$$$$$$$$$$$$$$
-   $$$$$$$$$$$$$$$$$$$$$$$    $$$$$$$$$$$$$    $$$$$$$$$$$$$$  $$$$$$
-    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$
-     $$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$$
-    $$$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$       $$$$
-    $$$    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$ $$$$$$$$$$$$$$$$$
-   $$$$$$$$$$$$$  $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$   $$$$$$$$$$$$$$$$$$
-   $$$$$$$$$$$$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$$$$$$$$$
-  $$$$       $$$$    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$      $$$$
-             $$$$$     $$$$$$$$$$$$$$$$$$$$$$$$$         $$$
-               $$$$          $$$$$$$$$$$$$$$           $$$$
-                $$$$$                                $$$$$
-                 $$$$$$      $$$$$$$$$$$$$$        $$$$$
-                   $$$$$$$$     $$$$$$$$$$$$$   $$$$$$$
-                      $$$$$$$$$$$  $$$$$$$$$$$$$$$$$
-                         $$$$$$$$$$$$$$$$$$$$$$
-                                 $$$$$$$$$$$$$$$
-                                     $$$$$$$$$$$$
-                                      $$$$$$$$$$$
-                                       $$$$$$$$\n"
-                ); // Insert this fun guy 
-            });
-            siv.call_on_name("input", |view: &mut EditView| {
-                view.set_content("");
-            });
+        // Whisper keys are kept for sealing ours, and sealed whispers opened (our own copies with the recipient's key)
+        if msg.message_type == MessageType::Key {
+            shared.e2e.learn(&msg.username, &msg.content);
+            continue;
+        }
+        if msg.encrypted {
+            let recipient = msg.recipient.clone().unwrap_or_default();
+            msg.content = shared
+                .e2e
+                .open(&msg.username, &msg.content)
+                .or_else(|| shared.e2e.open(&recipient, &msg.content))
+                .unwrap_or_else(|| "(sealed with a key this device doesn't have, it can't be opened here)".to_string());
+        }
+        // Update UI with the new message
+        let ctx = shared.ui.clone();
+        if sink.send(Box::new(move |siv: &mut Cursive| ui::show_message(siv, &msg, &ctx))).is_err() {
+            break; 
         }
-        _ => {}
     }
+    rejection
+}
 
+// Hands the drafts typing paused on to the server, which passes them to our other devices,
+// along with how far we have read (see ui::due_reads)
+// A draft or read mark that doesn't make it is simply overtaken by the next one, it isn't worth the outbox
+fn send_due(siv: &mut Cursive, writer: &Writer) {
+    let mut lines = ui::due_drafts(siv, Instant::now());
+    lines.extend(ui::due_reads(siv));
+    for line in lines {
+        writer.send(Outgoing::Quiet(line));
+    }
+}
 
+// Shows a connection notice in the message view, returns false once the UI has closed
+// For the ones that end the connection for good, anything passing goes in a toast
+fn notify(sink: &cursive::CbSink, text: &str) -> bool {
+    let text = text.to_string();
+    sink.send(Box::new(move |siv: &mut Cursive| ui::notice(siv, &text))).is_ok()
+}
 
-    let msg = emojify(&msg);
+// Shows a toast, returns false once the UI has closed
+fn toast(sink: &cursive::CbSink, kind: ToastKind, text: &str) -> bool {
+    let text = text.to_string();
+    sink.send(Box::new(move |siv: &mut Cursive| ui::toast(siv, kind, &text))).is_ok()
+}
 
+fn set_connection(sink: &cursive::CbSink, connection: Connection) {
+    let _ = sink.send(Box::new(move |siv: &mut Cursive| ui::set_connection(siv, connection)));
+}
 
+// Asks for the server password, then reconnects with it
+fn show_password_dialog(siv: &mut Cursive, handshake: Handshake, shared: Shared) {
+    ui::show_password_dialog(siv, move |s, password| submit_password(s, handshake.clone(), shared.clone(), password));
+}
 
-    // Send the message to the server
-    // Convert the message to a ChatMessage struct
-    let writer = siv.user_data::<Arc<Mutex<tokio::net::tcp::OwnedWriteHalf>>>().unwrap().clone();
-    tokio::spawn(async move {
-        let _ = writer.lock().await.write_all(format!("{}\n", msg).as_bytes()).await;
-    });
+fn submit_password(siv: &mut Cursive, mut handshake: Handshake, shared: Shared, password: &str) {
+    handshake.password = Some(password.to_string());
 
-    
-    siv.call_on_name("input", |view: &mut EditView| {
-        view.set_content("");
+    let sink = siv.cb_sink().clone();
+    tokio::spawn(async move {
+        let server = shared.server();
+        match ChatClient::connect_via(&server.addr, server.transport.clone(), handshake, shared.ui.tracer.clone()).await {
+            Ok(client) => {
+                connected(&shared, &client);
+                let offered = shared.clone();
+                let _ = sink.send(Box::new(move |siv: &mut Cursive| {
+                    ui::set_connection(siv, Connection::Connected);
+                    offer_unsent(siv, &offered);
+                }));
+                spawn_connection(client, sink, shared, false);
+            }
+            Err(e) => {
+                notify(&sink, &format!("Could not reconnect: {}", e));
+            }
+        }
     });
 }
 
+// Function to handle sending messages
+// Local commands are handled by the UI, everything else goes to the server
+fn send_message(siv: &mut Cursive, msg: &str, shared: &Shared) {
+    let Some(msg) = ui::submit(siv, msg, &shared.ui) else { return };
+    // Whispers to someone with a key are sealed before they go anywhere, the outbox included
+    let msg = shared.e2e.seal_line(&msg);
+
+    // Moving to another server is ours to do, the one we're on never hears of it
+    if let Some(name) = ui::connect_to(&msg) {
+        switch_server(siv, name, shared);
+        return;
+    }
+    // So is forgetting what we kept of a room, once the user says yes
+    if let Some(room) = ui::purge_local(&msg) {
+        let server = shared.server.clone();
+        ui::confirm_purge(siv, room, &shared.ui, move |room| {
+            let outbox = server.read().unwrap_or_else(|poisoned| poisoned.into_inner()).outbox.clone();
+            outbox.purge(room).map_err(|e| e.to_string())
+        });
+        return;
+    }
+    // A ping is only worth anything right away, it doesn't wait in the outbox
+    if ui::is_ping(&msg) {
+        shared.writer.send(Outgoing::Now(msg));
+        return;
+    }
 
-fn create_space_theme() -> Theme {
-    let mut theme = Theme::default();
-    theme.shadow = true;
-    theme.borders = BorderStyle::Simple;
-
-    let mut palette = Palette::default();
-    palette[PaletteColor::Background] = Color::Rgb(8, 8, 32);            // Deep space blue-black
-    palette[PaletteColor::View] = Color::Rgb(20, 16, 48);                // Slightly lighter, cosmic purple
-    palette[PaletteColor::Primary] = Color::Rgb(0, 255, 255);            // Neon cyan for main text
-    palette[PaletteColor::TitlePrimary] = Color::Rgb(180, 0, 255);       // Electric purple for titles
-    palette[PaletteColor::Secondary] = Color::Rgb(0, 200, 255);          // Blue for secondary elements
-    palette[PaletteColor::Highlight] = Color::Rgb(255, 255, 0);          // Bright yellow highlight (stars)
-    palette[PaletteColor::HighlightInactive] = Color::Rgb(80, 80, 120);  // Dimmed blue for inactive
-    palette[PaletteColor::Shadow] = Color::Rgb(0, 0, 0);                 // Black shadow
-    theme.palette = palette;
-    theme
+    // Written down first, so it survives the client dying before it goes out
+    if let Err(e) = shared.server().outbox.push(&msg, ui::current_room(siv).as_deref()) {
+        ui::toast(siv, ToastKind::Error, &format!("Could not save the outbox: {}", e));
+    }
+    deliver(siv, msg, shared);
 }
 
+// Hands a line from the outbox to the writer (signed for the room we talk in, as it goes out), see report_to
+// for what happens next
+fn deliver(siv: &mut Cursive, msg: String, shared: &Shared) {
+    let room = ui::current_room(siv).unwrap_or_default();
+    let wire = shared.signer.as_ref().map_or_else(|| msg.clone(), |signer| signer.sign_line(&room, &msg));
+    shared.writer.send(Outgoing::Chat { line: msg, wire });
+}
 
-fn emojify(text: &str) -> String {
-    // Replace text with emojis 
-    text.replace(":)", "😊")
-        .replace(":(", "😢")
-        .replace(":D", "😄")
-        .replace("<3", "❤️")
-        .replace(":/", "😕")
-        .replace("XD", "😂")
-        .replace("!?", "❓❗")
-        .replace("...", "😶")
-        .replace(":-)", "😊")
-        .replace(":-(", "😢")
-        .replace("wtf", "🤬")
-        .replace("brb", "🏃‍♂️")
-        .replace(";)", "😉")
+// What the writer says about the lines it was handed: one from the outbox comes off it once it went out,
+// and a line that didn't go out puts up an error
+fn report_to(sink: cursive::CbSink, server: Arc<RwLock<Arc<Server>>>) -> impl Fn(Report) + Send + 'static {
+    move |report| match report {
+        Report::Sent(Outgoing::Chat { line, .. }) => {
+            let outbox = server.read().unwrap_or_else(|poisoned| poisoned.into_inner()).outbox.clone();
+            if let Err(e) = outbox.remove(&line) {
+                toast(&sink, ToastKind::Error, &format!("Could not save the outbox: {}", e));
+            }
+        }
+        Report::Sent(_) => {}
+        Report::Failed(Outgoing::Chat { .. }, e) => {
+            toast(&sink, ToastKind::Error, &format!("Send failed, kept for resending: {}", e));
+        }
+        Report::Failed(_, e) => {
+            toast(&sink, ToastKind::Error, &format!("Ping failed: {}", e));
+        }
+    }
 }
 
+// Lines go out on this connection from now on, and it hears first that we're in do not disturb if we are
+fn connected(shared: &Shared, client: &ChatClient) {
+    shared.writer.connected(client.sender());
+    if shared.ui.notifier.dnd() {
+        shared.writer.send(Outgoing::Quiet(ui::dnd_line(true)));
+    }
+}
 
+// Asks about each line still in the outbox: send it now, edit it first, or drop it
+fn offer_unsent(siv: &mut Cursive, shared: &Shared) {
+    let unsent = shared.server().outbox.pending();
+    if unsent.is_empty() {
+        return;
+    }
+    let shared = shared.clone();
+    ui::offer_unsent(siv, unsent, move |s, line, choice| match choice {
+        UnsentChoice::Send => deliver(s, line.to_string(), &shared),
+        // An edited line is queued again when it is sent
+        UnsentChoice::Edit | UnsentChoice::Discard => {
+            if let Err(e) = shared.server().outbox.remove(line) {
+                ui::toast(s, ToastKind::Error, &format!("Could not save the outbox: {}", e));
+            }
+        }
+    });
+}
 
-// Function to generate a color based on the username, makes it easier to distinguish username from chat messages 
 
-fn color_for_username(username: &str) -> ColorStyle {
-    let colors = [
-        Color::Light(BaseColor::Red),
-        Color::Light(BaseColor::Green),
-        Color::Light(BaseColor::Yellow),
-        Color::Light(BaseColor::Blue),
-        Color::Light(BaseColor::Magenta),
-        Color::Light(BaseColor::Cyan),
-    ];
-    let idx = username.bytes().fold(0u8, |acc, b| acc.wrapping_add(b)) as usize % colors.len();
-    ColorStyle::new(colors[idx], Color::TerminalDefault)
-}
\ No newline at end of file
+// Wire trace file used when tracing is switched on without --trace-wire
+fn default_trace_file() -> PathBuf {
+    config_dir()
+        .map(|dir| dir.join("wire-trace.log"))
+        .unwrap_or_else(|| PathBuf::from("wire-trace.log"))
+}
@@ -0,0 +1,232 @@
+// Load tester: simulated clients chatting in the lobby at a set rate, so server changes can be benchmarked
+// The clients connect one after the other over --ramp seconds and wait for their history replay to end. Then
+// for --duration seconds each sends messages at random intervals averaging --rate a second. Every message says
+// who sent it and when (run tag, client, sequence number, microseconds since the start), so each client that
+// receives one adds an end-to-end latency sample. At the end come the latency percentiles and the deliveries
+// that never arrived: every message should reach every client, what didn't is a broadcast that lagged.
+// The server counts every simulated client against its reconnect limit and per-IP cap, raise those first
+// (--reconnect-limit and [limits] max_per_ip).
+//
+// cargo run --release --bin loadtest -- [--addr 127.0.0.1:8082] [--clients 50] [--rate 1] [--duration 30] [--ramp 5] [--password <server password>]
+
+use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use retro_chat_project::client::connection::{ChatClient, DEFAULT_ADDR};
+use retro_chat_project::protocol::{ChatMessage, Handshake, MessageType};
+
+// Every test message starts with this and the run tag, so other traffic is left alone
+const MARK: &str = "lt";
+const WORDS: [&str; 8] = ["ping", "orbit", "comet", "nebula", "warp", "quasar", "rocket", "stardust"];
+
+// How long clients keep listening after the last message went out, for those still on their way
+const DRAIN: Duration = Duration::from_secs(3);
+
+// Command line knobs for the simulated load
+#[derive(Debug, Clone)]
+struct LoadOptions {
+    addr: String,
+    clients: usize,
+    // Average messages per second, per client
+    rate: f64,
+    duration: Duration,
+    ramp: Duration,
+    password: Option<String>,
+}
+
+impl LoadOptions {
+    fn from_args() -> Result<Self, String> {
+        let mut options = LoadOptions {
+            addr: DEFAULT_ADDR.to_string(),
+            clients: 50,
+            rate: 1.0,
+            duration: Duration::from_secs(30),
+            ramp: Duration::from_secs(5),
+            password: None,
+        };
+        let seconds = |flag: &str, value: Option<String>| -> Result<Duration, String> {
+            let value = value.ok_or(format!("{} needs a number of seconds", flag))?;
+            value.parse().map(Duration::from_secs_f64).map_err(|_| format!("invalid {}: {}", flag, value))
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--addr" => options.addr = args.next().ok_or("--addr needs a value")?,
+                "--clients" => {
+                    let clients = args.next().ok_or("--clients needs a value")?;
+                    options.clients = clients.parse().map_err(|_| format!("invalid --clients: {}", clients))?;
+                }
+                "--rate" => {
+                    let rate = args.next().ok_or("--rate needs a value")?;
+                    options.rate = rate.parse().map_err(|_| format!("invalid --rate: {}", rate))?;
+                }
+                "--duration" => options.duration = seconds("--duration", args.next())?,
+                "--ramp" => options.ramp = seconds("--ramp", args.next())?,
+                "--password" => options.password = Some(args.next().ok_or("--password needs a value")?),
+                other => return Err(format!("unknown argument: {}", other)),
+            }
+        }
+        if options.clients == 0 {
+            return Err("--clients must be at least 1".to_string());
+        }
+        if options.rate <= 0.0 {
+            return Err("--rate must be positive".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// What the clients found, added up as they go
+#[derive(Debug, Default)]
+struct Stats {
+    // Clients that got through their history replay, and those that couldn't connect or were let go early
+    ready: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+    sent: AtomicU64,
+    delivered: AtomicU64,
+    // Error frames, refusals of the handshake or of messages
+    errors: AtomicU64,
+    latencies: Mutex<Vec<Duration>>,
+}
+
+// When the run started and when the clients start and stop talking
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    started: Instant,
+    talk_from: Instant,
+    talk_until: Instant,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let options = Arc::new(LoadOptions::from_args()?);
+    let tag = format!("{:04x}", rand::rng().random::<u16>());
+    let started = Instant::now();
+    let talk_from = started + options.ramp;
+    let schedule = Schedule { started, talk_from, talk_until: talk_from + options.duration };
+    let stats = Arc::new(Stats::default());
+    println!("🏋️ {} clients on {}, {} messages/s each for {}s (run {})",
+        options.clients, options.addr, options.rate, options.duration.as_secs_f64(), tag);
+
+    let mut clients = JoinSet::new();
+    for n in 0..options.clients {
+        tokio::time::sleep_until(started + options.ramp.mul_f64(n as f64 / options.clients as f64)).await;
+        clients.spawn(simulate(n, options.clone(), tag.clone(), schedule, stats.clone()));
+    }
+    while clients.join_next().await.is_some() {}
+
+    report(&stats);
+    Ok(())
+}
+
+// One client: connect, catch up, then talk and listen until the run is over
+async fn simulate(n: usize, options: Arc<LoadOptions>, tag: String, schedule: Schedule, stats: Arc<Stats>) {
+    let handshake = Handshake { username: format!("load{}-{}", tag, n), password: options.password.clone(), ..Default::default() };
+    let mut client = match ChatClient::connect(&options.addr, handshake).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("[WARN] client {} couldn't connect: {}", n, e);
+            stats.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let sender = client.sender();
+    let prefix = format!("{} {} ", MARK, tag);
+    let mut caught_up = false;
+    let mut seq = 0u64;
+    let mut next_send = schedule.talk_from;
+    let stop = schedule.talk_until + DRAIN;
+    loop {
+        let talking = caught_up && next_send < schedule.talk_until;
+        tokio::select! {
+            frame = client.recv() => {
+                let Some(msg) = frame else {
+                    eprintln!("[WARN] client {} was disconnected", n);
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                };
+                match msg.message_type {
+                    // The count comes right after the history replay
+                    MessageType::UserCount if !caught_up => {
+                        caught_up = true;
+                        stats.ready.fetch_add(1, Ordering::Relaxed);
+                    }
+                    MessageType::Error => {
+                        eprintln!("[WARN] client {}: {}", n, msg.content);
+                        stats.errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                    MessageType::UserMessage if caught_up => {
+                        if let Some(latency) = latency(&msg, &prefix, schedule.started) {
+                            stats.delivered.fetch_add(1, Ordering::Relaxed);
+                            stats.latencies.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(latency);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ = tokio::time::sleep_until(next_send), if talking => {
+                let micros = schedule.started.elapsed().as_micros();
+                let word = WORDS[rand::rng().random_range(0..WORDS.len())];
+                if sender.send(&format!("{}{} {} {} {}", prefix, n, seq, micros, word)).await.is_err() {
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                seq += 1;
+                stats.sent.fetch_add(1, Ordering::Relaxed);
+                next_send += next_gap(options.rate);
+            }
+            _ = tokio::time::sleep_until(stop) => {
+                let _ = sender.close().await;
+                return;
+            }
+        }
+    }
+}
+
+// Exponential gaps make for messages that come as they would from people, at `rate` a second on average
+fn next_gap(rate: f64) -> Duration {
+    let uniform: f64 = rand::rng().random_range(f64::EPSILON..1.0);
+    Duration::from_secs_f64(-uniform.ln() / rate)
+}
+
+// How long a test message of this run took to get here: "<prefix><client> <seq> <micros> <word>"
+fn latency(msg: &ChatMessage, prefix: &str, started: Instant) -> Option<Duration> {
+    let sent = msg.content.strip_prefix(prefix)?.split_whitespace().nth(2)?.parse::<u64>().ok()?;
+    started.elapsed().checked_sub(Duration::from_micros(sent))
+}
+
+fn report(stats: &Stats) {
+    let ready = stats.ready.load(Ordering::Relaxed);
+    let sent = stats.sent.load(Ordering::Relaxed);
+    let delivered = stats.delivered.load(Ordering::Relaxed);
+    // Every message should have reached every client that caught up, the sender included
+    let expected = sent * ready;
+    let mut latencies = stats.latencies.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    latencies.sort();
+
+    println!("┌─ Clients: {} ready, {} couldn't connect, {} dropped early, {} error frames",
+        ready, stats.failed.load(Ordering::Relaxed), stats.dropped.load(Ordering::Relaxed), stats.errors.load(Ordering::Relaxed));
+    println!("├─ Messages: {} sent, {} of {} deliveries arrived", sent, delivered, expected);
+    println!("├─ Lagged: {} deliveries missing ({:.2}%)", expected.saturating_sub(delivered),
+        if expected == 0 { 0.0 } else { expected.saturating_sub(delivered) as f64 * 100.0 / expected as f64 });
+    if latencies.is_empty() {
+        println!("└─ Latency: no samples");
+        return;
+    }
+    let ms = |p: f64| percentile(&latencies, p).as_secs_f64() * 1000.0;
+    println!("└─ Latency: p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms", ms(50.0), ms(90.0), ms(99.0), ms(100.0));
+}
+
+// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,188 @@
+// Mock chat server for client UI development
+// Speaks the same protocol as the real server but makes up its own traffic: random users
+// chatting, joining and leaving, bursts of messages, huge messages and (with --malformed)
+// broken frames, so client changes can be exercised without rounding up real people.
+//
+// cargo run --bin mockserver -- [--addr 127.0.0.1:8082] [--rate 2] [--malformed]
+
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::Local;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use retro_chat_project::protocol::{ChatMessage, Handshake, Member, MessageType};
+
+const USERS: [&str; 8] = ["Zorg", "Nebula", "Quasar", "Astro", "Comet", "Pulsar", "Luna", "Orbit"];
+const LINES: [&str; 8] = [
+    "anyone seen my spaceship keys?",
+    "brb, refuelling :)",
+    "the view from Saturn is wild tonight",
+    "wtf is a light year anyway",
+    "hello from the dark side of the moon <3",
+    "who's up for a race around the asteroid belt?!?",
+    "beep boop... transmission unclear",
+    "XD that's the funniest thing I've heard all orbit",
+];
+
+// Command line knobs for the generated traffic
+#[derive(Debug, Clone)]
+struct MockOptions {
+    addr: String,
+    // Average generated events per second
+    rate: f64,
+    // Also send frames that aren't valid JSON or don't match ChatMessage
+    malformed: bool,
+}
+
+impl MockOptions {
+    fn from_args() -> Result<Self, String> {
+        let mut options = MockOptions {
+            addr: "127.0.0.1:8082".to_string(),
+            rate: 2.0,
+            malformed: false,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--addr" => options.addr = args.next().ok_or("--addr needs a value")?,
+                "--rate" => {
+                    let rate = args.next().ok_or("--rate needs a value")?;
+                    options.rate = rate.parse().map_err(|_| format!("invalid --rate: {}", rate))?;
+                }
+                "--malformed" => options.malformed = true,
+                other => return Err(format!("unknown argument: {}", other)),
+            }
+        }
+        if options.rate <= 0.0 {
+            return Err("--rate must be positive".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let options = MockOptions::from_args()?;
+    let listener = TcpListener::bind(&options.addr).await?;
+    println!("🧪 Mock server listening on {} ({} events/s{})", options.addr, options.rate,
+        if options.malformed { ", malformed frames on" } else { "" });
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        println!("┌─[{}] Mock client connected", Local::now().format("%D:%H:%M:%S"));
+        println!("└─ Address: {}", addr);
+        let options = options.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(socket, options).await {
+                eprintln!("[WARN] mock client {} ended: {}", addr, e);
+            }
+        });
+    }
+}
+
+// Feeds one client with made-up traffic and echoes back whatever it sends
+async fn serve(socket: TcpStream, options: MockOptions) -> std::io::Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut rng = StdRng::from_os_rng();
+
+    let Some(handshake_line) = lines.next_line().await? else { return Ok(()) };
+    let username = Handshake::parse(&handshake_line).username;
+    send(&mut writer, &ChatMessage::new(username.clone(), "has landed", MessageType::SystemNotification)).await?;
+
+    let mut next_id = 1;
+    let mean_delay = 1.0 / options.rate;
+    loop {
+        let delay = Duration::from_secs_f64(rng.random_range(0.2..1.8) * mean_delay);
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else { return Ok(()) };
+                if let Some(stamp) = line.trim().strip_prefix("/ping ") {
+                    send(&mut writer, &ChatMessage::new("System", stamp.trim(), MessageType::Ping)).await?;
+                    continue;
+                }
+                let mut echo = ChatMessage::new(username.clone(), line.trim(), MessageType::UserMessage);
+                echo.id = Some(next_id);
+                next_id += 1;
+                send(&mut writer, &echo).await?;
+            }
+            _ = tokio::time::sleep(delay) => {
+                for frame in generate(&mut rng, &options, &mut next_id) {
+                    writer.write_all(frame.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                }
+                writer.flush().await?;
+            }
+        }
+    }
+}
+
+// One tick of synthetic traffic, already serialized
+fn generate(rng: &mut StdRng, options: &MockOptions, next_id: &mut i64) -> Vec<String> {
+    let user = USERS[rng.random_range(0..USERS.len())];
+    let mut chat = |rng: &mut StdRng, content: String| {
+        let mut msg = ChatMessage::new(user, content, MessageType::UserMessage);
+        msg.id = Some(*next_id);
+        msg.verified = rng.random_bool(0.3);
+        *next_id += 1;
+        serde_json::to_string(&msg).unwrap_or_default()
+    };
+
+    match rng.random_range(0..100) {
+        // Someone arrives or leaves
+        0..=9 => {
+            let content = if rng.random_bool(0.5) { "has landed" } else { "has blasted off" };
+            let count = rng.random_range(1..=USERS.len());
+            let online = ChatMessage::user_count(count);
+            let roster = ChatMessage::roster(
+                USERS[..count].iter().map(|name| Member { username: name.to_string(), verified: name.len() % 2 == 0, dnd: name.len() % 3 == 0 }).collect(),
+            );
+            [ChatMessage::new(user, content, MessageType::SystemNotification), online, roster]
+                .iter()
+                .map(|msg| serde_json::to_string(msg).unwrap_or_default())
+                .collect()
+        }
+        // A burst of quick messages
+        10..=14 => (0..rng.random_range(10..40))
+            .map(|n| chat(rng, format!("burst message #{}", n + 1)))
+            .collect(),
+        // A huge message
+        15..=17 => {
+            let size = rng.random_range(2_000..20_000);
+            let content: String = LINES.iter().cycle().flat_map(|l| l.chars().chain([' '])).take(size).collect();
+            vec![chat(rng, content)]
+        }
+        // Broken frames, only when asked for
+        18..=22 if options.malformed => vec![malformed(rng)],
+        _ => {
+            let line = LINES[rng.random_range(0..LINES.len())].to_string();
+            vec![chat(rng, line)]
+        }
+    }
+}
+
+fn malformed(rng: &mut StdRng) -> String {
+    let samples = [
+        "{\"username\":\"Glitch\",\"content\":\"missing fields\"",
+        "this is not json at all",
+        "{\"username\":\"Glitch\",\"content\":\"?\",\"timestamp\":\"now\",\"message_type\":\"Hologram\"}",
+        "{\"username\":42,\"content\":[],\"timestamp\":null,\"message_type\":\"UserMessage\"}",
+        "\u{0}\u{0}\u{0}",
+        "",
+    ];
+    samples[rng.random_range(0..samples.len())].to_string()
+}
+
+async fn send(writer: &mut tokio::net::tcp::OwnedWriteHalf, msg: &ChatMessage) -> std::io::Result<()> {
+    let json = serde_json::to_string(msg)?;
+    writer.write_all(json.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
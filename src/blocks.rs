@@ -0,0 +1,80 @@
+// Multi-line messages framed with ``` fences, as in markdown
+// A line that opens a fence ("```" or "```rust") starts a block, every line up to the closing fence belongs
+// to it (indentation and all) and the whole block is posted as one message.
+// Blocks are capped so a client can't make the server buffer without end, an oversized one is dropped
+// up to its closing fence rather than leaking out line by line.
+
+pub const FENCE: &str = "```";
+pub const MAX_BLOCK_LINES: usize = 200;
+pub const MAX_BLOCK_BYTES: usize = 16 * 1024;
+
+// What a line turned out to be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Framed {
+    // An ordinary line, handled on its own
+    Single,
+    // Part of a block that isn't finished yet
+    Pending,
+    // The closing fence arrived, this is the whole block
+    Block(String),
+    // The block went over the caps and is being dropped
+    TooLong,
+}
+
+#[derive(Debug, Default)]
+pub struct BlockFraming {
+    state: State,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Idle,
+    Collecting { lines: Vec<String>, bytes: usize },
+    Discarding,
+}
+
+impl BlockFraming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes the next line as received (without its line ending)
+    pub fn feed(&mut self, line: &str) -> Framed {
+        let closes = line.trim() == FENCE;
+        match &mut self.state {
+            State::Idle if opens_block(line) => {
+                self.state = State::Collecting { lines: vec![line.trim().to_string()], bytes: line.len() };
+                Framed::Pending
+            }
+            State::Idle => Framed::Single,
+            State::Collecting { lines, bytes } => {
+                *bytes += line.len() + 1;
+                lines.push(if closes { FENCE.to_string() } else { line.trim_end().to_string() });
+                if closes {
+                    let block = lines.join("\n");
+                    self.state = State::Idle;
+                    Framed::Block(block)
+                } else if lines.len() > MAX_BLOCK_LINES || *bytes > MAX_BLOCK_BYTES {
+                    self.state = State::Discarding;
+                    Framed::TooLong
+                } else {
+                    Framed::Pending
+                }
+            }
+            State::Discarding => {
+                if closes {
+                    self.state = State::Idle;
+                }
+                Framed::Pending
+            }
+        }
+    }
+}
+
+// "```" or "```rust" on a line of its own, as opposed to ```inline``` code
+pub fn opens_block(line: &str) -> bool {
+    line.trim()
+        .strip_prefix(FENCE)
+        .is_some_and(|lang| !lang.contains('`') && !lang.contains(char::is_whitespace))
+}
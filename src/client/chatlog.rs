@@ -0,0 +1,119 @@
+// The chat as it was shown, kept in plain text files to grep outside the window
+// Every message that makes it on screen is appended to <dir>/<YYYY-MM-DD>.log for the day it was stamped,
+// one line each with the time and room: "[2026-10-16 14:32:05] #lobby <zorg> hello". [log] in config.toml
+// says whether it's on and where the files go, /log on|off switches it as the client runs, and /purge-local
+// takes a room's lines back out of every file. Sessions and two-factor secrets never go in.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, NaiveDate};
+
+use crate::protocol::{ChatMessage, MessageType};
+
+#[derive(Debug)]
+pub struct ChatLog {
+    dir: PathBuf,
+    enabled: AtomicBool,
+    // The file of the day last written to
+    file: Mutex<Option<(NaiveDate, File)>>,
+}
+
+impl ChatLog {
+    pub fn new(dir: impl Into<PathBuf>, enabled: bool) -> Self {
+        ChatLog { dir: dir.into(), enabled: AtomicBool::new(enabled), file: Mutex::new(None) }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Appends a message that was shown. A file that can't be written turns the log off, the error says why
+    pub fn record(&self, msg: &ChatMessage) -> Result<(), String> {
+        if !self.is_enabled() || matches!(msg.message_type, MessageType::Session | MessageType::TwoFactorSetup | MessageType::BackupCodes) {
+            return Ok(());
+        }
+        let at = DateTime::parse_from_rfc3339(&msg.timestamp).map(|at| at.with_timezone(&Local)).unwrap_or_else(|_| Local::now());
+        let day = at.date_naive();
+
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if file.as_ref().is_none_or(|(open, _)| *open != day) {
+            let path = self.dir.join(format!("{}.log", day.format("%Y-%m-%d")));
+            let opened = fs::create_dir_all(&self.dir)
+                .and_then(|_| OpenOptions::new().create(true).append(true).open(&path))
+                .map_err(|e| {
+                    self.set_enabled(false);
+                    format!("{}: {}", path.display(), e)
+                })?;
+            *file = Some((day, opened));
+        }
+        if let Some((_, file)) = file.as_mut() {
+            file.write_all(line(msg, &at).as_bytes()).map_err(|e| {
+                self.set_enabled(false);
+                e.to_string()
+            })?;
+        }
+        Ok(())
+    }
+
+    // Takes a room's messages out of every day's file, returns how many there were
+    pub fn purge(&self, room: &str) -> Result<usize, String> {
+        // Nothing is appended meanwhile, and the next message opens its file again
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *file = None;
+        let Ok(days) = fs::read_dir(&self.dir) else { return Ok(0) }; // no log yet, nothing in it
+        let tag = format!("#{} ", room);
+        let mut purged = 0;
+        for path in days.flatten().map(|entry| entry.path()) {
+            let is_day = path.extension().is_some_and(|ext| ext == "log")
+                && path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").is_ok());
+            if !is_day {
+                continue;
+            }
+            let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let (mut kept, mut dropping, before) = (String::with_capacity(text.len()), false, purged);
+            for line in text.split_inclusive('\n') {
+                // A message's later lines are indented, they go with its first
+                if !line.starts_with("    ") {
+                    dropping = line.split_once("] ").is_some_and(|(_, said)| said.starts_with(&tag));
+                    purged += usize::from(dropping);
+                }
+                if !dropping {
+                    kept.push_str(line);
+                }
+            }
+            if purged > before {
+                fs::write(&path, kept).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+// "[2026-10-16 14:32:05] #lobby <zorg> hello", the lines after the first of a message indented under it
+fn line(msg: &ChatMessage, at: &DateTime<Local>) -> String {
+    let room = msg.room.as_ref().map(|room| format!("#{} ", room)).unwrap_or_default();
+    let said = match msg.message_type {
+        MessageType::UserMessage => format!("{}<{}> {}", room, msg.username, msg.content),
+        MessageType::Action => format!("{}* {} {}", room, msg.username, msg.content),
+        MessageType::Whisper => {
+            let to = msg.recipient.as_deref().unwrap_or("you");
+            format!("<{}> (whisper to {}) {}", msg.username, to, msg.content)
+        }
+        MessageType::Error => format!("{}!! {}", room, msg.content),
+        _ if msg.username.is_empty() => format!("{}-- {}", room, msg.content),
+        _ => format!("{}-- {}: {}", room, msg.username, msg.content),
+    };
+    format!("[{}] {}\n", at.format("%Y-%m-%d %H:%M:%S"), said.trim_end().replace('\n', "\n    "))
+}
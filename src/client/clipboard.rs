@@ -0,0 +1,15 @@
+// The system clipboard, for /copyurl and /copy
+// It is opened once and kept for as long as the client runs: on X11 what was copied goes away with it.
+
+use std::sync::Mutex;
+
+use arboard::Clipboard;
+
+pub fn copy(text: &str) -> Result<(), arboard::Error> {
+    static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+    let mut clipboard = CLIPBOARD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new()?);
+    }
+    clipboard.as_mut().map_or(Ok(()), |clipboard| clipboard.set_text(text))
+}
@@ -0,0 +1,244 @@
+// Headless connection to a chat server: the handshake, sending lines and reading frames
+// The TUI is built on top of it, and so can bots (see examples/greeter_bot.rs).
+// The handshake follows what the server says (session token, newest message id, rooms),
+// so reconnect() picks up where the last connection left off.
+// Addresses are host:port (or tcp://host:port), quic://host:port for a server listening for QUIC (quic feature),
+// or unix:<path> for a server listening on a Unix socket (--unix-socket).
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::LinesStream;
+use tokio_stream::{Stream, StreamExt};
+
+use super::socks::Proxy;
+use crate::protocol::{ChatMessage, Handshake, MessageType};
+use crate::trace::{Direction, WireTracer};
+
+// Where the server listens unless told otherwise
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8082";
+// Addresses starting with these say how to get there (plain host:port is TCP too)
+pub const TCP_PREFIX: &str = "tcp://";
+pub const QUIC_PREFIX: &str = "quic://";
+// Addresses starting with this are the path of a Unix socket
+pub const UNIX_PREFIX: &str = "unix:";
+
+// Whether an address is one we know how to get to: host:port (after tcp:// or quic:// if given) or unix:<path>
+// Says what's wrong with it otherwise, before anything is tried
+pub fn check_addr(addr: &str) -> Result<(), String> {
+    if addr.chars().any(char::is_whitespace) {
+        return Err("the server address can't have spaces in it".to_string());
+    }
+    if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        return if path.is_empty() { Err("unix: needs the path of the socket".to_string()) } else { Ok(()) };
+    }
+    let target = addr.strip_prefix(QUIC_PREFIX).or_else(|| addr.strip_prefix(TCP_PREFIX)).unwrap_or(addr);
+    match target.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port > 0) => Ok(()),
+        _ => Err(format!("{:?} isn't host:port (like {})", addr, DEFAULT_ADDR)),
+    }
+}
+
+// How to get to the server, beyond its address
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    // SOCKS5 proxy TCP connections go through
+    pub proxy: Option<Proxy>,
+    // The certificate (PEM) a QUIC server is trusted by, instead of the system's roots
+    pub quic_cert: Option<PathBuf>,
+}
+
+// The two halves of a connection, whichever kind of socket it is
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+// Write half of a connection, cheap to clone and hand to other tasks
+#[derive(Clone)]
+pub struct ChatSender {
+    writer: Arc<Mutex<Writer>>,
+    tracer: Arc<WireTracer>,
+    addr: String,
+}
+
+impl ChatSender {
+    // Sends one line: chat text or a slash command, exactly as a user would type it
+    // (a ``` fenced block goes out as its lines, the server puts it back together)
+    pub async fn send(&self, line: &str) -> io::Result<()> {
+        self.tracer.record(Direction::Sent, &self.addr, line);
+        let mut writer = self.writer.lock().await;
+        writer.write_all(format!("{}\n", line).as_bytes()).await?;
+        writer.flush().await
+    }
+
+    // Closes our side of the connection, the server sees us leave
+    pub async fn close(&self) -> io::Result<()> {
+        self.writer.lock().await.shutdown().await
+    }
+}
+
+pub struct ChatClient {
+    addr: String,
+    // Proxy and certificate the connection goes through, reconnects included
+    transport: Transport,
+    handshake: Handshake,
+    tracer: Arc<WireTracer>,
+    lines: Lines<BufReader<Reader>>,
+    sender: ChatSender,
+}
+
+impl ChatClient {
+    // Connects and sends the handshake, without wire tracing
+    pub async fn connect(addr: &str, handshake: Handshake) -> io::Result<Self> {
+        let tracer = Arc::new(WireTracer::new("wire-trace.log", false));
+        Self::connect_traced(addr, handshake, tracer).await
+    }
+
+    // Connects and sends the handshake, logging every frame to `tracer` while it is switched on
+    pub async fn connect_traced(addr: &str, handshake: Handshake, tracer: Arc<WireTracer>) -> io::Result<Self> {
+        Self::connect_via(addr, Transport::default(), handshake, tracer).await
+    }
+
+    // Same, through a SOCKS5 proxy or trusting a QUIC certificate as `transport` says
+    pub async fn connect_via(addr: &str, transport: Transport, handshake: Handshake, tracer: Arc<WireTracer>) -> io::Result<Self> {
+        let (lines, sender) = open(addr, &transport, &handshake, &tracer).await?;
+        Ok(ChatClient { addr: addr.to_string(), transport, handshake, tracer, lines, sender })
+    }
+
+    // Connects again with the handshake as it is now (resuming the session, rejoining the rooms)
+    // Handles from sender() keep pointing at the old connection, take a new one afterwards
+    pub async fn reconnect(&mut self) -> io::Result<()> {
+        let (lines, sender) = open(&self.addr, &self.transport, &self.handshake, &self.tracer).await?;
+        self.lines = lines;
+        self.sender = sender;
+        Ok(())
+    }
+
+    pub fn handshake(&self) -> &Handshake {
+        &self.handshake
+    }
+
+    // Changes what the next reconnect sends, e.g. a password the server asked for
+    pub fn handshake_mut(&mut self) -> &mut Handshake {
+        &mut self.handshake
+    }
+
+    pub fn sender(&self) -> ChatSender {
+        self.sender.clone()
+    }
+
+    pub async fn send(&self, line: &str) -> io::Result<()> {
+        self.sender.send(line).await
+    }
+
+    // Waits for the next frame, None once the connection is closed
+    // Lines that aren't frames are skipped
+    pub async fn recv(&mut self) -> Option<ChatMessage> {
+        while let Ok(Some(line)) = self.lines.next_line().await {
+            self.tracer.record(Direction::Received, &self.addr, &line);
+            if let Ok(msg) = serde_json::from_str::<ChatMessage>(&line) {
+                follow(&mut self.handshake, &msg);
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    // Every frame until the connection closes, for callers that prefer streams
+    // The handshake isn't kept up to date this way, so there's no reconnecting afterwards
+    pub fn into_stream(self) -> impl Stream<Item = ChatMessage> {
+        let ChatClient { addr, tracer, lines, .. } = self;
+        LinesStream::new(lines).map_while(Result::ok).filter_map(move |line| {
+            tracer.record(Direction::Received, &addr, &line);
+            serde_json::from_str(&line).ok()
+        })
+    }
+}
+
+async fn open(
+    addr: &str,
+    transport: &Transport,
+    handshake: &Handshake,
+    tracer: &Arc<WireTracer>,
+) -> io::Result<(Lines<BufReader<Reader>>, ChatSender)> {
+    let no_proxy = |reason: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, reason.to_string()));
+    let (reader, mut writer) = if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        if transport.proxy.is_some() {
+            return no_proxy("a Unix socket is on this machine, there's no proxy in between");
+        }
+        connect_unix(path).await?
+    } else if let Some(target) = addr.strip_prefix(QUIC_PREFIX) {
+        if transport.proxy.is_some() {
+            return no_proxy("QUIC goes over UDP, a SOCKS5 proxy can't carry it");
+        }
+        connect_quic(target, transport).await?
+    } else {
+        let target = addr.strip_prefix(TCP_PREFIX).unwrap_or(addr);
+        match &transport.proxy {
+            Some(proxy) => boxed(proxy.connect(target).await?),
+            None => boxed(TcpStream::connect(target).await?),
+        }
+    };
+
+    let handshake_json = serde_json::to_string(handshake)?;
+    tracer.record(Direction::Sent, addr, &handshake_json);
+    writer.write_all(format!("{}\n", handshake_json).as_bytes()).await?;
+
+    let sender = ChatSender { writer: Arc::new(Mutex::new(writer)), tracer: tracer.clone(), addr: addr.to_string() };
+    Ok((BufReader::new(reader).lines(), sender))
+}
+
+fn boxed(stream: TcpStream) -> (Reader, Writer) {
+    let (reader, writer) = stream.into_split();
+    (Box::new(reader), Box::new(writer))
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &str) -> io::Result<(Reader, Writer)> {
+    let (reader, writer) = tokio::net::UnixStream::connect(path).await?.into_split();
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_path: &str) -> io::Result<(Reader, Writer)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are only there on Unix systems"))
+}
+
+#[cfg(feature = "quic")]
+async fn connect_quic(target: &str, transport: &Transport) -> io::Result<(Reader, Writer)> {
+    let (reader, writer) = super::quic::connect(target, transport.quic_cert.as_deref()).await?;
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+#[cfg(not(feature = "quic"))]
+async fn connect_quic(_target: &str, _transport: &Transport) -> io::Result<(Reader, Writer)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "this client was built without QUIC, build it with --features quic"))
+}
+
+// Keeps the handshake in step with the server so a reconnect resumes where we left off
+fn follow(handshake: &mut Handshake, msg: &ChatMessage) {
+    if matches!(msg.message_type, MessageType::UserMessage | MessageType::Action)
+        && let Some(id) = msg.id
+    {
+        handshake.last_message_id = Some(handshake.last_message_id.map_or(id, |last| last.max(id)));
+    }
+    // Remember our rooms so a reconnect rejoins them, the one we talk in goes last
+    if let Some(room) = &msg.room {
+        match msg.message_type {
+            MessageType::RoomJoined => {
+                handshake.rooms.retain(|joined| joined != room);
+                handshake.rooms.push(room.clone());
+            }
+            MessageType::RoomLeft => handshake.rooms.retain(|joined| joined != room),
+            _ => {}
+        }
+    }
+    // A session token means we are signed in as its account from now on
+    if msg.message_type == MessageType::Session {
+        handshake.username = msg.username.clone();
+        handshake.session_token = Some(msg.content.clone());
+    }
+}
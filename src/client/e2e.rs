@@ -0,0 +1,139 @@
+// End-to-end encrypted whispers
+// Every client has an X25519 key pair, made on first run and kept in e2e.key inside the config dir. The public
+// half goes to the server in the handshake and the server passes everyone's on to everyone (Key frames).
+// A whisper to someone whose key we know is sealed with ChaCha20-Poly1305, under a key both ends work out from
+// their own secret and the other's public key, so the server only ever relays ciphertext. Whispers to people
+// without a key (older clients, or offline, where the mailbox keeps them) go out readable as before.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::config_dir;
+
+// Bytes of the random nonce in front of every sealed whisper
+const NONCE_LEN: usize = 12;
+
+// Commands that whisper, and the one a sealed whisper goes out as
+const WHISPER_COMMANDS: [&str; 3] = ["/whisper", "/w", "/msg"];
+const SEALED_WHISPER: &str = "/ewhisper";
+
+pub struct E2e {
+    secret: StaticSecret,
+    public: PublicKey,
+    // Everyone's public key the server told us about, by lowercase name
+    peers: Mutex<HashMap<String, PublicKey>>,
+}
+
+impl E2e {
+    // Our key pair from e2e.key, made (and saved) the first time
+    pub fn load() -> io::Result<Self> {
+        let path = key_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        if let Ok(text) = fs::read_to_string(&path) {
+            let bytes: [u8; 32] = BASE64
+                .decode(text.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a key", path.display())))?;
+            return Ok(E2e::with_secret(bytes));
+        }
+
+        let e2e = E2e::generate();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, BASE64.encode(e2e.secret.to_bytes()))?;
+        // Whoever has the file can read our whispers
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(e2e)
+    }
+
+    // A key pair for this run only, for when e2e.key can't be had (and for tests)
+    pub fn generate() -> Self {
+        E2e::with_secret(rand::rng().random())
+    }
+
+    fn with_secret(bytes: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        E2e { secret, public, peers: Mutex::new(HashMap::new()) }
+    }
+
+    // Our public key as it goes in the handshake
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.public.as_bytes())
+    }
+
+    // Takes in a Key frame: `username` seals with `key` from now on, or (empty) has no key any more
+    pub fn learn(&self, username: &str, key: &str) {
+        let key: Option<[u8; 32]> = BASE64.decode(key).ok().and_then(|bytes| bytes.try_into().ok());
+        let mut peers = self.lock();
+        match key {
+            Some(key) => peers.insert(username.to_lowercase(), PublicKey::from(key)),
+            None => peers.remove(&username.to_lowercase()),
+        };
+    }
+
+    // A whisper line typed for someone whose key we know comes back as "/ewhisper <user> <sealed>",
+    // anything else (or a whisper to someone without one) as it was
+    pub fn seal_line(&self, line: &str) -> String {
+        let mut words = line.splitn(3, ' ');
+        let (Some(command), Some(to), Some(text)) = (words.next(), words.next(), words.next()) else { return line.to_string() };
+        if !WHISPER_COMMANDS.contains(&command) || text.trim().is_empty() {
+            return line.to_string();
+        }
+        match self.seal(to, text.trim()) {
+            Some(sealed) => format!("{} {} {}", SEALED_WHISPER, to, sealed),
+            None => line.to_string(),
+        }
+    }
+
+    // `text` sealed for `to`: base64 of a random nonce and the ciphertext, None when we don't have their key
+    pub fn seal(&self, to: &str, text: &str) -> Option<String> {
+        let cipher = self.cipher(to)?;
+        let nonce: [u8; NONCE_LEN] = rand::rng().random();
+        let mut sealed = nonce.to_vec();
+        sealed.extend(cipher.encrypt(&Nonce::from(nonce), text.as_bytes()).ok()?);
+        Some(BASE64.encode(sealed))
+    }
+
+    // What `peer` sealed for us (or we sealed for them, both ends share the key), None when it doesn't open
+    pub fn open(&self, peer: &str, sealed: &str) -> Option<String> {
+        let sealed = BASE64.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().ok()?;
+        let text = self.cipher(peer)?.decrypt(&Nonce::from(nonce), ciphertext).ok()?;
+        String::from_utf8(text).ok()
+    }
+
+    // The key we share with `peer`: their public key and our secret, hashed
+    fn cipher(&self, peer: &str) -> Option<ChaCha20Poly1305> {
+        let their_key = *self.lock().get(&peer.to_lowercase())?;
+        let shared = self.secret.diffie_hellman(&their_key);
+        ChaCha20Poly1305::new_from_slice(&Sha256::digest(shared.as_bytes())).ok()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, PublicKey>> {
+        self.peers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn key_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("e2e.key"))
+}
@@ -0,0 +1,292 @@
+// Emoji shortcodes like :rocket: and the older emoticons like :)
+// Only standalone ones are replaced: an emoticon has to be a word of its own ("wtf" but not "wtfoo")
+// and a shortcode can't be glued to letters or digits ("10:x:30" stays as typed).
+// The bundled table can be extended (or overridden) in emoji.toml inside the config dir:
+//
+//   party = "🥳"
+//   shipit = "🚢🇮🇹"
+//
+// The server shares shortcodes its users made (emoji or one-line ASCII art) in ShortcodeSync frames,
+// those work like the rest except that a local one of the same name wins.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use super::config_dir;
+
+// Most matches /emoji lists
+pub const MAX_SEARCH_RESULTS: usize = 20;
+// Where the picker puts shortcodes from emoji.toml that aren't bundled ones
+pub const CUSTOM_CATEGORY: &str = "Custom";
+// And the ones shared by the server
+pub const SERVER_CATEGORY: &str = "Server";
+
+// The bundled shortcodes by category, in the order the picker shows them
+const BUNDLED: [(&str, &[(&str, &str)]); 6] = [
+    (
+        "Smileys",
+        &[
+            ("alien", "👽"),
+            ("angry", "😠"),
+            ("cool", "😎"),
+            ("cry", "😢"),
+            ("ghost", "👻"),
+            ("grin", "😁"),
+            ("joy", "😂"),
+            ("laughing", "😆"),
+            ("poop", "💩"),
+            ("robot", "🤖"),
+            ("rofl", "🤣"),
+            ("sad", "😞"),
+            ("scream", "😱"),
+            ("skull", "💀"),
+            ("sleeping", "😴"),
+            ("smile", "😄"),
+            ("smirk", "😏"),
+            ("sunglasses", "😎"),
+            ("thinking", "🤔"),
+            ("tongue", "😛"),
+            ("wink", "😉"),
+        ],
+    ),
+    (
+        "People",
+        &[
+            ("+1", "👍"),
+            ("-1", "👎"),
+            ("clap", "👏"),
+            ("eyes", "👀"),
+            ("facepalm", "🤦"),
+            ("hug", "🤗"),
+            ("muscle", "💪"),
+            ("ok_hand", "👌"),
+            ("pray", "🙏"),
+            ("shrug", "🤷"),
+            ("thumbsdown", "👎"),
+            ("thumbsup", "👍"),
+            ("wave", "👋"),
+        ],
+    ),
+    (
+        "Nature",
+        &[
+            ("bug", "🐛"),
+            ("fire", "🔥"),
+            ("moon", "🌙"),
+            ("planet", "🪐"),
+            ("rainbow", "🌈"),
+            ("sparkles", "✨"),
+            ("star", "⭐"),
+            ("sun", "☀️"),
+            ("zap", "⚡"),
+        ],
+    ),
+    (
+        "Food",
+        &[
+            ("beer", "🍺"),
+            ("cake", "🎂"),
+            ("coffee", "☕"),
+            ("pizza", "🍕"),
+        ],
+    ),
+    (
+        "Objects",
+        &[
+            ("bomb", "💣"),
+            ("party", "🎉"),
+            ("rocket", "🚀"),
+            ("satellite", "🛰️"),
+            ("tada", "🎉"),
+            ("ufo", "🛸"),
+        ],
+    ),
+    (
+        "Symbols",
+        &[
+            ("100", "💯"),
+            ("boom", "💥"),
+            ("check", "✅"),
+            ("heart", "❤️"),
+            ("question", "❓"),
+            ("warning", "⚠️"),
+            ("x", "❌"),
+        ],
+    ),
+];
+
+// Replaced when they make up a whole word
+const EMOTICONS: [(&str, &str); 13] = [
+    (":)", "😊"),
+    (":(", "😢"),
+    (":D", "😄"),
+    ("<3", "❤️"),
+    (":/", "😕"),
+    ("XD", "😂"),
+    ("!?", "❓❗"),
+    ("...", "😶"),
+    (":-)", "😊"),
+    (":-(", "😢"),
+    ("wtf", "🤬"),
+    ("brb", "🏃‍♂️"),
+    (";)", "😉"),
+];
+
+#[derive(Debug)]
+pub struct Emoji {
+    // Shortcode (without the colons) to emoji
+    codes: BTreeMap<String, String>,
+    // Shortcodes from the server, replaced whenever it sends the list again
+    shared: RwLock<BTreeMap<String, String>>,
+    // Off means text is sent exactly as typed
+    enabled: AtomicBool,
+}
+
+impl Emoji {
+    // Just the bundled shortcodes
+    pub fn bundled() -> Self {
+        let codes = BUNDLED
+            .iter()
+            .flat_map(|(_, codes)| codes.iter())
+            .map(|(code, emoji)| (code.to_string(), emoji.to_string()))
+            .collect();
+        Emoji { codes, shared: RwLock::default(), enabled: AtomicBool::new(true) }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // The bundled shortcodes plus the user's emoji.toml, if there is one
+    pub fn load() -> Result<Self, String> {
+        let mut emoji = Emoji::bundled();
+        if let Some(path) = user_file().filter(|path| path.exists()) {
+            emoji.extend_from(&path)?;
+        }
+        Ok(emoji)
+    }
+
+    // Adds the shortcodes in a TOML file (code = "emoji"), replacing bundled ones of the same name
+    pub fn extend_from(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let codes: BTreeMap<String, String> = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (code, emoji) in codes {
+            self.insert(&code, &emoji);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, code: &str, emoji: &str) {
+        self.codes.insert(code.trim_matches(':').to_string(), emoji.to_string());
+    }
+
+    // Takes the server's shortcodes, in place of the ones it sent before
+    pub fn set_shared(&self, codes: impl IntoIterator<Item = (String, String)>) {
+        *self.shared.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = codes.into_iter().collect();
+    }
+
+    pub fn get(&self, code: &str) -> Option<String> {
+        if let Some(emoji) = self.codes.get(code) {
+            return Some(emoji.clone());
+        }
+        self.shared.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(code).cloned()
+    }
+
+    // Shortcodes containing the query (all of them for an empty one), alphabetically
+    pub fn search(&self, query: &str) -> Vec<(String, String)> {
+        let query = query.trim_matches(':').to_lowercase();
+        let shared = self.shared.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut matches: BTreeMap<&str, &str> = shared.iter().map(|(code, text)| (code.as_str(), text.as_str())).collect();
+        matches.extend(self.codes.iter().map(|(code, emoji)| (code.as_str(), emoji.as_str())));
+        matches
+            .into_iter()
+            .filter(|(code, _)| code.contains(&query))
+            .map(|(code, emoji)| (code.to_string(), emoji.to_string()))
+            .collect()
+    }
+
+    // The same matches grouped by category, bundled categories first, then custom and server shortcodes
+    // Categories without a match are left out
+    pub fn search_by_category(&self, query: &str) -> Vec<(&'static str, Vec<(String, String)>)> {
+        let matches = self.search(query);
+        let categories = BUNDLED.iter().map(|(category, _)| *category).chain([CUSTOM_CATEGORY, SERVER_CATEGORY]);
+        categories
+            .map(|category| {
+                let in_category = matches.iter().filter(|(code, _)| self.category_of(code) == category).cloned().collect();
+                (category, in_category)
+            })
+            .filter(|(_, codes): &(_, Vec<_>)| !codes.is_empty())
+            .collect()
+    }
+
+    // Turns standalone :codes: and emoticons into emoji, unless switched off
+    // Unknown :codes: and the whitespace between words are left as typed
+    pub fn expand(&self, text: &str) -> String {
+        if !self.is_enabled() {
+            return text.to_string();
+        }
+        let mut out = String::with_capacity(text.len());
+        for token in text.split_inclusive(char::is_whitespace) {
+            let word = token.trim_end_matches(char::is_whitespace);
+            match EMOTICONS.iter().find(|(emoticon, _)| *emoticon == word) {
+                Some((_, emoji)) => out.push_str(emoji),
+                None => out.push_str(&self.expand_codes(word)),
+            }
+            out.push_str(&token[word.len()..]);
+        }
+        out
+    }
+
+    fn expand_codes(&self, word: &str) -> String {
+        let mut out = String::with_capacity(word.len());
+        let mut rest = word;
+        while let Some(start) = rest.find(':') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let glued_before = out.chars().last().is_some_and(char::is_alphanumeric);
+            let code = after
+                .find(':')
+                .map(|end| &after[..end])
+                .filter(|code| !glued_before && !after[code.len() + 1..].starts_with(char::is_alphanumeric));
+            match code.and_then(|code| self.get(code).map(|emoji| (code, emoji))) {
+                Some((code, emoji)) => {
+                    out.push_str(&emoji);
+                    rest = &after[code.len() + 1..];
+                }
+                None => {
+                    // Not a code, but the next colon may still start one ("::rocket:")
+                    out.push(':');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn category_of(&self, code: &str) -> &'static str {
+        let bundled = BUNDLED.iter().find(|(_, codes)| codes.iter().any(|(bundled, _)| *bundled == code));
+        match bundled {
+            Some((category, _)) => category,
+            None if self.codes.contains_key(code) => CUSTOM_CATEGORY,
+            None => SERVER_CATEGORY,
+        }
+    }
+}
+
+impl Default for Emoji {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+fn user_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("emoji.toml"))
+}
@@ -0,0 +1,147 @@
+// /export: the message pane as it is now, written to a file to share a stretch of the conversation
+// As plain text (what the pane reads), JSON (the messages in it, as the server sent them) or an HTML page
+// in the pane's own colours, names included:
+//
+//   /export                                  chat-2026-10-16-143205.txt in the directory the client started in
+//   /export standup.html                     the format goes by the extension...
+//   /export standup --format json            ...unless --format says which
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use cursive::theme::{BaseColor, Color, ColorPair, ConcreteStyle, Effect, Palette, PaletteColor};
+use cursive::utils::markup::StyledString;
+
+use crate::protocol::ChatMessage;
+
+pub const USAGE: &str = "Usage: /export [path] [--format txt|json|html]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Txt,
+    Json,
+    Html,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "txt" | "text" => Some(Format::Txt),
+            "json" => Some(Format::Json),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Txt => "txt",
+            Format::Json => "json",
+            Format::Html => "html",
+        }
+    }
+}
+
+// Where /export writes and how, from what follows it
+pub fn parse_args(args: &str) -> Result<(PathBuf, Format), String> {
+    let mut path = None;
+    let mut format = None;
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "--format" => {
+                let name = words.next().ok_or(USAGE)?;
+                format = Some(Format::parse(name).ok_or(format!("{:?} isn't txt, json or html", name))?);
+            }
+            _ if path.is_none() && !word.starts_with("--") => path = Some(PathBuf::from(word)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let format = format
+        .or_else(|| path.as_ref().and_then(|path: &PathBuf| path.extension()?.to_str().and_then(Format::parse)))
+        .unwrap_or(Format::Txt);
+    let path = path.unwrap_or_else(|| PathBuf::from(format!("chat-{}.{}", Local::now().format("%Y-%m-%d-%H%M%S"), format.extension())));
+    Ok((path, format))
+}
+
+// The pane's `content` and the `messages` in it, written to `path`
+pub fn write(path: &Path, format: Format, content: &StyledString, messages: &[&ChatMessage], palette: &Palette) -> Result<(), String> {
+    let text = match format {
+        Format::Txt => content.source().to_string(),
+        Format::Json => serde_json::to_string_pretty(messages).map_err(|e| e.to_string())?,
+        Format::Html => html(content, palette),
+    };
+    fs::write(path, text).map_err(|e| format!("writing {}: {}", path.display(), e))
+}
+
+// A page with the pane's text in <pre>, each span in its own colours and effects
+pub fn html(content: &StyledString, palette: &Palette) -> String {
+    let base = ConcreteStyle { effects: Default::default(), color: ColorPair { front: palette[PaletteColor::Primary], back: palette[PaletteColor::View] } };
+    let mut body = String::new();
+    for span in content.spans() {
+        let style = span.attr.resolve(palette, base);
+        let mut css = Vec::new();
+        if let Some(front) = css_color(style.color.front).filter(|_| style.color.front != base.color.front) {
+            css.push(format!("color:{}", front));
+        }
+        if let Some(back) = css_color(style.color.back).filter(|_| style.color.back != base.color.back) {
+            css.push(format!("background:{}", back));
+        }
+        if style.effects.contains(Effect::Bold) {
+            css.push("font-weight:bold".to_string());
+        }
+        if style.effects.contains(Effect::Italic) {
+            css.push("font-style:italic".to_string());
+        }
+        if style.effects.contains(Effect::Underline) {
+            css.push("text-decoration:underline".to_string());
+        }
+        if style.effects.contains(Effect::Strikethrough) {
+            css.push("text-decoration:line-through".to_string());
+        }
+        if css.is_empty() {
+            body.push_str(&escape(span.content));
+        } else {
+            body.push_str(&format!("<span style=\"{}\">{}</span>", css.join(";"), escape(span.content)));
+        }
+    }
+    let page = [css_color(base.color.back).map(|back| format!("background:{}", back)), css_color(base.color.front).map(|front| format!("color:{}", front))];
+    let page: Vec<String> = page.into_iter().flatten().collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Chat, {}</title>\n</head>\n<body style=\"{}\">\n<pre>{}</pre>\n</body>\n</html>\n",
+        Local::now().format("%Y-%m-%d %H:%M"),
+        page.join(";"),
+        body
+    )
+}
+
+// "#rrggbb", None for the terminal's own colour
+fn css_color(color: Color) -> Option<String> {
+    let (r, g, b) = match color {
+        Color::TerminalDefault => return None,
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::RgbLowRes(r, g, b) => (r * 51, g * 51, b * 51),
+        Color::Dark(base) => xterm(base, false),
+        Color::Light(base) => xterm(base, true),
+    };
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+// The xterm defaults for the eight colours, dark and light
+fn xterm(base: BaseColor, light: bool) -> (u8, u8, u8) {
+    let (on, off) = if light { (255, 85) } else { (205, 0) };
+    match base {
+        BaseColor::Black if light => (127, 127, 127),
+        BaseColor::White if !light => (229, 229, 229),
+        base => {
+            let bits = base as u8;
+            let pick = |bit: u8| if bits & bit != 0 { on } else { off };
+            (pick(1), pick(2), pick(4))
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,85 @@
+// People whose messages aren't shown, set with /ignore <user> and /unignore <user>
+// What they say (messages, /me actions, whispers) is dropped as it arrives, and so are their
+// "has landed" / "joined #room" notices unless ignore_joins is off in config.toml.
+// Nothing is sent to the server: they can still see us, and their messages are still in the history.
+// The list is kept in config.toml as `ignore = [...]`, saved whenever it changes.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::protocol::{ChatMessage, MessageType};
+
+use super::settings::{self, Settings};
+
+#[derive(Debug, Default)]
+pub struct IgnoreList {
+    // Names in lowercase, as the server treats them
+    users: RwLock<BTreeSet<String>>,
+    joins: bool,
+    // Where the list is saved, None keeps it for this run only
+    file: Option<PathBuf>,
+}
+
+impl IgnoreList {
+    // The list from config.toml, saved back there when it changes
+    pub fn load(settings: &Settings) -> Self {
+        let mut ignore = IgnoreList::new(settings.ignore.iter().cloned(), settings.ignore_joins);
+        ignore.file = settings::settings_file();
+        ignore
+    }
+
+    // A list that isn't saved anywhere
+    pub fn new(users: impl IntoIterator<Item = String>, joins: bool) -> Self {
+        let users = users.into_iter().map(|user| user.to_lowercase()).collect();
+        IgnoreList { users: RwLock::new(users), joins, file: None }
+    }
+
+    // Whether the user was ignored already, Err when the list couldn't be saved (it still changed)
+    pub fn add(&self, user: &str) -> Result<bool, String> {
+        let added = self.write().insert(user.to_lowercase());
+        if added {
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    // Whether the user was ignored at all, Err as for add()
+    pub fn remove(&self, user: &str) -> Result<bool, String> {
+        let removed = self.write().remove(&user.to_lowercase());
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn users(&self) -> Vec<String> {
+        self.read().iter().cloned().collect()
+    }
+
+    pub fn is_ignored(&self, user: &str) -> bool {
+        self.read().contains(&user.to_lowercase())
+    }
+
+    // Whether a frame is an ignored user talking (or coming and going) and shouldn't be shown
+    pub fn hides(&self, msg: &ChatMessage) -> bool {
+        match msg.message_type {
+            MessageType::UserMessage | MessageType::Action | MessageType::Whisper => self.is_ignored(&msg.username),
+            MessageType::SystemNotification => self.joins && self.is_ignored(&msg.username),
+            _ => false,
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else { return Ok(()) };
+        settings::save_value(path, "ignore", self.users().into())
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, BTreeSet<String>> {
+        self.users.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeSet<String>> {
+        self.users.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,215 @@
+// Keys for what the chat window does, with the defaults changed by [keys] in config.toml
+// /keys lists what they are. A key is a character ("/", "q"), a key name (esc, enter, tab, pageup, pagedown,
+// home, end, up, down, left, right, f1..f12) or either with ctrl+, alt+ or shift+ in front; "none" unbinds one:
+//
+//   [keys]
+//   quit = "f12"
+//   focus_input = "none"
+//   next_tab = "ctrl+pagedown"
+//
+// The input box keeps the keys it edits with (ctrl+a, ctrl+u, the arrows, ...) unless the emoji picker
+// is bound to one of them, Esc still closes dialogs first, and the keys of navigation mode (j, k, gg, G, ...)
+// are fixed.
+
+use std::collections::BTreeMap;
+
+use cursive::event::{Event, Key};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    // Leaves the input box for navigation mode, see ui::MessagePane
+    Navigate,
+    // Puts the cursor in the input box, "/" starts a command there too
+    FocusInput,
+    PageUp,
+    PageDown,
+    NextTab,
+    PrevTab,
+    EmojiPicker,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::Quit,
+        Action::Navigate,
+        Action::FocusInput,
+        Action::PageUp,
+        Action::PageDown,
+        Action::NextTab,
+        Action::PrevTab,
+        Action::EmojiPicker,
+    ];
+
+    // Its name under [keys]
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Navigate => "navigate",
+            Action::FocusInput => "focus_input",
+            Action::PageUp => "page_up",
+            Action::PageDown => "page_down",
+            Action::NextTab => "next_tab",
+            Action::PrevTab => "prev_tab",
+            Action::EmojiPicker => "emoji_picker",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Navigate => "Leave the input box to read back (navigation mode)",
+            Action::FocusInput => "Back to the input box",
+            Action::PageUp => "Scroll the messages up a page",
+            Action::PageDown => "Scroll the messages down a page",
+            Action::NextTab => "Next room tab",
+            Action::PrevTab => "Previous room tab",
+            Action::EmojiPicker => "Pick an emoji",
+        }
+    }
+
+    fn default_key(self) -> Event {
+        match self {
+            // Esc was quit once, and quit was one slip away
+            Action::Quit => Event::CtrlChar('q'),
+            Action::Navigate => Event::Key(Key::Esc),
+            Action::FocusInput => Event::Char('/'),
+            Action::PageUp => Event::Key(Key::PageUp),
+            Action::PageDown => Event::Key(Key::PageDown),
+            Action::NextTab => Event::Alt(Key::Right),
+            Action::PrevTab => Event::Alt(Key::Left),
+            Action::EmojiPicker => Event::CtrlChar('e'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    // In Action::ALL order, None for the ones unbound
+    keys: Vec<(Action, Option<Event>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap { keys: Action::ALL.iter().map(|&action| (action, Some(action.default_key()))).collect() }
+    }
+}
+
+impl Keymap {
+    // The defaults with [keys] from config.toml over them
+    pub fn new(config: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut keymap = Keymap::default();
+        for (name, key) in config {
+            let Some(action) = Action::ALL.into_iter().find(|action| action.name() == name) else {
+                let names: Vec<&str> = Action::ALL.iter().map(|action| action.name()).collect();
+                return Err(format!("keys.{} isn't something a key can do, try {}", name, names.join(", ")));
+            };
+            let event = parse_key(key).map_err(|e| format!("keys.{}: {}", name, e))?;
+            keymap.keys.iter_mut().filter(|(bound, _)| *bound == action).for_each(|(_, bound)| *bound = event.clone());
+        }
+        for (i, (action, event)) in keymap.keys.iter().enumerate() {
+            let Some(event) = event else { continue };
+            if let Some((other, _)) = keymap.keys[i + 1..].iter().find(|(_, bound)| bound.as_ref() == Some(event)) {
+                return Err(format!("{} is bound to both {} and {}", key_name(event), action.name(), other.name()));
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn key(&self, action: Action) -> Option<&Event> {
+        self.keys.iter().find(|(bound, _)| *bound == action).and_then(|(_, event)| event.as_ref())
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (Action, Option<&Event>)> {
+        self.keys.iter().map(|(action, event)| (*action, event.as_ref()))
+    }
+}
+
+// "ctrl+e", "alt+right", "pagedown", "/" or "none"
+pub fn parse_key(text: &str) -> Result<Option<Event>, String> {
+    let lower = text.trim().to_lowercase();
+    if lower == "none" {
+        return Ok(None);
+    }
+    // "+" on its own, or at the end as in "ctrl++", is the key itself
+    let (mods, key) = match lower.strip_suffix("++") {
+        Some(mods) => (mods, "+"),
+        None => lower.rsplit_once('+').filter(|(_, key)| !key.is_empty()).unwrap_or(("", &lower)),
+    };
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    for modifier in mods.split('+').filter(|modifier| !modifier.is_empty()) {
+        match modifier {
+            "ctrl" => ctrl = true,
+            "alt" => alt = true,
+            "shift" => shift = true,
+            other => return Err(format!("{:?} isn't ctrl, alt or shift", other)),
+        }
+    }
+    let unknown = || format!("{:?} isn't a key", text);
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // Keep the case of a plain character, "Q" and "q" are different keys
+        let c = if ctrl || alt { c } else { text.trim().chars().last().unwrap_or(c) };
+        return match (ctrl, alt, shift) {
+            (false, false, false) => Ok(Some(Event::Char(c))),
+            (true, false, false) if c.is_ascii_lowercase() => Ok(Some(Event::CtrlChar(c))),
+            (false, true, false) => Ok(Some(Event::AltChar(c))),
+            _ => Err(format!("{:?} can't be told apart by the terminal", text)),
+        };
+    }
+    let key = match key {
+        "esc" | "escape" => Key::Esc,
+        "enter" => Key::Enter,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "del" | "delete" => Key::Del,
+        "ins" | "insert" => Key::Ins,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" | "pgup" => Key::PageUp,
+        "pagedown" | "pgdn" => Key::PageDown,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        f => match f.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n @ 1..=12) => Key::from_f(n),
+            _ => return Err(unknown()),
+        },
+    };
+    Ok(Some(match (ctrl, alt, shift) {
+        (false, false, false) => Event::Key(key),
+        (true, false, false) => Event::Ctrl(key),
+        (false, true, false) => Event::Alt(key),
+        (false, false, true) => Event::Shift(key),
+        (true, true, false) => Event::CtrlAlt(key),
+        (true, false, true) => Event::CtrlShift(key),
+        (false, true, true) => Event::AltShift(key),
+        (true, true, true) => return Err(format!("{:?} is one modifier too many", text)),
+    }))
+}
+
+// How a key is written in /keys: "Ctrl+E", "Alt+Right", "PageDown", "/"
+pub fn key_name(event: &Event) -> String {
+    match event {
+        Event::Char(c) => c.to_string(),
+        Event::CtrlChar(c) => format!("Ctrl+{}", c.to_ascii_uppercase()),
+        Event::AltChar(c) => format!("Alt+{}", c),
+        Event::Key(key) => named(*key),
+        Event::Ctrl(key) => format!("Ctrl+{}", named(*key)),
+        Event::Alt(key) => format!("Alt+{}", named(*key)),
+        Event::Shift(key) => format!("Shift+{}", named(*key)),
+        Event::CtrlAlt(key) => format!("Ctrl+Alt+{}", named(*key)),
+        Event::CtrlShift(key) => format!("Ctrl+Shift+{}", named(*key)),
+        Event::AltShift(key) => format!("Alt+Shift+{}", named(*key)),
+        other => format!("{:?}", other),
+    }
+}
+
+fn named(key: Key) -> String {
+    match key {
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        other => format!("{:?}", other),
+    }
+}
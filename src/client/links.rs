@@ -0,0 +1,48 @@
+// Links in chat messages: found by markup (which numbers them as it shows them), opened in the
+// user's browser with /open <n> (or a click) and put on the clipboard with /copyurl <n>
+// Only http:// and https:// links count, anything else could start who knows what.
+
+use std::io;
+
+const SCHEMES: [&str; 2] = ["https://", "http://"];
+
+// Punctuation that ends a sentence rather than the link ("see https://example.com.")
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', '*', '_'];
+
+// Length of the link `text` starts with, None when it doesn't start with one
+pub fn url_len(text: &str) -> Option<usize> {
+    let scheme = SCHEMES.iter().find(|scheme| text.starts_with(*scheme))?;
+    let end = text.find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '`')).unwrap_or(text.len());
+    let mut url = &text[..end];
+    loop {
+        // A closing bracket belongs to the link only when it opened one, as in wikipedia's (disambiguation) pages
+        let unbalanced = |open, close| url.ends_with(close) && url.matches(open).count() < url.matches(close).count();
+        if url.ends_with(TRAILING) || unbalanced('(', ')') || unbalanced('[', ']') {
+            url = &url[..url.len() - 1];
+        } else {
+            break;
+        }
+    }
+    (url.len() > scheme.len()).then_some(url.len())
+}
+
+// The link shown at byte `at` of a line as markup draws it ("https://example.com[3]"), without its number
+pub fn link_at(line: &str, at: usize) -> Option<String> {
+    let word_start = line[..at.min(line.len())].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let start = SCHEMES.iter().filter_map(|scheme| line[word_start..].find(scheme)).min()? + word_start;
+    let url = &line[start..start + url_len(&line[start..])?];
+    if at < start || at >= start + url.len() {
+        return None;
+    }
+    // The [n] after it is part of what was clicked, not of the link
+    let url = match url.strip_suffix(']').and_then(|rest| rest.rsplit_once('[')) {
+        Some((url, number)) if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => url,
+        _ => url,
+    };
+    Some(url.to_string())
+}
+
+// Hands the link to whatever opens links on this system, without waiting for it
+pub fn open(url: &str) -> io::Result<()> {
+    open::that_detached(url)
+}
@@ -0,0 +1,275 @@
+// Markdown-lite for chat messages: *bold* (or **bold**), _italic_, `inline code` and "> " quotes
+// Markers only count at word boundaries, so snake_case_names and 2*3*4 are shown as typed.
+// Links are underlined and numbered for /open and /copyurl.
+// ``` fenced blocks are drawn in a box and highlighted by their language tag, long ones are cut short
+// until /expand asks for the rest.
+// /raw switches it off to see messages exactly as they were sent.
+// Stamps are shown relative to today ("today 14:32", "yesterday 09:10") unless absolute times are asked for.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use cursive::theme::{BaseColor, Color, Effect, Style};
+use cursive::utils::markup::StyledString;
+use super::links;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+// Emphasis markers, longest first so ** isn't read as two *
+const EMPHASIS: [(&str, Effect); 3] = [("**", Effect::Bold), ("*", Effect::Bold), ("_", Effect::Italic)];
+const QUOTE_BAR: &str = "│ ";
+
+const FENCE: &str = "```";
+// Lines of a code block shown before it is cut short
+pub const COLLAPSED_LINES: usize = 12;
+// Cut-short blocks remembered for /expand, the oldest are forgotten first
+const MAX_COLLAPSED: usize = 50;
+// Same for links and /open
+const MAX_LINKS: usize = 100;
+// Width of a code block's frame, whatever the code
+const MIN_BOX_WIDTH: usize = 20;
+const MAX_BOX_WIDTH: usize = 76;
+const SYNTAX_THEME: &str = "base16-ocean.dark";
+
+#[derive(Debug, Default)]
+pub struct Markup {
+    // On means messages are shown exactly as sent
+    raw: AtomicBool,
+    // On means stamps are shown in full rather than as today/yesterday
+    absolute_times: AtomicBool,
+    // Cut-short code blocks as (number, (language, code)), newest last
+    collapsed: Mutex<VecDeque<(usize, (String, String))>>,
+    // Links shown so far as (number, url), newest last
+    links: Mutex<VecDeque<(usize, String)>>,
+}
+
+impl Markup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_raw(&self, raw: bool) {
+        self.raw.store(raw, Ordering::Relaxed);
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.raw.load(Ordering::Relaxed)
+    }
+
+    pub fn set_absolute_times(&self, absolute: bool) {
+        self.absolute_times.store(absolute, Ordering::Relaxed);
+    }
+
+    pub fn absolute_times(&self) -> bool {
+        self.absolute_times.load(Ordering::Relaxed)
+    }
+
+    // The text styled line by line on top of `base` (or just in `base` while raw), one row per line
+    // Leading whitespace is left as it is, it's somebody's indentation
+    pub fn render(&self, text: &str, base: Style) -> Vec<StyledString> {
+        if self.is_raw() {
+            return text.split('\n').map(|line| StyledString::styled(line, base)).collect();
+        }
+        let mut rows = Vec::new();
+        let mut lines = text.split('\n');
+        while let Some(line) = lines.next() {
+            if let Some(lang) = line.trim().strip_prefix(FENCE).filter(|lang| !lang.contains('`')) {
+                // Everything up to the closing fence (or the end, if there is none)
+                let code: Vec<&str> = lines.by_ref().take_while(|line| line.trim() != FENCE).collect();
+                if rows.is_empty() {
+                    rows.push(StyledString::new()); // the box starts on a line of its own
+                }
+                rows.extend(self.code_block(lang.trim(), &code, true));
+                continue;
+            }
+            rows.push(match line.strip_prefix("> ").or_else(|| (line == ">").then_some("")) {
+                Some(quoted) => {
+                    let quote = base.combine(Color::Dark(BaseColor::White)).combine(Effect::Italic);
+                    let mut row = StyledString::styled(QUOTE_BAR, quote);
+                    row.append(self.inline(quoted, quote));
+                    row
+                }
+                None => self.inline(line, base),
+            });
+        }
+        rows
+    }
+
+    // A block that was cut short, in full (None once it has been forgotten)
+    pub fn expand(&self, number: usize) -> Option<StyledString> {
+        let collapsed = self.collapsed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (_, (lang, code)) = collapsed.iter().find(|(n, _)| *n == number)?;
+        let lines: Vec<&str> = code.split('\n').collect();
+        Some(join_rows(self.code_block(lang, &lines, false)))
+    }
+
+    // The block framed and highlighted, one row per line
+    // With `collapse` only the first COLLAPSED_LINES are shown and the block is kept for /expand
+    fn code_block(&self, lang: &str, code: &[&str], collapse: bool) -> Vec<StyledString> {
+        let frame = Style::from(Color::Dark(BaseColor::White));
+        let shown = if collapse { &code[..code.len().min(COLLAPSED_LINES)] } else { code };
+        let code_width = shown.iter().map(|line| untab(line).chars().count()).max().unwrap_or(0);
+        let width = code_width.clamp(MIN_BOX_WIDTH, MAX_BOX_WIDTH);
+
+        let label = if lang.is_empty() { "code" } else { lang };
+        let mut top = StyledString::styled("┌─ ", frame);
+        top.append_styled(label, frame.combine(Effect::Bold));
+        top.append_styled(format!(" {}", "─".repeat(width.saturating_sub(label.chars().count() + 1))), frame);
+        let mut rows = vec![top];
+        for line in highlight(lang, shown) {
+            let mut row = StyledString::styled("│ ", frame);
+            row.append(line);
+            rows.push(row);
+        }
+        if shown.len() < code.len() {
+            let number = remember(&self.collapsed, (lang.to_string(), code.join("\n")), MAX_COLLAPSED);
+            let hidden = code.len() - shown.len();
+            let mut row = StyledString::styled("│ ", frame);
+            row.append_styled(
+                format!("… {} more line{}, /expand {} to see them", hidden, if hidden == 1 { "" } else { "s" }, number),
+                Color::Light(BaseColor::Yellow),
+            );
+            rows.push(row);
+        }
+        rows.push(StyledString::styled(format!("└{}", "─".repeat(width + 2)), frame));
+        rows
+    }
+
+    // The link shown with [number] (None once it has been forgotten)
+    pub fn link(&self, number: usize) -> Option<String> {
+        let links = self.links.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        links.iter().find(|(n, _)| *n == number).map(|(_, url)| url.clone())
+    }
+
+    // Emphasis, code spans and links within one line
+    fn inline(&self, text: &str, style: Style) -> StyledString {
+        let mut styled = StyledString::new();
+        let mut plain_from = 0;
+        let mut i = 0;
+        while i < text.len() {
+            let rest = &text[i..];
+            let at_boundary = !text[..i].chars().next_back().is_some_and(char::is_alphanumeric);
+            let span = if let Some(after) = rest.strip_prefix('`') {
+                // Nothing inside code is interpreted
+                after.find('`').filter(|&len| len > 0).map(|len| {
+                    let code = StyledString::styled(&after[..len], style.combine(Color::Light(BaseColor::Green)));
+                    (code, len + 2)
+                })
+            } else if let Some(len) = links::url_len(rest).filter(|_| at_boundary) {
+                let url = &rest[..len];
+                let number = remember(&self.links, url.to_string(), MAX_LINKS);
+                let mut link = StyledString::styled(url, style.combine(Color::Light(BaseColor::Blue)).combine(Effect::Underline));
+                link.append_styled(format!("[{}]", number), style.combine(Color::Dark(BaseColor::White)));
+                Some((link, len))
+            } else {
+                EMPHASIS.iter().filter(|(marker, _)| rest.starts_with(marker)).find_map(|(marker, effect)| {
+                    let len = closing(text, i, marker)?;
+                    let inner = &text[i + marker.len()..i + marker.len() + len];
+                    Some((self.inline(inner, style.combine(*effect)), len + 2 * marker.len()))
+                })
+            };
+            match span {
+                Some((span, consumed)) => {
+                    styled.append_styled(&text[plain_from..i], style);
+                    styled.append(span);
+                    i += consumed;
+                    plain_from = i;
+                }
+                None => i += rest.chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        styled.append_styled(&text[plain_from..], style);
+        styled
+    }
+}
+
+// Keeps something for a command to find by number later and returns the number, forgetting the oldest past `max`
+fn remember<T>(list: &Mutex<VecDeque<(usize, T)>>, item: T, max: usize) -> usize {
+    let mut list = list.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let number = list.back().map_or(1, |(n, _)| n + 1);
+    if list.len() == max {
+        list.pop_front();
+    }
+    list.push_back((number, item));
+    number
+}
+
+fn join_rows(rows: Vec<StyledString>) -> StyledString {
+    let mut styled = StyledString::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        if i > 0 {
+            styled.append_plain("\n");
+        }
+        styled.append(row);
+    }
+    styled
+}
+
+// Syntax definitions and the colour theme, loaded the first time a block is shown
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: syntect::highlighting::Theme,
+}
+
+fn highlighter() -> &'static Highlighter {
+    static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+    HIGHLIGHTER.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_nonewlines(),
+            theme: themes.remove(SYNTAX_THEME).unwrap_or_default(),
+        }
+    })
+}
+
+// Colours code by its language tag ("rust", "py", "sh", ...), unknown ones stay plain
+fn highlight(lang: &str, lines: &[&str]) -> Vec<StyledString> {
+    let highlighter = highlighter();
+    let syntax = highlighter
+        .syntaxes
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| highlighter.syntaxes.find_syntax_plain_text());
+    let mut lines_highlighter = HighlightLines::new(syntax, &highlighter.theme);
+    lines
+        .iter()
+        .map(|line| {
+            let line = untab(line);
+            match lines_highlighter.highlight_line(&line, &highlighter.syntaxes) {
+                Ok(ranges) => {
+                    let mut styled = StyledString::new();
+                    for (style, text) in ranges {
+                        let color = style.foreground;
+                        styled.append_styled(text, Color::Rgb(color.r, color.g, color.b));
+                    }
+                    styled
+                }
+                Err(_) => StyledString::plain(line.as_str()),
+            }
+        })
+        .collect()
+}
+
+// Tabs as four spaces, so the frame lines up
+fn untab(line: &str) -> String {
+    line.replace('\t', "    ")
+}
+
+// Length of the text between a marker opening at `start` and the one closing it, if it does open a span:
+// the opening marker follows a word boundary and touches text, the closing one the other way round
+fn closing(text: &str, start: usize, marker: &str) -> Option<usize> {
+    let opens_after = text[..start].chars().next_back();
+    let inner_start = start + marker.len();
+    let first = text[inner_start..].chars().next()?;
+    if opens_after.is_some_and(char::is_alphanumeric) || first.is_whitespace() {
+        return None;
+    }
+    text[inner_start..].match_indices(marker).map(|(len, _)| len).find(|&len| {
+        let inner = &text[inner_start..inner_start + len];
+        let after = text[inner_start + len + marker.len()..].chars().next();
+        !inner.is_empty()
+            && !inner.ends_with(char::is_whitespace)
+            && !after.is_some_and(|c| c.is_alphanumeric() || marker.starts_with(c))
+    })
+}
@@ -0,0 +1,56 @@
+// Client side building blocks
+// chatlog: what's shown, appended to a log file a day for grepping later
+// clipboard: copying links and messages to the system clipboard
+// connection: headless ChatClient (connect, send, receive frames) for the TUI and for bots
+// e2e: whispers sealed end to end, with a key pair kept in the config dir
+// emoji: :shortcode: and emoticon expansion, extensible with emoji.toml
+// export: the message pane saved as text, JSON or HTML (/export)
+// ignore: people whose messages aren't shown, kept in config.toml
+// keymap: which keys do what, from [keys] in config.toml
+// links: opening and copying the links found in messages
+// markup: *bold*, _italic_, `code` and > quotes in messages, numbered links
+// notify: noticing when someone says our name or whispers to us
+// outbox: lines not sent yet, kept on disk until they are
+// quic: connecting over QUIC, experimental (quic feature)
+// reconnect: decides what to do after the connection drops
+// session: session tokens remembered between runs, in the OS keychain
+// settings: client preferences from config.toml
+// signing: messages signed with a key pair kept in the config dir, so nobody else can pass for us
+// socks: connecting through a SOCKS5 proxy (--proxy)
+// themes: the built-in colour themes and theme files of our own
+// ui: the cursive chat window, independent of the connection
+// writer: the task every outgoing line goes through, reporting what didn't make it
+
+pub mod chatlog;
+pub mod clipboard;
+pub mod connection;
+pub mod e2e;
+pub mod emoji;
+pub mod export;
+pub mod ignore;
+pub mod keymap;
+pub mod links;
+pub mod markup;
+pub mod notify;
+pub mod outbox;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod reconnect;
+pub mod session;
+pub mod settings;
+pub mod signing;
+pub mod socks;
+pub mod themes;
+pub mod ui;
+pub mod writer;
+
+use std::env;
+use std::path::PathBuf;
+
+// Directory for the client's own files: $XDG_CONFIG_HOME/rust-chat or ~/.config/rust-chat
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("rust-chat"))
+}
@@ -0,0 +1,161 @@
+// Telling us when we're wanted: someone saying our name (or @name), whispering to us, or saying a keyword
+// we watch for (/notify add deploy)
+// Whether that's worth a toast is up to the UI (not for the room we're looking at), the terminal bell
+// rings for it here when [notifications] bell is on, and a desktop notification goes up when desktop is.
+// The switches are in config.toml, /set changes them; the keywords are under [notifications.keywords].
+// Do not disturb (/dnd) silences all of it until it's switched off again.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::protocol::{ChatMessage, MessageType};
+
+use super::settings::Notifications;
+
+// A message stamped longer ago than this is history being replayed
+const FRESH_SECONDS: i64 = 60;
+
+// As much of a message as a desktop notification shows
+const ALERT_CHARS: usize = 200;
+
+#[derive(Debug, Default)]
+pub struct Notifier {
+    mentions: AtomicBool,
+    bell: AtomicBool,
+    desktop: AtomicBool,
+    dnd: AtomicBool,
+    // Keywords in lowercase, and whether each is switched on
+    keywords: RwLock<BTreeMap<String, bool>>,
+    // Who we are, a sign-in can change it
+    name: RwLock<String>,
+}
+
+impl Notifier {
+    pub fn new(name: &str, settings: &Notifications) -> Self {
+        Notifier {
+            mentions: AtomicBool::new(settings.mentions),
+            bell: AtomicBool::new(settings.bell),
+            desktop: AtomicBool::new(settings.desktop),
+            dnd: AtomicBool::new(false),
+            keywords: RwLock::new(settings.keywords.iter().map(|(keyword, on)| (keyword.to_ascii_lowercase(), *on)).collect()),
+            name: RwLock::new(name.to_string()),
+        }
+    }
+
+    pub fn set_name(&self, name: &str) {
+        *self.name.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = name.to_string();
+    }
+
+    pub fn set_mentions(&self, on: bool) {
+        self.mentions.store(on, Ordering::Relaxed);
+    }
+
+    pub fn mentions(&self) -> bool {
+        self.mentions.load(Ordering::Relaxed)
+    }
+
+    pub fn set_bell(&self, on: bool) {
+        self.bell.store(on, Ordering::Relaxed);
+    }
+
+    pub fn bell(&self) -> bool {
+        self.bell.load(Ordering::Relaxed)
+    }
+
+    pub fn set_desktop(&self, on: bool) {
+        self.desktop.store(on, Ordering::Relaxed);
+    }
+
+    pub fn desktop(&self) -> bool {
+        self.desktop.load(Ordering::Relaxed)
+    }
+
+    // Do not disturb: no bell and no desktop notifications, and the UI holds back what it would say
+    pub fn set_dnd(&self, on: bool) {
+        self.dnd.store(on, Ordering::Relaxed);
+    }
+
+    pub fn dnd(&self) -> bool {
+        self.dnd.load(Ordering::Relaxed)
+    }
+
+    // Whether we sent a frame ourselves
+    pub fn from_us(&self, msg: &ChatMessage) -> bool {
+        msg.username.eq_ignore_ascii_case(&self.name.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    // Every keyword, and whether it's on
+    pub fn keywords(&self) -> BTreeMap<String, bool> {
+        self.keywords.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    // Adds a keyword, or switches one on or off. Whether it was there before
+    pub fn set_keyword(&self, keyword: &str, on: bool) -> bool {
+        self.keywords.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(keyword.to_ascii_lowercase(), on).is_some()
+    }
+
+    // Whether the keyword was there to remove
+    pub fn remove_keyword(&self, keyword: &str) -> bool {
+        self.keywords.write().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&keyword.to_ascii_lowercase()).is_some()
+    }
+
+    // The keywords switched on that someone else's message says, any case. Old messages count too,
+    // they are highlighted all the same
+    pub fn keywords_in(&self, msg: &ChatMessage) -> Vec<String> {
+        if self.from_us(msg) || !matches!(msg.message_type, MessageType::UserMessage | MessageType::Action) {
+            return Vec::new();
+        }
+        let content = msg.content.to_ascii_lowercase();
+        let keywords = self.keywords.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        keywords.iter().filter(|(keyword, on)| **on && !keyword.is_empty() && content.contains(keyword.as_str())).map(|(keyword, _)| keyword.clone()).collect()
+    }
+
+    // Whether a frame arrived just now, rather than with a room's history
+    pub fn is_fresh(&self, msg: &ChatMessage) -> bool {
+        let stamped = DateTime::parse_from_rfc3339(&msg.timestamp).map(|at| at.with_timezone(&Utc));
+        !stamped.is_ok_and(|at| Utc::now() - at > TimeDelta::seconds(FRESH_SECONDS))
+    }
+
+    // Whether a frame is someone else calling on us: a whisper, or our name as a word of what they said
+    pub fn wants_us(&self, msg: &ChatMessage) -> bool {
+        let name = self.name.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !self.mentions() || name.is_empty() || msg.username.eq_ignore_ascii_case(&name) {
+            return false;
+        }
+        // What comes back with a room's history called on us before, not now
+        if !self.is_fresh(msg) {
+            return false;
+        }
+        match msg.message_type {
+            MessageType::Whisper => true,
+            MessageType::UserMessage | MessageType::Action => msg
+                .content
+                .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .any(|word| word.eq_ignore_ascii_case(&name)),
+            _ => false,
+        }
+    }
+
+    // Rings the terminal bell, if it's on
+    pub fn ring(&self) {
+        if self.bell() && !self.dnd() {
+            let mut out = std::io::stdout();
+            let _ = out.write_all(b"\x07");
+            let _ = out.flush();
+        }
+    }
+
+    // A desktop notification through the terminal (OSC 9, which most of them turn into one), if they're on
+    pub fn alert(&self, title: &str, text: &str) {
+        if self.desktop() && !self.dnd() {
+            let text: String = format!("{}: {}", title, text).chars().filter(|c| !c.is_control()).take(ALERT_CHARS).collect();
+            let mut out = std::io::stdout();
+            let _ = write!(out, "\x1b]9;{}\x07", text);
+            let _ = out.flush();
+        }
+    }
+}
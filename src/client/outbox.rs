@@ -0,0 +1,133 @@
+// Messages typed but not sent yet, kept on disk so a crash or an outage doesn't lose them
+// Every line is written to outbox.json (keyed by "username@server", like sessions.json) before it goes out
+// and taken off once it has, so whatever is still there at startup never made it and can be offered again.
+// Each line keeps the room it was typed in, for /purge-local. Commands carrying passwords are never written down.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use super::config_dir;
+
+// Same list as the UI's, a line starting with one of these is sent but not kept
+const SECRET_COMMANDS: [&str; 6] = ["/register ", "/login ", "/passwd ", "/2fa ", "/join ", "/create "];
+
+#[derive(Debug)]
+pub struct Outbox {
+    key: String,
+    pending: Mutex<Vec<Unsent>>,
+}
+
+// A line and the room it was typed in. Outboxes written before rooms were kept hold bare lines
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Unsent {
+    Typed { line: String, room: Option<String> },
+    Bare(String),
+}
+
+impl Unsent {
+    fn line(&self) -> &str {
+        match self {
+            Unsent::Typed { line, .. } | Unsent::Bare(line) => line,
+        }
+    }
+
+    fn room(&self) -> Option<&str> {
+        match self {
+            Unsent::Typed { room, .. } => room.as_deref(),
+            Unsent::Bare(_) => None,
+        }
+    }
+}
+
+impl Outbox {
+    // The outbox for this user on this server, with whatever an earlier run left in it
+    pub fn open(server: &str, username: &str) -> Self {
+        let key = key(server, username);
+        let pending = read_all().remove(&key).unwrap_or_default();
+        Outbox { key, pending: Mutex::new(pending) }
+    }
+
+    // Lines still waiting, oldest first
+    pub fn pending(&self) -> Vec<String> {
+        self.lock().iter().map(|unsent| unsent.line().to_string()).collect()
+    }
+
+    // Keeps a line, typed in `room`, until remove() says it went out
+    pub fn push(&self, line: &str, room: Option<&str>) -> io::Result<()> {
+        if SECRET_COMMANDS.iter().any(|cmd| line.starts_with(cmd)) {
+            return Ok(());
+        }
+        let mut pending = self.lock();
+        pending.push(Unsent::Typed { line: line.to_string(), room: room.map(str::to_string) });
+        self.save(&pending)
+    }
+
+    // Forgets a line (the oldest copy, if it was typed more than once)
+    pub fn remove(&self, line: &str) -> io::Result<()> {
+        let mut pending = self.lock();
+        let Some(index) = pending.iter().position(|queued| queued.line() == line) else { return Ok(()) };
+        pending.remove(index);
+        self.save(&pending)
+    }
+
+    // Forgets every line typed in `room`, returns how many there were
+    pub fn purge(&self, room: &str) -> io::Result<usize> {
+        let mut pending = self.lock();
+        let before = pending.len();
+        pending.retain(|unsent| unsent.room() != Some(room));
+        let purged = before - pending.len();
+        if purged > 0 {
+            self.save(&pending)?;
+        }
+        Ok(purged)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Unsent>> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Rewrites the file with our lines, leaving other users' and servers' alone
+    fn save(&self, pending: &[Unsent]) -> io::Result<()> {
+        let path = outbox_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut all = read_all();
+        if pending.is_empty() {
+            all.remove(&self.key);
+        } else {
+            all.insert(self.key.clone(), pending.to_vec());
+        }
+        fs::write(&path, serde_json::to_string_pretty(&all)?)?;
+
+        // Whispers end up here too
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
+
+fn outbox_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("outbox.json"))
+}
+
+fn key(server: &str, username: &str) -> String {
+    format!("{}@{}", username.to_lowercase(), server)
+}
+
+fn read_all() -> HashMap<String, Vec<Unsent>> {
+    outbox_file()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,68 @@
+// Connecting over QUIC (quic feature, quic://host:port addresses), experimental
+// One bidirectional stream carries the usual protocol. The server's certificate is checked against the
+// system's trusted roots, or only against the one given with --quic-cert (a server's self-made quic-cert.pem).
+// QUIC copes better with lossy networks than TCP: a lost packet holds up only that packet, and moving to
+// another network doesn't drop the connection.
+
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use quinn::{ClientConfig, Endpoint, RecvStream, SendStream, TransportConfig};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Sent while nothing else is, so the connection doesn't idle out between the client's pings
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+// The receiving half, holding on to the endpoint the connection goes through
+pub struct QuicReader {
+    stream: RecvStream,
+    _endpoint: Endpoint,
+}
+
+impl AsyncRead for QuicReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+// A stream to the server at `target` (host:port), trusting `cert` (PEM) when given
+pub async fn connect(target: &str, cert: Option<&Path>) -> io::Result<(QuicReader, SendStream)> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidInput, reason);
+    let (host, _) = target.rsplit_once(':').ok_or_else(|| invalid(format!("{} is not host:port", target)))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addr = tokio::net::lookup_host(target)
+        .await?
+        .next()
+        .ok_or_else(|| invalid(format!("{} has no address", host)))?;
+
+    let mut config = match cert {
+        Some(cert) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(cert).map_err(|e| invalid(format!("reading {}: {}", cert.display(), e)))? {
+                roots.add(cert.map_err(|e| invalid(e.to_string()))?).map_err(|e| invalid(e.to_string()))?;
+            }
+            ClientConfig::with_root_certificates(Arc::new(roots)).map_err(|e| invalid(e.to_string()))?
+        }
+        None => ClientConfig::try_with_platform_verifier().map_err(io::Error::other)?,
+    };
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    config.transport_config(Arc::new(transport));
+
+    let local: std::net::SocketAddr = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse().map_err(io::Error::other)?;
+    let mut endpoint = Endpoint::client(local)?;
+    endpoint.set_default_client_config(config);
+    let connection = endpoint
+        .connect(addr, host)
+        .map_err(|e| invalid(e.to_string()))?
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e))?;
+    let (send, recv) = connection.open_bi().await.map_err(io::Error::other)?;
+    Ok((QuicReader { stream: recv, _endpoint: endpoint }, send))
+}
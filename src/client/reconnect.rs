@@ -0,0 +1,73 @@
+// Reconnection state machine
+// When the connection drops the client looks at the last error frame the server sent (if any)
+// and decides whether to wait for the server's Retry-After, back off, ask for a password or give up.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::protocol::{ChatMessage, ErrorKind};
+
+// Backoff starts here and doubles after every failed attempt
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+// Blind retries (no structured reason) before giving up
+const MAX_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectAction {
+    // Wait this long, then connect again
+    Retry(Duration),
+    // The server wants a (different) password before it lets us in
+    PromptPassword,
+    // Retrying will not help, the string says why
+    GiveUp(String),
+}
+
+#[derive(Debug, Default)]
+pub struct ReconnectPolicy {
+    attempts: u32,
+}
+
+impl ReconnectPolicy {
+    // Called once the server has accepted us, so the next drop starts a fresh backoff
+    pub fn connected(&mut self) {
+        self.attempts = 0;
+    }
+
+    // Called when the connection dropped or a connect attempt failed
+    // `rejection` is the error frame the server sent just before closing, if there was one
+    pub fn next_action(&mut self, rejection: Option<&ChatMessage>) -> ReconnectAction {
+        let (kind, retry_after) = match rejection {
+            Some(frame) => (frame.error.as_ref(), frame.retry_after),
+            None => (None, None),
+        };
+        match (kind, retry_after) {
+            (Some(ErrorKind::Banned | ErrorKind::Kicked), _) => {
+                let reason = rejection.map(|frame| frame.content.clone()).unwrap_or_default();
+                ReconnectAction::GiveUp(reason)
+            }
+            (Some(ErrorKind::AuthFailed), _) => ReconnectAction::PromptPassword,
+            // We were idle, coming straight back would just make us a ghost again
+            (Some(ErrorKind::IdleTimeout), _) => ReconnectAction::GiveUp("timed out for inactivity".to_string()),
+            // The server told us exactly how long to wait, so don't guess
+            (Some(ErrorKind::Cooldown), Some(seconds)) => ReconnectAction::Retry(Duration::from_secs(seconds)),
+            _ => self.backoff(retry_after),
+        }
+    }
+
+    // Exponential backoff with a little jitter so many clients don't retry in lockstep
+    fn backoff(&mut self, at_least: Option<u64>) -> ReconnectAction {
+        if self.attempts >= MAX_ATTEMPTS {
+            return ReconnectAction::GiveUp(format!("Gave up after {} reconnect attempts", self.attempts));
+        }
+        let delay = BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(MAX_DELAY);
+        self.attempts += 1;
+
+        let jitter = Duration::from_millis(rand::rng().random_range(0..500));
+        let delay = delay.max(Duration::from_secs(at_least.unwrap_or_default()));
+        ReconnectAction::Retry(delay + jitter)
+    }
+}
@@ -0,0 +1,119 @@
+// Session tokens remembered between runs
+// They go in the OS keychain (Keychain, Credential Manager, the kernel keyring on Linux) under
+// "username@server". With --no-keyring they are kept in sessions.json inside the config dir instead,
+// as they all were before: a token still found there is moved to the keychain the next time it's used.
+// Saved servers' passwords are kept the same way, under no username ("@server"), which no account can have.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use keyring::Entry;
+
+use super::config_dir;
+
+// Service name the tokens are filed under in the keychain
+const KEYRING_SERVICE: &str = "rust-chat";
+
+fn sessions_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("sessions.json"))
+}
+
+fn key(server: &str, username: &str) -> String {
+    format!("{}@{}", username.to_lowercase(), server)
+}
+
+fn keyring_entry(server: &str, username: &str) -> io::Result<Entry> {
+    Entry::new(KEYRING_SERVICE, &key(server, username)).map_err(keyring_error)
+}
+
+fn keyring_error(e: keyring::Error) -> io::Error {
+    io::Error::other(format!("keychain: {} (--no-keyring keeps tokens in a file instead)", e))
+}
+
+// Where tokens are kept: the keychain, or with --no-keyring the sessions file (the user's, or one of our choosing)
+#[derive(Debug, Clone)]
+pub struct Sessions {
+    file: Option<PathBuf>,
+    keyring: bool,
+}
+
+impl Sessions {
+    pub fn user(keyring: bool) -> Self {
+        Sessions { file: sessions_file(), keyring }
+    }
+
+    pub fn at(file: PathBuf, keyring: bool) -> Self {
+        Sessions { file: Some(file), keyring }
+    }
+
+    // Token saved for this user on this server, if any
+    pub fn load_token(&self, server: &str, username: &str) -> Option<String> {
+        if self.keyring && let Some(token) = keyring_entry(server, username).ok().and_then(|entry| entry.get_password().ok()) {
+            return Some(token);
+        }
+        let token = self.read_all().remove(&key(server, username))?;
+        if self.keyring {
+            let _ = self.save_token(server, username, &token); // out of the plaintext file while we're at it
+        }
+        Some(token)
+    }
+
+    // Remembers a token, replacing any older one for the same user and server
+    pub fn save_token(&self, server: &str, username: &str, token: &str) -> io::Result<()> {
+        let mut tokens = self.read_all();
+        if !self.keyring {
+            tokens.insert(key(server, username), token.to_string());
+            return self.write_all(&tokens);
+        }
+        keyring_entry(server, username)?.set_password(token).map_err(keyring_error)?;
+        // A plaintext copy left from before the keychain goes once the keychain has the token
+        if tokens.remove(&key(server, username)).is_some() {
+            self.write_all(&tokens)?;
+        }
+        Ok(())
+    }
+
+    // The password a saved server took last time, if any
+    pub fn load_password(&self, server: &str) -> Option<String> {
+        self.load_token(server, "")
+    }
+
+    pub fn save_password(&self, server: &str, password: &str) -> io::Result<()> {
+        self.save_token(server, "", password)
+    }
+
+    fn read_all(&self) -> HashMap<String, String> {
+        self.file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_all(&self, tokens: &HashMap<String, String>) -> io::Result<()> {
+        let path = self.file.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(tokens)?;
+
+        // The token is as good as a password, so the file is only ever readable by us (from the moment it exists)
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        // A file from an older version may still be readable by others, tighten it before the tokens go in
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(json.as_bytes())
+    }
+}
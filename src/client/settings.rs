@@ -0,0 +1,259 @@
+// Client preferences, read from config.toml inside the config dir
+// Every setting is optional, a missing file means the defaults. /set changes most of them as the client runs
+// and saves them here too (see SettingsFile):
+//
+//   # Who to sign in as and where, when neither is given on the command line
+//   username = "zorg"
+//   server = "chat.example.org:8082"
+//
+//   # Colours of the chat window
+//   theme = "space"
+//
+//   # Turn :) and :rocket: into emoji when sending (/set emojify on|off for this run)
+//   emojify = true
+//
+//   # People whose messages aren't shown (/ignore and /unignore keep this up to date)
+//   ignore = ["spammer"]
+//   # Hide them coming and going too
+//   ignore_joins = true
+//
+//   # Sign what we send with the key in signing.key, so the server can tell it's really us
+//   sign_messages = true
+//
+//   # "today 14:32" and "yesterday 09:10", or "absolute" for the full date and time (/set times for this run)
+//   times = "relative"
+//
+//   [notifications]
+//   # A toast when someone says our name (or @name) or whispers to us while we're looking elsewhere
+//   mentions = true
+//   # Ring the terminal bell for them too
+//   bell = false
+//   # And put up a desktop notification, for terminals that can (iTerm2, WezTerm, kitty, Windows Terminal, ...)
+//   desktop = true
+//
+//   # Words that call on us as well when someone says them, any case (/notify add|remove|on|off <keyword>)
+//   [notifications.keywords]
+//   deploy = true
+//   outage = false                        # kept, but switched off
+//
+//   # Keys for quit, navigate, focus_input, page_up, page_down, next_tab, prev_tab and emoji_picker, see keymap
+//   [keys]
+//   quit = "ctrl+q"
+//
+//   # Append what's shown to a file a day, <dir>/2026-10-16.log (/log on|off for this run and after)
+//   [log]
+//   enabled = false
+//   dir = "~/chat-logs"                   # logs/ in the config dir otherwise
+//
+//   # Servers kept by name, offered when the client starts without a username or server on the command line
+//   # and switched to with /connect <name>. All but the address are optional. The server's password isn't kept
+//   # here but in the keychain (sessions.json with --no-keyring), once it has got us in
+//   [servers.home]
+//   address = "chat.example.org:8082"     # host:port, quic://host:port or unix:<path>
+//   username = "zorg"                     # the username above otherwise
+//   quic_cert = "/etc/chat/cert.pem"      # quic:// is TLS: the certificate to trust instead of the system's roots
+//   proxy = "socks5://127.0.0.1:9050"     # SOCKS5 proxy to go through
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::Value;
+
+use super::config_dir;
+
+// The theme the chat window starts with when config.toml doesn't name one
+pub const DEFAULT_THEME: &str = "space";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub username: Option<String>,
+    pub server: Option<String>,
+    pub theme: String,
+    pub emojify: bool,
+    pub ignore: Vec<String>,
+    pub ignore_joins: bool,
+    pub sign_messages: bool,
+    pub times: Times,
+    pub notifications: Notifications,
+    pub log: LogSettings,
+    pub servers: BTreeMap<String, Profile>,
+    pub keys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Notifications {
+    pub mentions: bool,
+    pub bell: bool,
+    pub desktop: bool,
+    pub keywords: BTreeMap<String, bool>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications { mentions: true, bell: false, desktop: true, keywords: BTreeMap::new() }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub enabled: bool,
+    pub dir: Option<PathBuf>,
+}
+
+impl LogSettings {
+    // Where the log files go: dir with ~ for the home directory, or logs/ in the config dir
+    pub fn dir(&self) -> PathBuf {
+        let home = env::var_os("HOME").map(PathBuf::from);
+        match (&self.dir, home) {
+            (Some(dir), Some(home)) if dir.starts_with("~") => home.join(dir.strip_prefix("~").unwrap_or(dir)),
+            (Some(dir), _) => dir.clone(),
+            (None, _) => config_dir().unwrap_or_default().join("logs"),
+        }
+    }
+}
+
+// A saved server: where it is, who to sign in as there and how to get there
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub address: String,
+    pub username: Option<String>,
+    pub quic_cert: Option<PathBuf>,
+    pub proxy: Option<String>,
+}
+
+// How message stamps are shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Times {
+    #[default]
+    Relative,
+    Absolute,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            username: None,
+            server: None,
+            theme: DEFAULT_THEME.to_string(),
+            emojify: true,
+            ignore: Vec::new(),
+            ignore_joins: true,
+            sign_messages: true,
+            times: Times::Relative,
+            notifications: Notifications::default(),
+            log: LogSettings::default(),
+            servers: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    // The user's config.toml, or the defaults when there is none
+    pub fn load() -> Result<Self, String> {
+        SettingsFile::user().load()
+    }
+
+    fn load_from(path: Option<&Path>) -> Result<Self, String> {
+        let Some(path) = path.filter(|path| path.exists()) else {
+            return Ok(Settings::default());
+        };
+        let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("parsing {}: {}", path.display(), e))
+    }
+}
+
+// Sets one key in a config.toml, keeping the others (comments are lost, the file is written out again)
+// A dotted key like "notifications.bell" goes in that table
+pub fn save_value(path: &Path, key: &str, value: Value) -> Result<(), String> {
+    let mut table = match fs::read_to_string(path) {
+        Ok(text) => text.parse::<toml::Table>().map_err(|e| format!("parsing {}: {}", path.display(), e))?,
+        Err(_) => toml::Table::new(),
+    };
+    let (tables, key) = key.rsplit_once('.').map_or((None, key), |(tables, key)| (Some(tables), key));
+    let mut inner = &mut table;
+    for name in tables.into_iter().flat_map(|tables| tables.split('.')) {
+        let entry = inner.entry(name).or_insert_with(|| Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(toml::Table::new());
+        }
+        inner = entry.as_table_mut().expect("a table was just put there");
+    }
+    inner.insert(key.to_string(), value);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("creating {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, table.to_string()).map_err(|e| format!("writing {}: {}", path.display(), e))
+}
+
+// config.toml in the config dir
+pub fn settings_file() -> Option<PathBuf> {
+    config_dir().map(|dir| settings_file_in(&dir))
+}
+
+// config.toml in `dir`. One still called client.toml, from before the rename, is moved there the first time
+pub fn settings_file_in(dir: &Path) -> PathBuf {
+    let (path, old) = (dir.join("config.toml"), dir.join("client.toml"));
+    if !path.exists() && old.exists() && fs::rename(&old, &path).is_err() {
+        return old; // keep using it where it is rather than lose it
+    }
+    path
+}
+
+// Where /set keeps what it changes: the user's config.toml, or nowhere (the change lasts for this run)
+#[derive(Debug, Clone, Default)]
+pub struct SettingsFile {
+    path: Option<PathBuf>,
+}
+
+impl SettingsFile {
+    pub fn user() -> Self {
+        SettingsFile { path: settings_file() }
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        SettingsFile { path: Some(path) }
+    }
+
+    // What the file says now, the defaults when there's no file
+    pub fn load(&self) -> Result<Settings, String> {
+        Settings::load_from(self.path.as_deref())
+    }
+
+    // Ok(false) when there's nowhere to keep it
+    pub fn save(&self, key: &str, value: impl Into<Value>) -> Result<bool, String> {
+        let Some(path) = &self.path else { return Ok(false) };
+        save_value(path, key, value.into())?;
+        Ok(true)
+    }
+
+    // Passwords saved servers still have in the file, from before they went in the keychain: (server name, password)
+    pub fn old_passwords(&self) -> Vec<(String, String)> {
+        let table = self.path.as_ref().and_then(|path| fs::read_to_string(path).ok()).and_then(|text| text.parse::<toml::Table>().ok());
+        let Some(Value::Table(servers)) = table.and_then(|mut table| table.remove("servers")) else { return Vec::new() };
+        servers
+            .into_iter()
+            .filter_map(|(name, server)| Some((name, server.get("password")?.as_str()?.to_string())))
+            .collect()
+    }
+
+    // Takes a server's password out of the file once it's kept elsewhere
+    pub fn forget_password(&self, server: &str) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        let mut table = text.parse::<toml::Table>().map_err(|e| format!("parsing {}: {}", path.display(), e))?;
+        let profile = table.get_mut("servers").and_then(|servers| servers.get_mut(server)).and_then(Value::as_table_mut);
+        if profile.and_then(|profile| profile.remove("password")).is_some() {
+            fs::write(path, table.to_string()).map_err(|e| format!("writing {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
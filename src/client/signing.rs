@@ -0,0 +1,108 @@
+// Signed messages
+// Every client has an ed25519 key pair, made on first run and kept in signing.key inside the config dir. The
+// public half goes to the server in the handshake, and every line we send goes out with a "/sign <stamp>
+// <signature>" line in front of it, over the room, the stamp and the text (see protocol::signed_text). The
+// server records the key a name first signed with (or an account signs in with) and flags messages under that
+// name that weren't signed with it, or whose signature is stale or was used before, so taking someone's name
+// after they left (or replaying their lines) doesn't make you them. `sign_messages = false` in config.toml sends lines unsigned.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::{Signer as _, SigningKey};
+use rand::Rng;
+
+use super::config_dir;
+use crate::blocks::{BlockFraming, Framed};
+use crate::protocol::{normalize_content, signed_text};
+
+pub struct Signer {
+    key: SigningKey,
+    // Stamp of the last line signed, the next one goes higher even when the clock doesn't
+    last_stamp: Mutex<u64>,
+}
+
+impl Signer {
+    // Our key pair from signing.key, made (and saved) the first time
+    pub fn load() -> io::Result<Self> {
+        let path = key_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        if let Ok(text) = fs::read_to_string(&path) {
+            let bytes: [u8; 32] = BASE64
+                .decode(text.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a key", path.display())))?;
+            return Ok(Signer::new(SigningKey::from_bytes(&bytes)));
+        }
+
+        let signer = Signer::generate();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Whoever has the file can talk as us, so it's only ever readable by us (from the moment it exists)
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&path)?.write_all(BASE64.encode(signer.key.to_bytes()).as_bytes())?;
+        Ok(signer)
+    }
+
+    // A key pair for this run only, for when signing.key can't be had (and for tests)
+    pub fn generate() -> Self {
+        Signer::new(SigningKey::from_bytes(&rand::rng().random()))
+    }
+
+    fn new(key: SigningKey) -> Self {
+        Signer { key, last_stamp: Mutex::new(0) }
+    }
+
+    // Our public key as it goes in the handshake
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.key.verifying_key().as_bytes())
+    }
+
+    // `line`, said in `room`, with its "/sign <stamp> <signature>" line in front, both go out together
+    pub fn sign_line(&self, room: &str, line: &str) -> String {
+        let stamp = self.next_stamp();
+        format!("/sign {} {}\n{}", stamp, self.sign(&signed_text(room, stamp, &as_received(line))), line)
+    }
+
+    // Signature (base64) of `text`
+    pub fn sign(&self, text: &str) -> String {
+        BASE64.encode(self.key.sign(text.as_bytes()).to_bytes())
+    }
+
+    // Milliseconds since 1970, or one more than the last stamp when that's not higher
+    fn next_stamp(&self) -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        let mut last = self.last_stamp.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *last = now.max(*last + 1);
+        *last
+    }
+}
+
+// What the server makes of a line, the text it checks the signature against: framed into one message the way
+// it frames ``` blocks, trimmed and normalized
+fn as_received(line: &str) -> String {
+    let mut framing = BlockFraming::new();
+    let mut received = line.trim().to_string();
+    for part in line.split('\n') {
+        if let Framed::Block(block) = framing.feed(part.trim_end_matches('\r')) {
+            received = block;
+            break;
+        }
+    }
+    normalize_content(&received).unwrap_or(received)
+}
+
+fn key_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("signing.key"))
+}
@@ -0,0 +1,173 @@
+// Colours of the chat window: the built-in themes, and palettes of our own in theme files
+// /theme <name> (or theme in config.toml) picks one. A name that isn't built in is looked up as
+// themes/<name>.toml in the config dir, which can start from a built-in and change what it likes:
+//
+//   # ~/.config/rust-chat/themes/dusk.toml
+//   base = "solarized"          # optional, the default palette otherwise
+//   shadow = false
+//   borders = "outset"          # simple, outset or none
+//
+//   [palette]                   # background, view, shadow, primary, secondary, tertiary, title_primary,
+//   background = "#1d1f21"      # title_secondary, highlight, highlight_inactive, highlight_text
+//   primary = "light cyan"      # "#rrggbb", a colour name ("red", "light red") or "default" for the terminal's
+//
+// Themes colour the window itself, names and formatted text in messages keep their own colours.
+
+use std::fs;
+use std::path::PathBuf;
+
+use cursive::theme::{BorderStyle, Color, Palette, PaletteColor, Theme};
+use serde::Deserialize;
+
+use super::config_dir;
+
+pub const BUILT_IN: [&str; 5] = ["space", "light", "high-contrast", "solarized", "monochrome"];
+
+// What a theme file says, everything optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    base: Option<String>,
+    shadow: Option<bool>,
+    borders: Option<String>,
+    palette: toml::Table,
+}
+
+// A built-in theme, or the one in themes/<name>.toml
+pub fn load(name: &str) -> Result<Theme, String> {
+    if let Some(theme) = built_in(name) {
+        return Ok(theme);
+    }
+    let path = theme_file(name).ok_or(format!("there's no theme called {:?}", name))?;
+    let text = fs::read_to_string(&path).map_err(|_| format!("there's no theme called {:?}, try {}", name, available().join(", ")))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Built-in themes first, then the theme files there are
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = BUILT_IN.iter().map(|name| name.to_string()).collect();
+    let mut files: Vec<String> = themes_dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let stem = path.file_stem()?.to_str()?.to_string();
+            (path.extension()? == "toml" && !BUILT_IN.contains(&stem.as_str())).then_some(stem)
+        })
+        .collect();
+    files.sort();
+    names.extend(files);
+    names
+}
+
+// A theme from the text of a theme file
+pub fn parse(text: &str) -> Result<Theme, String> {
+    let file: ThemeFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    let mut theme = match &file.base {
+        Some(base) => built_in(base).ok_or(format!("base {:?} isn't a built-in theme ({})", base, BUILT_IN.join(", ")))?,
+        None => Theme::default(),
+    };
+    if let Some(shadow) = file.shadow {
+        theme.shadow = shadow;
+    }
+    if let Some(borders) = &file.borders {
+        theme.borders = match borders.as_str() {
+            "simple" => BorderStyle::Simple,
+            "outset" => BorderStyle::Outset,
+            "none" => BorderStyle::None,
+            other => return Err(format!("borders {:?} isn't simple, outset or none", other)),
+        };
+    }
+    for (key, value) in &file.palette {
+        let color = value.as_str().and_then(Color::parse).ok_or(format!("palette.{} = {} isn't a colour", key, value))?;
+        theme.palette.set_basic_color(key, color).map_err(|_| format!("palette.{} isn't part of the palette", key))?;
+    }
+    Ok(theme)
+}
+
+pub fn built_in(name: &str) -> Option<Theme> {
+    let (shadow, borders, colors) = match name {
+        "space" => (true, BorderStyle::Simple, SPACE),
+        "light" => (false, BorderStyle::Simple, LIGHT),
+        "high-contrast" => (false, BorderStyle::Simple, HIGH_CONTRAST),
+        "solarized" => (false, BorderStyle::Simple, SOLARIZED),
+        "monochrome" => (false, BorderStyle::Simple, MONOCHROME),
+        _ => return None,
+    };
+    let mut palette = Palette::default();
+    for (key, color) in colors {
+        palette[key] = color;
+    }
+    Some(Theme { shadow, borders, palette })
+}
+
+type Colors = [(PaletteColor, Color); 8];
+
+const SPACE: Colors = [
+    (PaletteColor::Background, Color::Rgb(8, 8, 32)),            // Deep space blue-black
+    (PaletteColor::View, Color::Rgb(20, 16, 48)),                // Slightly lighter, cosmic purple
+    (PaletteColor::Primary, Color::Rgb(0, 255, 255)),            // Neon cyan for main text
+    (PaletteColor::TitlePrimary, Color::Rgb(180, 0, 255)),       // Electric purple for titles
+    (PaletteColor::Secondary, Color::Rgb(0, 200, 255)),          // Blue for secondary elements
+    (PaletteColor::Highlight, Color::Rgb(255, 255, 0)),          // Bright yellow highlight (stars)
+    (PaletteColor::HighlightInactive, Color::Rgb(80, 80, 120)),  // Dimmed blue for inactive
+    (PaletteColor::Shadow, Color::Rgb(0, 0, 0)),                 // Black shadow
+];
+
+const LIGHT: Colors = [
+    (PaletteColor::Background, Color::Rgb(228, 228, 232)),
+    (PaletteColor::View, Color::Rgb(250, 250, 250)),
+    (PaletteColor::Primary, Color::Rgb(32, 32, 40)),
+    (PaletteColor::TitlePrimary, Color::Rgb(40, 80, 160)),
+    (PaletteColor::Secondary, Color::Rgb(90, 90, 110)),
+    (PaletteColor::Highlight, Color::Rgb(40, 110, 200)),
+    (PaletteColor::HighlightInactive, Color::Rgb(170, 180, 200)),
+    (PaletteColor::Shadow, Color::Rgb(180, 180, 188)),
+];
+
+// Pure black and white with yellow to pick things out, for bright rooms and tired eyes
+const HIGH_CONTRAST: Colors = [
+    (PaletteColor::Background, Color::Rgb(0, 0, 0)),
+    (PaletteColor::View, Color::Rgb(0, 0, 0)),
+    (PaletteColor::Primary, Color::Rgb(255, 255, 255)),
+    (PaletteColor::TitlePrimary, Color::Rgb(255, 255, 0)),
+    (PaletteColor::Secondary, Color::Rgb(255, 255, 255)),
+    (PaletteColor::Highlight, Color::Rgb(255, 255, 0)),
+    (PaletteColor::HighlightInactive, Color::Rgb(128, 128, 128)),
+    (PaletteColor::Shadow, Color::Rgb(0, 0, 0)),
+];
+
+// Ethan Schoonover's Solarized, the dark variant
+const SOLARIZED: Colors = [
+    (PaletteColor::Background, Color::Rgb(0, 43, 54)),             // base03
+    (PaletteColor::View, Color::Rgb(7, 54, 66)),                   // base02
+    (PaletteColor::Primary, Color::Rgb(147, 161, 161)),            // base1
+    (PaletteColor::TitlePrimary, Color::Rgb(38, 139, 210)),        // blue
+    (PaletteColor::Secondary, Color::Rgb(131, 148, 150)),          // base0
+    (PaletteColor::Highlight, Color::Rgb(181, 137, 0)),            // yellow
+    (PaletteColor::HighlightInactive, Color::Rgb(88, 110, 117)),   // base01
+    (PaletteColor::Shadow, Color::Rgb(0, 30, 38)),
+];
+
+// The terminal's own colours and nothing else
+const MONOCHROME: Colors = [
+    (PaletteColor::Background, Color::TerminalDefault),
+    (PaletteColor::View, Color::TerminalDefault),
+    (PaletteColor::Primary, Color::TerminalDefault),
+    (PaletteColor::TitlePrimary, Color::TerminalDefault),
+    (PaletteColor::Secondary, Color::TerminalDefault),
+    (PaletteColor::Highlight, Color::Light(cursive::theme::BaseColor::White)),
+    (PaletteColor::HighlightInactive, Color::Dark(cursive::theme::BaseColor::White)),
+    (PaletteColor::Shadow, Color::TerminalDefault),
+];
+
+fn themes_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("themes"))
+}
+
+// themes/<name>.toml, for names that can't climb out of the directory
+fn theme_file(name: &str) -> Option<PathBuf> {
+    let plain = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    themes_dir().filter(|_| plain).map(|dir| dir.join(format!("{}.toml", name)))
+}
@@ -0,0 +1,2674 @@
+// The chat window: layout, theme, how frames are shown and the commands handled locally
+// Kept apart from the networking so it can be driven by tests without a terminal or server
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use cursive::{
+    align::HAlign,
+    direction::Direction,
+    event::{Event, EventResult, EventTrigger, Key, MouseButton, MouseEvent},
+    theme::{BaseColor, Color, ColorStyle, Effect, PaletteStyle, Style},
+    traits::*,
+    utils::lines::simple::simple_prefix,
+    utils::lines::spans::{LinesIterator, Row},
+    utils::markup::StyledString,
+    view::{scroll::Scroller, CannotFocus, ScrollStrategy, ViewWrapper},
+    views::{Checkbox, Dialog, DummyView, EditView, HideableView, LinearLayout, NamedView, OnEventView, Panel, ResizedView, ScrollView, SelectView, TextArea, TextView},
+    Cursive, Printer, Vec2,
+};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+use crate::client::chatlog::ChatLog;
+use crate::client::clipboard;
+use crate::client::connection::{check_addr, UNIX_PREFIX};
+use crate::client::emoji::{Emoji, MAX_SEARCH_RESULTS};
+use crate::client::export;
+use crate::client::ignore::IgnoreList;
+use crate::client::keymap::{key_name, Action, Keymap};
+use crate::client::links;
+use crate::client::markup::Markup;
+use crate::client::notify::Notifier;
+use crate::client::settings::{Profile, SettingsFile, DEFAULT_THEME};
+use crate::client::themes;
+use crate::protocol::{ChatMessage, CommandInfo, Member, MessageType, OpsEvent, Quote, Reaction, RoomInfo, DEFAULT_ROOM};
+use crate::trace::WireTracer;
+
+const SET_USAGE: &str = "Usage: /set emojify|mentions|bell|desktop|log on|off, /set times relative|absolute, /set theme|username|server <value>";
+
+// Names of the views other code reaches for
+pub const MESSAGES: &str = "messages";
+const MESSAGE_SCROLL: &str = "message_scroll";
+const MESSAGE_PANE: &str = "message_pane";
+const MESSAGE_SEARCH: &str = "message_search";
+pub const INPUT: &str = "input";
+pub const INPUT_BOX: &str = "input_box";
+pub const PASSWORD: &str = "password";
+pub const SERVER_PICKER: &str = "server_picker";
+pub const SETTINGS: &str = "settings";
+pub const HELP: &str = "help";
+pub const CONNECT_USERNAME: &str = "connect_username";
+pub const CONNECT_SERVER: &str = "connect_server";
+const CONNECT_ERROR: &str = "connect_error";
+pub const STATUS: &str = "status";
+pub const TABS: &str = "tabs";
+pub const TOPIC: &str = "topic";
+const HEADER: &str = "header";
+pub const TOASTS: &str = "toasts";
+pub const ROSTER: &str = "roster";
+const ROSTER_PANEL: &str = "roster_panel";
+pub const UNSENT: &str = "unsent";
+pub const PURGE: &str = "purge";
+pub const EMOJI_PICKER: &str = "emoji_picker";
+pub const EMOJI_SEARCH: &str = "emoji_search";
+const EMOJI_LIST: &str = "emoji_list";
+pub const CODE_EDITOR: &str = "code_editor";
+pub const ROOM_LIST: &str = "room_list";
+pub const PINS: &str = "pins";
+pub const QUOTE: &str = "quote";
+const QUOTE_PANEL: &str = "quote_panel";
+const PINS_PANEL: &str = "pins_panel";
+
+// Columns the roster takes, names longer than fit are cut short
+const ROSTER_WIDTH: usize = 22;
+
+// Rows one notch of the mouse wheel scrolls
+const WHEEL_ROWS: usize = 3;
+// Keys that aren't in the keymap, for /keys
+const FIXED_KEYS: [(&str, &str); 5] = [
+    ("Alt+1..9", "That room's tab"),
+    ("F2", "Who's online"),
+    ("F3", "Pinned messages in full, or folded"),
+    ("F10", "Settings"),
+    ("Enter", "Send, or pick in a list"),
+];
+// What the keys do once navigation mode has the messages, see MessagePane
+const NAVIGATION_KEYS: [(&str, &str); 6] = [
+    ("j / k", "A line down / up"),
+    ("Ctrl+D / U", "Half a page down / up"),
+    ("gg / G", "To the oldest / newest message"),
+    ("/", "Search back through the messages"),
+    ("n / N", "The next older / newer match"),
+    ("i or Enter", "Back to the input box"),
+];
+
+// How long a toast stays up, and how many can be up at once (the oldest goes first)
+pub const TOAST_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TOASTS: usize = 3;
+
+// Starts the second and later lines of a multi-line message
+const CONTINUATION: &str = "   ┆ ";
+
+// Starts a message that goes under the header of the one before it, same person again within GROUP_WINDOW
+const FOLLOW_UP: &str = "   ↳ ";
+const GROUP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+// Tabs Alt+1..9 can reach
+const MAX_NUMBERED_TABS: usize = 9;
+
+// How long typing has to pause before the draft goes to our other devices
+pub const DRAFT_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Title of the input box, unless the room we talk in is read-only
+const INPUT_TITLE: &str = "Chit Chat";
+
+const NOTIFY_USAGE: &str = "Usage: /notify add|remove|on|off <keyword>, /notify lists them";
+
+// Commands carrying passwords, which must not go through emoji expansion
+const SECRET_COMMANDS: [&str; 6] = ["/register ", "/login ", "/passwd ", "/2fa ", "/join ", "/create "];
+
+// What the client does itself, for /help; the server's own commands come in its Capabilities frame
+const CLIENT_COMMANDS: [(&str, &str); 27] = [
+    ("/help", "Show this help"),
+    ("/clear", "Clear the message view (nothing is deleted, links and blocks still open)"),
+    ("/quit", "Exit chat"),
+    ("/keys", "Which keys do what (change them under [keys] in config.toml)"),
+    ("/settings", "Switch emoji, notifications, the bell, desktop notifications, the chat log, timestamps and the theme in a panel (or F10)"),
+    ("/set emojify|mentions|bell|desktop|log on|off", "Emoji substitution, a toast when someone calls on you, the bell and a desktop notification for it, and the chat log"),
+    ("/set times relative|absolute", "Stamp new messages \"today 14:32\" or with the full date"),
+    ("/set theme|username|server <value>", "The window's colours, and who to sign in as where from the next start"),
+    ("/theme [name]", "List the themes, or switch to one (built in, or your own in themes/<name>.toml)"),
+    ("/connect [name]", "Switch to a server saved in config.toml, or pick one from the list"),
+    ("/copy", "Copy the message you clicked to the clipboard"),
+    ("/quote <id>", "Reply to message #id, quoting it (clicking a message does the same), /unquote to drop it"),
+    ("/react <emoji>", "React to the message you clicked"),
+    ("/dnd [on|off]", "Do not disturb: no toasts, bell or desktop notifications, and whispers wait until it's over"),
+    ("/notify add|remove|on|off <keyword>", "Be called on when someone says a keyword too, /notify lists them"),
+    ("/ignore <user>", "Stop showing what they say (/unignore <user> to undo, /ignore lists them)"),
+    ("/emoji <query>", "Look up :shortcodes: (:rocket: becomes 🚀)"),
+    ("/pins", "List the messages pinned in this room"),
+    ("/raw", "Show new messages exactly as sent, or formatted again (*bold*, _italic_, `code`, > quote)"),
+    ("```lang", "Write a code block (```rust, ```py, ...) in its own editor"),
+    ("/expand <n>", "Show all of code block n"),
+    ("/open <n>", "Open link [n] in your browser, /copyurl <n> copies it"),
+    ("/ping", "Time a round trip to the server (also kept in the status bar)"),
+    ("/export [path] [--format txt|json|html]", "Save the messages in view to a file, HTML in their colours"),
+    ("/log on|off", "Keep what's shown in a log file a day, to grep later (/log says where)"),
+    ("/purge-local [room]", "Delete what this client kept of a room (its chat log lines, unsent lines and draft) after asking"),
+    ("/debug wire on|off", "Log every frame to the wire trace file"),
+];
+
+const FUNFACE: &str = 
+"                        $$$$$$$$$$$$$$$$$$$$
+                       $$$$$$$$$$$$$$$$$$$$$$$$$$$
+                    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$         $$   $$$$$
+    $$$$$$        $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$       $$$$$$$$$$
+ $$ $$$$$$      $$$$$$$$$$    $$$$$$$$$$$$$    $$$$$$$$$$       $$$$$$$$
+ $$$$$$$$$     $$$$$$$$$$      $$$$$$$$$$$      $$$$$$$$$$$    $$$$$$$$
+   $$$$$$$    $$$$$$$$$$$      $$$$$$$$$$$      $$$$$$$$$$$$$$$$$$$$$$$
+   $$$$$$$$$$$$$$$$$$$$$$$    $$$$$$$$$$$$$    $$$$$$$$$$$$$$  $$$$$$
+    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$
+     $$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$$
+    $$$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$       $$$$
+    $$$    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$ $$$$$$$$$$$$$$$$$
+   $$$$$$$$$$$$$  $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$   $$$$$$$$$$$$$$$$$$
+   $$$$$$$$$$$$$   $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$$     $$$$$$$$$$$$
+  $$$$       $$$$    $$$$$$$$$$$$$$$$$$$$$$$$$$$$$$      $$$$
+             $$$$$     $$$$$$$$$$$$$$$$$$$$$$$$$         $$$
+               $$$$          $$$$$$$$$$$$$$$           $$$$
+                $$$$$                                $$$$$
+                 $$$$$$      $$$$$$$$$$$$$$        $$$$$
+                   $$$$$$$$     $$$$$$$$$$$$$   $$$$$$$
+                      $$$$$$$$$$$  $$$$$$$$$$$$$$$$$
+                         $$$$$$$$$$$$$$$$$$$$$$
+                                 $$$$$$$$$$$$$$$
+                                     $$$$$$$$$$$$
+                                      $$$$$$$$$$$
+                                       $$$$$$$$\n";
+
+// What the chat window works with besides its views: the client's lists and the preferences /set changes
+#[derive(Clone)]
+pub struct Context {
+    pub tracer: Arc<WireTracer>,
+    pub emoji: Arc<Emoji>,
+    pub markup: Arc<Markup>,
+    pub ignore: Arc<IgnoreList>,
+    pub notifier: Arc<Notifier>,
+    // Where /set saves what it changes
+    pub settings: SettingsFile,
+    // The keys bound when the window is built, for /keys
+    pub keymap: Arc<Keymap>,
+    // The commands the server said it has in its Capabilities frame, for /help
+    pub server_commands: Arc<RwLock<Vec<CommandInfo>>>,
+    // What's shown, appended to a file a day when /log is on
+    pub chat_log: Arc<ChatLog>,
+}
+
+// Sets up the theme, the chat layout and the global key bindings
+// on_submit gets every line typed into the input box, the emoji picker offers what `emoji` knows
+pub fn build<F>(siv: &mut Cursive, username: &str, ctx: &Context, on_submit: F)
+where
+    F: Fn(&mut Cursive, &str) + Send + Sync + 'static,
+{
+    let on_submit = Arc::new(on_submit);
+
+    siv.set_theme(themes::built_in(DEFAULT_THEME).unwrap_or_default()); //"space" theme for chat room 
+
+    // Header of the chat room 
+    let header = TextView::new(header(username))
+    .style(Color::Light(BaseColor::Cyan)) 
+    .h_align(HAlign::Center)
+    .with_name(HEADER); 
+    // Under it, what the room we talk in is about
+    let topic = TextView::new("").style(Color::Light(BaseColor::Magenta)).h_align(HAlign::Center).with_name(TOPIC);
+
+    // Message area that is scrollable, kept at the bottom as messages come in
+    let messages = MessagePane::new()
+        .with_name(MESSAGE_PANE)
+        .min_height(50) 
+        .min_width(30) 
+        .full_width(); 
+
+    // Who is online, on the right of the messages once F2 opens it
+    let roster = HideableView::new(
+        Dialog::around(TextView::new("").with_name(ROSTER).scrollable())
+            .title("Online")
+            .fixed_width(ROSTER_WIDTH),
+    )
+    .hidden()
+    .with_name(ROSTER_PANEL);
+
+    // Creating an input area for typing messages
+    let input = EditView::new()
+        .on_edit(|s, text, _| draft_edited(s, text))
+        .on_submit({
+            let on_submit = on_submit.clone();
+            move |s, text| on_submit(s, text)
+        })
+        .with_name(INPUT) 
+        .min_width(50) 
+        .max_height(5) 
+        .full_width(); 
+
+    // Status bar along the bottom: connection, the room we talk in, users online and the time
+    let status_bar = StatusBar::new().with_name(STATUS);
+
+    // Creating the main layout of the chat application
+    let layout = LinearLayout::vertical()
+        .child(Panel::new(LinearLayout::vertical().child(header).child(topic)))
+        .child(
+            LinearLayout::horizontal()
+                .child(
+                    Dialog::around(
+                        LinearLayout::vertical()
+                            .child(TabBar::new().with_name(TABS))
+                            .child(HideableView::new(TextView::new("").with_name(PINS)).hidden().with_name(PINS_PANEL))
+                            .child(messages),
+                    )
+                        .title("Chattering") // Title 
+                        .title_position(HAlign::Center) // Center-align 
+                        .full_width()
+                )
+                .child(roster)
+        )
+        .child(Toasts::new().with_name(TOASTS))
+        .child(HideableView::new(TextView::new("").with_name(QUOTE)).hidden().with_name(QUOTE_PANEL))
+        .child( 
+            Dialog::around(input) 
+                .title(INPUT_TITLE) 
+                .title_position(HAlign::Left) 
+                .with_name(INPUT_BOX)
+                .full_width()
+        )
+        .child(Panel::new(status_bar.full_width()).full_width()); 
+
+    // Wrapping layout for centering
+    let centered_layout = LinearLayout::horizontal()
+        .child(DummyView.full_width()) 
+        .child(layout)
+        .child(DummyView.full_width());
+
+    // The wheel scrolls the messages wherever the mouse is, the keyboard stays with the input box
+    let centered_layout = OnEventView::new(centered_layout).on_pre_event_inner(EventTrigger::mouse(), |_, event| match event {
+        Event::Mouse { event: MouseEvent::WheelUp, .. } => Some(EventResult::with_cb(|s| scroll_messages(s, false, false))),
+        Event::Mouse { event: MouseEvent::WheelDown, .. } => Some(EventResult::with_cb(|s| scroll_messages(s, true, false))),
+        _ => None,
+    });
+
+    // Adding the centered layout to the Cursive root
+    siv.add_fullscreen_layer(centered_layout);
+    // The message pane takes focus from a click, so it would take the first focus too
+    focus_input(siv);
+
+    // Redraw now and then even when nothing happens, so toasts go away on time
+    siv.set_fps(2);
+
+    // Adding global key bindings, the ones [keys] in config.toml can change first
+    // Quitting, navigation mode and going back to the input box come after the views (Esc closes dialogs,
+    // "/" is typed in the box), the emoji picker ahead of the input box, which would take Ctrl+E as End
+    let keymap = &ctx.keymap;
+    if let Some(key) = keymap.key(Action::Quit) {
+        siv.add_global_callback(key.clone(), |s| s.quit());
+    }
+    if let Some(key) = keymap.key(Action::Navigate) {
+        siv.add_global_callback(key.clone(), navigate);
+    }
+    if let Some(key) = keymap.key(Action::FocusInput) {
+        let command = *key == Event::Char('/');
+        siv.add_global_callback(key.clone(), move |s| {
+            focus_input(s);
+            if command {
+                s.call_on_name(INPUT, |view: &mut EditView| {
+                    view.set_content("/");
+                });
+            }
+        });
+    }
+    for (action, down) in [(Action::PageUp, false), (Action::PageDown, true)] {
+        if let Some(key) = keymap.key(action) {
+            siv.add_global_callback(key.clone(), move |s| scroll_messages(s, down, true));
+        }
+    }
+    for (action, step) in [(Action::NextTab, 1), (Action::PrevTab, -1)] {
+        if let Some(key) = keymap.key(action) {
+            let on_submit = on_submit.clone();
+            siv.add_global_callback(key.clone(), move |s| {
+                let Some((active, count)) = s.call_on_name(TABS, |tabs: &mut TabBar| (tabs.active, tabs.tabs.len())) else { return };
+                let index = (active as isize + step).rem_euclid(count as isize) as usize;
+                bring_up_tab(s, index, on_submit.as_ref());
+            });
+        }
+    }
+    if let Some(key) = keymap.key(Action::EmojiPicker) {
+        let emoji = ctx.emoji.clone();
+        siv.set_on_pre_event(key.clone(), move |s| show_emoji_picker(s, emoji.clone()));
+    }
+    siv.add_global_callback(Key::F2, toggle_roster);
+    siv.add_global_callback(Key::F3, toggle_pins);
+    // F10 is /settings, what's being typed stays in the box
+    siv.add_global_callback(Key::F10, {
+        let on_submit = on_submit.clone();
+        move |s| {
+            let draft = s.call_on_name(INPUT, |view: &mut EditView| view.get_content()).unwrap_or_default();
+            on_submit(s, "/settings");
+            s.call_on_name(INPUT, |view: &mut EditView| {
+                view.set_content(draft.as_str());
+            });
+        }
+    });
+    // Alt+1..9 bring up a room's tab, and tell the server that's where we talk now
+    for number in 1..=MAX_NUMBERED_TABS {
+        let on_submit = on_submit.clone();
+        let key = char::from_digit(number as u32, 10).unwrap_or('1');
+        siv.add_global_callback(Event::AltChar(key), move |s| bring_up_tab(s, number - 1, on_submit.as_ref()));
+    }
+}
+
+// Puts tab `index` up and moves the server along with a /join that goes out through on_submit like a typed one,
+// the room's draft is back in the box afterwards
+fn bring_up_tab(siv: &mut Cursive, index: usize, on_submit: &dyn Fn(&mut Cursive, &str)) {
+    let Some(room) = switch_tab(siv, index) else { return };
+    let draft = siv.call_on_name(INPUT, |view: &mut EditView| view.get_content()).unwrap_or_default();
+    on_submit(siv, &format!("/join {}", room));
+    siv.call_on_name(INPUT, |view: &mut EditView| {
+        view.set_content(draft.as_str());
+    });
+}
+
+// Handles a line typed into the input box
+// Local commands are dealt with here, anything else comes back (with emoji expanded) to be sent to the server
+pub fn submit(siv: &mut Cursive, msg: &str, ctx: &Context) -> Option<String> {
+    let Context { tracer, emoji, markup, ignore, .. } = ctx;
+    if msg.is_empty() { 
+        return None;
+    }
+    // Drafts go out as they are and leave the input box alone
+    if is_draft(msg) {
+        return Some(msg.to_string());
+    }
+    // Commands still work in a read-only room, chat stays in the box
+    if !msg.starts_with('/') && is_read_only(siv) {
+        toast(siv, ToastKind::Error, "This room is read-only, only announcers can post here");
+        return None;
+    }
+    // A line typed in the box is done with, so is its draft (Alt+1's /join isn't typed and leaves it be)
+    let typed = siv.call_on_name(INPUT, |view: &mut EditView| view.get_content().as_str() == msg).unwrap_or(false);
+    clear_input(siv);
+    if typed {
+        draft_edited(siv, "");
+    }
+
+    // extra commands 
+    match msg {
+        "/help" => show_help(siv, ctx),
+        // Only the view is cleared: /open, /expand and the server's history are untouched,
+        // and the pane goes back to following new messages
+        "/clear" => {
+            siv.call_on_name(MESSAGES, |view: &mut MessageLog| {
+                view.clear(); // Clear messages
+            });
+            siv.call_on_name(MESSAGE_SCROLL, |view: &mut ScrollView<NamedView<MessageLog>>| {
+                view.set_scroll_strategy(ScrollStrategy::StickToBottom);
+            });
+        }
+        "/debug wire on" | "/debug wire off" => {
+            let enabled = msg.ends_with("on");
+            tracer.set_enabled(enabled);
+            let state = if enabled { "on" } else { "off" };
+            toast(siv, ToastKind::Info, &format!("Wire tracing {} ({})", state, tracer.path().display()));
+        }
+        _ if msg == "/set" || msg.starts_with("/set ") => set(siv, &msg["/set".len()..], ctx),
+        _ if msg == "/export" || msg.starts_with("/export ") => export_pane(siv, &msg["/export".len()..]),
+        "/log" => {
+            let state = if ctx.chat_log.is_enabled() { "on" } else { "off" };
+            toast(siv, ToastKind::Info, &format!("Chat log {} ({}), /log on|off to switch", state, ctx.chat_log.dir().display()));
+        }
+        _ if msg.starts_with("/log ") => set(siv, &format!("log {}", &msg["/log ".len()..]), ctx),
+        // /connect <name> is the client's to carry out, with no name the saved servers are offered
+        "/connect" => match ctx.settings.load() {
+            Ok(settings) if settings.servers.is_empty() => toast(siv, ToastKind::Info, "No saved servers, add them to config.toml as [servers.<name>]"),
+            Ok(settings) => show_server_picker(siv, &settings.servers, None, false, |s, name| {
+                if let Some(name) = name {
+                    submit_input(s, &format!("/connect {}", name));
+                }
+            }),
+            Err(e) => toast(siv, ToastKind::Error, &format!("Settings not loaded: {}", e)),
+        },
+        _ if connect_to(msg).is_some() => return Some(msg.to_string()),
+        // So is forgetting a room's local history, the outbox is theirs
+        _ if purge_local(msg).is_some() => return Some(msg.to_string()),
+        "/settings" => show_settings(siv, ctx),
+        "/keys" => show_keys(siv, &ctx.keymap),
+        "/theme" => toast(siv, ToastKind::Info, &format!("Themes: {} (/theme <name> to switch)", themes::available().join(", "))),
+        _ if msg.starts_with("/theme ") => set(siv, &format!("theme {}", &msg["/theme ".len()..]), ctx),
+        "/raw" => {
+            markup.set_raw(!markup.is_raw());
+            let state = if markup.is_raw() { "exactly as sent" } else { "formatted" };
+            toast(siv, ToastKind::Info, &format!("New messages are shown {}", state));
+        }
+        "/ping" => {
+            siv.call_on_name(STATUS, |view: &mut StatusBar| view.ping_asked = true);
+            return Some(ping_line());
+        }
+        "/quit" => siv.quit(),
+        "/pins" => list_pins(siv),
+        "/quote" => match selected_message(siv) {
+            Some(_) => toast(siv, ToastKind::Info, "What you send next replies to the selected message"),
+            None => toast(siv, ToastKind::Error, "Usage: /quote <id>, or click a message"),
+        },
+        "/unquote" => {
+            siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.selected = None);
+            show_quote(siv);
+        }
+        _ if msg.starts_with("/quote ") => {
+            let id = msg["/quote ".len()..].trim().trim_start_matches('#');
+            let found = id.parse().ok().and_then(|id| siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.select(id)).flatten());
+            if found.is_none() {
+                toast(siv, ToastKind::Error, &format!("There is no message #{} here to quote", id));
+            }
+            show_quote(siv);
+        }
+        "/ignore" => {
+            let users = ignore.users();
+            if users.is_empty() {
+                toast(siv, ToastKind::Info, "You aren't ignoring anyone");
+            } else {
+                toast(siv, ToastKind::Info, &format!("Ignoring {}", users.join(", ")));
+            }
+        }
+        _ if matches!(msg.split_whitespace().next(), Some("/ignore" | "/unignore")) => {
+            let (command, user) = msg.split_once(' ').unwrap_or((msg, ""));
+            let user = user.trim();
+            let ignoring = command == "/ignore";
+            if user.is_empty() || user.contains(char::is_whitespace) {
+                toast(siv, ToastKind::Error, &format!("Usage: {} <user>", command));
+                return None;
+            }
+            match if ignoring { ignore.add(user) } else { ignore.remove(user) } {
+                Ok(true) if ignoring => toast(siv, ToastKind::Info, &format!("Ignoring {}, /unignore {} to see them again", user, user)),
+                Ok(true) => toast(siv, ToastKind::Info, &format!("No longer ignoring {}", user)),
+                Ok(false) if ignoring => toast(siv, ToastKind::Info, &format!("{} is ignored already", user)),
+                Ok(false) => toast(siv, ToastKind::Info, &format!("{} isn't ignored", user)),
+                Err(e) => toast(siv, ToastKind::Error, &format!("Only for this run, the list wasn't saved: {}", e)),
+            }
+        }
+        // Ours to keep quiet, the server's to show by our name
+        "/dnd" | "/dnd on" | "/dnd off" => {
+            let on = match msg {
+                "/dnd on" => true,
+                "/dnd off" => false,
+                _ => !ctx.notifier.dnd(),
+            };
+            set_dnd(siv, ctx, on);
+            return Some(dnd_line(on));
+        }
+        _ if msg == "/notify" || msg.starts_with("/notify ") => notify(siv, &msg["/notify".len()..], ctx),
+        _ if msg == "/emoji" || msg.starts_with("/emoji ") => show_emoji_search(siv, emoji, &msg["/emoji".len()..]),
+        _ if msg.starts_with("/expand") => {
+            match msg["/expand".len()..].trim().parse().ok().and_then(|number| markup.expand(number)) {
+                Some(block) => {
+                    append(siv, "\n");
+                    append(siv, block);
+                    append(siv, "\n");
+                }
+                None => toast(siv, ToastKind::Error, "Usage: /expand <n>, with n from a block's \"/expand n\""),
+            }
+        }
+        _ if matches!(msg.split_whitespace().next(), Some("/open" | "/copyurl")) => {
+            let (command, number) = msg.split_once(' ').unwrap_or((msg, ""));
+            match number.trim().parse().ok().and_then(|number| markup.link(number)) {
+                Some(url) if command == "/open" => match links::open(&url) {
+                    Ok(()) => toast(siv, ToastKind::Info, &format!("Opening {}", url)),
+                    Err(e) => toast(siv, ToastKind::Error, &format!("Could not open {}: {}", url, e)),
+                },
+                Some(url) => match clipboard::copy(&url) {
+                    Ok(()) => toast(siv, ToastKind::Info, &format!("Copied {}", url)),
+                    Err(e) => toast(siv, ToastKind::Error, &format!("Could not copy {}: {}", url, e)),
+                },
+                None => toast(siv, ToastKind::Error, &format!("Usage: {} <n>, with n from a link's [n]", command)),
+            }
+        }
+        "/copy" => match selected_message(siv) {
+            Some(selected) => match clipboard::copy(&selected.content) {
+                Ok(()) => toast(siv, ToastKind::Info, &format!("Copied {}'s message", selected.username)),
+                Err(e) => toast(siv, ToastKind::Error, &format!("Could not copy the message: {}", e)),
+            },
+            None => toast(siv, ToastKind::Error, "Click a message first to select it"),
+        },
+        // "/react 👍" goes to the message selected with a click
+        _ if msg.starts_with("/react ") && msg.split_whitespace().count() == 2 => {
+            let Some(id) = selected_message(siv).and_then(|selected| selected.id) else { return Some(emoji.expand(msg)) };
+            return Some(format!("/react {} {}", id, emoji.expand(msg["/react ".len()..].trim())));
+        }
+        // A fence on its own opens the editor, what comes back from it is a whole block to send as it is
+        _ if is_fence(msg) => show_code_editor(siv, msg.trim()["```".len()..].trim()),
+        _ if msg.starts_with("```") && msg.contains('\n') => return Some(msg.to_string()),
+        "/funface" => {
+            append(siv, FUNFACE); // Insert this fun guy, then share it like any other message
+            return Some(msg.to_string());
+        }
+        // Shortcodes are made exactly as typed, art and all
+        _ if msg.starts_with("/shortcode ") => return Some(msg.to_string()),
+        // Passwords are sent exactly as typed
+        _ if SECRET_COMMANDS.iter().any(|cmd| msg.starts_with(cmd)) => return Some(msg.to_string()),
+        // With a message selected, what we say replies to it (and lets go of it)
+        _ if !msg.starts_with('/') || msg.starts_with("/me ") => {
+            let Some(id) = selected_message(siv).and_then(|selected| selected.id) else { return Some(emoji.expand(msg)) };
+            siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.selected = None);
+            show_quote(siv);
+            return Some(format!("/reply {} {}", id, emoji.expand(msg)));
+        }
+        _ => return Some(emoji.expand(msg)),
+    }
+    None
+}
+
+// Which key does what: the ones [keys] in config.toml sets, then the fixed ones
+fn show_keys(siv: &mut Cursive, keymap: &Keymap) {
+    let mut text = key_list(keymap);
+    text.append_styled("\nChange them under [keys] in config.toml", Color::Dark(BaseColor::White));
+    let dialog = Dialog::around(TextView::new(text)).title("Keys").button("Close", |s| {
+        s.pop_layer();
+    });
+    siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+fn key_list(keymap: &Keymap) -> StyledString {
+    let mut text = StyledString::new();
+    let row = |text: &mut StyledString, key: &str, what: &str| {
+        text.append_styled(format!("{:<14}", key), Color::Light(BaseColor::Yellow));
+        text.append_plain(format!("{}\n", what));
+    };
+    for (action, key) in keymap.bindings() {
+        let key = key.map_or_else(|| "(none)".to_string(), key_name);
+        row(&mut text, &key, action.describe());
+    }
+    text.append_styled("\nFixed\n", Color::Dark(BaseColor::White));
+    for (key, what) in FIXED_KEYS {
+        row(&mut text, key, what);
+    }
+    text.append_styled("\nIn navigation mode\n", Color::Dark(BaseColor::White));
+    for (key, what) in NAVIGATION_KEYS {
+        row(&mut text, key, what);
+    }
+    text
+}
+
+// /help: what the client and the server can do, the keys and which version this is, over the chat
+// Only one is up at a time, Esc closes it
+fn show_help(siv: &mut Cursive, ctx: &Context) {
+    if siv.find_name::<TextView>(HELP).is_some() {
+        return;
+    }
+    let heading = |text: &mut StyledString, title: &str| text.append_styled(format!("{}\n", title), Style::from(Color::Light(BaseColor::Cyan)).combine(Effect::Bold));
+    let row = |text: &mut StyledString, usage: &str, about: &str| {
+        text.append_styled(usage.to_string(), Color::Light(BaseColor::Yellow));
+        text.append_plain(format!(" - {}\n", about));
+    };
+    let mut text = StyledString::plain(format!("{} {}\n\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    heading(&mut text, "This client");
+    for (usage, about) in CLIENT_COMMANDS {
+        row(&mut text, usage, about);
+    }
+    heading(&mut text, "\nThe server");
+    let server_commands = ctx.server_commands.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    if server_commands.is_empty() {
+        text.append_styled("It hasn't said which commands it has (an older server, or not connected yet)\n", Color::Dark(BaseColor::White));
+    }
+    for command in &server_commands {
+        row(&mut text, &command.usage, &command.about);
+    }
+    heading(&mut text, "\nKeys");
+    text.append(key_list(&ctx.keymap));
+    let dialog = Dialog::around(TextView::new(text).with_name(HELP).scrollable()).title("Help").button("Close", |s| {
+        s.pop_layer();
+    });
+    siv.add_layer(OnEventView::new(dialog.max_width(100).max_height(50)).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+// The settings /set changes, as switches and lists: each change goes through /set, so it applies right away
+// and is saved in config.toml. Only one is up at a time
+fn show_settings(siv: &mut Cursive, ctx: &Context) {
+    if siv.find_name::<LinearLayout>(SETTINGS).is_some() {
+        return;
+    }
+    let toggle = |label: &str, checked: bool, setting: &'static str| {
+        let ctx = ctx.clone();
+        let checkbox = Checkbox::new().with_checked(checked).on_change(move |s, on| {
+            set(s, &format!("{} {}", setting, if on { "on" } else { "off" }), &ctx);
+        });
+        LinearLayout::horizontal().child(checkbox).child(TextView::new(format!(" {}", label)))
+    };
+    let choice = |label: &str, options: Vec<String>, current: &str, setting: &'static str| {
+        let ctx = ctx.clone();
+        let selected = options.iter().position(|option| option == current).unwrap_or(0);
+        let list = SelectView::new().popup().with_all_str(options).selected(selected).on_submit(move |s, value: &String| {
+            set(s, &format!("{} {}", setting, value), &ctx);
+        });
+        LinearLayout::horizontal().child(TextView::new(format!("{:<12}", label))).child(list)
+    };
+    let times = if ctx.markup.absolute_times() { "absolute" } else { "relative" };
+    // The theme as it was last saved, the one /set theme put up this run when there's no config.toml
+    let theme = ctx.settings.load().map(|settings| settings.theme).unwrap_or_else(|_| DEFAULT_THEME.to_string());
+
+    let panel = LinearLayout::vertical()
+        .child(toggle("Emoji substitution, :) and :rocket: as you send", ctx.emoji.is_enabled(), "emojify"))
+        .child(toggle("Notify me when someone says my name or whispers", ctx.notifier.mentions(), "mentions"))
+        .child(toggle("Ring the terminal bell for it", ctx.notifier.bell(), "bell"))
+        .child(toggle("And put up a desktop notification", ctx.notifier.desktop(), "desktop"))
+        .child(toggle("Keep a log of the chat, a file a day", ctx.chat_log.is_enabled(), "log"))
+        .child(DummyView)
+        .child(choice("Timestamps", vec!["relative".to_string(), "absolute".to_string()], times, "times"))
+        .child(choice("Theme", themes::available(), &theme, "theme"))
+        .with_name(SETTINGS);
+    let dialog = Dialog::around(panel).title("Settings").button("Close", |s| {
+        s.pop_layer();
+    });
+    siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+// "/export [path] [--format txt|json|html]": the message pane as it is now, written to a file
+fn export_pane(siv: &mut Cursive, args: &str) {
+    let (path, format) = match export::parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            toast(siv, ToastKind::Error, &e);
+            return;
+        }
+    };
+    let palette = siv.current_theme().palette.clone();
+    let written = siv.call_on_name(MESSAGES, |view: &mut MessageLog| {
+        if view.content.is_empty() {
+            return Ok(None);
+        }
+        let messages: Vec<&ChatMessage> = view.pieces.iter().filter_map(|(_, msg)| msg.as_ref()).collect();
+        export::write(&path, format, &view.content, &messages, &palette).map(|()| Some(messages.len()))
+    });
+    match written {
+        Some(Ok(Some(count))) => toast(siv, ToastKind::Info, &format!("Exported {} messages to {}", count, path.display())),
+        Some(Ok(None)) => toast(siv, ToastKind::Info, "Nothing to export, the message view is empty"),
+        Some(Err(e)) => toast(siv, ToastKind::Error, &format!("Couldn't export, {}", e)),
+        None => {}
+    }
+}
+
+// "/set <setting> <value>": takes effect right away (username and server from the next start)
+// and is saved in config.toml for the runs after
+fn set(siv: &mut Cursive, args: &str, ctx: &Context) {
+    let (setting, value) = args.trim().split_once(' ').map_or((args.trim(), ""), |(setting, value)| (setting, value.trim()));
+    let on = match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    };
+    let state = |on: bool| if on { "on" } else { "off" };
+    let (key, saved, done): (&str, toml::Value, String) = match (setting, on) {
+        ("emojify", Some(on)) => {
+            ctx.emoji.set_enabled(on);
+            ("emojify", on.into(), format!("Emoji substitution {}", state(on)))
+        }
+        ("mentions", Some(on)) => {
+            ctx.notifier.set_mentions(on);
+            ("notifications.mentions", on.into(), format!("Notifications when someone wants you {}", state(on)))
+        }
+        ("bell", Some(on)) => {
+            ctx.notifier.set_bell(on);
+            ("notifications.bell", on.into(), format!("Terminal bell {}", state(on)))
+        }
+        ("desktop", Some(on)) => {
+            ctx.notifier.set_desktop(on);
+            ("notifications.desktop", on.into(), format!("Desktop notifications {}", state(on)))
+        }
+        ("log", Some(on)) => {
+            ctx.chat_log.set_enabled(on);
+            ("log.enabled", on.into(), format!("Chat log {} ({})", state(on), ctx.chat_log.dir().display()))
+        }
+        ("times", _) if value == "relative" || value == "absolute" => {
+            ctx.markup.set_absolute_times(value == "absolute");
+            let shown = if value == "absolute" { "with their full date and time" } else { "relative to today" };
+            ("times", value.into(), format!("New messages are stamped {}", shown))
+        }
+        ("theme", _) => match themes::load(value) {
+            Ok(theme) => {
+                siv.set_theme(theme);
+                ("theme", value.into(), format!("Theme {}", value))
+            }
+            Err(e) => {
+                toast(siv, ToastKind::Error, &format!("Couldn't switch themes, {}", e));
+                return;
+            }
+        },
+        ("username", _) if !value.is_empty() && !value.contains(char::is_whitespace) => {
+            ("username", value.into(), format!("Signing in as {} from the next start", value))
+        }
+        ("server", _) if !value.is_empty() && !value.contains(char::is_whitespace) => {
+            ("server", value.into(), format!("Connecting to {} from the next start", value))
+        }
+        _ => {
+            toast(siv, ToastKind::Error, SET_USAGE);
+            return;
+        }
+    };
+    save(siv, ctx, key, saved, &done);
+}
+
+// Keeps a change in config.toml and says it's done (and whether it's kept)
+fn save(siv: &mut Cursive, ctx: &Context, key: &str, value: toml::Value, done: &str) {
+    match ctx.settings.save(key, value) {
+        Ok(true) => toast(siv, ToastKind::Info, done),
+        Ok(false) => toast(siv, ToastKind::Info, &format!("{} (for this run, there's no config.toml to keep it in)", done)),
+        Err(e) => toast(siv, ToastKind::Error, &format!("{} for this run only, it wasn't saved: {}", done, e)),
+    }
+}
+
+// "/notify add|remove|on|off <keyword>": the words that call on us like our name does, "/notify" lists them
+fn notify(siv: &mut Cursive, args: &str, ctx: &Context) {
+    let (action, keyword) = args.trim().split_once(' ').map_or((args.trim(), ""), |(action, keyword)| (action, keyword.trim()));
+    let keyword = keyword.to_ascii_lowercase();
+    let known = ctx.notifier.keywords().contains_key(&keyword);
+    let done = match action {
+        "" => {
+            let keywords = ctx.notifier.keywords();
+            let listed: Vec<String> = keywords.iter().map(|(keyword, on)| if *on { keyword.clone() } else { format!("{} (off)", keyword) }).collect();
+            let text = if listed.is_empty() { "No keywords, /notify add <keyword> to watch for one".to_string() } else { format!("Keywords: {}", listed.join(", ")) };
+            toast(siv, ToastKind::Info, &text);
+            return;
+        }
+        _ if keyword.is_empty() => {
+            toast(siv, ToastKind::Error, NOTIFY_USAGE);
+            return;
+        }
+        "add" => {
+            ctx.notifier.set_keyword(&keyword, true);
+            format!("Watching for {:?}", keyword)
+        }
+        "remove" if ctx.notifier.remove_keyword(&keyword) => format!("No longer watching for {:?}", keyword),
+        "on" | "off" if known => {
+            ctx.notifier.set_keyword(&keyword, action == "on");
+            format!("{:?} switched {}", keyword, action)
+        }
+        "remove" | "on" | "off" => {
+            toast(siv, ToastKind::Error, &format!("{:?} isn't a keyword, /notify add {} to watch for it", keyword, keyword));
+            return;
+        }
+        _ => {
+            toast(siv, ToastKind::Error, NOTIFY_USAGE);
+            return;
+        }
+    };
+    let keywords: toml::Table = ctx.notifier.keywords().into_iter().map(|(keyword, on)| (keyword, on.into())).collect();
+    save(siv, ctx, "notifications.keywords", keywords.into(), &done);
+}
+
+// "```" or "```rust" alone, which starts a code block
+fn is_fence(line: &str) -> bool {
+    line.trim().strip_prefix("```").is_some_and(|lang| !lang.contains('`') && !lang.contains(char::is_whitespace))
+}
+
+// A multi-line editor for a code block, sent fenced (with its language tag) like any typed line
+pub fn show_code_editor(siv: &mut Cursive, lang: &str) {
+    let lang = lang.to_string();
+    let title = if lang.is_empty() { "Code block".to_string() } else { format!("Code block ({})", lang) };
+    let editor = TextArea::new().with_name(CODE_EDITOR).min_width(60).min_height(8);
+    let dialog = Dialog::around(editor)
+        .title(title)
+        .button("Send", move |s| {
+            let code = s
+                .call_on_name(CODE_EDITOR, |view: &mut TextArea| view.get_content().trim_end().to_string())
+                .unwrap_or_default();
+            s.pop_layer();
+            if !code.trim().is_empty() {
+                submit_input(s, &format!("```{}\n{}\n```", lang, code));
+            }
+        })
+        .button("Cancel", |s| {
+            s.pop_layer();
+        });
+    siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+// Hands text to the input box's on_submit, as if it had been typed there
+fn submit_input(siv: &mut Cursive, text: &str) {
+    let result = siv.call_on_name(INPUT, |view: &mut EditView| {
+        view.set_content(text);
+        view.on_event(Event::Key(Key::Enter))
+    });
+    if let Some(result) = result {
+        result.process(siv);
+    }
+}
+
+// The answer to /rooms, one line per room to pick with Enter or a click, which joins it
+// A newer answer while it's up refreshes the list in place
+fn show_room_list(siv: &mut Cursive, rooms: &[RoomInfo]) {
+    if rooms.is_empty() {
+        toast(siv, ToastKind::Info, "No public rooms are open right now");
+        return;
+    }
+    let items: Vec<(StyledString, String)> = rooms
+        .iter()
+        .map(|room| {
+            let mut label = StyledString::styled(format!("#{:<20}", room.name), Color::Light(BaseColor::Magenta));
+            label.append_styled(format!(" 👥 {:<4}", room.members), Color::Light(BaseColor::Cyan));
+            if let Some(topic) = &room.topic {
+                label.append_plain(format!(" {}", topic));
+            }
+            (label, room.name.clone())
+        })
+        .collect();
+    let refreshed = siv.call_on_name(ROOM_LIST, |view: &mut SelectView<String>| {
+        view.clear();
+        view.add_all(items.clone());
+    });
+    if refreshed.is_some() {
+        return;
+    }
+    let list = SelectView::new().with_all(items).on_submit(|s, room: &String| {
+        s.pop_layer();
+        submit_input(s, &format!("/join {}", room));
+    });
+    let dialog = Dialog::around(list.with_name(ROOM_LIST).scrollable().max_height(15)).title("Rooms").button("Close", |s| {
+        s.pop_layer();
+    });
+    siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+// Lists the shortcodes matching a query in the message view
+fn show_emoji_search(siv: &mut Cursive, emoji: &Emoji, query: &str) {
+    let query = query.trim();
+    let matches = emoji.search(query);
+    if matches.is_empty() {
+        toast(siv, ToastKind::Info, &format!("No shortcodes match \"{}\"", query));
+        return;
+    }
+    let mut list = format!("\n=== Emoji matching \"{}\" ===\n", query);
+    for (code, emoji) in matches.iter().take(MAX_SEARCH_RESULTS) {
+        list.push_str(&format!("{}  :{}:\n", emoji, code));
+    }
+    if matches.len() > MAX_SEARCH_RESULTS {
+        list.push_str(&format!("...and {} more, narrow it down\n", matches.len() - MAX_SEARCH_RESULTS));
+    }
+    append(siv, list);
+}
+
+// Lets the user pick an emoji from a list grouped by category, narrowed down as they type
+// The pick goes into the input box where its cursor was, Enter in the search box takes the first match
+pub fn show_emoji_picker(siv: &mut Cursive, emoji: Arc<Emoji>) {
+    if siv.find_name::<Dialog>(EMOJI_PICKER).is_some() {
+        return;
+    }
+    let search = EditView::new()
+        .on_edit({
+            let emoji = emoji.clone();
+            move |s, query, _| {
+                s.call_on_name(EMOJI_LIST, |list: &mut SelectView<Option<String>>| fill_emoji_list(list, &emoji, query));
+            }
+        })
+        .on_submit(|s, _| {
+            let first = s.call_on_name(EMOJI_LIST, |list: &mut SelectView<Option<String>>| {
+                list.iter().find_map(|(_, choice)| choice.clone())
+            });
+            if let Some(Some(picked)) = first {
+                insert_emoji(s, &picked);
+            }
+        })
+        .with_name(EMOJI_SEARCH)
+        .fixed_width(30);
+
+    // Only "No matches" comes without an emoji
+    let mut list = SelectView::<Option<String>>::new().on_submit(|s, choice: &Option<String>| {
+        if let Some(picked) = choice {
+            insert_emoji(s, picked);
+        }
+    });
+    fill_emoji_list(&mut list, &emoji, "");
+
+    let layout = LinearLayout::vertical()
+        .child(search)
+        .child(DummyView)
+        .child(list.with_name(EMOJI_LIST).scrollable().fixed_height(12));
+    let dialog = Dialog::around(layout).title("Emoji").button("Close", |s| {
+        s.pop_layer();
+    });
+    // Esc closes the picker rather than the whole chat
+    siv.add_layer(OnEventView::new(dialog.with_name(EMOJI_PICKER)).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+fn fill_emoji_list(list: &mut SelectView<Option<String>>, emoji: &Emoji, query: &str) {
+    list.clear();
+    for (category, codes) in emoji.search_by_category(query.trim()) {
+        // The category goes in a column on the left, next to its first emoji
+        for (i, (code, emoji)) in codes.into_iter().enumerate() {
+            let heading = if i == 0 { category } else { "" };
+            let mut label = StyledString::styled(format!("{:<9}", heading), Color::Light(BaseColor::Cyan));
+            label.append_plain(format!("{}  :{}:", emoji, code));
+            list.add_item(label, Some(emoji.to_string()));
+        }
+    }
+    if list.is_empty() {
+        list.add_item("No matches", None);
+    }
+}
+
+// Closes the picker and puts the emoji in the input box at its cursor
+fn insert_emoji(siv: &mut Cursive, picked: &str) {
+    siv.pop_layer();
+    siv.call_on_name(INPUT, |view: &mut EditView| {
+        for c in picked.chars() {
+            let _ = view.insert(c);
+        }
+    });
+}
+
+// Shows a frame from the server in the message view (or the status bar and roster, for user counts, pings and who's online)
+pub fn show_message(siv: &mut Cursive, msg: &ChatMessage, ctx: &Context) {
+    let markup = &ctx.markup;
+    if ctx.ignore.hides(msg) {
+        return;
+    }
+    if msg.message_type == MessageType::UserCount {
+        if let Ok(count) = msg.content.parse::<usize>() {
+            set_user_count(siv, count);
+        }
+        return;
+    }
+    if msg.message_type == MessageType::Roster {
+        set_roster(siv, &msg.roster);
+        return;
+    }
+    if msg.message_type == MessageType::Ping {
+        pong(siv, &msg.content);
+        return;
+    }
+    if matches!(msg.message_type, MessageType::ShortcodeSync | MessageType::Key) {
+        return; // the connection hands these to the emoji table and the whisper keys
+    }
+    if msg.message_type == MessageType::Capabilities {
+        *ctx.server_commands.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = msg.commands.clone();
+        return;
+    }
+    if msg.message_type == MessageType::Pins {
+        if let Some(room) = &msg.room {
+            siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.set_pins(room, msg.pins.clone()));
+            if msg.room == current_room(siv) {
+                show_pins(siv);
+            }
+        }
+        return;
+    }
+    if msg.message_type == MessageType::RoomList {
+        show_room_list(siv, &msg.rooms);
+        return;
+    }
+    if msg.message_type == MessageType::Seen {
+        toast(siv, ToastKind::Info, &format!("✓ {} saw your whisper", msg.username));
+        return;
+    }
+    // In do not disturb, whispers from others wait for it to be over (see set_dnd)
+    if msg.message_type == MessageType::Whisper
+        && !ctx.notifier.from_us(msg)
+        && siv.call_on_name(STATUS, |view: &mut StatusBar| view.hold(msg)).unwrap_or(false)
+    {
+        return;
+    }
+    // Whispers land in the pane whatever tab is up, the whisperer hears we saw them once we're scrolled down to them
+    if msg.message_type == MessageType::Whisper {
+        siv.call_on_name(TABS, |tabs: &mut TabBar| {
+            if !tabs.unseen_whispers.contains(&msg.username) {
+                tabs.unseen_whispers.push(msg.username.clone());
+            }
+        });
+    }
+    if msg.message_type == MessageType::Draft {
+        if let Some(room) = &msg.room {
+            take_draft(siv, room, &msg.content);
+        }
+        return;
+    }
+    // The room we talk in gets a tab (if it has none yet) and comes up
+    if msg.message_type == MessageType::RoomJoined
+        && let Some(room) = &msg.room
+    {
+        open_tab(siv, room, msg.read_only, msg.topic.as_deref().unwrap_or_default(), msg.last_read);
+    }
+    if msg.message_type == MessageType::RoomLeft
+        && let Some(room) = &msg.room
+    {
+        close_tab(siv, room);
+    }
+    // Only the room we talk in decides whether the input box is usable, the others' tabs remember it for later
+    if msg.message_type == MessageType::RoomMode
+        && let Some(room) = &msg.room
+    {
+        siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.set_read_only(room, msg.read_only));
+        if msg.room == current_room(siv) {
+            set_read_only(siv, msg.read_only);
+        }
+    }
+    // A new topic goes in the room's tab, and in the header if we talk there
+    if msg.message_type == MessageType::SystemNotification
+        && let (Some(room), Some(topic)) = (&msg.room, &msg.topic)
+    {
+        siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.set_topic(room, topic));
+        if msg.room == current_room(siv) {
+            set_topic(siv, topic);
+        }
+    }
+    // Someone joining or leaving one of our rooms (or changing its topic) is worth a glance, not a line in the history
+    // (those are the only notices the server tags with a room)
+    if msg.message_type == MessageType::SystemNotification && msg.room.is_some() {
+        toast(siv, ToastKind::Info, &format!("{} {}", msg.username, msg.content));
+        return;
+    }
+    // What people said can be selected with a click, what the server said can't
+    let selectable = matches!(msg.message_type, MessageType::UserMessage | MessageType::Action | MessageType::Whisper);
+    // Frames for a room whose tab isn't up wait in that tab, everything else goes on screen
+    let waiting = msg.room.as_deref().filter(|room| siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.is_waiting(room)).unwrap_or(false));
+    // The first message newer than where we left off in a room comes under a divider
+    let divides = selectable
+        && msg.id.zip(msg.room.as_deref()).is_some_and(|(id, room)| siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.divides(room, id)).unwrap_or(false));
+    if divides {
+        let divider = StyledString::styled("──────── new since you were last here ────────\n", Color::Light(BaseColor::Yellow));
+        match waiting {
+            Some(room) => {
+                siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.hold(room, divider, None));
+            }
+            None => append(siv, divider),
+        }
+    }
+    // Being called on (or someone saying a keyword we watch for) rings the bell and puts up a desktop notification,
+    // and where it can't be seen yet it gets a toast too. Not a word of it in do not disturb
+    let keywords = ctx.notifier.keywords_in(msg);
+    let mentioned = ctx.notifier.wants_us(msg);
+    if !ctx.notifier.dnd() && (mentioned || !keywords.is_empty() && ctx.notifier.is_fresh(msg)) {
+        ctx.notifier.ring();
+        ctx.notifier.alert(&msg.username, &msg.content);
+        if let Some(room) = waiting {
+            let what = if mentioned { "mentioned you".to_string() } else { format!("said {:?}", keywords[0]) };
+            toast(siv, ToastKind::Info, &format!("{} {} in #{}", msg.username, what, room));
+        }
+    }
+    // Someone saying several things in a row gets one header for all of them
+    let follows_up = !divides
+        && match waiting {
+            Some(room) => siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.continues(room, msg)),
+            None => siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.continues(msg)),
+        }
+        .unwrap_or(false);
+    let text = if follows_up { format_follow_up(msg, markup) } else { format_message(msg, markup) };
+    let text = if keywords.is_empty() { text } else { mark_keywords(text, &keywords) };
+    if !text.is_empty()
+        && let Err(e) = ctx.chat_log.record(msg)
+    {
+        toast(siv, ToastKind::Error, &format!("Chat log turned off, {}", e));
+    }
+    if let Some(room) = waiting {
+        siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.hold(room, text, selectable.then_some(msg)));
+    } else if selectable {
+        siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.append_message(text, msg));
+    } else {
+        append(siv, text);
+    }
+}
+
+// Brings up the tab of a room we joined (or switched to), opening one if it's new
+// `last_read` is where our account left off there, the first message after it gets the divider
+fn open_tab(siv: &mut Cursive, room: &str, read_only: bool, topic: &str, last_read: Option<i64>) {
+    let index = siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        let index = tabs.position(room).unwrap_or_else(|| {
+            tabs.tabs.push(Tab::new(room));
+            tabs.tabs.len() - 1
+        });
+        tabs.tabs[index].read_only = read_only;
+        tabs.tabs[index].topic = topic.to_string();
+        let tab = &mut tabs.tabs[index];
+        tab.read = tab.read.max(last_read);
+        tab.divider_after = last_read.and(tab.read);
+        index
+    });
+    if let Some(index) = index {
+        switch_tab(siv, index);
+    }
+}
+
+// Drops the tab of a room we left, and its messages with it
+// When it was up another one comes up for now, the server says which room we talk in next
+fn close_tab(siv: &mut Cursive, room: &str) {
+    let Some((index, active, count)) = siv
+        .call_on_name(TABS, |tabs: &mut TabBar| tabs.position(room).map(|index| (index, tabs.active, tabs.tabs.len())))
+        .flatten()
+    else {
+        return;
+    };
+    if count == 1 {
+        return; // the server never lets us leave our last room
+    }
+    if index == active {
+        switch_tab(siv, if index == 0 { 1 } else { 0 });
+    }
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        tabs.tabs.remove(index);
+        tabs.unsent_drafts.remove(room); // the server no longer takes drafts for a room we left
+        if tabs.active > index {
+            tabs.active -= 1;
+        }
+    });
+}
+
+// Puts tab `index` up: its messages go in the pane (the ones there go back to their own tab)
+// and its room, topic and mode in the status bar, header and input box. Returns the room, None when there's no such tab
+pub fn switch_tab(siv: &mut Cursive, index: usize) -> Option<String> {
+    if !siv.call_on_name(TABS, |tabs: &mut TabBar| index < tabs.tabs.len()).unwrap_or(false) {
+        return None;
+    }
+    let mut log = siv.call_on_name(MESSAGES, |view: &mut MessageLog| std::mem::take(view)).unwrap_or_default();
+    let (room, topic, read_only) = siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        let active = tabs.active;
+        std::mem::swap(&mut tabs.tabs[active].log, &mut log);
+        tabs.active = index;
+        let tab = &mut tabs.tabs[index];
+        std::mem::swap(&mut tab.log, &mut log);
+        tab.unread = 0;
+        (tab.room.clone(), tab.topic.clone(), tab.read_only)
+    })?;
+    log.shown_again();
+    siv.call_on_name(MESSAGES, |view: &mut MessageLog| *view = log);
+    siv.call_on_name(MESSAGE_SCROLL, |view: &mut ScrollView<NamedView<MessageLog>>| {
+        view.set_scroll_strategy(ScrollStrategy::StickToBottom);
+    });
+    set_room(siv, &room);
+    set_topic(siv, &topic);
+    show_pins(siv);
+    show_quote(siv);
+    set_read_only(siv, read_only);
+    let draft = siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.drafts.get(&room).cloned()).flatten().unwrap_or_default();
+    siv.call_on_name(INPUT, |view: &mut EditView| {
+        view.set_content(draft);
+    });
+    Some(room)
+}
+
+// Remembers what is typed in the room we talk in, to go to our other devices once typing pauses
+fn draft_edited(siv: &mut Cursive, text: &str) {
+    let Some(room) = current_room(siv) else { return };
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        if tabs.drafts.get(&room).map_or("", String::as_str) != text {
+            tabs.drafts.insert(room.clone(), text.to_string());
+            tabs.unsent_drafts.insert(room, Instant::now());
+        }
+    });
+}
+
+// A draft from the server, the newest any of our devices wrote
+// Ignored while we have changes of our own on the way: those are newer and will replace it everywhere
+fn take_draft(siv: &mut Cursive, room: &str, text: &str) {
+    let taken = siv
+        .call_on_name(TABS, |tabs: &mut TabBar| {
+            let taken = !tabs.unsent_drafts.contains_key(room);
+            if taken {
+                tabs.drafts.insert(room.to_string(), text.to_string());
+            }
+            taken
+        })
+        .unwrap_or(false);
+    if taken && current_room(siv).as_deref() == Some(room) {
+        siv.call_on_name(INPUT, |view: &mut EditView| {
+            if view.get_content().as_str() != text {
+                view.set_content(text);
+            }
+        });
+    }
+}
+
+// "/draft <room> <text>" lines for drafts nobody typed in for DRAFT_DEBOUNCE, to send to the server
+pub fn due_drafts(siv: &mut Cursive, now: Instant) -> Vec<String> {
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        let due: Vec<String> = tabs
+            .unsent_drafts
+            .iter()
+            .filter(|(_, edited)| now.duration_since(**edited) >= DRAFT_DEBOUNCE)
+            .map(|(room, _)| room.clone())
+            .collect();
+        due.into_iter()
+            .map(|room| {
+                tabs.unsent_drafts.remove(&room);
+                format!("/draft {} {}", room, tabs.drafts.get(&room).map_or("", String::as_str))
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+pub fn is_draft(line: &str) -> bool {
+    line.starts_with("/draft ")
+}
+
+// Once we're scrolled down to the newest message: "/read <room> <id>" when that's further than we had read,
+// so the server keeps our place for every device, and "/read @<user>" for whoever whispered to us since
+pub fn due_reads(siv: &mut Cursive) -> Vec<String> {
+    let at_bottom = siv.call_on_name(MESSAGE_SCROLL, |view: &mut ScrollView<NamedView<MessageLog>>| view.is_at_bottom()).unwrap_or(false);
+    if !at_bottom {
+        return Vec::new();
+    }
+    let newest = siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.newest_id()).flatten();
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        let mut lines: Vec<String> = tabs.unseen_whispers.drain(..).map(|user| format!("/read @{}", user)).collect();
+        let active = tabs.active;
+        let tab = &mut tabs.tabs[active];
+        // Caught up, what comes next is read as it comes and needs no divider
+        tab.divider_after = None;
+        if let Some(id) = newest.filter(|&id| tab.read.is_none_or(|read| id > read)) {
+            tab.read = Some(id);
+            lines.push(format!("/read {} {}", tab.room, id));
+        }
+        lines
+    })
+    .unwrap_or_default()
+}
+
+// Shows the room our messages go to in the status bar
+pub fn set_room(siv: &mut Cursive, room: &str) {
+    siv.call_on_name(STATUS, |view: &mut StatusBar| view.room = room.to_string());
+}
+
+// Shows what the room we talk in is about under the header, nothing when it has no topic
+pub fn set_topic(siv: &mut Cursive, topic: &str) {
+    siv.call_on_name(TOPIC, |view: &mut TextView| view.set_content(topic));
+}
+
+// The room shown in the status bar, the one our lines are said in
+pub fn current_room(siv: &mut Cursive) -> Option<String> {
+    siv.call_on_name(STATUS, |view: &mut StatusBar| view.room.clone())
+}
+
+// Greys out the input box (and says why) while we talk in a room we can't post in
+pub fn set_read_only(siv: &mut Cursive, read_only: bool) {
+    let title = if read_only { "Read-only room, commands only".to_string() } else { INPUT_TITLE.to_string() };
+    siv.call_on_name(INPUT_BOX, |view: &mut Dialog| view.set_title(title));
+    let style = if read_only { PaletteStyle::EditableTextInactive } else { PaletteStyle::EditableText };
+    siv.call_on_name(INPUT, |view: &mut EditView| view.set_style(style));
+}
+
+fn is_read_only(siv: &mut Cursive) -> bool {
+    siv.call_on_name(INPUT_BOX, |view: &mut Dialog| view.get_title() != INPUT_TITLE).unwrap_or(false)
+}
+
+pub fn set_user_count(siv: &mut Cursive, count: usize) {
+    siv.call_on_name(STATUS, |view: &mut StatusBar| view.users = Some(count));
+}
+
+pub fn set_connection(siv: &mut Cursive, connection: Connection) {
+    siv.call_on_name(STATUS, |view: &mut StatusBar| {
+        view.connection = connection;
+        if connection != Connection::Connected {
+            view.ping = None; // whatever it was, it's not the line we'll have next
+        }
+    });
+}
+
+// Everyone online in their chat colours, signed-in accounts with a ✔ and those who don't want to be disturbed with a ⛔
+pub fn set_roster(siv: &mut Cursive, members: &[Member]) {
+    let mut roster = StyledString::new();
+    for member in members {
+        roster.append_styled(member.username.clone(), color_for_username(&member.username));
+        if member.verified {
+            roster.append_styled(" ✔", Color::Light(BaseColor::Green));
+        }
+        if member.dnd {
+            roster.append_styled(" ⛔", Color::Light(BaseColor::Red)); // do not disturb
+        }
+        roster.append_plain("\n");
+    }
+    siv.call_on_name(ROSTER, |view: &mut TextView| view.set_content(roster));
+    let title = format!("Online ({})", members.len());
+    siv.call_on_name(ROSTER_PANEL, |view: &mut HideableView<ResizedView<Dialog>>| view.get_inner_mut().get_inner_mut().set_title(title));
+}
+
+// The pinned messages of the room we talk in, over its messages: one line saying how many until F3 opens it up
+// Not there at all when nothing is pinned
+fn show_pins(siv: &mut Cursive) {
+    let Some((pins, expanded)) = siv.call_on_name(TABS, |tabs: &mut TabBar| (tabs.tabs[tabs.active].pins.clone(), tabs.pins_expanded)) else {
+        return;
+    };
+    let mut text = StyledString::new();
+    if expanded {
+        text.append_styled("📌 Pinned (F3 to fold)\n", Color::Light(BaseColor::Yellow));
+        for pin in &pins {
+            text.append(pin_line(pin));
+            text.append_plain("\n");
+        }
+    } else {
+        let count = format!("📌 {} pinned message{} (F3 to show)", pins.len(), if pins.len() == 1 { "" } else { "s" });
+        text.append_styled(count, Color::Light(BaseColor::Yellow));
+    }
+    siv.call_on_name(PINS, |view: &mut TextView| view.set_content(text));
+    siv.call_on_name(PINS_PANEL, |view: &mut HideableView<NamedView<TextView>>| view.set_visible(!pins.is_empty()));
+}
+
+// "#12 Luna: the first line of what they said"
+fn pin_line(pin: &ChatMessage) -> StyledString {
+    let mut line = StyledString::styled(format!("#{} ", pin.id.unwrap_or_default()), Color::Dark(BaseColor::White));
+    line.append_styled(pin.username.clone(), color_for_username(&pin.username));
+    line.append_plain(format!(": {}", pin.content.lines().next().unwrap_or_default()));
+    line
+}
+
+// F3 opens the pins up, or folds them back into one line
+fn toggle_pins(siv: &mut Cursive) {
+    siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.pins_expanded = !tabs.pins_expanded);
+    show_pins(siv);
+}
+
+// /pins: the pinned messages of the room we talk in, in the message view
+fn list_pins(siv: &mut Cursive) {
+    let Some(room) = current_room(siv) else { return };
+    let pins = siv.call_on_name(TABS, |tabs: &mut TabBar| tabs.tabs[tabs.active].pins.clone()).unwrap_or_default();
+    if pins.is_empty() {
+        toast(siv, ToastKind::Info, &format!("Nothing is pinned in #{}", room));
+        return;
+    }
+    let mut list = StyledString::plain(format!("\n=== Pinned in #{} ===\n", room));
+    for pin in &pins {
+        list.append(pin_line(pin));
+        list.append_plain("\n");
+    }
+    append(siv, list);
+}
+
+// F2 shows the roster, or hides it again
+fn toggle_roster(siv: &mut Cursive) {
+    siv.call_on_name(ROSTER_PANEL, |view: &mut HideableView<ResizedView<Dialog>>| view.set_visible(!view.is_visible()));
+}
+
+// "/ping <ms since the epoch>", which the server sends straight back in a Ping frame
+// What tells the server we're in do not disturb, or out of it: after /dnd, and on every new connection while it's on
+pub fn dnd_line(on: bool) -> String {
+    format!("/dnd {}", if on { "on" } else { "off" })
+}
+
+// Do not disturb on, or off again. Once it's over, the whispers that waited are summed up in a dialog
+// and then go in the pane like any other
+fn set_dnd(siv: &mut Cursive, ctx: &Context, on: bool) {
+    if on {
+        ctx.notifier.set_dnd(true);
+        siv.call_on_name(STATUS, |view: &mut StatusBar| {
+            view.dnd.get_or_insert_with(Vec::new);
+        });
+        toast(siv, ToastKind::Info, "Do not disturb, no notifications and whispers wait until /dnd again");
+        return;
+    }
+    let held = siv.call_on_name(STATUS, |view: &mut StatusBar| view.dnd.take()).flatten().unwrap_or_default();
+    // Still quiet while they go in, the summary says it all
+    for msg in &held {
+        show_message(siv, msg, ctx);
+    }
+    ctx.notifier.set_dnd(false);
+    if held.is_empty() {
+        toast(siv, ToastKind::Info, "Do not disturb is over, no whispers came");
+        return;
+    }
+    let mut text = StyledString::new();
+    for msg in &held {
+        text.append_styled(format!("{}  ", local_time(&msg.timestamp, ctx.markup.absolute_times())), Color::Dark(BaseColor::White));
+        text.append_styled(msg.username.clone(), color_for_username(&msg.username));
+        text.append_plain(format!(": {}\n", msg.content));
+    }
+    let title = format!("{} whisper{} while you weren't to be disturbed", held.len(), if held.len() == 1 { "" } else { "s" });
+    let dialog = Dialog::around(TextView::new(text).scrollable()).title(title).button("Close", |s| {
+        s.pop_layer();
+    });
+    siv.add_layer(OnEventView::new(dialog.max_width(100).max_height(30)).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+pub fn ping_line() -> String {
+    format!("/ping {}", epoch_millis())
+}
+
+pub fn is_ping(line: &str) -> bool {
+    line.starts_with("/ping ")
+}
+
+// The round trip a Ping frame took, into the status bar and (after a typed /ping) a toast
+fn pong(siv: &mut Cursive, stamp: &str) {
+    let Ok(sent) = stamp.parse::<u128>() else { return };
+    let rtt = Duration::from_millis(epoch_millis().saturating_sub(sent) as u64);
+    let asked = siv
+        .call_on_name(STATUS, |view: &mut StatusBar| {
+            view.ping = Some(rtt);
+            std::mem::take(&mut view.ping_asked)
+        })
+        .unwrap_or(false);
+    if asked {
+        toast(siv, ToastKind::Info, &format!("Pong, {} ms there and back", rtt.as_millis()));
+    }
+}
+
+// The saved server a "/connect <name>" line asks for
+pub fn connect_to(line: &str) -> Option<&str> {
+    line.strip_prefix("/connect ").map(str::trim).filter(|name| !name.is_empty())
+}
+
+// The room a "/purge-local [room]" line is about, "" for the one we talk in
+pub fn purge_local(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("/purge-local")?;
+    (rest.is_empty() || rest.starts_with(' ')).then(|| rest.trim().trim_start_matches('#'))
+}
+
+// Asks before forgetting what this client kept of a room ("" for the one we talk in): its lines in the chat log,
+// the ones still in the outbox (purge_outbox takes those off) and its draft. The server's history stays
+pub fn confirm_purge<F>(siv: &mut Cursive, room: &str, ctx: &Context, purge_outbox: F)
+where
+    F: Fn(&str) -> Result<usize, String> + Send + Sync + 'static,
+{
+    let Some(room) = Some(room.to_string()).filter(|room| !room.is_empty()).or_else(|| current_room(siv)) else {
+        return toast(siv, ToastKind::Error, "Not in a room, /purge-local <room> names one");
+    };
+    let chat_log = ctx.chat_log.clone();
+    let question = format!(
+        "Delete what this client kept of #{}: its messages in the chat log, its unsent lines and its draft?\nThe server's history isn't touched.",
+        room
+    );
+    let dialog = Dialog::text(question)
+        .title("Purge local history")
+        .button("Cancel", |s| {
+            s.pop_layer();
+        })
+        .button("Delete", move |s| {
+            s.pop_layer();
+            forget_draft(s, &room);
+            match (chat_log.purge(&room), purge_outbox(&room)) {
+                (Ok(logged), Ok(unsent)) => {
+                    let done = format!("Forgot #{} here: {} logged messages, {} unsent lines and its draft", room, logged, unsent);
+                    toast(s, ToastKind::Info, &done);
+                }
+                (Err(e), _) | (_, Err(e)) => toast(s, ToastKind::Error, &format!("Couldn't purge #{}: {}", room, e)),
+            }
+        });
+    siv.add_layer(dialog.with_name(PURGE));
+}
+
+// Empties a room's draft, on our other devices too while we're in it
+fn forget_draft(siv: &mut Cursive, room: &str) {
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        if tabs.drafts.remove(room).is_some_and(|draft| !draft.is_empty()) && tabs.position(room).is_some() {
+            tabs.unsent_drafts.insert(room.to_string(), Instant::now());
+        }
+    });
+    if current_room(siv).as_deref() == Some(room) {
+        clear_input(siv);
+    }
+}
+
+fn epoch_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis()).unwrap_or_default()
+}
+
+// Shows a connection notice in the message view
+pub fn notice(siv: &mut Cursive, text: &str) {
+    append(siv, StyledString::styled(format!("\n[~~ {} ~~]\n", text), Color::Light(BaseColor::Yellow)));
+}
+
+// Shows a short-lived notice above the input box, gone after TOAST_TIMEOUT
+pub fn toast(siv: &mut Cursive, kind: ToastKind, text: &str) {
+    siv.call_on_name(TOASTS, |view: &mut Toasts| {
+        view.push(kind, text, Instant::now() + TOAST_TIMEOUT);
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastKind {
+    Info,
+    Error,
+}
+
+// The toasts currently up, one line each, newest at the bottom
+// Takes no room at all when there are none
+pub struct Toasts {
+    // Each toast with the moment it expires
+    toasts: VecDeque<(StyledString, Instant)>,
+}
+
+impl Toasts {
+    pub fn new() -> Self {
+        Toasts { toasts: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, kind: ToastKind, text: &str, expires: Instant) {
+        let toast = match kind {
+            ToastKind::Info => StyledString::styled(format!(" ℹ {} ", text), Color::Light(BaseColor::Yellow)),
+            ToastKind::Error => StyledString::styled(format!(" ⚠ {} ", text), Color::Light(BaseColor::Red)),
+        };
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back((toast, expires));
+    }
+
+    // Drops the toasts that have expired by `now`
+    pub fn expire(&mut self, now: Instant) {
+        self.toasts.retain(|(_, expires)| *expires > now);
+    }
+}
+
+impl Default for Toasts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for Toasts {
+    fn draw(&self, printer: &Printer) {
+        for (row, (toast, _)) in self.toasts.iter().enumerate() {
+            printer.print_styled((0, row), toast);
+        }
+    }
+
+    // Asked on every redraw, which is when expired toasts are dropped
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        self.expire(Instant::now());
+        // A long one is cut off rather than pushing the whole window wider than the screen
+        let width = self.toasts.iter().map(|(toast, _)| toast.width()).max().unwrap_or(0);
+        Vec2::new(width.min(constraint.x), self.toasts.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connection {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Disconnected,
+}
+
+// One line of live status, the connection and room on the left and the time on the right
+// Redrawn with everything else (a couple of times a second), which keeps the clock going
+pub struct StatusBar {
+    connection: Connection,
+    room: String,
+    // Unknown until the server sends the first count
+    users: Option<usize>,
+    // Round trip of the last ping, and whether someone typed /ping and waits for the answer
+    ping: Option<Duration>,
+    ping_asked: bool,
+    // The message pane has the keys, see MessagePane
+    navigating: bool,
+    // Do not disturb is on, with the whispers that came since
+    dnd: Option<Vec<ChatMessage>>,
+}
+
+impl StatusBar {
+    pub fn new() -> Self {
+        StatusBar { connection: Connection::Connecting, room: DEFAULT_ROOM.to_string(), users: None, ping: None, ping_asked: false, navigating: false, dnd: None }
+    }
+
+    // Keeps a whisper for later in do not disturb, false when it isn't on
+    fn hold(&mut self, msg: &ChatMessage) -> bool {
+        self.dnd.as_mut().map(|held| held.push(msg.clone())).is_some()
+    }
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for StatusBar {
+    fn draw(&self, printer: &Printer) {
+        let (connection, color) = match self.connection {
+            Connection::Connecting => ("◌ connecting", Color::Light(BaseColor::Yellow)),
+            Connection::Connected => ("● connected", Color::Light(BaseColor::Green)),
+            Connection::Reconnecting => ("◌ reconnecting", Color::Light(BaseColor::Yellow)),
+            Connection::Disconnected => ("○ disconnected", Color::Light(BaseColor::Red)),
+        };
+        let mut left = StyledString::styled(connection, color);
+        left.append_styled(format!("  #{}", self.room), Color::Light(BaseColor::Magenta));
+        if let Some(users) = self.users {
+            left.append_styled(format!("  👥 {} online", users), Color::Light(BaseColor::Cyan));
+        }
+        if let Some(ping) = self.ping {
+            left.append_styled(format!("  ⏱ {} ms", ping.as_millis()), Color::Dark(BaseColor::White));
+        }
+        if self.navigating {
+            left.append_plain("  ");
+            left.append_styled(" NAVIGATE ", Style::from(Color::Light(BaseColor::Yellow)).combine(Effect::Reverse));
+            left.append_styled(" i to type", Color::Dark(BaseColor::White));
+        }
+        if let Some(held) = &self.dnd {
+            left.append_plain("  ");
+            left.append_styled(" DND ", Style::from(Color::Light(BaseColor::Red)).combine(Effect::Reverse));
+            if !held.is_empty() {
+                left.append_styled(format!(" {} whisper{} waiting", held.len(), if held.len() == 1 { "" } else { "s" }), Color::Dark(BaseColor::White));
+            }
+        }
+        printer.print_styled((0, 0), &left);
+
+        let right = StyledString::styled(format!("/help  {}", Local::now().format("%H:%M")), Color::Dark(BaseColor::Green));
+        if printer.size.x >= left.width() + right.width() + 2 {
+            printer.print_styled((printer.size.x - right.width(), 0), &right);
+        }
+    }
+
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        Vec2::new(constraint.x, 1)
+    }
+}
+
+// One tab per room we're in, numbered for Alt+1..9, with how many messages came in while it wasn't up
+// The pane shows the messages of the tab that is up, the others' wait here in logs of their own
+// It also keeps what was typed in each room (ours and our other devices'), so every room has its own draft
+pub struct TabBar {
+    tabs: Vec<Tab>,
+    active: usize,
+    // Half-typed lines by room, for rooms without a tab too
+    drafts: HashMap<String, String>,
+    // Rooms whose draft changed here and hasn't gone to the server yet, with when it last changed
+    unsent_drafts: HashMap<String, Instant>,
+    // Pins are shown in full (F3) rather than counted, in every room
+    pins_expanded: bool,
+    // Who whispered to us since we were last scrolled down to the newest message
+    unseen_whispers: Vec<String>,
+}
+
+struct Tab {
+    room: String,
+    // Messages people sent since the tab was last up, not counting ones we had read before (on any device)
+    unread: usize,
+    // Newest message we have read here, as far as the server knows or we told it since
+    read: Option<i64>,
+    // Where we left off when we (re)joined: the first message after it goes under the "new" divider
+    divider_after: Option<i64>,
+    // We can't post there, for the input box once the tab comes up
+    read_only: bool,
+    // What the room is about, empty when it has no topic
+    topic: String,
+    // Its pinned messages, oldest pin first
+    pins: Vec<ChatMessage>,
+    // Empty while the tab is up, its messages are in the pane then
+    log: MessageLog,
+}
+
+impl Tab {
+    fn new(room: &str) -> Self {
+        Tab {
+            room: room.to_string(),
+            unread: 0,
+            read: None,
+            divider_after: None,
+            read_only: false,
+            topic: String::new(),
+            pins: Vec::new(),
+            log: MessageLog::new(),
+        }
+    }
+}
+
+impl TabBar {
+    // Everyone starts in the lobby
+    pub fn new() -> Self {
+        TabBar {
+            tabs: vec![Tab::new(DEFAULT_ROOM)],
+            active: 0,
+            drafts: HashMap::new(),
+            unsent_drafts: HashMap::new(),
+            pins_expanded: false,
+            unseen_whispers: Vec::new(),
+        }
+    }
+
+    fn position(&self, room: &str) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.room == room)
+    }
+
+    // A room we're in whose tab isn't up
+    fn is_waiting(&self, room: &str) -> bool {
+        self.position(room).is_some_and(|index| index != self.active)
+    }
+
+    // Keeps a frame for a tab that isn't up, counting what people said
+    fn hold(&mut self, room: &str, text: StyledString, msg: Option<&ChatMessage>) {
+        let Some(tab) = self.tabs.iter_mut().find(|tab| tab.room == room) else { return };
+        match msg {
+            Some(msg) => {
+                tab.log.append_message(text, msg);
+                if msg.id.zip(tab.read).is_none_or(|(id, read)| id > read) {
+                    tab.unread += 1;
+                }
+            }
+            None => tab.log.append(text),
+        }
+    }
+
+    // Whether `msg` goes under the header of the newest message in the tab of `room`
+    fn continues(&self, room: &str, msg: &ChatMessage) -> bool {
+        self.tabs.iter().find(|tab| tab.room == room).is_some_and(|tab| tab.log.continues(msg))
+    }
+
+    // Whether message `id` is the first one in `room` past where we left off, which goes under the divider
+    fn divides(&mut self, room: &str, id: i64) -> bool {
+        let Some(tab) = self.tabs.iter_mut().find(|tab| tab.room == room) else { return false };
+        let divides = tab.divider_after.is_some_and(|read| id > read);
+        if divides {
+            tab.divider_after = None;
+        }
+        divides
+    }
+
+    fn set_read_only(&mut self, room: &str, read_only: bool) {
+        if let Some(tab) = self.tabs.iter_mut().find(|tab| tab.room == room) {
+            tab.read_only = read_only;
+        }
+    }
+
+    fn set_pins(&mut self, room: &str, pins: Vec<ChatMessage>) {
+        if let Some(tab) = self.tabs.iter_mut().find(|tab| tab.room == room) {
+            tab.pins = pins;
+        }
+    }
+
+    fn set_topic(&mut self, room: &str, topic: &str) {
+        if let Some(tab) = self.tabs.iter_mut().find(|tab| tab.room == room) {
+            tab.topic = topic.to_string();
+        }
+    }
+}
+
+impl Default for TabBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for TabBar {
+    fn draw(&self, printer: &Printer) {
+        let room = Style::from(Color::Light(BaseColor::Magenta));
+        let mut line = StyledString::new();
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let label = if i < MAX_NUMBERED_TABS { format!(" {} #{} ", i + 1, tab.room) } else { format!(" #{} ", tab.room) };
+            line.append_styled(label, if i == self.active { room.combine(Effect::Reverse) } else { room });
+            if tab.unread > 0 {
+                line.append_styled(format!("({})", tab.unread), Color::Light(BaseColor::Yellow));
+            }
+            line.append_plain(" ");
+        }
+        printer.print_styled((0, 0), &line);
+    }
+
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        Vec2::new(constraint.x, 1)
+    }
+}
+
+// The scrolling around the message log
+// Following the newest messages, one taller than the pane would have its top scrolled out of sight
+// before anyone read it, so it is shown from its first line and the view stays put from there.
+// Navigation mode gives it the keys, vi-style: j/k a line, Ctrl+D/U half a page, gg/G the top and bottom,
+// "/" searches back through the messages and n/N go on to the next older/newer match, i or Enter go back to typing
+pub struct MessagePane {
+    scroll: NamedView<ScrollView<NamedView<MessageLog>>>,
+    // The first g of gg
+    pending_g: bool,
+    // What was last searched for, and the row it was last found on
+    search: Option<(String, Option<usize>)>,
+}
+
+impl MessagePane {
+    pub fn new() -> Self {
+        let scroll = ScrollView::new(MessageLog::new().with_name(MESSAGES)).scroll_strategy(ScrollStrategy::StickToBottom);
+        MessagePane { scroll: scroll.with_name(MESSAGE_SCROLL), pending_g: false, search: None }
+    }
+
+    // Scrolls the next row with `query` in it (older or newer than the one found last, or the top row) to the top
+    fn search(&mut self, query: &str, older: bool) -> EventResult {
+        let last = self.search.take().filter(|(searched, _)| searched == query).and_then(|(_, row)| row);
+        let mut scroll = self.scroll.get_mut();
+        let viewport = scroll.content_viewport();
+        // Where the last match was, if it's still in sight (at the bottom the top row can't be it)
+        let from = last.filter(|row| (viewport.top()..=viewport.bottom()).contains(row)).unwrap_or(viewport.top());
+        let found = scroll.get_inner_mut().get_mut().find(query, from, older);
+        if let Some(row) = found {
+            scroll.set_scroll_strategy(ScrollStrategy::KeepRow);
+            scroll.set_offset((0, row));
+            keep_place(&mut scroll);
+        }
+        self.search = Some((query.to_string(), found.or(last)));
+        match found {
+            Some(_) => EventResult::Consumed(None),
+            None => {
+                let hint = format!("Nothing {} says {:?}", if older { "older" } else { "newer" }, query);
+                EventResult::with_cb(move |s| toast(s, ToastKind::Info, &hint))
+            }
+        }
+    }
+}
+
+impl Default for MessagePane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ViewWrapper for MessagePane {
+    cursive::wrap_impl!(self.scroll: NamedView<ScrollView<NamedView<MessageLog>>>);
+
+    // Only navigation mode gives the pane the keys, other events go on to the scroll view
+    fn wrap_on_event(&mut self, event: Event) -> EventResult {
+        let pending_g = std::mem::take(&mut self.pending_g);
+        match event {
+            Event::Char('i') | Event::Key(Key::Enter) => return EventResult::with_cb(focus_input),
+            Event::Char('/') => return EventResult::with_cb(show_message_search),
+            Event::Char('g') if !pending_g => {
+                self.pending_g = true;
+                return EventResult::Consumed(None);
+            }
+            Event::Char(c @ ('n' | 'N')) => {
+                return match self.search.as_ref().map(|(query, _)| query.clone()) {
+                    Some(query) => self.search(&query, c == 'n'),
+                    None => EventResult::with_cb(|s| toast(s, ToastKind::Info, "Nothing searched for yet, / starts a search")),
+                };
+            }
+            // A click somewhere else, focus_input says so itself
+            Event::FocusLost => return EventResult::with_cb(|s| set_navigating(s, false)),
+            _ => {}
+        }
+        let mut scroll = self.scroll.get_mut();
+        let half_page = (scroll.content_viewport().height() / 2).max(1);
+        let all = scroll.inner_size().y;
+        let (down, rows) = match event {
+            Event::Char('j') => (true, 1),
+            Event::Char('k') => (false, 1),
+            Event::CtrlChar('d') => (true, half_page),
+            Event::CtrlChar('u') => (false, half_page),
+            Event::Char('g') => (false, all),
+            Event::Char('G') => (true, all),
+            _ => return scroll.on_event(event),
+        };
+        // Still following the newest messages, the scroll view would put us back at the bottom first
+        scroll.set_scroll_strategy(ScrollStrategy::KeepRow);
+        if down {
+            scroll.get_scroller_mut().scroll_down(rows);
+        } else {
+            scroll.get_scroller_mut().scroll_up(rows);
+        }
+        keep_place(&mut scroll);
+        EventResult::Consumed(None)
+    }
+
+    fn wrap_layout(&mut self, size: Vec2) {
+        let mut scroll = self.scroll.get_mut();
+        let following = scroll.is_at_bottom();
+        scroll.layout(size);
+        let newest = {
+            let mut log = scroll.get_inner_mut().get_mut();
+            std::mem::take(&mut log.fresh).then(|| log.newest_rows()).flatten()
+        };
+        if let Some((first_row, rows)) = newest.filter(|_| following)
+            && rows > scroll.content_viewport().height()
+        {
+            scroll.set_scroll_strategy(ScrollStrategy::KeepRow);
+            scroll.set_offset((0, first_row));
+        }
+    }
+}
+
+// The message pane: reads like one long text, but knows which message is on which row,
+// so a click can select a message (for /react and /copy) or open a link in it
+pub struct MessageLog {
+    content: StyledString,
+    // Where each piece of the content starts (its first span) and the frame it shows, if it is one
+    pieces: Vec<(usize, Option<ChatMessage>)>,
+    selected: Option<usize>,
+    // Content wrapped to `width`, redone when either changes
+    rows: Vec<Row>,
+    width: Option<usize>,
+    // Wrapping for a size isn't laying out, the scroll view still has to hear about new content
+    laid_out: bool,
+    // Something was added since the pane last looked
+    fresh: bool,
+    // Local day of the newest stamped message, a message from another day goes under a dated divider
+    day: Option<NaiveDate>,
+}
+
+impl MessageLog {
+    pub fn new() -> Self {
+        MessageLog { content: StyledString::new(), pieces: Vec::new(), selected: None, rows: Vec::new(), width: None, laid_out: false, fresh: false, day: None }
+    }
+
+    // Notices, help and the like, which can't be selected
+    pub fn append(&mut self, text: StyledString) {
+        self.push(text, None);
+    }
+
+    // A message from a day other than the one before it (or, the first time, other than today) comes under that day's date
+    pub fn append_message(&mut self, text: StyledString, msg: &ChatMessage) {
+        if let Some(day) = local_stamp(&msg.timestamp).map(|at| at.date_naive()) {
+            if day != self.day.unwrap_or_else(|| Local::now().date_naive()) {
+                let divider = format!("──────── {} ────────\n", day.format("%A, %-d %B %Y"));
+                self.push(StyledString::styled(divider, Color::Dark(BaseColor::White)), None);
+            }
+            self.day = Some(day);
+        }
+        self.push(text, Some(msg.clone()));
+    }
+
+    fn push(&mut self, text: StyledString, msg: Option<ChatMessage>) {
+        self.pieces.push((self.content.spans_raw().len(), msg));
+        self.content.append(text);
+        self.width = None;
+        self.laid_out = false;
+        self.fresh = true;
+    }
+
+    pub fn clear(&mut self) {
+        *self = MessageLog::new();
+    }
+
+    // Back in the pane after waiting in a tab: laid out afresh, and nothing in it is news to jump to
+    fn shown_again(&mut self) {
+        self.laid_out = false;
+        self.fresh = false;
+    }
+
+    pub fn selected(&self) -> Option<&ChatMessage> {
+        self.pieces.get(self.selected?)?.1.as_ref()
+    }
+
+    // Whether `msg` carries straight on from the newest piece: the same person saying something else in the
+    // same room, on the same day and within GROUP_WINDOW, with nothing (a divider, a notice) in between
+    fn continues(&self, msg: &ChatMessage) -> bool {
+        let Some((_, Some(last))) = self.pieces.last() else { return false };
+        let (Some(then), Some(now)) = (local_stamp(&last.timestamp), local_stamp(&msg.timestamp)) else { return false };
+        last.message_type == MessageType::UserMessage
+            && msg.message_type == MessageType::UserMessage
+            && msg.quote.is_none()
+            && (&last.username, &last.room, last.verified, last.bad_signature) == (&msg.username, &msg.room, msg.verified, msg.bad_signature)
+            && then.date_naive() == now.date_naive()
+            && (now - then).to_std().is_ok_and(|gap| gap <= GROUP_WINDOW)
+    }
+
+    // Selects message `id` as a click would (the newest copy, should it be there twice), None when it isn't here
+    fn select(&mut self, id: i64) -> Option<()> {
+        self.selected = Some(self.pieces.iter().rposition(|(_, msg)| msg.as_ref().is_some_and(|msg| msg.id == Some(id)))?);
+        Some(())
+    }
+
+    // Id of the newest stored message in the log
+    fn newest_id(&self) -> Option<i64> {
+        self.pieces.iter().filter_map(|(_, msg)| msg.as_ref()?.id).max()
+    }
+
+    // The piece a row shows, None for blank rows between messages
+    fn piece_at(&self, row: &Row) -> Option<usize> {
+        let span = row.segments.first()?.span_id;
+        Some(self.pieces.partition_point(|(first_span, _)| *first_span <= span).saturating_sub(1))
+    }
+
+    // The row the newest piece starts on and how many it takes, as last laid out
+    fn newest_rows(&self) -> Option<(usize, usize)> {
+        let (first_span, _) = self.pieces.last()?;
+        let first_row = self.rows.iter().position(|row| row.segments.first().is_some_and(|segment| segment.span_id >= *first_span))?;
+        Some((first_row, self.rows.len() - first_row))
+    }
+
+    // The nearest row above `from` (below it when not `older`) with `query` in it, any case, as last laid out
+    fn find(&self, query: &str, from: usize, older: bool) -> Option<usize> {
+        let query = query.to_lowercase();
+        let has_it = |row: &usize| {
+            let line: String = self.rows[*row].resolve_stream(&self.content).map(|span| span.content).collect();
+            line.to_lowercase().contains(&query)
+        };
+        if older { (0..from.min(self.rows.len())).rev().find(has_it) } else { (from + 1..self.rows.len()).find(has_it) }
+    }
+
+    fn compute_rows(&mut self, width: usize) {
+        if self.width != Some(width) {
+            self.rows = LinesIterator::new(&self.content, width).collect();
+            self.width = Some(width);
+        }
+    }
+
+    // Clicking a link opens it, clicking a message selects it (or lets go of it again)
+    fn click(&mut self, position: Vec2) -> EventResult {
+        let Some(row) = self.rows.get(position.y) else { return EventResult::Ignored };
+        let line: String = row.resolve_stream(&self.content).map(|span| span.content).collect();
+        if let Some(url) = links::link_at(&line, simple_prefix(&line, position.x).length) {
+            return EventResult::with_cb(move |s| {
+                focus_input(s);
+                match links::open(&url) {
+                    Ok(()) => toast(s, ToastKind::Info, &format!("Opening {}", url)),
+                    Err(e) => toast(s, ToastKind::Error, &format!("Could not open {}: {}", url, e)),
+                }
+            });
+        }
+        let piece = self.piece_at(row).filter(|&piece| self.pieces[piece].1.is_some());
+        self.selected = if piece == self.selected { None } else { piece };
+        let hint = match self.selected() {
+            Some(msg) => format!("Selected {}'s message, what you send replies to it, /react <emoji> or /copy it (click again to let go)", msg.username),
+            None => String::new(),
+        };
+        EventResult::with_cb(move |s| {
+            focus_input(s);
+            show_quote(s);
+            if !hint.is_empty() {
+                toast(s, ToastKind::Info, &hint);
+            }
+        })
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for MessageLog {
+    fn draw(&self, printer: &Printer) {
+        for (y, row) in self.rows.iter().enumerate().skip(printer.content_offset.y).take(printer.output_size.y) {
+            let selected = self.selected.is_some() && self.piece_at(row) == self.selected;
+            let mut x = 0;
+            for span in row.resolve_stream(&self.content) {
+                let style = if selected { span.attr.combine(Effect::Reverse) } else { *span.attr };
+                printer.with_style(style, |printer| printer.print((x, y), span.content));
+                x += span.width;
+            }
+        }
+    }
+
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        self.compute_rows(constraint.x);
+        // Wrapped rows want all the width there is, like a TextView
+        let width = if self.rows.iter().any(|row| row.is_wrapped) {
+            constraint.x
+        } else {
+            self.rows.iter().map(|row| row.width).max().unwrap_or(0)
+        };
+        Vec2::new(width, self.rows.len())
+    }
+
+    fn layout(&mut self, size: Vec2) {
+        self.compute_rows(size.x);
+        self.laid_out = true;
+    }
+
+    fn needs_relayout(&self) -> bool {
+        !self.laid_out
+    }
+
+    // Only a click or navigation mode gets here, the keyboard stays with the input box
+    fn take_focus(&mut self, source: Direction) -> Result<EventResult, CannotFocus> {
+        if source == Direction::none() { Ok(EventResult::Consumed(None)) } else { Err(CannotFocus) }
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        match event {
+            Event::Mouse { offset, position, event: MouseEvent::Press(MouseButton::Left) } => {
+                position.checked_sub(offset).map_or(EventResult::Ignored, |position| self.click(position))
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+}
+
+// Scrolled up, the messages stay put while new ones come in; back at the bottom they follow them again
+// A page is what fits in the pane less a row, so there's something to read on from
+fn scroll_messages(siv: &mut Cursive, down: bool, page: bool) {
+    siv.call_on_name(MESSAGE_SCROLL, |view: &mut ScrollView<NamedView<MessageLog>>| {
+        let rows = if page { view.content_viewport().height().saturating_sub(1).max(1) } else { WHEEL_ROWS };
+        if down {
+            view.get_scroller_mut().scroll_down(rows);
+        } else {
+            view.get_scroller_mut().scroll_up(rows);
+        }
+        keep_place(view);
+    });
+}
+
+// After a scroll: follow new messages at the bottom, stay put anywhere else
+fn keep_place(view: &mut ScrollView<NamedView<MessageLog>>) {
+    let strategy = if view.is_at_bottom() { ScrollStrategy::StickToBottom } else { ScrollStrategy::KeepRow };
+    view.set_scroll_strategy(strategy);
+}
+
+// Navigation mode: the message pane takes the keys from the input box
+// Not from under a dialog, Esc there is for closing it
+fn navigate(siv: &mut Cursive) {
+    if siv.screen().len() == 1 && siv.focus_name(MESSAGE_PANE).is_ok() {
+        set_navigating(siv, true);
+    }
+}
+
+fn set_navigating(siv: &mut Cursive, on: bool) {
+    siv.call_on_name(STATUS, |view: &mut StatusBar| view.navigating = on);
+}
+
+// "/" in navigation mode: what to look for back through the messages, n and N go on from there
+fn show_message_search(siv: &mut Cursive) {
+    let find = |s: &mut Cursive, query: &str| {
+        s.pop_layer();
+        if query.is_empty() {
+            return;
+        }
+        let query = query.to_string();
+        if let Some(EventResult::Consumed(Some(cb))) = s.call_on_name(MESSAGE_PANE, |pane: &mut MessagePane| pane.search(&query, true)) {
+            cb(s);
+        }
+    };
+    let dialog = Dialog::around(EditView::new().on_submit(find).with_name(MESSAGE_SEARCH).fixed_width(30))
+        .title("Search the messages")
+        .button("Find", move |s| {
+            let query = s.call_on_name(MESSAGE_SEARCH, |view: &mut EditView| view.get_content()).unwrap_or_default();
+            find(s, &query);
+        });
+    siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+        s.pop_layer();
+    }));
+}
+
+// The message a reply will quote, over the input box: "> what they said — who", gone when nothing is selected
+fn show_quote(siv: &mut Cursive) {
+    let quote = selected_message(siv).and_then(|msg| Some(Quote { id: msg.id?, username: msg.username, content: msg.content }));
+    if let Some(quote) = &quote {
+        let mut line = quote_line(quote);
+        line.append_styled("  (click it again or /unquote to drop)", Color::Dark(BaseColor::White));
+        siv.call_on_name(QUOTE, |view: &mut TextView| view.set_content(line));
+    }
+    siv.call_on_name(QUOTE_PANEL, |view: &mut HideableView<NamedView<TextView>>| view.set_visible(quote.is_some()));
+}
+
+// "> the first line of what they said — who", as quoted by a reply
+fn quote_line(quote: &Quote) -> StyledString {
+    let dim = Style::from(Color::Dark(BaseColor::White)).combine(Effect::Italic);
+    let mut line = StyledString::styled(format!("> {} — ", quote.content.lines().next().unwrap_or_default()), dim);
+    line.append_styled(quote.username.clone(), color_for_username(&quote.username));
+    line
+}
+
+// The message selected with a click, if any
+pub fn selected_message(siv: &mut Cursive) -> Option<ChatMessage> {
+    siv.call_on_name(MESSAGES, |view: &mut MessageLog| view.selected().cloned()).flatten()
+}
+
+// Ends navigation mode too
+fn focus_input(siv: &mut Cursive) {
+    let _ = siv.focus_name(INPUT);
+    set_navigating(siv, false);
+}
+
+fn append<S: Into<StyledString>>(siv: &mut Cursive, text: S) {
+    let text = text.into();
+    siv.call_on_name(MESSAGES, |view: &mut MessageLog| {
+        view.append(text);
+    });
+}
+
+fn clear_input(siv: &mut Cursive) {
+    siv.call_on_name(INPUT, |view: &mut EditView| {
+        view.set_content("");
+    });
+}
+
+// Asks for the server password and hands it to on_submit once entered
+pub fn show_password_dialog<F>(siv: &mut Cursive, on_submit: F)
+where
+    F: Fn(&mut Cursive, &str) + Clone + Send + Sync + 'static,
+{
+    let on_enter = on_submit.clone();
+    let password_input = EditView::new()
+        .secret()
+        .on_submit(move |s, password| {
+            s.pop_layer(); // Close the password dialog
+            on_enter(s, password);
+        })
+        .with_name(PASSWORD)
+        .fixed_width(30);
+
+    siv.add_layer(
+        Dialog::around(password_input)
+            .title("Server password")
+            .button("Connect", move |s| {
+                let password = s
+                    .call_on_name(PASSWORD, |view: &mut EditView| view.get_content())
+                    .unwrap_or_default();
+                s.pop_layer();
+                on_submit(s, &password);
+            })
+            .button("Quit", |s| s.quit()),
+    );
+}
+
+// Asks who to sign in as and which server to, for a start without a username from the command line or
+// config.toml. on_connect hears both once they look right, `error` says why the last attempt didn't work
+pub fn show_connect_dialog<F>(siv: &mut Cursive, username: &str, server: &str, error: Option<&str>, on_connect: F)
+where
+    F: Fn(&mut Cursive, &str, &str) + Send + Sync + 'static,
+{
+    let on_connect = Arc::new(on_connect);
+    let connect = move |s: &mut Cursive| {
+        let field = |s: &mut Cursive, name: &str| s.call_on_name(name, |view: &mut EditView| view.get_content().trim().to_string()).unwrap_or_default();
+        let (username, server) = (field(s, CONNECT_USERNAME), field(s, CONNECT_SERVER));
+        let problem = if username.is_empty() || username.contains(char::is_whitespace) {
+            Some("Pick a username, one word without spaces".to_string())
+        } else {
+            check_addr(&server).err().map(|e| format!("Check the server: {}", e))
+        };
+        match problem {
+            Some(problem) => {
+                s.call_on_name(CONNECT_ERROR, |view: &mut TextView| view.set_content(StyledString::styled(problem, Color::Light(BaseColor::Red))));
+            }
+            None => {
+                s.pop_layer();
+                on_connect(s, &username, &server);
+            }
+        }
+    };
+    let field = |name: &str, content: &str| {
+        let connect = connect.clone();
+        EditView::new().content(content).on_submit(move |s, _| connect(s)).with_name(name).fixed_width(40)
+    };
+    let error = StyledString::styled(error.unwrap_or_default(), Color::Light(BaseColor::Red));
+    let form = LinearLayout::vertical()
+        .child(TextView::new("Username"))
+        .child(field(CONNECT_USERNAME, username))
+        .child(DummyView)
+        .child(TextView::new(format!("Server (host:port, quic://host:port or {}<path>)", UNIX_PREFIX)))
+        .child(field(CONNECT_SERVER, server))
+        .child(DummyView)
+        .child(TextView::new(error).with_name(CONNECT_ERROR).fixed_width(40));
+
+    siv.add_layer(Dialog::around(form).title("Connect to a chat server").button("Connect", connect).button("Quit", |s| s.quit()));
+    // Straight to whichever is still empty
+    let _ = siv.focus_name(if username.is_empty() { CONNECT_USERNAME } else { CONNECT_SERVER });
+}
+
+// Offers the saved servers to pick one from, on_pick hears its name
+// At the start another server can be picked instead (on_pick hears None then) and the only way out is Quit;
+// otherwise Esc or Cancel just close the list
+pub fn show_server_picker<F>(siv: &mut Cursive, servers: &BTreeMap<String, Profile>, error: Option<&str>, at_start: bool, on_pick: F)
+where
+    F: Fn(&mut Cursive, Option<&str>) + Send + Sync + 'static,
+{
+    let mut list: SelectView<Option<String>> = SelectView::new();
+    for (name, profile) in servers {
+        let mut label = StyledString::styled(format!("{:<16}", name), Color::Light(BaseColor::Magenta));
+        let who = profile.username.as_deref().map(|username| format!("{} @ ", username)).unwrap_or_default();
+        label.append_plain(format!(" {}{}", who, profile.address));
+        list.add_item(label, Some(name.clone()));
+    }
+    if at_start {
+        list.add_item("Another server...", None);
+    }
+    let list = list.on_submit(move |s, picked: &Option<String>| {
+        s.pop_layer();
+        on_pick(s, picked.as_deref());
+    });
+    let mut layout = LinearLayout::vertical();
+    if let Some(error) = error {
+        layout.add_child(TextView::new(StyledString::styled(error, Color::Light(BaseColor::Red))).max_width(60));
+        layout.add_child(DummyView);
+    }
+    layout.add_child(list.with_name(SERVER_PICKER).scrollable().max_height(15));
+
+    let dialog = Dialog::around(layout).title("Saved servers");
+    if at_start {
+        siv.add_layer(dialog.button("Quit", |s| s.quit()));
+    } else {
+        let dialog = dialog.button("Cancel", |s| {
+            s.pop_layer();
+        });
+        siv.add_layer(OnEventView::new(dialog).on_event(Key::Esc, |s| {
+            s.pop_layer();
+        }));
+    }
+}
+
+// Starts over on another server: the tabs, messages and who's online from the last one go,
+// and the header says who we are there
+pub fn switch_server(siv: &mut Cursive, username: &str, server: &str) {
+    siv.call_on_name(TABS, |tabs: &mut TabBar| {
+        *tabs = TabBar { pins_expanded: tabs.pins_expanded, ..TabBar::new() };
+    });
+    siv.call_on_name(MESSAGES, |view: &mut MessageLog| *view = MessageLog::new());
+    switch_tab(siv, 0);
+    set_roster(siv, &[]);
+    siv.call_on_name(STATUS, |view: &mut StatusBar| view.users = None);
+    siv.call_on_name(HEADER, |view: &mut TextView| view.set_content(header(username)));
+    notice(siv, &format!("Connected to {} as {}", server, username));
+}
+
+fn header(username: &str) -> String {
+    format!(r#"== CHATBOX == Chatterer: {} == {} =="#, username, Local::now().format("%D:%H:%M:%S"))
+}
+
+// What to do with a message left in the outbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsentChoice {
+    Send,
+    // Back into the input box to be changed (and sent) like a new line
+    Edit,
+    Discard,
+}
+
+// Goes through messages that never went out one at a time, asking whether to send, edit or discard each
+// on_choice hears every decision, an edited message is already in the input box by then
+// Does nothing while an earlier round is still being gone through
+pub fn offer_unsent<F>(siv: &mut Cursive, unsent: Vec<String>, on_choice: F)
+where
+    F: Fn(&mut Cursive, &str, UnsentChoice) + Send + Sync + 'static,
+{
+    if siv.find_name::<Dialog>(UNSENT).is_some() {
+        return;
+    }
+    let total = unsent.len();
+    offer_next_unsent(siv, unsent.into(), total, Arc::new(on_choice));
+}
+
+type OnUnsentChoice = Arc<dyn Fn(&mut Cursive, &str, UnsentChoice) + Send + Sync>;
+
+fn offer_next_unsent(siv: &mut Cursive, mut unsent: VecDeque<String>, total: usize, on_choice: OnUnsentChoice) {
+    let Some(line) = unsent.pop_front() else { return };
+    let mut dialog = Dialog::around(TextView::new(line.clone()))
+        .title(format!("Unsent message {} of {}", total - unsent.len(), total));
+    for (label, choice) in [("Send", UnsentChoice::Send), ("Edit", UnsentChoice::Edit), ("Discard", UnsentChoice::Discard)] {
+        let (line, unsent, on_choice) = (line.clone(), unsent.clone(), on_choice.clone());
+        dialog.add_button(label, move |s| {
+            s.pop_layer();
+            if choice == UnsentChoice::Edit {
+                s.call_on_name(INPUT, |view: &mut EditView| {
+                    view.set_content(line.as_str());
+                });
+            }
+            on_choice(s, &line, choice);
+            offer_next_unsent(s, unsent.clone(), total, on_choice.clone());
+        });
+    }
+    siv.add_layer(dialog.with_name(UNSENT));
+}
+
+// "👍 2  ❤️ 1"
+fn reaction_summary(reactions: &[Reaction]) -> String {
+    reactions
+        .iter()
+        .map(|reaction| format!("{} {}", reaction.emoji, reaction.count))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+// "#dev " for frames from any room but the lobby, which stays untagged like before rooms existed
+fn room_tag(msg: &ChatMessage) -> StyledString {
+    match msg.room.as_deref() {
+        Some(room) if room != DEFAULT_ROOM => StyledString::styled(format!("#{} ", room), Color::Light(BaseColor::Magenta)),
+        _ => StyledString::new(),
+    }
+}
+
+// After the name of someone whose message wasn't signed with the key their name signs with
+const UNSIGNED_MARK: &str = " ⚠ not signed with their key";
+
+// A message that goes under the header of the one before it (see MessageLog::continues): what they said, the id after it
+pub fn format_follow_up(msg: &ChatMessage, markup: &Markup) -> StyledString {
+    let mut styled = StyledString::styled(FOLLOW_UP, Color::Dark(BaseColor::White));
+    append_content(&mut styled, markup.render(&msg.content, Style::none()));
+    if let Some(id) = msg.id {
+        styled.append_styled(format!(" #{}", id), Color::Dark(BaseColor::White)); // for /react
+    }
+    styled.append_plain("\n");
+    if !msg.reactions.is_empty() {
+        styled.append_styled(format!("   {}\n", reaction_summary(&msg.reactions)), Color::Light(BaseColor::Yellow));
+    }
+    styled
+}
+
+// Format incoming message based on type, what people typed goes through markup
+pub fn format_message(msg: &ChatMessage, markup: &Markup) -> StyledString {
+    match msg.message_type {
+        MessageType::UserMessage => {
+            let mut styled = room_tag(msg);
+            styled.append_plain(format!("┌─[{}]", local_time(&msg.timestamp, markup.absolute_times())));
+            if let Some(id) = msg.id {
+                styled.append_styled(format!(" #{}", id), Color::Dark(BaseColor::White)); // for /react
+            }
+            if let Some(quote) = &msg.quote {
+                styled.append_plain("\n│  ");
+                styled.append(quote_line(quote));
+            }
+            styled.append_plain("\n└─ ");
+            styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
+            if msg.verified {
+                styled.append_styled(" ✔", Color::Light(BaseColor::Green)); // Registered account
+            }
+            if msg.bad_signature {
+                styled.append_styled(UNSIGNED_MARK, Color::Light(BaseColor::Red)); // Maybe not who the name says
+            }
+            styled.append_plain(" --> ");
+            append_content(&mut styled, markup.render(&msg.content, Style::none()));
+            styled.append_plain("\n");
+            if !msg.reactions.is_empty() {
+                styled.append_styled(format!("   {}\n", reaction_summary(&msg.reactions)), Color::Light(BaseColor::Yellow));
+            }
+            styled
+        }
+        // "* alice waves", in italics
+        MessageType::Action => {
+            let italic = |color: ColorStyle| Style::from(color).combine(Effect::Italic);
+            let mut styled = room_tag(msg);
+            if let Some(quote) = &msg.quote {
+                styled.append(quote_line(quote));
+                styled.append_plain("\n");
+            }
+            styled.append_styled("* ", italic(Color::Light(BaseColor::White).into()));
+            styled.append_styled(msg.username.clone(), italic(color_for_username(&msg.username)));
+            if msg.verified {
+                styled.append_styled(" ✔", Color::Light(BaseColor::Green));
+            }
+            if msg.bad_signature {
+                styled.append_styled(UNSIGNED_MARK, Color::Light(BaseColor::Red));
+            }
+            styled.append_styled(" ", italic(Color::Light(BaseColor::White).into()));
+            append_content(&mut styled, markup.render(&msg.content, italic(Color::Light(BaseColor::White).into())));
+            if let Some(id) = msg.id {
+                styled.append_styled(format!(" #{}", id), Color::Dark(BaseColor::White)); // for /react
+            }
+            styled.append_plain("\n");
+            if !msg.reactions.is_empty() {
+                styled.append_styled(format!("   {}\n", reaction_summary(&msg.reactions)), Color::Light(BaseColor::Yellow));
+            }
+            styled
+        }
+        MessageType::Whisper => {
+            let whisper = Color::Light(BaseColor::Magenta);
+            let lock = if msg.encrypted { "🔒 " } else { "" }; // nobody but the two of them could read it
+            let mut styled = StyledString::styled(format!("┌─[{}] {}whisper\n└─ ", local_time(&msg.timestamp, markup.absolute_times()), lock), whisper);
+            styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
+            styled.append_styled(" → ", whisper);
+            let recipient = msg.recipient.clone().unwrap_or_default();
+            styled.append_styled(recipient.clone(), color_for_username(&recipient));
+            styled.append_styled(" ~~> ", whisper);
+            append_content(&mut styled, markup.render(&msg.content, whisper.into()));
+            styled.append_plain("\n");
+            styled
+        }
+        MessageType::Reactions => {
+            let mut styled = room_tag(msg);
+            styled.append_plain("[");
+            styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
+            styled.append_plain(format!(" {}", msg.content));
+            if !msg.reactions.is_empty() {
+                styled.append_styled(format!(" | {}", reaction_summary(&msg.reactions)), Color::Light(BaseColor::Yellow));
+            }
+            styled.append_plain("]\n");
+            styled
+        }
+        // Something to act on (a reported message, for the moderators): black on yellow so it isn't missed
+        MessageType::SystemNotification if msg.highlight => {
+            let style = Style::from(ColorStyle::new(Color::Dark(BaseColor::Black), Color::Light(BaseColor::Yellow))).combine(Effect::Bold);
+            let mut styled = StyledString::plain("\n");
+            styled.append(room_tag(msg));
+            styled.append_styled(format!("[{}]", msg.content), style);
+            styled.append_plain("\n");
+            styled
+        }
+        MessageType::SystemNotification => {
+            let mut styled = StyledString::plain("\n");
+            styled.append(room_tag(msg));
+            styled.append_plain("[");
+            styled.append_styled(msg.username.clone(), color_for_username(&msg.username));
+            styled.append_plain(" ");
+            append_content(&mut styled, msg.content.split('\n').map(StyledString::plain).collect()); // /stats, a long MOTD
+            styled.append_plain("]\n");
+            styled
+        }
+        MessageType::Error => {
+            StyledString::styled(format!("\n[!! {} !!]\n", msg.content), Color::Light(BaseColor::Red))
+        }
+        // "#server-ops [time] ban 203.0.113.7 tried to connect but is banned", the kind in its own colour
+        MessageType::OpsEvent => {
+            let color = match msg.ops_event {
+                Some(OpsEvent::Ban) => Color::Light(BaseColor::Red),
+                Some(OpsEvent::RateLimit) => Color::Light(BaseColor::Yellow),
+                Some(OpsEvent::Lag) => Color::Light(BaseColor::Cyan),
+                Some(OpsEvent::Error) => Color::Dark(BaseColor::Red),
+                Some(OpsEvent::Chaos) => Color::Light(BaseColor::Magenta),
+                Some(OpsEvent::Other) | None => Color::Light(BaseColor::White),
+            };
+            let label = msg.ops_event.map_or("event", |event| event.label());
+            let mut styled = room_tag(msg);
+            styled.append_styled(format!("[{}] ", local_time(&msg.timestamp, markup.absolute_times())), Color::Dark(BaseColor::White));
+            styled.append_styled(label, Style::from(color).combine(Effect::Bold));
+            styled.append_plain(" ");
+            append_content(&mut styled, msg.content.split('\n').map(StyledString::plain).collect());
+            styled.append_plain("\n");
+            styled
+        }
+        MessageType::RoomJoined | MessageType::RoomLeft | MessageType::RoomMode => {
+            StyledString::styled(format!("\n[# {} #]\n", msg.content), Color::Light(BaseColor::Magenta))
+        }
+        MessageType::TwoFactorSetup => {
+            let mut styled = StyledString::styled("\n[== Two-factor setup ==]\n", Color::Light(BaseColor::Yellow));
+            styled.append_plain(format!("{}\n", qr_code(&msg.content)));
+            // Apps without a camera take the secret from the URI typed in
+            if let Some(secret) = msg.content.split(['?', '&']).find_map(|param| param.strip_prefix("secret=")) {
+                styled.append_styled(format!("Can't scan it? Enter this key in the app: {}\n", secret), Color::Dark(BaseColor::White));
+            }
+            styled
+        }
+        MessageType::BackupCodes => {
+            let mut styled = StyledString::styled(
+                "\n[== Backup codes: each works once in place of a code, keep them somewhere safe ==]\n",
+                Color::Light(BaseColor::Yellow),
+            );
+            for code in msg.content.lines() {
+                styled.append_plain(format!("   {}\n", code));
+            }
+            styled
+        }
+        MessageType::Session => StyledString::new(), // Never shown, the connection keeps the token
+        MessageType::UserCount | MessageType::Ping => StyledString::new(), // Go to the status bar instead
+        MessageType::Roster => StyledString::new(), // Goes to the roster instead
+        MessageType::ShortcodeSync => StyledString::new(), // Goes to the emoji table instead
+        MessageType::Draft => StyledString::new(), // Goes in the input box instead
+        MessageType::RoomList => StyledString::new(), // Opens the room picker instead
+        MessageType::Pins => StyledString::new(), // Goes in the pins panel instead
+        MessageType::Seen => StyledString::new(), // A toast instead
+        MessageType::Key => StyledString::new(), // Goes to the connection's keys instead
+        MessageType::Capabilities => StyledString::new(), // Kept for /help instead
+        // Something from a newer server: say so, with what text it has, rather than drop it without a word
+        MessageType::Unknown => {
+            let mut styled = room_tag(msg);
+            styled.append_styled(format!("[{}] ", local_time(&msg.timestamp, markup.absolute_times())), Color::Dark(BaseColor::White));
+            let what = match msg.content.is_empty() {
+                true => format!("{} sent a kind of message this client doesn't support, try updating it", msg.username),
+                false => format!("{} sent a kind of message this client doesn't support: {}", msg.username, msg.content),
+            };
+            styled.append_styled(what, Style::from(Color::Dark(BaseColor::White)).combine(Effect::Italic));
+            styled.append_plain("\n");
+            styled
+        }
+    }
+}
+
+// When a message was stamped, in the viewer's time zone: "today 14:32", "yesterday 09:10", "Oct 12 18:05" earlier this
+// year and "2025-03-01 22:15" before that, or always the full date and time when `absolute`
+// Stamps that aren't RFC 3339 (servers from before they were) are shown as they came
+pub fn local_time(timestamp: &str, absolute: bool) -> String {
+    let Some(at) = local_stamp(timestamp) else { return timestamp.to_string() };
+    let (day, today) = (at.date_naive(), Local::now().date_naive());
+    let format = if absolute {
+        "%Y-%m-%d %H:%M:%S"
+    } else if day == today {
+        "today %H:%M"
+    } else if today.pred_opt() == Some(day) {
+        "yesterday %H:%M"
+    } else if day.year() == today.year() {
+        "%b %-d %H:%M"
+    } else {
+        "%Y-%m-%d %H:%M"
+    };
+    at.format(format).to_string()
+}
+
+fn local_stamp(timestamp: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|at| at.with_timezone(&Local))
+}
+
+// A message's rendered lines, the ones after the first under a continuation mark so they read as
+// part of it and not as something else
+// The keywords a message says, picked out like the notices someone should act on
+// (a keyword split across differently styled text isn't)
+fn mark_keywords(text: StyledString, keywords: &[String]) -> StyledString {
+    let marked = Style::from(ColorStyle::new(Color::Dark(BaseColor::Black), Color::Light(BaseColor::Yellow))).combine(Effect::Bold);
+    let mut out = StyledString::new();
+    for span in text.spans() {
+        // ASCII lowercase keeps every byte where it was, so what's found in one is found in the other
+        let lower = span.content.to_ascii_lowercase();
+        let mut at = 0;
+        while let Some((start, end)) = keywords.iter().filter_map(|keyword| lower[at..].find(keyword.as_str()).map(|i| (at + i, at + i + keyword.len()))).min() {
+            out.append_styled(&span.content[at..start], *span.attr);
+            out.append_styled(&span.content[start..end], span.attr.combine(marked));
+            at = end;
+        }
+        out.append_styled(&span.content[at..], *span.attr);
+    }
+    out
+}
+
+fn append_content(styled: &mut StyledString, rows: Vec<StyledString>) {
+    for (i, row) in rows.into_iter().enumerate() {
+        if i > 0 {
+            styled.append_styled(format!("\n{}", CONTINUATION), Color::Dark(BaseColor::White));
+        }
+        styled.append(row);
+    }
+}
+
+// The text as a QR code drawn with half blocks, light on dark so it scans on the space theme
+fn qr_code(text: &str) -> String {
+    match QrCode::new(text.as_bytes()) {
+        Ok(code) => code.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build(),
+        Err(_) => String::new(), // too long to encode, the text is shown anyway
+    }
+}
+
+
+// Function to generate a color based on the username, makes it easier to distinguish username from chat messages 
+
+fn color_for_username(username: &str) -> ColorStyle {
+    let colors = [
+        Color::Light(BaseColor::Red),
+        Color::Light(BaseColor::Green),
+        Color::Light(BaseColor::Yellow),
+        Color::Light(BaseColor::Blue),
+        Color::Light(BaseColor::Magenta),
+        Color::Light(BaseColor::Cyan),
+    ];
+    let idx = username.bytes().fold(0u8, |acc, b| acc.wrapping_add(b)) as usize % colors.len();
+    ColorStyle::new(colors[idx], Color::TerminalDefault)
+}
\ No newline at end of file
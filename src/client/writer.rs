@@ -0,0 +1,103 @@
+// The client's one way out to the server: a task that owns the write half of whichever connection is up
+// and sends what it is handed in order. What became of each line comes back as a Report, so the UI can take
+// a line off the outbox once it went out, or put up an error when it didn't (no connection counts as that too).
+// The connection task tells it when a connection comes up and when it goes away.
+
+use std::io;
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::connection::ChatSender;
+
+// A line for the server, by how much it matters that it gets there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    // A line from the outbox: `line` as it was queued, `wire` as it goes out (signed, when we sign)
+    Chat { line: String, wire: String },
+    // Only worth anything right away, like a ping the user asked for
+    Now(String),
+    // Drafts, read marks and the pings that time the round trip: a lost one is overtaken by the next,
+    // so nothing is said about it
+    Quiet(String),
+}
+
+#[derive(Debug)]
+pub enum Report {
+    Sent(Outgoing),
+    Failed(Outgoing, io::Error),
+}
+
+enum Command {
+    Send(Outgoing),
+    Connected(ChatSender),
+    Disconnected,
+    Close(oneshot::Sender<()>),
+}
+
+// Handle on the writer task, cheap to clone into UI callbacks
+#[derive(Clone)]
+pub struct Writer {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Writer {
+    // Starts the task, `report` hears about every line but the quiet ones that failed
+    pub fn spawn(report: impl Fn(Report) + Send + 'static) -> Writer {
+        let (commands, queue) = mpsc::unbounded_channel();
+        tokio::spawn(run(queue, report));
+        Writer { commands }
+    }
+
+    pub fn send(&self, outgoing: Outgoing) {
+        let _ = self.commands.send(Command::Send(outgoing));
+    }
+
+    // Lines go out on this connection from now on
+    pub fn connected(&self, sender: ChatSender) {
+        let _ = self.commands.send(Command::Connected(sender));
+    }
+
+    // The connection dropped, lines fail until the next one is up
+    pub fn disconnected(&self) {
+        let _ = self.commands.send(Command::Disconnected);
+    }
+
+    // Closes our side of the connection once everything queued before has gone out
+    pub async fn close(&self) {
+        let (done, closed) = oneshot::channel();
+        if self.commands.send(Command::Close(done)).is_ok() {
+            let _ = closed.await;
+        }
+    }
+}
+
+async fn run(mut queue: mpsc::UnboundedReceiver<Command>, report: impl Fn(Report)) {
+    let mut connection: Option<ChatSender> = None;
+    while let Some(command) = queue.recv().await {
+        match command {
+            Command::Send(outgoing) => {
+                let line = match &outgoing {
+                    Outgoing::Chat { wire, .. } => wire,
+                    Outgoing::Now(line) | Outgoing::Quiet(line) => line,
+                };
+                let sent = match &connection {
+                    Some(sender) => sender.send(line).await,
+                    None => Err(io::Error::new(io::ErrorKind::NotConnected, "not connected to the server")),
+                };
+                match (sent, &outgoing) {
+                    (Ok(()), _) => report(Report::Sent(outgoing)),
+                    (Err(_), Outgoing::Quiet(_)) => {}
+                    (Err(e), _) => report(Report::Failed(outgoing, e)),
+                }
+            }
+            Command::Connected(sender) => connection = Some(sender),
+            Command::Disconnected => connection = None,
+            Command::Close(done) => {
+                if let Some(sender) = connection.take() {
+                    let _ = sender.close().await;
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+// Shared code for the chat server and client binaries.
+// blocks: multi-line messages framed with ``` fences, the same on both ends (the client signs what the server frames)
+// protocol: the JSON frames that travel over the TCP connection (one frame per line)
+// server: building blocks used by the server binary
+// client: building blocks used by the client binary
+// trace: wire-level frame logging shared by both binaries
+
+pub mod blocks;
+pub mod client;
+pub mod protocol;
+pub mod server;
+pub mod trace;
@@ -0,0 +1,428 @@
+// Wire protocol shared by the server and the client.
+// Every frame is a single JSON object terminated by a newline.
+// Decoding is forward compatible: fields this build doesn't know are skipped, and message types, error kinds
+// and ops events added after it come out as Unknown/Other, so an older client degrades instead of dropping frames.
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+// Room every message belongs to until rooms are chosen explicitly
+pub const DEFAULT_ROOM: &str = "lobby";
+
+// Room only moderators can join, where the server reports what its operators should know about
+pub const OPS_ROOM: &str = "server-ops";
+
+// Blank lines kept in a row within a message, more are squeezed down to this many
+pub const MAX_BLANK_LINES: usize = 2;
+
+// Longest line the server reads from a client (the handshake included), a longer one ends the connection
+pub const MAX_LINE_BYTES: usize = 32 * 1024;
+
+// Structure of a chat message (the frame the server sends to clients)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    // Server assigned id of a stored chat message, used to resume after a reconnect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    // Room the frame belongs to, absent on server-wide frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub username: String,
+    pub content: String,
+    pub timestamp: String,
+    pub message_type: MessageType,
+    // Set by the server when the sender is signed in to a registered account
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verified: bool,
+    // Set by the server when the sender's name has a signing key on record and this message wasn't signed with it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bad_signature: bool,
+    // Present on Error frames, and on notices that explain why the server is closing the connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorKind>,
+    // Seconds the client should wait before connecting again (Retry-After)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    // Who a Whisper is for (the sender is in username)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    // On whispers: content is sealed end to end (base64), only the sender and the recipient can read it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    // Reactions so far, on replayed messages and Reactions updates
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    // On RoomJoined frames and topic notices: what `room` is about, empty when it has no topic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    // On RoomJoined and RoomMode frames: `room` is read-only and we aren't one of those who can post there
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    // On RoomJoined frames for signed-in accounts: the newest message of `room` the account read (on any device),
+    // what comes after it is new
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read: Option<i64>,
+    // On Roster frames: everyone online, by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roster: Vec<Member>,
+    // On OpsEvent frames: what kind of event it is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops_event: Option<OpsEvent>,
+    // On ShortcodeSync frames: every shortcode the server's users made
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shortcodes: Vec<Shortcode>,
+    // On replies: the message they quote, as it was when quoted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Quote>,
+    // On Pins frames: every pinned message of the room, oldest pin first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pins: Vec<ChatMessage>,
+    // On RoomList frames: the public rooms open right now
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rooms: Vec<RoomInfo>,
+    // On SystemNotifications someone should act on (a reported message for the moderators): shown so they stand out
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub highlight: bool,
+    // On chat from a linked server (see federation.rs): that server's name, absent on what was said here
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    // On notices that a connection's name changed (a guest name for a registered one, /login): the name it had,
+    // the one it has now is in username
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+    // On Capabilities frames: the commands the server understands, for the client's help
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandInfo>,
+}
+
+// One emoji and how many people reacted with it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+}
+
+// Someone online, as listed in a Roster frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub username: String,
+    // Signed in to a registered account
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verified: bool,
+    // Has do not disturb on (/dnd), on any of their connections
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dnd: bool,
+}
+
+// What a reply quotes: who said what, in which message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quote {
+    pub id: i64,
+    pub username: String,
+    pub content: String,
+}
+
+// A room as listed in a RoomList frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub name: String,
+    // How many connections are in it
+    pub members: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+// A slash command as listed in a Capabilities frame: how it's typed ("/join <room> [password]") and what it does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandInfo {
+    pub usage: String,
+    pub about: String,
+}
+
+// A :shortcode: a user made, and what it stands for (an emoji sequence or a line of ASCII art)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shortcode {
+    pub code: String,
+    pub text: String,
+}
+
+// Define the type of messages that can be sent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageType {
+    #[default]
+    UserMessage,
+    SystemNotification,
+    Error,
+    // Carries a session token (in content) the client presents when it reconnects
+    Session,
+    // Number of connected users (in content), sent whenever it changes
+    UserCount,
+    // Private side comment, seen only by the sender and the recipient and never stored
+    Whisper,
+    // "/me waves": a chat message in the third person, content is what the sender does ("waves")
+    Action,
+    // The client is now in `room` and its plain messages go there
+    RoomJoined,
+    // The client is no longer in `room`
+    RoomLeft,
+    // Someone reacted to message `id`: who in username, what happened in content,
+    // and the message's reactions afterwards in reactions
+    Reactions,
+    // Two-factor setup: content is the otpauth:// URI for an authenticator app to scan
+    TwoFactorSetup,
+    // Backup codes for two-factor sign-in, one per line in content, each good for one use
+    BackupCodes,
+    // Who may post in `room` changed, read_only says whether we still can
+    RoomMode,
+    // Answer to /ping: content is the stamp the client sent, untouched
+    Ping,
+    // Who is online (in roster), sent whenever that changes
+    Roster,
+    // Something the server reports in #server-ops, the kind in ops_event and the details in content
+    OpsEvent,
+    // The shortcodes users made (in shortcodes), sent on connect and whenever they change
+    ShortcodeSync,
+    // What our account is typing in `room` on another device (in content, empty once sent or cleared)
+    Draft,
+    // Answer to /rooms: the rooms anyone can join (in rooms), busiest first
+    RoomList,
+    // The pinned messages of `room` (in pins), sent on join and whenever they change
+    Pins,
+    // Whoever is in username read the whispers we sent them
+    Seen,
+    // The X25519 public key (base64, in content) whispers to username are sealed with, empty once there's none
+    Key,
+    // The slash commands the server understands (in commands), sent on connect
+    Capabilities,
+    // A type from a newer protocol than this build speaks (never sent)
+    #[serde(other)]
+    Unknown,
+}
+
+// What an OpsEvent frame is about, so clients and bots can sort events without parsing text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpsEvent {
+    // An address was banned, or a banned one tried to connect
+    Ban,
+    // Someone ran into a rate limit (reconnects, joins)
+    RateLimit,
+    // A connection fell behind and missed frames
+    Lag,
+    // Something failed on the server's side (the database, a socket)
+    Error,
+    // An owner injected a failure with /chaos
+    Chaos,
+    // A kind from a newer protocol (never sent)
+    #[serde(other)]
+    Other,
+}
+
+impl OpsEvent {
+    pub fn label(&self) -> &'static str {
+        match self {
+            OpsEvent::Ban => "ban",
+            OpsEvent::RateLimit => "rate-limit",
+            OpsEvent::Lag => "lag",
+            OpsEvent::Error => "error",
+            OpsEvent::Chaos => "chaos",
+            OpsEvent::Other => "event",
+        }
+    }
+}
+
+// Machine readable reason attached to an Error frame, so clients can react without parsing text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    AuthFailed,
+    // Too many reconnects in a short time, see retry_after
+    Cooldown,
+    // The server has no room for another connection right now
+    ServerFull,
+    // The client's address or name is banned, retrying will not help
+    Banned,
+    // A server command was malformed or could not be carried out
+    CommandFailed,
+    // The connection was dropped for inactivity
+    IdleTimeout,
+    // An operator disconnected the client
+    Kicked,
+    // The word filter refused a message
+    MessageBlocked,
+    // Joining would go over the per-user room cap
+    TooManyRooms,
+    // Joining rooms too fast, see retry_after
+    JoinRateLimited,
+    // The account has two-factor sign-in, /login needs the code as well
+    TwoFactorRequired,
+    // A message (a code block, say) went over the size limits
+    MessageTooLong,
+    // The room is read-only and the sender isn't an announcer
+    ReadOnlyRoom,
+    // The message had something in it no message may carry (a NUL byte)
+    InvalidContent,
+    // Guests can't post on this server, the sender has to /register or /login first
+    SignInRequired,
+    // A moderator muted the sender, see retry_after for when it runs out
+    Muted,
+    // The room is private: joining takes an invite or its password
+    PrivateRoom,
+    // A reason from a newer protocol (never sent), the text in content still says what happened
+    #[serde(other)]
+    Other,
+}
+
+impl ChatMessage {
+    // Builds a message stamped with the current time, RFC 3339 in UTC (clients show it in their own time zone)
+    pub fn new(username: impl Into<String>, content: impl Into<String>, message_type: MessageType) -> Self {
+        ChatMessage {
+            username: username.into(),
+            content: content.into(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            message_type,
+            ..Default::default()
+        }
+    }
+
+    // Builds the frame that hands a session token for `username` to a client
+    pub fn session(username: impl Into<String>, token: impl Into<String>) -> Self {
+        ChatMessage::new(username, token, MessageType::Session)
+    }
+
+    // Builds the frame that tells clients how many users are online
+    pub fn user_count(count: usize) -> Self {
+        ChatMessage::new("System", count.to_string(), MessageType::UserCount)
+    }
+
+    // Builds the frame that tells clients who is online
+    pub fn roster(members: Vec<Member>) -> Self {
+        ChatMessage { roster: members, ..ChatMessage::new("System", "", MessageType::Roster) }
+    }
+
+    // Builds the frame that passes on someone's whisper key
+    pub fn key(username: impl Into<String>, key: impl Into<String>) -> Self {
+        ChatMessage::new(username, key, MessageType::Key)
+    }
+
+    // Builds the frame with a room's pinned messages
+    pub fn pins(room: &str, pins: Vec<ChatMessage>) -> Self {
+        ChatMessage { pins, ..ChatMessage::new("System", "", MessageType::Pins).in_room(room) }
+    }
+
+    // Builds the answer to /rooms
+    pub fn room_list(rooms: Vec<RoomInfo>) -> Self {
+        ChatMessage { rooms, ..ChatMessage::new("System", "", MessageType::RoomList) }
+    }
+
+    // Builds the frame that hands clients the shortcodes users made
+    pub fn shortcode_sync(shortcodes: Vec<Shortcode>) -> Self {
+        ChatMessage { shortcodes, ..ChatMessage::new("System", "", MessageType::ShortcodeSync) }
+    }
+
+    // Builds the frame that tells a client which commands the server has
+    pub fn capabilities(commands: Vec<CommandInfo>) -> Self {
+        ChatMessage { commands, ..ChatMessage::new("System", "", MessageType::Capabilities) }
+    }
+
+    // Builds the frame that hands a draft to the account's other devices
+    pub fn draft(room: &str, text: impl Into<String>) -> Self {
+        ChatMessage::new("System", text, MessageType::Draft).in_room(room)
+    }
+
+    // Builds an event for #server-ops
+    pub fn ops_event(event: OpsEvent, content: impl Into<String>) -> Self {
+        ChatMessage {
+            ops_event: Some(event),
+            ..ChatMessage::new("server", content, MessageType::OpsEvent).in_room(OPS_ROOM)
+        }
+    }
+
+    // Builds an error frame sent by the "System" user
+    pub fn error(kind: ErrorKind, content: impl Into<String>) -> Self {
+        ChatMessage {
+            error: Some(kind),
+            ..ChatMessage::new("System", content, MessageType::Error)
+        }
+    }
+
+    // Tags the frame with the room it belongs to
+    pub fn in_room(mut self, room: impl Into<String>) -> Self {
+        self.room = Some(room.into());
+        self
+    }
+
+    // Attaches a Retry-After hint (in seconds) to an error frame
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+}
+
+// The one shape message content takes on the server, whoever sent it and from where: line endings
+// as \n (not \r\n or \r), no whitespace at the end of a line or of the message, no blank lines at
+// either end and at most MAX_BLANK_LINES in a row. Leading whitespace is kept, code needs it.
+// Content with a NUL byte is refused rather than cleaned up, nothing sends one by accident.
+pub fn normalize_content(content: &str) -> Result<String, &'static str> {
+    if content.contains('\0') {
+        return Err("Messages can't contain NUL bytes, it was not sent");
+    }
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    let mut normalized = String::with_capacity(content.len());
+    let mut blanks = 0;
+    for line in content.split('\n').map(str::trim_end) {
+        if line.is_empty() {
+            blanks += 1;
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push_str(&"\n".repeat(blanks.min(MAX_BLANK_LINES) + 1));
+        }
+        normalized.push_str(line);
+        blanks = 0;
+    }
+    Ok(normalized)
+}
+
+// What a "/sign <stamp> <signature>" line signs for the message after it: the room it's said in, the stamp
+// (milliseconds since 1970, higher on every line a key signs) and the content as the server frames it. The
+// room and the stamp keep a signature from being posted again elsewhere or later
+pub fn signed_text(room: &str, stamp: u64, content: &str) -> String {
+    format!("{}\n{}\n{}", room, stamp, content)
+}
+
+// First line a client sends after connecting
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Handshake {
+    pub username: String,
+    // Server password, only needed when the server was started with --password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // Token from an earlier login: resumes that identity instead of joining fresh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    // Newest message id the client has seen, the server replays everything after it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_message_id: Option<i64>,
+    // Rooms the client was in, rejoined on connect; the last one becomes active
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rooms: Vec<String>,
+    // X25519 public key (base64) others seal their whispers to us with, passed on to everyone in Key frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    // Ed25519 public key (base64) the client signs its messages with, recorded for the name on first use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    // Federation link token: the connection is another server (named in username) linking up, not a user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_token: Option<String>,
+}
+
+impl Handshake {
+    // Older clients send the bare username instead of a JSON handshake
+    pub fn parse(line: &str) -> Handshake {
+        let line = line.trim();
+        serde_json::from_str(line).unwrap_or_else(|_| Handshake {
+            username: line.to_string(),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,54 @@
+// Password hashing for registered accounts
+// Hashes are argon2id PHC strings, so the parameters travel with the hash.
+// Hashing is deliberately slow, so both helpers run on tokio's blocking pool.
+
+use rand::distr::Alphanumeric;
+use rand::Rng;
+
+use argon2::{
+    password_hash::{phc::PasswordHash, PasswordHasher, PasswordVerifier},
+    Argon2,
+};
+
+// Shortest password /register accepts
+pub const MIN_PASSWORD_LEN: usize = 6;
+
+// Length of the passwords an operator's reset hands out
+const TEMPORARY_PASSWORD_LEN: usize = 12;
+
+// Sessions unused for this many days stop working
+pub const SESSION_TTL_DAYS: i64 = 30;
+
+// Random 256 bit session token, hex encoded
+pub fn new_session_token() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Random password for an operator reset, meant to be changed with /passwd right away
+pub fn temporary_password() -> String {
+    rand::rng().sample_iter(Alphanumeric).take(TEMPORARY_PASSWORD_LEN).map(char::from).collect()
+}
+
+// Hashes a password with a fresh random salt
+pub async fn hash_password(password: String) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes())
+            .map(|hash| hash.to_string())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Checks a password against a stored PHC string
+pub async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+            .unwrap_or(false)
+    })
+    .await
+    .unwrap_or(false)
+}
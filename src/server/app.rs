@@ -0,0 +1,3743 @@
+
+// The chat server itself: shared state, the accept loop and everything a connection goes through
+//setting up tokio for TCP server with broadcast messaging, asynchronous handling of client connections,
+// and message history management
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+};
+
+//Chrono: timestamp for when a user joins the chat room 
+//Arc: good for shared ownership of data across threads
+//Mutex: allows mutable access to shared data in a thread-safe manner
+//VecDeque: a double-ended queue that allows efficient push and pop operations from both ends
+use chrono::{DateTime, Local, Utc};
+use std::env;
+use std::path::Path;
+use std::error::Error;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::task::JoinSet;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{StreamExt, StreamMap};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Chat message frames are shared with the client through the protocol module
+use crate::blocks::{BlockFraming, Framed, MAX_BLOCK_BYTES, MAX_BLOCK_LINES};
+use crate::protocol::{normalize_content, signed_text, ChatMessage, ErrorKind, Handshake, Member, MessageType, OpsEvent, Quote, DEFAULT_ROOM, MAX_LINE_BYTES, OPS_ROOM};
+use crate::server::accounts::{self, MIN_PASSWORD_LEN};
+use crate::server::chaos::Chaos;
+use crate::server::cluster::{self, Cluster, Online, Remote};
+use crate::server::commands::{self, parse_action, parse_reply, ChaosAction, Command, ProfileAction, ProfileField, ShortcodeAction, TwoFactorAction, MAX_QUOTE_LEN};
+use crate::server::config::{Preset, ServerConfig};
+use crate::server::console::{self, AdminCommand};
+use crate::server::maintenance;
+use crate::server::matrix::{self, MatrixMessage};
+use crate::server::metrics::{self, Scrape, Snapshot};
+use crate::server::cooldown::ReconnectGuard;
+use crate::server::federation::{Arrivals, Federation};
+use crate::server::filter::WordFilter;
+use crate::server::irc::IrcListener;
+use crate::server::limits::ConnectionLimits;
+use crate::server::listen::{self, ACCEPT_QUEUE, DEFAULT_BIND};
+use crate::server::nicknames;
+use crate::server::outbound::Outbound;
+use crate::server::plugins::{ClientInfo, LoggerPlugin, Plugins, RateLimitPlugin, WordFilterPlugin};
+use crate::server::presence::{Control, Presence};
+use crate::server::quic::QuicListener;
+use crate::server::roles::{Permission, Role};
+use crate::server::rooms::{Rooms, RATE_WINDOW};
+use crate::server::same_token;
+#[cfg(feature = "scripting")]
+use crate::server::scripting::{ScriptMessage, ScriptPlugin};
+use crate::server::store::{ModerationEntry, Store};
+use crate::server::totp;
+use crate::server::unix::{LocalListener, LOCAL_PEER};
+use crate::server::webhooks::{self, WebhookPlugin, WebhookPost};
+use crate::trace::{Direction, WireTracer};
+use rand::Rng;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+
+// Config file picked up from the working directory when --config isn't given
+const DEFAULT_CONFIG: &str = "server.toml";
+
+// Wire trace file used when tracing is switched on without --trace-wire
+const DEFAULT_TRACE_FILE: &str = "wire-trace.log";
+
+// Shown as the account's provider in authenticator apps
+const TOTP_ISSUER: &str = "Retro Chat";
+
+// Most missed messages replayed to a resumed session
+const MAX_MISSED_REPLAY: usize = 500;
+
+// Refusal for guests while [guests] can_post is off
+const GUESTS_READ_ONLY: &str = "Guests can only read here, /register or /login to join in";
+// Same for accounts demoted to guest
+const DEMOTED_READ_ONLY: &str = "You were made a guest, guests can only read here";
+
+// Wrong passwords and codes a connection, or an account, gets a minute before /login is held off
+const FAILED_LOGINS_PER_MINUTE: usize = 5;
+
+// How far a /sign stamp may be from our clock before the signature counts as stale
+const SIGNATURE_WINDOW: Duration = Duration::from_secs(300);
+
+// Entries /auditlog (and the console's auditlog) show
+const AUDIT_LOG_LINES: usize = 20;
+
+// Most messages a room keeps pinned
+const MAX_PINS: usize = 25;
+
+// Most whispers an offline account's mailbox takes
+const MAX_MAILBOX: usize = 100;
+
+// Who the moderation log says did what the operator typed in the console, or what a reloaded config did
+const CONSOLE: &str = "(console)";
+const CONFIG: &str = "(config)";
+
+// Rhai bots are loaded from here (with the scripting feature)
+#[cfg(feature = "scripting")]
+const SCRIPT_DIR: &str = "plugins";
+
+// Seconds of warning clients get before a Ctrl+C shutdown
+const SHUTDOWN_COUNTDOWN: u64 = 5;
+
+// Joins and leaves within this window are reported as a single user count update
+const USER_COUNT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// Time a new connection gets to send its handshake (less with a shorter idle timeout), so silent sockets
+// don't hold on to connection slots
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+// Options given on the command line when starting the server
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    // When set, clients must send this password in their handshake
+    pub password: Option<String>,
+    // Connections allowed per IP (and per username) each minute before a cooldown kicks in
+    pub reconnect_limit: usize,
+    // SQLite file holding registered accounts and message history
+    pub db_path: String,
+    // TOML config file, "server.toml" is picked up automatically when it exists
+    pub config_path: Option<String>,
+    // Bundle of settings the config file goes on top of (--preset)
+    pub preset: Option<Preset>,
+    // Log every frame to this file from startup (tracing can also be switched on later)
+    pub trace_wire: Option<String>,
+    // Addresses to listen on (--bind, comma-separated), one accept loop each
+    pub bind: Vec<SocketAddr>,
+    // Also listen on this Unix domain socket (--unix-socket)
+    pub unix_socket: Option<String>,
+    // Also listen for QUIC on this UDP address (--quic, quic feature), with this certificate and key (PEM)
+    pub quic: Option<SocketAddr>,
+    pub quic_cert: Option<String>,
+    pub quic_key: Option<String>,
+    // Also take IRC clients on this address (--irc)
+    pub irc: Option<SocketAddr>,
+    // Read admin commands from the terminal (the binary does, servers run in-process don't)
+    pub console: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            password: None,
+            reconnect_limit: 10,
+            db_path: "chat.db".to_string(),
+            config_path: None,
+            preset: None,
+            trace_wire: None,
+            bind: listen::parse_bind_list(DEFAULT_BIND).unwrap_or_default(),
+            unix_socket: None,
+            quic: None,
+            quic_cert: None,
+            quic_key: None,
+            irc: None,
+            console: false,
+        }
+    }
+}
+
+impl ServerOptions {
+    pub fn from_args() -> Result<Self, String> {
+        let mut options = ServerOptions { console: true, ..Default::default() };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--password" => {
+                    let password = args.next().ok_or("--password needs a value")?;
+                    options.password = Some(password);
+                }
+                "--reconnect-limit" => {
+                    let limit = args.next().ok_or("--reconnect-limit needs a value")?;
+                    options.reconnect_limit = limit
+                        .parse()
+                        .map_err(|_| format!("invalid --reconnect-limit: {}", limit))?;
+                }
+                "--db" => {
+                    options.db_path = args.next().ok_or("--db needs a value")?;
+                }
+                "--config" => {
+                    options.config_path = Some(args.next().ok_or("--config needs a value")?);
+                }
+                "--preset" => {
+                    options.preset = Some(Preset::parse(&args.next().ok_or("--preset needs a name")?)?);
+                }
+                "--trace-wire" => {
+                    options.trace_wire = Some(args.next().ok_or("--trace-wire needs a file path")?);
+                }
+                "--bind" => {
+                    options.bind = listen::parse_bind_list(&args.next().ok_or("--bind needs a comma-separated list of addresses")?)?;
+                }
+                "--quic" => {
+                    let addr = args.next().ok_or("--quic needs an address, like 0.0.0.0:8443")?;
+                    options.quic = Some(addr.parse().map_err(|_| format!("invalid --quic address: {}", addr))?);
+                }
+                "--quic-cert" => {
+                    options.quic_cert = Some(args.next().ok_or("--quic-cert needs a PEM file")?);
+                }
+                "--quic-key" => {
+                    options.quic_key = Some(args.next().ok_or("--quic-key needs a PEM file")?);
+                }
+                "--irc" => {
+                    let addr = args.next().ok_or("--irc needs an address, like 0.0.0.0:6667")?;
+                    options.irc = Some(addr.parse().map_err(|_| format!("invalid --irc address: {}", addr))?);
+                }
+                "--unix-socket" => {
+                    options.unix_socket = Some(args.next().ok_or("--unix-socket needs a path")?);
+                }
+                other => return Err(format!("unknown argument: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+
+    // Loads the config file given with --config, or ./server.toml if present, over the preset or the defaults
+    pub fn load_config(&self) -> Result<ServerConfig, String> {
+        let path = match &self.config_path {
+            Some(path) => Some(Path::new(path)),
+            None => Some(Path::new(DEFAULT_CONFIG)).filter(|path| path.exists()),
+        };
+        ServerConfig::load_with_preset(self.preset, path)
+    }
+}
+
+
+// Everything the connection tasks share with each other
+
+struct ServerState {
+    options: ServerOptions,
+    // Current config, replaced on reload
+    config: watch::Sender<ServerConfig>,
+    // Message of the day: from the config, or whatever an owner set since
+    motd: Mutex<Option<String>>,
+    // Hooks every connection runs through, in order: rate limit, word filter, logger
+    plugins: Plugins,
+    // The word filter plugin again, so the console and reloads can change its rules
+    filter: Arc<WordFilterPlugin>,
+    // Script bots, reloaded along with the config
+    #[cfg(feature = "scripting")]
+    scripts: Arc<ScriptPlugin>,
+    // The webhook relay, its hooks swapped on reload
+    webhooks: Arc<WebhookPlugin>,
+    // Links to the servers we share rooms with, when this server has a federation name
+    federation: Option<Arc<Federation>>,
+    // The other processes of the cluster, when there's a [redis] section
+    cluster: Option<Cluster>,
+    // Open rooms with their recent history
+    rooms: Mutex<Rooms>,
+    // /join attempts per username, same cooldown rules as reconnects
+    joins: Mutex<ReconnectGuard>,
+    // Failed /login attempts per connection and per account
+    failed_logins: Mutex<ReconnectGuard>,
+    // Last /sign stamp accepted per signing key, a signature with a stamp no higher was used before
+    signature_stamps: Mutex<HashMap<String, u64>>,
+    limits: ConnectionLimits,
+    store: Arc<Mutex<Store>>,
+    // Shared with every connection's writer task
+    tracer: Arc<WireTracer>,
+    // Who is connected right now, used by the admin console
+    presence: Mutex<Presence>,
+    // Addresses refused at the door, mirrored in the database
+    bans: Mutex<HashSet<IpAddr>>,
+    // Accounts (lowercase) that can't sign in, mirrored in the database
+    banned_accounts: Mutex<HashSet<String>>,
+    // Muted usernames (lowercase) and when each mute runs out, mirrored in the database
+    mutes: Mutex<HashMap<String, DateTime<Utc>>>,
+    // Who was whispered to (lowercase) and by whom, until their client says they saw it
+    unseen_whispers: Mutex<HashMap<String, HashSet<String>>>,
+    // Chat messages relayed since startup
+    messages: AtomicU64,
+    started: Instant,
+    // Set by the console's shutdown command, holds the countdown in seconds
+    shutdown: watch::Sender<Option<u64>>,
+    // Flipped once the countdown is over, tells every connection to say goodbye and close
+    closing: watch::Sender<bool>,
+    // Events for #server-ops, posted there by relay_ops_events (see report)
+    ops: mpsc::UnboundedSender<ChatMessage>,
+    // Lag and partitions injected with /chaos, shared with every connection's writer task
+    chaos: Arc<Chaos>,
+}
+
+
+// Who a connection speaks as and the rooms it is in
+
+struct Client {
+    // Presence id, also the member id in every room
+    id: u64,
+    addr: SocketAddr,
+    username: String,
+    verified: bool,
+    // Role stored for the account (guests are members here), see role()
+    role: Role,
+    // Room plain messages go to
+    room: String,
+    // Frames from every room the client is in, by room name
+    rooms: StreamMap<String, BroadcastStream<String>>,
+    // Ed25519 key (base64) the client signs its messages with, and the /sign (stamp and signature) it sent for
+    // the message that comes next
+    signing_key: Option<String>,
+    signature: Option<(u64, String)>,
+}
+
+impl Client {
+    // What the plugin hooks get to see
+    fn info(&self) -> ClientInfo<'_> {
+        ClientInfo { username: &self.username, addr: self.addr, room: &self.room, verified: self.verified }
+    }
+
+    fn role(&self, state: &ServerState) -> Role {
+        state.config.borrow().role_of(&self.username, self.verified, self.role)
+    }
+
+    fn can(&self, state: &ServerState, permission: Permission) -> bool {
+        state.config.borrow().allows(self.role(state), permission)
+    }
+
+    // Whether this client can post in a read-only room
+    fn is_announcer(&self, state: &ServerState) -> bool {
+        self.can(state, Permission::Announce) || (self.verified && state.config.borrow().moderation.is_announcer(&self.username))
+    }
+
+    // Why this client can't talk, when it's a guest and guests can only read
+    fn guest_refusal(&self, state: &ServerState) -> Option<ChatMessage> {
+        if self.role(state) > Role::Guest || state.config.borrow().guests.can_post {
+            return None;
+        }
+        let reason = if self.verified { DEMOTED_READ_ONLY } else { GUESTS_READ_ONLY };
+        Some(ChatMessage::error(ErrorKind::SignInRequired, reason))
+    }
+}
+
+
+
+// The server, set up and waiting to be run: the database is open, the extra listeners (Unix socket, QUIC, IRC)
+// are bound and the background tasks are going. The binary runs one on the --bind addresses, the tests on a
+// port of their own.
+
+pub struct ChatServer {
+    state: Arc<ServerState>,
+    tx: broadcast::Sender<String>,
+    local_listener: LocalListener,
+    quic_listener: QuicListener,
+    irc_listener: IrcListener,
+}
+
+// Shuts a server down (or reloads its config) from outside, the way the console's shutdown and reload commands do
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ServerState>,
+}
+
+impl ShutdownHandle {
+    // Starts the graceful shutdown, after a countdown of `seconds`
+    pub fn shutdown(&self, seconds: u64) {
+        self.state.shutdown.send_replace(Some(seconds));
+    }
+
+    // Re-reads the config file, as SIGHUP does
+    pub async fn reload(&self) {
+        reload_config(&self.state).await;
+    }
+}
+
+impl ChatServer {
+    pub async fn new(options: ServerOptions, config: ServerConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let local_listener = LocalListener::bind(options.unix_socket.as_deref())?; // and the Unix socket, when asked for one
+        let quic_listener = QuicListener::bind(options.quic, options.quic_cert.as_deref(), options.quic_key.as_deref())?; // and QUIC
+        let irc_listener = IrcListener::bind(options.irc)?; // and IRC
+        // The webhook endpoint only takes posts that carry the token
+        let webhook_posts = match (config.webhooks.listen, &config.webhooks.token) {
+            (Some(addr), Some(token)) if !token.is_empty() => Some(webhooks::listen(addr, token.clone())?),
+            (Some(_), _) => return Err("webhooks.listen needs a webhooks.token".into()),
+            (None, _) => None,
+        };
+        // The metrics endpoint is open to whoever reaches it, unless it has a token
+        let metrics_scrapes = match config.metrics.listen {
+            Some(addr) => Some(metrics::listen(addr, config.metrics.token.clone().filter(|token| !token.is_empty()))?),
+            None => None,
+        };
+
+        // The database keeps accounts and history across restarts, so new arrivals can catch up right away
+        let store = Store::open(&options.db_path)?;
+        let bans = store.bans()?.into_iter().chain(config.moderation.banned_ips.iter().copied()).collect();
+        let banned_accounts = store.account_bans()?.into_iter().map(|username| username.to_lowercase()).collect();
+        let mutes = store.mutes()?.into_iter().map(|(username, until)| (username.to_lowercase(), until)).collect();
+        let mut rooms = Rooms::new(store.read_only_rooms()?);
+        for (room, topic) in store.room_topics()? {
+            rooms.set_topic(&room, Some(topic));
+        }
+        for room in store.private_rooms()? {
+            rooms.set_private(&room);
+        }
+        rooms.open_permanent(OPS_ROOM);
+        let store = Arc::new(Mutex::new(store));
+        let limits = ConnectionLimits::new(config.limits.max_clients, config.limits.max_per_ip);
+        let motd = config.load_motd()?;
+        let joins = ReconnectGuard::new(config.rooms.joins_per_minute, Duration::from_secs(60));
+
+        // Built-in plugins: reconnect storm protection, the word filter and the terminal log
+        let filter = Arc::new(WordFilterPlugin::new(WordFilter::from_config(&config.filter)));
+        let mut plugins = Plugins::default();
+        plugins.register(Arc::new(RateLimitPlugin::new(options.reconnect_limit)));
+        plugins.register(filter.clone());
+        plugins.register(Arc::new(LoggerPlugin));
+        let webhooks = Arc::new(WebhookPlugin::new(config.webhooks.outbound.clone()));
+        plugins.register(webhooks.clone());
+        let matrix_messages = match matrix::start(config.matrix.clone())? {
+            Some((bridge, messages)) => {
+                let room = Rooms::normalize(bridge.local_room()).map_err(|e| format!("matrix.local_room: {}", e))?;
+                plugins.register(Arc::new(bridge));
+                Some((room, messages))
+            }
+            None => None,
+        };
+        let (federation, federated) = match Federation::new(&config.federation)? {
+            Some((federation, arrivals)) => {
+                plugins.register(federation.clone());
+                (Some(federation), Some(arrivals))
+            }
+            None => (None, None),
+        };
+        let (cluster, remote) = match cluster::start(config.redis.clone())? {
+            Some((cluster, remote)) => (Some(cluster), Some(remote)),
+            None => (None, None),
+        };
+        #[cfg(feature = "scripting")]
+        let (scripts, script_outbox) = {
+            let (outbox, messages) = mpsc::unbounded_channel();
+            let scripts = Arc::new(ScriptPlugin::new(SCRIPT_DIR, outbox));
+            plugins.register(scripts.clone());
+            (scripts, messages)
+        };
+
+        let (ops, ops_events) = mpsc::unbounded_channel();
+
+        // Shared state: the rooms (each keeps 20 messages of history unless configured otherwise),
+        // the plugins, join flood tracking (limit is per minute), connection caps and the database
+        let state = Arc::new(ServerState {
+            rooms: Mutex::new(rooms),
+            joins: Mutex::new(joins),
+            failed_logins: Mutex::new(ReconnectGuard::new(FAILED_LOGINS_PER_MINUTE, Duration::from_secs(60))),
+            signature_stamps: Mutex::new(HashMap::new()),
+            limits,
+            store,
+            tracer: Arc::new(WireTracer::new(
+                options.trace_wire.clone().unwrap_or_else(|| DEFAULT_TRACE_FILE.to_string()),
+                options.trace_wire.is_some(),
+            )),
+            presence: Mutex::new(Presence::default()),
+            bans: Mutex::new(bans),
+            banned_accounts: Mutex::new(banned_accounts),
+            mutes: Mutex::new(mutes),
+            unseen_whispers: Mutex::new(HashMap::new()),
+            motd: Mutex::new(motd),
+            plugins,
+            filter,
+            #[cfg(feature = "scripting")]
+            scripts,
+            webhooks,
+            federation,
+            cluster,
+            messages: AtomicU64::new(0),
+            started: Instant::now(),
+            shutdown: watch::Sender::new(None),
+            closing: watch::Sender::new(false),
+            options,
+            config: watch::Sender::new(config),
+            ops,
+            chaos: Arc::new(Chaos::default()),
+        });
+
+        // Bans, rate limits, lag and errors go to the moderators in #server-ops
+        tokio::spawn(relay_ops_events(state.clone(), ops_events));
+
+        // Whatever the script bots say gets posted to their rooms
+        #[cfg(feature = "scripting")]
+        tokio::spawn(relay_script_messages(state.clone(), script_outbox));
+
+        // And whatever comes in on the webhook endpoint
+        if let Some(posts) = webhook_posts {
+            tokio::spawn(relay_webhook_posts(state.clone(), posts));
+        }
+
+        // Scrapes of the metrics endpoint get the numbers as they are
+        if let Some(scrapes) = metrics_scrapes {
+            tokio::spawn(answer_metrics_scrapes(state.clone(), scrapes));
+        }
+
+        // And what's said in the bridged Matrix room
+        if let Some((room, messages)) = matrix_messages {
+            tokio::spawn(relay_matrix_messages(state.clone(), room, messages));
+        }
+
+        // And what the linked servers relay, once we've started dialing the ones we dial
+        if let (Some(federation), Some(arrivals)) = (&state.federation, federated) {
+            federation.dial();
+            tokio::spawn(relay_federated_messages(state.clone(), arrivals));
+        }
+
+        // And what's said on the other processes of the cluster, which hear who's on this one
+        if let Some(remote) = remote {
+            tokio::spawn(relay_cluster_messages(state.clone(), remote));
+            tokio::spawn(share_online(state.clone()));
+        }
+
+        // Retention and VACUUM run in the background on the configured schedule
+        tokio::spawn(maintenance::run(state.store.clone(), state.config.subscribe()));
+
+        // SIGHUP re-reads the config file, like the console's reload command
+        #[cfg(unix)]
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                use tokio::signal::unix::{signal, SignalKind};
+                let Ok(mut hangups) = signal(SignalKind::hangup()) else { return };
+                while hangups.recv().await.is_some() {
+                    println!("🔄 SIGHUP received, reloading configuration");
+                    reload_config(&state).await;
+                }
+            }
+        });
+
+
+        //tx used for broadcasting messages to all connected clients
+        //rx used for receiving messages from the broadcast channel
+        //broadcast channel with a buffer size of 200 messages (meaning that it hold up to 200 messages before blocking) 
+
+
+        let (tx, _) = broadcast::channel::<String>(200);
+
+        // Operator commands typed into this terminal
+        if state.options.console {
+            tokio::spawn(run_console(state.clone(), tx.clone()));
+        }
+
+        // Everyone's status bar follows the number of people online (on every process of the cluster when there is one)
+        let (user_count, roster) = match &state.cluster {
+            Some(cluster) => (cluster.watch_count(), cluster.watch_roster()),
+            None => {
+                let presence = state.presence.lock().await;
+                (presence.watch_count(), presence.watch_roster())
+            }
+        };
+        tokio::spawn(broadcast_user_count(user_count, tx.clone()));
+        tokio::spawn(broadcast_roster(roster, tx.clone()));
+
+        Ok(ChatServer { state, tx, local_listener, quic_listener, irc_listener })
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { state: self.state.clone() }
+    }
+
+    // Lets clients in from `listeners` (and the extra listeners) until Ctrl+C or a shutdown request,
+    // then counts down, says goodbye to everyone and waits for the connections to drain
+    pub async fn run(self, listeners: Vec<TcpListener>) {
+        let ChatServer { state, tx, local_listener, mut quic_listener, mut irc_listener } = self;
+
+        //Output in the command line for server startup (yes I know it is slightly off-centered)
+
+        println!("################################################################################");
+        println!("#                                                                              #");
+        println!("#   ██████   ███    ██  ███      ██  ███    ██  ███████                        #");
+        println!("#   ██   ██  ████   ██   ██      ██  ████   ██  ██                             #");
+        println!("#   ██    ██ ██ ██  ██   ██      ██  ██ ██  ██  █████                          #");
+        println!("#   ██    ██ ██  ██ ██   ██      ██  ██  ██ ██  ██                             #");
+        println!("#    ██████  ██   ████   ███████ ██  ██   ████  ███████                        #");
+        println!("#                                                                              #");
+        println!("#                        🚀  SERVER ONLINE  🚀                                 #");
+        let addrs: Vec<SocketAddr> = listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect();
+        for (i, addr) in addrs.iter().enumerate() {
+            let label = if i == 0 { "Listening:" } else { "" };
+            println!("#                   {:<10} {:<48}#", label, addr.to_string());
+        }
+        println!("#                [CTRL+C] to disengage hyperdrive                              #");
+        println!("#                Type 'help' for admin commands                                #");
+        println!("#                                                                              #");
+        println!("################################################################################");
+        if let Some(preset) = state.options.preset {
+            println!("⚙️  Started from the {} preset, the config file wins where it says otherwise", preset.name());
+        }
+        if let Some(path) = local_listener.path() {
+            println!("🔌 Also listening on the Unix socket {}", path.display());
+        }
+        if let Some(addr) = quic_listener.local_addr() {
+            println!("🧪 Also listening for QUIC on {} (UDP, experimental)", addr);
+        }
+        if let Some(addr) = irc_listener.local_addr() {
+            println!("💬 Also listening for IRC clients on {}", addr);
+        }
+        if state.config.borrow().chaos.enabled {
+            println!("🧪 Chaos commands are on, owners can break things with /chaos (staging only!)");
+        }
+
+
+        //shutdown_signal is used to gracefully shut down the server when Ctrl+C is pressed
+        //tokio::signal::ctrl_c() creates a future that resolves when the user presses Ctrl+C
+        //tokio::pin! is used to pin the shutdown_signal future so it can be used in a select statement
+
+        let shutdown_signal = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown_signal);
+
+
+
+        //this loop accepts new connections and spawns a new task for each connection
+        //tokio::select! is used to wait for either a new connection or the shutdown signal
+        //when a new connection is accepted, it prints the connection details and spawns a new task to handle the connection
+        //the task handles reading messages from the client, broadcasting them to all connected clients, and sending the message history to the new client
+        //connection tasks live in a JoinSet so shutdown can wait for them to finish writing
+        //when the shutdown signal is received, the loop breaks with the countdown to give clients
+        let mut connections = JoinSet::new();
+        let (accepted_tx, mut accepted) = mpsc::channel::<(TcpStream, SocketAddr)>(ACCEPT_QUEUE);
+        let mut accept_loops = listen::spawn_accept_loops(listeners, accepted_tx);
+        let mut shutdown_requests = state.shutdown.subscribe();
+        let countdown = loop {
+            tokio::select! {
+                // From any of the --bind addresses
+                Some((socket, addr)) = accepted.recv() => {
+                    admit(socket, addr, addr.to_string(), &state, &tx, &mut connections).await;
+                }
+
+                // Unix socket clients go through the same door
+                Ok(socket) = local_listener.accept() => {
+                    let peer = format!("unix:{}", local_listener.path().map(|path| path.display().to_string()).unwrap_or_default());
+                    admit(socket, LOCAL_PEER, peer, &state, &tx, &mut connections).await;
+                }
+
+                // And QUIC clients, each with the stream it opened
+                Some((stream, addr)) = quic_listener.accept() => {
+                    admit(stream, addr, format!("quic:{}", addr), &state, &tx, &mut connections).await;
+                }
+
+                // IRC clients too, once they've registered, through the bridge that translates for them
+                Some((stream, addr)) = irc_listener.accept() => {
+                    admit(stream, addr, format!("irc:{}", addr), &state, &tx, &mut connections).await;
+                }
+
+                // Reap finished connection tasks so the set doesn't grow forever
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+
+                _ = &mut shutdown_signal => {
+                    println!("\n🛑 Ctrl+C received. Starting graceful shutdown… (Ctrl+C again to skip the countdown)");
+                    break SHUTDOWN_COUNTDOWN;
+                }
+
+                Ok(()) = shutdown_requests.changed() => {
+                    let seconds = shutdown_requests.borrow_and_update().unwrap_or_default();
+                    println!("\n🛑 Shutdown requested from the console. Starting graceful shutdown…");
+                    break seconds;
+                }
+            }
+        };
+        accept_loops.shutdown().await; // no new arrivals while we wind down
+        drop(local_listener); // (the socket file goes with it)
+        drop(quic_listener);
+        drop(irc_listener);
+
+        // Give everyone a moment to finish their sentence
+        tokio::select! {
+            _ = count_down(countdown, &tx) => {}
+            _ = tokio::signal::ctrl_c() => println!("⏩ Countdown skipped"),
+        }
+
+        // Every connection sends its goodbye and closes, wait (within [shutdown] drain_timeout_seconds) for them to get it out
+        state.closing.send_replace(true);
+        let drain_timeout = state.config.borrow().shutdown.drain_timeout();
+        let open = connections.len();
+        let mut drained = 0;
+        let _ = tokio::time::timeout(drain_timeout, async {
+            while connections.join_next().await.is_some() {
+                drained += 1;
+            }
+        })
+        .await;
+        let forced = connections.len();
+        if forced > 0 {
+            println!("⚠️  {} connection(s) didn't close within {}s, dropping them", forced, drain_timeout.as_secs());
+            connections.shutdown().await;
+        }
+        println!("🚪 {} of {} connection(s) drained cleanly, {} force-closed", drained, open, forced);
+
+        drop(tx);
+
+        println!("✅ Server has shut down gracefully.");
+    }
+}
+
+
+
+// Broadcasts the number of connected users whenever it changes
+// Waits a moment after each change so a burst of joins or leaves becomes one update
+
+async fn broadcast_user_count(mut count: watch::Receiver<usize>, tx: broadcast::Sender<String>) {
+    let mut last_sent = None;
+    while count.changed().await.is_ok() {
+        tokio::time::sleep(USER_COUNT_DEBOUNCE).await;
+        let current = *count.borrow_and_update();
+        if last_sent == Some(current) {
+            continue;
+        }
+        last_sent = Some(current);
+        if let Ok(json) = serde_json::to_string(&ChatMessage::user_count(current)) {
+            let _ = tx.send(json);
+        }
+    }
+}
+
+
+// Broadcasts who is online whenever that changes, debounced like the count
+
+async fn broadcast_roster(mut roster: watch::Receiver<Vec<Member>>, tx: broadcast::Sender<String>) {
+    while roster.changed().await.is_ok() {
+        tokio::time::sleep(USER_COUNT_DEBOUNCE).await;
+        let current = roster.borrow_and_update().clone();
+        if let Ok(json) = serde_json::to_string(&ChatMessage::roster(current)) {
+            let _ = tx.send(json);
+        }
+    }
+}
+
+
+
+// Broadcasts how long is left before shutdown: at the start, every ten seconds and for the last three
+
+async fn count_down(seconds: u64, tx: &broadcast::Sender<String>) {
+    for remaining in (1..=seconds).rev() {
+        if remaining == seconds || remaining % 10 == 0 || remaining <= 3 {
+            println!("⏳ Shutting down in {}s", remaining);
+            let notice = format!("Server is shutting down in {} second{}", remaining, if remaining == 1 { "" } else { "s" });
+            let notice = ChatMessage::new("System", notice, MessageType::SystemNotification);
+            if let Ok(json) = serde_json::to_string(&notice) {
+                let _ = tx.send(json);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+
+
+// This function handles a single client connection asynchronously 
+
+// Lets a new connection in, or explains why not and hangs up (banned, partitioned, over the caps)
+// `peer` is how it shows in the log and the wire trace: the address, or the socket path for Unix socket clients
+
+async fn admit<S>(socket: S, addr: SocketAddr, peer: String, state: &Arc<ServerState>, tx: &broadcast::Sender<String>, connections: &mut JoinSet<()>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    println!("┌─[{}] New connection", Local::now().format("%D:%H:%M:%S"));
+    println!("└─ Address: {}", peer);
+
+    // Banned addresses are turned away before they take up a slot
+    if state.bans.lock().await.contains(&addr.ip()) {
+        println!("└─ Refused {}: banned", peer);
+        report(state, OpsEvent::Ban, format!("{} tried to connect but is banned", addr.ip()));
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut socket = socket;
+            let frame = ChatMessage::error(ErrorKind::Banned, "You are banned from this server");
+            let send = send_frame(&mut socket, &frame, &state.tracer, &peer);
+            let _ = tokio::time::timeout(Duration::from_secs(5), send).await;
+        });
+        return;
+    }
+
+    // A /chaos partition hangs up on everyone until it's over
+    if let Some(left) = state.chaos.partitioned() {
+        println!("└─ Refused {}: partitioned for another {}", peer, format_duration(left));
+        return;
+    }
+
+    // Over the connection caps: explain and hang up without ever joining the room
+    let permit = match state.limits.try_acquire(addr.ip()) {
+        Ok(permit) => permit,
+        Err(exceeded) => {
+            println!("└─ Refused {}: {}", peer, exceeded.message());
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut socket = socket;
+                let frame = ChatMessage::error(ErrorKind::ServerFull, exceeded.message());
+                let send = send_frame(&mut socket, &frame, &state.tracer, &peer);
+                let _ = tokio::time::timeout(Duration::from_secs(5), send).await;
+            });
+            return;
+        }
+    };
+
+    let tx = tx.clone();
+    let rx = tx.subscribe();
+    let state = state.clone();
+
+    connections.spawn(async move {
+        handle_connection(socket, addr, peer, tx, rx, state).await;
+        drop(permit); // frees the slot for the next client
+    });
+}
+
+
+// A line that filled MAX_LINE_BYTES without ending, whatever follows is more of it
+fn too_long(line: &str) -> bool {
+    line.len() >= MAX_LINE_BYTES && !line.ends_with('\n')
+}
+
+fn line_too_long() -> String {
+    format!("Lines are at most {} KiB, disconnecting", MAX_LINE_BYTES / 1024)
+}
+
+
+async fn handle_connection<S>(
+    socket: S,
+    addr: SocketAddr,
+    peer: String,
+    tx: broadcast::Sender<String>,
+    mut rx: broadcast::Receiver<String>,
+    state: Arc<ServerState>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(socket);
+    let mut reader = BufReader::new(reader);
+    let mut handshake_line = String::new();
+    let tracer = &state.tracer;
+
+    // 1. Read the handshake (gracefully bail on error)
+    let patience = state.config.borrow().limits.idle_timeout().map_or(HANDSHAKE_TIMEOUT, |idle| idle.min(HANDSHAKE_TIMEOUT));
+    match tokio::time::timeout(patience, (&mut reader).take(MAX_LINE_BYTES as u64).read_line(&mut handshake_line)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            eprintln!("[ERROR] failed to read handshake: {}", e);
+            return;
+        }
+        Err(_) => {
+            eprintln!("[WARN] {} sent no handshake in {}s, dropping the connection", peer, patience.as_secs_f64());
+            return;
+        }
+    }
+    if too_long(&handshake_line) {
+        eprintln!("[WARN] {} sent a handshake over {} bytes, dropping the connection", peer, MAX_LINE_BYTES);
+        let out = Outbound::spawn(writer, tracer.clone(), state.chaos.clone(), peer.clone());
+        out.send(&ChatMessage::error(ErrorKind::MessageTooLong, line_too_long()));
+        out.close().await;
+        return;
+    }
+    tracer.record(Direction::Received, &peer, &handshake_line);
+    let handshake = Handshake::parse(&handshake_line);
+
+    // 1 continued.... Another server linking up only trades chat, it never becomes a client (see federation.rs)
+    if let Some(token) = &handshake.link_token {
+        serve_link(&state, &handshake.username, token, reader, writer, &peer).await;
+        return;
+    }
+
+    // Everything for this client goes through its writer task, system frames ahead of chat
+    let out = Outbound::spawn(writer, tracer.clone(), state.chaos.clone(), peer.clone());
+    let mut username = handshake.username.trim().to_string();
+    let mut verified = false;
+
+    // 1 continued.... Plugins get to turn the connection away (the rate limiter does for reconnect storms)
+    let info = ClientInfo { username: &username, addr, room: DEFAULT_ROOM, verified };
+    if let Err(refusal) = state.plugins.on_connect(&info) {
+        if refusal.error == Some(ErrorKind::Cooldown) {
+            let seconds = refusal.retry_after.unwrap_or_default();
+            report(&state, OpsEvent::RateLimit, format!("{} ({}) is reconnecting too fast, held off for {}s", username, addr.ip(), seconds));
+        }
+        out.send(&refusal);
+        out.close().await;
+        return;
+    }
+
+    // 1 continued.... Check the server password before the user is announced
+    if let Some(expected) = &state.options.password
+        && !handshake.password.as_deref().is_some_and(|given| same_token(expected, given))
+    {
+        println!("└─[{}] {} failed authentication", Local::now().format("%D:%H:%M:%S"), username);
+        let reason = match handshake.password {
+            Some(_) => "Wrong server password",
+            None => "This server requires a password",
+        };
+        out.send(&ChatMessage::error(ErrorKind::AuthFailed, reason));
+        out.close().await;
+        return;
+    }
+
+    // 1 continued.... A valid session token resumes a signed-in identity instead of a fresh join
+    let resumed = match &handshake.session_token {
+        Some(token) => state.store.lock().await.resume_session(token).unwrap_or_else(|e| {
+            report_error(&state, format!("session lookup for {} failed: {}", username, e));
+            None
+        }),
+        None => None,
+    };
+    // Guests whose name could pass for someone's account get pointed out to everyone
+    let mut lookalikes = Vec::new();
+    if let Some(account) = &resumed {
+        username = account.clone();
+        verified = true;
+    } else {
+        // Registered names belong to their owners, so guests get a variant of the name
+        let registered = state.store.lock().await.account_exists(&username).unwrap_or_else(|e| {
+            report_error(&state, format!("account lookup for {} failed: {}", username, e));
+            false
+        });
+        if registered {
+            let guest_name = format!("{}_guest{}", username, rand::rng().random_range(1000..10000));
+            let mut notice = ChatMessage::new(
+                guest_name.clone(),
+                format!("joined as a guest, {} is a registered name. Use /login {} <password> to sign in", username, username),
+                MessageType::SystemNotification,
+            );
+            notice.renamed_from = Some(username.clone());
+            out.send(&notice);
+            username = guest_name;
+        } else {
+            match state.store.lock().await.account_names() {
+                Ok(accounts) => lookalikes = nicknames::lookalikes(&username, &accounts).into_iter().map(str::to_string).collect(),
+                Err(e) => eprintln!("[ERROR] listing accounts failed: {}", e),
+            }
+        }
+    }
+
+    // From here on the connection shows up in the console's list and can be kicked
+    let role = if verified { stored_role(&state, &username).await } else { Role::default() };
+    let public_key = handshake.public_key.clone().filter(|key| is_public_key(key));
+    let (presence_id, mut control) = {
+        let mut presence = state.presence.lock().await;
+        let (id, control) = presence.register(&username, verified, role, addr);
+        presence.set_public_key(id, public_key.clone());
+        (id, control)
+    };
+    if public_key.is_some() {
+        announce_key(&state, &username).await;
+    }
+    let mut client = Client {
+        id: presence_id,
+        addr,
+        username,
+        verified,
+        role,
+        room: DEFAULT_ROOM.to_string(),
+        rooms: StreamMap::new(),
+        signing_key: handshake.signing_key.clone().filter(|key| verifying_key(key).is_some()),
+        signature: None,
+    };
+    if let Some(notice) = register_signing_key(&state, &client).await {
+        out.send(&notice);
+    }
+
+    // Helper to send broadcast without panicking (such as with unwrap)
+    let try_send = |tx: &broadcast::Sender<String>, msg: String| {
+        if let Err(e) = tx.send(msg) {
+            eprintln!("[WARN] broadcast send failed: {}", e);
+        }
+    };
+
+    // 2. Announce new user arrival (a resumed session slips back in quietly)
+    if resumed.is_none() {
+        let join_msg = ChatMessage::new(client.username.clone(), "has landed", MessageType::SystemNotification);
+        match serde_json::to_string(&join_msg) {
+            Ok(join_json) => try_send(&tx, join_json),
+            Err(e) => eprintln!("[ERROR] failed to serialize join message: {}", e),
+        }
+        if !lookalikes.is_empty() {
+            let accounts = lookalikes.join(", ");
+            println!("└─[{}] ⚠️  {} looks like the account of {}", Local::now().format("%D:%H:%M:%S"), client.username, accounts);
+            let warning = format!("⚠️ {} is not signed in, and is not {} (only their messages carry a ✔)", client.username, accounts);
+            if let Ok(json) = serde_json::to_string(&ChatMessage::new("System", warning, MessageType::SystemNotification)) {
+                try_send(&tx, json);
+            }
+        }
+    } else {
+        println!("└─[{}] {} resumed their session", Local::now().format("%D:%H:%M:%S"), client.username);
+    }
+
+    // 2 continued.... The message of the day comes first, before any history
+    let motd = state.motd.lock().await.clone();
+    if let Some(motd) = motd {
+        out.send(&motd_frame(&motd));
+    }
+
+    // 2 continued.... Everyone starts in the lobby, plus the rooms the client was in before it reconnected
+    // Each room sends its history so the client can catch up,
+    // a resumed session gets everything it missed since the last message it saw instead
+    let mut wanted = vec![DEFAULT_ROOM.to_string()];
+    for room in &handshake.rooms {
+        if let Ok(room) = Rooms::normalize(room)
+            && !wanted.contains(&room)
+        {
+            wanted.push(room);
+        }
+    }
+    let last_seen = handshake.last_message_id.filter(|_| resumed.is_some());
+    let mut missed = 0;
+    let mut replay = Vec::new();
+    for room in wanted {
+        match join_room(&state, &mut client, &room, false, None).await {
+            Ok(joined) => {
+                replay.push(joined);
+                replay.push(pins_frame(&state, &room).await);
+            }
+            Err(refused) => {
+                replay.push(refused);
+                continue;
+            }
+        }
+        let messages = match last_seen {
+            Some(last_id) => {
+                let since = state.store.lock().await.messages_since(&room, last_id, MAX_MISSED_REPLAY);
+                let since = since.unwrap_or_else(|e| {
+                    report_error(&state, format!("loading missed messages for {} failed: {}", client.username, e));
+                    Vec::new()
+                });
+                missed += since.len();
+                since
+            }
+            None => state.rooms.lock().await.history(&room),
+        };
+        replay.extend(messages.into_iter().map(|msg| msg.in_room(room.as_str())));
+    }
+    if last_seen.is_some() {
+        let notice = format!("Welcome back {}, {} new messages while you were away", client.username, missed);
+        out.send(&ChatMessage::new("System", notice, MessageType::SystemNotification));
+    }
+    // Replayed messages carry their reactions so far, not just the raw text
+    let enriched = state.store.lock().await.enrich(&mut replay);
+    if let Err(e) = enriched {
+        report_error(&state, format!("loading reactions for {} failed: {}", client.username, e));
+    }
+    out.replay(&replay).await;
+
+    // 2 continued.... The count and roster broadcasts only go out on changes, so newcomers get the current ones here
+    let (online, roster) = match &state.cluster {
+        Some(cluster) => cluster.online(),
+        None => {
+            let presence = state.presence.lock().await;
+            (presence.len(), presence.roster())
+        }
+    };
+    out.send(&ChatMessage::user_count(online));
+    out.send(&ChatMessage::roster(roster));
+    // Likewise everyone's whisper key
+    for (username, key) in state.presence.lock().await.public_keys() {
+        out.send(&ChatMessage::key(username, key));
+    }
+    // Same for the shortcodes users made, so they expand in this client too
+    match state.store.lock().await.shortcodes() {
+        Ok(shortcodes) => out.send(&ChatMessage::shortcode_sync(shortcodes)),
+        Err(e) => report_error(&state, format!("loading shortcodes failed: {}", e)),
+    }
+    // And the commands it can type here, for its help
+    out.send(&ChatMessage::capabilities(commands::help()));
+    // And what this account was typing on its other devices, and the whispers it missed while offline
+    if client.verified {
+        for draft in draft_frames(&state, &client.username).await {
+            out.send(&draft);
+        }
+        for mail in mailbox_frames(&state, &client.username).await {
+            out.send(&mail);
+        }
+    }
+
+    // 3. Main loop: read client messages & forward broadcasts
+    // Anything the client sends pushes the idle deadline back
+    // The timeout is looked up every time round so a config reload applies to everyone right away
+    let mut last_activity = tokio::time::Instant::now();
+    let mut farewell = "has blasted off";
+    let mut shutting_down = false;
+    // Cut by /chaos: nothing more is written, not even what is queued
+    let mut dropped = false;
+    let mut closing = state.closing.subscribe();
+    let mut line = String::new();
+    let mut framing = BlockFraming::new();
+    loop {
+        let idle_timeout = state.config.borrow().limits.idle_timeout();
+        let idle_deadline = last_activity + idle_timeout.unwrap_or_default();
+        // What was read before another branch won is still in `line`, only the rest of the limit is left
+        let mut limited = (&mut reader).take(MAX_LINE_BYTES.saturating_sub(line.len()) as u64);
+        tokio::select! {
+            // A) Incoming from client
+            result = limited.read_line(&mut line) => {
+                match result {
+                    Ok(_) if too_long(&line) => {
+                        eprintln!("[WARN] {} sent a line over {} bytes, dropping the connection", client.username, MAX_LINE_BYTES);
+                        out.send(&ChatMessage::error(ErrorKind::MessageTooLong, line_too_long()));
+                        farewell = "was dropped for sending too much";
+                        break;
+                    }
+                    Ok(0) => break, // client disconnected
+                    Ok(_) => {
+                        tracer.record(Direction::Received, &peer, &line);
+                        // Any line at all shows the connection is alive, the client's own pings included
+                        last_activity = tokio::time::Instant::now();
+                        // But checking the line or saying how far we've read isn't someone being there (for /whois)
+                        if !line.starts_with("/ping ") && !line.starts_with("/read ") {
+                            state.presence.lock().await.touch(client.id);
+                        }
+                        // Lines inside a ``` block are held back until the block is complete
+                        let block = match framing.feed(line.trim_end_matches(['\r', '\n'])) {
+                            Framed::Single => None,
+                            Framed::Block(block) => Some(block),
+                            Framed::Pending => {
+                                line.clear();
+                                continue;
+                            }
+                            Framed::TooLong => {
+                                let reason = format!("Code block too long (at most {} lines, {} KiB), it was not sent", MAX_BLOCK_LINES, MAX_BLOCK_BYTES / 1024);
+                                out.send(&ChatMessage::error(ErrorKind::MessageTooLong, reason));
+                                line.clear();
+                                continue;
+                            }
+                        };
+                        // Everyone sees the same content, whatever platform it was typed on
+                        let content = match normalize_content(block.as_deref().unwrap_or(line.trim())) {
+                            Ok(content) => content,
+                            Err(reason) => {
+                                out.send(&ChatMessage::error(ErrorKind::InvalidContent, reason));
+                                line.clear();
+                                continue;
+                            }
+                        };
+                        let trimmed = content.as_str();
+                        if let Some(command) = Command::parse(trimmed) {
+                            // Server commands are answered privately and never reach the history
+                            let replies = match command {
+                                Ok(command) => match state.plugins.on_command(&client.info(), &command) {
+                                    Ok(()) => {
+                                        let (name_before, verified_before) = (client.username.clone(), client.verified);
+                                        let mut replies = run_command(command, &mut client, &state, &tx).await;
+                                        state.presence.lock().await.rename(presence_id, &client.username, client.verified, client.role);
+                                        // Signed in as someone else: our whisper key goes with us to the new name
+                                        if client.username != name_before {
+                                            announce_key(&state, &name_before).await;
+                                            announce_key(&state, &client.username).await;
+                                        }
+                                        // A new name (or the password proving this one) may change what our signature counts for
+                                        if client.username != name_before || client.verified != verified_before {
+                                            replies.extend(register_signing_key(&state, &client).await);
+                                        }
+                                        replies
+                                    }
+                                    Err(refusal) => vec![*refusal],
+                                },
+                                Err(usage) => vec![ChatMessage::error(ErrorKind::CommandFailed, usage)],
+                            };
+                            for reply in replies {
+                                out.send(&reply);
+                            }
+                        } else if !trimmed.is_empty() {
+                            // A /sign just before is for this message and no other
+                            let signature = client.signature.take();
+                            // "/reply 12 sounds good" is too, quoting #12
+                            let (body, reply_to) = match parse_reply(trimmed) {
+                                Some(Ok((id, text))) => (text, Some(id)),
+                                Some(Err(usage)) => {
+                                    out.send(&ChatMessage::error(ErrorKind::CommandFailed, usage));
+                                    line.clear();
+                                    continue;
+                                }
+                                None => (trimmed, None),
+                            };
+                            // "/me waves" is posted like any other message, as an action
+                            let (text, message_type) = match parse_action(body) {
+                                Some(Ok(action)) => (action, MessageType::Action),
+                                Some(Err(usage)) => {
+                                    out.send(&ChatMessage::error(ErrorKind::CommandFailed, usage));
+                                    line.clear();
+                                    continue;
+                                }
+                                None => (body, MessageType::UserMessage),
+                            };
+                            // Guests may have to sign in before they can talk
+                            if let Some(refusal) = client.guest_refusal(&state) {
+                                out.send(&refusal);
+                                line.clear();
+                                continue;
+                            }
+                            // Muted people talk to nobody until their time is up
+                            if let Some(left) = muted_for(&state, &client.username).await {
+                                let reason = format!("You are muted for another {}", format_duration(left));
+                                out.send(&ChatMessage::error(ErrorKind::Muted, reason).with_retry_after(left.as_secs().max(1)));
+                                line.clear();
+                                continue;
+                            }
+                            // Announcement rooms can be read by all but only announcers post there
+                            if state.rooms.lock().await.is_read_only(&client.room) && !client.is_announcer(&state) {
+                                let reason = format!("#{} is read-only, only announcers can post there", client.room);
+                                out.send(&ChatMessage::error(ErrorKind::ReadOnlyRoom, reason).in_room(client.room.as_str()));
+                                line.clear();
+                                continue;
+                            }
+                            // Plugins (the word filter among them) get their say before anything is stored or sent
+                            let mut text = text.to_string();
+                            if let Err(refusal) = state.plugins.on_message(&client.info(), &mut text) {
+                                out.send(&refusal);
+                                line.clear();
+                                continue;
+                            }
+                            let mut msg = ChatMessage::new(client.username.clone(), text, message_type).in_room(client.room.as_str());
+                            msg.verified = client.verified;
+                            msg.bad_signature = bad_signature(&state, &client, signature, trimmed).await;
+                            if let Some(id) = reply_to {
+                                match quote_of(&state, &client.room, id).await {
+                                    Ok(quote) => msg.quote = Some(quote),
+                                    Err(refusal) => {
+                                        out.send(&refusal);
+                                        line.clear();
+                                        continue;
+                                    }
+                                }
+                            }
+                            let posted = post_message(&state, &client.room, msg).await;
+                            state.plugins.on_broadcast(&posted);
+                        }
+                        line.clear();
+                    }
+                    Err(e) => {
+                        eprintln!("[ERROR] failed to read from {}: {}", client.username, e);
+                        break;
+                    }
+                }
+            }
+
+            // B) Incoming server-wide broadcast to send to this client
+            result = rx.recv() => {
+                match result {
+                    Ok(msg) => out.forward(msg),
+
+                    //broadcasts errors 
+                    Err(broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        eprintln!("[WARN] {} missed {} messages", client.username, n);
+                        report(&state, OpsEvent::Lag, format!("{} fell behind and missed {} server-wide frames", client.username, n));
+                        continue;
+                    }
+                }
+            }
+
+            // B continued.... Frames from the rooms this client is in
+            Some((room, result)) = client.rooms.next() => {
+                match result {
+                    Ok(msg) => out.forward(msg),
+                    Err(BroadcastStreamRecvError::Lagged(n)) => {
+                        eprintln!("[WARN] {} missed {} messages in #{}", client.username, n, room);
+                        state.rooms.lock().await.record_lag(&room, n);
+                        // A moderator falling behind in #server-ops would only add to what they're behind on
+                        if room != OPS_ROOM {
+                            report(&state, OpsEvent::Lag, format!("{} fell behind in #{} and missed {} frames", client.username, room, n));
+                        }
+                    }
+                }
+            }
+
+            // C) Silent for too long: drop the connection so it doesn't linger as a ghost
+            _ = tokio::time::sleep_until(idle_deadline), if idle_timeout.is_some() => {
+                let minutes = state.config.borrow().limits.idle_timeout_minutes;
+                let notice = format!("You timed out after {} minutes without activity", minutes);
+                let mut frame = ChatMessage::new("System", notice, MessageType::SystemNotification);
+                frame.error = Some(ErrorKind::IdleTimeout); // tells the client not to reconnect on its own
+                out.send(&frame);
+                farewell = "timed out";
+                break;
+            }
+
+            // D) Instructions from the rest of the server
+            Some(instruction) = control.recv() => {
+                match instruction {
+                    Control::Send(frame) => out.send(&frame),
+                    Control::Kick { reason } => {
+                        out.send(&ChatMessage::error(ErrorKind::Kicked, reason));
+                        farewell = "was kicked";
+                        break;
+                    }
+                    Control::Ban => {
+                        out.send(&ChatMessage::error(ErrorKind::Banned, "You are banned from this server"));
+                        farewell = "was banned";
+                        break;
+                    }
+                    Control::Drop => {
+                        farewell = "lost their connection";
+                        dropped = true;
+                        break;
+                    }
+                    Control::RoomMode { room } => {
+                        if client.rooms.contains_key(&room) {
+                            out.send(&room_mode_frame(&state, &client, &room).await);
+                        }
+                    }
+                    Control::Role { role } => {
+                        client.role = role;
+                        state.presence.lock().await.rename(presence_id, &client.username, client.verified, role);
+                        // Announcing may have come or gone with it
+                        if state.rooms.lock().await.is_read_only(&client.room) {
+                            out.send(&room_mode_frame(&state, &client, &client.room).await);
+                        }
+                        // And #server-ops is only for those who still moderate
+                        if client.rooms.contains_key(OPS_ROOM) && !client.can(&state, Permission::Moderate) {
+                            for reply in run_command(Command::Leave { room: Some(OPS_ROOM.to_string()) }, &mut client, &state, &tx).await {
+                                out.send(&reply);
+                            }
+                        }
+                    }
+                }
+            }
+
+            // E) The server is going down: say goodbye and close the socket cleanly
+            _ = async { closing.wait_for(|closing| *closing).await.is_ok() } => {
+                out.send(&ChatMessage::new("System", "Server is shutting down...", MessageType::SystemNotification));
+                shutting_down = true;
+                break;
+            }
+
+            // F) The writer task stopped, the client can't be written to any more
+            _ = out.closed() => {
+                eprintln!("[ERROR] writing to {} failed, dropping the connection", client.username);
+                break;
+            }
+        }
+    }
+    // Whatever is still queued (a kick's reason, the goodbye) goes out before the socket closes
+    if dropped {
+        out.abort();
+    } else {
+        out.close().await;
+    }
+    if shutting_down {
+        leave_all_rooms(&state, &client).await;
+        remember_last_seen(&state, &client, "left as the server shut down").await;
+        state.plugins.on_disconnect(&client.info(), "left as the server shut down");
+        state.presence.lock().await.unregister(presence_id);
+        return; // nobody left to tell about it
+    }
+
+    // 4. Announce departure
+    leave_all_rooms(&state, &client).await;
+    remember_last_seen(&state, &client, farewell).await;
+    state.presence.lock().await.unregister(presence_id);
+    announce_key(&state, &client.username).await;
+    let leave_msg = ChatMessage::new(client.username.clone(), farewell, MessageType::SystemNotification);
+    let leave_json = match serde_json::to_string(&leave_msg) {
+        Ok(j) => j,
+        Err(e) => {
+            eprintln!("[ERROR] failed to serialize leave message: {}", e);
+            return;
+        }
+    };
+    try_send(&tx, leave_json);
+    state.plugins.on_disconnect(&client.info(), farewell);
+}
+
+
+
+// Tells everyone the whisper key `username` has now, empty when it has none (any more)
+
+async fn announce_key(state: &ServerState, username: &str) {
+    let presence = state.presence.lock().await;
+    let key = presence.public_key(username).unwrap_or_default();
+    presence.send_to_all(Control::Send(Box::new(ChatMessage::key(username, key))));
+}
+
+// An X25519 public key as clients send it: 32 bytes in base64
+fn is_public_key(key: &str) -> bool {
+    BASE64.decode(key).is_ok_and(|bytes| bytes.len() == 32)
+}
+
+
+// Records the client's signing key for its name. The first key used under a name is the one it signs with,
+// and an account also takes on any key it signs in with, its password vouches for it. A client whose
+// key isn't on record for its name is told its messages will be flagged.
+
+async fn register_signing_key(state: &ServerState, client: &Client) -> Option<ChatMessage> {
+    let store = state.store.lock().await;
+    let keys = match store.signing_keys(&client.username) {
+        Ok(keys) => keys,
+        Err(e) => {
+            report_error(state, format!("loading the signing keys of {} failed: {}", client.username, e));
+            return None;
+        }
+    };
+    let key = client.signing_key.as_ref();
+    if key.is_some_and(|key| keys.contains(key)) || (keys.is_empty() && key.is_none()) {
+        return None;
+    }
+    if let Some(key) = key.filter(|_| keys.is_empty() || client.verified) {
+        if let Err(e) = store.add_signing_key(&client.username, key) {
+            report_error(state, format!("saving the signing key of {} failed: {}", client.username, e));
+        }
+        return None;
+    }
+    let notice = format!(
+        "Messages from {} are signed with another key here, yours will be flagged until you sign in to the account",
+        client.username
+    );
+    Some(ChatMessage::new("System", notice, MessageType::SystemNotification))
+}
+
+// Whether a message needs flagging: its sender's name has signing keys on record and `content`, in the room the
+// client talks in, wasn't signed (with `signature`) by the one of them this client holds, or the signature's stamp
+// is stale or no higher than the last one that key signed with
+async fn bad_signature(state: &ServerState, client: &Client, signature: Option<(u64, String)>, content: &str) -> bool {
+    let keys = match state.store.lock().await.signing_keys(&client.username) {
+        Ok(keys) => keys,
+        Err(e) => {
+            report_error(state, format!("loading the signing keys of {} failed: {}", client.username, e));
+            return false;
+        }
+    };
+    if keys.is_empty() {
+        return false;
+    }
+    let Some(key) = client.signing_key.as_ref().filter(|key| keys.contains(key)) else { return true };
+    let Some((stamp, signature)) = signature else { return true };
+    let Some(signature) = BASE64.decode(signature).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) else { return true };
+    let Some(verifying) = verifying_key(key) else { return true };
+    if verifying.verify_strict(signed_text(&client.room, stamp, content).as_bytes(), &signature).is_err() {
+        return true;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+    if now.abs_diff(stamp) > SIGNATURE_WINDOW.as_millis() as u64 {
+        return true;
+    }
+    let mut stamps = state.signature_stamps.lock().await;
+    let last = stamps.entry(key.clone()).or_default();
+    if stamp <= *last {
+        return true;
+    }
+    *last = stamp;
+    false
+}
+
+// An ed25519 public key as clients send it: 32 bytes in base64
+fn verifying_key(key: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = BASE64.decode(key).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+
+// Keeps when an account left and how, for /seen and /whois once it's offline
+
+async fn remember_last_seen(state: &ServerState, client: &Client, farewell: &str) {
+    if !client.verified {
+        return;
+    }
+    if let Err(e) = state.store.lock().await.set_last_seen(&client.username, farewell) {
+        report_error(state, format!("saving when {} was last seen failed: {}", client.username, e));
+    }
+}
+
+
+// Stores a chat message, adds it to the room's history and sends it to everyone in the room
+// Hands back the message as it went out (with its id)
+
+async fn post_message(state: &ServerState, room: &str, mut msg: ChatMessage) -> ChatMessage {
+    // Store it first, which gives it an id
+    match state.store.lock().await.save_message(room, &msg) {
+        Ok(id) => msg.id = Some(id),
+        Err(e) => eprintln!("[ERROR] failed to store message from {}: {}", msg.username, e),
+    }
+    let json = match serde_json::to_string(&msg) {
+        Ok(j) => j,
+        Err(e) => {
+            eprintln!("[ERROR] failed to serialize message: {}", e);
+            return msg;
+        }
+    };
+    // Add to the room's history so it remains dynamic, here and on the rest of the cluster
+    let size = state.config.borrow().history.size;
+    if let Some(cluster) = &state.cluster {
+        cluster.publish(room, &msg, size);
+    }
+    let mut rooms = state.rooms.lock().await;
+    rooms.push_history(room, msg.clone(), size);
+    rooms.send(room, json);
+    drop(rooms);
+    state.messages.fetch_add(1, Ordering::Relaxed);
+    msg
+}
+
+
+// What a reply to message `id` quotes, which has to be in the room the reply goes to
+// Long messages are cut short, and quoting a reply quotes only what it said
+
+async fn quote_of(state: &ServerState, room: &str, id: i64) -> Result<Quote, ChatMessage> {
+    let found = state.store.lock().await.message(id);
+    match found {
+        Ok(Some(msg)) if msg.room.as_deref() == Some(room) => {
+            let mut content: String = msg.content.chars().take(MAX_QUOTE_LEN).collect();
+            if content.len() < msg.content.len() {
+                content.push('…');
+            }
+            Ok(Quote { id, username: msg.username, content })
+        }
+        Ok(_) => Err(ChatMessage::error(ErrorKind::CommandFailed, format!("There is no message #{} here to reply to", id))),
+        Err(e) => {
+            report_error(state, format!("looking up message #{} failed: {}", id, e));
+            Err(ChatMessage::error(ErrorKind::CommandFailed, "Replying failed, try again later"))
+        }
+    }
+}
+
+
+// Posts what scripts send, as the script, to rooms that are open (a bot can't open a room on its own)
+// Script messages don't go through the plugins, so bots can't set each other off
+
+#[cfg(feature = "scripting")]
+async fn relay_script_messages(state: Arc<ServerState>, mut outbox: mpsc::UnboundedReceiver<ScriptMessage>) {
+    while let Some(out) = outbox.recv().await {
+        let room = match Rooms::normalize(&out.room) {
+            Ok(room) if state.rooms.lock().await.exists(&room) => room,
+            _ => {
+                eprintln!("[WARN] script {} sent to #{}, which nobody is in", out.bot, out.room);
+                continue;
+            }
+        };
+        let text = match normalize_content(&out.text) {
+            Ok(text) if !text.is_empty() => text,
+            Ok(_) => continue,
+            Err(reason) => {
+                eprintln!("[WARN] script {} sent to #{}: {}", out.bot, room, reason);
+                continue;
+            }
+        };
+        let msg = ChatMessage::new(out.bot, text, MessageType::UserMessage).in_room(room.as_str());
+        let posted = post_message(&state, &room, msg).await;
+        state.plugins.on_broadcast(&posted);
+    }
+}
+
+
+// Posts what came in on the webhook endpoint, to rooms that are open like script messages
+
+async fn relay_webhook_posts(state: Arc<ServerState>, mut posts: mpsc::Receiver<WebhookPost>) {
+    while let Some(post) = posts.recv().await {
+        let room = match Rooms::normalize(&post.room) {
+            Ok(room) if state.rooms.lock().await.exists(&room) => room,
+            _ => {
+                let _ = post.done.send(Err((404, format!("#{} isn't open", post.room))));
+                continue;
+            }
+        };
+        let posted = post_bridged(&state, &room, post.username, post.text, MessageType::UserMessage, post.from).await;
+        let _ = post.done.send(posted.map_err(|reason| (403, reason)));
+    }
+}
+
+
+// Posts chat that came over a bridge (a webhook, Matrix) in `room`, held to what clients are: nothing goes in a
+// read-only room, and the plugins (the word filter, scripts) get their say first. Only on_broadcast is skipped,
+// or the bridges would send it right back where it came from
+
+async fn post_bridged(
+    state: &ServerState,
+    room: &str,
+    username: String,
+    text: String,
+    message_type: MessageType,
+    from: SocketAddr,
+) -> Result<(), String> {
+    if state.rooms.lock().await.is_read_only(room) {
+        return Err(format!("#{} is read-only, only announcers can post there", room));
+    }
+    let mut text = text;
+    let info = ClientInfo { username: &username, addr: from, room, verified: false };
+    if let Err(refusal) = state.plugins.on_message(&info, &mut text) {
+        return Err(refusal.content);
+    }
+    let msg = ChatMessage::new(username, text, message_type).in_room(room);
+    post_message(state, room, msg).await;
+    Ok(())
+}
+
+
+// Answers scrapes of the metrics endpoint with the same numbers /stats and the console's rooms command show
+
+async fn answer_metrics_scrapes(state: Arc<ServerState>, mut scrapes: mpsc::Receiver<Scrape>) {
+    while let Some(scrape) = scrapes.recv().await {
+        let snapshot = Snapshot {
+            online: state.presence.lock().await.len(),
+            messages: state.messages.load(Ordering::Relaxed),
+            rooms: state.rooms.lock().await.stats(),
+        };
+        let _ = scrape.done.send(snapshot);
+    }
+}
+
+
+// Posts what's said on the Matrix side in the room it's mirrored with, opening it (with its history) if need be
+// Checked like webhook posts, and like them not passed to on_broadcast, so the bridge doesn't send them back
+
+async fn relay_matrix_messages(state: Arc<ServerState>, room: String, mut messages: mpsc::UnboundedReceiver<MatrixMessage>) {
+    while let Some(message) = messages.recv().await {
+        let text = match normalize_content(&message.text) {
+            Ok(text) if !text.is_empty() => text,
+            _ => continue,
+        };
+        if !state.rooms.lock().await.exists(&room) {
+            let history = load_history(&state, &room).await;
+            state.rooms.lock().await.open(&room, history);
+        }
+        let message_type = if message.action { MessageType::Action } else { MessageType::UserMessage };
+        // The bridge runs here, so it comes from this machine as far as the plugins can tell
+        let username = message.username.clone();
+        if let Err(reason) = post_bridged(&state, &room, message.username, text, message_type, LOCAL_PEER).await {
+            println!("└─[{}] Dropped {} from Matrix: {}", Local::now().format("%D:%H:%M:%S"), username, reason);
+        }
+    }
+}
+
+
+// Keeps a link with a server that dialed us up until it drops, when it's a peer and has the right token
+
+async fn serve_link<R, W>(state: &ServerState, name: &str, token: &str, reader: R, mut writer: W, peer: &str)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Some(federation) = state.federation.clone().filter(|federation| federation.admits(name, token)) else {
+        println!("└─[{}] {} ({}) tried to link up without being a peer", Local::now().format("%D:%H:%M:%S"), name, peer);
+        let refusal = ChatMessage::error(ErrorKind::AuthFailed, "Not a peer of this server, or the wrong link token");
+        let _ = writer.write_all(format!("{}\n", serde_json::to_string(&refusal).unwrap_or_default()).as_bytes()).await;
+        return;
+    };
+    if let Err(e) = federation.serve(name, reader, writer, true).await {
+        println!("🔗 Link with {} closed: {}", name, e);
+    }
+}
+
+
+// Posts what the linked servers relay in the shared rooms, opening them (with their history) if need be
+// They do go through the plugins, so webhooks and bridges see them, the federation itself doesn't pass them on
+
+async fn relay_federated_messages(state: Arc<ServerState>, mut arrivals: Arrivals) {
+    while let Some(mut msg) = arrivals.recv().await {
+        let Some(room) = msg.room.as_deref().and_then(|room| Rooms::normalize(room).ok()) else { continue };
+        msg.content = match normalize_content(&msg.content) {
+            Ok(text) if !text.is_empty() => text,
+            _ => continue,
+        };
+        if !state.rooms.lock().await.exists(&room) {
+            let history = load_history(&state, &room).await;
+            state.rooms.lock().await.open(&room, history);
+        }
+        let posted = post_message(&state, &room, msg.in_room(room.as_str())).await;
+        state.plugins.on_broadcast(&posted);
+    }
+}
+
+
+// Posts what was said on the other processes of the cluster, in rooms that are open here (they load it with
+// their history otherwise). It's been stored and through the plugins where it was said, so it only goes out
+
+async fn relay_cluster_messages(state: Arc<ServerState>, mut remote: Remote) {
+    while let Some((room, msg)) = remote.recv().await {
+        let size = state.config.borrow().history.size;
+        if state.rooms.lock().await.post(&room, msg, size) {
+            state.messages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+
+// Tells the rest of the cluster who's on this process whenever that changes
+
+async fn share_online(state: Arc<ServerState>) {
+    let Some(cluster) = &state.cluster else { return };
+    let (mut count, mut roster) = {
+        let presence = state.presence.lock().await;
+        (presence.watch_count(), presence.watch_roster())
+    };
+    loop {
+        let connections = *count.borrow_and_update();
+        let members = roster.borrow_and_update().clone();
+        cluster.set_online(Online { connections, members });
+        tokio::select! {
+            changed = count.changed() => if changed.is_err() { return },
+            changed = roster.changed() => if changed.is_err() { return },
+        }
+    }
+}
+
+
+// A room's recent history, from Redis in a cluster and from the database otherwise
+
+async fn load_history(state: &ServerState, room: &str) -> Vec<ChatMessage> {
+    let size = state.config.borrow().history.size;
+    let recent = match &state.cluster {
+        Some(cluster) => cluster.history(room, size).await,
+        None => state.store.lock().await.recent_messages(room, size).map_err(|e| e.to_string()),
+    };
+    recent.unwrap_or_else(|e| {
+        report_error(state, format!("loading history of #{} failed: {}", room, e));
+        Vec::new()
+    })
+}
+
+
+// Tells the moderators in #server-ops about something, from anywhere (even inside a lock or a closure)
+
+fn report(state: &ServerState, event: OpsEvent, text: impl Into<String>) {
+    let _ = state.ops.send(ChatMessage::ops_event(event, text));
+}
+
+
+// Logs a failure on the server's side and reports it in #server-ops
+
+fn report_error(state: &ServerState, text: String) {
+    eprintln!("[ERROR] {}", text);
+    report(state, OpsEvent::Error, text);
+}
+
+
+// Posts reported events to #server-ops, which is always open so its history waits for the next moderator
+// Events are kept in memory only, they aren't chat and the console's own log has them anyway
+
+async fn relay_ops_events(state: Arc<ServerState>, mut events: mpsc::UnboundedReceiver<ChatMessage>) {
+    while let Some(event) = events.recv().await {
+        let Ok(json) = serde_json::to_string(&event) else { continue };
+        let size = state.config.borrow().history.size;
+        let mut rooms = state.rooms.lock().await;
+        rooms.push_history(OPS_ROOM, event, size);
+        rooms.send(OPS_ROOM, json);
+    }
+}
+
+
+// Puts the client in a room and makes it the one plain messages go to
+// A room the client is already in is just switched to. Joins typed with /join are rate limited
+// and announced to the room, the ones made while connecting are not.
+// Returns the RoomJoined frame for the client, or the error explaining why it was refused
+
+async fn join_room(
+    state: &ServerState,
+    client: &mut Client,
+    room: &str,
+    typed: bool,
+    password: Option<&str>,
+) -> Result<ChatMessage, ChatMessage> {
+    if client.rooms.contains_key(room) {
+        client.room = room.to_string();
+        return Ok(room_joined_frame(state, client, room).await);
+    }
+    if room == OPS_ROOM && !client.can(state, Permission::Moderate) {
+        return Err(ChatMessage::error(ErrorKind::CommandFailed, format!("#{} is for moderators only", OPS_ROOM)).in_room(room));
+    }
+
+    // Every room is one more fan-out to feed, so nobody gets to sit in hundreds of them
+    let max_rooms = state.config.borrow().rooms.max_per_user.max(1); // there's always the lobby
+    if client.rooms.len() >= max_rooms {
+        let reason = format!("You can be in at most {} rooms, /leave one first", max_rooms);
+        return Err(ChatMessage::error(ErrorKind::TooManyRooms, reason).in_room(room));
+    }
+    if typed {
+        let check = state.joins.lock().await.check(&format!("join:{}", client.username), Instant::now());
+        if let Err(cooldown) = check {
+            let seconds = cooldown.as_secs().max(1);
+            println!("└─[{}] {} is joining rooms too fast, cooling down for {}s", Local::now().format("%D:%H:%M:%S"), client.username, seconds);
+            report(state, OpsEvent::RateLimit, format!("{} is joining rooms too fast, held off for {}s", client.username, seconds));
+            let reason = format!("You are joining rooms too fast, try again in {} seconds", seconds);
+            return Err(ChatMessage::error(ErrorKind::JoinRateLimited, reason).in_room(room).with_retry_after(seconds));
+        }
+    }
+    // Checked after the rate limit, so guessing passwords is as slow as joining
+    if state.rooms.lock().await.is_private(room) && !client.can(state, Permission::Moderate) {
+        check_private_room(state, client, room, password).await.map_err(|refused| refused.in_room(room))?;
+    }
+
+    // A room nobody is in gets its recent history back
+    let history = if state.rooms.lock().await.exists(room) {
+        Vec::new()
+    } else {
+        load_history(state, room).await
+    };
+    if typed {
+        room_notice(state, room, &client.username, format!("joined #{}", room)).await;
+    }
+    let subscription = {
+        let mut rooms = state.rooms.lock().await;
+        rooms.open(room, history);
+        rooms.join(room, client.id)
+    };
+    if let Some(rx) = subscription {
+        client.rooms.insert(room.to_string(), BroadcastStream::new(rx));
+    }
+    client.room = room.to_string();
+    Ok(room_joined_frame(state, client, room).await)
+}
+
+
+// Whether the client may come into private `room`: signed in to an account that was let in, or with the password
+// An account that gets in with the password is let in for good, so reconnecting doesn't ask again
+
+async fn check_private_room(state: &ServerState, client: &Client, room: &str, password: Option<&str>) -> Result<(), ChatMessage> {
+    let failed = |e: rusqlite::Error| {
+        report_error(state, format!("checking who may join #{} failed: {}", room, e));
+        ChatMessage::error(ErrorKind::CommandFailed, "Joining failed, try again later")
+    };
+    let (invited, hash) = {
+        let store = state.store.lock().await;
+        let invited = client.verified && store.has_room_access(room, &client.username).map_err(failed)?;
+        (invited, store.room_password(room).map_err(failed)?.flatten())
+    };
+    if invited {
+        return Ok(());
+    }
+    let (Some(password), Some(hash)) = (password, hash.clone()) else {
+        let reason = match hash {
+            Some(_) => format!("#{} is private, join it with an invite or /join {} <password>", room, room),
+            None => format!("#{} is private, you need an invite", room),
+        };
+        return Err(ChatMessage::error(ErrorKind::PrivateRoom, reason));
+    };
+    if !accounts::verify_password(password.to_string(), hash).await {
+        return Err(ChatMessage::error(ErrorKind::PrivateRoom, format!("Wrong password for #{}", room)));
+    }
+    if client.verified {
+        state.store.lock().await.grant_room_access(room, &client.username, &client.username).map_err(failed)?;
+    }
+    Ok(())
+}
+
+
+// Tells the client it now talks in `room`, what the room is about and whether it can post there at all
+
+async fn room_joined_frame(state: &ServerState, client: &Client, room: &str) -> ChatMessage {
+    let mut frame = ChatMessage::new("System", format!("You are talking in #{}", room), MessageType::RoomJoined).in_room(room);
+    let rooms = state.rooms.lock().await;
+    frame.topic = Some(rooms.topic(room).unwrap_or_default().to_string());
+    frame.read_only = rooms.is_read_only(room) && !client.is_announcer(state);
+    drop(rooms);
+    // Where the account left off, so its client can tell what's new
+    if client.verified {
+        frame.last_read = state.store.lock().await.read_mark(&client.username, room).unwrap_or_else(|e| {
+            report_error(state, format!("loading where {} left off in #{} failed: {}", client.username, room, e));
+            None
+        });
+    }
+    frame
+}
+
+
+// Tells a member of `room` who can post there now
+
+async fn room_mode_frame(state: &ServerState, client: &Client, room: &str) -> ChatMessage {
+    let read_only = state.rooms.lock().await.is_read_only(room);
+    let content = match (read_only, client.is_announcer(state)) {
+        (false, _) => format!("#{} is open, everyone can post", room),
+        (true, false) => format!("#{} is read-only, only announcers can post", room),
+        (true, true) => format!("#{} is read-only, you can post as an announcer", room),
+    };
+    let mut frame = ChatMessage::new("System", content, MessageType::RoomMode).in_room(room);
+    frame.read_only = read_only && !client.is_announcer(state);
+    frame
+}
+
+
+// Takes the client out of every room it is in, closing the ones left empty
+
+async fn leave_all_rooms(state: &ServerState, client: &Client) {
+    let mut rooms = state.rooms.lock().await;
+    for room in client.rooms.keys() {
+        rooms.leave(room, client.id);
+    }
+}
+
+
+// The pinned messages of `room`, sent on join and to the whole room when they change
+
+async fn pins_frame(state: &ServerState, room: &str) -> ChatMessage {
+    let pins = state.store.lock().await.pins(room).unwrap_or_else(|e| {
+        report_error(state, format!("loading the pins of #{} failed: {}", room, e));
+        Vec::new()
+    });
+    ChatMessage::pins(room, pins)
+}
+
+async fn send_pins(state: &ServerState, room: &str) {
+    let frame = pins_frame(state, room).await;
+    if let Ok(json) = serde_json::to_string(&frame) {
+        state.rooms.lock().await.send(room, json);
+    }
+}
+
+
+// Sends a notice about `username` to everyone in a room
+
+async fn room_notice(state: &ServerState, room: &str, username: &str, text: String) {
+    let notice = ChatMessage::new(username, text, MessageType::SystemNotification).in_room(room);
+    if let Ok(json) = serde_json::to_string(&notice) {
+        state.rooms.lock().await.send(room, json);
+    }
+}
+
+
+
+// Reads operator commands from the terminal the server runs in
+// stdin is read on a plain thread: tokio's stdin would keep the runtime from shutting down
+// while it waits for a line
+
+async fn run_console(state: Arc<ServerState>, tx: broadcast::Sender<String>) {
+    let (line_tx, mut lines) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(line) = lines.recv().await {
+        match AdminCommand::parse(&line) {
+            Ok(command) => run_admin_command(command, &state, &tx).await,
+            Err(e) if e.is_empty() => {}
+            Err(e) => println!("⚠️  {}", e),
+        }
+    }
+}
+
+
+async fn run_admin_command(command: AdminCommand, state: &Arc<ServerState>, tx: &broadcast::Sender<String>) {
+    let broadcast_notice = |text: String| {
+        let notice = ChatMessage::new("Server", text, MessageType::SystemNotification);
+        if let Ok(json) = serde_json::to_string(&notice) {
+            let _ = tx.send(json);
+        }
+    };
+
+    match command {
+        AdminCommand::Help => println!("{}", console::HELP),
+        AdminCommand::List => {
+            let presence = state.presence.lock().await;
+            println!("┌─ {} connected", presence.len());
+            for client in presence.clients() {
+                println!("├─ {} ({}) for {}", client.username, client.addr, format_duration(client.connected_at.elapsed()));
+            }
+            println!("└─");
+        }
+        AdminCommand::Kick { username, reason } => {
+            let notice = match &reason {
+                Some(reason) => format!("You were kicked by the server operator: {}", reason),
+                None => "You were kicked by the server operator".to_string(),
+            };
+            let kicked = state.presence.lock().await.send_to_user(&username, Control::Kick { reason: notice });
+            match kicked {
+                0 => println!("⚠️  {} is not connected", username),
+                n => {
+                    println!("👢 Kicked {} ({} connection{})", username, n, if n == 1 { "" } else { "s" });
+                    audit(state, CONSOLE, "kick", &username, reason.as_deref()).await;
+                }
+            }
+        }
+        AdminCommand::Names { account } => match state.store.lock().await.nicknames(&account) {
+            Ok(names) if names.is_empty() => println!("📇 No other names on record for {}", account),
+            Ok(names) => {
+                println!("┌─ Names {} went by before signing in", account);
+                for (i, (name, last_seen)) in names.iter().enumerate() {
+                    let branch = if i + 1 == names.len() { "└─" } else { "├─" };
+                    let last_seen = chrono::DateTime::parse_from_rfc3339(last_seen)
+                        .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|_| last_seen.clone());
+                    println!("{} {} (last {})", branch, name, last_seen);
+                }
+            }
+            Err(e) => eprintln!("[ERROR] nickname lookup for {} failed: {}", account, e),
+        },
+        AdminCommand::ResetPassword { username } => reset_password(state, &username).await,
+        AdminCommand::Ban { ip, reason } => {
+            if let Err(e) = state.store.lock().await.add_ban(ip) {
+                report_error(state, format!("storing ban for {} failed: {}", ip, e));
+            }
+            state.bans.lock().await.insert(ip);
+            let kicked = state.presence.lock().await.send_to_ip(ip, Control::Ban);
+            println!("🚫 Banned {} ({} connection{} dropped)", ip, kicked, if kicked == 1 { "" } else { "s" });
+            audit(state, CONSOLE, "ban", &ip.to_string(), reason.as_deref()).await;
+            report(state, OpsEvent::Ban, format!("{} was banned from the console ({} connection{} dropped)", ip, kicked, if kicked == 1 { "" } else { "s" }));
+        }
+        AdminCommand::Unban { ip } => {
+            let removed = state.store.lock().await.remove_ban(ip).unwrap_or_else(|e| {
+                report_error(state, format!("removing ban for {} failed: {}", ip, e));
+                false
+            });
+            state.bans.lock().await.remove(&ip);
+            if removed {
+                println!("✅ Unbanned {}", ip);
+                audit(state, CONSOLE, "unban", &ip.to_string(), None).await;
+                report(state, OpsEvent::Ban, format!("{} was unbanned from the console", ip));
+            } else {
+                println!("⚠️  {} was not banned", ip);
+            }
+        }
+        AdminCommand::UnbanAccount { username } => {
+            let removed = state.store.lock().await.remove_account_ban(&username).unwrap_or_else(|e| {
+                report_error(state, format!("removing ban for account {} failed: {}", username, e));
+                false
+            });
+            state.banned_accounts.lock().await.remove(&username.to_lowercase());
+            if removed {
+                println!("✅ Unbanned account {}", username);
+                audit(state, CONSOLE, "unban", &username, None).await;
+                report(state, OpsEvent::Ban, format!("account {} was unbanned from the console", username));
+            } else {
+                println!("⚠️  {} is neither an IP address nor a banned account", username);
+            }
+        }
+        AdminCommand::AuditLog { target } => {
+            let lines = audit_lines(state, target.as_deref()).await;
+            match target {
+                Some(target) => println!("┌─ Moderation of {}, oldest first", target),
+                None => println!("┌─ Recent moderation, oldest first"),
+            }
+            for line in &lines {
+                println!("├─ {}", line);
+            }
+            println!("└─");
+        }
+        AdminCommand::Say { text } => broadcast_notice(text),
+        AdminCommand::Stats => {
+            println!("┌─ Server stats");
+            let lines = stats_lines(state, true).await;
+            for (i, line) in lines.iter().enumerate() {
+                println!("{} {}", if i + 1 == lines.len() { "└─" } else { "├─" }, line);
+            }
+        }
+        AdminCommand::Rooms => {
+            let stats = state.rooms.lock().await.stats();
+            println!("┌─ {} room{} open, busiest first (rates over the last {}s)", stats.len(), if stats.len() == 1 { "" } else { "s" }, RATE_WINDOW.as_secs());
+            for room in stats {
+                println!(
+                    "├─ #{}: {} member{}, {:.2} msg/s, {}/s out, {} lag event{} ({} frames missed)",
+                    room.name,
+                    room.members,
+                    if room.members == 1 { "" } else { "s" },
+                    room.messages_per_sec,
+                    format_bytes(room.bytes_per_sec),
+                    room.lag_events,
+                    if room.lag_events == 1 { "" } else { "s" },
+                    room.missed,
+                );
+            }
+            println!("└─");
+        }
+        AdminCommand::Shutdown { seconds } => {
+            state.shutdown.send_replace(Some(seconds));
+        }
+        AdminCommand::Reload => reload_config(state).await,
+        AdminCommand::FilterList => {
+            let filter = state.filter.filter();
+            println!("┌─ Filtered words (every room)");
+            for (word, action) in filter.rules() {
+                println!("├─ {}: {}", word, action);
+            }
+            println!("└─ Room overrides live in server.toml");
+        }
+        AdminCommand::FilterAdd { word, action } => {
+            println!("🧼 Filtering \"{}\": {} (until the next reload, add it to server.toml to keep it)", word, action);
+            state.filter.filter().set(&word, action);
+        }
+        AdminCommand::FilterRemove { word } => {
+            if state.filter.filter().remove(&word) {
+                println!("🧼 No longer filtering \"{}\"", word);
+            } else {
+                println!("⚠️  \"{}\" is not filtered", word);
+            }
+        }
+        AdminCommand::Motd { text: None } => match state.motd.lock().await.as_deref() {
+            Some(motd) => println!("📜 {}", motd),
+            None => println!("📜 No message of the day is set"),
+        },
+        AdminCommand::Motd { text: Some(text) } => {
+            set_motd(state, tx, text).await;
+            println!("📜 MOTD updated");
+        }
+        AdminCommand::Trace { enabled } => {
+            state.tracer.set_enabled(enabled);
+            if enabled {
+                println!("🔬 Wire trace on, writing to {}", state.tracer.path().display());
+            } else {
+                println!("🔬 Wire trace off");
+            }
+        }
+    }
+}
+
+
+// Gives an account a random password, turns off its two-factor sign-in, ends its sessions
+// and disconnects it wherever it is signed in
+// The new password is only shown here, for the operator to pass on
+
+async fn reset_password(state: &ServerState, username: &str) {
+    let account = match state.store.lock().await.account(username) {
+        Ok(Some((account, _))) => account,
+        Ok(None) => {
+            println!("⚠️  {} is not a registered account", username);
+            return;
+        }
+        Err(e) => {
+            report_error(state, format!("account lookup for {} failed: {}", username, e));
+            return;
+        }
+    };
+    let password = accounts::temporary_password();
+    let hash = match accounts::hash_password(password.clone()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            report_error(state, format!("hashing password for {} failed: {}", account, e));
+            return;
+        }
+    };
+    // A reset is how a lost authenticator gets recovered too, so two-factor goes off with it
+    let reset = {
+        let store = state.store.lock().await;
+        store
+            .set_password_hash(&account, &hash)
+            .and_then(|_| store.disable_two_factor(&account))
+            .and_then(|_| store.delete_sessions(&account))
+    };
+    let sessions = match reset {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            report_error(state, format!("resetting password for {} failed: {}", account, e));
+            return;
+        }
+    };
+    let kicked = state.presence.lock().await.send_to_user(&account, Control::Kick {
+        reason: "Your password was reset by the server operator".to_string(),
+    });
+    println!("└─[{}] operator reset the password of {} ({} session{} ended, {} connection{} dropped)",
+        Local::now().format("%D:%H:%M:%S"), account,
+        sessions, if sessions == 1 { "" } else { "s" },
+        kicked, if kicked == 1 { "" } else { "s" });
+    println!("🔑 New password for {}: {}", account, password);
+    audit(state, CONSOLE, "reset", &account, None).await;
+}
+
+
+// Appends to the moderation log; a failure is reported but doesn't undo what was done
+
+async fn audit(state: &ServerState, moderator: &str, action: &str, target: &str, detail: Option<&str>) {
+    if let Err(e) = state.store.lock().await.log_moderation(moderator, action, target, detail) {
+        report_error(state, format!("logging {} {} {} failed: {}", moderator, action, target, e));
+    }
+}
+
+
+// The moderation log as lines for /auditlog and the console, "2026-10-16 14:02 alice mute bob (10m 00s: spam)"
+
+async fn audit_lines(state: &ServerState, target: Option<&str>) -> Vec<String> {
+    let entries = state.store.lock().await.moderation_log(target, AUDIT_LOG_LINES).unwrap_or_else(|e| {
+        report_error(state, format!("reading the moderation log failed: {}", e));
+        Vec::new()
+    });
+    if entries.is_empty() {
+        return vec!["Nothing on record".to_string()];
+    }
+    entries.iter().map(audit_line).collect()
+}
+
+fn audit_line(entry: &ModerationEntry) -> String {
+    let at = DateTime::parse_from_rfc3339(&entry.created_at)
+        .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| entry.created_at.clone());
+    match &entry.detail {
+        Some(detail) => format!("{} {} {} {} ({})", at, entry.moderator, entry.action, entry.target, detail),
+        None => format!("{} {} {} {}", at, entry.moderator, entry.action, entry.target),
+    }
+}
+
+
+// Re-reads the config file and applies it without disconnecting anyone
+// A broken file is reported and the running config stays as it was
+
+async fn reload_config(state: &ServerState) {
+    let loaded = state.options.load_config().and_then(|config| {
+        let motd = config.load_motd()?;
+        Ok((config, motd))
+    });
+    let (config, motd) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("⚠️  Config not reloaded: {}", e);
+            return;
+        }
+    };
+    *state.motd.lock().await = motd;
+
+    state.limits.set_caps(config.limits.max_clients, config.limits.max_per_ip);
+    *state.filter.filter() = WordFilter::from_config(&config.filter);
+
+    // Shrinking the history drops the oldest messages, growing it fills up as people talk
+    state.rooms.lock().await.truncate_history(config.history.size);
+
+    // A new join limit starts everyone's count afresh
+    if config.rooms.joins_per_minute != state.config.borrow().rooms.joins_per_minute {
+        *state.joins.lock().await = ReconnectGuard::new(config.rooms.joins_per_minute, Duration::from_secs(60));
+    }
+
+    // Console bans live in the database, the rest come from the file
+    let stored = state.store.lock().await.bans().unwrap_or_else(|e| {
+        report_error(state, format!("loading bans failed: {}", e));
+        Vec::new()
+    });
+    let bans: HashSet<IpAddr> = stored.into_iter().chain(config.moderation.banned_ips.iter().copied()).collect();
+    let newly_banned: Vec<IpAddr> = bans.difference(&*state.bans.lock().await).copied().collect();
+    *state.bans.lock().await = bans;
+    {
+        let presence = state.presence.lock().await;
+        for ip in &newly_banned {
+            let kicked = presence.send_to_ip(*ip, Control::Ban);
+            report(state, OpsEvent::Ban, format!("{} was banned by the reloaded config ({} connection{} dropped)", ip, kicked, if kicked == 1 { "" } else { "s" }));
+        }
+    }
+    for ip in newly_banned {
+        audit(state, CONFIG, "ban", &ip.to_string(), None).await;
+    }
+
+    // Switching chaos off also ends whatever was injected
+    if !config.chaos.enabled && state.chaos.heal() {
+        println!("🧪 Chaos commands turned off, lag and partition ended");
+    }
+
+    #[cfg(feature = "scripting")]
+    state.scripts.reload();
+    state.webhooks.set_hooks(config.webhooks.outbound.clone());
+
+    state.config.send_replace(config);
+    println!("🔄 Configuration reloaded");
+}
+
+
+// 1536.0 -> "1.5 KB"
+
+fn format_bytes(bytes: f64) -> String {
+    match bytes {
+        b if b >= 1024.0 * 1024.0 => format!("{:.1} MB", b / (1024.0 * 1024.0)),
+        b if b >= 1024.0 => format!("{:.1} KB", b / 1024.0),
+        b => format!("{:.0} B", b),
+    }
+}
+
+
+// What /stats and the console's stats command show, one line each
+// Everyone gets the basics, `full` adds limits, per-room history and what the server holds in memory
+
+async fn stats_lines(state: &ServerState, full: bool) -> Vec<String> {
+    let rooms = state.rooms.lock().await.stats();
+    let online = state.presence.lock().await.len();
+    let mut lines = vec![
+        format!("Uptime: {}", format_duration(state.started.elapsed())),
+        format!("Online: {} user{} in {} room{}", online, if online == 1 { "" } else { "s" }, rooms.len(), if rooms.len() == 1 { "" } else { "s" }),
+        format!("Messages since start: {}", state.messages.load(Ordering::Relaxed)),
+    ];
+    if !full {
+        return lines;
+    }
+    lines.push(format!("Connections: {} of {} allowed", state.limits.active(), state.limits.max_clients()));
+    let history_size = state.config.borrow().history.size;
+    let held: usize = rooms.iter().map(|room| room.history).sum();
+    let held_bytes: usize = rooms.iter().map(|room| room.history_bytes).sum();
+    lines.push(format!("History in memory: {} message{} ({}), up to {} per room", held, if held == 1 { "" } else { "s" }, format_bytes(held_bytes as f64), history_size));
+    for room in &rooms {
+        lines.push(format!(
+            "#{}: {} member{}, {} in history ({}), {} lag event{}",
+            room.name,
+            room.members,
+            if room.members == 1 { "" } else { "s" },
+            room.history,
+            format_bytes(room.history_bytes as f64),
+            room.lag_events,
+            if room.lag_events == 1 { "" } else { "s" },
+        ));
+    }
+    lines.push(format!("Banned IPs: {}", state.bans.lock().await.len()));
+    lines.push(format!("Plugins: {}", state.plugins.names().join(", ")));
+    #[cfg(feature = "scripting")]
+    lines.push(format!("Scripts ({}/): {}", state.scripts.dir().display(), state.scripts.names().join(", ")));
+    lines.push(format!("Wire trace: {}", if state.tracer.is_enabled() { "on" } else { "off" }));
+    lines
+}
+
+
+// 3725s -> "1h 02m 05s"
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+
+
+// Runs a server command for one client and returns the private replies
+// /register and /login change who the connection speaks as and /join and /leave its rooms,
+// so they get the client; both sign-ins hand out a session token the client can use to resume after a reconnect
+
+async fn run_command(
+    command: Command,
+    client: &mut Client,
+    state: &ServerState,
+    tx: &broadcast::Sender<String>,
+) -> Vec<ChatMessage> {
+    let reply = |text: String| ChatMessage::new("System", text, MessageType::SystemNotification);
+    let fail = |text: &str| vec![ChatMessage::error(ErrorKind::CommandFailed, text)];
+
+    match command {
+        Command::Register { password } => {
+            if client.verified {
+                return fail("You are already signed in");
+            }
+            if password.chars().count() < MIN_PASSWORD_LEN {
+                return fail(&format!("Passwords need at least {} characters", MIN_PASSWORD_LEN));
+            }
+            let hash = match accounts::hash_password(password).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    report_error(state, format!("hashing password for {} failed: {}", client.username, e));
+                    return fail("Registration failed, try again later");
+                }
+            };
+            let created = state.store.lock().await.create_account(&client.username, &hash);
+            match created {
+                Ok(true) => {
+                    client.verified = true;
+                    println!("└─[{}] {} registered an account", Local::now().format("%D:%H:%M:%S"), client.username);
+                    let mut replies = vec![reply(format!("Registered {}, your messages now carry a ✔", client.username))];
+                    replies.extend(issue_session(state, &client.username).await);
+                    replies
+                }
+                Ok(false) => fail("That name is already registered"),
+                Err(e) => {
+                    report_error(state, format!("storing account {} failed: {}", client.username, e));
+                    fail("Registration failed, try again later")
+                }
+            }
+        }
+        Command::Login { username: account, password, code } => {
+            // Guessing gets slow: failures count against this connection and the account it tries
+            let keys = [format!("login:#{}", client.id), format!("login:{}", account.to_lowercase())];
+            let held_off = {
+                let failed_logins = state.failed_logins.lock().await;
+                keys.iter().filter_map(|key| failed_logins.cooling_down(key, Instant::now())).max()
+            };
+            if let Some(left) = held_off {
+                return fail(&format!("Too many failed logins, try again in {} seconds", left.as_secs().max(1)));
+            }
+            let stored = state.store.lock().await.account(&account);
+            let (account, hash) = match stored {
+                Ok(Some(found)) => found,
+                Ok(None) => return failed_login(state, client, &keys, "Wrong username or password").await,
+                Err(e) => {
+                    report_error(state, format!("account lookup for {} failed: {}", account, e));
+                    return fail("Login failed, try again later");
+                }
+            };
+            if !accounts::verify_password(password, hash).await {
+                println!("└─[{}] failed login for {} from {}", Local::now().format("%D:%H:%M:%S"), account, client.username);
+                return failed_login(state, client, &keys, "Wrong username or password").await;
+            }
+            if state.banned_accounts.lock().await.contains(&account.to_lowercase()) {
+                println!("└─[{}] banned account {} tried to sign in from {}", Local::now().format("%D:%H:%M:%S"), account, client.username);
+                return fail(&format!("{} is banned from this server", account));
+            }
+            // Accounts with two-factor sign-in need a code from the app (or a backup code) as well
+            let two_factor = state.store.lock().await.two_factor(&account);
+            match two_factor {
+                Ok(Some(two_factor)) if two_factor.enabled => match code {
+                    None => {
+                        let hint = format!("{} has two-factor sign-in, add the code: /login {} <password> <code>", account, account);
+                        return vec![ChatMessage::error(ErrorKind::TwoFactorRequired, hint)];
+                    }
+                    Some(code) => {
+                        if let Err(refusal) = check_second_factor(state, &account, &code).await {
+                            println!("└─[{}] failed two-factor code for {} from {}", Local::now().format("%D:%H:%M:%S"), account, client.username);
+                            return failed_login(state, client, &keys, refusal).await;
+                        }
+                    }
+                },
+                Ok(_) => {}
+                Err(e) => {
+                    report_error(state, format!("two-factor lookup for {} failed: {}", account, e));
+                    return fail("Login failed, try again later");
+                }
+            }
+
+            // Remember what the account was called before (not the guest name it was handed for its own name)
+            if !client.username.to_lowercase().starts_with(&format!("{}_guest", account.to_lowercase()))
+                && let Err(e) = state.store.lock().await.record_nickname(&account, &client.username)
+            {
+                report_error(state, format!("recording nickname {} for {} failed: {}", client.username, account, e));
+            }
+
+            // Tell the room who this connection really is
+            let mut notice = ChatMessage::new(account.clone(), format!("signed in (was {})", client.username), MessageType::SystemNotification);
+            notice.verified = true;
+            notice.renamed_from = Some(client.username.clone());
+            if let Ok(json) = serde_json::to_string(&notice)
+                && let Err(e) = tx.send(json)
+            {
+                eprintln!("[WARN] broadcast send failed: {}", e);
+            }
+            client.username = account;
+            client.verified = true;
+            client.role = stored_role(state, &client.username).await;
+            let mut replies: Vec<ChatMessage> = issue_session(state, &client.username).await.into_iter().collect();
+            replies.extend(draft_frames(state, &client.username).await);
+            replies.extend(mailbox_frames(state, &client.username).await);
+            // An announcer signing in can post in the read-only room it is looking at now
+            if state.rooms.lock().await.is_read_only(&client.room) {
+                replies.push(room_mode_frame(state, client, &client.room).await);
+            }
+            replies
+        }
+        Command::Passwd { old, new } => {
+            if !client.verified {
+                return fail("Sign in with /login first");
+            }
+            if new.chars().count() < MIN_PASSWORD_LEN {
+                return fail(&format!("Passwords need at least {} characters", MIN_PASSWORD_LEN));
+            }
+            let stored = state.store.lock().await.account(&client.username);
+            let hash = match stored {
+                Ok(Some((_, hash))) => hash,
+                Ok(None) => return fail("Your account no longer exists"),
+                Err(e) => {
+                    report_error(state, format!("account lookup for {} failed: {}", client.username, e));
+                    return fail("Password change failed, try again later");
+                }
+            };
+            if !accounts::verify_password(old, hash).await {
+                println!("└─[{}] failed password change for {}", Local::now().format("%D:%H:%M:%S"), client.username);
+                return fail("Wrong password");
+            }
+            let hash = match accounts::hash_password(new).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    report_error(state, format!("hashing password for {} failed: {}", client.username, e));
+                    return fail("Password change failed, try again later");
+                }
+            };
+            // Every other device signed in to the account has to log in again, this one gets a fresh token
+            let changed = {
+                let store = state.store.lock().await;
+                store.set_password_hash(&client.username, &hash).and_then(|_| store.delete_sessions(&client.username))
+            };
+            match changed {
+                Ok(sessions) => {
+                    println!("└─[{}] {} changed their password ({} session{} ended)",
+                        Local::now().format("%D:%H:%M:%S"), client.username, sessions, if sessions == 1 { "" } else { "s" });
+                    let detail = format!("{} session{} ended", sessions, if sessions == 1 { "" } else { "s" });
+                    audit(state, &client.username, "passwd", &client.username, Some(&detail)).await;
+                    let mut replies = vec![reply("Password changed, your other sessions were signed out".to_string())];
+                    replies.extend(issue_session(state, &client.username).await);
+                    replies
+                }
+                Err(e) => {
+                    report_error(state, format!("storing new password for {} failed: {}", client.username, e));
+                    fail("Password change failed, try again later")
+                }
+            }
+        }
+        Command::TwoFactor { action } => {
+            if !client.verified {
+                return fail("Sign in with /login first");
+            }
+            run_two_factor(action, &client.username, state).await
+        }
+        Command::Whisper { to, text, encrypted } => {
+            if let Some(refusal) = client.guest_refusal(state) {
+                return vec![refusal];
+            }
+            let mut whisper = ChatMessage::new(client.username.clone(), text, MessageType::Whisper);
+            whisper.verified = client.verified;
+            whisper.recipient = Some(to.clone());
+            whisper.encrypted = encrypted;
+            // Relayed straight to the recipient's connections, never broadcast
+            let (delivered, dnd) = {
+                let presence = state.presence.lock().await;
+                (presence.send_to_user(&to, Control::Send(Box::new(whisper.clone()))), presence.is_dnd(&to))
+            };
+            if delivered > 0 {
+                seen_pending(state, &to, &client.username).await;
+                if dnd {
+                    return vec![whisper, reply(format!("{} has do not disturb on, they'll see it when it's over", to))];
+                }
+                return vec![whisper]; // the sender's own copy
+            }
+            // Sealed for a key that left with them, nobody could open it from the mailbox
+            if encrypted {
+                return fail(&format!("{} went offline, send it again to leave it in their mailbox", to));
+            }
+            // An account that is offline finds it in its mailbox when it next signs in
+            let store = state.store.lock().await;
+            let account = match store.account(&to) {
+                Ok(Some((account, _))) => account,
+                Ok(None) => return fail(&format!("{} is not online", to)),
+                Err(e) => {
+                    report_error(state, format!("account lookup for {} failed: {}", to, e));
+                    return fail("Whispering failed, try again later");
+                }
+            };
+            whisper.recipient = Some(account.clone());
+            let kept = match store.mailbox_len(&account) {
+                Ok(len) if len >= MAX_MAILBOX => return fail(&format!("{} is offline and their mailbox is full", account)),
+                Ok(_) => store.add_to_mailbox(&account, &whisper),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = kept {
+                report_error(state, format!("keeping a whisper for {} failed: {}", account, e));
+                return fail("Whispering failed, try again later");
+            }
+            vec![whisper, reply(format!("{} is offline, they'll get it when they're back", account))]
+        }
+        Command::Whois { user } => whois(state, client, &user).await,
+        Command::Seen { user } => {
+            if let Some((_, entry)) = state.presence.lock().await.connections_of(&user).first() {
+                return vec![reply(format!("{} is online right now", entry.username))];
+            }
+            let store = state.store.lock().await;
+            let seen = store.account(&user).and_then(|account| match account {
+                Some((name, _)) => Ok(Some((name.clone(), store.last_seen(&name)?))),
+                None => Ok(None),
+            });
+            match seen {
+                Ok(Some((name, Some((when, presence))))) => vec![reply(format!("{} was {}", name, last_seen_text(&name, when, &presence)))],
+                Ok(Some((name, None))) => vec![reply(format!("{} is offline, and hasn't been around since the server started keeping track", name))],
+                Ok(None) => fail(&format!("Nobody called {} is online or has an account", user)),
+                Err(e) => {
+                    report_error(state, format!("looking up when {} was last seen failed: {}", user, e));
+                    fail("Looking them up failed, try again later")
+                }
+            }
+        }
+        Command::Profile { action } => {
+            if !client.verified {
+                return fail("Sign in to an account to have a profile");
+            }
+            let (field, text) = match action {
+                ProfileAction::Show => return whois(state, client, &client.username).await,
+                ProfileAction::Set { field, text } => (field, Some(text)),
+                ProfileAction::Clear { field } => (field, None),
+            };
+            let store = state.store.lock().await;
+            let (saved, what) = match field {
+                ProfileField::Bio => (store.set_bio(&client.username, text.as_deref()), "bio is"),
+                ProfileField::Pronouns => (store.set_pronouns(&client.username, text.as_deref()), "pronouns are"),
+            };
+            match (saved, text) {
+                (Err(e), _) => {
+                    report_error(state, format!("saving the profile of {} failed: {}", client.username, e));
+                    fail("Your profile could not be saved, try again later")
+                }
+                (Ok(()), Some(text)) => vec![reply(format!("Your {} now: {}", what, text))],
+                (Ok(()), None) => vec![reply(format!("Your {} gone from your profile", what))],
+            }
+        }
+        Command::Mailbox { clear } => {
+            if !client.verified {
+                return fail("Sign in to an account to have a mailbox");
+            }
+            let store = state.store.lock().await;
+            if clear {
+                return match store.clear_mailbox(&client.username) {
+                    Ok(0) => vec![reply("Your mailbox is empty".to_string())],
+                    Ok(n) => vec![reply(format!("Threw away {} message{} from your mailbox", n, if n == 1 { "" } else { "s" }))],
+                    Err(e) => {
+                        report_error(state, format!("clearing the mailbox of {} failed: {}", client.username, e));
+                        fail("Clearing your mailbox failed, try again later")
+                    }
+                };
+            }
+            match store.mailbox(&client.username, false) {
+                Ok(mail) if mail.is_empty() => vec![reply("Your mailbox is empty".to_string())],
+                Ok(mail) => {
+                    let mut lines = vec![format!("📬 Mailbox ({}), /mailbox clear to empty it", mail.len())];
+                    lines.extend(mail.iter().map(|msg| format!("[{}] {}: {}", utc_time(&msg.timestamp), msg.username, msg.content)));
+                    vec![reply(lines.join("\n"))]
+                }
+                Err(e) => {
+                    report_error(state, format!("loading the mailbox of {} failed: {}", client.username, e));
+                    fail("Loading your mailbox failed, try again later")
+                }
+            }
+        }
+        Command::React { message_id, emoji } => {
+            // Only messages in the client's own rooms can be reacted to
+            let room = state.store.lock().await.message_room(message_id);
+            let room = match room {
+                Ok(Some(room)) if client.rooms.contains_key(&room) => room,
+                Ok(_) => return fail(&format!("There is no message #{} here", message_id)),
+                Err(e) => {
+                    report_error(state, format!("looking up message #{} failed: {}", message_id, e));
+                    return fail("Reaction failed, try again later");
+                }
+            };
+            let toggled = state.store.lock().await.toggle_reaction(&room, message_id, &client.username, &emoji);
+            match toggled {
+                Ok(Some((added, reactions))) => {
+                    let action = if added { "reacted" } else { "took back" };
+                    let content = format!("{} {} on #{}", action, emoji, message_id);
+                    let mut update = ChatMessage::new(client.username.clone(), content, MessageType::Reactions).in_room(room.as_str());
+                    update.id = Some(message_id);
+                    update.verified = client.verified;
+                    update.reactions = reactions;
+                    if let Ok(json) = serde_json::to_string(&update) {
+                        state.rooms.lock().await.send(&room, json);
+                    }
+                    Vec::new()
+                }
+                Ok(None) => fail(&format!("There is no message #{} here", message_id)),
+                Err(e) => {
+                    report_error(state, format!("storing reaction from {} failed: {}", client.username, e));
+                    fail("Reaction failed, try again later")
+                }
+            }
+        }
+        Command::Pin { message_id } => {
+            if !client.can(state, Permission::Pin) {
+                return fail("You can't pin messages");
+            }
+            // Like reports, only messages in the client's own rooms
+            let found = state.store.lock().await.message(message_id);
+            let (msg, room) = match found {
+                Ok(Some(msg)) => match msg.room.clone().filter(|room| client.rooms.contains_key(room)) {
+                    Some(room) => (msg, room),
+                    None => return fail(&format!("There is no message #{} here", message_id)),
+                },
+                Ok(None) => return fail(&format!("There is no message #{} here", message_id)),
+                Err(e) => {
+                    report_error(state, format!("looking up message #{} failed: {}", message_id, e));
+                    return fail("Pinning failed, try again later");
+                }
+            };
+            let pinned = {
+                let store = state.store.lock().await;
+                match store.pins(&room) {
+                    Ok(pins) if pins.len() >= MAX_PINS => return fail(&format!("#{} has {} pins already, /unpin one first", room, MAX_PINS)),
+                    Ok(_) => store.pin(&room, message_id, &client.username),
+                    Err(e) => Err(e),
+                }
+            };
+            match pinned {
+                Ok(true) => {}
+                Ok(false) => return fail(&format!("#{} is already pinned", message_id)),
+                Err(e) => {
+                    report_error(state, format!("pinning #{} failed: {}", message_id, e));
+                    return fail("Pinning failed, try again later");
+                }
+            }
+            println!("└─[{}] {} pinned #{} in #{}", Local::now().format("%D:%H:%M:%S"), client.username, message_id, room);
+            audit(state, &client.username, "pin", &format!("#{}", room), Some(&format!("#{}", message_id))).await;
+            room_notice(state, &room, &client.username, format!("pinned #{} by {}", message_id, msg.username)).await;
+            send_pins(state, &room).await;
+            Vec::new()
+        }
+        Command::Unpin { message_id } => {
+            if !client.can(state, Permission::Pin) {
+                return fail("You can't unpin messages");
+            }
+            let found = state.store.lock().await.message(message_id);
+            let room = match found {
+                Ok(Some(msg)) => match msg.room.filter(|room| client.rooms.contains_key(room)) {
+                    Some(room) => room,
+                    None => return fail(&format!("There is no message #{} here", message_id)),
+                },
+                Ok(None) => return fail(&format!("There is no message #{} here", message_id)),
+                Err(e) => {
+                    report_error(state, format!("looking up message #{} failed: {}", message_id, e));
+                    return fail("Unpinning failed, try again later");
+                }
+            };
+            match state.store.lock().await.unpin(message_id) {
+                Ok(true) => {}
+                Ok(false) => return fail(&format!("#{} isn't pinned", message_id)),
+                Err(e) => {
+                    report_error(state, format!("unpinning #{} failed: {}", message_id, e));
+                    return fail("Unpinning failed, try again later");
+                }
+            }
+            println!("└─[{}] {} unpinned #{} in #{}", Local::now().format("%D:%H:%M:%S"), client.username, message_id, room);
+            audit(state, &client.username, "unpin", &format!("#{}", room), Some(&format!("#{}", message_id))).await;
+            room_notice(state, &room, &client.username, format!("unpinned #{}", message_id)).await;
+            send_pins(state, &room).await;
+            Vec::new()
+        }
+        Command::Report { message_id, reason } => {
+            // Only messages in the client's own rooms can be reported, like reactions
+            let found = state.store.lock().await.message(message_id);
+            let msg = match found {
+                Ok(Some(msg)) if msg.room.as_ref().is_some_and(|room| client.rooms.contains_key(room)) => msg,
+                Ok(_) => return fail(&format!("There is no message #{} here", message_id)),
+                Err(e) => {
+                    report_error(state, format!("looking up message #{} failed: {}", message_id, e));
+                    return fail("Reporting failed, try again later");
+                }
+            };
+            let filed = state.store.lock().await.add_report(&msg, &client.username, &reason);
+            let report_id = match filed {
+                Ok(Some(report_id)) => report_id,
+                Ok(None) => return fail(&format!("You already reported #{}", message_id)),
+                Err(e) => {
+                    report_error(state, format!("storing the report from {} failed: {}", client.username, e));
+                    return fail("Reporting failed, try again later");
+                }
+            };
+            let room = msg.room.clone().unwrap_or_default();
+            println!("└─[{}] ⚑ {} reported #{} by {} in #{}: {}", Local::now().format("%D:%H:%M:%S"), client.username, message_id, msg.username, room, reason);
+
+            // Every moderator signed in right now hears about it, wherever they are
+            let notice = format!(
+                "⚑ Report {}: {} flagged #{} by {} in #{} (\"{}\"): {}",
+                report_id, client.username, message_id, msg.username, room, msg.content, reason
+            );
+            let notice = ChatMessage { highlight: true, ..ChatMessage::new("System", notice, MessageType::SystemNotification) };
+            let presence = state.presence.lock().await;
+            let config = state.config.borrow();
+            presence.send_where(
+                |entry| config.allows(config.role_of(&entry.username, entry.verified, entry.role), Permission::Moderate),
+                Control::Send(Box::new(notice)),
+            );
+            vec![reply(format!("Thanks, #{} was reported to the moderators", message_id))]
+        }
+        Command::Join { room, password } => {
+            let room = match Rooms::normalize(&room) {
+                Ok(room) => room,
+                Err(e) => return fail(&e),
+            };
+            let was_member = client.rooms.contains_key(&room);
+            match join_room(state, client, &room, true, password.as_deref()).await {
+                Ok(joined) if was_member => vec![joined],
+                Ok(joined) => {
+                    // Catch up on the room like a newcomer to the server would
+                    let mut history = state.rooms.lock().await.history(&room);
+                    let enriched = state.store.lock().await.enrich(&mut history);
+                    if let Err(e) = enriched {
+                        report_error(state, format!("loading reactions for {} failed: {}", client.username, e));
+                    }
+                    let pins = pins_frame(state, &room).await;
+                    [joined, pins].into_iter().chain(history.into_iter().map(|msg| msg.in_room(room.as_str()))).collect()
+                }
+                Err(refused) => vec![refused],
+            }
+        }
+        Command::Create { room, password } => {
+            if !client.verified {
+                return fail("Sign in to an account to create private rooms");
+            }
+            let room = match Rooms::normalize(&room) {
+                Ok(room) => room,
+                Err(e) => return fail(&e),
+            };
+            {
+                let rooms = state.rooms.lock().await;
+                if rooms.is_private(&room) {
+                    return fail(&format!("#{} already is a private room", room));
+                }
+                // Nobody gets shut out of a room they're in
+                if room == DEFAULT_ROOM || room == OPS_ROOM || rooms.exists(&room) {
+                    return fail(&format!("#{} is already in use, pick another name", room));
+                }
+            }
+            if let Some(password) = &password
+                && password.chars().count() < MIN_PASSWORD_LEN
+            {
+                return fail(&format!("Passwords need at least {} characters", MIN_PASSWORD_LEN));
+            }
+            let hash = match password {
+                Some(password) => match accounts::hash_password(password).await {
+                    Ok(hash) => Some(hash),
+                    Err(e) => {
+                        report_error(state, format!("hashing the password of #{} failed: {}", room, e));
+                        return fail("Creating the room failed, try again later");
+                    }
+                },
+                None => None,
+            };
+            let created = {
+                let store = state.store.lock().await;
+                // A room with a past has regulars, making it private would shut them out (and hand over its history)
+                match store.room_in_use(&room) {
+                    Ok(true) => return fail(&format!("#{} is already in use, pick another name", room)),
+                    Ok(false) => store.create_private_room(&room, &client.username, hash.as_deref()),
+                    Err(e) => Err(e),
+                }
+            };
+            match created {
+                Ok(true) => {}
+                Ok(false) => return fail(&format!("#{} already is a private room", room)),
+                Err(e) => {
+                    report_error(state, format!("creating #{} failed: {}", room, e));
+                    return fail("Creating the room failed, try again later");
+                }
+            }
+            state.rooms.lock().await.set_private(&room);
+            println!("└─[{}] {} created private #{}", Local::now().format("%D:%H:%M:%S"), client.username, room);
+            match join_room(state, client, &room, true, None).await {
+                Ok(joined) => {
+                    let how = if hash.is_some() { "/invite people or give them the password" } else { "/invite people to let them in" };
+                    vec![joined, reply(format!("Created private #{}, {}", room, how))]
+                }
+                Err(refused) => vec![refused],
+            }
+        }
+        Command::Invite { user, room } => {
+            let room = match room.as_deref().map(Rooms::normalize) {
+                Some(Ok(room)) => room,
+                Some(Err(e)) => return fail(&e),
+                None => client.room.clone(),
+            };
+            if !state.rooms.lock().await.is_private(&room) {
+                return fail(&format!("#{} isn't private, anyone can /join it", room));
+            }
+            let (account, allowed) = {
+                let store = state.store.lock().await;
+                let allowed = client.can(state, Permission::Moderate)
+                    || (client.verified && store.has_room_access(&room, &client.username).unwrap_or(false));
+                (store.account(&user), allowed)
+            };
+            if !allowed {
+                return fail(&format!("Only those let into #{} can invite people", room));
+            }
+            let account = match account {
+                Ok(Some((account, _))) => account,
+                Ok(None) => return fail(&format!("{} is not a registered account, invites are for accounts", user)),
+                Err(e) => {
+                    report_error(state, format!("account lookup for {} failed: {}", user, e));
+                    return fail("Inviting failed, try again later");
+                }
+            };
+            match state.store.lock().await.grant_room_access(&room, &account, &client.username) {
+                Ok(true) => {}
+                Ok(false) => return vec![reply(format!("{} can already join #{}", account, room))],
+                Err(e) => {
+                    report_error(state, format!("inviting {} to #{} failed: {}", account, room, e));
+                    return fail("Inviting failed, try again later");
+                }
+            }
+            println!("└─[{}] {} invited {} to #{}", Local::now().format("%D:%H:%M:%S"), client.username, account, room);
+            let invite = format!("{} invited you to #{}, /join {} to come in", client.username, room, room);
+            let invite = ChatMessage::new("System", invite, MessageType::SystemNotification);
+            state.presence.lock().await.send_to_account(&account, Control::Send(Box::new(invite)));
+            vec![reply(format!("Invited {} to #{}", account, room))]
+        }
+        Command::Rooms => vec![ChatMessage::room_list(state.rooms.lock().await.public())],
+        Command::Leave { room } => {
+            let room = match room.as_deref().map(Rooms::normalize) {
+                Some(Ok(room)) => room,
+                Some(Err(e)) => return fail(&e),
+                None => client.room.clone(),
+            };
+            if !client.rooms.contains_key(&room) {
+                return fail(&format!("You are not in #{}", room));
+            }
+            if client.rooms.len() == 1 {
+                return fail("You can't leave your last room, /join another one first");
+            }
+            client.rooms.remove(&room);
+            state.rooms.lock().await.leave(&room, client.id);
+            room_notice(state, &room, &client.username, format!("left #{}", room)).await;
+            let mut replies = vec![ChatMessage::new("System", format!("You left #{}", room), MessageType::RoomLeft).in_room(room.as_str())];
+            // Leaving the room you were talking in moves you to one you're still in
+            if client.room == room
+                && let Some(next) = client.rooms.keys().next().cloned()
+            {
+                replies.push(room_joined_frame(state, client, &next).await);
+                client.room = next;
+            }
+            replies
+        }
+        Command::RoomMode { room, read_only } => {
+            if !client.can(state, Permission::RoomMode) {
+                return fail("You can't change a room's mode");
+            }
+            let room = match Rooms::normalize(&room) {
+                Ok(room) => room,
+                Err(e) => return fail(&e),
+            };
+            if let Err(e) = state.store.lock().await.set_room_read_only(&room, read_only, &client.username) {
+                report_error(state, format!("storing the mode of #{} failed: {}", room, e));
+                return fail("Changing the room's mode failed, try again later");
+            }
+            let (changed, members) = {
+                let mut rooms = state.rooms.lock().await;
+                (rooms.set_read_only(&room, read_only), rooms.members(&room))
+            };
+            let mode = if read_only { "read-only" } else { "open" };
+            if !changed {
+                return vec![reply(format!("#{} already is {}", room, mode))];
+            }
+            println!("└─[{}] {} made #{} {}", Local::now().format("%D:%H:%M:%S"), client.username, room, mode);
+            audit(state, &client.username, "roommode", &format!("#{}", room), Some(mode)).await;
+            // Every member hears it from its own connection, which knows whether it may still post
+            let presence = state.presence.lock().await;
+            for member in &members {
+                presence.send_to(*member, Control::RoomMode { room: room.clone() });
+            }
+            if members.contains(&client.id) {
+                Vec::new()
+            } else {
+                vec![reply(format!("#{} is {} now", room, mode))]
+            }
+        }
+        Command::RoomPublic { room } => {
+            if !client.can(state, Permission::RoomMode) {
+                return fail("You can't change a room's mode");
+            }
+            let room = match Rooms::normalize(&room) {
+                Ok(room) => room,
+                Err(e) => return fail(&e),
+            };
+            match state.store.lock().await.make_room_public(&room) {
+                Ok(true) => {}
+                Ok(false) => return fail(&format!("#{} isn't a private room", room)),
+                Err(e) => {
+                    report_error(state, format!("making #{} public failed: {}", room, e));
+                    return fail("Changing the room's mode failed, try again later");
+                }
+            }
+            state.rooms.lock().await.set_public(&room);
+            println!("└─[{}] {} made #{} public", Local::now().format("%D:%H:%M:%S"), client.username, room);
+            audit(state, &client.username, "roommode", &format!("#{}", room), Some("public")).await;
+            vec![reply(format!("#{} is public now, anyone can join", room))]
+        }
+        Command::Topic { text: None } => {
+            let room = client.room.clone();
+            match state.rooms.lock().await.topic(&room) {
+                Some(topic) => vec![reply(format!("The topic of #{} is: {}", room, topic))],
+                None => vec![reply(format!("#{} has no topic", room))],
+            }
+        }
+        Command::Topic { text: Some(text) } => {
+            if !client.can(state, Permission::Topic) {
+                return fail("You can't change the topic");
+            }
+            let room = client.room.clone();
+            let topic = (!text.is_empty()).then_some(text);
+            if let Err(e) = state.store.lock().await.set_room_topic(&room, topic.as_deref(), &client.username) {
+                report_error(state, format!("storing the topic of #{} failed: {}", room, e));
+                return fail("Changing the topic failed, try again later");
+            }
+            state.rooms.lock().await.set_topic(&room, topic.clone());
+            println!("└─[{}] {} changed the topic of #{}", Local::now().format("%D:%H:%M:%S"), client.username, room);
+            audit(state, &client.username, "topic", &format!("#{}", room), topic.as_deref()).await;
+            // Everyone in the room gets the new topic with the notice, for their header
+            let content = match &topic {
+                Some(topic) => format!("set the topic of #{} to: {}", room, topic),
+                None => format!("took away the topic of #{}", room),
+            };
+            let notice = ChatMessage {
+                topic: Some(topic.unwrap_or_default()),
+                ..ChatMessage::new(client.username.clone(), content, MessageType::SystemNotification).in_room(&room)
+            };
+            if let Ok(json) = serde_json::to_string(&notice) {
+                state.rooms.lock().await.send(&room, json);
+            }
+            Vec::new()
+        }
+        Command::Stats => {
+            let full = client.can(state, Permission::Moderate);
+            let mut lines = vec!["Server stats".to_string()];
+            lines.extend(stats_lines(state, full).await);
+            vec![reply(lines.join("\n"))]
+        }
+        // Answered in the system lane, so the round trip isn't stuck behind a backlog of chat
+        Command::Ping { stamp } => vec![ChatMessage::new("System", stamp, MessageType::Ping)],
+        Command::Motd { text: None } => {
+            let motd = state.motd.lock().await.clone();
+            vec![motd.map_or_else(|| reply("No message of the day is set".to_string()), |motd| motd_frame(&motd))]
+        }
+        Command::Motd { text: Some(text) } => {
+            if !client.can(state, Permission::Motd) {
+                return fail("You can't change the message of the day");
+            }
+            println!("└─[{}] {} changed the MOTD", Local::now().format("%D:%H:%M:%S"), client.username);
+            set_motd(state, tx, text).await;
+            Vec::new()
+        }
+        Command::Shortcode { action } => {
+            if !client.verified {
+                return fail("Sign in to an account to make shortcodes");
+            }
+            run_shortcode(action, client, state, tx).await
+        }
+        Command::Kick { user, reason } => {
+            if !client.can(state, Permission::Kick) {
+                return fail("You can't kick people");
+            }
+            if account_role(state, &user).await >= client.role(state) {
+                return fail(&format!("{} ranks as high as you, they can't be kicked by you", user));
+            }
+            let notice = match &reason {
+                Some(reason) => format!("You were kicked by {}: {}", client.username, reason),
+                None => format!("You were kicked by {}", client.username),
+            };
+            let kicked = state.presence.lock().await.send_to_user(&user, Control::Kick { reason: notice });
+            if kicked == 0 {
+                return fail(&format!("{} isn't online", user));
+            }
+            println!("└─[{}] 👢 {} kicked {}", Local::now().format("%D:%H:%M:%S"), client.username, user);
+            audit(state, &client.username, "kick", &user, reason.as_deref()).await;
+            vec![reply(format!("Kicked {}", user))]
+        }
+        Command::Ban { user, reason } => {
+            if !client.can(state, Permission::Ban) {
+                return fail("You can't ban people");
+            }
+            if account_role(state, &user).await >= client.role(state) {
+                return fail(&format!("{} ranks as high as you, they can't be banned by you", user));
+            }
+            let connections = state.presence.lock().await.connections_of(&user);
+            if connections.is_empty() {
+                return fail(&format!("{} isn't online, their address can be banned from the server console", user));
+            }
+            // Their addresses, leaving out this machine (Unix socket clients and anyone on it share it) and the moderator's own
+            let ips: BTreeSet<IpAddr> = connections
+                .iter()
+                .map(|(_, entry)| entry.addr.ip())
+                .filter(|ip| !ip.is_loopback() && *ip != LOCAL_PEER.ip() && *ip != client.addr.ip())
+                .collect();
+            // Signed in to an account, the account goes too so a new address doesn't get them back in
+            let account = connections.iter().find(|(_, entry)| entry.verified).map(|(_, entry)| entry.username.clone());
+            if ips.is_empty() && account.is_none() {
+                return fail(&format!("{} connects from this machine or your own address, /kick them instead", user));
+            }
+            for ip in &ips {
+                if let Err(e) = state.store.lock().await.add_ban(*ip) {
+                    report_error(state, format!("storing ban for {} failed: {}", ip, e));
+                    return fail("Banning failed, try again later");
+                }
+                state.bans.lock().await.insert(*ip);
+            }
+            if let Some(account) = &account {
+                let stored = state.store.lock().await.add_account_ban(account, &client.username, reason.as_deref());
+                if let Err(e) = stored {
+                    report_error(state, format!("storing ban for account {} failed: {}", account, e));
+                    return fail("Banning failed, try again later");
+                }
+                if let Err(e) = state.store.lock().await.delete_sessions(account) {
+                    report_error(state, format!("signing {} out failed: {}", account, e));
+                }
+                state.banned_accounts.lock().await.insert(account.to_lowercase());
+            }
+            // Only their own connections go, not whoever else shares an address with them
+            {
+                let presence = state.presence.lock().await;
+                for (id, _) in &connections {
+                    presence.send_to(*id, Control::Ban);
+                }
+            }
+            let mut banned: Vec<String> = ips.iter().map(IpAddr::to_string).collect();
+            if let Some(account) = &account {
+                banned.push(format!("account {}", account));
+            }
+            let addresses = banned.join(", ");
+            println!("└─[{}] 🚫 {} banned {} ({})", Local::now().format("%D:%H:%M:%S"), client.username, user, addresses);
+            report(state, OpsEvent::Ban, format!("{} banned {} ({})", client.username, user, addresses));
+            let detail = match &reason {
+                Some(reason) => format!("{}: {}", addresses, reason),
+                None => addresses,
+            };
+            audit(state, &client.username, "ban", &user, Some(&detail)).await;
+            vec![reply(format!("Banned {}", user))]
+        }
+        Command::Promote { user, role } => change_role(state, client, tx, &user, role, true).await,
+        Command::Demote { user, role } => change_role(state, client, tx, &user, role, false).await,
+        Command::Mute { user, duration, reason } => {
+            if !client.can(state, Permission::Mute) {
+                return fail("You can't mute people");
+            }
+            if account_role(state, &user).await >= client.role(state) {
+                return fail(&format!("{} ranks as high as you, they can't be muted by you", user));
+            }
+            let until = Utc::now() + chrono::Duration::seconds(duration.as_secs() as i64);
+            let length = format_duration(duration);
+            let detail = match &reason {
+                Some(reason) => format!("{}: {}", length, reason),
+                None => length.clone(),
+            };
+            let stored = {
+                let store = state.store.lock().await;
+                store
+                    .add_mute(&user, until, &client.username, reason.as_deref())
+                    .and_then(|_| store.log_moderation(&client.username, "mute", &user, Some(&detail)))
+            };
+            if let Err(e) = stored {
+                report_error(state, format!("storing the mute of {} failed: {}", user, e));
+                return fail("Muting failed, try again later");
+            }
+            state.mutes.lock().await.insert(user.to_lowercase(), until);
+            println!("└─[{}] {} muted {} for {}", Local::now().format("%D:%H:%M:%S"), client.username, user, detail);
+
+            // They hear it from the server rather than finding out one refused message at a time
+            let notice = match &reason {
+                Some(reason) => format!("You were muted for {} by {}: {}", length, client.username, reason),
+                None => format!("You were muted for {} by {}", length, client.username),
+            };
+            let notice = ChatMessage::new("System", notice, MessageType::SystemNotification);
+            state.presence.lock().await.send_to_user(&user, Control::Send(Box::new(notice)));
+            vec![reply(format!("{} is muted for {}", user, length))]
+        }
+        Command::Unmute { user } => {
+            if !client.can(state, Permission::Mute) {
+                return fail("You can't unmute people");
+            }
+            let removed = {
+                let store = state.store.lock().await;
+                // Only mutes that were there get a line in the log
+                store.remove_mute(&user).and_then(|removed| {
+                    if removed {
+                        store.log_moderation(&client.username, "unmute", &user, None)?;
+                    }
+                    Ok(removed)
+                })
+            };
+            let removed = match removed {
+                Ok(removed) => removed,
+                Err(e) => {
+                    report_error(state, format!("removing the mute of {} failed: {}", user, e));
+                    return fail("Unmuting failed, try again later");
+                }
+            };
+            let was_muted = state.mutes.lock().await.remove(&user.to_lowercase()).is_some_and(|until| until > Utc::now());
+            if !removed || !was_muted {
+                return fail(&format!("{} isn't muted", user));
+            }
+            println!("└─[{}] {} unmuted {}", Local::now().format("%D:%H:%M:%S"), client.username, user);
+            let notice = ChatMessage::new("System", format!("{} lifted your mute, you can talk again", client.username), MessageType::SystemNotification);
+            state.presence.lock().await.send_to_user(&user, Control::Send(Box::new(notice)));
+            vec![reply(format!("{} can talk again", user))]
+        }
+        Command::AuditLog { target } => {
+            if !client.can(state, Permission::Moderate) {
+                return fail("You can't read the moderation log");
+            }
+            let title = match &target {
+                Some(target) => format!("Moderation of {}, oldest first", target),
+                None => "Recent moderation, oldest first".to_string(),
+            };
+            let mut lines = vec![title];
+            lines.extend(audit_lines(state, target.as_deref()).await);
+            vec![reply(lines.join("\n"))]
+        }
+        Command::Chaos { action } => {
+            if !client.can(state, Permission::Chaos) {
+                return fail("You can't use /chaos");
+            }
+            if !state.config.borrow().chaos.enabled {
+                return fail("Chaos commands are off, [chaos] enabled = true in the config turns them on (staging only)");
+            }
+            let done = match action {
+                ChaosAction::DropClient { user } => {
+                    let dropped = state.presence.lock().await.send_to_user(&user, Control::Drop);
+                    if dropped == 0 {
+                        return fail(&format!("{} isn't online", user));
+                    }
+                    format!("dropped {} connection{} of {}", dropped, if dropped == 1 { "" } else { "s" }, user)
+                }
+                ChaosAction::Lag { lag } => {
+                    state.chaos.set_lag(lag);
+                    match lag.as_millis() {
+                        0 => "stopped the lag".to_string(),
+                        ms => format!("is holding back every frame by {} ms", ms),
+                    }
+                }
+                ChaosAction::Partition { length } => {
+                    // Everyone goes, the owner who asked included, and nobody gets back in until it's over
+                    state.chaos.partition(length);
+                    let dropped = state.presence.lock().await.send_to_all(Control::Drop);
+                    format!("partitioned the server for {}, {} connection{} dropped", format_duration(length), dropped, if dropped == 1 { "" } else { "s" })
+                }
+                ChaosAction::Off => {
+                    if !state.chaos.heal() {
+                        return fail("No chaos going on");
+                    }
+                    "ended the lag and partition".to_string()
+                }
+            };
+            println!("└─[{}] 🧪 {} {}", Local::now().format("%D:%H:%M:%S"), client.username, done);
+            report(state, OpsEvent::Chaos, format!("{} {}", client.username, done));
+            vec![reply(format!("Chaos: {} {}", client.username, done))]
+        }
+        Command::Read { room, id } => {
+            // Only accounts have a place to come back to
+            if !client.verified {
+                return Vec::new();
+            }
+            let Ok(room) = Rooms::normalize(&room) else { return Vec::new() };
+            if !client.rooms.contains_key(&room) {
+                return fail(&format!("You are not in #{}", room));
+            }
+            if let Err(e) = state.store.lock().await.mark_read(&client.username, &room, id) {
+                report_error(state, format!("saving where {} left off in #{} failed: {}", client.username, room, e));
+            }
+            Vec::new()
+        }
+        Command::Sign { stamp, signature } => {
+            client.signature = Some((stamp, signature));
+            Vec::new()
+        }
+        // Everyone sees it by our name in the roster, the client keeps its own quiet
+        Command::Dnd { on } => {
+            state.presence.lock().await.set_dnd(client.id, on);
+            Vec::new()
+        }
+        Command::ReadWhispers { from } => {
+            // Only passed on for whispers that really came, nobody gets told "seen" out of the blue
+            let seen = state
+                .unseen_whispers
+                .lock()
+                .await
+                .get_mut(&client.username.to_lowercase())
+                .is_some_and(|senders| senders.remove(&from.to_lowercase()));
+            if seen {
+                let receipt = ChatMessage::new(client.username.clone(), "", MessageType::Seen);
+                state.presence.lock().await.send_to_user(&from, Control::Send(Box::new(receipt)));
+            }
+            Vec::new()
+        }
+        Command::Draft { room, text } => {
+            // Drafts follow accounts, a guest has no other devices to hand them to
+            if !client.verified {
+                return Vec::new();
+            }
+            let Ok(room) = Rooms::normalize(&room) else { return Vec::new() };
+            if !client.rooms.contains_key(&room) {
+                return fail(&format!("You are not in #{}", room));
+            }
+            if let Err(e) = state.store.lock().await.save_draft(&client.username, &room, &text) {
+                report_error(state, format!("saving a draft for {} failed: {}", client.username, e));
+            }
+            // Last write wins: every device of the account (this one too) gets the drafts in the order they
+            // reached us, so they all end up with the newest one even when two of them typed at once
+            let draft = Control::Send(Box::new(ChatMessage::draft(&room, text)));
+            state.presence.lock().await.send_to_account(&client.username, draft);
+            Vec::new()
+        }
+    }
+}
+
+
+// /promote and /demote: `user` goes one step up (or down), or to `role` when given
+// Nobody changes the role of someone who ranks as high as they do (owners aside), or hands out more than their own,
+// and the owners in the config stay owners
+
+async fn change_role(state: &ServerState, client: &Client, tx: &broadcast::Sender<String>, user: &str, role: Option<Role>, up: bool) -> Vec<ChatMessage> {
+    let fail = |text: &str| vec![ChatMessage::error(ErrorKind::CommandFailed, text)];
+    let own = client.role(state);
+    if !client.can(state, Permission::Promote) {
+        return fail("You can't change roles");
+    }
+    let account = match state.store.lock().await.account(user) {
+        Ok(Some((account, _))) => account,
+        Ok(None) => return fail(&format!("{} has no account, only accounts get roles", user)),
+        Err(e) => {
+            report_error(state, format!("account lookup for {} failed: {}", user, e));
+            return fail("Changing roles failed, try again later");
+        }
+    };
+    if state.config.borrow().moderation.is_owner(&account) {
+        return fail(&format!("{} is an owner in the server's config, only the config can change that", account));
+    }
+    let current = stored_role(state, &account).await;
+    if current >= own && own < Role::Owner {
+        return fail(&format!("{} ranks as high as you, you can't change their role", account));
+    }
+    let next = role.or(if up { current.above() } else { current.below() });
+    let next = match next {
+        Some(next) if (up && next > current) || (!up && next < current) => next,
+        _ => return fail(&format!("{} already is {} {}", account, if current == Role::Owner { "an" } else { "a" }, current)),
+    };
+    if next > own {
+        return fail(&format!("You can hand out roles up to your own ({})", own));
+    }
+    let stored = {
+        let store = state.store.lock().await;
+        store
+            .set_role(&account, next, &client.username)
+            .and_then(|_| store.log_moderation(&client.username, if up { "promote" } else { "demote" }, &account, Some(&format!("{} → {}", current, next))))
+    };
+    if let Err(e) = stored {
+        report_error(state, format!("storing the role of {} failed: {}", account, e));
+        return fail("Changing roles failed, try again later");
+    }
+    state.presence.lock().await.send_to_account(&account, Control::Role { role: next });
+
+    // Everyone hears about it, the one who did it included
+    let verb = if up { "promoted" } else { "demoted" };
+    println!("└─[{}] {} {} {} to {}", Local::now().format("%D:%H:%M:%S"), client.username, verb, account, next);
+    let notice = ChatMessage::new("System", format!("{} {} {} to {}", client.username, verb, account, next), MessageType::SystemNotification);
+    if let Ok(json) = serde_json::to_string(&notice) {
+        let _ = tx.send(json);
+    }
+    Vec::new()
+}
+
+
+// The role stored for an account, members when the database can't say
+
+async fn stored_role(state: &ServerState, username: &str) -> Role {
+    state.store.lock().await.role(username).unwrap_or_else(|e| {
+        report_error(state, format!("looking up the role of {} failed: {}", username, e));
+        Role::default()
+    })
+}
+
+
+// The role `username` has as an account, guest when there is no such account
+
+async fn account_role(state: &ServerState, username: &str) -> Role {
+    let exists = state.store.lock().await.account_exists(username).unwrap_or_else(|e| {
+        report_error(state, format!("account lookup for {} failed: {}", username, e));
+        false
+    });
+    let stored = stored_role(state, username).await;
+    state.config.borrow().role_of(username, exists, stored)
+}
+
+
+// How much longer `username` stays muted, None once they can talk (a mute that ran out is forgotten here)
+
+async fn muted_for(state: &ServerState, username: &str) -> Option<Duration> {
+    let mut mutes = state.mutes.lock().await;
+    let key = username.to_lowercase();
+    let left = (*mutes.get(&key)? - Utc::now()).to_std().ok().filter(|left| !left.is_zero());
+    if left.is_none() {
+        mutes.remove(&key);
+    }
+    left
+}
+
+
+// Drafts an account left half typed, for a device that just signed in
+
+async fn draft_frames(state: &ServerState, username: &str) -> Vec<ChatMessage> {
+    match state.store.lock().await.drafts(username) {
+        Ok(drafts) => drafts.into_iter().map(|(room, text)| ChatMessage::draft(&room, text)).collect(),
+        Err(e) => {
+            report_error(state, format!("loading drafts for {} failed: {}", username, e));
+            Vec::new()
+        }
+    }
+}
+
+
+// Whispers that came for an account while it was offline, under a header, for a device that just signed in
+// Each is handed over once, /mailbox shows them all again
+
+async fn mailbox_frames(state: &ServerState, username: &str) -> Vec<ChatMessage> {
+    let mail = match state.store.lock().await.mailbox(username, true) {
+        Ok(mail) => mail,
+        Err(e) => {
+            report_error(state, format!("loading the mailbox of {} failed: {}", username, e));
+            return Vec::new();
+        }
+    };
+    if mail.is_empty() {
+        return mail;
+    }
+    for msg in &mail {
+        seen_pending(state, username, &msg.username).await;
+    }
+    let header = format!("📬 {} offline message{} while you were away", mail.len(), if mail.len() == 1 { "" } else { "s" });
+    std::iter::once(ChatMessage::new("System", header, MessageType::SystemNotification)).chain(mail).collect()
+}
+
+
+// /whois: an account's profile, and for whoever is online how long they've been connected, how long since
+// they did anything and which rooms they're in (private ones only for those in them too, and moderators)
+// Someone on several devices is online since their first connection and idle since their latest activity
+
+async fn whois(state: &ServerState, client: &Client, user: &str) -> Vec<ChatMessage> {
+    let connections = state.presence.lock().await.connections_of(user);
+    let (account, (bio, pronouns), last_seen) = {
+        let store = state.store.lock().await;
+        let looked_up = store.account(user).and_then(|account| match account {
+            Some((name, _)) => Ok((Some(name), store.profile(user)?, store.last_seen(user)?)),
+            None => Ok((None, Default::default(), None)),
+        });
+        match looked_up {
+            Ok(found) => found,
+            Err(e) => {
+                report_error(state, format!("looking up {} for /whois failed: {}", user, e));
+                return vec![ChatMessage::error(ErrorKind::CommandFailed, "Looking them up failed, try again later")];
+            }
+        }
+    };
+    let Some(name) = account.clone().or_else(|| connections.first().map(|(_, entry)| entry.username.clone())) else {
+        return vec![ChatMessage::error(ErrorKind::CommandFailed, format!("Nobody called {} is online or has an account", user))];
+    };
+
+    let mut lines = vec![format!("👤 {}{}{}", name, if account.is_some() { " ✔" } else { "" }, pronouns.map(|p| format!(" ({})", p)).unwrap_or_default())];
+    if let Some(bio) = bio {
+        lines.push(format!("   {}", bio));
+    }
+    if connections.is_empty() {
+        match last_seen {
+            Some((when, presence)) => lines.push(format!("   Offline, {}", last_seen_text(&name, when, &presence))),
+            None => lines.push("   Offline".to_string()),
+        }
+    } else {
+        let online = connections.iter().map(|(_, entry)| entry.connected_at.elapsed()).max().unwrap_or_default();
+        let idle = connections.iter().map(|(_, entry)| entry.last_active.elapsed()).min().unwrap_or_default();
+        let devices = match connections.len() {
+            1 => String::new(),
+            n => format!(" on {} connections", n),
+        };
+        let dnd = if connections.iter().any(|(_, entry)| entry.dnd) { ", do not disturb" } else { "" };
+        lines.push(format!("   Online for {}{}, idle for {}{}", format_duration(online), devices, format_duration(idle), dnd));
+        let sees_all = client.can(state, Permission::Moderate);
+        let rooms = state.rooms.lock().await;
+        let mut shown: Vec<String> = connections
+            .iter()
+            .flat_map(|(id, _)| rooms.rooms_of(*id))
+            .filter(|room| sees_all || client.rooms.contains_key(room) || !(rooms.is_private(room) || room == OPS_ROOM))
+            .collect();
+        shown.sort();
+        shown.dedup();
+        if !shown.is_empty() {
+            lines.push(format!("   In {}", shown.iter().map(|room| format!("#{}", room)).collect::<Vec<_>>().join(", ")));
+        }
+    }
+    vec![ChatMessage::new("System", lines.join("\n"), MessageType::SystemNotification)]
+}
+
+
+// "last seen 3 hours ago (10/16/26 14:03): alice timed out"
+fn last_seen_text(name: &str, when: DateTime<Utc>, presence: &str) -> String {
+    let ago = (Utc::now() - when).to_std().unwrap_or_default().as_secs();
+    let (amount, unit) = match ago {
+        0..60 => return format!("last seen just now: {} {}", name, presence),
+        60..3600 => (ago / 60, "minute"),
+        3600..86400 => (ago / 3600, "hour"),
+        _ => (ago / 86400, "day"),
+    };
+    let local = when.with_timezone(&Local).format("%D %H:%M");
+    format!("last seen {} {}{} ago ({}): {} {}", amount, unit, if amount == 1 { "" } else { "s" }, local, name, presence)
+}
+
+// A message stamp for text we write out ourselves, in UTC since the server can't know the reader's time zone
+// (mail from before stamps were RFC 3339 is shown as it was kept)
+fn utc_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|at| at.with_timezone(&Utc).format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+
+// Remembers that `sender` whispered to `recipient`, for the receipt once the recipient has seen it
+async fn seen_pending(state: &ServerState, recipient: &str, sender: &str) {
+    state.unseen_whispers.lock().await.entry(recipient.to_lowercase()).or_default().insert(sender.to_lowercase());
+}
+
+
+// /shortcode for a signed-in account
+// Shortcodes are everyone's, so who can make them is up to the config and they are capped in number
+// and length. Only the account that made one (or a moderator) can change or remove it.
+
+async fn run_shortcode(action: ShortcodeAction, client: &Client, state: &ServerState, tx: &broadcast::Sender<String>) -> Vec<ChatMessage> {
+    let reply = |text: String| ChatMessage::new("System", text, MessageType::SystemNotification);
+    let fail = |text: &str| vec![ChatMessage::error(ErrorKind::CommandFailed, text)];
+    let failed = |e: rusqlite::Error| {
+        report_error(state, format!("updating shortcodes for {} failed: {}", client.username, e));
+        fail("Shortcodes could not be updated, try again later")
+    };
+    let config = state.config.borrow().shortcodes.clone();
+    let is_moderator = client.can(state, Permission::ManageShortcodes);
+    let store = state.store.lock().await;
+    let owner = match store.shortcode_owner(action_code(&action)) {
+        Ok(owner) => owner,
+        Err(e) => return failed(e),
+    };
+    if let Some(owner) = &owner
+        && !owner.eq_ignore_ascii_case(&client.username)
+        && !is_moderator
+    {
+        return fail(&format!(":{}: belongs to {}, only they or a moderator can change it", action_code(&action), owner));
+    }
+
+    let done = match action {
+        ShortcodeAction::Add { code, text } => {
+            if !(is_moderator || config.is_maker(&client.username)) {
+                return fail("Making shortcodes is up to the moderators on this server");
+            }
+            if text.len() > config.max_len {
+                return fail(&format!("A shortcode can stand for at most {} bytes", config.max_len));
+            }
+            if text.chars().any(char::is_control) {
+                return fail("A shortcode stands for emoji or a single line of text");
+            }
+            if owner.is_none() {
+                let (total, made) = match store.count_shortcodes(&client.username) {
+                    Ok(counts) => counts,
+                    Err(e) => return failed(e),
+                };
+                if total >= config.max {
+                    return fail(&format!("This server has all the {} shortcodes it takes", config.max));
+                }
+                if made >= config.max_per_user {
+                    return fail(&format!("You can make {} shortcodes, /shortcode remove one first", config.max_per_user));
+                }
+            }
+            // A moderator touching up someone's shortcode doesn't take it from them
+            if let Err(e) = store.save_shortcode(&code, &text, owner.as_deref().unwrap_or(&client.username)) {
+                return failed(e);
+            }
+            format!(":{}: now stands for {}", code, text)
+        }
+        ShortcodeAction::Remove { code } => {
+            if owner.is_none() {
+                return fail(&format!("There is no :{}: shortcode", code));
+            }
+            if let Err(e) = store.remove_shortcode(&code) {
+                return failed(e);
+            }
+            format!(":{}: is gone", code)
+        }
+    };
+    drop(store);
+    println!("└─[{}] {}: {}", Local::now().format("%D:%H:%M:%S"), client.username, done);
+    sync_shortcodes(state, tx).await;
+    vec![reply(done)]
+}
+
+fn action_code(action: &ShortcodeAction) -> &str {
+    match action {
+        ShortcodeAction::Add { code, .. } | ShortcodeAction::Remove { code } => code,
+    }
+}
+
+
+// Sends everyone the shortcodes as they are now
+
+async fn sync_shortcodes(state: &ServerState, tx: &broadcast::Sender<String>) {
+    let shortcodes = match state.store.lock().await.shortcodes() {
+        Ok(shortcodes) => shortcodes,
+        Err(e) => return report_error(state, format!("loading shortcodes failed: {}", e)),
+    };
+    if let Ok(json) = serde_json::to_string(&ChatMessage::shortcode_sync(shortcodes)) {
+        let _ = tx.send(json);
+    }
+}
+
+
+// /2fa for a signed-in account: setup (enable, then confirm with a code) and switching it off
+
+async fn run_two_factor(action: TwoFactorAction, account: &str, state: &ServerState) -> Vec<ChatMessage> {
+    let reply = |text: &str| ChatMessage::new("System", text, MessageType::SystemNotification);
+    let fail = |text: &str| vec![ChatMessage::error(ErrorKind::CommandFailed, text)];
+    let failed = |e: rusqlite::Error| {
+        report_error(state, format!("two-factor update for {} failed: {}", account, e));
+        fail("Two-factor setup failed, try again later")
+    };
+    let current = match state.store.lock().await.two_factor(account) {
+        Ok(current) => current,
+        Err(e) => return failed(e),
+    };
+
+    match action {
+        TwoFactorAction::Enable => {
+            if current.is_some_and(|current| current.enabled) {
+                return fail("Two-factor sign-in is already on");
+            }
+            let secret = totp::new_secret();
+            if let Err(e) = state.store.lock().await.begin_two_factor(account, &secret) {
+                return failed(e);
+            }
+            let setup = ChatMessage::new("System", totp::provisioning_uri(&secret, account, TOTP_ISSUER), MessageType::TwoFactorSetup);
+            vec![setup, reply("Scan the code with your authenticator app, then finish with /2fa confirm <code>")]
+        }
+        TwoFactorAction::Confirm { code } => {
+            let Some(current) = current.filter(|current| !current.enabled) else {
+                return fail("Start with /2fa enable");
+            };
+            let Some(step) = totp::verify(&current.secret, &code, unix_time()) else {
+                return fail("That code doesn't match, check the app's clock and try again");
+            };
+            // The backup codes are shown once and only their hashes are kept
+            let codes: Vec<String> = (0..totp::BACKUP_CODES).map(|_| totp::new_backup_code()).collect();
+            let mut hashes = Vec::with_capacity(codes.len());
+            for code in &codes {
+                match accounts::hash_password(code.clone()).await {
+                    Ok(hash) => hashes.push(hash),
+                    Err(e) => {
+                        report_error(state, format!("hashing backup codes for {} failed: {}", account, e));
+                        return fail("Two-factor setup failed, try again later");
+                    }
+                }
+            }
+            if let Err(e) = state.store.lock().await.enable_two_factor(account, step, &hashes) {
+                return failed(e);
+            }
+            println!("└─[{}] {} turned on two-factor sign-in", Local::now().format("%D:%H:%M:%S"), account);
+            vec![
+                reply("Two-factor sign-in is on, /login now needs a code from the app"),
+                ChatMessage::new("System", codes.join("\n"), MessageType::BackupCodes),
+            ]
+        }
+        TwoFactorAction::Disable { code } => {
+            if !current.is_some_and(|current| current.enabled) {
+                return fail("Two-factor sign-in is not on");
+            }
+            if let Err(refusal) = check_second_factor(state, account, &code).await {
+                return fail(refusal);
+            }
+            if let Err(e) = state.store.lock().await.disable_two_factor(account) {
+                return failed(e);
+            }
+            println!("└─[{}] {} turned off two-factor sign-in", Local::now().format("%D:%H:%M:%S"), account);
+            vec![reply("Two-factor sign-in is off")]
+        }
+    }
+}
+
+
+// Counts a failed /login against each of `keys`, refusing with `reason` or, once one is over the limit, with how long to wait
+
+async fn failed_login(state: &ServerState, client: &Client, keys: &[String], reason: &str) -> Vec<ChatMessage> {
+    let held_off = {
+        let mut failed_logins = state.failed_logins.lock().await;
+        let now = Instant::now();
+        keys.iter().filter_map(|key| failed_logins.check(key, now).err()).max()
+    };
+    let reason = match held_off {
+        Some(cooldown) => {
+            let seconds = cooldown.as_secs().max(1);
+            report(state, OpsEvent::RateLimit, format!("{} ({}) failed to log in too often, held off for {}s", client.username, client.addr.ip(), seconds));
+            format!("Too many failed logins, try again in {} seconds", seconds)
+        }
+        None => reason.to_string(),
+    };
+    vec![ChatMessage::error(ErrorKind::CommandFailed, reason)]
+}
+
+
+// Checks a code from the authenticator app, or a backup code (which is used up), for an account with 2FA on
+// A code from the app only works once
+
+async fn check_second_factor(state: &ServerState, account: &str, code: &str) -> Result<(), &'static str> {
+    const WRONG: &str = "Wrong two-factor code";
+    const UNAVAILABLE: &str = "Login failed, try again later";
+
+    if totp::looks_like_code(code) {
+        let store = state.store.lock().await;
+        let two_factor = match store.two_factor(account) {
+            Ok(Some(two_factor)) => two_factor,
+            Ok(None) => return Err(WRONG),
+            Err(e) => {
+                report_error(state, format!("two-factor lookup for {} failed: {}", account, e));
+                return Err(UNAVAILABLE);
+            }
+        };
+        let step = totp::verify(&two_factor.secret, code, unix_time()).ok_or(WRONG)?;
+        return match store.use_two_factor_step(account, step) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("That code was already used, wait for the next one"),
+            Err(e) => {
+                report_error(state, format!("recording two-factor use for {} failed: {}", account, e));
+                Err(UNAVAILABLE)
+            }
+        };
+    }
+
+    let backup_codes = state.store.lock().await.backup_codes(account).map_err(|e| {
+        report_error(state, format!("backup code lookup for {} failed: {}", account, e));
+        UNAVAILABLE
+    })?;
+    for (id, hash) in backup_codes {
+        if accounts::verify_password(code.to_string(), hash).await {
+            return match state.store.lock().await.remove_backup_code(id) {
+                Ok(true) => {
+                    println!("└─[{}] {} used a backup code", Local::now().format("%D:%H:%M:%S"), account);
+                    Ok(())
+                }
+                Ok(false) => Err(WRONG), // used by someone else in the meantime
+                Err(e) => {
+                    report_error(state, format!("using up a backup code for {} failed: {}", account, e));
+                    Err(UNAVAILABLE)
+                }
+            };
+        }
+    }
+    Err(WRONG)
+}
+
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default()
+}
+
+
+// Replaces the message of the day and shows the new one to everyone online
+
+async fn set_motd(state: &ServerState, tx: &broadcast::Sender<String>, text: String) {
+    let frame = motd_frame(&text);
+    *state.motd.lock().await = Some(text);
+    if let Ok(json) = serde_json::to_string(&frame) {
+        let _ = tx.send(json);
+    }
+}
+
+fn motd_frame(motd: &str) -> ChatMessage {
+    ChatMessage::new("MOTD", motd, MessageType::SystemNotification)
+}
+
+
+// Creates a session token for a signed-in account, None if it couldn't be stored
+
+async fn issue_session(state: &ServerState, username: &str) -> Option<ChatMessage> {
+    let token = accounts::new_session_token();
+    match state.store.lock().await.create_session(&token, username) {
+        Ok(()) => Some(ChatMessage::session(username, token)),
+        Err(e) => {
+            report_error(state, format!("creating a session for {} failed: {}", username, e));
+            None
+        }
+    }
+}
+
+
+
+// Writes a single frame straight to one client (used before the client joins the broadcast)
+
+async fn send_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &ChatMessage, tracer: &WireTracer, peer: &str) {
+    if let Ok(json) = serde_json::to_string(frame) {
+        tracer.record(Direction::Sent, peer, &json);
+        let _ = writer.write_all(json.as_bytes()).await;
+        let _ = writer.write_all(b"\n").await;
+        let _ = writer.flush().await;
+    }
+}
+
+
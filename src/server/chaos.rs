@@ -0,0 +1,57 @@
+// Failures injected on purpose, for trying clients against a real server in staging
+// Only reachable through /chaos when the config says [chaos] enabled = true, and everything is off until then.
+// lag: whatever the server writes to a connection waits this long first, pings and command replies included
+// partition: every connection is cut without a goodbye and new ones are hung up on until it's over,
+// so clients have to reconnect, resume their session and catch up on what they missed
+
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Most lag /chaos lag takes, in milliseconds
+pub const MAX_LAG_MS: u64 = 10_000;
+// Longest partition /chaos partition takes
+pub const MAX_PARTITION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Default)]
+pub struct Chaos {
+    lag_ms: AtomicU64,
+    partition_until: Mutex<Option<Instant>>,
+}
+
+impl Chaos {
+    pub fn lag(&self) -> Duration {
+        Duration::from_millis(self.lag_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn set_lag(&self, lag: Duration) {
+        self.lag_ms.store(lag.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    // Starts a partition (or moves the end of the current one)
+    pub fn partition(&self, length: Duration) {
+        *self.until() = Some(Instant::now() + length);
+    }
+
+    // How much longer the partition lasts, None when there isn't one
+    pub fn partitioned(&self) -> Option<Duration> {
+        let mut until = self.until();
+        let left = until.map(|until| until.saturating_duration_since(Instant::now())).filter(|left| !left.is_zero());
+        if left.is_none() {
+            *until = None;
+        }
+        left
+    }
+
+    // Back to normal, false when nothing was going on
+    pub fn heal(&self) -> bool {
+        let lagging = self.lag_ms.swap(0, Ordering::Relaxed) > 0;
+        let partitioned = self.partitioned().is_some();
+        *self.until() = None;
+        lagging || partitioned
+    }
+
+    fn until(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.partition_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
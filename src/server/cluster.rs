@@ -0,0 +1,299 @@
+// Cluster mode (redis feature, [redis] in the config): several server processes behind a TCP load balancer
+// that look like one. Each process keeps its own connections, and through Redis:
+// - chat posted in a room is published on <prefix>:events, and every other process posts it in that room too
+// - room history lives in <prefix>:history:<room> (newest first), so a room opening anywhere gets it back
+// - who's online on each process lives in <prefix>:online:<process>, refreshed while the process runs and gone
+//   a minute after it dies, so the user count and the roster cover every process
+// Chat from another process isn't stored in this one's database and doesn't go through its plugins (the process
+// it was posted on did that). Message ids are only good on the process that gave them, so chat crosses over
+// without one: replies, reactions and edits only work on the process a message was posted on.
+// Redis is talked to from a background task that reconnects on its own, nothing waits on it but loading history.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use super::config::RedisConfig;
+use crate::protocol::{ChatMessage, Member};
+
+// A process counts as online this long (seconds) after it last said who's on it, it says so this often
+#[cfg(feature = "redis")]
+const ONLINE_TTL: u64 = 60;
+#[cfg(feature = "redis")]
+const ONLINE_REFRESH: Duration = Duration::from_secs(20);
+
+// How long loading a room's history waits for Redis before the room opens without it
+const HISTORY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Chat posted on the other processes: the room and the message
+pub type Remote = mpsc::UnboundedReceiver<(String, ChatMessage)>;
+
+// Who's on one process, or on all of them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Online {
+    pub connections: usize,
+    pub members: Vec<Member>,
+}
+
+impl Online {
+    // Connections add up, members show up once (signed in if they are anywhere), sorted like a process's roster
+    pub fn merge(all: impl IntoIterator<Item = Online>) -> Online {
+        let mut merged = Online::default();
+        for online in all {
+            merged.connections += online.connections;
+            merged.members.extend(online.members);
+        }
+        merged.members.sort_by(|a, b| a.username.to_lowercase().cmp(&b.username.to_lowercase()).then(b.verified.cmp(&a.verified)));
+        // Do not disturb on one process is do not disturb for the name
+        merged.members.dedup_by(|a, b| {
+            let same = a.username.eq_ignore_ascii_case(&b.username);
+            b.dnd |= same && a.dnd;
+            same
+        });
+        merged
+    }
+}
+
+// What goes over <prefix>:events, tagged with the process it came from so that one can skip it
+#[cfg(feature = "redis")]
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    process: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[cfg(feature = "redis")]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    // Chat posted in a room
+    Chat { room: String, message: Box<ChatMessage> },
+    // Who's online there changed, time to look again
+    Online,
+}
+
+// What the server asks of the background task
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+enum Request {
+    Chat { room: String, message: Box<ChatMessage>, keep: usize },
+    History { room: String, size: usize, reply: oneshot::Sender<Result<Vec<ChatMessage>, String>> },
+    Online(Online),
+}
+
+pub struct Cluster {
+    requests: mpsc::UnboundedSender<Request>,
+    count: watch::Receiver<usize>,
+    roster: watch::Receiver<Vec<Member>>,
+}
+
+impl Cluster {
+    // Hands chat posted here to the other processes and adds it to the room's history, which keeps `keep` messages
+    pub fn publish(&self, room: &str, msg: &ChatMessage, keep: usize) {
+        let mut message = msg.clone();
+        message.id = None;
+        let _ = self.requests.send(Request::Chat { room: room.to_string(), message: Box::new(message), keep });
+    }
+
+    // The last `size` messages of a room, oldest first
+    pub async fn history(&self, room: &str, size: usize) -> Result<Vec<ChatMessage>, String> {
+        let (reply, history) = oneshot::channel();
+        let _ = self.requests.send(Request::History { room: room.to_string(), size, reply });
+        match tokio::time::timeout(HISTORY_TIMEOUT, history).await {
+            Ok(Ok(history)) => history,
+            Ok(Err(_)) => Err("the Redis task is gone".to_string()),
+            Err(_) => Err("Redis didn't answer in time".to_string()),
+        }
+    }
+
+    // Who's on this process now
+    pub fn set_online(&self, online: Online) {
+        let _ = self.requests.send(Request::Online(online));
+    }
+
+    // Follows the number of connections on every process
+    pub fn watch_count(&self) -> watch::Receiver<usize> {
+        self.count.clone()
+    }
+
+    // Follows who is online on any process
+    pub fn watch_roster(&self) -> watch::Receiver<Vec<Member>> {
+        self.roster.clone()
+    }
+
+    // The count and roster as last seen
+    pub fn online(&self) -> (usize, Vec<Member>) {
+        (*self.count.borrow(), self.roster.borrow().clone())
+    }
+}
+
+// Joins the cluster when there's a [redis] section, what the other processes post comes out of the returned channel
+#[cfg(feature = "redis")]
+pub fn start(config: Option<RedisConfig>) -> Result<Option<(Cluster, Remote)>, String> {
+    use rand::Rng;
+
+    let Some(config) = config else { return Ok(None) };
+    let client = redis::Client::open(config.url.as_str()).map_err(|e| format!("redis.url {}: {}", config.url, e))?;
+    let (requests, pending) = mpsc::unbounded_channel();
+    let (remote, posted) = mpsc::unbounded_channel();
+    let (count, count_rx) = watch::channel(0);
+    let (roster, roster_rx) = watch::channel(Vec::new());
+    let task = Task {
+        client,
+        prefix: config.prefix,
+        process: format!("{:08x}", rand::rng().random::<u32>()),
+        remote,
+        here: Online::default(),
+        count,
+        roster,
+    };
+    tokio::spawn(task.run(pending));
+    Ok(Some((Cluster { requests, count: count_rx, roster: roster_rx }, posted)))
+}
+
+#[cfg(not(feature = "redis"))]
+pub fn start(config: Option<RedisConfig>) -> Result<Option<(Cluster, Remote)>, String> {
+    match config {
+        Some(_) => Err("this server was built without Redis, build it with --features redis".to_string()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(feature = "redis")]
+struct Task {
+    client: redis::Client,
+    prefix: String,
+    // This process's name in the cluster, made up at startup
+    process: String,
+    remote: mpsc::UnboundedSender<(String, ChatMessage)>,
+    // Who's on this process, said again after every reconnect
+    here: Online,
+    count: watch::Sender<usize>,
+    roster: watch::Sender<Vec<Member>>,
+}
+
+#[cfg(feature = "redis")]
+impl Task {
+    // Keeps talking to Redis until the server winds down, starting over a while after anything goes wrong
+    async fn run(mut self, mut requests: mpsc::UnboundedReceiver<Request>) {
+        loop {
+            match self.session(&mut requests).await {
+                Ok(()) => return,
+                Err(e) => eprintln!("[WARN] Redis: {}, trying again in 5s", e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    // One connection (plus the subscription): handle requests and events until either fails
+    async fn session(&mut self, requests: &mut mpsc::UnboundedReceiver<Request>) -> Result<(), String> {
+        use tokio_stream::StreamExt;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await.map_err(|e| e.to_string())?;
+        let mut pubsub = self.client.get_async_pubsub().await.map_err(|e| e.to_string())?;
+        pubsub.subscribe(self.key("events")).await.map_err(|e| e.to_string())?;
+        let mut events = pubsub.into_on_message();
+        println!("🧩 Joined the cluster as process {}", self.process);
+
+        let mut refresh = tokio::time::interval(ONLINE_REFRESH);
+        loop {
+            tokio::select! {
+                request = requests.recv() => {
+                    let Some(request) = request else { return Ok(()) };
+                    self.handle(&mut conn, request).await?;
+                }
+                event = events.next() => {
+                    let Some(event) = event else { return Err("the subscription dropped".to_string()) };
+                    let Ok(payload) = event.get_payload::<String>() else { continue };
+                    let Ok(envelope) = serde_json::from_str::<Envelope>(&payload) else { continue };
+                    if envelope.process == self.process {
+                        continue;
+                    }
+                    match envelope.event {
+                        Event::Chat { room, message } => {
+                            let _ = self.remote.send((room, *message));
+                        }
+                        Event::Online => self.look_around(&mut conn).await?,
+                    }
+                }
+                _ = refresh.tick() => {
+                    self.say_online(&mut conn).await?;
+                    self.look_around(&mut conn).await?;
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, conn: &mut redis::aio::MultiplexedConnection, request: Request) -> Result<(), String> {
+        match request {
+            Request::Chat { room, message, keep } => {
+                let history = self.key(&format!("history:{}", room));
+                let json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+                let envelope = self.envelope(Event::Chat { room, message })?;
+                redis::pipe()
+                    .cmd("PUBLISH").arg(self.key("events")).arg(envelope).ignore()
+                    .cmd("LPUSH").arg(&history).arg(json).ignore()
+                    .cmd("LTRIM").arg(&history).arg(0).arg(keep.max(1) as i64 - 1).ignore()
+                    .query_async::<()>(conn)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            Request::History { room, size, reply } => {
+                let found = redis::cmd("LRANGE")
+                    .arg(self.key(&format!("history:{}", room)))
+                    .arg(0)
+                    .arg(size as i64 - 1)
+                    .query_async::<Vec<String>>(conn)
+                    .await;
+                let history = found
+                    .map(|newest_first| newest_first.iter().rev().filter_map(|json| serde_json::from_str(json).ok()).collect())
+                    .map_err(|e| e.to_string());
+                let _ = reply.send(history);
+                Ok(())
+            }
+            Request::Online(online) => {
+                self.here = online;
+                self.say_online(conn).await?;
+                let envelope = self.envelope(Event::Online)?;
+                redis::cmd("PUBLISH").arg(self.key("events")).arg(envelope).query_async::<()>(conn).await.map_err(|e| e.to_string())?;
+                self.look_around(conn).await
+            }
+        }
+    }
+
+    // Writes who's on this process, good for ONLINE_TTL
+    async fn say_online(&self, conn: &mut redis::aio::MultiplexedConnection) -> Result<(), String> {
+        let json = serde_json::to_string(&self.here).map_err(|e| e.to_string())?;
+        redis::cmd("SET")
+            .arg(self.key(&format!("online:{}", self.process)))
+            .arg(json)
+            .arg("EX")
+            .arg(ONLINE_TTL)
+            .query_async::<()>(conn)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    // Reads who's on every process, watchers only hear about real changes
+    // (KEYS is fine here, there's one key per process)
+    async fn look_around(&self, conn: &mut redis::aio::MultiplexedConnection) -> Result<(), String> {
+        let keys: Vec<String> = redis::cmd("KEYS").arg(self.key("online:*")).query_async(conn).await.map_err(|e| e.to_string())?;
+        let everywhere: Vec<Option<String>> = match keys.is_empty() {
+            true => Vec::new(),
+            false => redis::cmd("MGET").arg(&keys).query_async(conn).await.map_err(|e| e.to_string())?,
+        };
+        let merged = Online::merge(everywhere.iter().flatten().filter_map(|json| serde_json::from_str(json).ok()));
+        self.count.send_if_modified(|current| std::mem::replace(current, merged.connections) != merged.connections);
+        self.roster.send_if_modified(|current| std::mem::replace(current, merged.members.clone()) != merged.members);
+        Ok(())
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    fn envelope(&self, event: Event) -> Result<String, String> {
+        serde_json::to_string(&Envelope { process: self.process.clone(), event }).map_err(|e| e.to_string())
+    }
+}
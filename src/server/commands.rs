@@ -0,0 +1,550 @@
+// Slash commands the server understands
+// Lines that start with an unknown command are treated as ordinary chat text.
+
+use std::time::Duration;
+
+use crate::protocol::CommandInfo;
+
+use super::chaos::{MAX_LAG_MS, MAX_PARTITION};
+use super::roles::Role;
+
+// Longest emoji (in characters) accepted by /react, enough for flags and skin tones
+pub const MAX_REACTION_LEN: usize = 8;
+// Longest stamp /ping echoes back, plenty for a timestamp
+pub const MAX_PING_STAMP_LEN: usize = 32;
+// Longest draft (in bytes) /draft keeps for the account's other devices
+pub const MAX_DRAFT_LEN: usize = 4096;
+// Longest /mute there is, a month
+pub const MAX_MUTE: Duration = Duration::from_secs(30 * 24 * 3600);
+// Longest reason (in characters) /report takes
+pub const MAX_REPORT_LEN: usize = 300;
+// Longest part of the original (in characters) a reply quotes
+pub const MAX_QUOTE_LEN: usize = 200;
+// Longest room topic (in characters) /topic takes
+pub const MAX_TOPIC_LEN: usize = 200;
+// Longest bio (in characters) /profile keeps, and longest pronouns
+pub const MAX_BIO_LEN: usize = 160;
+pub const MAX_PRONOUNS_LEN: usize = 24;
+// Longest :shortcode: (without its colons) /shortcode takes
+pub const MAX_SHORTCODE_LEN: usize = 32;
+
+// The commands people type, for the Capabilities frame clients build their help from
+// The ones clients send on their own (/draft, /read, /sign, /reply) aren't listed
+pub const HELP: [(&str, &str); 32] = [
+    ("/register <password>", "Register your current name"),
+    ("/login <name> <password> [code]", "Sign in to your account (code only with 2FA on)"),
+    ("/passwd <old> <new>", "Change your password"),
+    ("/2fa enable|confirm <code>|disable <code>", "Two-factor sign-in with an authenticator app"),
+    ("/motd [text]", "Show the message of the day (owners can replace it)"),
+    ("/stats", "How the server is doing (more for moderators)"),
+    ("/ping", "Time a round trip to the server"),
+    ("/whisper <user> <text>", "Side comment only they see (or /w, /msg), kept for them if they have an account and are offline"),
+    ("/mailbox [clear]", "Whispers that came while you were offline, or throw them away"),
+    ("/whois <user>", "Their profile, and how long they've been online (and idle) and in which rooms"),
+    ("/seen <user>", "When they were last around, if they're offline"),
+    ("/profile set|clear bio|pronouns [text]", "What /whois says about you (signed in)"),
+    ("/me <action>", "Act it out, shown as * name action"),
+    ("/react <id> <emoji>", "React to message #id (again to take it back)"),
+    ("/report <id> <reason>", "Flag message #id for the moderators"),
+    ("/pin <id>", "Pin message #id at the top of its room, /unpin <id> to take it off (moderators)"),
+    ("/shortcode add <code> <text> | remove <code>", "Share a :shortcode: with everyone (signed in)"),
+    ("/join <room> [password]", "Join a room, or switch to one you're in (the password for private rooms)"),
+    ("/create <room> --private [password]", "Make a private room (signed in)"),
+    ("/invite <user> [room]", "Let someone into a private room"),
+    ("/rooms", "The public rooms, with who's in them and their topics"),
+    ("/leave [room]", "Leave a room (the current one by default)"),
+    ("/topic [text]", "Show the room's topic, or set it (/topic - to take it away, moderators)"),
+    ("/roommode <room> readonly|open|public", "Only announcers can post there, or everyone again; public lets everyone into a private room (owners)"),
+    ("/kick <user> [reason]", "Disconnect someone (moderators)"),
+    ("/ban <user> [reason]", "Disconnect someone and keep them out (moderators)"),
+    ("/mute <user> <30s|10m|2h|1d> [reason]", "Silence someone for a while (moderators)"),
+    ("/unmute <user>", "Let them talk again (moderators)"),
+    ("/promote <user> [role]", "One role up, or to guest, member, moderator, owner (owners)"),
+    ("/demote <user> [role]", "One role down, or to the role given (owners)"),
+    ("/auditlog [user]", "Recent kicks, bans and mutes, or one user's (moderators)"),
+    ("/chaos drop-client <user>|lag <ms>|partition <duration>|off", "Inject failures, staging only (owners)"),
+];
+
+pub fn help() -> Vec<CommandInfo> {
+    HELP.iter().map(|(usage, about)| CommandInfo { usage: usage.to_string(), about: about.to_string() }).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // /register <password>: claims the current username
+    Register { password: String },
+    // /login <username> <password> [code]: signs in to an existing account,
+    // the code (from an authenticator app, or a backup code) only when it has two-factor sign-in
+    Login { username: String, password: String, code: Option<String> },
+    // /2fa enable|confirm <code>|disable <code>: two-factor sign-in for our account
+    TwoFactor { action: TwoFactorAction },
+    // /passwd <old> <new>: changes the password of the account we're signed in to
+    Passwd { old: String, new: String },
+    // /motd [text]: shows the message of the day, owners can replace it
+    Motd { text: Option<String> },
+    // /whisper <user> <text> (or /w, /msg): a side comment only that user sees,
+    // kept in their mailbox when they have an account and are offline
+    // /ewhisper <user> <sealed>: the same sealed end to end by the client, the server can't read it
+    Whisper { to: String, text: String, encrypted: bool },
+    // /mailbox [clear]: the whispers that came while we were offline, or throws them away
+    Mailbox { clear: bool },
+    // /whois <user>: someone's profile, whether they're online, for how long and in which rooms
+    Whois { user: String },
+    // /seen <user>: when an account was last around, or that they're online now
+    Seen { user: String },
+    // /profile [set bio|pronouns <text> | clear bio|pronouns]: what /whois says about our account
+    Profile { action: ProfileAction },
+    // /react <id> <emoji>: adds (or takes back) a reaction to a message
+    React { message_id: i64, emoji: String },
+    // /pin <id> and /unpin <id>: moderators keep messages at the top of their room
+    Pin { message_id: i64 },
+    Unpin { message_id: i64 },
+    // /report <id> <reason>: flags a message for the moderators
+    Report { message_id: i64, reason: String },
+    // /join <room> [password]: joins a room (or switches to it) and talks there from now on,
+    // the password only for private rooms we weren't invited to
+    Join { room: String, password: Option<String> },
+    // /create <room> --private [password]: a room only invited accounts (or those with the password) can join
+    Create { room: String, password: Option<String> },
+    // /invite <user> [room]: lets an account into a private room, the current one when none is given
+    Invite { user: String, room: Option<String> },
+    // /rooms: the public rooms, with how many are in each and their topics
+    Rooms,
+    // /leave [room]: leaves a room, the current one when none is given
+    Leave { room: Option<String> },
+    // /roommode <room> readonly|open: owners decide whether only announcers can post in a room
+    RoomMode { room: String, read_only: bool },
+    // /roommode <room> public: owners open a private room to everyone again
+    RoomPublic { room: String },
+    // /topic [text]: shows what the current room is about, moderators can set it (or take it away with "/topic -")
+    // Some("") is taking it away
+    Topic { text: Option<String> },
+    // /stats: how the server is doing, in full for moderators
+    Stats,
+    // /ping <stamp>: the client measuring the round trip, the stamp comes straight back in a Ping frame
+    Ping { stamp: String },
+    // /shortcode add <code> <text> | remove <code>: shortcodes shared with everyone
+    Shortcode { action: ShortcodeAction },
+    // /draft <room> [text]: what we're typing in a room, for our other devices (sent by the client, debounced)
+    Draft { room: String, text: String },
+    // /read <room> <id>: we read #room up to message #id (sent by the client once it scrolled down to it)
+    Read { room: String, id: i64 },
+    // /read @<user>: we saw the whispers <user> sent us, so they can be told (sent by the client too)
+    ReadWhispers { from: String },
+    // /dnd on|off: do not disturb, shown by our name in the roster (sent by the client's /dnd)
+    Dnd { on: bool },
+    // /sign <stamp> <signature>: ed25519 signature (base64) of the message that comes next, its room and the
+    // stamp (see protocol::signed_text, sent by the client)
+    Sign { stamp: u64, signature: String },
+    // /kick <user> [reason]: disconnects someone, telling them why
+    Kick { user: String, reason: Option<String> },
+    // /ban <user> [reason]: disconnects someone and refuses their addresses (not loopback) and account
+    Ban { user: String, reason: Option<String> },
+    // /promote <user> [role] and /demote <user> [role]: one step up or down, or straight to the role given
+    Promote { user: String, role: Option<Role> },
+    Demote { user: String, role: Option<Role> },
+    // /mute <user> <duration> [reason]: moderators silence someone for a while (30s, 10m, 2h, 1d)
+    Mute { user: String, duration: Duration, reason: Option<String> },
+    // /unmute <user>: lets them talk again before the time is up
+    Unmute { user: String },
+    // /auditlog [user]: moderators look through recent moderation, or what was done to one user
+    AuditLog { target: Option<String> },
+    // /chaos drop-client <user> | lag <ms> | partition <duration> | off: owners break things on purpose (staging)
+    Chaos { action: ChaosAction },
+}
+
+// "/me <text>" isn't a command but chat, posted as an action
+// None means the line isn't an action; Err carries the usage hint
+pub fn parse_action(line: &str) -> Option<Result<&str, String>> {
+    let rest = line.strip_prefix("/me")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None; // "/meh" and the like are just text
+    }
+    match rest.trim() {
+        "" => Some(Err("Usage: /me <action>".to_string())),
+        action => Some(Ok(action)),
+    }
+}
+
+// "/reply <id> <text>" is chat too, quoting message #id (sent by the client for a message typed with a quote up)
+// None means the line isn't a reply; Err carries the usage hint
+pub fn parse_reply(line: &str) -> Option<Result<(i64, &str), String>> {
+    let rest = line.strip_prefix("/reply")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let (id, text) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    match (id.trim_start_matches('#').parse(), text.trim()) {
+        (Ok(id), text) if !text.is_empty() => Some(Ok((id, text))),
+        _ => Some(Err("Usage: /reply <message id> <text>".to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TwoFactorAction {
+    // Starts setup: a new secret to scan, not required at sign-in until confirmed
+    Enable,
+    // Finishes setup with a code from the app, which proves it was scanned
+    Confirm { code: String },
+    // Turns it off again, given a code or a backup code
+    Disable { code: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortcodeAction {
+    // Makes :code: stand for `text` (or changes what one of ours stands for)
+    Add { code: String, text: String },
+    Remove { code: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileAction {
+    // Shows our own, as /whois would
+    Show,
+    Set { field: ProfileField, text: String },
+    Clear { field: ProfileField },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileField {
+    Bio,
+    Pronouns,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChaosAction {
+    // Cuts someone's connections without a goodbye
+    DropClient { user: String },
+    // Holds back everything the server writes by this much, zero for none
+    Lag { lag: Duration },
+    // Cuts everyone and turns new connections away for this long
+    Partition { length: Duration },
+    // Stops the lag and ends a partition early
+    Off,
+}
+
+impl Command {
+    // The command word without its slash, safe to log (no arguments)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Register { .. } => "register",
+            Command::Login { .. } => "login",
+            Command::TwoFactor { .. } => "2fa",
+            Command::Passwd { .. } => "passwd",
+            Command::Motd { .. } => "motd",
+            Command::Whisper { .. } => "whisper",
+            Command::Whois { .. } => "whois",
+            Command::Seen { .. } => "seen",
+            Command::Profile { .. } => "profile",
+            Command::Mailbox { .. } => "mailbox",
+            Command::React { .. } => "react",
+            Command::Pin { .. } => "pin",
+            Command::Unpin { .. } => "unpin",
+            Command::Report { .. } => "report",
+            Command::Join { .. } => "join",
+            Command::Create { .. } => "create",
+            Command::Invite { .. } => "invite",
+            Command::Rooms => "rooms",
+            Command::Leave { .. } => "leave",
+            Command::RoomMode { .. } | Command::RoomPublic { .. } => "roommode",
+            Command::Topic { .. } => "topic",
+            Command::Ping { .. } => "ping",
+            Command::Stats => "stats",
+            Command::Shortcode { .. } => "shortcode",
+            Command::Draft { .. } => "draft",
+            Command::Read { .. } | Command::ReadWhispers { .. } => "read",
+            Command::Dnd { .. } => "dnd",
+            Command::Sign { .. } => "sign",
+            Command::Kick { .. } => "kick",
+            Command::Ban { .. } => "ban",
+            Command::Promote { .. } => "promote",
+            Command::Demote { .. } => "demote",
+            Command::Mute { .. } => "mute",
+            Command::Unmute { .. } => "unmute",
+            Command::Chaos { .. } => "chaos",
+            Command::AuditLog { .. } => "auditlog",
+        }
+    }
+
+    // None means "not a server command"; Err carries a usage hint for the sender
+    pub fn parse(line: &str) -> Option<Result<Command, String>> {
+        let mut words = line.split_whitespace();
+        let name = words.next()?;
+        // Everything after the command word, for commands that take free text
+        let rest = line.trim_start()[name.len()..].trim();
+        let command = match name {
+            "/register" => match (words.next(), words.next()) {
+                (Some(password), None) => Ok(Command::Register { password: password.to_string() }),
+                _ => Err("Usage: /register <password>".to_string()),
+            },
+            "/login" => match (words.next(), words.next(), words.next(), words.next()) {
+                (Some(username), Some(password), code, None) => Ok(Command::Login {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                    code: code.map(str::to_string),
+                }),
+                _ => Err("Usage: /login <username> <password> [2fa code]".to_string()),
+            },
+            "/2fa" => match (words.next(), words.next(), words.next()) {
+                (Some("enable"), None, None) => Ok(Command::TwoFactor { action: TwoFactorAction::Enable }),
+                (Some("confirm"), Some(code), None) => {
+                    Ok(Command::TwoFactor { action: TwoFactorAction::Confirm { code: code.to_string() } })
+                }
+                (Some("disable"), Some(code), None) => {
+                    Ok(Command::TwoFactor { action: TwoFactorAction::Disable { code: code.to_string() } })
+                }
+                _ => Err("Usage: /2fa enable | /2fa confirm <code> | /2fa disable <code>".to_string()),
+            },
+            "/passwd" => match (words.next(), words.next(), words.next()) {
+                (Some(old), Some(new), None) => Ok(Command::Passwd { old: old.to_string(), new: new.to_string() }),
+                _ => Err("Usage: /passwd <old password> <new password>".to_string()),
+            },
+            "/mailbox" => match (words.next(), words.next()) {
+                (None, None) => Ok(Command::Mailbox { clear: false }),
+                (Some("clear"), None) => Ok(Command::Mailbox { clear: true }),
+                _ => Err("Usage: /mailbox [clear]".to_string()),
+            },
+            "/whois" | "/seen" => match (words.next(), words.next()) {
+                (Some(user), None) => {
+                    let user = user.to_string();
+                    Ok(if name == "/whois" { Command::Whois { user } } else { Command::Seen { user } })
+                }
+                _ => Err(format!("Usage: {} <user>", name)),
+            },
+            "/profile" => {
+                let usage = "Usage: /profile [set bio|pronouns <text> | clear bio|pronouns]".to_string();
+                let field = match words.nth(1) {
+                    Some("bio") => Some(ProfileField::Bio),
+                    Some("pronouns") => Some(ProfileField::Pronouns),
+                    _ => None,
+                };
+                match (rest.split_whitespace().next(), field) {
+                    (None, _) => Ok(Command::Profile { action: ProfileAction::Show }),
+                    (Some("clear"), Some(field)) if words.next().is_none() => Ok(Command::Profile { action: ProfileAction::Clear { field } }),
+                    (Some("set"), Some(field)) => {
+                        // The text is the rest of the line, spaces and all
+                        let text = rest["set".len()..].trim_start();
+                        let text = text.split_once(char::is_whitespace).map_or("", |(_, text)| text.trim());
+                        let max = match field {
+                            ProfileField::Bio => MAX_BIO_LEN,
+                            ProfileField::Pronouns => MAX_PRONOUNS_LEN,
+                        };
+                        match text.chars().count() {
+                            0 => Err(usage),
+                            len if len > max => Err(format!("That's {} characters, keep it to {}", len, max)),
+                            _ => Ok(Command::Profile { action: ProfileAction::Set { field, text: text.to_string() } }),
+                        }
+                    }
+                    _ => Err(usage),
+                }
+            }
+            "/whisper" | "/w" | "/msg" | "/ewhisper" => match words.next() {
+                Some(to) if !rest[to.len()..].trim().is_empty() => Ok(Command::Whisper {
+                    to: to.to_string(),
+                    text: rest[to.len()..].trim().to_string(),
+                    encrypted: name == "/ewhisper",
+                }),
+                _ => Err("Usage: /whisper <user> <text>".to_string()),
+            },
+            "/react" => match (words.next(), words.next(), words.next()) {
+                (Some(id), Some(emoji), None) => match id.trim_start_matches('#').parse() {
+                    Ok(message_id) if emoji.chars().count() <= MAX_REACTION_LEN => {
+                        Ok(Command::React { message_id, emoji: emoji.to_string() })
+                    }
+                    Ok(_) => Err("That reaction is too long, use a single emoji".to_string()),
+                    Err(_) => Err("Usage: /react <message id> <emoji>".to_string()),
+                },
+                _ => Err("Usage: /react <message id> <emoji>".to_string()),
+            },
+            "/pin" | "/unpin" => match (words.next().map(|id| id.trim_start_matches('#').parse()), words.next()) {
+                (Some(Ok(message_id)), None) if name == "/pin" => Ok(Command::Pin { message_id }),
+                (Some(Ok(message_id)), None) => Ok(Command::Unpin { message_id }),
+                _ => Err(format!("Usage: {} <message id>", name)),
+            },
+            "/report" => {
+                let usage = || "Usage: /report <message id> <reason>".to_string();
+                match words.next().map(|id| (id, id.trim_start_matches('#').parse())) {
+                    Some((id, Ok(message_id))) => match rest[id.len()..].trim() {
+                        "" => Err(usage()),
+                        reason if reason.chars().count() > MAX_REPORT_LEN => {
+                            Err(format!("Keep the reason under {} characters", MAX_REPORT_LEN))
+                        }
+                        reason => Ok(Command::Report { message_id, reason: reason.to_string() }),
+                    },
+                    _ => Err(usage()),
+                }
+            }
+            "/join" => match (words.next(), words.next(), words.next()) {
+                (Some(room), password, None) => Ok(Command::Join { room: room.to_string(), password: password.map(str::to_string) }),
+                _ => Err("Usage: /join <room> [password]".to_string()),
+            },
+            "/create" => match (words.next(), words.next(), words.next(), words.next()) {
+                (Some(room), Some("--private"), password, None) => {
+                    Ok(Command::Create { room: room.to_string(), password: password.map(str::to_string) })
+                }
+                _ => Err("Usage: /create <room> --private [password]".to_string()),
+            },
+            "/invite" => match (words.next(), words.next(), words.next()) {
+                (Some(user), room, None) => Ok(Command::Invite { user: user.to_string(), room: room.map(str::to_string) }),
+                _ => Err("Usage: /invite <user> [room]".to_string()),
+            },
+            "/rooms" => match words.next() {
+                None => Ok(Command::Rooms),
+                Some(_) => Err("Usage: /rooms".to_string()),
+            },
+            "/leave" => match (words.next(), words.next()) {
+                (room, None) => Ok(Command::Leave { room: room.map(str::to_string) }),
+                _ => Err("Usage: /leave [room]".to_string()),
+            },
+            "/roommode" => match (words.next(), words.next(), words.next()) {
+                (Some(room), Some(mode @ ("readonly" | "open")), None) => {
+                    Ok(Command::RoomMode { room: room.to_string(), read_only: mode == "readonly" })
+                }
+                (Some(room), Some("public"), None) => Ok(Command::RoomPublic { room: room.to_string() }),
+                _ => Err("Usage: /roommode <room> readonly|open|public".to_string()),
+            },
+            "/topic" => match rest {
+                "" => Ok(Command::Topic { text: None }),
+                "-" => Ok(Command::Topic { text: Some(String::new()) }),
+                text if text.chars().count() > MAX_TOPIC_LEN => Err(format!("Keep the topic under {} characters", MAX_TOPIC_LEN)),
+                text => Ok(Command::Topic { text: Some(text.to_string()) }),
+            },
+            "/ping" => match (words.next(), words.next()) {
+                (Some(stamp), None) if stamp.len() <= MAX_PING_STAMP_LEN => Ok(Command::Ping { stamp: stamp.to_string() }),
+                _ => Err("Usage: /ping <stamp>".to_string()),
+            },
+            "/dnd" => match (words.next(), words.next()) {
+                (Some(state @ ("on" | "off")), None) => Ok(Command::Dnd { on: state == "on" }),
+                _ => Err("Usage: /dnd on|off".to_string()),
+            },
+            "/stats" => match words.next() {
+                None => Ok(Command::Stats),
+                Some(_) => Err("Usage: /stats".to_string()),
+            },
+            "/shortcode" => {
+                let usage = || "Usage: /shortcode add <code> <emoji or text> | /shortcode remove <code>".to_string();
+                let code = words.nth(1).map(|code| code.trim_matches(':').to_lowercase());
+                let valid = code.as_deref().is_some_and(|code| {
+                    !code.is_empty()
+                        && code.chars().count() <= MAX_SHORTCODE_LEN
+                        && code.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '+'))
+                });
+                match (rest.split_whitespace().next(), code) {
+                    (Some(_), Some(_)) if !valid => {
+                        Err(format!("Shortcodes are 1-{} letters, digits, '_', '-' or '+'", MAX_SHORTCODE_LEN))
+                    }
+                    (Some("add"), Some(code)) => {
+                        // What it stands for is the rest of the line, spaces and all
+                        let after_add = rest["add".len()..].trim_start();
+                        match after_add.split_once(char::is_whitespace).map_or("", |(_, text)| text.trim()) {
+                            "" => Err(usage()),
+                            text => Ok(Command::Shortcode { action: ShortcodeAction::Add { code, text: text.to_string() } }),
+                        }
+                    }
+                    (Some("remove"), Some(code)) if words.next().is_none() => {
+                        Ok(Command::Shortcode { action: ShortcodeAction::Remove { code } })
+                    }
+                    _ => Err(usage()),
+                }
+            }
+            "/draft" => match words.next() {
+                // The text is kept as typed, spaces and all
+                Some(room) => match rest[room.len()..].strip_prefix(' ').unwrap_or("") {
+                    text if text.len() <= MAX_DRAFT_LEN => Ok(Command::Draft { room: room.to_string(), text: text.to_string() }),
+                    _ => Err(format!("Drafts are kept up to {} bytes", MAX_DRAFT_LEN)),
+                },
+                None => Err("Usage: /draft <room> [text]".to_string()),
+            },
+            "/read" => match (words.next(), words.next(), words.next()) {
+                (Some(user), None, None) if user.len() > 1 && user.starts_with('@') => {
+                    Ok(Command::ReadWhispers { from: user[1..].to_string() })
+                }
+                (Some(room), Some(id), None) => match id.trim_start_matches('#').parse::<i64>() {
+                    Ok(id) => Ok(Command::Read { room: room.to_string(), id }),
+                    Err(_) => Err("Usage: /read <room> <id> or /read @<user>".to_string()),
+                },
+                _ => Err("Usage: /read <room> <id> or /read @<user>".to_string()),
+            },
+            "/sign" => match (words.next().map(str::parse), words.next(), words.next()) {
+                (Some(Ok(stamp)), Some(signature), None) => Ok(Command::Sign { stamp, signature: signature.to_string() }),
+                _ => Err("Usage: /sign <stamp> <signature>".to_string()),
+            },
+            "/kick" | "/ban" => match words.next() {
+                Some(user) => {
+                    let reason = rest[user.len()..].trim();
+                    let reason = (!reason.is_empty()).then(|| reason.to_string());
+                    let user = user.to_string();
+                    Ok(if name == "/kick" { Command::Kick { user, reason } } else { Command::Ban { user, reason } })
+                }
+                None => Err(format!("Usage: {} <user> [reason]", name)),
+            },
+            "/promote" | "/demote" => match (words.next(), words.next(), words.next()) {
+                (Some(user), role, None) => match role.map(|role| Role::parse(role).ok_or(role)).transpose() {
+                    Ok(role) => {
+                        let user = user.to_string();
+                        Ok(if name == "/promote" { Command::Promote { user, role } } else { Command::Demote { user, role } })
+                    }
+                    Err(role) => Err(format!("There's no {} role, roles are guest, member, moderator and owner", role)),
+                },
+                _ => Err(format!("Usage: {} <user> [role]", name)),
+            },
+            "/mute" => match (words.next(), words.next()) {
+                (Some(user), Some(length)) => match parse_duration(length) {
+                    Some(duration) if duration <= MAX_MUTE => {
+                        // Whatever follows the duration is the reason, as typed
+                        let reason = rest[user.len()..].trim_start()[length.len()..].trim();
+                        let reason = (!reason.is_empty()).then(|| reason.to_string());
+                        Ok(Command::Mute { user: user.to_string(), duration, reason })
+                    }
+                    Some(_) => Err("A mute lasts 30 days at most".to_string()),
+                    None => Err("Usage: /mute <user> <duration> [reason], with a duration like 30s, 10m, 2h or 1d".to_string()),
+                },
+                _ => Err("Usage: /mute <user> <duration> [reason], with a duration like 30s, 10m, 2h or 1d".to_string()),
+            },
+            "/unmute" => match (words.next(), words.next()) {
+                (Some(user), None) => Ok(Command::Unmute { user: user.to_string() }),
+                _ => Err("Usage: /unmute <user>".to_string()),
+            },
+            "/auditlog" => match (words.next(), words.next()) {
+                (target, None) => Ok(Command::AuditLog { target: target.map(str::to_string) }),
+                _ => Err("Usage: /auditlog [user]".to_string()),
+            },
+            "/chaos" => {
+                let usage = "Usage: /chaos drop-client <user> | lag <ms> | partition <duration> | off".to_string();
+                match (words.next(), words.next(), words.next()) {
+                    (Some("drop-client"), Some(user), None) => Ok(Command::Chaos { action: ChaosAction::DropClient { user: user.to_string() } }),
+                    (Some("lag"), Some(ms), None) => match ms.trim_end_matches("ms").parse::<u64>() {
+                        Ok(ms) if ms <= MAX_LAG_MS => Ok(Command::Chaos { action: ChaosAction::Lag { lag: Duration::from_millis(ms) } }),
+                        Ok(_) => Err(format!("Lag goes up to {} ms", MAX_LAG_MS)),
+                        Err(_) => Err(usage),
+                    },
+                    (Some("partition"), Some(length), None) => match parse_duration(length) {
+                        Some(length) if length <= MAX_PARTITION => Ok(Command::Chaos { action: ChaosAction::Partition { length } }),
+                        Some(_) => Err(format!("A partition lasts {} minutes at most", MAX_PARTITION.as_secs() / 60)),
+                        None => Err(usage),
+                    },
+                    (Some("off"), None, None) => Ok(Command::Chaos { action: ChaosAction::Off }),
+                    _ => Err(usage),
+                }
+            }
+            "/motd" => Ok(Command::Motd { text: (!rest.is_empty()).then(|| rest.to_string()) }),
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+// "30s", "10m", "2h" or "1d"; None for anything else (no unit, zero, or nonsense)
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let amount: u64 = text[..text.len() - unit.len_utf8()].parse().ok().filter(|&amount| amount > 0)?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        _ => return None,
+    };
+    amount.checked_mul(seconds).map(Duration::from_secs)
+}
@@ -0,0 +1,633 @@
+// Server configuration file (TOML)
+// Every section is optional, missing values fall back to the defaults below.
+// The server reloads it on SIGHUP (or the console's reload command) without dropping anyone.
+// With --preset the named preset's values come first and the file changes whatever it mentions.
+//
+// motd = "Welcome aboard!"       # or motd_file = "motd.txt" (the file wins)
+//
+// [history]
+// size = 20
+//
+// [retention]
+// max_age_days = 30
+// max_messages = 10000
+// [retention.rooms.lobby]
+// max_messages = 500
+//
+// [maintenance]
+// interval_minutes = 60
+//
+// [limits]
+// max_clients = 100
+// max_per_ip = 5
+// idle_timeout_minutes = 30      # fractions work too, 0.5 is half a minute
+//
+// [shutdown]
+// drain_timeout_seconds = 5      # how long connections get to say goodbye before they are cut off
+//
+// [rooms]
+// max_per_user = 10              # rooms one connection can be in at once
+// joins_per_minute = 10          # faster joining earns a growing cooldown
+//
+// [moderation]
+// banned_ips = ["203.0.113.7"]
+// owners = ["alice"]            # accounts that are owners whatever /promote and /demote say (admins = [...] works too)
+// announcers = ["carol"]         # accounts that can post in read-only rooms (so can moderators and owners)
+//
+// [permissions]                  # the lowest role (guest, member, moderator, owner) that may, these are the defaults
+// kick = "moderator"
+// ban = "moderator"
+// mute = "moderator"
+// moderate = "moderator"         # reports, #server-ops, the full /stats and /auditlog
+// announce = "moderator"
+// manage-shortcodes = "moderator"
+// motd = "owner"
+// room-mode = "owner"
+// chaos = "owner"
+// promote = "owner"
+//
+// [guests]
+// can_post = true                # false: guests read along until they /register or /login
+//
+// [shortcodes]
+// everyone = true                # any signed-in account can make :shortcodes: (false: makers and moderators only)
+// makers = ["dave"]
+// max = 200                      # on the whole server
+// max_per_user = 20
+// max_len = 64                   # bytes of emoji or text one stands for
+//
+// [filter]
+// mask = ["darn"]                # shown as ****
+// block = ["spoilers"]           # message refused
+// replace = { heck = "heckin" }  # swapped for another word
+// [filter.rooms.lobby]
+// mask = ["spoilers"]            # a room's own action wins
+//
+// [webhooks]
+// listen = "127.0.0.1:8090"      # POST /hooks/<room> with a Slack ({"text"}) or Discord ({"content"}) body (read at startup)
+// token = "change-me"            # required with listen, as ?token=... or Authorization: Bearer ...
+// [[webhooks.outbound]]          # chat messages go out to each of these
+// url = "https://hooks.slack.com/services/..."
+// format = "slack"               # or "discord"
+// rooms = ["lobby"]              # the rooms relayed (only the lobby when left out)
+//
+// [metrics]
+// listen = "127.0.0.1:9100"      # GET /metrics for Prometheus, per-room members, msg/s, bytes/s and lag (read at startup)
+// token = "change-me"            # when set, scrapes carry it as Authorization: Bearer ...
+//
+// [matrix]                       # Matrix bridge (matrix feature, read at startup)
+// homeserver = "https://matrix.org"
+// user = "@retro-bridge:matrix.org"
+// password = "..."
+// room = "#retro-chat:matrix.org" # id or alias of a room that isn't end-to-end encrypted
+// local_room = "lobby"           # mirrored with it (the lobby when left out)
+// prefix = "mx-"                 # Matrix users show up here as mx-<name>
+//
+// [federation]                   # links with other servers (read at startup)
+// name = "alpha"                 # how the other side knows us, our users show up there as <name>@alpha
+// [[federation.peers]]
+// name = "beta"
+// token = "shared-secret"        # the same on both sides
+// addr = "beta.example.org:8082" # we dial it; left out on the side that waits for the other to dial
+// rooms = ["lobby"]              # the rooms shared with it (only the lobby when left out)
+//
+// [redis]                        # several servers behind one load balancer, as one (redis feature, read at startup)
+// url = "redis://127.0.0.1/"
+// prefix = "retro-chat"          # keys and channels start with it, so clusters can share a Redis
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::roles::{Permission, Role};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    // Message of the day, shown to everyone right after they connect
+    pub motd: Option<String>,
+    pub motd_file: Option<PathBuf>,
+    pub history: HistoryConfig,
+    pub retention: RetentionConfig,
+    pub maintenance: MaintenanceConfig,
+    pub limits: LimitsConfig,
+    pub shutdown: ShutdownConfig,
+    pub rooms: RoomsConfig,
+    pub moderation: ModerationConfig,
+    pub guests: GuestsConfig,
+    pub shortcodes: ShortcodesConfig,
+    pub filter: FilterConfig,
+    pub chaos: ChaosConfig,
+    pub permissions: PermissionsConfig,
+    pub webhooks: WebhooksConfig,
+    pub metrics: MetricsConfig,
+    pub matrix: Option<MatrixConfig>,
+    pub federation: FederationConfig,
+    pub redis: Option<RedisConfig>,
+}
+
+// In-memory history replayed to new arrivals
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Recent messages kept in memory and replayed to new arrivals
+    pub size: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { size: 20 }
+    }
+}
+
+// How long stored history is kept, with optional per-room overrides
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    // Messages older than this are deleted (None keeps them forever)
+    pub max_age_days: Option<u64>,
+    // Newest messages kept per room (None keeps them all)
+    pub max_messages: Option<usize>,
+    pub rooms: HashMap<String, RoomRetention>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoomRetention {
+    pub max_age_days: Option<u64>,
+    pub max_messages: Option<usize>,
+}
+
+// Schedule of the periodic retention + VACUUM task
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub interval_minutes: u64,
+}
+
+// Concurrent connection caps, connections beyond them get a "server full" error
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_clients: usize,
+    pub max_per_ip: usize,
+    // Connections that send nothing for this long are dropped (0 disables the timeout)
+    pub idle_timeout_minutes: f64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_clients: 100,
+            max_per_ip: 5,
+            idle_timeout_minutes: 30.0,
+        }
+    }
+}
+
+// Winding down once the countdown is over
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    // Connections still open this long after they were told to close are dropped
+    pub drain_timeout_seconds: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { drain_timeout_seconds: 5 }
+    }
+}
+
+// How many rooms a connection may be in, and how fast it may join them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoomsConfig {
+    pub max_per_user: usize,
+    pub joins_per_minute: usize,
+}
+
+impl Default for RoomsConfig {
+    fn default() -> Self {
+        RoomsConfig {
+            max_per_user: 10,
+            joins_per_minute: 10,
+        }
+    }
+}
+
+// Bans that live in the config file, on top of the ones made from the admin console
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    pub banned_ips: Vec<IpAddr>,
+    // Account names (signed in) that are owners, whatever role the database has for them
+    #[serde(alias = "admins")]
+    pub owners: Vec<String>,
+    // Account names (signed in) allowed to post in read-only rooms, on top of the roles with the announce permission
+    pub announcers: Vec<String>,
+}
+
+// What connections that aren't signed in to an account may do
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GuestsConfig {
+    // Chat, actions and whispers; commands (/register and /login above all) always work
+    pub can_post: bool,
+}
+
+impl Default for GuestsConfig {
+    fn default() -> Self {
+        GuestsConfig { can_post: true }
+    }
+}
+
+// Who can make shortcodes for everyone, and how many (and how long) they can be
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShortcodesConfig {
+    // Every signed-in account can make them, not just the makers (and the roles with manage-shortcodes)
+    pub everyone: bool,
+    // Account names (signed in) allowed to make them when not everyone can
+    pub makers: Vec<String>,
+    pub max: usize,
+    pub max_per_user: usize,
+    pub max_len: usize,
+}
+
+impl Default for ShortcodesConfig {
+    fn default() -> Self {
+        ShortcodesConfig {
+            everyone: true,
+            makers: Vec::new(),
+            max: 200,
+            max_per_user: 20,
+            max_len: 64,
+        }
+    }
+}
+
+// The lowest role each permission takes, where it isn't the default (see roles.rs)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct PermissionsConfig(HashMap<Permission, Role>);
+
+impl PermissionsConfig {
+    pub fn role_for(&self, permission: Permission) -> Role {
+        self.0.get(&permission).copied().unwrap_or_else(|| permission.default_role())
+    }
+}
+
+// Failure injection with /chaos, for staging servers only
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    // Lets /chaos be used at all (by the roles with the chaos permission)
+    pub enabled: bool,
+}
+
+// Chat relayed to Slack/Discord style webhooks, and posted from them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    // Where the inbound endpoint listens, none when left out
+    pub listen: Option<SocketAddr>,
+    // What inbound posts have to carry
+    pub token: Option<String>,
+    pub outbound: Vec<OutboundWebhook>,
+}
+
+// Per-room load for Prometheus and the like
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    // Where GET /metrics is served, nowhere when left out
+    pub listen: Option<SocketAddr>,
+    // What scrapes have to carry, anyone who can reach the endpoint may read it when left out
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutboundWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    #[serde(default = "lobby_only")]
+    pub rooms: Vec<String>,
+}
+
+// The body the receiving end understands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    #[default]
+    Slack,
+    Discord,
+}
+
+// Nothing leaves a private room or #server-ops unless it's listed
+fn lobby_only() -> Vec<String> {
+    vec![crate::protocol::DEFAULT_ROOM.to_string()]
+}
+
+impl OutboundWebhook {
+    pub fn relays(&self, room: &str) -> bool {
+        self.rooms.iter().any(|relayed| relayed.trim_start_matches('#').eq_ignore_ascii_case(room))
+    }
+}
+
+// Other servers we share rooms with (see federation.rs), no links without a name of our own
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FederationConfig {
+    pub name: Option<String>,
+    pub peers: Vec<PeerConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerConfig {
+    pub name: String,
+    pub token: String,
+    // Where to dial it, none when it dials us
+    #[serde(default)]
+    pub addr: Option<String>,
+    #[serde(default = "lobby_only")]
+    pub rooms: Vec<String>,
+}
+
+impl PeerConfig {
+    pub fn shares(&self, room: &str) -> bool {
+        self.rooms.iter().any(|shared| shared.trim_start_matches('#').eq_ignore_ascii_case(room))
+    }
+}
+
+// A Matrix room mirrored with one of ours, through a bot account (see matrix.rs)
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixConfig {
+    pub homeserver: String,
+    pub user: String,
+    pub password: String,
+    pub room: String,
+    #[serde(default = "default_matrix_room")]
+    pub local_room: String,
+    #[serde(default = "default_matrix_prefix")]
+    pub prefix: String,
+}
+
+fn default_matrix_room() -> String {
+    crate::protocol::DEFAULT_ROOM.to_string()
+}
+
+fn default_matrix_prefix() -> String {
+    "mx-".to_string()
+}
+
+// Server processes sharing chat, history and who's online through Redis (see cluster.rs)
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedisConfig {
+    pub url: String,
+    #[serde(default = "default_redis_prefix")]
+    pub prefix: String,
+}
+
+fn default_redis_prefix() -> String {
+    "retro-chat".to_string()
+}
+
+// Word filter rules for every room, plus per-room overrides
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    #[serde(flatten)]
+    pub words: FilterWords,
+    pub rooms: HashMap<String, FilterWords>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FilterWords {
+    pub mask: Vec<String>,
+    pub block: Vec<String>,
+    pub replace: HashMap<String, String>,
+}
+
+impl ModerationConfig {
+    pub fn is_owner(&self, username: &str) -> bool {
+        self.owners.iter().any(|owner| owner.eq_ignore_ascii_case(username))
+    }
+
+    pub fn is_announcer(&self, username: &str) -> bool {
+        self.announcers.iter().any(|announcer| announcer.eq_ignore_ascii_case(username))
+    }
+}
+
+impl ShortcodesConfig {
+    // Roles with manage-shortcodes always can, on top of what this says
+    pub fn is_maker(&self, username: &str) -> bool {
+        self.everyone || self.makers.iter().any(|maker| maker.eq_ignore_ascii_case(username))
+    }
+}
+
+impl LimitsConfig {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        Duration::try_from_secs_f64(self.idle_timeout_minutes * 60.0).ok().filter(|timeout| !timeout.is_zero())
+    }
+}
+
+impl ShutdownConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_seconds)
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_age_days: Some(30),
+            max_messages: Some(10_000),
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig { interval_minutes: 60 }
+    }
+}
+
+impl RetentionConfig {
+    // Retention for one room: the room's own values win over the defaults
+    pub fn for_room(&self, room: &str) -> RoomRetention {
+        let overrides = self.rooms.get(room).cloned().unwrap_or_default();
+        RoomRetention {
+            max_age_days: overrides.max_age_days.or(self.max_age_days),
+            max_messages: overrides.max_messages.or(self.max_messages),
+        }
+    }
+}
+
+impl ServerConfig {
+    // The role a connection has: guests are guests, the configured owners are owners,
+    // and any other account has the role stored for it (`stored`)
+    pub fn role_of(&self, username: &str, verified: bool, stored: Role) -> Role {
+        match verified {
+            false => Role::Guest,
+            true if self.moderation.is_owner(username) => Role::Owner,
+            true => stored,
+        }
+    }
+
+    // Whether that role may do it
+    pub fn allows(&self, role: Role, permission: Permission) -> bool {
+        role >= self.permissions.role_for(permission)
+    }
+
+    // Reads and parses a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        Self::load_with_preset(None, Some(path.as_ref()))
+    }
+
+    // The preset's values (if any) with the config file's (if any) on top, the defaults for the rest
+    pub fn load_with_preset(preset: Option<Preset>, path: Option<&Path>) -> Result<Self, String> {
+        let mut table = match preset {
+            Some(preset) => preset.table(),
+            None => toml::Table::new(),
+        };
+        if let Some(path) = path {
+            let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+            let file: toml::Table = toml::from_str(&text).map_err(|e| format!("parsing {}: {}", path.display(), e))?;
+            overlay(&mut table, file);
+        }
+        let from = path.map_or_else(|| "the preset".to_string(), |path| path.display().to_string());
+        toml::Value::Table(table).try_into().map_err(|e| format!("parsing {}: {}", from, e))
+    }
+
+    // The configured message of the day, read from motd_file when one is given
+    pub fn load_motd(&self) -> Result<Option<String>, String> {
+        let motd = match &self.motd_file {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?),
+            None => self.motd.clone(),
+        };
+        Ok(motd.map(|text| text.trim().to_string()).filter(|text| !text.is_empty()))
+    }
+}
+
+// Bundles of settings for a kind of server, picked with --preset <name>
+// They only fill in what the config file leaves out, so an operator can start from one and change a thing or two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    // A handful of people who know each other: roomy limits, no timeouts, history kept for a year
+    SmallFriends,
+    // Open to anyone: tight caps and join limits, guests read until they register, a word filter
+    PublicCommunity,
+    // One class behind one school address: everyone signs in to talk, few rooms, a term's worth of history
+    Classroom,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::SmallFriends, Preset::PublicCommunity, Preset::Classroom];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::SmallFriends => "small-friends",
+            Preset::PublicCommunity => "public-community",
+            Preset::Classroom => "classroom",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Preset, String> {
+        Preset::ALL.into_iter().find(|preset| preset.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Preset::ALL.iter().map(|preset| preset.name()).collect();
+            format!("unknown preset {} (there are {})", name, names.join(", "))
+        })
+    }
+
+    // The preset in the config file's own terms
+    pub fn settings(self) -> &'static str {
+        match self {
+            Preset::SmallFriends => {
+                r#"
+                [history]
+                size = 100
+                [retention]
+                max_age_days = 365
+                max_messages = 100000
+                [limits]
+                max_clients = 25
+                max_per_ip = 5
+                idle_timeout_minutes = 0
+                [rooms]
+                max_per_user = 20
+                joins_per_minute = 30
+                [guests]
+                can_post = true
+                "#
+            }
+            Preset::PublicCommunity => {
+                r#"
+                [history]
+                size = 20
+                [retention]
+                max_age_days = 30
+                max_messages = 10000
+                [limits]
+                max_clients = 500
+                max_per_ip = 2
+                idle_timeout_minutes = 30
+                [rooms]
+                max_per_user = 5
+                joins_per_minute = 5
+                [guests]
+                can_post = false
+                [shortcodes]
+                everyone = false
+                [filter]
+                mask = ["damn", "crap", "bastard"]
+                block = ["viagra", "casino", "giveaway"]
+                "#
+            }
+            Preset::Classroom => {
+                r#"
+                [history]
+                size = 50
+                [retention]
+                max_age_days = 120
+                [limits]
+                max_clients = 60
+                max_per_ip = 60
+                idle_timeout_minutes = 60
+                [rooms]
+                max_per_user = 3
+                joins_per_minute = 10
+                [guests]
+                can_post = false
+                [shortcodes]
+                everyone = false
+                [filter]
+                mask = ["damn", "crap", "hell", "sucks"]
+                "#
+            }
+        }
+    }
+
+    fn table(self) -> toml::Table {
+        toml::from_str(self.settings()).unwrap_or_default()
+    }
+}
+
+// Puts `top` over `base`: tables are merged key by key, anything else in `top` replaces what `base` had
+fn overlay(base: &mut toml::Table, top: toml::Table) {
+    for (key, value) in top {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(top)) => overlay(base, top),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
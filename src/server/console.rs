@@ -0,0 +1,129 @@
+// Commands typed by the operator into the server's terminal
+
+use std::net::IpAddr;
+
+use crate::server::filter::FilterAction;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    Help,
+    // Who is connected, from where and for how long
+    List,
+    // Disconnect a user, the reason is shown to them and kept in the moderation log
+    Kick { username: String, reason: Option<String> },
+    // Names an account went by before signing in
+    Names { account: String },
+    Ban { ip: IpAddr, reason: Option<String> },
+    // Give an account a new random password and sign it out everywhere
+    ResetPassword { username: String },
+    Unban { ip: IpAddr },
+    // Let an account /ban shut out sign in again
+    UnbanAccount { username: String },
+    // Recent moderation (kicks, bans, mutes, resets...), only the ones about a user or address when given
+    AuditLog { target: Option<String> },
+    // Broadcast a notice from the server
+    Say { text: String },
+    Stats,
+    // Load per room: members, message and byte rates, lag
+    Rooms,
+    // Shut down after a countdown (0 shuts down right away)
+    Shutdown { seconds: u64 },
+    // Switch wire tracing on or off
+    Trace { enabled: bool },
+    // Re-read the config file
+    Reload,
+    // Show or replace the message of the day
+    Motd { text: Option<String> },
+    // Word filter edits, kept until the next reload or restart
+    FilterList,
+    FilterAdd { word: String, action: FilterAction },
+    FilterRemove { word: String },
+}
+
+pub const HELP: &str = "\
+list               connected users
+kick <user> [why]  disconnect a user
+names <account>    nicknames an account used before signing in
+ban <ip> [why]     disconnect and refuse an IP address
+reset <user>       new random password for an account, signs it out everywhere
+unban <ip|account> lift a ban
+auditlog [target]  recent kicks, bans, mutes and resets (of one user or IP)
+say <text>         broadcast a server notice
+stats              server statistics
+rooms              per-room load (members, msg/s, bytes/s, lag)
+shutdown [secs]    shut down, optionally after a countdown
+trace on|off       wire-level frame tracing
+reload             re-read the config file (same as SIGHUP)
+motd [text]        show or replace the message of the day
+filter list        filtered words
+filter add <word> [mask|block|replace <with>]
+filter remove <word>";
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<AdminCommand, String> {
+        let line = line.trim();
+        let (word, rest) = line.split_once(' ').map_or((line, ""), |(w, r)| (w, r.trim()));
+        let parse_ip = |rest: &str| rest.parse::<IpAddr>().map_err(|_| format!("not an IP address: {}", rest));
+        // "bob spamming links" -> ("bob", Some("spamming links"))
+        let with_reason = |rest: &str| match rest.split_once(' ') {
+            Some((target, reason)) => (target.to_string(), Some(reason.trim().to_string())),
+            None => (rest.to_string(), None),
+        };
+
+        match (word, rest) {
+            ("help", _) => Ok(AdminCommand::Help),
+            ("list", "") => Ok(AdminCommand::List),
+            ("names", account) if !account.is_empty() => Ok(AdminCommand::Names { account: account.to_string() }),
+            ("kick", user) if !user.is_empty() => {
+                let (username, reason) = with_reason(user);
+                Ok(AdminCommand::Kick { username, reason })
+            }
+            ("reset", user) if !user.is_empty() => Ok(AdminCommand::ResetPassword { username: user.to_string() }),
+            ("ban", ip) if !ip.is_empty() => {
+                let (ip, reason) = with_reason(ip);
+                Ok(AdminCommand::Ban { ip: parse_ip(&ip)?, reason })
+            }
+            ("unban", target) if !target.is_empty() => Ok(match target.parse() {
+                Ok(ip) => AdminCommand::Unban { ip },
+                Err(_) => AdminCommand::UnbanAccount { username: target.to_string() },
+            }),
+            ("auditlog", "") => Ok(AdminCommand::AuditLog { target: None }),
+            ("auditlog", target) => Ok(AdminCommand::AuditLog { target: Some(target.to_string()) }),
+            ("say", text) if !text.is_empty() => Ok(AdminCommand::Say { text: text.to_string() }),
+            ("stats", "") => Ok(AdminCommand::Stats),
+            ("rooms", "") => Ok(AdminCommand::Rooms),
+            ("shutdown", "") => Ok(AdminCommand::Shutdown { seconds: 0 }),
+            ("shutdown", secs) => secs
+                .parse()
+                .map(|seconds| AdminCommand::Shutdown { seconds })
+                .map_err(|_| format!("not a number of seconds: {}", secs)),
+            ("trace", "on") => Ok(AdminCommand::Trace { enabled: true }),
+            ("trace", "off") => Ok(AdminCommand::Trace { enabled: false }),
+            ("reload", "") => Ok(AdminCommand::Reload),
+            ("filter", rest) => parse_filter(rest),
+            ("motd", "") => Ok(AdminCommand::Motd { text: None }),
+            ("motd", text) => Ok(AdminCommand::Motd { text: Some(text.to_string()) }),
+            ("", _) => Err(String::new()),
+            _ => Err(format!("unknown or incomplete command: {} (try help)", line)),
+        }
+    }
+}
+
+fn parse_filter(args: &str) -> Result<AdminCommand, String> {
+    let usage = || "usage: filter list | filter add <word> [mask|block|replace <with>] | filter remove <word>".to_string();
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        ["list"] => Ok(AdminCommand::FilterList),
+        ["remove", word] => Ok(AdminCommand::FilterRemove { word: word.to_string() }),
+        ["add", word, action @ ..] => {
+            let action = match action {
+                [] | ["mask"] => FilterAction::Mask,
+                ["block"] => FilterAction::Block,
+                ["replace", with] => FilterAction::Replace(with.to_string()),
+                _ => return Err(usage()),
+            };
+            Ok(AdminCommand::FilterAdd { word: word.to_string(), action })
+        }
+        _ => Err(usage()),
+    }
+}
@@ -0,0 +1,99 @@
+// Reconnect storm protection
+// Every connection attempt is recorded per key (an IP address or a username). When a key
+// connects more than `limit` times inside the window it is put on a cooldown, and every
+// repeat offence doubles the cooldown so buggy auto-reconnect loops back off quickly.
+// Failed /login attempts are counted the same way, so passwords can't be guessed at speed.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// First cooldown handed out, doubled for each repeat offence
+const BASE_COOLDOWN: Duration = Duration::from_secs(10);
+// Cooldowns never grow past this
+const MAX_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+// A key that behaves for this long has its strikes forgiven
+const STRIKE_RESET: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Default)]
+struct Entry {
+    attempts: VecDeque<Instant>,
+    strikes: u32,
+    blocked_until: Option<Instant>,
+    last_strike: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct ReconnectGuard {
+    limit: usize,
+    window: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl ReconnectGuard {
+    // `limit` connections are allowed per `window`, the next one starts a cooldown
+    pub fn new(limit: usize, window: Duration) -> Self {
+        ReconnectGuard {
+            limit,
+            window,
+            entries: HashMap::new(),
+        }
+    }
+
+    // Records a connection attempt for `key`
+    // Returns the remaining cooldown when the attempt must be rejected
+    pub fn check(&mut self, key: &str, now: Instant) -> Result<(), Duration> {
+        self.prune(now);
+
+        let entry = self.entries.entry(key.to_string()).or_default();
+
+        // Still cooling down from an earlier storm
+        if let Some(until) = entry.blocked_until {
+            if until > now {
+                return Err(until - now);
+            }
+            entry.blocked_until = None;
+        }
+
+        // Forgive old strikes once the key has behaved for a while
+        if entry.last_strike.is_some_and(|t| now.duration_since(t) > STRIKE_RESET) {
+            entry.strikes = 0;
+            entry.last_strike = None;
+        }
+
+        entry.attempts.push_back(now);
+        while entry.attempts.front().is_some_and(|t| now.duration_since(*t) > self.window) {
+            entry.attempts.pop_front();
+        }
+
+        if entry.attempts.len() <= self.limit {
+            return Ok(());
+        }
+
+        // Too many reconnects: start (or escalate) the cooldown
+        let cooldown = BASE_COOLDOWN
+            .saturating_mul(2u32.saturating_pow(entry.strikes))
+            .min(MAX_COOLDOWN);
+        entry.strikes += 1;
+        entry.last_strike = Some(now);
+        entry.blocked_until = Some(now + cooldown);
+        entry.attempts.clear();
+        Err(cooldown)
+    }
+
+    // The cooldown `key` is serving right now, without counting an attempt
+    pub fn cooling_down(&self, key: &str, now: Instant) -> Option<Duration> {
+        let until = self.entries.get(key)?.blocked_until?;
+        (until > now).then(|| until - now)
+    }
+
+    // Drops keys that have nothing left to remember
+    fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.entries.retain(|_, entry| {
+            let recent = entry.attempts.back().is_some_and(|t| now.duration_since(*t) <= window);
+            let blocked = entry.blocked_until.is_some_and(|t| t > now);
+            let strikes = entry.last_strike.is_some_and(|t| now.duration_since(t) <= STRIKE_RESET);
+            recent || blocked || strikes
+        });
+    }
+}
@@ -0,0 +1,202 @@
+// Federation: links between servers that share rooms, so small communities can connect their instances
+// Both sides list each other under [federation] with the same token and the rooms they share. One side dials
+// (`addr`) and keeps the link up, dialing again 30s after it drops. A link opens with the usual handshake (our name
+// as the username, plus the link token) and from then on carries chat both ways, one JSON frame per line.
+// Chat in a shared room goes over with origin set to our name and shows up there as <user>@<origin>.
+// Loop suppression: what came in over a link (origin set) never goes out over another one, and what comes back
+// with our own name as its origin is dropped. So links don't relay for each other, every pair of servers that
+// should talk needs a link of its own.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::config::{FederationConfig, PeerConfig};
+use super::plugins::ServerPlugin;
+use super::same_token;
+use crate::protocol::{ChatMessage, ErrorKind, Handshake, MessageType};
+
+// How long a dropped link waits before dialing again
+const REDIAL: Duration = Duration::from_secs(30);
+
+// What the side that was dialed says once the link is up
+const LINKED: &str = "linked";
+
+// Chat that came in over the links, ready to post
+pub type Arrivals = mpsc::UnboundedReceiver<ChatMessage>;
+
+pub struct Federation {
+    name: String,
+    peers: Vec<PeerConfig>,
+    // Links that are up, by peer name (lowercase), with the queue of what goes out over each
+    links: Mutex<HashMap<String, mpsc::UnboundedSender<ChatMessage>>>,
+    // Chat that came in over a link, ready to post
+    arrivals: mpsc::UnboundedSender<ChatMessage>,
+}
+
+impl Federation {
+    // Nothing when there's no name of our own, what comes in over links comes out of the returned channel
+    pub fn new(config: &FederationConfig) -> Result<Option<(Arc<Self>, Arrivals)>, String> {
+        let Some(name) = config.name.clone().filter(|name| !name.is_empty()) else {
+            return match config.peers.is_empty() {
+                true => Ok(None),
+                false => Err("federation.peers needs a federation.name".to_string()),
+            };
+        };
+        if let Some(peer) = config.peers.iter().find(|peer| peer.token.is_empty()) {
+            return Err(format!("federation peer {} needs a token", peer.name));
+        }
+        let (arrivals, received) = mpsc::unbounded_channel();
+        let federation = Federation { name, peers: config.peers.clone(), links: Mutex::new(HashMap::new()), arrivals };
+        Ok(Some((Arc::new(federation), received)))
+    }
+
+    // Starts dialing the peers we're to dial, each kept up for good
+    pub fn dial(self: &Arc<Self>) {
+        for peer in self.peers.iter().filter(|peer| peer.addr.is_some()) {
+            tokio::spawn(self.clone().keep_linked(peer.clone()));
+        }
+    }
+
+    // The peer that handshake is from, if the token is right
+    pub fn admits(&self, name: &str, token: &str) -> bool {
+        self.peer(name).is_some_and(|peer| same_token(&peer.token, token))
+    }
+
+    fn peer(&self, name: &str) -> Option<&PeerConfig> {
+        self.peers.iter().find(|peer| peer.name.eq_ignore_ascii_case(name))
+    }
+
+    fn links(&self) -> std::sync::MutexGuard<'_, HashMap<String, mpsc::UnboundedSender<ChatMessage>>> {
+        self.links.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn keep_linked(self: Arc<Self>, peer: PeerConfig) {
+        let addr = peer.addr.clone().unwrap_or_default();
+        loop {
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => {
+                    let (reader, mut writer) = stream.into_split();
+                    let hello = Handshake { username: self.name.clone(), link_token: Some(peer.token.clone()), ..Default::default() };
+                    let hello = serde_json::to_string(&hello).unwrap_or_default();
+                    let ended = match writer.write_all(format!("{}\n", hello).as_bytes()).await {
+                        Ok(()) => self.clone().serve(&peer.name, BufReader::new(reader), writer, false).await,
+                        Err(e) => Err(e.to_string()),
+                    };
+                    if let Err(e) = ended {
+                        println!("🔗 Link with {} closed: {}", peer.name, e);
+                    }
+                }
+                Err(e) => eprintln!("[WARN] can't reach {} ({}) for the federation link: {}", peer.name, addr, e),
+            }
+            tokio::time::sleep(REDIAL).await;
+        }
+    }
+
+    // Carries chat both ways over a link until it drops, `dialed` on the side that was dialed (it says LINKED)
+    // Only one link per peer: if both sides dial, the second one is turned away
+    pub async fn serve<R, W>(self: Arc<Self>, peer: &str, reader: R, mut writer: W, dialed: bool) -> Result<(), String>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let Some(config) = self.peer(peer).cloned() else { return Err(format!("{} isn't a peer of ours", peer)) };
+        let (outgoing, mut queue) = mpsc::unbounded_channel();
+        let key = config.name.to_lowercase();
+        let taken = {
+            let mut links = self.links();
+            let taken = links.get(&key).is_some_and(|link| !link.is_closed());
+            if !taken {
+                links.insert(key.clone(), outgoing);
+            }
+            taken
+        };
+        if taken {
+            if dialed {
+                let refusal = ChatMessage::error(ErrorKind::AuthFailed, format!("{} is linked already", config.name));
+                let _ = write_frame(&mut writer, &refusal).await;
+            }
+            return Err("there's a link already".to_string());
+        }
+        if dialed {
+            println!("🔗 Linked with {}", config.name);
+            write_frame(&mut writer, &ChatMessage::new(self.name.as_str(), LINKED, MessageType::SystemNotification)).await?;
+        }
+
+        let mut frames = reader.lines();
+        let ended = loop {
+            tokio::select! {
+                msg = queue.recv() => {
+                    let Some(msg) = msg else { break Ok(()) };
+                    if let Err(e) = write_frame(&mut writer, &msg).await {
+                        break Err(e);
+                    }
+                }
+                frame = frames.next_line() => {
+                    let frame = match frame {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => break Err("the other side hung up".to_string()),
+                        Err(e) => break Err(e.to_string()),
+                    };
+                    let Ok(msg) = serde_json::from_str::<ChatMessage>(&frame) else { continue };
+                    match msg.message_type {
+                        MessageType::Error => break Err(msg.content),
+                        MessageType::SystemNotification if msg.content == LINKED => println!("🔗 Linked with {}", config.name),
+                        _ => {
+                            if let Some(arrival) = self.arrival(&config, msg) {
+                                let _ = self.arrivals.send(arrival);
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        self.links().remove(&key);
+        ended
+    }
+
+    // Chat from a peer as it's posted here, if it's chat in a room we share with it and didn't start with us
+    fn arrival(&self, peer: &PeerConfig, msg: ChatMessage) -> Option<ChatMessage> {
+        if !matches!(msg.message_type, MessageType::UserMessage | MessageType::Action) {
+            return None;
+        }
+        if msg.origin.as_deref().is_some_and(|origin| origin.eq_ignore_ascii_case(&self.name)) {
+            return None; // our own, back again
+        }
+        let room = msg.room.filter(|room| peer.shares(room))?;
+        let mut arrival = ChatMessage::new(format!("{}@{}", msg.username, peer.name), msg.content, msg.message_type).in_room(room.as_str());
+        arrival.origin = Some(peer.name.clone());
+        Some(arrival)
+    }
+}
+
+impl ServerPlugin for Federation {
+    fn name(&self) -> &str {
+        "federation"
+    }
+
+    // Chat said here goes to every linked peer that shares the room, what came over a link goes nowhere else
+    fn on_broadcast(&self, msg: &ChatMessage) {
+        let Some(room) = msg.room.as_deref() else { return };
+        if msg.origin.is_some() || !matches!(msg.message_type, MessageType::UserMessage | MessageType::Action) {
+            return;
+        }
+        let mut outgoing = ChatMessage::new(msg.username.as_str(), msg.content.as_str(), msg.message_type.clone()).in_room(room);
+        outgoing.origin = Some(self.name.clone());
+        let links = self.links();
+        for peer in self.peers.iter().filter(|peer| peer.shares(room)) {
+            if let Some(link) = links.get(&peer.name.to_lowercase()) {
+                let _ = link.send(outgoing.clone());
+            }
+        }
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, msg: &ChatMessage) -> Result<(), String> {
+    let json = serde_json::to_string(msg).map_err(|e| e.to_string())?;
+    writer.write_all(format!("{}\n", json).as_bytes()).await.map_err(|e| e.to_string())
+}
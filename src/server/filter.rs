@@ -0,0 +1,96 @@
+// Word filter for chat messages, applied before they are stored and broadcast
+// Words match whole and case-insensitively; each listed word has an action:
+// mask turns it into asterisks, replace swaps in another word, block refuses the whole message.
+// Rooms can override the action for a word, or filter words of their own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::server::config::{FilterConfig, FilterWords};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterAction {
+    Mask,
+    Replace(String),
+    Block,
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterAction::Mask => write!(f, "mask"),
+            FilterAction::Replace(with) => write!(f, "replace with \"{}\"", with),
+            FilterAction::Block => write!(f, "block"),
+        }
+    }
+}
+
+type Rules = BTreeMap<String, FilterAction>;
+
+#[derive(Debug, Clone, Default)]
+pub struct WordFilter {
+    rules: Rules,
+    rooms: HashMap<String, Rules>,
+}
+
+impl WordFilter {
+    pub fn from_config(config: &FilterConfig) -> Self {
+        WordFilter {
+            rules: rules_from(&config.words),
+            rooms: config.rooms.iter().map(|(room, words)| (room.clone(), rules_from(words))).collect(),
+        }
+    }
+
+    // Adds (or changes) a filtered word for every room
+    pub fn set(&mut self, word: &str, action: FilterAction) {
+        self.rules.insert(word.to_lowercase(), action);
+    }
+
+    // Returns false when the word wasn't filtered
+    pub fn remove(&mut self, word: &str) -> bool {
+        self.rules.remove(&word.to_lowercase()).is_some()
+    }
+
+    // Words filtered everywhere, alphabetically
+    pub fn rules(&self) -> impl Iterator<Item = (&str, &FilterAction)> {
+        self.rules.iter().map(|(word, action)| (word.as_str(), action))
+    }
+
+    // Filtered text for a message sent to `room`, or Err with the word that blocks it
+    pub fn apply(&self, room: &str, text: &str) -> Result<String, String> {
+        let room_rules = self.rooms.get(room);
+        let mut filtered = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(|c: char| c.is_alphanumeric()) {
+            filtered.push_str(&rest[..start]);
+            let after = &rest[start..];
+            let end = after.find(|c: char| !c.is_alphanumeric()).unwrap_or(after.len());
+            let word = &after[..end];
+            let key = word.to_lowercase();
+            match room_rules.and_then(|rules| rules.get(&key)).or_else(|| self.rules.get(&key)) {
+                None => filtered.push_str(word),
+                Some(FilterAction::Mask) => filtered.extend(word.chars().map(|_| '*')),
+                Some(FilterAction::Replace(with)) => filtered.push_str(with),
+                Some(FilterAction::Block) => return Err(word.to_string()),
+            }
+            rest = &after[end..];
+        }
+        filtered.push_str(rest);
+        Ok(filtered)
+    }
+}
+
+fn rules_from(words: &FilterWords) -> Rules {
+    let mut rules = Rules::new();
+    for word in &words.mask {
+        rules.insert(word.to_lowercase(), FilterAction::Mask);
+    }
+    for (word, with) in &words.replace {
+        rules.insert(word.to_lowercase(), FilterAction::Replace(with.clone()));
+    }
+    // Blocking wins when a word is listed more than once
+    for word in &words.block {
+        rules.insert(word.to_lowercase(), FilterAction::Block);
+    }
+    rules
+}
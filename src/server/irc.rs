@@ -0,0 +1,523 @@
+// Minimal IRC listener (--irc <addr>), so weechat/irssi users can join the same chat as the TUI client
+// Every IRC connection is translated to the usual protocol over an in-memory pipe and let in like any other
+// connection: bans, limits, plugins and the server password (as PASS) all apply. Rooms are #channels one to one.
+// Understood: NICK/USER to register, JOIN/PART, PRIVMSG to a #room or a nick (CTCP ACTION is /me), TOPIC, LIST,
+// PING and QUIT. Any of the server's own slash commands can be sent raw too (/quote LOGIN name password).
+// Channel text the server would take for a command ("/kick bob") or a ``` code fence is refused with a notice, not sent.
+// Registration is waited for in the background like QUIC's stream, a slow client holds up nobody.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::commands::{Command, MAX_PING_STAMP_LEN};
+use crate::blocks::opens_block;
+use crate::protocol::{ChatMessage, Handshake, MessageType};
+
+// How we introduce ourselves, and the host part of everyone's nick!user@host
+pub const SERVER_NAME: &str = "retro-chat";
+
+// Time a client gets to send NICK and USER before it's let go
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Bytes of message text per PRIVMSG, leaving room for the prefix within IRC's 512 byte lines
+const MAX_TEXT_BYTES: usize = 400;
+
+// Longest line we read from an IRC client (with tags), anything longer hangs up
+const MAX_LINE_BYTES: usize = 8192;
+
+// Buffer of the pipe between an IRC connection and the server side
+const PIPE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+pub struct IrcListener {
+    addr: Option<SocketAddr>,
+    accepted: Option<mpsc::Receiver<(DuplexStream, SocketAddr)>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl IrcListener {
+    // Listens on `addr` (TCP), or doesn't listen at all when there is none
+    pub fn bind(addr: Option<SocketAddr>) -> Result<Self, String> {
+        let Some(addr) = addr else { return Ok(IrcListener::default()) };
+        let listener = super::listen::bind(addr).map_err(|e| format!("can't listen for IRC on {}: {}", addr, e))?;
+        let addr = listener.local_addr().unwrap_or(addr);
+
+        let (tx, accepted) = mpsc::channel(super::listen::ACCEPT_QUEUE);
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, addr)) => {
+                        tokio::spawn(serve(socket, addr, tx.clone()));
+                    }
+                    Err(e) => {
+                        eprintln!("[WARN] accepting IRC on {} failed: {}", addr, e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+        Ok(IrcListener { addr: Some(addr), accepted: Some(accepted), task: Some(task) })
+    }
+
+    // The address we listen on, if any
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    // The next registered client, as the server end of its pipe, waits forever when we aren't listening
+    pub async fn accept(&mut self) -> Option<(DuplexStream, SocketAddr)> {
+        match &mut self.accepted {
+            Some(accepted) => accepted.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+// No new connections once the listener is gone, those already in carry on
+impl Drop for IrcListener {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+// What a client has to tell us before it's let in
+#[derive(Debug, Default)]
+struct Registration {
+    nick: Option<String>,
+    user: bool,
+    password: Option<String>,
+}
+
+// One IRC client: registers it, hands the server end of a pipe over and translates both ways until either side hangs up
+async fn serve(socket: TcpStream, addr: SocketAddr, accepted: mpsc::Sender<(DuplexStream, SocketAddr)>) {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let Ok(Some((nick, password))) = tokio::time::timeout(REGISTER_TIMEOUT, register(&mut reader, &mut writer)).await else {
+        return;
+    };
+
+    let (server_end, our_end) = tokio::io::duplex(PIPE_BYTES);
+    if accepted.send((server_end, addr)).await.is_err() {
+        return; // the server is winding down
+    }
+    let (native_reader, mut native_writer) = tokio::io::split(our_end);
+    let mut frames = BufReader::new(native_reader).lines();
+    let handshake = Handshake { username: nick.clone(), password, ..Default::default() };
+    let Ok(handshake) = serde_json::to_string(&handshake) else { return };
+    if native_writer.write_all(format!("{}\n", handshake).as_bytes()).await.is_err() {
+        return;
+    }
+    if write_lines(&mut writer, &welcome(&nick)).await.is_err() {
+        return;
+    }
+
+    // Client lines come through a channel: a read cut short by the select below would lose what it had read
+    let (line_tx, mut client_lines) = mpsc::channel(16);
+    let reading = tokio::spawn(async move {
+        while let Some(line) = read_line(&mut reader).await {
+            if line_tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut translator = Translator::new(&nick);
+    loop {
+        tokio::select! {
+            line = client_lines.recv() => {
+                let Some(line) = line else { break };
+                match translator.translate_line(&line) {
+                    Inbound::Forward(lines) => {
+                        let lines: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                        if native_writer.write_all(lines.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Inbound::Reply(lines) => {
+                        if write_lines(&mut writer, &lines).await.is_err() {
+                            break;
+                        }
+                    }
+                    Inbound::Quit => {
+                        let _ = write_lines(&mut writer, &["ERROR :Closing link (quit)".to_string()]).await;
+                        break;
+                    }
+                }
+            }
+            frame = frames.next_line() => {
+                let Ok(Some(frame)) = frame else {
+                    let _ = write_lines(&mut writer, &["ERROR :Closing link (the chat server hung up)".to_string()]).await;
+                    break;
+                };
+                let Ok(msg) = serde_json::from_str::<ChatMessage>(&frame) else { continue };
+                if write_lines(&mut writer, &translator.translate_frame(&msg)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    reading.abort();
+}
+
+// Reads until NICK and USER are in, answering what clients ask along the way. None when the client gives up
+async fn register<R, W>(reader: &mut R, writer: &mut W) -> Option<(String, Option<String>)>
+where
+    R: AsyncBufReadExt + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut registration = Registration::default();
+    loop {
+        let line = read_line(reader).await?;
+        let Some((verb, params)) = parse_line(&line) else { continue };
+        let reply = match verb.as_str() {
+            "CAP" if params.first().map(String::as_str) == Some("LS") => Some("CAP * LS :".to_string()), // no capabilities
+            "PASS" => {
+                registration.password = params.first().cloned();
+                None
+            }
+            "NICK" => match params.first().map(String::as_str) {
+                Some(nick) if is_nick(nick) => {
+                    registration.nick = Some(nick.to_string());
+                    None
+                }
+                Some(nick) => Some(format!(":{} 432 * {} :That nick can't be used", SERVER_NAME, nick)),
+                None => Some(format!(":{} 431 * :No nickname given", SERVER_NAME)),
+            },
+            "USER" => {
+                registration.user = true;
+                None
+            }
+            "PING" => Some(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, params.first().map(String::as_str).unwrap_or_default())),
+            "QUIT" => return None,
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            write_lines(writer, &[reply]).await.ok()?;
+        }
+        if let (Some(nick), true) = (&registration.nick, registration.user) {
+            return Some((nick.clone(), registration.password.take()));
+        }
+    }
+}
+
+// The numerics clients wait for before they consider themselves connected
+fn welcome(nick: &str) -> Vec<String> {
+    vec![
+        format!(":{} 001 {} :Welcome to the chat, {}", SERVER_NAME, nick, nick),
+        format!(":{} 002 {} :Your host is {}", SERVER_NAME, nick, SERVER_NAME),
+        format!(":{} 003 {} :This server speaks a small part of IRC, the rest of the chat is in the TUI client", SERVER_NAME, nick),
+        format!(":{} 004 {} {} 1 o o", SERVER_NAME, nick, SERVER_NAME),
+        // The MOTD comes as a notice like everything else the server says
+        format!(":{} 422 {} :No MOTD here, it comes as a notice", SERVER_NAME, nick),
+    ]
+}
+
+// One line from the client without its line ending, None once it hangs up (or sends an endless line)
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<String> {
+    let mut line = Vec::new();
+    let read = (&mut *reader).take(MAX_LINE_BYTES as u64).read_until(b'\n', &mut line).await.ok()?;
+    if read == 0 || (read == MAX_LINE_BYTES && !line.ends_with(b"\n")) {
+        return None;
+    }
+    // Old clients may send latin-1 and such, better mangled than dropped
+    Some(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn write_lines<W: AsyncWrite + Unpin>(writer: &mut W, lines: &[String]) -> std::io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let lines: String = lines.iter().map(|line| format!("{}\r\n", line)).collect();
+    writer.write_all(lines.as_bytes()).await
+}
+
+// What to do with a line from the client
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inbound {
+    // Lines for the server, in the usual protocol
+    Forward(Vec<String>),
+    // Lines straight back to the client (may be none)
+    Reply(Vec<String>),
+    Quit,
+}
+
+// Translation state of one connection: who we are and which channels the client thinks it's in
+#[derive(Debug)]
+pub struct Translator {
+    nick: String,
+    // Lowercased, like the server compares room names
+    joined: HashSet<String>,
+    // Where plain lines go on the server side; None after leaving it until we switch again
+    active: Option<String>,
+}
+
+impl Translator {
+    pub fn new(nick: &str) -> Self {
+        Translator { nick: nick.to_string(), joined: HashSet::new(), active: None }
+    }
+
+    // Our name on the server, which can change (a guest name for a registered nick, then /login)
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    // A line from the IRC client
+    pub fn translate_line(&mut self, line: &str) -> Inbound {
+        let Some((verb, params)) = parse_line(line) else { return Inbound::Reply(Vec::new()) };
+        let first = params.first().map(String::as_str).unwrap_or_default();
+        match verb.as_str() {
+            // Passed on as /ping, so the server sees we're still there (it drops silent connections),
+            // and its answer comes back as the PONG. A token /ping can't carry is answered here
+            "PING" => {
+                let token = if first.is_empty() { SERVER_NAME } else { first };
+                if token.len() <= MAX_PING_STAMP_LEN && !token.contains(char::is_whitespace) {
+                    Inbound::Forward(vec![format!("/ping {}", token)])
+                } else {
+                    Inbound::Reply(vec![format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, token)])
+                }
+            }
+            "QUIT" => Inbound::Quit,
+            // "JOIN #a,#b key": one /join each, the keys go with the channels in order
+            "JOIN" => {
+                let keys: Vec<&str> = params.get(1).map(|keys| keys.split(',').collect()).unwrap_or_default();
+                let joins = first
+                    .split(',')
+                    .filter_map(channel_room)
+                    .enumerate()
+                    .map(|(i, room)| match keys.get(i).filter(|key| !key.is_empty()) {
+                        Some(key) => format!("/join {} {}", room, key),
+                        None => format!("/join {}", room),
+                    })
+                    .collect();
+                Inbound::Forward(joins)
+            }
+            "PART" => Inbound::Forward(first.split(',').filter_map(channel_room).map(|room| format!("/leave {}", room)).collect()),
+            "PRIVMSG" => {
+                let text = params.get(1).map(String::as_str).unwrap_or_default();
+                // Other CTCP (VERSION, PING) isn't chat
+                let line = match text.strip_prefix("\x01ACTION ") {
+                    Some(action) => format!("/me {}", action.trim_end_matches('\x01')),
+                    None if text.starts_with('\x01') || text.trim().is_empty() => return Inbound::Reply(Vec::new()),
+                    None => text.to_string(),
+                };
+                match channel_room(first) {
+                    // The server would run it as a command, or hold everything after it as a code block (even
+                    // lines for other channels), so it isn't sent: commands go raw with /quote, blocks need the chat client
+                    Some(_) if Command::parse(&line).is_some() => Inbound::Reply(vec![self.notice("Not sent: lines starting with / are commands here, send them with /quote")]),
+                    Some(_) if opens_block(&line) => Inbound::Reply(vec![self.notice("Not sent: ``` starts a code block here, which only the chat client can send")]),
+                    Some(room) => self.in_room(room, line),
+                    None if line.starts_with("/me ") => Inbound::Reply(Vec::new()), // whispers have no actions
+                    None => Inbound::Forward(vec![format!("/whisper {} {}", first, line)]),
+                }
+            }
+            "TOPIC" => match (channel_room(first), params.get(1)) {
+                (Some(room), Some(text)) => {
+                    // "-" clears it on our side, an empty IRC topic does the same
+                    let text = if text.is_empty() { "-" } else { text.as_str() };
+                    self.in_room(room, format!("/topic {}", text))
+                }
+                (Some(room), None) => self.in_room(room, "/topic".to_string()),
+                (None, _) => Inbound::Reply(vec![format!(":{} 461 {} TOPIC :Not enough parameters", SERVER_NAME, self.nick)]),
+            },
+            "LIST" => Inbound::Forward(vec!["/rooms".to_string()]),
+            "WHOIS" => Inbound::Forward(vec![format!("/whois {}", params.last().map(String::as_str).unwrap_or_default())]),
+            "NICK" => Inbound::Reply(vec![format!(
+                ":{} NOTICE {} :Nick changes aren't supported here, reconnect with the new nick (or /quote LOGIN <name> <password>)",
+                SERVER_NAME, self.nick
+            )]),
+            // What clients ask about on their own, answered so they don't complain
+            "MODE" if first.starts_with('#') => Inbound::Reply(vec![format!(":{} 324 {} {} +", SERVER_NAME, self.nick, first)]),
+            "MODE" => Inbound::Reply(vec![format!(":{} 221 {} +", SERVER_NAME, self.nick)]),
+            "WHO" => Inbound::Reply(vec![format!(":{} 315 {} {} :End of /WHO list", SERVER_NAME, self.nick, first)]),
+            "CAP" | "PASS" | "USER" | "PONG" | "NOTICE" | "USERHOST" | "ISON" | "AWAY" => Inbound::Reply(Vec::new()),
+            // The server's own commands sent raw (/quote LOGIN alice hunter2)
+            _ => {
+                let command = std::iter::once(format!("/{}", verb.to_lowercase())).chain(params).collect::<Vec<_>>().join(" ");
+                match Command::parse(&command) {
+                    Some(_) => Inbound::Forward(vec![command]),
+                    None => Inbound::Reply(vec![format!(":{} 421 {} {} :Unknown command", SERVER_NAME, self.nick, verb)]),
+                }
+            }
+        }
+    }
+
+    // `line` goes to `room`, switching to it first when it isn't where plain lines go now
+    // Only rooms the server confirmed we're in: a /join that fails would leave the line in the wrong room
+    fn in_room(&mut self, room: &str, line: String) -> Inbound {
+        let key = room.to_lowercase();
+        if !self.joined.contains(&key) {
+            return Inbound::Reply(vec![format!(":{} 442 {} #{} :You're not on that channel", SERVER_NAME, self.nick, room)]);
+        }
+        if self.active.as_deref() == Some(key.as_str()) {
+            return Inbound::Forward(vec![line]);
+        }
+        self.active = Some(key);
+        Inbound::Forward(vec![format!("/join {}", room), line])
+    }
+
+    // A frame from the server, as lines for the IRC client
+    pub fn translate_frame(&mut self, msg: &ChatMessage) -> Vec<String> {
+        let from = format!("{}!{}@{}", irc_nick(&msg.username), irc_nick(&msg.username), SERVER_NAME);
+        let ours = msg.username.eq_ignore_ascii_case(&self.nick);
+        let mut lines = Vec::new();
+        match msg.message_type {
+            // Our own lines come back from the server, the client shows them already
+            MessageType::UserMessage | MessageType::Action if ours => {}
+            MessageType::UserMessage | MessageType::Action => {
+                let room = msg.room.clone().unwrap_or_else(|| self.active.clone().unwrap_or_default());
+                // History can come before the room's RoomJoined, the client wants the JOIN first
+                if !self.joined.contains(&room.to_lowercase()) {
+                    lines.extend(self.joined_room(&room, msg.topic.as_deref()));
+                }
+                let action = msg.message_type == MessageType::Action;
+                for text in split_text(&msg.content) {
+                    match action {
+                        true => lines.push(format!(":{} PRIVMSG #{} :\x01ACTION {}\x01", from, room, text)),
+                        false => lines.push(format!(":{} PRIVMSG #{} :{}", from, room, text)),
+                    }
+                }
+            }
+            MessageType::Whisper if ours => {}
+            MessageType::Whisper if msg.encrypted => lines.push(self.notice(&format!("{} sent you a sealed whisper, only the chat client can open it", msg.username))),
+            MessageType::Whisper => lines.extend(split_text(&msg.content).map(|text| format!(":{} PRIVMSG {} :{}", from, irc_nick(&self.nick), text))),
+            MessageType::RoomJoined => {
+                let room = msg.room.clone().unwrap_or_default();
+                if !self.joined.contains(&room.to_lowercase()) {
+                    lines.extend(self.joined_room(&room, msg.topic.as_deref()));
+                }
+                self.active = Some(room.to_lowercase());
+            }
+            MessageType::RoomLeft => {
+                let room = msg.room.clone().unwrap_or_default();
+                if self.joined.remove(&room.to_lowercase()) {
+                    lines.push(format!(":{}!{}@{} PART #{}", irc_nick(&self.nick), irc_nick(&self.nick), SERVER_NAME, room));
+                }
+                if self.active.as_deref() == Some(room.to_lowercase().as_str()) {
+                    self.active = None;
+                }
+            }
+            MessageType::RoomList => {
+                lines.push(format!(":{} 321 {} Channel :Users Name", SERVER_NAME, self.nick));
+                for room in &msg.rooms {
+                    lines.push(format!(":{} 322 {} #{} {} :{}", SERVER_NAME, self.nick, room.name, room.members, room.topic.as_deref().unwrap_or_default()));
+                }
+                lines.push(format!(":{} 323 {} :End of /LIST", SERVER_NAME, self.nick));
+            }
+            MessageType::SystemNotification => {
+                // Others coming and going in a room we're in, our own comings and goings are the Room frames
+                if let Some(room) = &msg.room {
+                    if msg.content == format!("joined #{}", room) {
+                        if !ours {
+                            lines.push(format!(":{} JOIN #{}", from, room));
+                        }
+                        return lines;
+                    }
+                    if msg.content == format!("left #{}", room) {
+                        if !ours {
+                            lines.push(format!(":{} PART #{}", from, room));
+                        }
+                        return lines;
+                    }
+                }
+                // Our name changed under us: the guest name for a registered nick, then the account on /login
+                if msg.renamed_from.as_deref().is_some_and(|was| was.eq_ignore_ascii_case(&self.nick)) {
+                    lines.push(format!(":{}!{}@{} NICK :{}", irc_nick(&self.nick), irc_nick(&self.nick), SERVER_NAME, irc_nick(&msg.username)));
+                    self.nick = msg.username.clone();
+                }
+                let text = match msg.username.as_str() {
+                    "System" | "" => msg.content.clone(),
+                    who => format!("{} {}", who, msg.content),
+                };
+                lines.extend(text.split('\n').filter(|line| !line.trim().is_empty()).map(|line| self.notice(line)));
+            }
+            // Errors and anything else meant to be read, as notices
+            MessageType::Error | MessageType::TwoFactorSetup | MessageType::BackupCodes | MessageType::OpsEvent | MessageType::RoomMode => {
+                lines.extend(msg.content.split('\n').filter(|line| !line.trim().is_empty()).map(|line| self.notice(line)));
+            }
+            // The server answering a PING we passed on
+            MessageType::Ping => lines.push(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, msg.content)),
+            // Bookkeeping for the TUI client (counts, rosters, keys, drafts and so on)
+            _ => {}
+        }
+        lines
+    }
+
+    // JOIN for a room we're now in, with its topic and names so clients open the window properly
+    fn joined_room(&mut self, room: &str, topic: Option<&str>) -> Vec<String> {
+        self.joined.insert(room.to_lowercase());
+        let nick = irc_nick(&self.nick);
+        let mut lines = vec![format!(":{}!{}@{} JOIN #{}", nick, nick, SERVER_NAME, room)];
+        match topic.filter(|topic| !topic.is_empty()) {
+            Some(topic) => lines.push(format!(":{} 332 {} #{} :{}", SERVER_NAME, nick, room, topic)),
+            None => lines.push(format!(":{} 331 {} #{} :No topic is set", SERVER_NAME, nick, room)),
+        }
+        // Only us: the server doesn't say who else is in a room, they show up as they talk and come and go
+        lines.push(format!(":{} 353 {} = #{} :{}", SERVER_NAME, nick, room, nick));
+        lines.push(format!(":{} 366 {} #{} :End of /NAMES list", SERVER_NAME, nick, room));
+        lines
+    }
+
+    fn notice(&self, text: &str) -> String {
+        format!(":{} NOTICE {} :{}", SERVER_NAME, irc_nick(&self.nick), text)
+    }
+}
+
+// "@tags :prefix VERB a b :trailing text": the verb (uppercased) and its parameters, tags and prefix dropped
+pub fn parse_line(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line.trim_start();
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    let (middle, trailing) = match rest.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = middle.split(' ').filter(|word| !word.is_empty());
+    let verb = words.next()?.to_uppercase();
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    params.extend(trailing.map(str::to_string));
+    Some((verb, params))
+}
+
+// "#lobby" is the room "lobby"; anything not a channel (a nick) is None
+fn channel_room(channel: &str) -> Option<&str> {
+    channel.strip_prefix('#').filter(|room| !room.is_empty())
+}
+
+// Nicks go in lines split on spaces, and can't start like a channel or a prefix
+fn is_nick(nick: &str) -> bool {
+    !nick.is_empty() && !nick.starts_with(['#', ':', '&']) && !nick.contains(|c: char| c.is_whitespace() || c == ',' || c == '!' || c == '@')
+}
+
+// A name from the server as it can go in an IRC line
+fn irc_nick(name: &str) -> String {
+    name.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect()
+}
+
+// Message text as PRIVMSG-sized pieces: one per line, long lines cut (on a character boundary)
+fn split_text(content: &str) -> impl Iterator<Item = &str> {
+    content.split('\n').map(|line| line.trim_end_matches('\r')).filter(|line| !line.is_empty()).flat_map(|mut line| {
+        let mut pieces = Vec::new();
+        while line.len() > MAX_TEXT_BYTES {
+            let mut cut = MAX_TEXT_BYTES;
+            while !line.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            pieces.push(&line[..cut]);
+            line = &line[cut..];
+        }
+        pieces.push(line);
+        pieces
+    })
+}
@@ -0,0 +1,100 @@
+// Caps on concurrent connections, globally and per IP address
+// A ConnectionPermit is held for the lifetime of a connection and frees its slot when dropped.
+// The caps can be changed while running; connections already in are never dropped for it.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    max_clients: Arc<AtomicUsize>,
+    max_per_ip: Arc<AtomicUsize>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+// Why a connection was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    ServerFull,
+    TooManyFromIp,
+}
+
+impl LimitExceeded {
+    pub fn message(self) -> &'static str {
+        match self {
+            LimitExceeded::ServerFull => "Server is full, try again later",
+            LimitExceeded::TooManyFromIp => "Too many connections from your address",
+        }
+    }
+}
+
+// Frees the connection's slot when dropped
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    ip: IpAddr,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionLimits {
+    pub fn new(max_clients: usize, max_per_ip: usize) -> Self {
+        ConnectionLimits {
+            max_clients: Arc::new(AtomicUsize::new(max_clients)),
+            max_per_ip: Arc::new(AtomicUsize::new(max_per_ip)),
+            counts: Arc::new(Mutex::new(Counts::default())),
+        }
+    }
+
+    // New caps apply to the next connection attempt
+    pub fn set_caps(&self, max_clients: usize, max_per_ip: usize) {
+        self.max_clients.store(max_clients, Ordering::Relaxed);
+        self.max_per_ip.store(max_per_ip, Ordering::Relaxed);
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.max_clients.load(Ordering::Relaxed)
+    }
+
+    // Claims a slot for a new connection from `ip`
+    pub fn try_acquire(&self, ip: IpAddr) -> Result<ConnectionPermit, LimitExceeded> {
+        let mut counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if counts.total >= self.max_clients() {
+            return Err(LimitExceeded::ServerFull);
+        }
+        let from_ip = counts.per_ip.entry(ip).or_default();
+        if *from_ip >= self.max_per_ip.load(Ordering::Relaxed) {
+            return Err(LimitExceeded::TooManyFromIp);
+        }
+        *from_ip += 1;
+        counts.total += 1;
+        Ok(ConnectionPermit {
+            ip,
+            counts: self.counts.clone(),
+        })
+    }
+
+    // Number of connections currently holding a permit
+    pub fn active(&self) -> usize {
+        self.counts.lock().map(|counts| counts.total).unwrap_or_default()
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(from_ip) = counts.per_ip.get_mut(&self.ip) {
+            *from_ip -= 1;
+            if *from_ip == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// TCP listeners for the --bind addresses, one accept loop each
+// Every loop hands what it accepts to the same channel, so the main loop lets clients in the same way whichever
+// address they came in on. IPv6 listeners take IPv6 only, so 0.0.0.0:8082 and [::]:8082 can be bound side by side.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use socket2::{Domain, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+// Where the server listens unless told otherwise
+pub const DEFAULT_BIND: &str = "127.0.0.1:8082";
+
+// Connections waiting to be let in, across all listeners
+pub const ACCEPT_QUEUE: usize = 64;
+
+// Pending connections the OS holds for each listener
+const BACKLOG: i32 = 1024;
+
+// "0.0.0.0:8082,[::]:8082": the addresses to listen on, in order and without repeats
+pub fn parse_bind_list(list: &str) -> Result<Vec<SocketAddr>, String> {
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let addr = part.parse().map_err(|_| format!("invalid --bind address: {} (expected ip:port, [ipv6]:port)", part))?;
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err("--bind needs at least one address".to_string());
+    }
+    Ok(addrs)
+}
+
+// A listener on `addr`, made the way TcpListener::bind makes them apart from IPv6 being IPv6 only
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // A restarted server can take its port straight back
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+// Accepts on every listener until the returned set is shut down (or dropped), everything goes to `accepted`
+pub fn spawn_accept_loops(listeners: Vec<TcpListener>, accepted: mpsc::Sender<(TcpStream, SocketAddr)>) -> JoinSet<()> {
+    let mut loops = JoinSet::new();
+    for listener in listeners {
+        let accepted = accepted.clone();
+        loops.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok(connection) => {
+                        if accepted.send(connection).await.is_err() {
+                            return; // the server is winding down
+                        }
+                    }
+                    // Out of file descriptors and the like, give it a moment rather than spin
+                    Err(e) => {
+                        eprintln!("[WARN] accepting on {} failed: {}", listener.local_addr().map(|addr| addr.to_string()).unwrap_or_default(), e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+    }
+    loops
+}
@@ -0,0 +1,51 @@
+// Periodic storage maintenance
+// Enforces the retention policy, VACUUMs SQLite and logs how much space came back.
+// Follows config reloads: each pass uses the current retention, a new interval restarts the schedule.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Local;
+use tokio::sync::{watch, Mutex};
+
+use crate::server::config::ServerConfig;
+use crate::server::store::Store;
+
+// Runs until the config sender goes away, one pass every `interval_minutes`
+// (the first pass happens after one interval)
+pub async fn run(store: Arc<Mutex<Store>>, mut config: watch::Receiver<ServerConfig>) {
+    let mut minutes = config.borrow().maintenance.interval_minutes.max(1);
+    let mut interval = tokio::time::interval(Duration::from_secs(minutes * 60));
+    interval.tick().await; // the first tick fires immediately
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            changed = config.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let reloaded = config.borrow().maintenance.interval_minutes.max(1);
+                if reloaded != minutes {
+                    minutes = reloaded;
+                    interval = tokio::time::interval(Duration::from_secs(minutes * 60));
+                    interval.tick().await;
+                }
+                continue;
+            }
+        }
+        let retention = config.borrow().retention.clone();
+        let result = store.lock().await.run_maintenance(&retention);
+        match result {
+            Ok(report) => println!(
+                "🧹[{}] Maintenance: removed {} messages, reclaimed {} KiB ({} KiB -> {} KiB)",
+                Local::now().format("%D:%H:%M:%S"),
+                report.deleted_messages,
+                report.reclaimed_bytes() / 1024,
+                report.bytes_before / 1024,
+                report.bytes_after / 1024,
+            ),
+            Err(e) => eprintln!("[ERROR] maintenance pass failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// Matrix bridge (matrix feature, [matrix] in the config)
+// A bot account joins one Matrix room and mirrors it with one room here, both ways. Matrix users show up here
+// under the configured prefix (mx-alice), and what's said here goes to Matrix as "<alice> hi" from the bot.
+// Only what's said after the bot joined is mirrored, and edits, notices and files aren't. Without the sdk's
+// end-to-end encryption the Matrix room can't be an encrypted one.
+// Like webhook posts, what comes in from Matrix doesn't go through the plugins, so it isn't sent back out.
+
+use tokio::sync::mpsc;
+
+use super::config::MatrixConfig;
+use super::plugins::ServerPlugin;
+use crate::protocol::{ChatMessage, MessageType};
+
+// Something said on the Matrix side, for the server to post in the local room
+#[derive(Debug, Clone)]
+pub struct MatrixMessage {
+    pub username: String,
+    pub text: String,
+    pub action: bool,
+}
+
+// Hands what's said in the local room to the bot
+pub struct MatrixBridge {
+    local_room: String,
+    outbox: mpsc::UnboundedSender<ChatMessage>,
+}
+
+impl MatrixBridge {
+    // A bridge for `local_room`, what it passes on comes out of the returned channel (the bot reads it)
+    pub fn new(local_room: &str) -> (Self, mpsc::UnboundedReceiver<ChatMessage>) {
+        let (outbox, outgoing) = mpsc::unbounded_channel();
+        (MatrixBridge { local_room: local_room.to_string(), outbox }, outgoing)
+    }
+
+    pub fn local_room(&self) -> &str {
+        &self.local_room
+    }
+}
+
+impl ServerPlugin for MatrixBridge {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    fn on_broadcast(&self, msg: &ChatMessage) {
+        let in_room = msg.room.as_deref().is_some_and(|room| room.eq_ignore_ascii_case(&self.local_room));
+        if in_room && matches!(msg.message_type, MessageType::UserMessage | MessageType::Action) {
+            let _ = self.outbox.send(msg.clone());
+        }
+    }
+}
+
+// The name a Matrix user goes by here: "mx-" and the localpart of @alice:example
+pub fn local_name(prefix: &str, localpart: &str) -> String {
+    format!("{}{}", prefix, localpart)
+}
+
+// What the bot says on Matrix for a message from here
+pub fn matrix_text(msg: &ChatMessage) -> String {
+    match msg.message_type {
+        MessageType::Action => format!("* {} {}", msg.username, msg.content),
+        _ => format!("<{}> {}", msg.username, msg.content),
+    }
+}
+
+// Starts the bot when there's a [matrix] section, what it hears comes out of the returned channel
+#[cfg(feature = "matrix")]
+pub fn start(config: Option<MatrixConfig>) -> Result<Option<(MatrixBridge, mpsc::UnboundedReceiver<MatrixMessage>)>, String> {
+    use matrix_sdk::ruma::RoomOrAliasId;
+
+    let Some(config) = config else { return Ok(None) };
+    RoomOrAliasId::parse(&config.room).map_err(|e| format!("matrix.room {}: {}", config.room, e))?;
+    let (bridge, outgoing) = MatrixBridge::new(&config.local_room);
+    let (inbox, incoming) = mpsc::unbounded_channel();
+    tokio::spawn(run(config, outgoing, inbox));
+    Ok(Some((bridge, incoming)))
+}
+
+#[cfg(not(feature = "matrix"))]
+pub fn start(config: Option<MatrixConfig>) -> Result<Option<(MatrixBridge, mpsc::UnboundedReceiver<MatrixMessage>)>, String> {
+    match config {
+        Some(_) => Err("this server was built without the Matrix bridge, build it with --features matrix".to_string()),
+        None => Ok(None),
+    }
+}
+
+// Keeps the bot signed in and syncing, starting over a while after anything goes wrong
+#[cfg(feature = "matrix")]
+async fn run(config: MatrixConfig, mut outgoing: mpsc::UnboundedReceiver<ChatMessage>, inbox: mpsc::UnboundedSender<MatrixMessage>) {
+    use std::time::Duration;
+
+    loop {
+        match session(&config, &mut outgoing, &inbox).await {
+            Ok(()) => return, // the server is winding down
+            Err(e) => eprintln!("[WARN] Matrix bridge: {}, trying again in 30s", e),
+        }
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+// One sign-in: join the room, then relay both ways until the sync fails
+#[cfg(feature = "matrix")]
+async fn session(config: &MatrixConfig, outgoing: &mut mpsc::UnboundedReceiver<ChatMessage>, inbox: &mpsc::UnboundedSender<MatrixMessage>) -> Result<(), String> {
+    use matrix_sdk::config::SyncSettings;
+    use matrix_sdk::ruma::events::room::message::{MessageType as MatrixType, OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent};
+    use matrix_sdk::ruma::RoomOrAliasId;
+    use matrix_sdk::{Client, Room};
+
+    let client = Client::builder().homeserver_url(&config.homeserver).build().await.map_err(|e| format!("{}: {}", config.homeserver, e))?;
+    client
+        .matrix_auth()
+        .login_username(&config.user, &config.password)
+        .initial_device_display_name("retro-chat bridge")
+        .await
+        .map_err(|e| format!("signing in as {}: {}", config.user, e))?;
+    let alias = RoomOrAliasId::parse(&config.room).map_err(|e| e.to_string())?;
+    let room = client.join_room_by_id_or_alias(&alias, &[]).await.map_err(|e| format!("joining {}: {}", config.room, e))?;
+    let own = client.user_id().ok_or("signed in without a user id")?.to_owned();
+
+    // What was said before we came stays there
+    let synced = client.sync_once(SyncSettings::default()).await.map_err(|e| e.to_string())?;
+    println!("🌉 Matrix bridge: {} is mirrored with #{}", config.room, config.local_room);
+
+    let target = room.room_id().to_owned();
+    let prefix = config.prefix.clone();
+    let inbox = inbox.clone();
+    client.add_event_handler(move |event: OriginalSyncRoomMessageEvent, room: Room| {
+        let (target, own, prefix, inbox) = (target.clone(), own.clone(), prefix.clone(), inbox.clone());
+        async move {
+            if room.room_id() != target || event.sender == own || matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+                return;
+            }
+            let (text, action) = match event.content.msgtype {
+                MatrixType::Text(text) => (text.body, false),
+                MatrixType::Emote(emote) => (emote.body, true),
+                _ => return,
+            };
+            let username = local_name(&prefix, event.sender.localpart());
+            let _ = inbox.send(MatrixMessage { username, text, action });
+        }
+    });
+
+    let sync = client.sync(SyncSettings::default().token(synced.next_batch));
+    tokio::pin!(sync);
+    loop {
+        tokio::select! {
+            result = &mut sync => return Err(result.err().map_or_else(|| "the sync stopped".to_string(), |e| e.to_string())),
+            msg = outgoing.recv() => {
+                let Some(msg) = msg else { return Ok(()) };
+                if let Err(e) = room.send(RoomMessageEventContent::text_plain(matrix_text(&msg))).await {
+                    eprintln!("[WARN] Matrix bridge: sending to {} failed: {}", config.room, e);
+                }
+            }
+        }
+    }
+}
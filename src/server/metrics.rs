@@ -0,0 +1,158 @@
+// Prometheus style metrics endpoint, so operators can graph which room makes the load
+// GET /metrics answers in the text exposition format: who's online, messages since start, and per open room its
+// members, msg/s and bytes/s fanned out (over the rooms' rate window) and the lag its members ran into.
+// Like the webhook endpoint it only asks the server for numbers, each scrape is one request on a channel.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+use super::rooms::RoomStats;
+use super::same_token;
+
+// Scrapes waiting for the server to answer
+const QUEUE: usize = 16;
+
+// Time a scraper gets to send its request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// What the server reports on a scrape
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub online: usize,
+    pub messages: u64,
+    pub rooms: Vec<RoomStats>,
+}
+
+// A scrape, answered with the numbers as they are now
+#[derive(Debug)]
+pub struct Scrape {
+    pub done: oneshot::Sender<Snapshot>,
+}
+
+// The text a scraper gets back, one family of samples after the other
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(Option<&str>, String)>| {
+        let _ = writeln!(out, "# HELP retro_chat_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE retro_chat_{} {}", name, kind);
+        for (room, value) in samples {
+            // Room names are letters, digits, '-' and '_', nothing to escape
+            match room {
+                Some(room) => {
+                    let _ = writeln!(out, "retro_chat_{}{{room=\"{}\"}} {}", name, room, value);
+                }
+                None => {
+                    let _ = writeln!(out, "retro_chat_{} {}", name, value);
+                }
+            }
+        }
+    };
+    let per_room = |value: fn(&RoomStats) -> String| snapshot.rooms.iter().map(|room| (Some(room.name.as_str()), value(room))).collect();
+    family("online", "gauge", "Users connected.", vec![(None, snapshot.online.to_string())]);
+    family("messages_total", "counter", "Chat messages since the server started.", vec![(None, snapshot.messages.to_string())]);
+    family("room_members", "gauge", "Members of an open room.", per_room(|room| room.members.to_string()));
+    family("room_messages_per_second", "gauge", "Frames a room sent, averaged over the rate window.", per_room(|room| room.messages_per_sec.to_string()));
+    family("room_bytes_per_second", "gauge", "Bytes a room fanned out to its members, averaged over the rate window.", per_room(|room| room.bytes_per_sec.to_string()));
+    family("room_lag_events_total", "counter", "Times a member fell behind a room's channel.", per_room(|room| room.lag_events.to_string()));
+    family("room_missed_frames_total", "counter", "Frames members lost by falling behind.", per_room(|room| room.missed.to_string()));
+    out
+}
+
+// Listens on `addr`, scrapes come out of the returned channel for the server to answer
+// With a token, only requests carrying it (as Authorization: Bearer) get the numbers
+pub fn listen(addr: SocketAddr, token: Option<String>) -> Result<mpsc::Receiver<Scrape>, String> {
+    let listener = super::listen::bind(addr).map_err(|e| format!("can't serve metrics on {}: {}", addr, e))?;
+    let (scrapes, received) = mpsc::channel(QUEUE);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    let scrapes = scrapes.clone();
+                    let token = token.clone();
+                    tokio::spawn(async move {
+                        let _ = tokio::time::timeout(TIMEOUT, serve(socket, token.as_deref(), scrapes)).await;
+                    });
+                }
+                Err(e) => {
+                    eprintln!("[WARN] accepting a metrics scrape on {} failed: {}", addr, e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+    Ok(received)
+}
+
+// One request, one answer, then the connection closes
+async fn serve(socket: TcpStream, token: Option<&str>, scrapes: mpsc::Sender<Scrape>) {
+    let mut socket = BufReader::new(socket);
+    let (status, content_type, body) = match handle(&mut socket, token, scrapes).await {
+        Ok(body) => (200, "text/plain; version=0.0.4; charset=utf-8", body),
+        Err((status, reason)) => (status, "text/plain; charset=utf-8", reason),
+    };
+    let answer = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        status_text(status),
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = socket.get_mut().write_all(answer.as_bytes()).await;
+    let _ = socket.get_mut().shutdown().await;
+}
+
+async fn handle(socket: &mut BufReader<TcpStream>, token: Option<&str>, scrapes: mpsc::Sender<Scrape>) -> Result<String, (u16, String)> {
+    let request_line = read_header_line(socket).await.ok_or((400, "no request".to_string()))?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    let mut bearer = None;
+    for _ in 0..100 {
+        let line = read_header_line(socket).await.ok_or((400, "headers cut short".to_string()))?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        if name.trim().eq_ignore_ascii_case("authorization") {
+            bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+        }
+    }
+
+    if target.split_once('?').map_or(target, |(path, _)| path) != "/metrics" {
+        return Err((404, "metrics are at /metrics".to_string()));
+    }
+    if method != "GET" {
+        return Err((405, "only GET".to_string()));
+    }
+    if token.is_some_and(|token| !bearer.is_some_and(|given| same_token(token, &given))) {
+        return Err((401, "wrong or missing token".to_string()));
+    }
+    let (done, answer) = oneshot::channel();
+    scrapes.send(Scrape { done }).await.map_err(|_| (503, "the server is shutting down".to_string()))?;
+    let snapshot = answer.await.map_err(|_| (503, "the server is shutting down".to_string()))?;
+    Ok(render(&snapshot))
+}
+
+// A header line without its line ending, None when the client hung up or sent something endless
+async fn read_header_line(socket: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    let read = (&mut *socket).take(8 * 1024).read_line(&mut line).await.ok()?;
+    (read > 0 && line.ends_with('\n')).then(|| line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    }
+}
@@ -0,0 +1,65 @@
+// Server side building blocks, kept out of the binary so they stay small and focused
+// accounts: argon2 password hashing for registered users
+// app: the server itself (ChatServer), shared state, the accept loop and connection handling
+// chaos: failures injected on purpose (lag, dropped connections) for staging
+// cluster: several server processes sharing chat, history and who's online through Redis (redis feature)
+// commands: parsing of the slash commands the server handles itself
+// config: the TOML configuration file
+// console: operator commands typed into the server terminal
+// cooldown: reconnect storm protection per IP and per username
+// federation: links with other servers, trading chat in the rooms we share
+// filter: word filter applied to chat messages before they go out
+// irc: minimal IRC listener translating to the usual protocol, for weechat/irssi users
+// limits: caps on concurrent connections (total and per IP)
+// listen: TCP listeners for the --bind addresses, one accept loop each
+// matrix: bridge mirroring a Matrix room with one of ours (matrix feature)
+// maintenance: periodic history retention and VACUUM
+// metrics: Prometheus style endpoint with per-room load
+// nicknames: lookalike name detection against registered accounts
+// outbound: per-connection writer with a system lane ahead of the chat lane
+// plugins: hooks into connections, messages and commands, plus the built-in plugins
+// presence: registry of connected clients with a control channel each
+// quic: experimental QUIC listener (quic feature)
+// roles: guest, member, moderator and owner, and the permissions they take
+// rooms: chat rooms with their own broadcast channel, history and members
+// scripting: rhai scripts as server-side bots (scripting feature)
+// store: SQLite persistence (accounts, message history, reactions, bans)
+// totp: time-based one-time codes for two-factor sign-in
+// unix: Unix domain socket listener for local-only deployments
+// webhooks: Slack/Discord style webhooks, relayed out and posted in
+
+pub mod accounts;
+pub mod app;
+pub mod chaos;
+pub mod cluster;
+pub mod commands;
+pub mod config;
+pub mod console;
+pub mod cooldown;
+pub mod federation;
+pub mod filter;
+pub mod irc;
+pub mod limits;
+pub mod listen;
+pub mod maintenance;
+pub mod matrix;
+pub mod metrics;
+pub mod nicknames;
+pub mod outbound;
+pub mod plugins;
+pub mod presence;
+pub mod quic;
+pub mod roles;
+pub mod rooms;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod store;
+pub mod totp;
+pub mod unix;
+pub mod webhooks;
+
+// Whether a token someone sent is the one expected (the server password, federation links, webhook posts)
+// Compares all of it whatever the first difference, so timing doesn't give the token away
+pub fn same_token(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,88 @@
+// Spotting nicknames made to pass for someone else's account
+// Names are compared by their "skeleton": lower case, separators dropped and characters that look alike
+// (0 and o, 1 and l, Cyrillic а and Latin a, rn and m, ...) folded together. A name whose skeleton
+// matches an account's, or is a single edit away from it, looks like that account.
+
+// Names shorter than this (as skeletons) only count when they match exactly, one edit is too little to go on
+const MIN_FUZZY_LEN: usize = 5;
+// Edits allowed between two skeletons that still look alike
+const MAX_DISTANCE: usize = 1;
+
+// Characters folded into the Latin letter they pass for
+const HOMOGLYPHS: [(char, char); 28] = [
+    ('0', 'o'),
+    ('1', 'l'),
+    ('i', 'l'),
+    ('|', 'l'),
+    ('3', 'e'),
+    ('4', 'a'),
+    ('@', 'a'),
+    ('5', 's'),
+    ('$', 's'),
+    ('7', 't'),
+    ('8', 'b'),
+    ('9', 'g'),
+    // Cyrillic and Greek letters that render like Latin ones
+    ('а', 'a'),
+    ('е', 'e'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('х', 'x'),
+    ('у', 'y'),
+    ('і', 'l'),
+    ('ј', 'j'),
+    ('ѕ', 's'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('т', 't'),
+    ('ο', 'o'),
+    ('α', 'a'),
+    ('ν', 'v'),
+];
+
+// Letter pairs that read as one letter at a glance
+const DIGRAPHS: [(&str, &str); 3] = [("rn", "m"), ("vv", "w"), ("cl", "d")];
+
+// The name reduced to what it looks like
+pub fn skeleton(name: &str) -> String {
+    let folded: String = name
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !matches!(c, '_' | '-' | '.' | ' '))
+        .map(|c| HOMOGLYPHS.iter().find(|(glyph, _)| *glyph == c).map_or(c, |(_, latin)| *latin))
+        .collect();
+    DIGRAPHS.iter().fold(folded, |name, (pair, letter)| name.replace(pair, letter))
+}
+
+// True when `name` could be mistaken for `account` without being it
+pub fn looks_like(name: &str, account: &str) -> bool {
+    if name.eq_ignore_ascii_case(account) {
+        return false;
+    }
+    let (name, account) = (skeleton(name), skeleton(account));
+    if name == account {
+        return true;
+    }
+    name.chars().count().min(account.chars().count()) >= MIN_FUZZY_LEN && levenshtein(&name, &account) <= MAX_DISTANCE
+}
+
+// The accounts `name` could be mistaken for, out of all registered ones
+pub fn lookalikes<'a>(name: &str, accounts: &'a [String]) -> Vec<&'a str> {
+    accounts.iter().filter(|account| looks_like(name, account)).map(String::as_str).collect()
+}
+
+// Edits (insert, delete, substitute) between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitute.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,205 @@
+// What one connection sends, in two lanes: system frames (notices, errors, command replies, countdowns,
+// user counts) and chat. A writer task owns the socket's write half and always empties the system lane
+// before it takes the next chat frame, so control traffic never waits behind a backlog of chat.
+// The chat lane is bounded: a client too slow to keep up misses chat frames, never system ones.
+// The system lane is bounded too, but a client that lets it fill up has stopped reading: it is dropped. Roster and
+// user count frames only matter in their newest version, so at most one of each waits and later ones replace it.
+// Lag injected with /chaos holds back each batch of frames that finds the writer idle.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+use crate::protocol::{ChatMessage, MessageType};
+use crate::server::chaos::Chaos;
+use crate::trace::{Direction, WireTracer};
+
+// Chat frames queued for one connection before new ones are dropped
+pub const CHAT_LANE_CAPACITY: usize = 256;
+
+// System frames queued for one connection before it is dropped
+pub const SYSTEM_LANE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    System,
+    Chat,
+}
+
+impl Lane {
+    // What people say goes in the chat lane, everything the server says in the system lane
+    pub fn of(message_type: &MessageType) -> Lane {
+        match message_type {
+            MessageType::UserMessage | MessageType::Action | MessageType::Whisper | MessageType::Reactions => Lane::Chat,
+            _ => Lane::System,
+        }
+    }
+
+    // Same for a frame that is already serialized (broadcasts are), unreadable ones count as chat
+    pub fn of_json(json: &str) -> Lane {
+        peek(json).map_or(Lane::Chat, |message_type| Lane::of(&message_type))
+    }
+}
+
+// The type of a serialized frame
+fn peek(json: &str) -> Option<MessageType> {
+    #[derive(Deserialize)]
+    struct Peek {
+        message_type: MessageType,
+    }
+    serde_json::from_str::<Peek>(json).ok().map(|peek| peek.message_type)
+}
+
+// Where the newest frame of a type waits when only the newest one matters
+fn latest_slot(message_type: &MessageType) -> Option<usize> {
+    match message_type {
+        MessageType::Roster => Some(0),
+        MessageType::UserCount => Some(1),
+        _ => None,
+    }
+}
+
+// What the system lane carries: a frame, or word that the newest frame in a slot is waiting
+#[derive(Debug)]
+enum SystemFrame {
+    Frame(String),
+    Latest(usize),
+}
+
+#[derive(Debug)]
+pub struct Outbound {
+    system: mpsc::Sender<SystemFrame>,
+    latest: Arc<Mutex<[Option<String>; 2]>>,
+    chat: mpsc::Sender<String>,
+    writer: JoinHandle<io::Result<()>>,
+    peer: String,
+}
+
+impl Outbound {
+    // Starts the writer task for one connection, frames are traced as they are written
+    pub fn spawn<W>(writer: W, tracer: Arc<WireTracer>, chaos: Arc<Chaos>, peer: String) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (system, system_rx) = mpsc::channel(SYSTEM_LANE_CAPACITY);
+        let (chat, chat_rx) = mpsc::channel(CHAT_LANE_CAPACITY);
+        let latest = Arc::new(Mutex::new([None, None]));
+        let writer = tokio::spawn(write_lanes(writer, system_rx, latest.clone(), chat_rx, tracer, chaos, peer.clone()));
+        Outbound { system, latest, chat, writer, peer }
+    }
+
+    // Queues a frame in the lane its type belongs in
+    pub fn send(&self, frame: &ChatMessage) {
+        match serde_json::to_string(frame) {
+            Ok(json) => self.queue(Some(&frame.message_type), json),
+            Err(e) => eprintln!("[ERROR] failed to serialize frame: {}", e),
+        }
+    }
+
+    // Queues an already serialized frame
+    pub fn forward(&self, json: String) {
+        self.queue(peek(&json).as_ref(), json);
+    }
+
+    // Queues a batch (history, missed messages) in the chat lane, in order and waiting for room rather
+    // than dropping any, so notices among them (a room being joined) stay where they are
+    pub async fn replay(&self, frames: &[ChatMessage]) {
+        for frame in frames {
+            let Ok(json) = serde_json::to_string(frame) else { continue };
+            if self.chat.send(json).await.is_err() {
+                return; // writer is gone
+            }
+        }
+    }
+
+    // Resolves once the writer has stopped (the client went away or can't be written to)
+    pub async fn closed(&self) {
+        self.system.closed().await
+    }
+
+    // Writes out everything queued so far, then closes the socket
+    pub async fn close(self) {
+        let Outbound { system, chat, writer, .. } = self;
+        drop((system, chat));
+        let _ = writer.await;
+    }
+
+    // Stops writing right away, whatever is still queued is lost
+    pub fn abort(self) {
+        self.writer.abort();
+    }
+
+    // A stopped writer is noticed through closed(), so only a full lane is worth a word
+    fn queue(&self, message_type: Option<&MessageType>, json: String) {
+        if message_type.map_or(Lane::Chat, Lane::of) == Lane::Chat {
+            if let Err(TrySendError::Full(_)) = self.chat.try_send(json) {
+                eprintln!("[WARN] {} can't keep up, dropped a chat frame", self.peer);
+            }
+            return;
+        }
+        let frame = match message_type.and_then(latest_slot) {
+            Some(slot) => {
+                // One already waiting goes out as this one instead
+                if self.latest.lock().unwrap_or_else(|poisoned| poisoned.into_inner())[slot].replace(json).is_some() {
+                    return;
+                }
+                SystemFrame::Latest(slot)
+            }
+            None => SystemFrame::Frame(json),
+        };
+        if let Err(TrySendError::Full(_)) = self.system.try_send(frame) {
+            // Nobody reads what we send, keeping it all would only eat memory; closed() tells the connection
+            eprintln!("[WARN] {} stopped reading, dropping the connection", self.peer);
+            self.writer.abort();
+        }
+    }
+}
+
+// The writer task: system frames first, chat when there are none, until both lanes are closed and empty
+async fn write_lanes<W: AsyncWrite + Unpin>(
+    writer: W,
+    mut system: mpsc::Receiver<SystemFrame>,
+    latest: Arc<Mutex<[Option<String>; 2]>>,
+    mut chat: mpsc::Receiver<String>,
+    tracer: Arc<WireTracer>,
+    chaos: Arc<Chaos>,
+    peer: String,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    // Whether everything written so far has been flushed, the next frame starts a new batch
+    let mut idle = true;
+    loop {
+        let frame = tokio::select! {
+            biased;
+            Some(frame) = system.recv() => match frame {
+                SystemFrame::Frame(frame) => frame,
+                SystemFrame::Latest(slot) => match latest.lock().unwrap_or_else(|poisoned| poisoned.into_inner())[slot].take() {
+                    Some(frame) => frame,
+                    None => continue,
+                },
+            },
+            Some(frame) = chat.recv() => frame,
+            else => break,
+        };
+        // Injected lag delays the batch as a whole, frames queued meanwhile go out along with it
+        let lag = chaos.lag();
+        if idle && !lag.is_zero() {
+            tokio::time::sleep(lag).await;
+        }
+        idle = false;
+        tracer.record(Direction::Sent, &peer, &frame);
+        writer.write_all(frame.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        // Only flush once nothing else is waiting, a backlog goes out in as few writes as it can
+        if system.is_empty() && chat.is_empty() {
+            writer.flush().await?;
+            idle = true;
+        }
+    }
+    writer.flush().await?;
+    writer.shutdown().await
+}
@@ -0,0 +1,195 @@
+// Server plugins: hooks that run at fixed points of a connection's life
+// Every hook can veto what is happening by returning the error frame the client gets instead;
+// on_message can also rewrite the text before it is stored and sent.
+// Plugins run in the order they were registered and the first veto wins.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::protocol::{ChatMessage, ErrorKind};
+use crate::server::commands::Command;
+use crate::server::cooldown::ReconnectGuard;
+use crate::server::filter::WordFilter;
+
+// What a hook decided: go ahead, or the error frame the client gets instead
+pub type Verdict = Result<(), Box<ChatMessage>>;
+
+// Who a hook is about
+#[derive(Debug, Clone, Copy)]
+pub struct ClientInfo<'a> {
+    pub username: &'a str,
+    pub addr: SocketAddr,
+    // Room the client's plain messages go to
+    pub room: &'a str,
+    pub verified: bool,
+}
+
+// Hooks default to doing nothing, so a plugin only implements the ones it cares about
+pub trait ServerPlugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    // A client sent its handshake; a veto hangs up on it
+    fn on_connect(&self, _client: &ClientInfo) -> Verdict {
+        Ok(())
+    }
+
+    // A chat message is about to be stored and sent
+    fn on_message(&self, _client: &ClientInfo, _text: &mut String) -> Verdict {
+        Ok(())
+    }
+
+    // A slash command is about to run
+    fn on_command(&self, _client: &ClientInfo, _command: &Command) -> Verdict {
+        Ok(())
+    }
+
+    // The client is gone, `reason` is what the others were told ("has blasted off", "was kicked", ...)
+    fn on_disconnect(&self, _client: &ClientInfo, _reason: &str) {}
+
+    // A chat message went out to everyone in its room, too late to change anything
+    fn on_broadcast(&self, _msg: &ChatMessage) {}
+}
+
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn ServerPlugin>>,
+}
+
+impl Plugins {
+    pub fn register(&mut self, plugin: Arc<dyn ServerPlugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|plugin| plugin.name()).collect()
+    }
+
+    pub fn on_connect(&self, client: &ClientInfo) -> Verdict {
+        self.plugins.iter().try_for_each(|plugin| plugin.on_connect(client))
+    }
+
+    pub fn on_message(&self, client: &ClientInfo, text: &mut String) -> Verdict {
+        self.plugins.iter().try_for_each(|plugin| plugin.on_message(client, text))
+    }
+
+    pub fn on_command(&self, client: &ClientInfo, command: &Command) -> Verdict {
+        self.plugins.iter().try_for_each(|plugin| plugin.on_command(client, command))
+    }
+
+    // Everyone hears about a disconnect, there's nothing left to veto
+    pub fn on_disconnect(&self, client: &ClientInfo, reason: &str) {
+        for plugin in &self.plugins {
+            plugin.on_disconnect(client, reason);
+        }
+    }
+
+    pub fn on_broadcast(&self, msg: &ChatMessage) {
+        for plugin in &self.plugins {
+            plugin.on_broadcast(msg);
+        }
+    }
+}
+
+// --- built-in plugins ---
+
+// Turns away addresses and usernames that reconnect too fast (see cooldown.rs)
+pub struct RateLimitPlugin {
+    guard: Mutex<ReconnectGuard>,
+}
+
+impl RateLimitPlugin {
+    // `limit` connections per minute for each IP and each username
+    pub fn new(limit: usize) -> Self {
+        RateLimitPlugin { guard: Mutex::new(ReconnectGuard::new(limit, Duration::from_secs(60))) }
+    }
+}
+
+impl ServerPlugin for RateLimitPlugin {
+    fn name(&self) -> &str {
+        "rate-limit"
+    }
+
+    fn on_connect(&self, client: &ClientInfo) -> Verdict {
+        let mut guard = self.guard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let ip = client.addr.ip().to_string();
+        guard.check(&format!("ip:{}", ip), now).map_err(|cooldown| cooldown_frame(&ip, cooldown))?;
+        guard
+            .check(&format!("user:{}", client.username), now)
+            .map_err(|cooldown| cooldown_frame(client.username, cooldown))
+    }
+}
+
+// Tells a client it reconnected too often and how long to wait before trying again
+fn cooldown_frame(who: &str, cooldown: Duration) -> Box<ChatMessage> {
+    let seconds = cooldown.as_secs().max(1);
+    println!("└─[{}] {} is reconnecting too fast, cooling down for {}s", Local::now().format("%D:%H:%M:%S"), who, seconds);
+    let frame = ChatMessage::error(ErrorKind::Cooldown, format!("Too many reconnects, try again in {} seconds", seconds));
+    Box::new(frame.with_retry_after(seconds))
+}
+
+// Runs chat messages through the word filter; the filter itself can be swapped or edited while running
+pub struct WordFilterPlugin {
+    filter: Mutex<WordFilter>,
+}
+
+impl WordFilterPlugin {
+    pub fn new(filter: WordFilter) -> Self {
+        WordFilterPlugin { filter: Mutex::new(filter) }
+    }
+
+    pub fn filter(&self) -> MutexGuard<'_, WordFilter> {
+        self.filter.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ServerPlugin for WordFilterPlugin {
+    fn name(&self) -> &str {
+        "word-filter"
+    }
+
+    fn on_message(&self, client: &ClientInfo, text: &mut String) -> Verdict {
+        match self.filter().apply(client.room, text) {
+            Ok(filtered) => {
+                *text = filtered;
+                Ok(())
+            }
+            Err(word) => {
+                let reason = format!("Your message was not sent: \"{}\" is not allowed here", word);
+                Err(Box::new(ChatMessage::error(ErrorKind::MessageBlocked, reason)))
+            }
+        }
+    }
+}
+
+// Prints arrivals, commands and departures to the server terminal
+pub struct LoggerPlugin;
+
+impl ServerPlugin for LoggerPlugin {
+    fn name(&self) -> &str {
+        "logger"
+    }
+
+    fn on_connect(&self, client: &ClientInfo) -> Verdict {
+        println!("└─[{}] {} connected from {}", Local::now().format("%D:%H:%M:%S"), client.username, client.addr);
+        Ok(())
+    }
+
+    // Only the command's name: arguments can hold passwords
+    // Drafts come in whenever someone stops typing for a moment, far too many to be worth a line each,
+    // and signatures with every message
+    fn on_command(&self, client: &ClientInfo, command: &Command) -> Verdict {
+        if matches!(command, Command::Draft { .. } | Command::Sign { .. }) {
+            return Ok(());
+        }
+        println!("└─[{}] {} ran /{}", Local::now().format("%D:%H:%M:%S"), client.username, command.name());
+        Ok(())
+    }
+
+    fn on_disconnect(&self, client: &ClientInfo, reason: &str) {
+        println!("└─[{}] {} {}", Local::now().format("%D:%H:%M:%S"), client.username, reason);
+    }
+}
@@ -0,0 +1,237 @@
+// Registry of the clients connected right now
+// Each connection task registers itself after the handshake and gets a control channel,
+// which lets other parts of the server (the admin console, moderation) reach into it.
+// The number of connected clients, and who they are, are published on watch channels for anyone who wants to follow them.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+use crate::protocol::{ChatMessage, Member};
+use crate::server::roles::Role;
+
+// Instructions a connection task accepts from the rest of the server
+#[derive(Debug, Clone)]
+pub enum Control {
+    // Write a frame to this client only
+    Send(Box<ChatMessage>),
+    // Disconnect the client, telling it why
+    Kick { reason: String },
+    // Disconnect the client because its address was banned
+    Ban,
+    // Cut the connection without a word, as if the network went down (/chaos)
+    Drop,
+    // Who may post in a room changed, the client is told whether it still can
+    RoomMode { room: String },
+    // The account was promoted or demoted to this role
+    Role { role: Role },
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientEntry {
+    pub username: String,
+    pub verified: bool,
+    // The role stored for the account, see ServerConfig::role_of for the one it has
+    pub role: Role,
+    pub addr: SocketAddr,
+    pub connected_at: Instant,
+    // When the client last sent something a person typed (not pings and read marks), for /whois
+    pub last_active: Instant,
+    // Key whispers to this connection are sealed with, from the handshake
+    pub public_key: Option<String>,
+    // Do not disturb, from the client's /dnd
+    pub dnd: bool,
+    control: UnboundedSender<Control>,
+}
+
+#[derive(Debug)]
+pub struct Presence {
+    next_id: u64,
+    clients: BTreeMap<u64, ClientEntry>,
+    count: watch::Sender<usize>,
+    roster: watch::Sender<Vec<Member>>,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Presence {
+            next_id: 0,
+            clients: BTreeMap::new(),
+            count: watch::Sender::new(0),
+            roster: watch::Sender::new(Vec::new()),
+        }
+    }
+}
+
+impl Presence {
+    // Follows the number of connected clients
+    pub fn watch_count(&self) -> watch::Receiver<usize> {
+        self.count.subscribe()
+    }
+
+    // Follows who is online
+    pub fn watch_roster(&self) -> watch::Receiver<Vec<Member>> {
+        self.roster.subscribe()
+    }
+
+    // Adds a connection, returns its id and the receiving end of its control channel
+    pub fn register(&mut self, username: &str, verified: bool, role: Role, addr: SocketAddr) -> (u64, UnboundedReceiver<Control>) {
+        let (control, rx) = unbounded_channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.insert(
+            id,
+            ClientEntry {
+                username: username.to_string(),
+                verified,
+                role,
+                addr,
+                connected_at: Instant::now(),
+                last_active: Instant::now(),
+                public_key: None,
+                dnd: false,
+                control,
+            },
+        );
+        self.publish();
+        (id, rx)
+    }
+
+    // Follows a connection that signed in (or out) under another name, or got another role
+    pub fn rename(&mut self, id: u64, username: &str, verified: bool, role: Role) {
+        if let Some(entry) = self.clients.get_mut(&id) {
+            entry.username = username.to_string();
+            entry.verified = verified;
+            entry.role = role;
+            self.publish();
+        }
+    }
+
+    pub fn set_public_key(&mut self, id: u64, key: Option<String>) {
+        if let Some(entry) = self.clients.get_mut(&id) {
+            entry.public_key = key;
+        }
+    }
+
+    // The whisper key for `username`: the newest of its connections' that has one
+    pub fn public_key(&self, username: &str) -> Option<String> {
+        self.clients.values().rev().filter(|entry| entry.username.eq_ignore_ascii_case(username)).find_map(|entry| entry.public_key.clone())
+    }
+
+    // Everyone's whisper key, by name
+    pub fn public_keys(&self) -> Vec<(String, String)> {
+        let mut keys: Vec<(String, String)> = Vec::new();
+        for entry in self.clients.values().rev() {
+            if let Some(key) = &entry.public_key
+                && !keys.iter().any(|(name, _)| name.eq_ignore_ascii_case(&entry.username))
+            {
+                keys.push((entry.username.clone(), key.clone()));
+            }
+        }
+        keys
+    }
+
+    pub fn set_dnd(&mut self, id: u64, on: bool) {
+        if let Some(entry) = self.clients.get_mut(&id) {
+            entry.dnd = on;
+            self.publish();
+        }
+    }
+
+    // Whether any of `username`'s connections has do not disturb on
+    pub fn is_dnd(&self, username: &str) -> bool {
+        self.clients.values().any(|entry| entry.dnd && entry.username.eq_ignore_ascii_case(username))
+    }
+
+    // The person behind a connection did something
+    pub fn touch(&mut self, id: u64) {
+        if let Some(entry) = self.clients.get_mut(&id) {
+            entry.last_active = Instant::now();
+        }
+    }
+
+    pub fn unregister(&mut self, id: u64) {
+        if self.clients.remove(&id).is_some() {
+            self.publish();
+        }
+    }
+
+    // Everyone online by name, once each however many connections they have
+    pub fn roster(&self) -> Vec<Member> {
+        let mut members: Vec<Member> = self
+            .clients
+            .values()
+            .map(|entry| Member { username: entry.username.clone(), verified: entry.verified, dnd: self.is_dnd(&entry.username) })
+            .collect();
+        members.sort_by(|a, b| a.username.to_lowercase().cmp(&b.username.to_lowercase()).then(b.verified.cmp(&a.verified)));
+        members.dedup_by(|a, b| a.username.eq_ignore_ascii_case(&b.username));
+        members
+    }
+
+    // Watchers only hear about real changes, a rename to the same name is nothing new
+    fn publish(&self) {
+        let count = self.clients.len();
+        self.count.send_if_modified(|current| std::mem::replace(current, count) != count);
+        let roster = self.roster();
+        self.roster.send_if_modified(|current| std::mem::replace(current, roster.clone()) != roster);
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    // Connected clients in connection order
+    pub fn clients(&self) -> impl Iterator<Item = &ClientEntry> {
+        self.clients.values()
+    }
+
+    // Every connection using `username`, with its id, in connection order
+    pub fn connections_of(&self, username: &str) -> Vec<(u64, ClientEntry)> {
+        self.clients
+            .iter()
+            .filter(|(_, entry)| entry.username.eq_ignore_ascii_case(username))
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect()
+    }
+
+    // Sends a control message to one connection, false when it is gone
+    pub fn send_to(&self, id: u64, control: Control) -> bool {
+        self.clients.get(&id).is_some_and(|entry| entry.control.send(control).is_ok())
+    }
+
+    // Sends a control message to every connection using `username`, returns how many got it
+    pub fn send_to_user(&self, username: &str, control: Control) -> usize {
+        self.send_where(|entry| entry.username.eq_ignore_ascii_case(username), control)
+    }
+
+    // Sends a control message to every connection signed in to the account `username`, returns how many got it
+    pub fn send_to_account(&self, username: &str, control: Control) -> usize {
+        self.send_where(|entry| entry.verified && entry.username.eq_ignore_ascii_case(username), control)
+    }
+
+    // Sends a control message to every connection from `ip`, returns how many got it
+    pub fn send_to_ip(&self, ip: IpAddr, control: Control) -> usize {
+        self.send_where(|entry| entry.addr.ip() == ip, control)
+    }
+
+    // Sends a control message to every connection, returns how many got it
+    pub fn send_to_all(&self, control: Control) -> usize {
+        self.send_where(|_| true, control)
+    }
+
+    // Sends a control message to every connection `matches` picks, returns how many got it
+    pub fn send_where(&self, matches: impl Fn(&ClientEntry) -> bool, control: Control) -> usize {
+        self.clients
+            .values()
+            .filter(|entry| matches(entry))
+            .filter(|entry| entry.control.send(control.clone()).is_ok())
+            .count()
+    }
+}
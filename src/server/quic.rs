@@ -0,0 +1,126 @@
+// Experimental QUIC listener (quic feature, --quic <addr>)
+// A QUIC client opens one bidirectional stream and speaks the usual protocol on it, line by line, so from there
+// on it's handled like any other connection. Clients on lossy networks (mobile, bad wifi) get QUIC's faster
+// recovery: a lost packet holds up only that packet, and a changed IP address doesn't drop the connection.
+// QUIC is always encrypted, the certificate comes from --quic-cert/--quic-key, or one made for "localhost" is
+// kept in quic-cert.pem/quic-key.pem (pass quic-cert.pem to clients with --quic-cert to have them trust it).
+// Connections are made and their stream awaited in the background and handed over through a channel, so
+// a slow handshake holds up nobody.
+
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Where the certificate made for "localhost" is kept
+pub const DEFAULT_CERT: &str = "quic-cert.pem";
+pub const DEFAULT_KEY: &str = "quic-key.pem";
+
+#[cfg(feature = "quic")]
+pub type QuicStream = tokio::io::Join<quinn::RecvStream, quinn::SendStream>;
+// Never made, only here so the accept loop reads the same with and without the feature
+#[cfg(not(feature = "quic"))]
+pub type QuicStream = tokio::io::DuplexStream;
+
+#[derive(Debug, Default)]
+pub struct QuicListener {
+    addr: Option<SocketAddr>,
+    accepted: Option<mpsc::Receiver<(QuicStream, SocketAddr)>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl QuicListener {
+    // Listens on `addr` (UDP), or doesn't listen at all when there is none
+    #[cfg(feature = "quic")]
+    pub fn bind(addr: Option<SocketAddr>, cert: Option<&str>, key: Option<&str>) -> Result<Self, String> {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let Some(addr) = addr else { return Ok(QuicListener::default()) };
+        let (certs, key) = load_or_make_cert(cert.unwrap_or(DEFAULT_CERT), key.unwrap_or(DEFAULT_KEY), cert.is_none())?;
+        let mut config = quinn::ServerConfig::with_single_cert(certs, key).map_err(|e| format!("QUIC certificate: {}", e))?;
+        // Clients ping every 30s, that's no reason to go quiet for longer than the TCP side would
+        let mut transport = quinn::TransportConfig::default();
+        transport.max_idle_timeout(Duration::from_secs(120).try_into().ok());
+        config.transport_config(Arc::new(transport));
+        let endpoint = quinn::Endpoint::server(config, addr).map_err(|e| format!("can't listen for QUIC on {}: {}", addr, e))?;
+        let addr = endpoint.local_addr().unwrap_or(addr);
+
+        let (tx, accepted) = mpsc::channel(super::listen::ACCEPT_QUEUE);
+        let task = tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let Ok(connection) = incoming.await else { return };
+                    let addr = connection.remote_address();
+                    // The stream shows up with the client's first bytes (the handshake), those that never send any are let go
+                    let Ok(Ok((send, recv))) = tokio::time::timeout(Duration::from_secs(10), connection.accept_bi()).await else {
+                        return;
+                    };
+                    let _ = tx.send((tokio::io::join(recv, send), addr)).await;
+                });
+            }
+        });
+        Ok(QuicListener { addr: Some(addr), accepted: Some(accepted), task: Some(task) })
+    }
+
+    #[cfg(not(feature = "quic"))]
+    pub fn bind(addr: Option<SocketAddr>, _cert: Option<&str>, _key: Option<&str>) -> Result<Self, String> {
+        match addr {
+            Some(_) => Err("this server was built without QUIC, build it with --features quic".to_string()),
+            None => Ok(QuicListener::default()),
+        }
+    }
+
+    // The UDP address we listen on, if any
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    // The next client with its stream, waits forever when we aren't listening
+    pub async fn accept(&mut self) -> Option<(QuicStream, SocketAddr)> {
+        match &mut self.accepted {
+            Some(accepted) => accepted.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+// No new connections once the listener is gone, those already in carry on
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+// The certificate chain and key in `cert`/`key` (PEM). The default files are made when they aren't there yet,
+// files given on the command line have to be
+#[cfg(feature = "quic")]
+fn load_or_make_cert(
+    cert: &str,
+    key: &str,
+    make: bool,
+) -> Result<(Vec<rustls::pki_types::CertificateDer<'static>>, rustls::pki_types::PrivateKeyDer<'static>), String> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use std::path::Path;
+
+    if make && !Path::new(cert).exists() {
+        let made = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).map_err(|e| format!("making a QUIC certificate: {}", e))?;
+        std::fs::write(cert, made.cert.pem()).map_err(|e| format!("writing {}: {}", cert, e))?;
+        std::fs::write(key, made.key_pair.serialize_pem()).map_err(|e| format!("writing {}: {}", key, e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(key, std::fs::Permissions::from_mode(0o600));
+        }
+        println!("🔐 Made a QUIC certificate for localhost in {} (clients trust it with --quic-cert {})", cert, cert);
+    }
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("reading {}: {}", cert, e))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| format!("reading {}: {}", key, e))?;
+    Ok((certs, key))
+}
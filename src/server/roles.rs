@@ -0,0 +1,92 @@
+// Who may do what: every connection has a role, and every gated command needs a permission
+// Guests (not signed in) are guests, accounts are members unless /promote made them more (kept in chat.db),
+// and the accounts listed under [moderation] owners in the config are owners whatever the database says.
+// Which role a permission takes starts from the defaults below and can be changed in [permissions].
+
+use std::fmt;
+
+use serde::Deserialize;
+
+// Lowest to highest, so roles compare the way they rank
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Guest,
+    #[default]
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Guest, Role::Member, Role::Moderator, Role::Owner];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name().eq_ignore_ascii_case(name))
+    }
+
+    // One step up or down, None past the ends
+    pub fn above(self) -> Option<Role> {
+        Role::ALL.get(self as usize + 1).copied()
+    }
+
+    pub fn below(self) -> Option<Role> {
+        (self as usize).checked_sub(1).map(|i| Role::ALL[i])
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Things only some roles can do, named as in [permissions]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    // Make :shortcodes: even when [shortcodes] doesn't let everyone, and change or remove anyone's
+    ManageShortcodes,
+    // Post in read-only rooms, on top of [moderation] announcers
+    Announce,
+    Kick,
+    Ban,
+    Mute,
+    // See reports, join #server-ops, the full /stats and /auditlog
+    Moderate,
+    Motd,
+    // /topic <text>, for any room
+    Topic,
+    // /pin and /unpin
+    Pin,
+    RoomMode,
+    Chaos,
+    // /promote and /demote, up to one's own role
+    Promote,
+}
+
+impl Permission {
+    // The role it takes unless [permissions] says otherwise
+    pub fn default_role(self) -> Role {
+        match self {
+            Permission::ManageShortcodes
+            | Permission::Announce
+            | Permission::Kick
+            | Permission::Ban
+            | Permission::Mute
+            | Permission::Moderate
+            | Permission::Topic
+            | Permission::Pin => Role::Moderator,
+            Permission::Motd | Permission::RoomMode | Permission::Chaos | Permission::Promote => Role::Owner,
+        }
+    }
+}
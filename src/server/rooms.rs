@@ -0,0 +1,287 @@
+// Chat rooms: each room's broadcast channel, recent history, members and traffic
+// A room exists while someone is in it; its history is reloaded from the store when it comes back.
+// Traffic is what the room fans out to its members, so operators can see which room makes the load.
+// Read-only rooms (announcements) outlive the room itself, the mode is there again when it reopens. So do topics,
+// and whether a room is private (who may join one is up to the store).
+// Permanent rooms (#server-ops) stay open with nobody in them, so their history waits for the next member.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+use crate::protocol::{ChatMessage, RoomInfo};
+
+// Frames a room's channel buffers before slow members start lagging
+const ROOM_CHANNEL_SIZE: usize = 200;
+
+// Longest room name accepted
+pub const MAX_ROOM_NAME_LEN: usize = 32;
+
+// Per-room rates are averaged over this window
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Room {
+    tx: broadcast::Sender<String>,
+    history: VecDeque<ChatMessage>,
+    members: HashSet<u64>,
+    traffic: Traffic,
+}
+
+#[derive(Debug, Default)]
+struct Traffic {
+    // When each frame went out and how many bytes it cost across all members
+    recent: VecDeque<(Instant, usize)>,
+    // Members that fell behind the channel, and the frames they lost
+    lag_events: u64,
+    missed: u64,
+}
+
+impl Traffic {
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+}
+
+// A room's load as shown by the console's rooms command
+#[derive(Debug, Clone)]
+pub struct RoomStats {
+    pub name: String,
+    pub members: usize,
+    pub messages_per_sec: f64,
+    // Fan-out: every frame counts once per member it went to
+    pub bytes_per_sec: f64,
+    pub lag_events: u64,
+    pub missed: u64,
+    // Messages kept in memory for joiners, and roughly what their text weighs
+    pub history: usize,
+    pub history_bytes: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: HashMap<String, Room>,
+    // Rooms only announcers can post in, open or not
+    read_only: HashSet<String>,
+    // What each room is about, open or not
+    topics: HashMap<String, String>,
+    // Rooms only invited accounts (or those with the password) can join, kept out of listings
+    private: HashSet<String>,
+    // Rooms that aren't closed when the last member leaves
+    permanent: HashSet<String>,
+}
+
+impl Rooms {
+    // No room is open yet, these are read-only once they are
+    pub fn new(read_only: impl IntoIterator<Item = String>) -> Self {
+        Rooms {
+            rooms: HashMap::new(),
+            read_only: read_only.into_iter().collect(),
+            topics: HashMap::new(),
+            private: HashSet::new(),
+            permanent: HashSet::new(),
+        }
+    }
+
+    // "#Dev-Chat" -> "dev-chat"; letters, digits, '-' and '_' only
+    pub fn normalize(name: &str) -> Result<String, String> {
+        let name = name.trim().trim_start_matches('#').to_lowercase();
+        let valid = !name.is_empty()
+            && name.chars().count() <= MAX_ROOM_NAME_LEN
+            && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if valid {
+            Ok(name)
+        } else {
+            Err(format!("Room names are 1-{} letters, digits, '-' or '_'", MAX_ROOM_NAME_LEN))
+        }
+    }
+
+    pub fn exists(&self, room: &str) -> bool {
+        self.rooms.contains_key(room)
+    }
+
+    // Brings a room into being with the history it had, if it isn't there already
+    pub fn open(&mut self, room: &str, history: Vec<ChatMessage>) {
+        self.rooms.entry(room.to_string()).or_insert_with(|| Room {
+            tx: broadcast::channel(ROOM_CHANNEL_SIZE).0,
+            history: history.into(),
+            members: HashSet::new(),
+            traffic: Traffic::default(),
+        });
+    }
+
+    // Opens a room that stays open whether anyone is in it or not
+    pub fn open_permanent(&mut self, room: &str) {
+        self.open(room, Vec::new());
+        self.permanent.insert(room.to_string());
+    }
+
+    // Adds a member to an open room and subscribes it to the room's frames
+    pub fn join(&mut self, room: &str, member: u64) -> Option<broadcast::Receiver<String>> {
+        let entry = self.rooms.get_mut(room)?;
+        entry.members.insert(member);
+        Some(entry.tx.subscribe())
+    }
+
+    // Removes a member, closing the room when it was the last one
+    pub fn leave(&mut self, room: &str, member: u64) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            entry.members.remove(&member);
+            if entry.members.is_empty() && !self.permanent.contains(room) {
+                self.rooms.remove(room);
+            }
+        }
+    }
+
+    // Members of an open room, by presence id
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        self.rooms.get(room).map(|entry| entry.members.iter().copied().collect()).unwrap_or_default()
+    }
+
+    // Rooms a member is in, alphabetically
+    pub fn rooms_of(&self, member: u64) -> Vec<String> {
+        let mut rooms: Vec<String> = self.rooms.iter().filter(|(_, entry)| entry.members.contains(&member)).map(|(name, _)| name.clone()).collect();
+        rooms.sort();
+        rooms
+    }
+
+    // The open rooms anyone can join (neither private nor permanent), busiest first
+    pub fn public(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .iter()
+            .filter(|(name, _)| !self.private.contains(*name) && !self.permanent.contains(*name))
+            .map(|(name, entry)| RoomInfo { name: name.clone(), members: entry.members.len(), topic: self.topics.get(name).cloned() })
+            .collect();
+        rooms.sort_by(|a, b| b.members.cmp(&a.members).then_with(|| a.name.cmp(&b.name)));
+        rooms
+    }
+
+    pub fn is_read_only(&self, room: &str) -> bool {
+        self.read_only.contains(room)
+    }
+
+    // Returns false when the room already was that way
+    pub fn set_read_only(&mut self, room: &str, read_only: bool) -> bool {
+        if read_only {
+            self.read_only.insert(room.to_string())
+        } else {
+            self.read_only.remove(room)
+        }
+    }
+
+    pub fn is_private(&self, room: &str) -> bool {
+        self.private.contains(room)
+    }
+
+    pub fn set_private(&mut self, room: &str) {
+        self.private.insert(room.to_string());
+    }
+
+    pub fn set_public(&mut self, room: &str) {
+        self.private.remove(room);
+    }
+
+    pub fn topic(&self, room: &str) -> Option<&str> {
+        self.topics.get(room).map(String::as_str)
+    }
+
+    // None takes the topic away
+    pub fn set_topic(&mut self, room: &str, topic: Option<String>) {
+        match topic {
+            Some(topic) => self.topics.insert(room.to_string(), topic),
+            None => self.topics.remove(room),
+        };
+    }
+
+    // Sends a frame to everyone in the room
+    pub fn send(&mut self, room: &str, frame: String) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            let bytes = frame.len() + 1; // and its newline
+            let receivers = entry.tx.send(frame).unwrap_or(0);
+            let now = Instant::now();
+            entry.traffic.prune(now);
+            entry.traffic.recent.push_back((now, bytes * receivers));
+        }
+    }
+
+    // A member fell so far behind that it lost `missed` frames
+    pub fn record_lag(&mut self, room: &str, missed: u64) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            entry.traffic.lag_events += 1;
+            entry.traffic.missed += missed;
+        }
+    }
+
+    pub fn history(&self, room: &str) -> Vec<ChatMessage> {
+        self.rooms.get(room).map(|entry| entry.history.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    // Keeps the newest `size` messages of a room
+    pub fn push_history(&mut self, room: &str, msg: ChatMessage, size: usize) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            entry.history.push_back(msg);
+            while entry.history.len() > size {
+                entry.history.pop_front();
+            }
+        }
+    }
+
+    // Chat that was already taken in elsewhere (on another process of the cluster): kept in the room's history
+    // and sent to its members. False when the room isn't open here, it's left alone then
+    pub fn post(&mut self, room: &str, msg: ChatMessage, size: usize) -> bool {
+        if !self.exists(room) {
+            return false;
+        }
+        let Ok(frame) = serde_json::to_string(&msg) else { return false };
+        self.push_history(room, msg, size);
+        self.send(room, frame);
+        true
+    }
+
+    // Trims every room's history after the configured size went down
+    pub fn truncate_history(&mut self, size: usize) {
+        for entry in self.rooms.values_mut() {
+            while entry.history.len() > size {
+                entry.history.pop_front();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    // Every open room's load, busiest (by bytes fanned out) first
+    pub fn stats(&mut self) -> Vec<RoomStats> {
+        let now = Instant::now();
+        let window = RATE_WINDOW.as_secs_f64();
+        let mut stats: Vec<RoomStats> = self
+            .rooms
+            .iter_mut()
+            .map(|(name, entry)| {
+                entry.traffic.prune(now);
+                let bytes: usize = entry.traffic.recent.iter().map(|(_, bytes)| bytes).sum();
+                RoomStats {
+                    name: name.clone(),
+                    members: entry.members.len(),
+                    messages_per_sec: entry.traffic.recent.len() as f64 / window,
+                    bytes_per_sec: bytes as f64 / window,
+                    lag_events: entry.traffic.lag_events,
+                    missed: entry.traffic.missed,
+                    history: entry.history.len(),
+                    history_bytes: entry.history.iter().map(|msg| msg.username.len() + msg.content.len()).sum(),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| b.bytes_per_sec.total_cmp(&a.bytes_per_sec).then_with(|| a.name.cmp(&b.name)));
+        stats
+    }
+}
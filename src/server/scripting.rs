@@ -0,0 +1,125 @@
+// Rhai scripts as server-side bots (built with --features scripting)
+// Every *.rhai file in the scripts directory is loaded at startup and again on reload.
+// A script can define on_message(room, user, text), called for every chat message that made it
+// past the other plugins, and call send(room, text) to post as itself (the file name is its username).
+// `this` inside the callbacks is a map that survives between calls, for whatever state the bot keeps
+// (rhai functions can't see the script's top-level variables).
+//
+//   fn on_message(room, user, text) {
+//       if text == "!ping" { send(room, "pong, " + user); }
+//   }
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use tokio::sync::mpsc;
+
+use crate::server::plugins::{ClientInfo, ServerPlugin, Verdict};
+
+// Work a single callback may do before it is stopped, so a runaway loop can't hang the server
+const MAX_OPERATIONS: u64 = 100_000;
+// How deeply expressions may nest, at top level and inside functions (rhai's defaults differ between debug and release)
+const MAX_EXPR_DEPTH: usize = 64;
+
+// Something a script asked to post
+#[derive(Debug, Clone)]
+pub struct ScriptMessage {
+    // The script's file name, which it posts as
+    pub bot: String,
+    pub room: String,
+    pub text: String,
+}
+
+struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    state: Dynamic,
+}
+
+pub struct ScriptPlugin {
+    dir: PathBuf,
+    outbox: mpsc::UnboundedSender<ScriptMessage>,
+    scripts: Mutex<Vec<Script>>,
+}
+
+impl ScriptPlugin {
+    // Loads the scripts in `dir`; what they send comes out of `outbox` for the server to post
+    pub fn new(dir: impl Into<PathBuf>, outbox: mpsc::UnboundedSender<ScriptMessage>) -> Self {
+        let plugin = ScriptPlugin { dir: dir.into(), outbox, scripts: Mutex::new(Vec::new()) };
+        plugin.reload();
+        plugin
+    }
+
+    // Throws away the loaded scripts (and their state) and loads the directory again
+    // A script that doesn't compile is reported and skipped
+    pub fn reload(&self) {
+        let mut scripts = Vec::new();
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+            Err(_) => Vec::new(), // no scripts directory, no bots
+        };
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "rhai"));
+        paths.sort();
+        for path in paths {
+            match self.load(&path) {
+                Ok(script) => scripts.push(script),
+                Err(e) => println!("⚠️  Script {} not loaded: {}", path.display(), e),
+            }
+        }
+        *self.scripts() = scripts;
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.scripts().iter().map(|script| script.name.clone()).collect()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn scripts(&self) -> MutexGuard<'_, Vec<Script>> {
+        self.scripts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn load(&self, path: &Path) -> Result<Script, String> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+        let outbox = self.outbox.clone();
+        let bot = name.clone();
+        engine.register_fn("send", move |room: &str, text: &str| {
+            let _ = outbox.send(ScriptMessage { bot: bot.clone(), room: room.to_string(), text: text.to_string() });
+        });
+
+        let ast = engine.compile_file(path.to_path_buf()).map_err(|e| e.to_string())?;
+        // Top-level statements run once, when the script is loaded
+        engine.run_ast_with_scope(&mut Scope::new(), &ast).map_err(|e| e.to_string())?;
+        Ok(Script { name, engine, ast, state: Dynamic::from_map(Map::new()) })
+    }
+}
+
+impl ServerPlugin for ScriptPlugin {
+    fn name(&self) -> &str {
+        "scripts"
+    }
+
+    // Scripts only watch, they never veto or change a message
+    fn on_message(&self, client: &ClientInfo, text: &mut String) -> Verdict {
+        for script in self.scripts().iter_mut() {
+            if !script.ast.iter_functions().any(|f| f.name == "on_message" && f.params.len() == 3) {
+                continue;
+            }
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+            let args = (client.room.to_string(), client.username.to_string(), text.clone());
+            let result = script.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, "on_message", args);
+            if let Err(e) = result {
+                eprintln!("[WARN] script {}: {}", script.name, e);
+            }
+        }
+        Ok(())
+    }
+}
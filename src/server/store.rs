@@ -0,0 +1,1074 @@
+// Persistent storage for the server, backed by a single SQLite file
+// Tables are created on open, so a fresh path just works.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::protocol::{ChatMessage, MessageType, Reaction, Shortcode};
+use crate::server::accounts::SESSION_TTL_DAYS;
+use crate::server::config::RetentionConfig;
+use crate::server::roles::Role;
+
+// Drafts nobody touched for this long are dropped by maintenance
+pub const DRAFT_TTL_DAYS: i64 = 7;
+
+// What one maintenance pass removed and reclaimed
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceReport {
+    pub deleted_messages: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl MaintenanceReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+// An account's TOTP secret; until it is enabled, setup hasn't been confirmed yet
+#[derive(Debug, Clone)]
+pub struct TwoFactor {
+    pub secret: String,
+    pub enabled: bool,
+    // Newest time step a code was accepted for, older ones can't be replayed
+    pub last_step: u64,
+}
+
+// One line of the moderation log: who did what to whom, why, and when (RFC 3339)
+#[derive(Debug, Clone)]
+pub struct ModerationEntry {
+    pub moderator: String,
+    pub action: String,
+    pub target: String,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    // Opens (or creates) the database file and makes sure every table exists
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let store = Store {
+            conn: Connection::open(path)?,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    // In-memory database, handy for throwaway servers
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        let store = Store {
+            conn: Connection::open_in_memory()?,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS users (
+                username      TEXT PRIMARY KEY COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                created_at    TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS messages (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                room       TEXT NOT NULL,
+                username   TEXT NOT NULL,
+                content    TEXT NOT NULL,
+                timestamp  TEXT NOT NULL,
+                verified   INTEGER NOT NULL DEFAULT 0,
+                action     INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS messages_room ON messages (room, id);
+            CREATE TABLE IF NOT EXISTS reactions (
+                message_id INTEGER NOT NULL,
+                username   TEXT NOT NULL COLLATE NOCASE,
+                emoji      TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (message_id, username, emoji)
+            );
+            CREATE TABLE IF NOT EXISTS bans (
+                ip         TEXT PRIMARY KEY,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS account_bans (
+                username   TEXT PRIMARY KEY COLLATE NOCASE,
+                banned_by  TEXT NOT NULL,
+                reason     TEXT,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS nicknames (
+                account    TEXT NOT NULL COLLATE NOCASE,
+                nickname   TEXT NOT NULL COLLATE NOCASE,
+                first_seen TEXT NOT NULL,
+                last_seen  TEXT NOT NULL,
+                PRIMARY KEY (account, nickname)
+            );
+            CREATE TABLE IF NOT EXISTS two_factor (
+                username   TEXT PRIMARY KEY COLLATE NOCASE,
+                secret     TEXT NOT NULL,
+                enabled    INTEGER NOT NULL DEFAULT 0,
+                last_step  INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS backup_codes (
+                id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                username  TEXT NOT NULL COLLATE NOCASE,
+                code_hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS sessions (
+                token      TEXT PRIMARY KEY,
+                username   TEXT NOT NULL COLLATE NOCASE,
+                created_at TEXT NOT NULL,
+                last_used  TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS read_only_rooms (
+                room       TEXT PRIMARY KEY,
+                changed_by TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS room_topics (
+                room       TEXT PRIMARY KEY,
+                topic      TEXT NOT NULL,
+                set_by     TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS private_rooms (
+                room          TEXT PRIMARY KEY,
+                owner         TEXT NOT NULL COLLATE NOCASE,
+                password_hash TEXT,
+                created_at    TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS room_access (
+                room       TEXT NOT NULL,
+                username   TEXT NOT NULL COLLATE NOCASE,
+                granted_by TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (room, username)
+            );
+            CREATE TABLE IF NOT EXISTS pins (
+                message_id INTEGER PRIMARY KEY,
+                room       TEXT NOT NULL,
+                pinned_by  TEXT NOT NULL,
+                pinned_at  TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS mailbox (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                recipient  TEXT NOT NULL COLLATE NOCASE,
+                sender     TEXT NOT NULL,
+                verified   INTEGER NOT NULL,
+                content    TEXT NOT NULL,
+                timestamp  TEXT NOT NULL,
+                delivered  INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS shortcodes (
+                code       TEXT PRIMARY KEY,
+                text       TEXT NOT NULL,
+                owner      TEXT NOT NULL COLLATE NOCASE,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS drafts (
+                username   TEXT NOT NULL COLLATE NOCASE,
+                room       TEXT NOT NULL,
+                text       TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (username, room)
+            );
+            CREATE TABLE IF NOT EXISTS profiles (
+                username   TEXT PRIMARY KEY COLLATE NOCASE,
+                bio        TEXT,
+                pronouns   TEXT,
+                updated_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS signing_keys (
+                username   TEXT NOT NULL COLLATE NOCASE,
+                key        TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (username, key)
+            );
+            CREATE TABLE IF NOT EXISTS read_marks (
+                username   TEXT NOT NULL COLLATE NOCASE,
+                room       TEXT NOT NULL,
+                message_id INTEGER NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (username, room)
+            );
+            CREATE TABLE IF NOT EXISTS mutes (
+                username   TEXT PRIMARY KEY COLLATE NOCASE,
+                until      TEXT NOT NULL,
+                muted_by   TEXT NOT NULL,
+                reason     TEXT,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS moderation_log (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                moderator  TEXT NOT NULL,
+                action     TEXT NOT NULL,
+                target     TEXT NOT NULL,
+                detail     TEXT,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS roles (
+                username   TEXT PRIMARY KEY COLLATE NOCASE,
+                role       TEXT NOT NULL,
+                granted_by TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS reports (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                room       TEXT NOT NULL,
+                author     TEXT NOT NULL,
+                content    TEXT NOT NULL,
+                reporter   TEXT NOT NULL COLLATE NOCASE,
+                reason     TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                UNIQUE (message_id, reporter)
+            );",
+        )?;
+
+        // Databases from before /me have no action column yet
+        if self.conn.prepare("SELECT action FROM messages LIMIT 0").is_err() {
+            self.conn.execute("ALTER TABLE messages ADD COLUMN action INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+        // ...and those from before quote-replies have no quote column (JSON, NULL when the message quotes nothing)
+        if self.conn.prepare("SELECT quote FROM messages LIMIT 0").is_err() {
+            self.conn.execute("ALTER TABLE messages ADD COLUMN quote TEXT", [])?;
+        }
+        // ...and those from before message signing were never flagged
+        if self.conn.prepare("SELECT bad_signature FROM messages LIMIT 0").is_err() {
+            self.conn.execute("ALTER TABLE messages ADD COLUMN bad_signature INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+        // Accounts from before last-seen tracking don't know when they were last around
+        if self.conn.prepare("SELECT last_seen FROM users LIMIT 0").is_err() {
+            self.conn.execute("ALTER TABLE users ADD COLUMN last_seen TEXT", [])?;
+            self.conn.execute("ALTER TABLE users ADD COLUMN last_presence TEXT", [])?;
+        }
+        Ok(())
+    }
+
+    // --- accounts ---
+
+    // True when the name belongs to a registered account (case-insensitive)
+    pub fn account_exists(&self, username: &str) -> rusqlite::Result<bool> {
+        self.conn
+            .query_row("SELECT 1 FROM users WHERE username = ?1", params![username], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    // Creates an account, returns false when the name is already taken
+    pub fn create_account(&self, username: &str, password_hash: &str) -> rusqlite::Result<bool> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+            params![username, password_hash, Utc::now().to_rfc3339()],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    // Returns the stored (canonical) username and its password hash
+    pub fn account(&self, username: &str) -> rusqlite::Result<Option<(String, String)>> {
+        self.conn
+            .query_row(
+                "SELECT username, password_hash FROM users WHERE username = ?1",
+                params![username],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+
+    // When the account last disconnected and what everyone was told then ("has blasted off", "timed out"),
+    // None when it hasn't since last-seen tracking began
+    pub fn last_seen(&self, username: &str) -> rusqlite::Result<Option<(DateTime<Utc>, String)>> {
+        let seen: Option<(Option<String>, Option<String>)> = self
+            .conn
+            .query_row("SELECT last_seen, last_presence FROM users WHERE username = ?1", params![username], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let Some((Some(when), presence)) = seen else { return Ok(None) };
+        Ok(DateTime::parse_from_rfc3339(&when).ok().map(|when| (when.with_timezone(&Utc), presence.unwrap_or_default())))
+    }
+
+    pub fn set_last_seen(&self, username: &str, presence: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE users SET last_seen = ?2, last_presence = ?3 WHERE username = ?1",
+            params![username, Utc::now().to_rfc3339(), presence],
+        )?;
+        Ok(())
+    }
+
+    // Every registered username, for checking new names against
+    pub fn account_names(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT username FROM users ORDER BY username")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    // Replaces an account's password hash, returns false when there is no such account
+    pub fn set_password_hash(&self, username: &str, password_hash: &str) -> rusqlite::Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE users SET password_hash = ?2 WHERE username = ?1",
+            params![username, password_hash],
+        )?;
+        Ok(updated == 1)
+    }
+
+    // --- nickname history ---
+
+    // Remembers a name an account went by before signing in
+    pub fn record_nickname(&self, account: &str, nickname: &str) -> rusqlite::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO nicknames (account, nickname, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (account, nickname) DO UPDATE SET last_seen = ?3",
+            params![account, nickname, now],
+        )?;
+        Ok(())
+    }
+
+    // Names an account went by, most recent first, with when they were last used
+    pub fn nicknames(&self, account: &str) -> rusqlite::Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT nickname, last_seen FROM nicknames WHERE account = ?1 ORDER BY last_seen DESC")?;
+        let rows = stmt.query_map(params![account], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    // --- two-factor ---
+
+    pub fn two_factor(&self, username: &str) -> rusqlite::Result<Option<TwoFactor>> {
+        self.conn
+            .query_row(
+                "SELECT secret, enabled, last_step FROM two_factor WHERE username = ?1",
+                params![username],
+                |row| Ok(TwoFactor { secret: row.get(0)?, enabled: row.get(1)?, last_step: row.get::<_, i64>(2)? as u64 }),
+            )
+            .optional()
+    }
+
+    // Starts (or restarts) setup with a new secret, sign-in doesn't ask for codes until it is enabled
+    pub fn begin_two_factor(&self, username: &str, secret: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO two_factor (username, secret, enabled, last_step, created_at)
+             VALUES (?1, ?2, 0, 0, ?3)",
+            params![username, secret, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Turns two-factor on with the step of the confirming code, replacing any older backup codes
+    pub fn enable_two_factor(&mut self, username: &str, step: u64, backup_hashes: &[String]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "UPDATE two_factor SET enabled = 1, last_step = ?2 WHERE username = ?1",
+            params![username, step as i64],
+        )?;
+        tx.execute("DELETE FROM backup_codes WHERE username = ?1", params![username])?;
+        for hash in backup_hashes {
+            tx.execute("INSERT INTO backup_codes (username, code_hash) VALUES (?1, ?2)", params![username, hash])?;
+        }
+        tx.commit()
+    }
+
+    // Records a code's time step as used, false when that step (or a later one) already was
+    pub fn use_two_factor_step(&self, username: &str, step: u64) -> rusqlite::Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE two_factor SET last_step = ?2 WHERE username = ?1 AND last_step < ?2",
+            params![username, step as i64],
+        )?;
+        Ok(updated == 1)
+    }
+
+    // Turns two-factor off and forgets the secret and backup codes
+    pub fn disable_two_factor(&self, username: &str) -> rusqlite::Result<bool> {
+        self.conn.execute("DELETE FROM backup_codes WHERE username = ?1", params![username])?;
+        Ok(self.conn.execute("DELETE FROM two_factor WHERE username = ?1", params![username])? > 0)
+    }
+
+    // The unused backup codes of an account (by id), as hashes
+    pub fn backup_codes(&self, username: &str) -> rusqlite::Result<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare("SELECT id, code_hash FROM backup_codes WHERE username = ?1")?;
+        let rows = stmt.query_map(params![username], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    // Uses up a backup code, false when it was already gone
+    pub fn remove_backup_code(&self, id: i64) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM backup_codes WHERE id = ?1", params![id])? > 0)
+    }
+
+    // --- sessions ---
+
+    // Stores a new session token for a signed-in account
+    pub fn create_session(&self, token: &str, username: &str) -> rusqlite::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO sessions (token, username, created_at, last_used) VALUES (?1, ?2, ?3, ?3)",
+            params![token, username, now],
+        )?;
+        Ok(())
+    }
+
+    // Looks up the account behind a token and refreshes its expiry
+    // Expired tokens are deleted and treated as unknown
+    pub fn resume_session(&self, token: &str) -> rusqlite::Result<Option<String>> {
+        let cutoff = (Utc::now() - Duration::days(SESSION_TTL_DAYS)).to_rfc3339();
+        self.conn.execute("DELETE FROM sessions WHERE last_used < ?1", params![cutoff])?;
+
+        let username: Option<String> = self
+            .conn
+            .query_row("SELECT username FROM sessions WHERE token = ?1", params![token], |row| row.get(0))
+            .optional()?;
+        if username.is_some() {
+            self.conn.execute(
+                "UPDATE sessions SET last_used = ?2 WHERE token = ?1",
+                params![token, Utc::now().to_rfc3339()],
+            )?;
+        }
+        Ok(username)
+    }
+
+    // Forgets every session of an account, so none of its tokens resume anymore
+    pub fn delete_sessions(&self, username: &str) -> rusqlite::Result<usize> {
+        self.conn.execute("DELETE FROM sessions WHERE username = ?1", params![username])
+    }
+
+    // --- bans ---
+
+    pub fn bans(&self) -> rusqlite::Result<Vec<IpAddr>> {
+        let mut stmt = self.conn.prepare("SELECT ip FROM bans")?;
+        let ips = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        // Skip anything that no longer parses rather than refusing to start
+        Ok(ips.filter_map(|ip| ip.ok()?.parse().ok()).collect())
+    }
+
+    pub fn add_ban(&self, ip: IpAddr) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO bans (ip, created_at) VALUES (?1, ?2)",
+            params![ip.to_string(), Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Returns false when the address wasn't banned
+    pub fn remove_ban(&self, ip: IpAddr) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM bans WHERE ip = ?1", params![ip.to_string()])? > 0)
+    }
+
+    // Accounts that can't sign in any more, whatever address they come from
+    pub fn account_bans(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT username FROM account_bans")?;
+        let usernames = stmt.query_map([], |row| row.get(0))?;
+        usernames.collect()
+    }
+
+    pub fn add_account_ban(&self, username: &str, banned_by: &str, reason: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO account_bans (username, banned_by, reason, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![username, banned_by, reason, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Returns false when the account wasn't banned
+    pub fn remove_account_ban(&self, username: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM account_bans WHERE username = ?1", params![username])? > 0)
+    }
+
+    // --- room modes ---
+
+    pub fn read_only_rooms(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT room FROM read_only_rooms")?;
+        let rooms = stmt.query_map([], |row| row.get(0))?;
+        rooms.collect()
+    }
+
+    pub fn set_room_read_only(&self, room: &str, read_only: bool, changed_by: &str) -> rusqlite::Result<()> {
+        if read_only {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO read_only_rooms (room, changed_by, created_at) VALUES (?1, ?2, ?3)",
+                params![room, changed_by, Utc::now().to_rfc3339()],
+            )?;
+        } else {
+            self.conn.execute("DELETE FROM read_only_rooms WHERE room = ?1", params![room])?;
+        }
+        Ok(())
+    }
+
+    // Every room's topic, by room
+    pub fn room_topics(&self) -> rusqlite::Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT room, topic FROM room_topics")?;
+        let topics = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        topics.collect()
+    }
+
+    // None takes the room's topic away
+    pub fn set_room_topic(&self, room: &str, topic: Option<&str>, set_by: &str) -> rusqlite::Result<()> {
+        match topic {
+            Some(topic) => self.conn.execute(
+                "INSERT OR REPLACE INTO room_topics (room, topic, set_by, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![room, topic, set_by, Utc::now().to_rfc3339()],
+            )?,
+            None => self.conn.execute("DELETE FROM room_topics WHERE room = ?1", params![room])?,
+        };
+        Ok(())
+    }
+
+    // --- private rooms ---
+
+    pub fn private_rooms(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT room FROM private_rooms")?;
+        let rooms = stmt.query_map([], |row| row.get(0))?;
+        rooms.collect()
+    }
+
+    // Makes `room` private with its creator let in, false when it already was
+    pub fn create_private_room(&self, room: &str, owner: &str, password_hash: Option<&str>) -> rusqlite::Result<bool> {
+        let now = Utc::now().to_rfc3339();
+        let created = self.conn.execute(
+            "INSERT OR IGNORE INTO private_rooms (room, owner, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![room, owner, password_hash, now],
+        )? > 0;
+        if created {
+            self.grant_room_access(room, owner, owner)?;
+        }
+        Ok(created)
+    }
+
+    // Whether a room was used before: any history, a topic or a mode kept for it
+    // Such a room belongs to its regulars, nobody gets to make it private and shut them out
+    pub fn room_in_use(&self, room: &str) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE room = ?1)
+                 OR EXISTS (SELECT 1 FROM room_topics WHERE room = ?1)
+                 OR EXISTS (SELECT 1 FROM read_only_rooms WHERE room = ?1)",
+            params![room],
+            |row| row.get(0),
+        )
+    }
+
+    // Opens a private room to everyone again and forgets who was let in, false when it wasn't private
+    pub fn make_room_public(&self, room: &str) -> rusqlite::Result<bool> {
+        let removed = self.conn.execute("DELETE FROM private_rooms WHERE room = ?1", params![room])? > 0;
+        self.conn.execute("DELETE FROM room_access WHERE room = ?1", params![room])?;
+        Ok(removed)
+    }
+
+    // The password hash of a private room, Some(None) when it only takes invites
+    pub fn room_password(&self, room: &str) -> rusqlite::Result<Option<Option<String>>> {
+        self.conn.query_row("SELECT password_hash FROM private_rooms WHERE room = ?1", params![room], |row| row.get(0)).optional()
+    }
+
+    // Lets an account into a private room from now on, false when it already could
+    pub fn grant_room_access(&self, room: &str, username: &str, granted_by: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute(
+            "INSERT OR IGNORE INTO room_access (room, username, granted_by, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![room, username, granted_by, Utc::now().to_rfc3339()],
+        )? > 0)
+    }
+
+    pub fn has_room_access(&self, room: &str, username: &str) -> rusqlite::Result<bool> {
+        self.conn
+            .query_row("SELECT 1 FROM room_access WHERE room = ?1 AND username = ?2", params![room, username], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    // --- mutes ---
+
+    // Mutes that haven't run out yet, with when they do
+    pub fn mutes(&self) -> rusqlite::Result<Vec<(String, DateTime<Utc>)>> {
+        let mut stmt = self.conn.prepare("SELECT username, until FROM mutes WHERE until > ?1")?;
+        let mutes = stmt.query_map(params![Utc::now().to_rfc3339()], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        let mutes: Vec<(String, String)> = mutes.collect::<rusqlite::Result<_>>()?;
+        Ok(mutes
+            .into_iter()
+            .filter_map(|(username, until)| Some((username, DateTime::parse_from_rfc3339(&until).ok()?.with_timezone(&Utc))))
+            .collect())
+    }
+
+    // Mutes someone until `until`, replacing a mute they had already
+    pub fn add_mute(&self, username: &str, until: DateTime<Utc>, muted_by: &str, reason: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO mutes (username, until, muted_by, reason, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![username, until.to_rfc3339(), muted_by, reason, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Returns false when they weren't muted
+    pub fn remove_mute(&self, username: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM mutes WHERE username = ?1", params![username])? > 0)
+    }
+
+    // --- roles ---
+
+    // The role stored for an account, members aren't stored
+    pub fn role(&self, username: &str) -> rusqlite::Result<Role> {
+        let role: Option<String> = self
+            .conn
+            .query_row("SELECT role FROM roles WHERE username = ?1", params![username], |row| row.get(0))
+            .optional()?;
+        Ok(role.and_then(|role| Role::parse(&role)).unwrap_or_default())
+    }
+
+    pub fn set_role(&self, username: &str, role: Role, granted_by: &str) -> rusqlite::Result<()> {
+        if role == Role::Member {
+            self.conn.execute("DELETE FROM roles WHERE username = ?1", params![username])?;
+        } else {
+            self.conn.execute(
+                "INSERT INTO roles (username, role, granted_by, updated_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(username) DO UPDATE SET role = excluded.role, granted_by = excluded.granted_by, updated_at = excluded.updated_at",
+                params![username, role.name(), granted_by, Utc::now().to_rfc3339()],
+            )?;
+        }
+        Ok(())
+    }
+
+    // --- moderation log ---
+
+    // Writes down what a moderator did to whom ("mute", "bob", "10m: spamming links")
+    pub fn log_moderation(&self, moderator: &str, action: &str, target: &str, detail: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO moderation_log (moderator, action, target, detail, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![moderator, action, target, detail, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // The newest `limit` entries (only the ones about `target` when given), oldest first
+    pub fn moderation_log(&self, target: Option<&str>, limit: usize) -> rusqlite::Result<Vec<ModerationEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT moderator, action, target, detail, created_at FROM moderation_log
+             WHERE ?1 IS NULL OR target = ?1 COLLATE NOCASE ORDER BY id DESC LIMIT ?2",
+        )?;
+        let entries = stmt.query_map(params![target, limit as i64], |row| {
+            Ok(ModerationEntry {
+                moderator: row.get(0)?,
+                action: row.get(1)?,
+                target: row.get(2)?,
+                detail: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        let mut entries = entries.collect::<rusqlite::Result<Vec<_>>>()?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    // --- reports ---
+
+    // Files a report, keeping a copy of the message in case it's pruned or edited away
+    // Returns the report's id, None when the reporter already reported this message
+    pub fn add_report(&self, msg: &ChatMessage, reporter: &str, reason: &str) -> rusqlite::Result<Option<i64>> {
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO reports (message_id, room, author, content, reporter, reason, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![msg.id, msg.room, msg.username, msg.content, reporter, reason, Utc::now().to_rfc3339()],
+        )?;
+        Ok((added > 0).then(|| self.conn.last_insert_rowid()))
+    }
+
+    // --- shortcodes ---
+
+    // Every shortcode users made, alphabetically
+    pub fn shortcodes(&self) -> rusqlite::Result<Vec<Shortcode>> {
+        let mut stmt = self.conn.prepare("SELECT code, text FROM shortcodes ORDER BY code")?;
+        let codes = stmt.query_map([], |row| Ok(Shortcode { code: row.get(0)?, text: row.get(1)? }))?;
+        codes.collect()
+    }
+
+    // Account that made a shortcode, None when there's no such shortcode
+    pub fn shortcode_owner(&self, code: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row("SELECT owner FROM shortcodes WHERE code = ?1", params![code], |row| row.get(0))
+            .optional()
+    }
+
+    // How many shortcodes there are in all, and how many of them `owner` made
+    pub fn count_shortcodes(&self, owner: &str) -> rusqlite::Result<(usize, usize)> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COUNT(CASE WHEN owner = ?1 THEN 1 END) FROM shortcodes",
+            params![owner],
+            |row| Ok((row.get::<_, i64>(0)? as usize, row.get::<_, i64>(1)? as usize)),
+        )
+    }
+
+    // Adds a shortcode, or changes what an existing one stands for
+    pub fn save_shortcode(&self, code: &str, text: &str, owner: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO shortcodes (code, text, owner, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![code, text, owner, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Returns false when there was no such shortcode
+    pub fn remove_shortcode(&self, code: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM shortcodes WHERE code = ?1", params![code])? > 0)
+    }
+
+    // --- mailbox ---
+
+    // Keeps a whisper for an account that is offline
+    pub fn add_to_mailbox(&self, recipient: &str, whisper: &ChatMessage) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO mailbox (recipient, sender, verified, content, timestamp, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![recipient, whisper.username, whisper.verified, whisper.content, whisper.timestamp, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn mailbox_len(&self, recipient: &str) -> rusqlite::Result<usize> {
+        self.conn.query_row("SELECT COUNT(*) FROM mailbox WHERE recipient = ?1", params![recipient], |row| row.get::<_, i64>(0)).map(|n| n as usize)
+    }
+
+    // Everything in an account's mailbox, oldest first; with `undelivered_only` just what it hasn't been given yet,
+    // which counts as given from now on
+    pub fn mailbox(&self, recipient: &str, undelivered_only: bool) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sender, verified, content, timestamp FROM mailbox
+             WHERE recipient = ?1 AND (delivered = 0 OR ?2 = 0) ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![recipient, undelivered_only], |row| {
+            Ok(ChatMessage {
+                username: row.get(0)?,
+                verified: row.get(1)?,
+                content: row.get(2)?,
+                timestamp: row.get(3)?,
+                message_type: MessageType::Whisper,
+                recipient: Some(recipient.to_string()),
+                ..Default::default()
+            })
+        })?;
+        let mail = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        if undelivered_only {
+            self.conn.execute("UPDATE mailbox SET delivered = 1 WHERE recipient = ?1", params![recipient])?;
+        }
+        Ok(mail)
+    }
+
+    // Returns how many messages were thrown away
+    pub fn clear_mailbox(&self, recipient: &str) -> rusqlite::Result<usize> {
+        self.conn.execute("DELETE FROM mailbox WHERE recipient = ?1", params![recipient])
+    }
+
+    // --- drafts ---
+
+    // What an account was in the middle of typing, by room
+    pub fn drafts(&self, username: &str) -> rusqlite::Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT room, text FROM drafts WHERE username = ?1 ORDER BY room")?;
+        let drafts = stmt.query_map(params![username], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        drafts.collect()
+    }
+
+    // Keeps the newest draft for a room, an empty one is simply forgotten
+    pub fn save_draft(&self, username: &str, room: &str, text: &str) -> rusqlite::Result<()> {
+        if text.is_empty() {
+            self.conn.execute("DELETE FROM drafts WHERE username = ?1 AND room = ?2", params![username, room])?;
+        } else {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO drafts (username, room, text, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![username, room, text, Utc::now().to_rfc3339()],
+            )?;
+        }
+        Ok(())
+    }
+
+    // --- profiles ---
+
+    // An account's bio and pronouns, None for the ones it never set
+    pub fn profile(&self, username: &str) -> rusqlite::Result<(Option<String>, Option<String>)> {
+        let profile = self
+            .conn
+            .query_row("SELECT bio, pronouns FROM profiles WHERE username = ?1", params![username], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        Ok(profile.unwrap_or_default())
+    }
+
+    // None takes the bio away
+    pub fn set_bio(&self, username: &str, bio: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO profiles (username, bio, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username) DO UPDATE SET bio = ?2, updated_at = ?3",
+            params![username, bio, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_pronouns(&self, username: &str, pronouns: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO profiles (username, pronouns, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username) DO UPDATE SET pronouns = ?2, updated_at = ?3",
+            params![username, pronouns, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // --- read marks ---
+
+    // Newest message of `room` the account has read, on any of its devices
+    pub fn read_mark(&self, username: &str, room: &str) -> rusqlite::Result<Option<i64>> {
+        self.conn
+            .query_row("SELECT message_id FROM read_marks WHERE username = ?1 AND room = ?2", params![username, room], |row| row.get(0))
+            .optional()
+    }
+
+    // Moves the mark up to message `id`, never back (a device that was behind doesn't undo what another read)
+    pub fn mark_read(&self, username: &str, room: &str, id: i64) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO read_marks (username, room, message_id, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (username, room) DO UPDATE SET message_id = MAX(message_id, ?3), updated_at = ?4",
+            params![username, room, id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // --- signing keys ---
+
+    // Keys messages under `username` are signed with, oldest first (empty when nobody signed under it yet)
+    pub fn signing_keys(&self, username: &str) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT key FROM signing_keys WHERE username = ?1 ORDER BY created_at")?;
+        let keys = stmt.query_map(params![username], |row| row.get(0))?;
+        keys.collect()
+    }
+
+    // Returns false when the key already was on record
+    pub fn add_signing_key(&self, username: &str, key: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute(
+            "INSERT OR IGNORE INTO signing_keys (username, key, created_at) VALUES (?1, ?2, ?3)",
+            params![username, key, Utc::now().to_rfc3339()],
+        )? > 0)
+    }
+
+    // --- message history ---
+
+    // Appends a chat message to a room's stored history and returns its id
+    pub fn save_message(&self, room: &str, msg: &ChatMessage) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO messages (room, username, content, timestamp, verified, action, quote, bad_signature, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                room,
+                msg.username,
+                msg.content,
+                msg.timestamp,
+                msg.verified,
+                msg.message_type == MessageType::Action,
+                msg.quote.as_ref().and_then(|quote| serde_json::to_string(quote).ok()),
+                msg.bad_signature,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    // The newest `limit` messages of a room, oldest first
+    pub fn recent_messages(&self, room: &str, limit: usize) -> rusqlite::Result<Vec<ChatMessage>> {
+        self.query_messages(
+            "SELECT id, username, content, timestamp, verified, action, quote, bad_signature FROM
+                (SELECT * FROM messages WHERE room = ?1 ORDER BY id DESC LIMIT ?2)
+             ORDER BY id ASC",
+            params![room, limit as i64],
+        )
+    }
+
+    // Up to `limit` messages of a room that came after `after_id`, oldest first
+    pub fn messages_since(&self, room: &str, after_id: i64, limit: usize) -> rusqlite::Result<Vec<ChatMessage>> {
+        self.query_messages(
+            "SELECT id, username, content, timestamp, verified, action, quote, bad_signature FROM messages
+             WHERE room = ?1 AND id > ?2 ORDER BY id ASC LIMIT ?3",
+            params![room, after_id, limit as i64],
+        )
+    }
+
+    fn query_messages(&self, sql: &str, params: impl rusqlite::Params) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok(ChatMessage {
+                id: Some(row.get(0)?),
+                username: row.get(1)?,
+                content: row.get(2)?,
+                timestamp: row.get(3)?,
+                verified: row.get(4)?,
+                message_type: if row.get(5)? { MessageType::Action } else { MessageType::UserMessage },
+                quote: row.get::<_, Option<String>>(6)?.and_then(|quote| serde_json::from_str(&quote).ok()),
+                bad_signature: row.get(7)?,
+                ..Default::default()
+            })
+        })?;
+        rows.collect()
+    }
+
+    // Room a stored message was posted in
+    pub fn message_room(&self, message_id: i64) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row("SELECT room FROM messages WHERE id = ?1", params![message_id], |row| row.get(0))
+            .optional()
+    }
+
+    // A stored message along with its room, None when there's no such message (or it was pruned)
+    pub fn message(&self, message_id: i64) -> rusqlite::Result<Option<ChatMessage>> {
+        let Some(room) = self.message_room(message_id)? else { return Ok(None) };
+        let found = self.query_messages(
+            "SELECT id, username, content, timestamp, verified, action, quote, bad_signature FROM messages WHERE id = ?1",
+            params![message_id],
+        )?;
+        Ok(found.into_iter().next().map(|msg| msg.in_room(room.as_str())))
+    }
+
+    // --- pins ---
+
+    // The pinned messages of a room, oldest pin first (pins of pruned messages are gone with them)
+    pub fn pins(&self, room: &str) -> rusqlite::Result<Vec<ChatMessage>> {
+        self.query_messages(
+            "SELECT m.id, m.username, m.content, m.timestamp, m.verified, m.action, m.quote, m.bad_signature
+             FROM pins p JOIN messages m ON m.id = p.message_id
+             WHERE p.room = ?1 ORDER BY p.pinned_at, p.message_id",
+            params![room],
+        )
+    }
+
+    // Returns false when the message already was pinned
+    pub fn pin(&self, room: &str, message_id: i64, pinned_by: &str) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute(
+            "INSERT OR IGNORE INTO pins (message_id, room, pinned_by, pinned_at) VALUES (?1, ?2, ?3, ?4)",
+            params![message_id, room, pinned_by, Utc::now().to_rfc3339()],
+        )? > 0)
+    }
+
+    // Returns false when the message wasn't pinned
+    pub fn unpin(&self, message_id: i64) -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("DELETE FROM pins WHERE message_id = ?1", params![message_id])? > 0)
+    }
+
+    // --- reactions ---
+
+    // Adds the reaction, or takes it back if the user had already reacted with that emoji
+    // Returns whether it was added and the message's reactions afterwards,
+    // None when the room has no such message
+    pub fn toggle_reaction(
+        &self,
+        room: &str,
+        message_id: i64,
+        username: &str,
+        emoji: &str,
+    ) -> rusqlite::Result<Option<(bool, Vec<Reaction>)>> {
+        let exists = self
+            .conn
+            .query_row("SELECT 1 FROM messages WHERE id = ?1 AND room = ?2", params![message_id, room], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+        let removed = self.conn.execute(
+            "DELETE FROM reactions WHERE message_id = ?1 AND username = ?2 AND emoji = ?3",
+            params![message_id, username, emoji],
+        )? > 0;
+        if !removed {
+            self.conn.execute(
+                "INSERT INTO reactions (message_id, username, emoji, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![message_id, username, emoji, Utc::now().to_rfc3339()],
+            )?;
+        }
+        let reactions = self.reactions_for(&[message_id])?.remove(&message_id).unwrap_or_default();
+        Ok(Some((!removed, reactions)))
+    }
+
+    // Reaction totals per message, each message's emojis in the order they were first used
+    pub fn reactions_for(&self, message_ids: &[i64]) -> rusqlite::Result<HashMap<i64, Vec<Reaction>>> {
+        let mut totals: HashMap<i64, Vec<Reaction>> = HashMap::new();
+        if message_ids.is_empty() {
+            return Ok(totals);
+        }
+        let placeholders = vec!["?"; message_ids.len()].join(", ");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT message_id, emoji, COUNT(*) FROM reactions WHERE message_id IN ({})
+             GROUP BY message_id, emoji ORDER BY message_id, MIN(created_at), emoji",
+            placeholders
+        ))?;
+        let rows = stmt.query_map(params_from_iter(message_ids), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        for row in rows {
+            let (message_id, emoji, count) = row?;
+            totals.entry(message_id).or_default().push(Reaction { emoji, count: count as usize });
+        }
+        Ok(totals)
+    }
+
+    // Fills in the stored reactions of messages about to be replayed
+    pub fn enrich(&self, messages: &mut [ChatMessage]) -> rusqlite::Result<()> {
+        let ids: Vec<i64> = messages.iter().filter_map(|msg| msg.id).collect();
+        let mut totals = self.reactions_for(&ids)?;
+        for msg in messages.iter_mut() {
+            if let Some(reactions) = msg.id.and_then(|id| totals.remove(&id)) {
+                msg.reactions = reactions;
+            }
+        }
+        Ok(())
+    }
+
+    // --- maintenance ---
+
+    // Applies the retention policy to every room, then VACUUMs the file
+    pub fn run_maintenance(&self, retention: &RetentionConfig) -> rusqlite::Result<MaintenanceReport> {
+        let mut report = MaintenanceReport {
+            bytes_before: self.size_bytes()?,
+            ..Default::default()
+        };
+
+        let rooms: Vec<String> = self
+            .conn
+            .prepare("SELECT DISTINCT room FROM messages")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for room in rooms {
+            let policy = retention.for_room(&room);
+            if let Some(days) = policy.max_age_days {
+                let cutoff = (Utc::now() - Duration::days(days as i64)).to_rfc3339();
+                report.deleted_messages += self.conn.execute(
+                    "DELETE FROM messages WHERE room = ?1 AND created_at < ?2",
+                    params![room, cutoff],
+                )?;
+            }
+            if let Some(max) = policy.max_messages {
+                report.deleted_messages += self.conn.execute(
+                    "DELETE FROM messages WHERE room = ?1 AND id NOT IN
+                        (SELECT id FROM messages WHERE room = ?1 ORDER BY id DESC LIMIT ?2)",
+                    params![room, max as i64],
+                )?;
+            }
+        }
+
+        // Reactions go with the messages they were on
+        self.conn.execute("DELETE FROM reactions WHERE message_id NOT IN (SELECT id FROM messages)", [])?;
+        let stale = (Utc::now() - Duration::days(DRAFT_TTL_DAYS)).to_rfc3339();
+        self.conn.execute("DELETE FROM drafts WHERE updated_at < ?1", params![stale])?;
+        self.conn.execute("DELETE FROM mutes WHERE until < ?1", params![Utc::now().to_rfc3339()])?;
+
+        self.conn.execute_batch("VACUUM")?;
+        report.bytes_after = self.size_bytes()?;
+        Ok(report)
+    }
+
+    // Size of the database in bytes (pages in use times page size)
+    fn size_bytes(&self) -> rusqlite::Result<u64> {
+        let pages: i64 = self.conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = self.conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok((pages * page_size) as u64)
+    }
+}
@@ -0,0 +1,113 @@
+// Time-based one-time passwords (RFC 6238) for two-factor sign-in
+// Same parameters every authenticator app defaults to: HMAC-SHA1, 30 second steps, 6 digits.
+// Secrets are shared with the app base32 encoded, inside an otpauth:// URI it can scan as a QR code.
+
+use hmac::{Hmac, KeyInit, Mac};
+use rand::distr::Alphanumeric;
+use rand::Rng;
+use sha1::Sha1;
+
+pub const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+// Steps either side of now still accepted, for clocks that drift a little
+const ALLOWED_DRIFT: u64 = 1;
+const SECRET_BYTES: usize = 20;
+
+// One-time codes that stand in for the authenticator, handed out when 2FA is switched on
+pub const BACKUP_CODES: usize = 8;
+const BACKUP_CODE_LEN: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Fresh random secret, base32 encoded (as stored and as shown to the user)
+pub fn new_secret() -> String {
+    let bytes: [u8; SECRET_BYTES] = rand::rng().random();
+    base32_encode(&bytes)
+}
+
+// "abcde-fghij", lower case so it can't be mistaken for a TOTP code
+pub fn new_backup_code() -> String {
+    let code: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(BACKUP_CODE_LEN)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    format!("{}-{}", &code[..BACKUP_CODE_LEN / 2], &code[BACKUP_CODE_LEN / 2..])
+}
+
+// What the authenticator app scans
+pub fn provisioning_uri(secret: &str, account: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = uri_escape(issuer),
+        account = uri_escape(account),
+    )
+}
+
+// Checks a code against the steps around `unix_time` and returns the step it matched,
+// which callers remember so the same code can't be used twice
+pub fn verify(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    if !looks_like_code(code) {
+        return None;
+    }
+    let key = base32_decode(secret)?;
+    let now = unix_time / STEP_SECONDS;
+    (now.saturating_sub(ALLOWED_DRIFT)..=now + ALLOWED_DRIFT).find(|&step| code_at(&key, step) == code)
+}
+
+// True for text shaped like a TOTP code, as opposed to a backup code
+pub fn looks_like_code(text: &str) -> bool {
+    text.len() == DIGITS && text.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn code_at(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation: the low nibble of the last byte picks 4 bytes to read
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    format!("{:0width$}", value % 10u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+// Just enough escaping for account and issuer names in the URI
+fn uri_escape(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
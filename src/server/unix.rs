@@ -0,0 +1,88 @@
+// Unix domain socket listener (--unix-socket), for deployments where only programs on the same machine
+// (a local bouncer, a bot, a reverse proxy) should reach the server
+// Clients connecting this way have no IP address, they count as 127.0.0.1 for bans and per-address limits.
+// On other systems there is nothing to listen on, asking for it is an error and accept() never returns.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+
+// Where Unix socket clients appear to come from
+pub const LOCAL_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+#[cfg(unix)]
+pub use tokio::net::UnixStream as LocalStream;
+// Never made, only here so the accept loop reads the same everywhere
+#[cfg(not(unix))]
+pub use tokio::io::DuplexStream as LocalStream;
+
+#[derive(Debug, Default)]
+pub struct LocalListener {
+    #[cfg(unix)]
+    listener: Option<(tokio::net::UnixListener, std::path::PathBuf)>,
+}
+
+impl LocalListener {
+    // Listens at `path`, or doesn't listen at all when there is none
+    // A socket file left behind by a server that is gone is replaced, one still answering is an error
+    #[cfg(unix)]
+    pub fn bind(path: Option<&str>) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::path::PathBuf;
+
+        let Some(path) = path else { return Ok(LocalListener::default()) };
+        let path = PathBuf::from(path);
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+            }
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("another server is listening at {}", path.display())));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        Ok(LocalListener { listener: Some((listener, path)) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(path: Option<&str>) -> io::Result<Self> {
+        match path {
+            Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are only there on Unix systems")),
+            None => Ok(LocalListener::default()),
+        }
+    }
+
+    // The socket file we listen at, if any
+    #[cfg(unix)]
+    pub fn path(&self) -> Option<&std::path::Path> {
+        self.listener.as_ref().map(|(_, path)| path.as_path())
+    }
+
+    #[cfg(not(unix))]
+    pub fn path(&self) -> Option<&std::path::Path> {
+        None
+    }
+
+    // The next client, waits forever when we aren't listening
+    #[cfg(unix)]
+    pub async fn accept(&self) -> io::Result<LocalStream> {
+        match &self.listener {
+            Some((listener, _)) => listener.accept().await.map(|(stream, _)| stream),
+            None => std::future::pending().await,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub async fn accept(&self) -> io::Result<LocalStream> {
+        std::future::pending().await
+    }
+}
+
+// Takes the socket file away with the listener, so nobody tries to connect to a server that is gone
+impl Drop for LocalListener {
+    fn drop(&mut self) {
+        if let Some(path) = self.path() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
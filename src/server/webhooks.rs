@@ -0,0 +1,344 @@
+// Slack/Discord style webhooks, to bridge the chat with existing team channels
+// Outbound: the plugin hands every chat message in a relayed room to a background task that POSTs it to the
+// configured webhooks, one after the other so they arrive in order. A hook that is slow or down holds up only
+// the relay, and what doesn't fit in the queue is dropped with a warning.
+// Inbound: a small HTTP endpoint takes POST /hooks/<room> with a Slack ({"text"}) or Discord ({"content"}) body
+// and the token, and hands the post to the server, which puts it in the room as "<name>@webhook".
+// Posts that came in by webhook aren't relayed out again, so two bridged channels can't echo each other forever.
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+use super::config::{OutboundWebhook, WebhookFormat};
+use super::plugins::ServerPlugin;
+use super::same_token;
+use crate::protocol::{normalize_content, ChatMessage, MessageType};
+
+// Deliveries waiting to go out, across all hooks
+const QUEUE: usize = 256;
+
+// Time a hook gets to answer, and an inbound client to send its request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Biggest inbound body we read
+const MAX_BODY: usize = 64 * 1024;
+
+// Discord refuses longer messages
+const MAX_DISCORD_LEN: usize = 2000;
+
+// Longest name an inbound post can give itself
+const MAX_NAME_LEN: usize = 32;
+
+struct Delivery {
+    url: String,
+    body: String,
+}
+
+pub struct WebhookPlugin {
+    hooks: Mutex<Vec<OutboundWebhook>>,
+    deliveries: mpsc::Sender<Delivery>,
+}
+
+impl WebhookPlugin {
+    // Starts the task that delivers to the hooks (so it needs a runtime)
+    pub fn new(hooks: Vec<OutboundWebhook>) -> Self {
+        let (deliveries, mut queue) = mpsc::channel::<Delivery>(QUEUE);
+        tokio::spawn(async move {
+            while let Some(delivery) = queue.recv().await {
+                if let Err(e) = post(&delivery.url, &delivery.body).await {
+                    eprintln!("[WARN] webhook {} failed: {}", redact(&delivery.url), e);
+                }
+            }
+        });
+        WebhookPlugin { hooks: Mutex::new(hooks), deliveries }
+    }
+
+    // Swaps the hooks for the ones from a reloaded config
+    pub fn set_hooks(&self, hooks: Vec<OutboundWebhook>) {
+        *self.hooks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = hooks;
+    }
+}
+
+impl ServerPlugin for WebhookPlugin {
+    fn name(&self) -> &str {
+        "webhooks"
+    }
+
+    fn on_broadcast(&self, msg: &ChatMessage) {
+        let Some(room) = msg.room.as_deref() else { return };
+        if !matches!(msg.message_type, MessageType::UserMessage | MessageType::Action) {
+            return;
+        }
+        let hooks = self.hooks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for hook in hooks.iter().filter(|hook| hook.relays(room)) {
+            let delivery = Delivery { url: hook.url.clone(), body: payload(hook.format, msg).to_string() };
+            if self.deliveries.try_send(delivery).is_err() {
+                eprintln!("[WARN] webhook queue is full, a message from #{} wasn't relayed to {}", room, redact(&hook.url));
+            }
+        }
+    }
+}
+
+// The JSON body for a chat message, as Slack or Discord take it
+pub fn payload(format: WebhookFormat, msg: &ChatMessage) -> serde_json::Value {
+    let username = format!("{} (#{})", msg.username, msg.room.as_deref().unwrap_or_default());
+    let action = msg.message_type == MessageType::Action;
+    match format {
+        // Slack wants &, < and > escaped, and italics in underscores
+        WebhookFormat::Slack => {
+            let text = msg.content.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+            let text = if action { format!("_{}_", text) } else { text };
+            json!({ "username": username, "text": text })
+        }
+        // No pinging @everyone from the chat
+        WebhookFormat::Discord => {
+            let text = if action { format!("*{}*", msg.content) } else { msg.content.clone() };
+            let mut content: String = text.chars().take(MAX_DISCORD_LEN - 1).collect();
+            if content.len() < text.len() {
+                content.push('…');
+            }
+            json!({ "username": username, "content": content, "allowed_mentions": { "parse": [] } })
+        }
+    }
+}
+
+// What an inbound body says: who posted it (when it says) and the text, Slack's "text" or Discord's "content"
+pub fn parse_payload(body: &[u8]) -> Result<(Option<String>, String), String> {
+    #[derive(Deserialize)]
+    struct Body {
+        username: Option<String>,
+        text: Option<String>,
+        content: Option<String>,
+    }
+    let body: Body = serde_json::from_slice(body).map_err(|e| format!("not a JSON webhook body: {}", e))?;
+    let text = body.text.or(body.content).ok_or("the body has no \"text\" or \"content\"")?;
+    let text = normalize_content(&text)?;
+    if text.trim().is_empty() {
+        return Err("the message is empty".to_string());
+    }
+    Ok((body.username, text))
+}
+
+// The name an inbound post shows under: what it called itself (letters, digits, - and _ only) and where it came from
+pub fn poster_name(username: Option<&str>) -> String {
+    let name: String = username
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_NAME_LEN)
+        .collect();
+    match name.is_empty() {
+        true => "webhook".to_string(),
+        false => format!("{}@webhook", name),
+    }
+}
+
+// --- outbound HTTP ---
+
+// An http:// or https:// URL taken apart
+struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    // "host:port" as written, for the Host header
+    authority: String,
+    // Path and query, "/" at least
+    target: String,
+}
+
+fn parse_url(url: &str) -> Result<Url, String> {
+    let (https, rest) = match url.split_once("://") {
+        Some(("https", rest)) => (true, rest),
+        Some(("http", rest)) => (false, rest),
+        _ => return Err("only http:// and https:// URLs work".to_string()),
+    };
+    let (authority, target) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], rest[slash..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let default_port = if https { 443 } else { 80 };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().map_err(|_| format!("bad port in {}", authority))?),
+        _ => (authority, default_port),
+    };
+    if host.is_empty() {
+        return Err("the URL has no host".to_string());
+    }
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+    Ok(Url { https, host, port, authority: authority.to_string(), target })
+}
+
+// POSTs a JSON body, anything but a 2xx answer is an error
+pub async fn post(url: &str, body: &str) -> Result<(), String> {
+    let url = parse_url(url)?;
+    let connect = TcpStream::connect((url.host.as_str(), url.port));
+    let stream = tokio::time::timeout(TIMEOUT, connect).await.map_err(|_| "timed out connecting".to_string())?.map_err(|e| e.to_string())?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: retro-chat\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.target,
+        url.authority,
+        body.len(),
+        body
+    );
+    let exchange = async {
+        if url.https {
+            let connector = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+            let stream = tokio_native_tls::TlsConnector::from(connector).connect(&url.host, stream).await.map_err(|e| e.to_string())?;
+            send_request(stream, &request).await
+        } else {
+            send_request(stream, &request).await
+        }
+    };
+    let status = tokio::time::timeout(TIMEOUT, exchange).await.map_err(|_| "timed out waiting for an answer".to_string())??;
+    match status {
+        200..=299 => Ok(()),
+        status => Err(format!("answered {}", status)),
+    }
+}
+
+// Writes the request and reads the status code off the answer
+async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(stream: S, request: &str) -> Result<u16, String> {
+    let mut stream = BufReader::new(stream);
+    stream.get_mut().write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut status_line = String::new();
+    (&mut stream).take(1024).read_line(&mut status_line).await.map_err(|e| e.to_string())?;
+    // "HTTP/1.1 204 No Content"
+    status_line.split_whitespace().nth(1).and_then(|code| code.parse().ok()).ok_or_else(|| "not an HTTP answer".to_string())
+}
+
+// Webhook URLs are secrets (Slack's and Discord's carry the key in the path), the log gets the host only
+fn redact(url: &str) -> String {
+    match parse_url(url) {
+        Ok(url) => format!("{}://{}/…", if url.https { "https" } else { "http" }, url.host),
+        Err(_) => "(bad URL)".to_string(),
+    }
+}
+
+// --- inbound endpoint ---
+
+// A post to put in a room, `done` says whether it was (or the HTTP status and why not)
+#[derive(Debug)]
+pub struct WebhookPost {
+    pub room: String,
+    pub username: String,
+    pub text: String,
+    // Who posted it, for the plugins
+    pub from: SocketAddr,
+    pub done: oneshot::Sender<Result<(), (u16, String)>>,
+}
+
+// Listens on `addr` for posts carrying `token`, what comes in comes out of the returned channel
+pub fn listen(addr: SocketAddr, token: String) -> Result<mpsc::Receiver<WebhookPost>, String> {
+    let listener = super::listen::bind(addr).map_err(|e| format!("can't listen for webhooks on {}: {}", addr, e))?;
+    let (posts, received) = mpsc::channel(QUEUE);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, from)) => {
+                    let posts = posts.clone();
+                    let token = token.clone();
+                    tokio::spawn(async move {
+                        let _ = tokio::time::timeout(TIMEOUT, serve(socket, from, &token, posts)).await;
+                    });
+                }
+                Err(e) => {
+                    eprintln!("[WARN] accepting a webhook on {} failed: {}", addr, e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+    Ok(received)
+}
+
+// One request, one answer, then the connection closes
+async fn serve(socket: TcpStream, from: SocketAddr, token: &str, posts: mpsc::Sender<WebhookPost>) {
+    let mut socket = BufReader::new(socket);
+    let (status, reason) = match handle(&mut socket, from, token, posts).await {
+        Ok(()) => (200, "ok".to_string()),
+        Err(failure) => failure,
+    };
+    let answer = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        status_text(status),
+        reason.len(),
+        reason
+    );
+    let _ = socket.get_mut().write_all(answer.as_bytes()).await;
+    let _ = socket.get_mut().shutdown().await;
+}
+
+async fn handle(socket: &mut BufReader<TcpStream>, from: SocketAddr, token: &str, posts: mpsc::Sender<WebhookPost>) -> Result<(), (u16, String)> {
+    let bad_request = |reason: &str| (400, reason.to_string());
+    let request_line = read_header_line(socket).await.ok_or_else(|| bad_request("no request"))?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    // Headers: the length of the body and maybe the token
+    let mut length = None;
+    let mut bearer = None;
+    for _ in 0..100 {
+        let line = read_header_line(socket).await.ok_or_else(|| bad_request("headers cut short"))?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = Some(value.trim().parse::<usize>().map_err(|_| bad_request("bad Content-Length"))?),
+            "authorization" => bearer = value.trim().strip_prefix("Bearer ").map(str::to_string),
+            _ => {}
+        }
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let room = path.strip_prefix("/hooks/").filter(|room| !room.is_empty() && !room.contains('/'));
+    let Some(room) = room else { return Err((404, "post to /hooks/<room>".to_string())) };
+    if method != "POST" {
+        return Err((405, "only POST".to_string()));
+    }
+    let given = query.split('&').find_map(|pair| pair.strip_prefix("token=")).map(str::to_string).or(bearer);
+    if !given.is_some_and(|given| same_token(token, &given)) {
+        return Err((401, "wrong or missing token".to_string()));
+    }
+    let length = length.ok_or((411, "Content-Length needed".to_string()))?;
+    if length > MAX_BODY {
+        return Err((413, format!("keep it under {} bytes", MAX_BODY)));
+    }
+    let mut body = vec![0; length];
+    socket.read_exact(&mut body).await.map_err(|_| bad_request("body cut short"))?;
+    let (username, text) = parse_payload(&body).map_err(|e| bad_request(&e))?;
+
+    let (done, answer) = oneshot::channel();
+    let post = WebhookPost { room: room.to_string(), username: poster_name(username.as_deref()), text, from, done };
+    posts.send(post).await.map_err(|_| (503, "the server is shutting down".to_string()))?;
+    answer.await.unwrap_or_else(|_| Err((503, "the server is shutting down".to_string())))
+}
+
+// A header line without its line ending, None when the client hung up or sent something endless
+async fn read_header_line(socket: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    let read = (&mut *socket).take(8 * 1024).read_line(&mut line).await.ok()?;
+    (read > 0 && line.ends_with('\n')).then(|| line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        _ => "Service Unavailable",
+    }
+}
@@ -0,0 +1,121 @@
+// Wire-level tracing for protocol debugging
+// Every frame sent or received is appended to a log file, pretty-printed when it is JSON.
+// Passwords, session tokens, federation link tokens and two-factor secrets are redacted before anything is written.
+// Tracing can be switched on and off at runtime, the file is opened on first use.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::Local;
+use serde_json::Value;
+
+// Object keys whose values never reach the trace file
+const SECRET_KEYS: [&str; 3] = ["password", "session_token", "link_token"];
+// Plain text commands whose arguments are secrets
+const SECRET_COMMANDS: [&str; 4] = ["/register", "/login", "/passwd", "/2fa"];
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug)]
+pub struct WireTracer {
+    path: PathBuf,
+    enabled: AtomicBool,
+    file: Mutex<Option<File>>,
+}
+
+impl WireTracer {
+    pub fn new(path: impl Into<PathBuf>, enabled: bool) -> Self {
+        WireTracer {
+            path: path.into(),
+            enabled: AtomicBool::new(enabled),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    // Logs one frame (without its trailing newline) exchanged with `peer`
+    pub fn record(&self, direction: Direction, peer: &str, frame: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let arrow = match direction {
+            Direction::Sent => "-->",
+            Direction::Received => "<--",
+        };
+        let entry = format!(
+            "[{}] {} {}\n{}\n",
+            Local::now().format("%D:%H:%M:%S%.3f"),
+            arrow,
+            peer,
+            redact(frame.trim_end())
+        );
+
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            match OpenOptions::new().create(true).append(true).open(&self.path) {
+                Ok(opened) => *file = Some(opened),
+                Err(e) => {
+                    eprintln!("[ERROR] cannot open wire trace {}: {}", self.path.display(), e);
+                    self.set_enabled(false);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = file.as_mut() {
+            let _ = file.write_all(entry.as_bytes());
+        }
+    }
+}
+
+// Pretty-prints JSON frames and blanks out anything secret
+fn redact(frame: &str) -> String {
+    match serde_json::from_str::<Value>(frame) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            serde_json::to_string_pretty(&value).unwrap_or_else(|_| frame.to_string())
+        }
+        // Plain text line, e.g. a chat message typed by the user
+        Err(_) => match SECRET_COMMANDS.iter().find(|cmd| frame.split_whitespace().next() == Some(**cmd)) {
+            Some(cmd) => format!("{} {}", cmd, REDACTED),
+            None => frame.to_string(),
+        },
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    let Value::Object(map) = value else { return };
+
+    // Session frames carry the token in their content, two-factor frames the secret and backup codes
+    if matches!(map.get("message_type").and_then(Value::as_str), Some("Session" | "TwoFactorSetup" | "BackupCodes")) {
+        map.insert("content".to_string(), Value::from(REDACTED));
+    }
+    for key in SECRET_KEYS {
+        if let Some(secret) = map.get_mut(key)
+            && !secret.is_null()
+        {
+            *secret = Value::from(REDACTED);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Property tests for the wire protocol
+// Generated frames (odd unicode, huge content, every kind of frame) have to come back from JSON exactly as
+// they went in, nothing a socket can hand the decoder (arbitrary bytes, mangled frames) may make it panic,
+// and frames from a newer protocol (message types and fields it has never heard of) still decode.
+
+use proptest::prelude::*;
+
+use retro_chat_project::protocol::{ChatMessage, ErrorKind, Handshake, Member, MessageType, OpsEvent, Quote, Reaction};
+
+const MESSAGE_TYPES: [MessageType; 23] = [
+    MessageType::UserMessage,
+    MessageType::SystemNotification,
+    MessageType::Error,
+    MessageType::Session,
+    MessageType::UserCount,
+    MessageType::Whisper,
+    MessageType::Action,
+    MessageType::RoomJoined,
+    MessageType::RoomLeft,
+    MessageType::Reactions,
+    MessageType::TwoFactorSetup,
+    MessageType::BackupCodes,
+    MessageType::RoomMode,
+    MessageType::Ping,
+    MessageType::Roster,
+    MessageType::OpsEvent,
+    MessageType::ShortcodeSync,
+    MessageType::Draft,
+    MessageType::RoomList,
+    MessageType::Pins,
+    MessageType::Seen,
+    MessageType::Key,
+    MessageType::Capabilities,
+];
+
+const ERROR_KINDS: [ErrorKind; 4] = [ErrorKind::AuthFailed, ErrorKind::Cooldown, ErrorKind::Kicked, ErrorKind::PrivateRoom];
+const OPS_EVENTS: [OpsEvent; 5] = [OpsEvent::Ban, OpsEvent::RateLimit, OpsEvent::Lag, OpsEvent::Error, OpsEvent::Chaos];
+
+// Text that has tripped up chat software before: direction marks, joiners, combining marks, emoji sequences
+const ODD_TEXT: [&str; 8] = [
+    "\u{202E}txet desrever",
+    "👩\u{200D}👩\u{200D}👧\u{200D}👦 family",
+    "e\u{0301}\u{0301}\u{0301} zalgo",
+    "\u{FEFF}bom",
+    "tab\tand\r\nnewlines\n\n\n",
+    "\"quotes\" and \\backslashes\\",
+    "日本語のテキスト",
+    "\u{1F1FA}\u{1F1F8} \u{FFFD}",
+];
+
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => any::<String>(),
+        2 => prop::sample::select(ODD_TEXT.to_vec()).prop_map(str::to_string),
+        1 => (any::<char>(), 10_000usize..100_000).prop_map(|(c, n)| c.to_string().repeat(n)),
+    ]
+}
+
+fn message_type() -> impl Strategy<Value = MessageType> {
+    prop::sample::select(MESSAGE_TYPES.to_vec())
+}
+
+// A frame with everything but the nested pins
+fn flat_message() -> impl Strategy<Value = ChatMessage> {
+    let head = (any::<Option<i64>>(), prop::option::of(text()), text(), text(), text(), message_type());
+    let flags = (any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>());
+    let extra = (
+        prop::option::of(prop::sample::select(ERROR_KINDS.to_vec())),
+        any::<Option<u64>>(),
+        prop::option::of(text()),
+        prop::collection::vec((text(), any::<usize>()).prop_map(|(emoji, count)| Reaction { emoji, count }), 0..3),
+        prop::collection::vec((text(), any::<bool>(), any::<bool>()).prop_map(|(username, verified, dnd)| Member { username, verified, dnd }), 0..3),
+        prop::option::of(prop::sample::select(OPS_EVENTS.to_vec())),
+        prop::option::of((any::<i64>(), text(), text()).prop_map(|(id, username, content)| Quote { id, username, content })),
+        prop::option::of(text()),
+    );
+    (head, flags, extra).prop_map(|(head, flags, extra)| {
+        let (id, room, username, content, timestamp, message_type) = head;
+        let (verified, bad_signature, encrypted, read_only, highlight) = flags;
+        let (error, retry_after, recipient, reactions, roster, ops_event, quote, origin) = extra;
+        ChatMessage {
+            id,
+            room,
+            username,
+            content,
+            timestamp,
+            message_type,
+            verified,
+            bad_signature,
+            encrypted,
+            read_only,
+            highlight,
+            error,
+            retry_after,
+            recipient,
+            reactions,
+            roster,
+            ops_event,
+            quote,
+            origin,
+            ..Default::default()
+        }
+    })
+}
+
+fn message() -> impl Strategy<Value = ChatMessage> {
+    (flat_message(), prop::collection::vec(flat_message(), 0..2)).prop_map(|(msg, pins)| ChatMessage { pins, ..msg })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn frames_come_back_from_json_as_they_went_in(msg in message()) {
+        let json = serde_json::to_string(&msg).unwrap();
+        prop_assert!(!json.contains('\n'), "a frame has to fit on one line");
+        let decoded: ChatMessage = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic_the_decoder(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = serde_json::from_slice::<ChatMessage>(&bytes);
+        let _ = serde_json::from_slice::<Handshake>(&bytes);
+        let _ = serde_json::from_str::<ChatMessage>(&String::from_utf8_lossy(&bytes));
+    }
+
+    #[test]
+    fn mangled_frames_never_panic_the_decoder(msg in flat_message(), cut in any::<prop::sample::Index>(), flip in any::<u8>()) {
+        let mut json = serde_json::to_vec(&msg).unwrap();
+        let at = cut.index(json.len());
+        json[at] ^= flip;
+        let _ = serde_json::from_slice::<ChatMessage>(&json);
+        let _ = serde_json::from_slice::<ChatMessage>(&json[..at]);
+    }
+
+    #[test]
+    fn unknown_message_types_and_fields_still_decode(msg in flat_message(), name in "[A-Z][A-Za-z]{0,20}", field in "[a-z_]{1,12}") {
+        prop_assume!(!MESSAGE_TYPES.iter().any(|known| serde_json::to_value(known).unwrap() == name.as_str()));
+        let mut value = serde_json::to_value(&msg).unwrap();
+        value["message_type"] = serde_json::Value::String(name);
+        if value.get(&field).is_none() {
+            value[&field] = serde_json::json!({ "added": "later" });
+        }
+        let decoded = serde_json::from_value::<ChatMessage>(value).unwrap();
+        prop_assert_eq!(decoded.message_type, MessageType::Unknown);
+        prop_assert_eq!(decoded.content, msg.content);
+    }
+}
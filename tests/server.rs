@@ -0,0 +1,1775 @@
+// Server tests against a ChatServer run in-process
+// Each test gets its own server on an ephemeral port with an in-memory database, and talks to it
+// through raw sockets: a handshake line out, one JSON frame per line back.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use retro_chat_project::client::connection::ChatClient;
+#[cfg(feature = "quic")]
+use retro_chat_project::client::connection::Transport;
+use retro_chat_project::client::signing::Signer;
+use retro_chat_project::protocol::{signed_text, ChatMessage, ErrorKind, Handshake, Member, MessageType, MAX_LINE_BYTES};
+use retro_chat_project::server;
+use retro_chat_project::server::accounts;
+use retro_chat_project::server::chaos::Chaos;
+use retro_chat_project::server::app::{ChatServer, ServerOptions, ShutdownHandle};
+use retro_chat_project::server::cluster::{self, Online};
+use retro_chat_project::server::config::{
+    FederationConfig, MatrixConfig, OutboundWebhook, Preset, RedisConfig, RetentionConfig, RoomRetention, ServerConfig, WebhookFormat,
+};
+use retro_chat_project::server::console::AdminCommand;
+use retro_chat_project::server::cooldown::ReconnectGuard;
+use retro_chat_project::server::federation::Federation;
+use retro_chat_project::server::irc::{self, Inbound, Translator};
+use retro_chat_project::server::limits::{ConnectionLimits, LimitExceeded};
+use retro_chat_project::server::listen;
+use retro_chat_project::server::matrix::{self, MatrixBridge};
+use retro_chat_project::server::outbound::{Outbound, SYSTEM_LANE_CAPACITY};
+use retro_chat_project::server::plugins::{ClientInfo, Plugins, ServerPlugin, Verdict};
+use retro_chat_project::server::rooms::{Rooms, RATE_WINDOW};
+use retro_chat_project::server::store::Store;
+use retro_chat_project::server::totp;
+use retro_chat_project::server::webhooks;
+use retro_chat_project::trace::WireTracer;
+
+// Longest a test waits for a frame it expects
+const PATIENCE: Duration = Duration::from_secs(5);
+
+// Idle timeout of the tests about it, kept short so they don't take long (the same again as a Duration)
+const IDLE_MINUTES: f64 = 0.02;
+const IDLE_TIMEOUT: Duration = Duration::from_millis(1200);
+
+struct TestServer {
+    addr: String,
+    shutdown: ShutdownHandle,
+    running: JoinHandle<()>,
+}
+
+async fn start_server() -> TestServer {
+    start_server_with(ServerConfig::default()).await
+}
+
+async fn start_server_with(config: ServerConfig) -> TestServer {
+    start_server_from(ServerOptions::default(), config).await
+}
+
+// A server with these command line options, always on a database of its own
+async fn start_server_from(options: ServerOptions, config: ServerConfig) -> TestServer {
+    start_server_on(":memory:", options, config).await
+}
+
+// The same on a database file, for tests that look inside it afterwards
+async fn start_server_on(db_path: &str, options: ServerOptions, config: ServerConfig) -> TestServer {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let options = ServerOptions { db_path: db_path.to_string(), ..options };
+    let server = ChatServer::new(options, config).await.unwrap();
+    let shutdown = server.shutdown_handle();
+    let running = tokio::spawn(server.run(vec![listener]));
+    TestServer { addr, shutdown, running }
+}
+
+struct RawClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl RawClient {
+    // Connects and reads up to the user count, the server has let us in by then
+    // Returns the frames read on the way (system frames jump the queue, so the history replay may not be over yet)
+    async fn connect(addr: &str, username: &str) -> (RawClient, Vec<ChatMessage>) {
+        RawClient::connect_with(addr, Handshake { username: username.to_string(), ..Default::default() }).await
+    }
+
+    async fn connect_with(addr: &str, handshake: Handshake) -> (RawClient, Vec<ChatMessage>) {
+        let mut client = RawClient::open(addr, handshake).await;
+        let replay = client.read_until(|msg| msg.message_type == MessageType::UserCount).await;
+        (client, replay)
+    }
+
+    // Connects and sends the handshake, reading nothing, for connections the server may turn away
+    async fn open(addr: &str, handshake: Handshake) -> RawClient {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut client = RawClient { lines: BufReader::new(reader).lines(), writer };
+        client.send(&serde_json::to_string(&handshake).unwrap()).await;
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+    }
+
+    // The next frame, None once the server has closed the connection
+    async fn recv(&mut self) -> Option<ChatMessage> {
+        loop {
+            let line = tokio::time::timeout(PATIENCE, self.lines.next_line()).await.expect("no frame in time").ok()??;
+            if let Ok(msg) = serde_json::from_str(&line) {
+                return Some(msg);
+            }
+        }
+    }
+
+    // Frames up to and including the first one `done` says yes to
+    async fn read_until(&mut self, done: impl Fn(&ChatMessage) -> bool) -> Vec<ChatMessage> {
+        let mut frames = Vec::new();
+        loop {
+            let msg = self.recv().await.expect("the server hung up");
+            let last = done(&msg);
+            frames.push(msg);
+            if last {
+                return frames;
+            }
+        }
+    }
+
+    // The next chat message, skipping notifications, counts and the like
+    async fn next_chat(&mut self) -> ChatMessage {
+        self.read_until(|msg| msg.message_type == MessageType::UserMessage).await.pop().unwrap()
+    }
+
+    // Registers the name it connected as, with the same password every time
+    async fn register(&mut self) {
+        self.send("/register hunter22").await;
+        self.read_until(|msg| msg.content.starts_with("Registered ")).await;
+    }
+
+    // What the error frame a command got back says
+    async fn refusal(&mut self) -> String {
+        self.read_until(|msg| msg.error.is_some()).await.pop().unwrap().content
+    }
+}
+
+fn chat(frames: &[ChatMessage]) -> Vec<String> {
+    frames.iter().filter(|msg| msg.message_type == MessageType::UserMessage).map(|msg| msg.content.clone()).collect()
+}
+
+#[tokio::test]
+async fn arrivals_are_announced_to_those_already_there() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (_bob, _) = RawClient::connect(&server.addr, "bob").await;
+
+    let landed = alice.read_until(|msg| msg.content == "has landed" && msg.username == "bob").await;
+    assert_eq!(landed.last().unwrap().message_type, MessageType::SystemNotification);
+}
+
+#[tokio::test]
+async fn history_is_replayed_oldest_first() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    for text in ["first", "second", "third"] {
+        alice.send(text).await;
+        assert_eq!(alice.next_chat().await.content, text);
+    }
+
+    let (mut bob, replay) = RawClient::connect(&server.addr, "bob").await;
+    let mut replayed = chat(&replay);
+    while replayed.len() < 3 {
+        replayed.push(bob.next_chat().await.content);
+    }
+    assert_eq!(replayed, ["first", "second", "third"]);
+}
+
+#[tokio::test]
+async fn messages_fan_out_to_everyone_in_the_room() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    let (mut carol, _) = RawClient::connect(&server.addr, "carol").await;
+
+    bob.send("hello all").await;
+    for client in [&mut alice, &mut bob, &mut carol] {
+        let msg = client.next_chat().await;
+        assert_eq!((msg.username.as_str(), msg.content.as_str()), ("bob", "hello all"));
+        assert_eq!(msg.room.as_deref(), Some("lobby"));
+    }
+}
+
+#[tokio::test]
+async fn a_server_password_is_checked_before_anyone_gets_in() {
+    let options = ServerOptions { password: Some("letmein".to_string()), ..Default::default() };
+    let server = start_server_from(options, ServerConfig::default()).await;
+
+    for (password, reason) in [(None, "This server requires a password"), (Some("guess"), "Wrong server password")] {
+        let handshake = Handshake { username: "eve".to_string(), password: password.map(str::to_string), ..Default::default() };
+        let mut eve = RawClient::open(&server.addr, handshake).await;
+        let refused = eve.recv().await.unwrap();
+        assert_eq!((refused.error, refused.content.as_str()), (Some(ErrorKind::AuthFailed), reason));
+        assert!(eve.recv().await.is_none());
+    }
+
+    let handshake = Handshake { username: "alice".to_string(), password: Some("letmein".to_string()), ..Default::default() };
+    let (mut alice, _) = RawClient::connect_with(&server.addr, handshake).await;
+    alice.send("hello").await;
+    assert_eq!(alice.next_chat().await.content, "hello");
+}
+
+#[test]
+fn maintenance_keeps_each_room_to_its_retention_and_reclaims_the_space() {
+    let store = Store::open(":memory:").unwrap();
+    let long = "x".repeat(2000);
+    for (room, count) in [("lobby", 50), ("news", 5), ("old", 3)] {
+        for i in 0..count {
+            store.save_message(room, &ChatMessage::new("alice", format!("{} {}", i, long), MessageType::UserMessage)).unwrap();
+        }
+    }
+
+    // Ten messages a room, news keeps two and old keeps nothing older than today
+    let retention = RetentionConfig {
+        max_messages: Some(10),
+        rooms: HashMap::from([
+            ("news".to_string(), RoomRetention { max_messages: Some(2), ..Default::default() }),
+            ("old".to_string(), RoomRetention { max_age_days: Some(0), ..Default::default() }),
+        ]),
+        ..Default::default()
+    };
+    let report = store.run_maintenance(&retention).unwrap();
+    assert_eq!(report.deleted_messages, 40 + 3 + 3);
+    assert!(report.reclaimed_bytes() > 40 * 2000, "{:?}", (report.bytes_before, report.bytes_after));
+
+    let kept: Vec<String> = store.recent_messages("lobby", 100).unwrap().into_iter().map(|msg| msg.content.split(' ').next().unwrap().to_string()).collect();
+    assert_eq!(kept, (40..50).map(|i| i.to_string()).collect::<Vec<_>>());
+    assert_eq!(store.recent_messages("news", 100).unwrap().len(), 2);
+    assert!(store.recent_messages("old", 100).unwrap().is_empty());
+
+    // Nothing left over means nothing to do
+    assert_eq!(store.run_maintenance(&retention).unwrap().deleted_messages, 0);
+}
+
+#[tokio::test]
+async fn connections_over_the_caps_are_told_and_closed() {
+    for (max_clients, max_per_ip, reason) in [(2, 10, "Server is full, try again later"), (10, 2, "Too many connections from your address")] {
+        let mut config = ServerConfig::default();
+        (config.limits.max_clients, config.limits.max_per_ip) = (max_clients, max_per_ip);
+        let server = start_server_with(config).await;
+        let _first = RawClient::connect(&server.addr, "first").await;
+        let _second = RawClient::connect(&server.addr, "second").await;
+
+        let mut third = RawClient::open(&server.addr, Handshake { username: "third".to_string(), ..Default::default() }).await;
+        let refused = third.recv().await.unwrap();
+        assert_eq!((refused.error, refused.content.as_str()), (Some(ErrorKind::ServerFull), reason));
+        assert!(third.recv().await.is_none());
+    }
+}
+
+#[test]
+fn connection_slots_come_back_when_the_connection_goes() {
+    let limits = ConnectionLimits::new(3, 2);
+    let (home, work): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+    let first = limits.try_acquire(home).unwrap();
+    let _second = limits.try_acquire(home).unwrap();
+    assert_eq!(limits.try_acquire(home).unwrap_err(), LimitExceeded::TooManyFromIp);
+    let _third = limits.try_acquire(work).unwrap();
+    assert_eq!(limits.try_acquire(work).unwrap_err(), LimitExceeded::ServerFull);
+
+    drop(first);
+    assert!(limits.try_acquire(home).is_ok());
+    // Lowered caps turn away the next one, nobody already in is dropped
+    limits.set_caps(1, 1);
+    assert_eq!(limits.try_acquire(work).unwrap_err(), LimitExceeded::ServerFull);
+}
+
+#[tokio::test]
+async fn rooms_per_user_and_joins_per_minute_are_capped() {
+    let mut config = ServerConfig::default();
+    (config.rooms.max_per_user, config.rooms.joins_per_minute) = (3, 3);
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+
+    for room in ["a", "b"] {
+        alice.send(&format!("/join {}", room)).await;
+        alice.read_until(|msg| msg.message_type == MessageType::RoomJoined && msg.room.as_deref() == Some(room)).await;
+    }
+    // The lobby counts too
+    alice.send("/join c").await;
+    let refused = alice.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!((refused.error, refused.room.as_deref()), (Some(ErrorKind::TooManyRooms), Some("c")));
+    assert_eq!(refused.content, "You can be in at most 3 rooms, /leave one first");
+
+    // Leaving makes room, but joining a fourth time inside the minute is too fast
+    alice.send("/leave a").await;
+    alice.send("/join c").await;
+    alice.read_until(|msg| msg.message_type == MessageType::RoomJoined && msg.room.as_deref() == Some("c")).await;
+    alice.send("/leave c").await;
+    alice.send("/join d").await;
+    let refused = alice.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!((refused.error, refused.retry_after), (Some(ErrorKind::JoinRateLimited), Some(10)));
+    assert_eq!(refused.content, "You are joining rooms too fast, try again in 10 seconds");
+}
+
+// Plugins for the registry test: one rewrites, one vetoes, one notes down what reached it
+struct Shout;
+
+impl ServerPlugin for Shout {
+    fn name(&self) -> &str {
+        "shout"
+    }
+
+    fn on_message(&self, _client: &ClientInfo, text: &mut String) -> Verdict {
+        *text = text.to_uppercase();
+        Ok(())
+    }
+}
+
+struct NoNope;
+
+impl ServerPlugin for NoNope {
+    fn name(&self) -> &str {
+        "no-nope"
+    }
+
+    fn on_message(&self, _client: &ClientInfo, text: &mut String) -> Verdict {
+        match text.contains("NOPE") {
+            true => Err(Box::new(ChatMessage::error(ErrorKind::MessageBlocked, "nope"))),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Seen(Mutex<Vec<String>>);
+
+impl ServerPlugin for Seen {
+    fn name(&self) -> &str {
+        "seen"
+    }
+
+    fn on_message(&self, _client: &ClientInfo, text: &mut String) -> Verdict {
+        self.0.lock().unwrap().push(text.clone());
+        Ok(())
+    }
+
+    fn on_disconnect(&self, client: &ClientInfo, reason: &str) {
+        self.0.lock().unwrap().push(format!("{} {}", client.username, reason));
+    }
+}
+
+#[test]
+fn plugins_run_in_order_and_the_first_veto_wins() {
+    let seen = Arc::new(Seen::default());
+    let mut plugins = Plugins::default();
+    plugins.register(Arc::new(Shout));
+    plugins.register(Arc::new(NoNope));
+    plugins.register(seen.clone());
+    assert_eq!(plugins.names(), ["shout", "no-nope", "seen"]);
+
+    let client = ClientInfo { username: "alice", addr: "127.0.0.1:4000".parse().unwrap(), room: "lobby", verified: false };
+    let mut text = "hello".to_string();
+    assert!(plugins.on_message(&client, &mut text).is_ok());
+    assert_eq!(text, "HELLO");
+    // Vetoed after the rewrite, before the plugins behind it
+    let mut text = "nope".to_string();
+    assert_eq!(plugins.on_message(&client, &mut text).unwrap_err().content, "nope");
+    // Nothing can veto a disconnect, every plugin hears of it
+    plugins.on_disconnect(&client, "has blasted off");
+    assert!(plugins.on_connect(&client).is_ok());
+    assert_eq!(*seen.0.lock().unwrap(), ["HELLO", "alice has blasted off"]);
+}
+
+#[tokio::test]
+async fn the_word_filter_plugin_masks_and_blocks_before_anything_is_sent() {
+    let mut config = ServerConfig::default();
+    config.filter.words.mask = vec!["darn".to_string()];
+    config.filter.words.block = vec!["scam".to_string()];
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+
+    alice.send("scam link here").await;
+    let refused = alice.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!(refused.error, Some(ErrorKind::MessageBlocked));
+    assert_eq!(refused.content, "Your message was not sent: \"scam\" is not allowed here");
+    alice.send("darn it").await;
+    assert_eq!(alice.next_chat().await.content, "**** it");
+}
+
+#[tokio::test]
+async fn the_motd_comes_before_the_history_and_owners_can_change_it() {
+    let config = ServerConfig { motd: Some("Be nice".to_string()), ..owners_config() };
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("said earlier").await;
+    alice.next_chat().await;
+
+    let (mut bob, mut frames) = RawClient::connect(&server.addr, "bob").await;
+    frames.extend(bob.read_until(|msg| msg.content == "said earlier").await);
+    let motd = frames.iter().position(|msg| msg.username == "MOTD").expect("no MOTD");
+    let history = frames.iter().position(|msg| msg.message_type == MessageType::UserMessage).unwrap();
+    assert_eq!(frames[motd].content, "Be nice");
+    assert!(motd < history, "{:?}", frames);
+
+    // Only owners change it, and everyone online hears the new one
+    bob.send("/motd Anything goes").await;
+    assert_eq!(bob.refusal().await, "You can't change the message of the day");
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    olive.send("/motd Be nicer").await;
+    let changed = bob.read_until(|msg| msg.username == "MOTD").await.pop().unwrap();
+    assert_eq!(changed.content, "Be nicer");
+    let (_, frames) = RawClient::connect(&server.addr, "carol").await;
+    assert_eq!(frames.iter().find(|msg| msg.username == "MOTD").unwrap().content, "Be nicer");
+}
+
+#[tokio::test]
+async fn a_reloaded_config_applies_without_dropping_anyone() {
+    let path = std::env::temp_dir().join(format!("server-test-reload-{}.toml", std::process::id()));
+    std::fs::write(&path, "motd = \"Welcome\"\n[filter]\nmask = [\"darn\"]\n").unwrap();
+    let options = ServerOptions { config_path: Some(path.to_str().unwrap().to_string()), ..Default::default() };
+    let server = start_server_from(options.clone(), options.load_config().unwrap()).await;
+    let (mut alice, frames) = RawClient::connect(&server.addr, "alice").await;
+    assert!(frames.iter().any(|msg| msg.username == "MOTD" && msg.content == "Welcome"));
+    alice.send("darn heck").await;
+    assert_eq!(alice.next_chat().await.content, "**** heck");
+
+    // A broken file leaves things as they were
+    std::fs::write(&path, "motd = \n").unwrap();
+    server.shutdown.reload().await;
+    alice.send("darn heck").await;
+    assert_eq!(alice.next_chat().await.content, "**** heck");
+
+    std::fs::write(&path, "motd = \"Welcome back\"\n[filter]\nmask = [\"heck\"]\n").unwrap();
+    server.shutdown.reload().await;
+    alice.send("darn heck").await;
+    assert_eq!(alice.next_chat().await.content, "darn ****");
+    let (_, frames) = RawClient::connect(&server.addr, "bob").await;
+    assert!(frames.iter().any(|msg| msg.username == "MOTD" && msg.content == "Welcome back"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn presets_fill_in_what_the_config_file_leaves_out() {
+    assert_eq!(Preset::parse("classroom"), Ok(Preset::Classroom));
+    assert_eq!(
+        Preset::parse("school"),
+        Err("unknown preset school (there are small-friends, public-community, classroom)".to_string())
+    );
+    let path = std::env::temp_dir().join(format!("server-test-preset-{}.toml", std::process::id()));
+    std::fs::write(&path, "[rooms]\nmax_per_user = 8\n").unwrap();
+    let config = ServerConfig::load_with_preset(Some(Preset::PublicCommunity), Some(&path)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((config.rooms.max_per_user, config.rooms.joins_per_minute, config.limits.max_per_ip), (8, 5, 2));
+
+    // Guests read along until they sign in, and the preset's filter is on
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("hello").await;
+    let refused = alice.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!(refused.error, Some(ErrorKind::SignInRequired));
+    alice.register().await;
+    alice.send("damn, hello").await;
+    assert_eq!(alice.next_chat().await.content, "****, hello");
+}
+
+#[tokio::test]
+async fn reconnect_storms_are_held_off_with_a_retry_after() {
+    let options = ServerOptions { reconnect_limit: 2, ..Default::default() };
+    let server = start_server_from(options, ServerConfig::default()).await;
+    for _ in 0..2 {
+        RawClient::connect(&server.addr, "bot").await;
+    }
+
+    let mut bot = RawClient::open(&server.addr, Handshake { username: "bot".to_string(), ..Default::default() }).await;
+    let refused = bot.recv().await.unwrap();
+    assert_eq!(refused.error, Some(ErrorKind::Cooldown));
+    assert_eq!(refused.retry_after, Some(10));
+    assert_eq!(refused.content, "Too many reconnects, try again in 10 seconds");
+    assert!(bot.recv().await.is_none());
+}
+
+#[test]
+fn reconnect_cooldowns_double_for_each_storm() {
+    let mut guard = ReconnectGuard::new(2, Duration::from_secs(60));
+    let start = Instant::now();
+    assert!(guard.check("ip:10.0.0.1", start).is_ok());
+    assert!(guard.check("ip:10.0.0.1", start).is_ok());
+    assert_eq!(guard.check("ip:10.0.0.1", start), Err(Duration::from_secs(10)));
+    // Other keys don't share the cooldown
+    assert!(guard.check("ip:10.0.0.2", start).is_ok());
+    // Still held off, and asking doesn't count as an attempt
+    let later = start + Duration::from_secs(4);
+    assert_eq!(guard.check("ip:10.0.0.1", later), Err(Duration::from_secs(6)));
+    assert_eq!(guard.cooling_down("ip:10.0.0.1", later), Some(Duration::from_secs(6)));
+
+    // The next storm waits twice as long
+    let after = start + Duration::from_secs(11);
+    for _ in 0..2 {
+        assert!(guard.check("ip:10.0.0.1", after).is_ok());
+    }
+    assert_eq!(guard.check("ip:10.0.0.1", after), Err(Duration::from_secs(20)));
+    assert_eq!(guard.cooling_down("ip:10.0.0.1", after + Duration::from_secs(20)), None);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_clients_share_the_rooms_with_tcp_ones() {
+    let path = std::env::temp_dir().join(format!("retro-chat-test-{}.sock", std::process::id()));
+    let socket = path.to_string_lossy().into_owned();
+    let options = ServerOptions { unix_socket: Some(socket.clone()), ..Default::default() };
+    let server = start_server_from(options, ServerConfig::default()).await;
+    // A second server can't take over a socket that still answers
+    assert_eq!(server::unix::LocalListener::bind(Some(&socket)).unwrap_err().kind(), std::io::ErrorKind::AddrInUse);
+
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (reader, mut writer) = tokio::net::UnixStream::connect(&path).await.unwrap().into_split();
+    let handshake = serde_json::to_string(&Handshake { username: "bot".to_string(), ..Default::default() }).unwrap();
+    writer.write_all(format!("{}\nbeep\n", handshake).as_bytes()).await.unwrap();
+    let beep = alice.read_until(|msg| msg.content == "beep").await.pop().unwrap();
+    assert_eq!(beep.username, "bot");
+    alice.send("hi bot").await;
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = tokio::time::timeout(PATIENCE, lines.next_line()).await.expect("no frame in time").unwrap().expect("the server hung up");
+        if serde_json::from_str::<ChatMessage>(&line).is_ok_and(|msg| msg.content == "hi bot") {
+            break;
+        }
+    }
+
+    // The socket file goes when the server does
+    server.shutdown.shutdown(0);
+    tokio::time::timeout(PATIENCE, server.running).await.expect("the server didn't stop").unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn bind_lists_parse_in_order_without_repeats() {
+    let addrs = listen::parse_bind_list(" 0.0.0.0:8082, [::]:8082,0.0.0.0:8082,").unwrap();
+    assert_eq!(addrs, ["0.0.0.0:8082".parse().unwrap(), "[::]:8082".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(listen::parse_bind_list("localhost:8082").unwrap_err(), "invalid --bind address: localhost:8082 (expected ip:port, [ipv6]:port)");
+    assert_eq!(listen::parse_bind_list(" , ").unwrap_err(), "--bind needs at least one address");
+}
+
+#[tokio::test]
+async fn clients_on_ipv4_and_ipv6_listeners_share_one_server() {
+    // The same port on both, IPv6 listeners take IPv6 only so they don't collide
+    let v4 = listen::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let port = v4.local_addr().unwrap().port();
+    let v6 = listen::bind(format!("[::1]:{}", port).parse().unwrap()).unwrap();
+    let options = ServerOptions { db_path: ":memory:".to_string(), ..Default::default() };
+    let server = ChatServer::new(options, ServerConfig::default()).await.unwrap();
+    tokio::spawn(server.run(vec![v4, v6]));
+
+    let (mut alice, _) = RawClient::connect(&format!("127.0.0.1:{}", port), "alice").await;
+    let (mut bob, _) = RawClient::connect(&format!("[::1]:{}", port), "bob").await;
+    bob.send("hello from v6").await;
+    assert_eq!(alice.next_chat().await.content, "hello from v6");
+    alice.send("hello from v4").await;
+    assert_eq!(bob.read_until(|msg| msg.content == "hello from v4").await.pop().unwrap().username, "alice");
+}
+
+#[cfg(not(feature = "quic"))]
+#[tokio::test]
+async fn quic_needs_the_feature_on_both_ends() {
+    let options = ServerOptions { db_path: ":memory:".to_string(), quic: Some("127.0.0.1:0".parse().unwrap()), ..Default::default() };
+    let refused = match ChatServer::new(options, ServerConfig::default()).await {
+        Ok(_) => panic!("listened for QUIC without the feature"),
+        Err(e) => e.to_string(),
+    };
+    assert_eq!(refused, "this server was built without QUIC, build it with --features quic");
+
+    let handshake = Handshake { username: "alice".to_string(), ..Default::default() };
+    let refused = ChatClient::connect("quic://127.0.0.1:8443", handshake).await.err().unwrap();
+    assert_eq!(refused.to_string(), "this client was built without QUIC, build it with --features quic");
+}
+
+#[cfg(feature = "quic")]
+#[tokio::test]
+async fn quic_clients_speak_the_same_protocol_as_tcp_ones() {
+    // A certificate for localhost the client is told to trust
+    let dir = std::env::temp_dir().join(format!("retro-chat-quic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let made = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    std::fs::write(&cert, made.cert.pem()).unwrap();
+    std::fs::write(&key, made.key_pair.serialize_pem()).unwrap();
+
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let options = ServerOptions {
+        quic: Some(format!("127.0.0.1:{}", port).parse().unwrap()),
+        quic_cert: Some(cert.to_string_lossy().into_owned()),
+        quic_key: Some(key.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    let server = start_server_from(options, ServerConfig::default()).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+
+    let transport = Transport { quic_cert: Some(cert), ..Default::default() };
+    let handshake = Handshake { username: "bob".to_string(), ..Default::default() };
+    let tracer = Arc::new(WireTracer::new(dir.join("trace.log"), false));
+    let mut bob = ChatClient::connect_via(&format!("quic://localhost:{}", port), transport, handshake, tracer).await.unwrap();
+    bob.send("hello over quic").await.unwrap();
+    let heard = alice.read_until(|msg| msg.content == "hello over quic").await.pop().unwrap();
+    assert_eq!(heard.username, "bob");
+    alice.send("hello over tcp").await;
+    loop {
+        let msg = tokio::time::timeout(PATIENCE, bob.recv()).await.expect("no frame in time").expect("the server hung up");
+        if msg.content == "hello over tcp" {
+            break;
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn shutdown_says_goodbye_and_closes_every_connection() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+
+    server.shutdown.shutdown(0);
+    for client in [&mut alice, &mut bob] {
+        let goodbye = client.read_until(|msg| msg.content == "Server is shutting down...").await;
+        assert_eq!(goodbye.last().unwrap().message_type, MessageType::SystemNotification);
+        while client.recv().await.is_some() {}
+    }
+    tokio::time::timeout(PATIENCE, server.running).await.expect("the server didn't stop").unwrap();
+    assert!(TcpStream::connect(&server.addr).await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_counts_down_and_lets_the_room_finish_first() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+
+    server.shutdown.shutdown(2);
+    let started = alice.read_until(|msg| msg.content == "Server is shutting down in 2 seconds").await;
+    assert_eq!(started.last().unwrap().message_type, MessageType::SystemNotification);
+    // Nobody new gets in, those already in still talk
+    assert!(TcpStream::connect(&server.addr).await.is_err());
+    bob.send("last words").await;
+    assert_eq!(alice.next_chat().await.content, "last words");
+
+    let frames = alice.read_until(|msg| msg.content == "Server is shutting down...").await;
+    let notices: Vec<&str> = frames.iter().map(|msg| msg.content.as_str()).filter(|text| text.starts_with("Server is shutting down")).collect();
+    assert_eq!(notices, ["Server is shutting down in 1 second", "Server is shutting down..."]);
+    for client in [&mut alice, &mut bob] {
+        while client.recv().await.is_some() {}
+    }
+    tokio::time::timeout(PATIENCE, server.running).await.expect("the server didn't stop").unwrap();
+}
+
+#[test]
+fn console_commands_parse_with_their_arguments() {
+    assert_eq!(AdminCommand::parse("list"), Ok(AdminCommand::List));
+    assert_eq!(
+        AdminCommand::parse("kick bob  spamming links"),
+        Ok(AdminCommand::Kick { username: "bob".to_string(), reason: Some("spamming links".to_string()) })
+    );
+    assert_eq!(AdminCommand::parse("kick bob"), Ok(AdminCommand::Kick { username: "bob".to_string(), reason: None }));
+    assert_eq!(AdminCommand::parse("ban 10.0.0.7"), Ok(AdminCommand::Ban { ip: "10.0.0.7".parse().unwrap(), reason: None }));
+    assert_eq!(AdminCommand::parse("ban bob"), Err("not an IP address: bob".to_string()));
+    assert_eq!(AdminCommand::parse("say  back in five "), Ok(AdminCommand::Say { text: "back in five".to_string() }));
+    assert_eq!(AdminCommand::parse("stats"), Ok(AdminCommand::Stats));
+    assert_eq!(AdminCommand::parse("shutdown"), Ok(AdminCommand::Shutdown { seconds: 0 }));
+    assert_eq!(AdminCommand::parse("shutdown 30"), Ok(AdminCommand::Shutdown { seconds: 30 }));
+    assert_eq!(AdminCommand::parse("shutdown soon"), Err("not a number of seconds: soon".to_string()));
+
+    // Empty lines are ignored quietly, anything else unknown is pointed at help
+    assert_eq!(AdminCommand::parse("   "), Err(String::new()));
+    assert_eq!(AdminCommand::parse("kick"), Err("unknown or incomplete command: kick (try help)".to_string()));
+    assert_eq!(AdminCommand::parse("dance"), Err("unknown or incomplete command: dance (try help)".to_string()));
+}
+
+#[tokio::test]
+async fn do_not_disturb_shows_in_the_roster_and_whisperers_hear_of_it() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    let bob_dnd = |msg: &ChatMessage| msg.roster.iter().find(|member| member.username == "bob").map(|member| member.dnd);
+
+    bob.send("/dnd on").await;
+    alice.read_until(|msg| msg.message_type == MessageType::Roster && bob_dnd(msg) == Some(true)).await;
+    alice.send("/whisper bob psst").await;
+    // Notices jump the queue, the copy of the whisper may come after
+    let mut told = alice.read_until(|msg| msg.content == "bob has do not disturb on, they'll see it when it's over").await;
+    if !told.iter().any(|msg| msg.message_type == MessageType::Whisper) {
+        told.extend(alice.read_until(|msg| msg.message_type == MessageType::Whisper).await);
+    }
+    assert!(told.iter().any(|msg| msg.message_type == MessageType::Whisper && msg.content == "psst"), "the whisper went anyway");
+    let held = bob.read_until(|msg| msg.message_type == MessageType::Whisper).await;
+    assert_eq!(held.last().unwrap().content, "psst");
+
+    bob.send("/dnd off").await;
+    alice.read_until(|msg| msg.message_type == MessageType::Roster && bob_dnd(msg) == Some(false)).await;
+}
+
+fn idle_config() -> ServerConfig {
+    let mut config = ServerConfig::default();
+    config.limits.idle_timeout_minutes = IDLE_MINUTES;
+    config
+}
+
+#[tokio::test]
+async fn pings_alone_keep_a_connection_open() {
+    let server = start_server_with(idle_config()).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+
+    // Three times the idle timeout with nothing but the client's pings
+    for stamp in 0..9 {
+        tokio::time::sleep(IDLE_TIMEOUT / 3).await;
+        alice.send(&format!("/ping {}", stamp)).await;
+        let frames = alice.read_until(|msg| msg.message_type == MessageType::Ping).await;
+        assert!(!frames.iter().any(|msg| msg.error == Some(ErrorKind::IdleTimeout)), "timed out while pinging");
+        assert_eq!(frames.last().unwrap().content, stamp.to_string());
+    }
+}
+
+#[tokio::test]
+async fn silent_connections_time_out_and_talking_keeps_them_open() {
+    let server = start_server_with(idle_config()).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+
+    // Bob keeps talking past the timeout, alice only listens (what she's sent doesn't count)
+    let mut heard = Vec::new();
+    for n in 0..5 {
+        tokio::time::sleep(IDLE_TIMEOUT / 3).await;
+        bob.send(&format!("still here {}", n)).await;
+        heard.extend(bob.read_until(|msg| msg.content == format!("still here {}", n)).await);
+    }
+
+    let timed_out = alice.read_until(|msg| msg.error == Some(ErrorKind::IdleTimeout)).await;
+    let notice = timed_out.last().unwrap();
+    assert_eq!(notice.message_type, MessageType::SystemNotification);
+    assert_eq!(notice.content, format!("You timed out after {} minutes without activity", IDLE_MINUTES));
+    while alice.recv().await.is_some() {}
+    let alice_left = |msg: &ChatMessage| msg.username == "alice" && msg.content == "timed out";
+    if !heard.iter().any(alice_left) {
+        bob.read_until(alice_left).await;
+    }
+    bob.send("/ping 1").await;
+    bob.read_until(|msg| msg.message_type == MessageType::Ping).await;
+}
+
+// A server where olive and otto are owners once they've registered
+fn owners_config() -> ServerConfig {
+    let mut config = ServerConfig::default();
+    config.moderation.owners = vec!["olive".to_string(), "otto".to_string()];
+    config
+}
+
+// Waits for the frame saying `client` now talks in `room`
+async fn joined(client: &mut RawClient, room: &str) {
+    client.read_until(|msg| msg.message_type == MessageType::RoomJoined && msg.room.as_deref() == Some(room)).await;
+}
+
+#[tokio::test]
+async fn private_rooms_take_an_invite_or_the_password_and_stay_off_the_list() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+    alice.send("/create plans --private opensesame").await;
+    alice.read_until(|msg| msg.content.starts_with("Created private #plans")).await;
+
+    // Neither invited nor with the password
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    bob.register().await;
+    bob.send("/join plans").await;
+    assert_eq!(bob.refusal().await, "#plans is private, join it with an invite or /join plans <password>");
+    bob.send("/join plans guess1234").await;
+    assert_eq!(bob.refusal().await, "Wrong password for #plans");
+
+    // By invite
+    alice.send("/invite bob plans").await;
+    alice.read_until(|msg| msg.content == "Invited bob to #plans").await;
+    bob.send("/join plans").await;
+    joined(&mut bob, "plans").await;
+
+    // By password, with no invite
+    let (mut carol, _) = RawClient::connect(&server.addr, "carol").await;
+    carol.send("/join plans opensesame").await;
+    joined(&mut carol, "plans").await;
+
+    // The listing leaves it out, open and busy as it is
+    let (mut dave, _) = RawClient::connect(&server.addr, "dave").await;
+    dave.send("/rooms").await;
+    let list = dave.read_until(|msg| msg.message_type == MessageType::RoomList).await.pop().unwrap();
+    let listed: Vec<&str> = list.rooms.iter().map(|room| room.name.as_str()).collect();
+    assert_eq!(listed, ["lobby"]);
+}
+
+#[tokio::test]
+async fn rooms_with_a_past_cant_be_made_private_and_owners_can_open_private_ones() {
+    let server = start_server_with(owners_config()).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+
+    // #games is empty now, but its history belongs to the people who talked there
+    alice.send("/join games").await;
+    joined(&mut alice, "games").await;
+    alice.send("anyone up for chess?").await;
+    alice.read_until(|msg| msg.content == "anyone up for chess?").await;
+    alice.send("/leave games").await;
+    alice.read_until(|msg| msg.message_type == MessageType::RoomLeft).await;
+    let (mut mallory, _) = RawClient::connect(&server.addr, "mallory").await;
+    mallory.register().await;
+    mallory.send("/create games --private").await;
+    assert_eq!(mallory.refusal().await, "#games is already in use, pick another name");
+    // So does a room that only ever got a topic or a mode
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    olive.send("/roommode news readonly").await;
+    olive.read_until(|msg| msg.content == "#news is read-only now").await;
+    mallory.send("/create news --private").await;
+    assert_eq!(mallory.refusal().await, "#news is already in use, pick another name");
+
+    // Only owners open a private room up again, and then anyone gets in
+    mallory.send("/create hideout --private").await;
+    mallory.read_until(|msg| msg.content.starts_with("Created private #hideout")).await;
+    alice.send("/roommode hideout public").await;
+    assert_eq!(alice.refusal().await, "You can't change a room's mode");
+    olive.send("/roommode hideout public").await;
+    olive.read_until(|msg| msg.content == "#hideout is public now, anyone can join").await;
+    alice.send("/join hideout").await;
+    joined(&mut alice, "hideout").await;
+    olive.send("/roommode hideout public").await;
+    assert_eq!(olive.refusal().await, "#hideout isn't a private room");
+}
+
+#[tokio::test]
+async fn bans_need_the_permission_and_a_higher_rank() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    let (mut otto, _) = RawClient::connect(&server.addr, "otto").await;
+    otto.register().await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+
+    bob.send("/ban olive").await;
+    assert_eq!(bob.refusal().await, "You can't ban people");
+    olive.send("/ban otto").await;
+    assert_eq!(olive.refusal().await, "otto ranks as high as you, they can't be banned by you");
+    olive.send("/ban nobody").await;
+    assert_eq!(olive.refusal().await, "nobody isn't online, their address can be banned from the server console");
+}
+
+#[tokio::test]
+async fn bans_leave_this_machine_alone_and_shut_the_account_out() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+
+    // A guest on the same machine has no address or account that can be banned
+    let (_eve, _) = RawClient::connect(&server.addr, "eve").await;
+    olive.send("/ban eve").await;
+    assert_eq!(olive.refusal().await, "eve connects from this machine or your own address, /kick them instead");
+
+    // An account is banned by name, the loopback address it came from stays open
+    let (mut mallory, _) = RawClient::connect(&server.addr, "mallory").await;
+    mallory.register().await;
+    olive.send("/ban mallory spam").await;
+    olive.read_until(|msg| msg.content == "Banned mallory").await;
+    let banned = mallory.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!(banned.error, Some(ErrorKind::Banned));
+    while mallory.recv().await.is_some() {}
+
+    let (mut again, _) = RawClient::connect(&server.addr, "mallory").await;
+    again.send("/login mallory hunter22").await;
+    assert_eq!(again.refusal().await, "mallory is banned from this server");
+    // Nobody else on the machine was dropped
+    olive.send("/ping 1").await;
+    olive.read_until(|msg| msg.message_type == MessageType::Ping).await;
+}
+
+#[tokio::test]
+async fn mutes_silence_someone_until_they_run_out_or_are_lifted() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    bob.send("/mute olive 10m").await;
+    assert_eq!(bob.refusal().await, "You can't mute people");
+
+    olive.send("/mute bob 2s spam").await;
+    olive.read_until(|msg| msg.content == "bob is muted for 2s").await;
+    bob.read_until(|msg| msg.content == "You were muted for 2s by olive: spam").await;
+    bob.send("can anyone hear me").await;
+    let muted = bob.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!(muted.error, Some(ErrorKind::Muted));
+    assert!(muted.content.starts_with("You are muted for another "), "{}", muted.content);
+    assert!(muted.retry_after.is_some_and(|seconds| (1..=2).contains(&seconds)));
+
+    // Once it runs out they talk again, and the room never saw the muted line
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    bob.send("back").await;
+    assert_eq!(olive.next_chat().await.content, "back");
+
+    olive.send("/mute bob 10m").await;
+    olive.read_until(|msg| msg.content == "bob is muted for 10m 00s").await;
+    olive.send("/unmute bob").await;
+    olive.read_until(|msg| msg.content == "bob can talk again").await;
+    bob.read_until(|msg| msg.content == "olive lifted your mute, you can talk again").await;
+    bob.send("thanks").await;
+    assert_eq!(olive.next_chat().await.content, "thanks");
+    olive.send("/unmute bob").await;
+    assert_eq!(olive.refusal().await, "bob isn't muted");
+
+    olive.send("/auditlog bob").await;
+    let log = olive.read_until(|msg| msg.content.starts_with("Moderation of bob")).await.pop().unwrap().content;
+    let actions: Vec<&str> = log.lines().skip(1).map(|line| line.split_once(" olive ").unwrap().1).collect();
+    assert_eq!(actions, ["mute bob (2s: spam)", "mute bob (10m 00s)", "unmute bob"]);
+}
+
+#[tokio::test]
+async fn chaos_commands_drop_lag_and_partition_only_when_turned_on() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    olive.send("/chaos lag 100").await;
+    assert_eq!(olive.refusal().await, "Chaos commands are off, [chaos] enabled = true in the config turns them on (staging only)");
+
+    let mut config = owners_config();
+    config.chaos.enabled = true;
+    let server = start_server_with(config).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    bob.send("/chaos off").await;
+    assert_eq!(bob.refusal().await, "You can't use /chaos");
+
+    // Dropped without a goodbye, the way a dead network would
+    olive.send("/chaos drop-client bob").await;
+    olive.read_until(|msg| msg.content == "Chaos: olive dropped 1 connection of bob").await;
+    let mut goodbyes = Vec::new();
+    while let Some(msg) = bob.recv().await {
+        goodbyes.push(msg);
+    }
+    assert!(!goodbyes.iter().any(|msg| msg.error.is_some() || msg.content.contains("shutting down")));
+
+    olive.send("/chaos lag 300").await;
+    olive.read_until(|msg| msg.content.starts_with("Chaos: olive is holding back")).await;
+    let sent = Instant::now();
+    olive.send("slow").await;
+    olive.read_until(|msg| msg.content == "slow").await;
+    assert!(sent.elapsed() >= Duration::from_millis(300));
+    olive.send("/chaos off").await;
+    olive.read_until(|msg| msg.content == "Chaos: olive ended the lag and partition").await;
+    olive.send("/chaos off").await;
+    assert_eq!(olive.refusal().await, "No chaos going on");
+
+    // A partition hangs up on everyone and lets nobody back until it's over
+    olive.send("/chaos partition 1s").await;
+    while olive.recv().await.is_some() {}
+    let mut shut_out = RawClient::open(&server.addr, Handshake { username: "carol".to_string(), ..Default::default() }).await;
+    assert!(shut_out.recv().await.is_none());
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    RawClient::connect(&server.addr, "carol").await;
+}
+
+#[tokio::test]
+async fn moderation_is_logged_and_read_back_by_moderators_only() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    let (mut mallory, _) = RawClient::connect(&server.addr, "mallory").await;
+    mallory.register().await;
+
+    bob.send("/auditlog").await;
+    assert_eq!(bob.refusal().await, "You can't read the moderation log");
+
+    olive.send("/kick bob spam").await;
+    olive.read_until(|msg| msg.content == "Kicked bob").await;
+    olive.send("/ban mallory flooding").await;
+    olive.read_until(|msg| msg.content == "Banned mallory").await;
+    olive.send("/roommode lobby readonly").await;
+    olive.read_until(|msg| msg.content.contains("read-only")).await;
+
+    olive.send("/auditlog").await;
+    let log = olive.read_until(|msg| msg.content.starts_with("Recent moderation")).await.pop().unwrap().content;
+    let entries: Vec<&str> = log.lines().skip(1).collect();
+    assert_eq!(entries.len(), 3);
+    assert!(entries[0].ends_with(" olive kick bob (spam)"), "{}", log);
+    assert!(entries[1].ends_with(" olive ban mallory (account mallory: flooding)"), "{}", log);
+    assert!(entries[2].ends_with(" olive roommode #lobby (read-only)"), "{}", log);
+
+    // One user's entries only
+    olive.send("/auditlog BOB").await;
+    let log = olive.read_until(|msg| msg.content.starts_with("Moderation of BOB")).await.pop().unwrap().content;
+    assert_eq!(log.lines().count(), 2);
+    assert!(log.ends_with(" olive kick bob (spam)"), "{}", log);
+    olive.send("/auditlog nobody").await;
+    let log = olive.read_until(|msg| msg.content.starts_with("Moderation of nobody")).await.pop().unwrap().content;
+    assert!(log.ends_with("\nNothing on record"), "{}", log);
+}
+
+#[test]
+fn console_resets_are_logged_like_any_moderation() {
+    // The console's reset writes its entry through the same log, read back oldest first by target
+    let store = Store::open(":memory:").unwrap();
+    store.log_moderation("olive", "kick", "bob", None).unwrap();
+    store.log_moderation("(console)", "reset", "bob", None).unwrap();
+    store.log_moderation("olive", "kick", "carl", Some("spam")).unwrap();
+
+    let entries = store.moderation_log(Some("Bob"), 20).unwrap();
+    let actions: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.moderator.as_str(), entry.action.as_str())).collect();
+    assert_eq!(actions, [("olive", "kick"), ("(console)", "reset")]);
+    // Only the latest entries are read, still oldest first
+    let latest: Vec<String> = store.moderation_log(None, 2).unwrap().into_iter().map(|entry| entry.target).collect();
+    assert_eq!(latest, ["bob", "carl"]);
+}
+
+#[tokio::test]
+async fn failed_logins_are_held_off_per_connection_and_per_account() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+    let (mut mallory, _) = RawClient::connect(&server.addr, "mallory").await;
+
+    for _ in 0..5 {
+        mallory.send("/login alice guess123").await;
+        assert_eq!(mallory.refusal().await, "Wrong username or password");
+    }
+    mallory.send("/login alice guess123").await;
+    assert_eq!(mallory.refusal().await, "Too many failed logins, try again in 10 seconds");
+    // Held off now, the right password included and other accounts too
+    mallory.send("/login alice hunter22").await;
+    assert!(mallory.refusal().await.starts_with("Too many failed logins"));
+    mallory.send("/login carol hunter22").await;
+    assert!(mallory.refusal().await.starts_with("Too many failed logins"));
+    // And the account is held off from another connection
+    let (mut trudy, _) = RawClient::connect(&server.addr, "trudy").await;
+    trudy.send("/login alice hunter22").await;
+    assert!(trudy.refusal().await.starts_with("Too many failed logins"));
+}
+
+// RFC 6238 appendix B, SHA-1 with its 20 byte ASCII secret, cut down to 6 digits
+#[test]
+fn totp_codes_match_the_rfc_test_vectors() {
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"; // "12345678901234567890"
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+    for (time, code) in vectors {
+        assert_eq!(totp::verify(secret, code, time), Some(time / 30), "code at {}", time);
+    }
+}
+
+#[test]
+fn totp_codes_are_good_for_one_step_either_side() {
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    let time = 1111111109;
+    let step = time / 30;
+    assert_eq!(totp::verify(secret, "081804", time - 30), Some(step));
+    assert_eq!(totp::verify(secret, "081804", time + 30), Some(step));
+    assert_eq!(totp::verify(secret, "081804", time - 60), None);
+    assert_eq!(totp::verify(secret, "081804", time + 60), None);
+    assert_eq!(totp::verify(secret, "081805", time), None);
+    assert_eq!(totp::verify(secret, "08180", time), None);
+}
+
+#[tokio::test]
+async fn signatures_count_only_for_their_room_once_and_while_fresh() {
+    let server = start_server().await;
+    let signer = Signer::generate();
+    let handshake = Handshake { username: "luna".to_string(), signing_key: Some(signer.public_key()), ..Default::default() };
+    let (mut luna, _) = RawClient::connect_with(&server.addr, handshake).await;
+
+    let wire = signer.sign_line("lobby", "it's me");
+    luna.send(&wire).await;
+    assert!(!luna.next_chat().await.bad_signature);
+
+    // The same line again is a replay
+    luna.send(&wire).await;
+    assert!(luna.next_chat().await.bad_signature);
+    // Signed for another room
+    luna.send(&signer.sign_line("ops", "it's me")).await;
+    assert!(luna.next_chat().await.bad_signature);
+    // Signed ten minutes ago
+    let stale = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64 - 600_000;
+    luna.send(&format!("/sign {} {}\nit's me", stale, signer.sign(&signed_text("lobby", stale, "it's me")))).await;
+    assert!(luna.next_chat().await.bad_signature);
+
+    // A fresh signature still counts after all that
+    luna.send(&signer.sign_line("lobby", "still me")).await;
+    assert!(!luna.next_chat().await.bad_signature);
+    luna.send("/sign soon abc").await;
+    assert_eq!(luna.refusal().await, "Usage: /sign <stamp> <signature>");
+}
+
+#[tokio::test]
+async fn registered_names_carry_a_check_and_guests_cant_take_them() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("/register short").await;
+    assert_eq!(alice.refusal().await, "Passwords need at least 6 characters");
+    alice.send("unverified").await;
+    assert!(!alice.next_chat().await.verified);
+    alice.register().await;
+    alice.send("it's me").await;
+    assert!(alice.next_chat().await.verified);
+    alice.send("/register hunter22").await;
+    assert_eq!(alice.refusal().await, "You are already signed in");
+
+    // Someone else asking for the name is a guest under another one until they sign in
+    let (mut other, frames) = RawClient::connect(&server.addr, "alice").await;
+    let notice = frames.iter().find(|msg| msg.renamed_from.as_deref() == Some("alice")).unwrap();
+    assert!(notice.username.starts_with("alice_guest"), "{}", notice.username);
+    other.send("hi").await;
+    let said = other.read_until(|msg| msg.content == "hi").await.pop().unwrap();
+    assert_eq!((said.username == notice.username, said.verified), (true, false));
+    other.send("/login alice wrongpass").await;
+    assert_eq!(other.refusal().await, "Wrong username or password");
+    other.send("/login alice hunter22").await;
+    other.read_until(|msg| msg.content.starts_with("signed in (was alice_guest")).await;
+    other.send("me again").await;
+    let said = other.next_chat().await;
+    assert_eq!((said.username.as_str(), said.verified), ("alice", true));
+}
+
+#[tokio::test]
+async fn lookalike_guests_are_pointed_out_and_the_names_accounts_went_by_are_kept() {
+    let db = std::env::temp_dir().join(format!("server-test-names-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    let server = start_server_on(db.to_str().unwrap(), ServerOptions::default(), ServerConfig::default()).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+
+    // Names that could pass for the account are let in, and everyone is told they aren't her
+    for lookalike in ["a1ice", "аlice", "alicee"] {
+        let (mut guest, _) = RawClient::connect(&server.addr, lookalike).await;
+        let warning = format!("⚠️ {} is not signed in, and is not alice (only their messages carry a ✔)", lookalike);
+        assert_eq!(alice.read_until(|msg| msg.content.starts_with("⚠️")).await.pop().unwrap().content, warning);
+        assert!(guest.read_until(|msg| msg.content.starts_with("⚠️")).await.iter().any(|msg| msg.content == warning));
+    }
+    // Names that don't are not
+    RawClient::connect(&server.addr, "bob").await;
+    RawClient::connect(&server.addr, "carol").await;
+    let frames = alice.read_until(|msg| msg.username == "carol" && msg.content == "has landed").await;
+    assert!(frames.iter().all(|msg| !msg.content.starts_with("⚠️")), "{:?}", frames);
+
+    // Signing in from another name keeps that name, the guest name handed out for her own doesn't count
+    let (mut wonderland, _) = RawClient::connect(&server.addr, "wonderland").await;
+    wonderland.send("/login alice hunter22").await;
+    wonderland.read_until(|msg| msg.content == "signed in (was wonderland)").await;
+    let (mut again, _) = RawClient::connect(&server.addr, "alice").await;
+    again.send("/login alice hunter22").await;
+    again.read_until(|msg| msg.content.starts_with("signed in (was alice_guest")).await;
+    let names: Vec<String> = Store::open(db.to_str().unwrap()).unwrap().nicknames("alice").unwrap().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["wonderland"]);
+    server.shutdown.shutdown(0);
+    let _ = server.running.await;
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[tokio::test]
+async fn session_tokens_resume_the_account_with_what_was_missed() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("/register hunter22").await;
+    let token = alice.read_until(|msg| msg.message_type == MessageType::Session).await.pop().unwrap().content;
+    alice.send("before").await;
+    let last_seen = alice.next_chat().await.id;
+    drop(alice);
+
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    for text in ["missed 1", "missed 2"] {
+        bob.send(text).await;
+        bob.read_until(|msg| msg.content == text).await;
+    }
+
+    // Whatever name the handshake gives, the token says who this is
+    let handshake = Handshake { username: "someone".to_string(), session_token: Some(token), last_message_id: last_seen, ..Default::default() };
+    let (mut back, mut frames) = RawClient::connect_with(&server.addr, handshake).await;
+    if !frames.iter().any(|msg| msg.content == "missed 2") {
+        frames.extend(back.read_until(|msg| msg.content == "missed 2").await);
+    }
+    assert!(frames.iter().any(|msg| msg.content == "Welcome back alice, 2 new messages while you were away"));
+    assert_eq!(chat(&frames), ["missed 1", "missed 2"]);
+    back.send("back again").await;
+    let said = back.read_until(|msg| msg.content == "back again").await.pop().unwrap();
+    assert_eq!((said.username.as_str(), said.verified), ("alice", true));
+
+    // A token nobody issued is a fresh join under the name given
+    let handshake = Handshake { username: "mallory".to_string(), session_token: Some("forged".to_string()), last_message_id: last_seen, ..Default::default() };
+    let (mut mallory, frames) = RawClient::connect_with(&server.addr, handshake).await;
+    assert!(!frames.iter().any(|msg| msg.content.starts_with("Welcome back")));
+    mallory.send("hi").await;
+    let said = mallory.read_until(|msg| msg.content == "hi").await.pop().unwrap();
+    assert_eq!((said.username.as_str(), said.verified), ("mallory", false));
+}
+
+#[tokio::test]
+async fn whispers_to_offline_accounts_wait_in_their_mailbox() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    drop(alice);
+    bob.read_until(|msg| msg.username == "alice" && msg.content == "has blasted off").await;
+
+    bob.send("/msg alice see you tomorrow").await;
+    bob.read_until(|msg| msg.content == "alice is offline, they'll get it when they're back").await;
+    bob.send("/msg nobody hello?").await;
+    assert_eq!(bob.refusal().await, "nobody is not online");
+
+    // Handed over on signing in, under a header
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("/login alice hunter22").await;
+    let frames = alice.read_until(|msg| msg.message_type == MessageType::Whisper).await;
+    assert!(frames.iter().any(|msg| msg.content == "📬 1 offline message while you were away"));
+    let whisper = frames.last().unwrap();
+    assert_eq!((whisper.username.as_str(), whisper.content.as_str()), ("bob", "see you tomorrow"));
+
+    alice.send("/mailbox").await;
+    let listed = alice.read_until(|msg| msg.content.starts_with("📬 Mailbox")).await.pop().unwrap().content;
+    assert!(listed.starts_with("📬 Mailbox (1), /mailbox clear to empty it\n["), "{}", listed);
+    assert!(listed.ends_with("] bob: see you tomorrow"), "{}", listed);
+    alice.send("/mailbox clear").await;
+    alice.read_until(|msg| msg.content == "Threw away 1 message from your mailbox").await;
+    alice.send("/mailbox").await;
+    alice.read_until(|msg| msg.content == "Your mailbox is empty").await;
+    bob.send("/mailbox").await;
+    assert_eq!(bob.refusal().await, "Sign in to an account to have a mailbox");
+}
+
+#[tokio::test]
+async fn whois_shows_the_profile_and_what_they_are_up_to() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.send("/profile set bio Space pilot").await;
+    assert_eq!(alice.refusal().await, "Sign in to an account to have a profile");
+    alice.register().await;
+    alice.send("/profile set bio Space pilot").await;
+    alice.read_until(|msg| msg.content == "Your bio is now: Space pilot").await;
+    alice.send("/profile set pronouns she/her").await;
+    alice.read_until(|msg| msg.content == "Your pronouns are now: she/her").await;
+    alice.send("/join hangar").await;
+    alice.read_until(|msg| msg.message_type == MessageType::RoomJoined).await;
+
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    bob.send("/whois alice").await;
+    let whois = bob.read_until(|msg| msg.content.starts_with("👤")).await.pop().unwrap().content;
+    let lines: Vec<&str> = whois.lines().collect();
+    assert_eq!(lines[..2], ["👤 alice ✔ (she/her)", "   Space pilot"]);
+    assert!(lines[2].starts_with("   Online for ") && lines[2].contains(", idle for "), "{}", whois);
+    assert_eq!(lines[3], "   In #hangar, #lobby");
+
+    // Guests have no profile, only what they're up to
+    alice.send("/profile clear bio").await;
+    alice.read_until(|msg| msg.content == "Your bio is gone from your profile").await;
+    alice.send("/whois bob").await;
+    let whois = alice.read_until(|msg| msg.content.starts_with("👤 bob")).await.pop().unwrap().content;
+    assert_eq!(whois.lines().next(), Some("👤 bob"));
+    alice.send("/profile").await;
+    let own = alice.read_until(|msg| msg.content.starts_with("👤 alice")).await.pop().unwrap().content;
+    assert!(!own.contains("Space pilot"));
+    alice.send("/whois nobody").await;
+    assert_eq!(alice.refusal().await, "Nobody called nobody is online or has an account");
+}
+
+#[tokio::test]
+async fn seen_tells_when_and_how_an_account_left() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+    let (mut dave, _) = RawClient::connect(&server.addr, "dave").await;
+    dave.register().await;
+    let (guest, _) = RawClient::connect(&server.addr, "guest").await;
+
+    olive.send("/seen alice").await;
+    olive.read_until(|msg| msg.content == "alice is online right now").await;
+    drop(alice);
+    olive.read_until(|msg| msg.username == "alice" && msg.content == "has blasted off").await;
+    olive.send("/kick dave").await;
+    olive.read_until(|msg| msg.content == "Kicked dave").await;
+    while dave.recv().await.is_some() {}
+    // The last sighting is kept before the others are told they left
+    olive.read_until(|msg| msg.username == "dave" && msg.content == "was kicked").await;
+    drop(guest);
+    olive.read_until(|msg| msg.username == "guest" && msg.content == "has blasted off").await;
+
+    olive.send("/seen alice").await;
+    olive.read_until(|msg| msg.content == "alice was last seen just now: alice has blasted off").await;
+    olive.send("/seen DAVE").await;
+    olive.read_until(|msg| msg.content == "dave was last seen just now: dave was kicked").await;
+    olive.send("/whois alice").await;
+    let whois = olive.read_until(|msg| msg.content.starts_with("👤 alice")).await.pop().unwrap().content;
+    assert_eq!(whois, "👤 alice ✔\n   Offline, last seen just now: alice has blasted off");
+    // Guests leave nothing behind to look up
+    olive.send("/seen guest").await;
+    assert_eq!(olive.refusal().await, "Nobody called guest is online or has an account");
+}
+
+#[tokio::test]
+async fn passwords_are_stored_as_argon2_hashes() {
+    let hash = accounts::hash_password("hunter22".to_string()).await.unwrap();
+    assert!(hash.starts_with("$argon2id$"), "{}", hash);
+    assert!(!hash.contains("hunter22"));
+    assert!(accounts::verify_password("hunter22".to_string(), hash.clone()).await);
+    assert!(!accounts::verify_password("hunter23".to_string(), hash).await);
+}
+
+#[tokio::test]
+async fn passwords_change_only_with_the_old_one_and_the_change_is_logged() {
+    let server = start_server_with(owners_config()).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    olive.register().await;
+
+    olive.send("/passwd wrong-one swordfish").await;
+    assert_eq!(olive.refusal().await, "Wrong password");
+    olive.send("/passwd hunter22 swordfish").await;
+    olive.read_until(|msg| msg.content == "Password changed, your other sessions were signed out").await;
+
+    // Only the new password signs in now, the name olive joined as and then has are in the notices
+    let (mut again, joined) = RawClient::connect(&server.addr, "olive").await;
+    let guest = joined.iter().find(|msg| msg.renamed_from.as_deref() == Some("olive")).expect("no guest name notice");
+    assert!(guest.username.starts_with("olive_guest"));
+    again.send("/login olive hunter22").await;
+    assert_eq!(again.refusal().await, "Wrong username or password");
+    again.send("/login olive swordfish").await;
+    let signed_in = again.read_until(|msg| msg.content.starts_with("signed in (was ")).await.pop().unwrap();
+    assert_eq!((signed_in.username.as_str(), signed_in.renamed_from.as_ref()), ("olive", Some(&guest.username)));
+
+    olive.send("/auditlog olive").await;
+    let log = olive.read_until(|msg| msg.content.starts_with("Moderation of olive")).await.pop().unwrap();
+    assert!(log.content.contains(" olive passwd olive (1 session ended)"), "{}", log.content);
+}
+
+#[test]
+fn irc_lines_and_frames_translate_both_ways() {
+    assert_eq!(
+        irc::parse_line("@time=x :nick!u@h PRIVMSG #lobby :hello there"),
+        Some(("PRIVMSG".to_string(), vec!["#lobby".to_string(), "hello there".to_string()]))
+    );
+    let mut t = Translator::new("weechy");
+
+    // Nothing goes to a channel the server hasn't put us in
+    assert!(matches!(t.translate_line("PRIVMSG #lobby :hi"), Inbound::Reply(lines) if lines[0].contains(" 442 ")));
+    let joined = t.translate_frame(&ChatMessage::new("System", "You are talking in #lobby", MessageType::RoomJoined).in_room("lobby"));
+    assert_eq!(joined[0], ":weechy!weechy@retro-chat JOIN #lobby");
+    assert_eq!(t.translate_line("PRIVMSG #lobby :hi"), Inbound::Forward(vec!["hi".to_string()]));
+    assert_eq!(t.translate_line("PRIVMSG #lobby :\x01ACTION waves\x01"), Inbound::Forward(vec!["/me waves".to_string()]));
+    assert_eq!(t.translate_line("PRIVMSG alice :psst"), Inbound::Forward(vec!["/whisper alice psst".to_string()]));
+    assert_eq!(t.translate_line("JOIN #a,#b key"), Inbound::Forward(vec!["/join a key".to_string(), "/join b".to_string()]));
+    assert_eq!(t.translate_line("LOGIN alice hunter22"), Inbound::Forward(vec!["/login alice hunter22".to_string()]));
+    assert!(matches!(t.translate_line("FROB x"), Inbound::Reply(lines) if lines[0].contains(" 421 ")));
+    assert_eq!(t.translate_line("QUIT :bye"), Inbound::Quit);
+
+    // PING goes on to the server, which has to hear from us now and then, and its answer is the PONG
+    assert_eq!(t.translate_line("PING :1697467925"), Inbound::Forward(vec!["/ping 1697467925".to_string()]));
+    let pong = t.translate_frame(&ChatMessage::new("System", "1697467925", MessageType::Ping));
+    assert_eq!(pong, vec![":retro-chat PONG retro-chat :1697467925"]);
+
+    // Talking in another joined room switches to it first
+    t.translate_frame(&ChatMessage::new("System", "You are talking in #games", MessageType::RoomJoined).in_room("games"));
+    assert_eq!(t.translate_line("PRIVMSG #lobby :back"), Inbound::Forward(vec!["/join lobby".to_string(), "back".to_string()]));
+
+    // Others' lines come as PRIVMSG, one per line, our own echoes don't
+    let said = t.translate_frame(&ChatMessage::new("alice", "one\ntwo", MessageType::UserMessage).in_room("lobby"));
+    assert_eq!(said, vec![":alice!alice@retro-chat PRIVMSG #lobby :one", ":alice!alice@retro-chat PRIVMSG #lobby :two"]);
+    assert!(t.translate_frame(&ChatMessage::new("weechy", "hi", MessageType::UserMessage).in_room("lobby")).is_empty());
+    let came = t.translate_frame(&ChatMessage::new("alice", "joined #games", MessageType::SystemNotification).in_room("games"));
+    assert_eq!(came, vec![":alice!alice@retro-chat JOIN #games"]);
+
+    // Command lines and code fences in a channel aren't sent, they'd be run or swallow what comes after
+    assert!(matches!(t.translate_line("PRIVMSG #lobby :/kick alice"), Inbound::Reply(lines) if lines[0].contains("NOTICE weechy :Not sent")));
+    assert!(matches!(t.translate_line("PRIVMSG #lobby :```rust"), Inbound::Reply(lines) if lines[0].contains("NOTICE weechy :Not sent")));
+    assert_eq!(t.translate_line("PRIVMSG #lobby :/meh and ```inline```"), Inbound::Forward(vec!["/meh and ```inline```".to_string()]));
+
+    // Renamed to a guest for a registered nick, then back on /login, as the notices' renamed_from says
+    let rename = |from: &str, to: &str, text: &str| ChatMessage { renamed_from: Some(from.to_string()), ..ChatMessage::new(to, text, MessageType::SystemNotification) };
+    let renamed = t.translate_frame(&rename("weechy", "weechy_guest1234", "joined as a guest"));
+    assert_eq!(renamed[0], ":weechy!weechy@retro-chat NICK :weechy_guest1234");
+    // Someone else's rename is just a notice
+    let other = t.translate_frame(&rename("alice_guest1", "alice", "signed in"));
+    assert_eq!(other, vec![":retro-chat NOTICE weechy_guest1234 :alice signed in"]);
+    t.translate_frame(&rename("weechy_guest1234", "weechy", "signed in"));
+    assert_eq!(t.nick(), "weechy");
+}
+
+#[test]
+fn webhooks_relay_listed_rooms_and_read_slack_and_discord_bodies() {
+    let hook: OutboundWebhook = toml::from_str("url = \"https://example.com/hook\"").unwrap();
+    assert_eq!(hook.format, WebhookFormat::Slack);
+    assert!(hook.relays("lobby"));
+    assert!(!hook.relays("secret"));
+
+    let said = ChatMessage::new("alice", "a <b> & c", MessageType::UserMessage).in_room("lobby");
+    let slack = webhooks::payload(WebhookFormat::Slack, &said);
+    assert_eq!(slack["text"], "a &lt;b&gt; &amp; c");
+    assert_eq!(slack["username"], "alice (#lobby)");
+    let waved = ChatMessage::new("alice", "waves @everyone", MessageType::Action).in_room("lobby");
+    let discord = webhooks::payload(WebhookFormat::Discord, &waved);
+    assert_eq!(discord["content"], "*waves @everyone*");
+    assert_eq!(discord["allowed_mentions"]["parse"], serde_json::json!([]));
+
+    assert_eq!(webhooks::parse_payload(br#"{"text": "from slack"}"#), Ok((None, "from slack".to_string())));
+    assert_eq!(webhooks::parse_payload(br#"{"username": "ci", "content": "green"}"#), Ok((Some("ci".to_string()), "green".to_string())));
+    assert!(webhooks::parse_payload(br#"{"text": "   "}"#).is_err());
+    assert!(webhooks::parse_payload(b"not json").is_err());
+    assert_eq!(webhooks::poster_name(Some("CI Bot!")), "CIBot@webhook");
+    assert_eq!(webhooks::poster_name(None), "webhook");
+}
+
+// One HTTP request to the webhook endpoint, the status and body of the answer
+async fn http(addr: &str, request: &str) -> (u16, String) {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(request.as_bytes()).await.unwrap();
+    let mut answer = String::new();
+    tokio::time::timeout(PATIENCE, socket.read_to_string(&mut answer)).await.expect("no answer in time").unwrap();
+    let status = answer.split_whitespace().nth(1).and_then(|status| status.parse().ok()).expect("not an HTTP answer");
+    let body = answer.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+    (status, body)
+}
+
+fn post(target: &str, headers: &str, body: &str) -> String {
+    format!("POST {} HTTP/1.1\r\nHost: chat\r\n{}Content-Length: {}\r\n\r\n{}", target, headers, body.len(), body)
+}
+
+#[tokio::test]
+async fn webhook_posts_need_the_token_and_land_in_the_room() {
+    // A free port for the endpoint, it binds the address itself
+    let hooks = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let mut config = ServerConfig::default();
+    config.webhooks.listen = Some(hooks);
+    config.webhooks.token = Some("s3cret".to_string());
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let hooks = hooks.to_string();
+    let body = r#"{"username": "ci", "content": "green"}"#;
+
+    assert_eq!(http(&hooks, &post("/hooks/lobby", "", body)).await.0, 401);
+    assert_eq!(http(&hooks, &post("/hooks/lobby?token=s3cre7", "", body)).await.0, 401);
+    assert_eq!(http(&hooks, &post("/hooks/lobby", "Authorization: Bearer s3cre\r\n", body)).await.0, 401);
+    assert_eq!(http(&hooks, &post("/elsewhere?token=s3cret", "", body)).await.0, 404);
+    assert_eq!(http(&hooks, "GET /hooks/lobby?token=s3cret HTTP/1.1\r\n\r\n").await.0, 405);
+    assert_eq!(http(&hooks, "POST /hooks/lobby?token=s3cret HTTP/1.1\r\n\r\n").await.0, 411);
+    assert_eq!(http(&hooks, "POST /hooks/lobby?token=s3cret HTTP/1.1\r\nContent-Length: lots\r\n\r\n").await.0, 400);
+    assert_eq!(http(&hooks, &post("/hooks/lobby?token=s3cret", "", "not json")).await.0, 400);
+    assert_eq!(http(&hooks, &post("/hooks/nowhere?token=s3cret", "", body)).await, (404, "#nowhere isn't open".to_string()));
+
+    assert_eq!(http(&hooks, &post("/hooks/lobby", "Authorization: Bearer s3cret\r\n", body)).await, (200, "ok".to_string()));
+    let said = alice.next_chat().await;
+    assert_eq!((said.username.as_str(), said.content.as_str()), ("ci@webhook", "green"));
+    assert_eq!(said.room.as_deref(), Some("lobby"));
+}
+
+#[tokio::test]
+async fn webhook_posts_go_through_the_filter_and_stay_out_of_read_only_rooms() {
+    let hooks = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let mut config = owners_config();
+    config.webhooks.listen = Some(hooks);
+    config.webhooks.token = Some("s3cret".to_string());
+    config.filter.words.block = vec!["spoilers".to_string()];
+    config.filter.words.mask = vec!["darn".to_string()];
+    let server = start_server_with(config).await;
+    let (mut olive, _) = RawClient::connect(&server.addr, "olive").await;
+    let hooks = hooks.to_string();
+    let said = |text: &str| post("/hooks/lobby?token=s3cret", "", &format!(r#"{{"text": "{}"}}"#, text));
+
+    let refused = http(&hooks, &said("spoilers ahead")).await;
+    assert_eq!(refused, (403, "Your message was not sent: \"spoilers\" is not allowed here".to_string()));
+    assert_eq!(http(&hooks, &said("darn it")).await.0, 200);
+    assert_eq!(olive.next_chat().await.content, "**** it", "the blocked post went out");
+
+    olive.register().await;
+    olive.send("/roommode lobby readonly").await;
+    olive.read_until(|msg| msg.message_type == MessageType::RoomMode).await;
+    let refused = http(&hooks, &said("anyone there?")).await;
+    assert_eq!(refused, (403, "#lobby is read-only, only announcers can post there".to_string()));
+}
+
+#[test]
+fn room_rates_are_averaged_over_the_minute_window() {
+    let mut rooms = Rooms::new(Vec::new());
+    rooms.open("lobby", Vec::new());
+    rooms.open("quiet", Vec::new());
+    let _alice = rooms.join("lobby", 1).unwrap();
+    let _bob = rooms.join("lobby", 2).unwrap();
+    let _carol = rooms.join("quiet", 3).unwrap();
+
+    // Three frames of nine bytes (and a newline) to two members, all of it inside the window
+    for _ in 0..3 {
+        rooms.send("lobby", "123456789".to_string());
+    }
+    rooms.record_lag("lobby", 4);
+
+    let window = RATE_WINDOW.as_secs_f64();
+    assert_eq!(RATE_WINDOW, Duration::from_secs(60));
+    let stats = rooms.stats();
+    let names: Vec<&str> = stats.iter().map(|room| room.name.as_str()).collect();
+    assert_eq!(names, ["lobby", "quiet"], "busiest first");
+    let lobby = &stats[0];
+    assert_eq!(lobby.members, 2);
+    assert_eq!(lobby.messages_per_sec, 3.0 / window);
+    assert_eq!(lobby.bytes_per_sec, (3 * 10 * 2) as f64 / window);
+    assert_eq!((lobby.lag_events, lobby.missed), (1, 4));
+    assert_eq!(stats[1].messages_per_sec, 0.0);
+}
+
+async fn scrape(addr: &str, headers: &str) -> (u16, String) {
+    http(addr, &format!("GET /metrics HTTP/1.1\r\nHost: chat\r\n{}\r\n", headers)).await
+}
+
+#[tokio::test]
+async fn metrics_endpoint_reports_per_room_load() {
+    let metrics = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let mut config = ServerConfig::default();
+    config.metrics.listen = Some(metrics);
+    config.metrics.token = Some("s3cret".to_string());
+    let server = start_server_with(config).await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (_bob, _) = RawClient::connect(&server.addr, "bob").await;
+    alice.send("hello").await;
+    alice.next_chat().await;
+    let metrics = metrics.to_string();
+
+    assert_eq!(scrape(&metrics, "").await.0, 401);
+    assert_eq!(scrape(&metrics, "Authorization: Bearer s3cre7\r\n").await.0, 401);
+    assert_eq!(http(&metrics, "GET /elsewhere HTTP/1.1\r\n\r\n").await.0, 404);
+    assert_eq!(http(&metrics, "POST /metrics HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await.0, 405);
+
+    let (status, body) = scrape(&metrics, "Authorization: Bearer s3cret\r\n").await;
+    assert_eq!(status, 200);
+    let lines: Vec<&str> = body.lines().collect();
+    assert!(lines.contains(&"# TYPE retro_chat_room_members gauge"), "{}", body);
+    assert!(lines.contains(&"retro_chat_online 2"), "{}", body);
+    assert!(lines.contains(&"retro_chat_messages_total 1"), "{}", body);
+    assert!(lines.contains(&"retro_chat_room_members{room=\"lobby\"} 2"), "{}", body);
+    assert!(lines.contains(&"retro_chat_room_lag_events_total{room=\"lobby\"} 0"), "{}", body);
+    let rate = |name: &str| -> f64 {
+        let prefix = format!("retro_chat_{}{{room=\"lobby\"}} ", name);
+        lines.iter().find_map(|line| line.strip_prefix(prefix.as_str())).expect("no lobby sample").parse().unwrap()
+    };
+    assert!(rate("room_messages_per_second") > 0.0);
+    assert!(rate("room_bytes_per_second") > 0.0);
+}
+
+#[test]
+fn matrix_bridge_defaults_to_the_lobby_with_a_prefix() {
+    let config: MatrixConfig = toml::from_str(
+        "homeserver = \"https://matrix.example\"\nuser = \"@bridge:example\"\npassword = \"pw\"\nroom = \"#retro:example\"",
+    )
+    .unwrap();
+    assert_eq!(config.local_room, "lobby");
+    assert_eq!(config.prefix, "mx-");
+    assert!(matrix::start(None).unwrap().is_none());
+}
+
+#[test]
+fn matrix_bridge_passes_on_chat_in_its_room_only() {
+    let (bridge, mut outgoing) = MatrixBridge::new("lobby");
+    bridge.on_broadcast(&ChatMessage::new("alice", "hi matrix", MessageType::UserMessage).in_room("Lobby"));
+    bridge.on_broadcast(&ChatMessage::new("alice", "waves", MessageType::Action).in_room("lobby"));
+    bridge.on_broadcast(&ChatMessage::new("bob", "elsewhere", MessageType::UserMessage).in_room("games"));
+    bridge.on_broadcast(&ChatMessage::new("bob", "joined #lobby", MessageType::SystemNotification).in_room("lobby"));
+    bridge.on_broadcast(&ChatMessage::new("bob", "psst", MessageType::Whisper));
+
+    let passed: Vec<String> = std::iter::from_fn(|| outgoing.try_recv().ok()).map(|msg| matrix::matrix_text(&msg)).collect();
+    assert_eq!(passed, ["<alice> hi matrix", "* alice waves"]);
+}
+
+#[test]
+fn matrix_users_go_by_their_localpart_behind_the_prefix() {
+    let config: MatrixConfig = toml::from_str(
+        "homeserver = \"https://matrix.example\"\nuser = \"@bridge:example\"\npassword = \"pw\"\nroom = \"#retro:example\"\nprefix = \"matrix/\"",
+    )
+    .unwrap();
+    assert_eq!(matrix::local_name(&config.prefix, "alice"), "matrix/alice");
+    assert_eq!(matrix::local_name("mx-", "bob"), "mx-bob");
+}
+
+#[test]
+fn federation_links_carry_shared_rooms_once_with_their_origin() {
+    let alpha: FederationConfig = toml::from_str(
+        "name = \"alpha\"\n[[peers]]\nname = \"beta\"\ntoken = \"s3cret\"\nrooms = [\"#lobby\", \"retro\"]",
+    )
+    .unwrap();
+    let beta: FederationConfig = toml::from_str("name = \"beta\"\n[[peers]]\nname = \"alpha\"\ntoken = \"s3cret\"").unwrap();
+    let nameless: FederationConfig = toml::from_str("[[peers]]\nname = \"beta\"\ntoken = \"s3cret\"").unwrap();
+    assert!(Federation::new(&nameless).is_err());
+    assert!(Federation::new(&FederationConfig::default()).unwrap().is_none());
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let (alpha, _) = Federation::new(&alpha).unwrap().unwrap();
+        let (beta, mut arrivals) = Federation::new(&beta).unwrap().unwrap();
+        assert!(alpha.admits("BETA", "s3cret"));
+        assert!(!alpha.admits("beta", "s3cre7"));
+        assert!(!alpha.admits("gamma", "s3cret"));
+
+        let (near, far) = tokio::io::duplex(4096);
+        let (near_read, near_write) = tokio::io::split(near);
+        let (far_read, far_write) = tokio::io::split(far);
+        tokio::spawn(alpha.clone().serve("beta", tokio::io::BufReader::new(near_read), near_write, true));
+        tokio::spawn(beta.clone().serve("alpha", tokio::io::BufReader::new(far_read), far_write, false));
+        tokio::task::yield_now().await;
+
+        alpha.on_broadcast(&ChatMessage::new("bob", "not shared", MessageType::UserMessage).in_room("secret"));
+        let mut relayed = ChatMessage::new("carol@gamma", "relayed", MessageType::UserMessage).in_room("retro");
+        relayed.origin = Some("gamma".to_string());
+        alpha.on_broadcast(&relayed);
+        alpha.on_broadcast(&ChatMessage::new("alice", "hi beta", MessageType::UserMessage).in_room("lobby"));
+
+        let arrival = tokio::time::timeout(std::time::Duration::from_secs(5), arrivals.recv()).await.unwrap().unwrap();
+        assert_eq!(arrival.username, "alice@alpha");
+        assert_eq!(arrival.content, "hi beta");
+        assert_eq!(arrival.room.as_deref(), Some("lobby"));
+        assert_eq!(arrival.origin.as_deref(), Some("alpha"));
+        assert!(arrivals.try_recv().is_err());
+    });
+}
+
+#[test]
+fn tokens_match_only_in_full() {
+    assert!(server::same_token("s3cret", "s3cret"));
+    assert!(!server::same_token("s3cret", "s3cre7"));
+    assert!(!server::same_token("s3cret", "s3cre"));
+    assert!(!server::same_token("s3cret", "s3crets"));
+    assert!(!server::same_token("s3cret", ""));
+}
+
+#[test]
+fn cluster_merges_who_is_online_on_every_process() {
+    let config: RedisConfig = toml::from_str("url = \"redis://127.0.0.1/\"").unwrap();
+    assert_eq!(config.prefix, "retro-chat");
+    assert!(cluster::start(None).unwrap().is_none());
+
+    let member = |name: &str, verified| Member { username: name.to_string(), verified, dnd: false };
+    let here = Online { connections: 2, members: vec![member("bob", false), Member { dnd: true, ..member("Alice", false) }] };
+    let there = Online { connections: 1, members: vec![member("alice", true), member("carol", false)] };
+    let merged = Online::merge([here, there]);
+    assert_eq!(merged.connections, 3);
+    assert_eq!(merged.members, vec![Member { dnd: true, ..member("alice", true) }, member("bob", false), member("carol", false)]);
+}
+
+#[test]
+fn cluster_chat_is_posted_only_in_rooms_open_here() {
+    let mut rooms = Rooms::new(Vec::new());
+    rooms.open("lobby", Vec::new());
+    let mut lobby = rooms.join("lobby", 1).unwrap();
+
+    let said = |text: &str| ChatMessage::new("carol", text, MessageType::UserMessage);
+    assert!(rooms.post("lobby", said("from the other process"), 10));
+    assert!(!rooms.post("games", said("nobody here"), 10));
+    assert!(!rooms.exists("games"), "a room was opened for chat from elsewhere");
+
+    let frame: ChatMessage = serde_json::from_str(&lobby.try_recv().unwrap()).unwrap();
+    assert_eq!(frame.content, "from the other process");
+    assert!(lobby.try_recv().is_err());
+    assert_eq!(rooms.history("lobby").len(), 1);
+}
+
+#[tokio::test]
+async fn read_markers_are_only_kept_for_rooms_we_are_in() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+
+    alice.send("/read secret 5").await;
+    assert_eq!(alice.refusal().await, "You are not in #secret");
+}
+
+#[tokio::test]
+async fn drafts_are_only_kept_for_rooms_we_are_in() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    alice.register().await;
+
+    alice.send("/draft secret psst").await;
+    assert_eq!(alice.refusal().await, "You are not in #secret");
+
+    alice.send("/draft lobby hi").await;
+    let draft = alice.read_until(|msg| msg.message_type == MessageType::Draft).await.pop().unwrap();
+    assert_eq!(draft.room.as_deref(), Some("lobby"));
+    assert_eq!(draft.content, "hi");
+}
+
+// A connection whose far end is never read: the writer is stuck after the first few bytes
+fn stalled_outbound() -> (Outbound, tokio::io::DuplexStream) {
+    let (near, far) = tokio::io::duplex(64);
+    let tracer = Arc::new(WireTracer::new("unused-trace.log", false));
+    (Outbound::spawn(near, tracer, Arc::new(Chaos::default()), "stalled".to_string()), far)
+}
+
+#[tokio::test]
+async fn only_the_newest_user_count_and_roster_wait_for_a_slow_client() {
+    let (out, mut far) = stalled_outbound();
+    out.send(&ChatMessage::new("System", "a notice long enough to get the writer stuck on the pipe", MessageType::SystemNotification));
+    for count in 0..100 {
+        out.send(&ChatMessage::user_count(count));
+        out.send(&ChatMessage::roster(Vec::new()));
+    }
+    let read = tokio::spawn(async move {
+        let mut text = String::new();
+        far.read_to_string(&mut text).await.unwrap();
+        text
+    });
+    out.close().await;
+    let frames: Vec<ChatMessage> = read.await.unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let types: Vec<MessageType> = frames.iter().map(|msg| msg.message_type.clone()).collect();
+    assert_eq!(types, [MessageType::SystemNotification, MessageType::UserCount, MessageType::Roster]);
+    assert_eq!(frames[1].content, "99");
+}
+
+#[tokio::test]
+async fn a_client_that_never_reads_is_dropped_once_its_system_lane_fills() {
+    let (out, _far) = stalled_outbound();
+    // Counts and rosters replace each other, however many there are
+    for count in 0..SYSTEM_LANE_CAPACITY * 4 {
+        out.send(&ChatMessage::user_count(count));
+        out.send(&ChatMessage::roster(Vec::new()));
+    }
+    assert!(tokio::time::timeout(Duration::from_millis(200), out.closed()).await.is_err(), "dropped over user counts");
+
+    for _ in 0..=SYSTEM_LANE_CAPACITY + 2 {
+        out.send(&ChatMessage::new("bob", "has landed", MessageType::SystemNotification));
+    }
+    tokio::time::timeout(PATIENCE, out.closed()).await.expect("a client that doesn't read was kept");
+}
+
+#[tokio::test]
+async fn a_line_over_the_limit_gets_an_error_and_the_connection_dropped() {
+    let server = start_server().await;
+    let (mut alice, _) = RawClient::connect(&server.addr, "alice").await;
+    let (mut bob, _) = RawClient::connect(&server.addr, "bob").await;
+    // Exactly the limit with no end in sight, all of it read, so the server hangs up on a drained socket
+    let endless = "x".repeat(MAX_LINE_BYTES);
+    bob.writer.write_all(endless.as_bytes()).await.unwrap();
+
+    let refused = bob.read_until(|msg| msg.error.is_some()).await.pop().unwrap();
+    assert_eq!(refused.error, Some(ErrorKind::MessageTooLong));
+    assert_eq!(refused.content, "Lines are at most 32 KiB, disconnecting");
+    while bob.recv().await.is_some() {}
+    alice.read_until(|msg| msg.username == "bob" && msg.content == "was dropped for sending too much").await;
+
+    // Not even a handshake gets to be that long
+    let (reader, writer) = TcpStream::connect(&server.addr).await.unwrap().into_split();
+    let mut carol = RawClient { lines: BufReader::new(reader).lines(), writer };
+    carol.writer.write_all(endless.as_bytes()).await.unwrap();
+    assert_eq!(carol.refusal().await, "Lines are at most 32 KiB, disconnecting");
+    assert!(carol.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn a_connection_that_never_sends_a_handshake_is_dropped_and_frees_its_slot() {
+    let mut config = idle_config();
+    config.limits.max_clients = 1;
+    let server = start_server_with(config).await;
+    let mut silent = TcpStream::connect(&server.addr).await.unwrap();
+    let opened = Instant::now();
+
+    let mut refused = RawClient::open(&server.addr, Handshake { username: "alice".to_string(), ..Default::default() }).await;
+    assert_eq!(refused.recv().await.unwrap().error, Some(ErrorKind::ServerFull));
+
+    let mut rest = Vec::new();
+    tokio::time::timeout(PATIENCE, silent.read_to_end(&mut rest)).await.expect("still waiting for a handshake").unwrap();
+    assert!(rest.is_empty());
+    assert!(opened.elapsed() >= IDLE_TIMEOUT / 2, "dropped before its time");
+    RawClient::connect(&server.addr, "alice").await;
+}
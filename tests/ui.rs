@@ -0,0 +1,1938 @@
+// UI tests for the chat window
+// Cursive runs against a recording backend: key events go in through the normal event path,
+// and every frame it draws lands in an in-memory screen the tests can search.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Instant;
+
+use cursive::backend::Backend;
+use cursive::event::{Event, Key, MouseButton, MouseEvent};
+use cursive::theme::{BaseColor, BorderStyle, Color, ColorPair, Effect, PaletteColor};
+use cursive::views::EditView;
+use cursive::{Cursive, CursiveRunner, Vec2};
+use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+
+use retro_chat_project::client::chatlog::ChatLog;
+use retro_chat_project::client::connection::{check_addr, ChatClient};
+use retro_chat_project::client::e2e::E2e;
+use retro_chat_project::client::emoji::Emoji;
+use retro_chat_project::client::signing::Signer;
+use retro_chat_project::client::socks::Proxy;
+use retro_chat_project::client::ignore::IgnoreList;
+use retro_chat_project::client::keymap::{self, Action, Keymap};
+use retro_chat_project::client::links;
+use retro_chat_project::client::markup::Markup;
+use retro_chat_project::client::notify::Notifier;
+use retro_chat_project::client::reconnect::{ReconnectAction, ReconnectPolicy};
+use retro_chat_project::client::session::Sessions;
+use retro_chat_project::client::settings::{settings_file_in, Notifications, Profile, Settings, SettingsFile, Times};
+use retro_chat_project::client::themes;
+use retro_chat_project::client::ui::{self, Connection, ToastKind, Toasts, UnsentChoice};
+use retro_chat_project::client::writer::{Outgoing, Report, Writer};
+use retro_chat_project::protocol::{signed_text, ChatMessage, CommandInfo, ErrorKind, Handshake, Member, MessageType, OpsEvent, Quote, Reaction, RoomInfo};
+use retro_chat_project::trace::WireTracer;
+
+const SCREEN: Vec2 = Vec2 { x: 120, y: 70 };
+
+// What the backend has drawn: one string per cell
+struct Screen {
+    cells: Vec<Vec<String>>,
+    cursor: Vec2,
+}
+
+struct RecordingBackend {
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl Backend for RecordingBackend {
+    fn poll_event(&mut self) -> Option<Event> {
+        None
+    }
+
+    fn set_title(&mut self, _title: String) {}
+
+    fn refresh(&mut self) {}
+
+    fn has_colors(&self) -> bool {
+        true
+    }
+
+    fn screen_size(&self) -> Vec2 {
+        SCREEN
+    }
+
+    fn move_to(&self, pos: Vec2) {
+        self.screen.borrow_mut().cursor = pos;
+    }
+
+    fn print(&self, text: &str) {
+        let mut screen = self.screen.borrow_mut();
+        let Vec2 { x, y } = screen.cursor;
+        if y < SCREEN.y && x < SCREEN.x {
+            screen.cells[y][x] = text.to_string();
+        }
+        screen.cursor.x += 1;
+    }
+
+    fn clear(&self, _color: cursive::theme::Color) {
+        for row in self.screen.borrow_mut().cells.iter_mut() {
+            row.fill(" ".to_string());
+        }
+    }
+
+    fn set_color(&self, colors: ColorPair) -> ColorPair {
+        colors
+    }
+
+    fn set_effect(&self, _effect: Effect) {}
+
+    fn unset_effect(&self, _effect: Effect) {}
+
+    fn name(&self) -> &str {
+        "recording"
+    }
+}
+
+// The chat window plus everything it tried to send to the server
+struct Harness {
+    runner: CursiveRunner<Cursive>,
+    screen: Rc<RefCell<Screen>>,
+    sent: Arc<Mutex<Vec<String>>>,
+    ctx: ui::Context,
+}
+
+impl Harness {
+    fn new() -> Self {
+        Self::with(SettingsFile::default(), Keymap::default())
+    }
+
+    // One whose /set saves to `settings`
+    fn with_settings(settings: SettingsFile) -> Self {
+        Self::with(settings, Keymap::default())
+    }
+
+    fn with_keymap(keymap: Keymap) -> Self {
+        Self::with(SettingsFile::default(), keymap)
+    }
+
+    fn with(settings: SettingsFile, keymap: Keymap) -> Self {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let tracer = Arc::new(WireTracer::new(std::env::temp_dir().join("ui-test-wire.log"), false));
+
+        let mut emoji = Emoji::bundled();
+        emoji.insert("shipit", "🚢🇮🇹");
+        let emoji = Arc::new(emoji);
+        let ctx = ui::Context {
+            tracer,
+            emoji: emoji.clone(),
+            markup: Arc::new(Markup::new()),
+            ignore: Arc::new(IgnoreList::new([], true)),
+            // No desktop notifications, they'd go to the terminal running the tests
+            notifier: Arc::new(Notifier::new("Tester", &Notifications { desktop: false, ..Notifications::default() })),
+            settings,
+            keymap: Arc::new(keymap),
+            server_commands: Arc::default(),
+            chat_log: Arc::new(ChatLog::new(std::env::temp_dir().join(format!("ui-test-logs-{}", std::process::id())), false)),
+        };
+
+        let mut siv = Cursive::new();
+        ui::build(&mut siv, "Tester", &ctx, {
+            let sent = sent.clone();
+            let ctx = ctx.clone();
+            move |s, text| {
+                if let Some(line) = ui::submit(s, text, &ctx) {
+                    sent.lock().unwrap().push(line);
+                }
+            }
+        });
+
+        let screen = Rc::new(RefCell::new(Screen {
+            cells: vec![vec![" ".to_string(); SCREEN.x]; SCREEN.y],
+            cursor: Vec2::zero(),
+        }));
+        let backend = RecordingBackend { screen: screen.clone() };
+        let mut harness = Harness { runner: siv.into_runner(Box::new(backend)), screen, sent, ctx };
+        harness.render();
+        harness
+    }
+
+    fn render(&mut self) {
+        while self.runner.process_events() {}
+        self.runner.refresh();
+    }
+
+    fn press(&mut self, event: impl Into<Event>) {
+        self.runner.on_event(event.into());
+        self.render();
+    }
+
+    fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            self.runner.on_event(Event::Char(c));
+        }
+        self.render();
+    }
+
+    fn submit(&mut self, text: &str) {
+        self.type_text(text);
+        self.press(Key::Enter);
+    }
+
+    fn show(&mut self, msg: ChatMessage) {
+        ui::show_message(&mut self.runner, &msg, &self.ctx);
+        self.render();
+    }
+
+    fn screen_text(&self) -> String {
+        let screen = self.screen.borrow();
+        screen.cells.iter().map(|row| row.concat() + "\n").collect()
+    }
+
+    fn shows(&self, text: &str) -> bool {
+        self.screen_text().contains(text)
+    }
+
+    // Where on screen `text` starts, in cells
+    fn find(&self, text: &str) -> Option<Vec2> {
+        let screen = self.screen.borrow();
+        screen.cells.iter().enumerate().find_map(|(y, row)| {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            (0..cells.len()).find(|&x| cells[x..].concat().starts_with(text)).map(|x| Vec2::new(x, y))
+        })
+    }
+
+    fn click(&mut self, position: Vec2) {
+        for event in [MouseEvent::Press(MouseButton::Left), MouseEvent::Release(MouseButton::Left)] {
+            self.runner.on_event(Event::Mouse { offset: Vec2::zero(), position, event });
+        }
+        self.render();
+    }
+
+    fn input(&mut self) -> String {
+        self.runner
+            .call_on_name(ui::INPUT, |view: &mut EditView| view.get_content().to_string())
+            .unwrap_or_default()
+    }
+
+    // Moves the toasts' clock forward past their timeout
+    fn expire_toasts(&mut self) {
+        self.runner.call_on_name(ui::TOASTS, |view: &mut Toasts| view.expire(Instant::now() + ui::TOAST_TIMEOUT));
+        self.render();
+    }
+
+    fn sent(&self) -> Vec<String> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn layout_shows_header_and_panels() {
+    let h = Harness::new();
+    assert!(h.shows("Chatterer: Tester"));
+    assert!(h.shows("Chattering"));
+    assert!(h.shows("Chit Chat"));
+}
+
+#[test]
+fn typed_text_appears_in_input() {
+    let mut h = Harness::new();
+    h.type_text("hello there");
+    assert!(h.shows("hello there"));
+    assert_eq!(h.input(), "hello there");
+}
+
+#[test]
+fn submitting_sends_and_clears_input() {
+    let mut h = Harness::new();
+    h.submit("hi :)");
+    assert_eq!(h.sent(), vec!["hi 😊".to_string()]);
+    assert_eq!(h.input(), "");
+}
+
+#[test]
+fn shortcodes_are_expanded() {
+    let mut h = Harness::new();
+    h.submit(":rocket: launch :) :shipit: :nope: 10:30");
+    assert_eq!(h.sent(), vec!["🚀 launch 😊 🚢🇮🇹 :nope: 10:30".to_string()]);
+}
+
+#[test]
+fn only_standalone_emoticons_are_replaced() {
+    let mut h = Harness::new();
+    h.submit("wtfoo well... ok :) wtf ... at 10:x:30 :x:");
+    assert_eq!(h.sent(), vec!["wtfoo well... ok 😊 🤬 😶 at 10:x:30 ❌".to_string()]);
+}
+
+#[test]
+fn emojify_can_be_switched_off() {
+    let mut h = Harness::new();
+    h.submit("/set emojify off");
+    assert!(h.shows("Emoji substitution off"));
+    h.submit("hi :) :rocket:");
+    h.submit("/set emojify on");
+    h.submit("hi :)");
+    assert_eq!(h.sent(), vec!["hi :) :rocket:".to_string(), "hi 😊".to_string()]);
+}
+
+#[test]
+fn set_applies_right_away_and_saves_to_config_toml() {
+    let file = std::env::temp_dir().join(format!("ui-test-config-{}.toml", std::process::id()));
+    std::fs::write(&file, "# mine\nemojify = true\nignore = [\"spammer\"]\n").unwrap();
+    let mut h = Harness::with_settings(SettingsFile::at(file.clone()));
+    h.submit("/set emojify off");
+    h.submit("/set bell on");
+    h.submit("/set mentions off");
+    h.submit("/set times absolute");
+    h.submit("/set username zorg");
+    h.submit("/set server chat.example.org:8082");
+    h.submit("/set theme space");
+    assert!(h.shows("Signing in as zorg from the next start"));
+    assert!(!h.ctx.emoji.is_enabled());
+    assert!(h.ctx.notifier.bell() && !h.ctx.notifier.mentions());
+    assert!(h.ctx.markup.absolute_times());
+
+    let saved: Settings = toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(!saved.emojify);
+    assert_eq!(saved.ignore, ["spammer"]);
+    assert!(saved.notifications.bell && !saved.notifications.mentions);
+    assert_eq!(saved.times, Times::Absolute);
+    assert_eq!(saved.username.as_deref(), Some("zorg"));
+    assert_eq!(saved.server.as_deref(), Some("chat.example.org:8082"));
+    assert_eq!(saved.theme, "space");
+
+    h.submit("/set theme plaid");
+    assert!(h.shows("no theme called \"plaid\""));
+    h.submit("/set bell maybe");
+    assert!(h.shows("Usage: /set"));
+}
+
+#[test]
+fn an_old_client_toml_is_moved_to_config_toml_once() {
+    let dir = std::env::temp_dir().join(format!("ui-test-settings-file-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(settings_file_in(&dir), dir.join("config.toml"));
+
+    std::fs::write(dir.join("client.toml"), "emojify = false\n").unwrap();
+    assert_eq!(settings_file_in(&dir), dir.join("config.toml"));
+    assert_eq!(std::fs::read_to_string(dir.join("config.toml")).unwrap(), "emojify = false\n");
+    assert!(!dir.join("client.toml").exists());
+
+    // Once there's a config.toml it's the one used, a client.toml turning up later is left alone
+    std::fs::write(dir.join("client.toml"), "emojify = true\n").unwrap();
+    assert_eq!(settings_file_in(&dir), dir.join("config.toml"));
+    assert_eq!(std::fs::read_to_string(dir.join("config.toml")).unwrap(), "emojify = false\n");
+    assert_eq!(std::fs::read_to_string(dir.join("client.toml")).unwrap(), "emojify = true\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn themes_are_built_in_or_read_from_theme_files() {
+    for name in themes::BUILT_IN {
+        assert!(themes::load(name).is_ok(), "{} should load", name);
+    }
+    assert!(themes::available().starts_with(&themes::BUILT_IN.map(String::from)));
+    assert!(themes::load("../client").is_err());
+
+    let dusk = themes::parse("base = \"solarized\"\nborders = \"outset\"\n[palette]\nbackground = \"#1d1f21\"\nprimary = \"light cyan\"\n").unwrap();
+    assert_eq!(dusk.palette[PaletteColor::Background], Color::Rgb(0x1d, 0x1f, 0x21));
+    assert_eq!(dusk.palette[PaletteColor::Primary], Color::Light(BaseColor::Cyan));
+    assert_eq!(dusk.palette[PaletteColor::View], themes::built_in("solarized").unwrap().palette[PaletteColor::View]);
+    assert_eq!(dusk.borders, BorderStyle::Outset);
+    assert!(themes::parse("[palette]\nprimary = \"plaid\"\n").unwrap_err().contains("palette.primary"));
+    assert!(themes::parse("[palette]\nwallpaper = \"red\"\n").unwrap_err().contains("isn't part of the palette"));
+    assert!(themes::parse("base = \"tartan\"\n").is_err());
+
+    let mut h = Harness::new();
+    h.submit("/theme");
+    assert!(h.shows("Themes: space, light, high-contrast, solarized, monochrome"));
+    h.submit("/theme light");
+    assert!(h.shows("Theme light"));
+    assert_eq!(h.runner.current_theme().palette[PaletteColor::View], Color::Rgb(250, 250, 250));
+}
+
+#[test]
+fn settings_panel_applies_and_saves_each_change() {
+    let file = std::env::temp_dir().join(format!("ui-test-panel-{}.toml", std::process::id()));
+    let mut h = Harness::with_settings(SettingsFile::at(file.clone()));
+    h.type_text("half a thought");
+    h.press(Key::F10);
+    assert!(h.shows("Settings"));
+    assert!(h.shows("Emoji substitution"));
+    assert!(h.shows("relative"));
+    assert!(h.shows("space"));
+
+    h.press(Key::Enter); // emoji off
+    h.press(Key::Down);
+    h.press(Key::Down);
+    h.press(Key::Enter); // bell on
+    h.press(Key::Down); // past desktop notifications
+    h.press(Key::Down); // and the chat log
+    h.press(Key::Down);
+    h.press(Key::Enter); // timestamps...
+    h.press(Key::Down);
+    h.press(Key::Enter); // ...absolute
+    h.press(Key::Down);
+    h.press(Key::Enter); // theme...
+    h.press(Key::Down);
+    h.press(Key::Enter); // ...light
+    assert!(!h.ctx.emoji.is_enabled());
+    assert!(h.ctx.notifier.bell() && h.ctx.notifier.mentions());
+    assert!(h.ctx.markup.absolute_times());
+    assert_eq!(h.runner.current_theme().palette[PaletteColor::View], Color::Rgb(250, 250, 250));
+
+    let saved: Settings = toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(!saved.emojify && saved.notifications.bell);
+    assert_eq!((saved.times, saved.theme.as_str()), (Times::Absolute, "light"));
+
+    h.press(Key::Esc);
+    assert!(!h.shows("Emoji substitution"));
+    assert_eq!(h.input(), "half a thought");
+}
+
+#[test]
+fn log_appends_what_is_shown_to_a_file_a_day() {
+    let mut h = Harness::new();
+    let dir = h.ctx.chat_log.dir().to_path_buf();
+    let _ = std::fs::remove_dir_all(&dir);
+    let at = |stamp: &str| {
+        let local = chrono::DateTime::parse_from_rfc3339(stamp).unwrap().with_timezone(&chrono::Local);
+        (local.format("%Y-%m-%d").to_string(), local.format("%Y-%m-%d %H:%M:%S").to_string())
+    };
+    let said = |who: &str, text: &str, stamp: &str, kind| ChatMessage { timestamp: stamp.to_string(), ..ChatMessage::new(who, text, kind).in_room("lobby") };
+
+    h.show(said("Zorg", "before the log", "2026-10-16T12:00:00Z", MessageType::UserMessage));
+    h.submit("/log on");
+    assert!(h.shows("Chat log on"));
+    h.show(said("Zorg", "hello\nthere", "2026-10-16T12:00:05Z", MessageType::UserMessage));
+    h.show(said("Luna", "waves", "2026-10-16T12:00:09Z", MessageType::Action));
+    h.show(ChatMessage { timestamp: "2026-10-17T12:00:00Z".to_string(), ..ChatMessage::new("System", "Server restarting", MessageType::SystemNotification) });
+    h.show(ChatMessage::new("System", "otpauth://totp/secret", MessageType::TwoFactorSetup));
+    h.submit("/log off");
+    h.show(said("Zorg", "after the log", "2026-10-17T12:00:05Z", MessageType::UserMessage));
+
+    let (day, hello) = at("2026-10-16T12:00:05Z");
+    let (_, waves) = at("2026-10-16T12:00:09Z");
+    let (next_day, restart) = at("2026-10-17T12:00:00Z");
+    let read = |day: &str| std::fs::read_to_string(dir.join(format!("{}.log", day))).unwrap();
+    assert_eq!(read(&day), format!("[{}] #lobby <Zorg> hello\n    there\n[{}] #lobby * Luna waves\n", hello, waves));
+    assert_eq!(read(&next_day), format!("[{}] -- System: Server restarting\n", restart));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn purge_local_forgets_a_rooms_log_unsent_lines_and_draft_once_confirmed() {
+    let mut h = Harness::new();
+    let dir = std::env::temp_dir().join(format!("ui-test-purge-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let log = Arc::new(ChatLog::new(&dir, true));
+    let said = |room: &str, text: &str, stamp: &str| ChatMessage { timestamp: stamp.to_string(), ..ChatMessage::new("Zorg", text, MessageType::UserMessage).in_room(room) };
+    log.record(&said("lobby", "hello\nthere", "2026-10-16T12:00:00Z")).unwrap();
+    log.record(&said("lobby-old", "not this one", "2026-10-16T12:00:03Z")).unwrap();
+    log.record(&said("dev", "about that bug", "2026-10-16T12:00:05Z")).unwrap();
+    log.record(&said("lobby", "see you", "2026-10-18T12:00:00Z")).unwrap();
+    let days: Vec<(String, String)> = ["2026-10-16T12:00:03Z", "2026-10-16T12:00:05Z", "2026-10-18T12:00:00Z"]
+        .iter()
+        .map(|stamp| {
+            let local = chrono::DateTime::parse_from_rfc3339(stamp).unwrap().with_timezone(&chrono::Local);
+            (local.format("%Y-%m-%d").to_string(), local.format("%Y-%m-%d %H:%M:%S").to_string())
+        })
+        .collect();
+    let read = |day: &str| std::fs::read_to_string(dir.join(format!("{}.log", day))).unwrap();
+    let before = read(&days[0].0);
+
+    // The client carries it out, the room is the one we talk in unless named
+    h.submit("/purge-local");
+    assert_eq!(h.sent(), ["/purge-local"]);
+    assert_eq!(ui::purge_local("/purge-local"), Some(""));
+    assert_eq!(ui::purge_local("/purge-local #dev "), Some("dev"));
+    assert_eq!(ui::purge_local("/purge-locally"), None);
+
+    h.type_text("half a thought");
+    let ctx = ui::Context { chat_log: log.clone(), ..h.ctx.clone() };
+    let purged = Arc::new(Mutex::new(Vec::new()));
+    let outbox = |purged: &Arc<Mutex<Vec<String>>>| {
+        let purged = purged.clone();
+        move |room: &str| {
+            purged.lock().unwrap().push(room.to_string());
+            Ok(2)
+        }
+    };
+
+    // Cancel is the one picked unless we move to Delete
+    ui::confirm_purge(&mut h.runner, "", &ctx, outbox(&purged));
+    h.render();
+    assert!(h.shows("Purge local history") && h.shows("#lobby"));
+    h.press(Key::Enter);
+    assert!(!h.shows("Purge local history"));
+    assert!(purged.lock().unwrap().is_empty());
+    assert_eq!(read(&days[0].0), before);
+    assert_eq!(h.input(), "half a thought");
+
+    ui::confirm_purge(&mut h.runner, "", &ctx, outbox(&purged));
+    h.render();
+    h.press(Key::Right);
+    h.press(Key::Enter);
+    assert!(h.shows("Forgot #lobby here: 2 logged messages"));
+    assert_eq!(*purged.lock().unwrap(), ["lobby"]);
+    assert_eq!(read(&days[0].0), format!("[{}] #lobby-old <Zorg> not this one\n[{}] #dev <Zorg> about that bug\n", days[0].1, days[1].1));
+    assert_eq!(read(&days[2].0), "");
+
+    // The draft goes, on our other devices too
+    assert_eq!(h.input(), "");
+    assert_eq!(ui::due_drafts(&mut h.runner, Instant::now() + ui::DRAFT_DEBOUNCE), ["/draft lobby "]);
+
+    // Logging carries on in the files afterwards
+    log.record(&said("lobby", "back again", "2026-10-18T12:00:00Z")).unwrap();
+    assert_eq!(read(&days[2].0), format!("[{}] #lobby <Zorg> back again\n", days[2].1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn export_writes_the_message_view_as_text_json_or_html() {
+    let mut h = Harness::new();
+    let dir = std::env::temp_dir().join(format!("ui-test-export-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = |name: &str| dir.join(name).display().to_string();
+
+    h.submit(&format!("/export {}", file("empty.txt")));
+    assert!(h.shows("Nothing to export"));
+    h.show(ChatMessage::new("Zorg", "ship it <now> & *loudly*", MessageType::UserMessage));
+    h.show(ChatMessage::new("Luna", "waves", MessageType::Action));
+    h.show(ChatMessage::new("System", "Server restarting soon", MessageType::SystemNotification));
+
+    h.submit(&format!("/export {}", file("chat.txt")));
+    assert!(h.shows("Exported 2 messages to"));
+    let text = std::fs::read_to_string(dir.join("chat.txt")).unwrap();
+    assert!(text.contains("Zorg --> ship it <now> & loudly") && text.contains("* Luna waves") && text.contains("Server restarting soon"), "{}", text);
+
+    h.submit(&format!("/export {} --format json", file("chat.data")));
+    let messages: Vec<ChatMessage> = serde_json::from_str(&std::fs::read_to_string(dir.join("chat.data")).unwrap()).unwrap();
+    assert_eq!(messages.iter().map(|msg| msg.username.as_str()).collect::<Vec<_>>(), ["Zorg", "Luna"]);
+
+    h.submit(&format!("/export {}", file("chat.html")));
+    let page = std::fs::read_to_string(dir.join("chat.html")).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>") && page.contains("background:#141030"), "the theme's colours: {}", page);
+    assert!(page.contains("<span style=\"color:#ff5555\">Zorg</span>"), "in their colour: {}", page);
+    assert!(page.contains("ship it &lt;now&gt; &amp; "));
+    assert!(page.contains("font-weight:bold\">loudly</span>"));
+
+    h.submit("/export --format pdf");
+    assert!(h.shows("\"pdf\" isn't txt, json or html"));
+    assert!(h.sent().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keys_come_from_the_keymap_and_keys_lists_them() {
+    let config = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let keymap = Keymap::new(&config(&[("quit", "ctrl+q"), ("next_tab", "Ctrl+PageDown"), ("focus_input", "none")])).unwrap();
+    assert_eq!(keymap.key(Action::Quit), Some(&Event::CtrlChar('q')));
+    assert_eq!(keymap.key(Action::NextTab), Some(&Event::Ctrl(Key::PageDown)));
+    assert_eq!(keymap.key(Action::FocusInput), None);
+    assert_eq!(keymap.key(Action::EmojiPicker), Some(&Event::CtrlChar('e')));
+    assert!(Keymap::new(&config(&[("launch", "f5")])).unwrap_err().contains("keys.launch"));
+    assert!(Keymap::new(&config(&[("quit", "hyper+q")])).is_err());
+    assert!(Keymap::new(&config(&[("quit", "ctrl+e")])).unwrap_err().contains("bound to both"));
+
+    assert_eq!(keymap::parse_key("alt+right"), Ok(Some(Event::Alt(Key::Right))));
+    assert_eq!(keymap::parse_key("F5"), Ok(Some(Event::Key(Key::F5))));
+    assert_eq!(keymap::parse_key("Q"), Ok(Some(Event::Char('Q'))));
+    assert_eq!(keymap::parse_key("+"), Ok(Some(Event::Char('+'))));
+    assert_eq!(keymap::parse_key("shift+tab"), Ok(Some(Event::Shift(Key::Tab))));
+    assert!(keymap::parse_key("f13").is_err());
+    assert_eq!(keymap::key_name(&Event::CtrlChar('e')), "Ctrl+E");
+
+    let mut h = Harness::with_keymap(keymap);
+    h.submit("/keys");
+    assert!(h.shows("Ctrl+Q        Quit"));
+    assert!(h.shows("(none)        Back to the input box"));
+    assert!(h.shows("Alt+1..9"));
+    h.press(Key::Esc); // closes /keys
+    assert!(!h.shows("Ctrl+Q"));
+    h.press(Key::Esc); // goes to navigation mode, it quits no more
+    assert!(h.runner.is_running());
+    h.press('i');
+
+    h.show(ChatMessage::new("System", "You are talking in #dev", MessageType::RoomJoined).in_room("dev"));
+    h.press(Event::Ctrl(Key::PageDown));
+    assert_eq!(h.sent(), ["/join lobby"]);
+    h.press(Event::Alt(Key::Left)); // prev_tab kept its default
+    assert_eq!(h.sent(), ["/join lobby", "/join dev"]);
+    h.press(Event::CtrlChar('q'));
+    assert!(!h.runner.is_running());
+}
+
+#[test]
+fn mentions_and_whispers_call_on_us() {
+    let notifier = Notifier::new("Tester", &Notifications::default());
+    let said = |who: &str, text: &str| ChatMessage::new(who, text, MessageType::UserMessage);
+    assert!(notifier.wants_us(&said("Zorg", "hey @tester, look")));
+    assert!(notifier.wants_us(&said("Zorg", "Tester: you there?")));
+    assert!(!notifier.wants_us(&said("Zorg", "the testers are in")));
+    assert!(!notifier.wants_us(&said("Tester", "talking about Tester")));
+    assert!(notifier.wants_us(&ChatMessage::new("Zorg", "psst", MessageType::Whisper)));
+    let mut replayed = said("Zorg", "hi tester");
+    replayed.timestamp = "2025-03-01T22:15:00Z".to_string();
+    assert!(!notifier.wants_us(&replayed));
+    notifier.set_name("zorg");
+    assert!(!notifier.wants_us(&said("Luna", "hi tester")));
+    notifier.set_mentions(false);
+    assert!(!notifier.wants_us(&said("Luna", "hi zorg")));
+
+    // Where the room isn't up, a toast says so
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("retro"));
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("lobby"));
+    h.show(said("Zorg", "tester, over here").in_room("retro"));
+    assert!(h.shows("Zorg mentioned you in #retro"));
+}
+
+#[test]
+fn notify_keywords_call_on_us_are_marked_and_kept_in_config_toml() {
+    let file = std::env::temp_dir().join(format!("ui-test-keywords-{}.toml", std::process::id()));
+    let mut h = Harness::with_settings(SettingsFile::at(file.clone()));
+    let said = |who: &str, text: &str| ChatMessage::new(who, text, MessageType::UserMessage);
+    h.submit("/notify");
+    assert!(h.shows("No keywords"));
+    h.submit("/notify add Deploy");
+    assert!(h.shows("Watching for \"deploy\""));
+    h.submit("/notify add outage");
+    h.submit("/notify off outage");
+    h.submit("/notify on nothing");
+    assert!(h.shows("\"nothing\" isn't a keyword"));
+    h.expire_toasts();
+    h.submit("/notify");
+    assert!(h.shows("Keywords: deploy, outage (off)"));
+    let saved: Settings = toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(saved.notifications.keywords, [("deploy".to_string(), true), ("outage".to_string(), false)].into());
+
+    assert_eq!(h.ctx.notifier.keywords_in(&said("Zorg", "DEPLOYING now, no outage")), ["deploy"]);
+    assert!(h.ctx.notifier.keywords_in(&said("Tester", "deploy")).is_empty(), "not when we say it");
+
+    // Where the room isn't up a toast says so, and in the view the keyword stands out
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("retro"));
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("lobby"));
+    h.show(said("Zorg", "deploy went out").in_room("retro"));
+    assert!(h.shows("Zorg said \"deploy\" in #retro"));
+    h.show(said("Luna", "the Deploy is green").in_room("lobby"));
+    let page = std::env::temp_dir().join(format!("ui-test-keywords-{}.html", std::process::id()));
+    h.submit(&format!("/export {}", page.display()));
+    let html = std::fs::read_to_string(&page).unwrap();
+    std::fs::remove_file(&page).unwrap();
+    assert!(html.contains("the <span style=\"color:#000000;background:#ffff55;font-weight:bold\">Deploy</span> is green"), "{}", html);
+
+    h.submit("/notify remove deploy");
+    let saved: Settings = toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(saved.notifications.keywords, [("outage".to_string(), false)].into());
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn dnd_keeps_quiet_holds_whispers_and_sums_them_up_after() {
+    let mut h = Harness::new();
+    let said = |who: &str, text: &str| ChatMessage::new(who, text, MessageType::UserMessage);
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("retro"));
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("lobby"));
+    h.submit("/dnd");
+    assert!(h.ctx.notifier.dnd());
+    assert!(h.shows(" DND "));
+    h.expire_toasts();
+
+    h.show(said("Zorg", "tester, over here").in_room("retro"));
+    assert!(!h.shows("Zorg mentioned you"), "no toasts");
+    h.show(ChatMessage { recipient: Some("Tester".to_string()), ..ChatMessage::new("Luna", "meet at the airlock", MessageType::Whisper) });
+    h.show(ChatMessage { recipient: Some("Luna".to_string()), ..ChatMessage::new("Tester", "on my way", MessageType::Whisper) });
+    assert!(!h.shows("meet at the airlock"));
+    assert!(h.shows("on my way"), "our own aren't held");
+    assert!(h.shows("1 whisper waiting"));
+    h.show(ChatMessage::roster(vec![Member { username: "Tester".to_string(), verified: false, dnd: true }]));
+    h.press(Key::F2);
+    assert!(h.shows("⛔"));
+
+    h.submit("/dnd");
+    assert!(!h.ctx.notifier.dnd());
+    assert!(h.shows("1 whisper while you weren't to be disturbed"));
+    assert!(h.shows("Luna: meet at the airlock"));
+    h.press(Key::Esc);
+    assert!(h.shows("meet at the airlock"), "in the pane now");
+    assert!(!h.shows(" DND "));
+    h.submit("/dnd off");
+    assert!(h.shows("no whispers came"));
+    assert_eq!(h.sent(), ["/dnd on", "/dnd off", "/dnd off"]);
+}
+
+#[test]
+fn emoji_search_stays_local() {
+    let mut h = Harness::new();
+    h.submit("/emoji thumbs");
+    assert!(h.shows("👍  :thumbsup:"));
+    assert!(h.shows("👎  :thumbsdown:"));
+    assert!(!h.shows(":rocket:"));
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn emoji_picker_inserts_at_the_cursor() {
+    let mut h = Harness::new();
+    h.type_text("hi there");
+    for _ in 0.." there".len() {
+        h.press(Key::Left);
+    }
+    h.press(Event::CtrlChar('e'));
+    assert!(h.shows("Smileys"));
+
+    // Typing narrows the list down, Enter takes the first match
+    h.type_text("rock");
+    assert!(h.shows("Objects  🚀"));
+    assert!(!h.shows("Smileys"));
+    h.press(Key::Enter);
+    assert!(!h.shows("Objects  🚀"));
+    assert_eq!(h.input(), "hi🚀 there");
+}
+
+#[test]
+fn emoji_picker_list_can_be_browsed() {
+    let mut h = Harness::new();
+    h.press(Event::CtrlChar('e'));
+    h.type_text("shi");
+    assert!(h.shows("Custom   🚢"), "emoji.toml additions get their own category");
+    for _ in 0.."shi".len() {
+        h.press(Key::Backspace);
+    }
+    h.type_text("sun");
+    // Down leaves the search box for the first match
+    h.press(Key::Down);
+    h.press(Key::Enter);
+    assert_eq!(h.input(), "😎");
+
+    h.press(Event::CtrlChar('e'));
+    h.press(Key::Esc);
+    assert!(!h.shows("Smileys"));
+    assert!(h.runner.is_running(), "Esc only closes the picker");
+}
+
+#[test]
+fn server_shortcodes_expand_but_local_ones_win() {
+    let mut emoji = Emoji::bundled();
+    emoji.insert("shipit", "🚢🇮🇹");
+    emoji.set_shared([("tableflip".to_string(), "(╯°□°)╯︵ ┻━┻".to_string()), ("shipit".to_string(), "⛴".to_string())]);
+    assert_eq!(emoji.expand("well :tableflip: :shipit:"), "well (╯°□°)╯︵ ┻━┻ 🚢🇮🇹");
+    assert_eq!(emoji.search_by_category("tableflip"), vec![("Server", vec![("tableflip".to_string(), "(╯°□°)╯︵ ┻━┻".to_string())])]);
+
+    // A new list from the server replaces the old one
+    emoji.set_shared([]);
+    assert_eq!(emoji.expand(":tableflip:"), ":tableflip:");
+}
+
+#[test]
+fn shortcode_definitions_are_sent_as_typed() {
+    let mut h = Harness::new();
+    h.submit("/shortcode add shrug :) ¯\\_(ツ)_/¯");
+    assert_eq!(h.sent(), vec!["/shortcode add shrug :) ¯\\_(ツ)_/¯".to_string()]);
+}
+
+#[test]
+fn ignored_users_are_not_shown() {
+    let mut h = Harness::new();
+    h.submit("/ignore Spammer");
+    assert!(h.shows("Ignoring Spammer, /unignore Spammer to see them again"));
+    h.show(ChatMessage::new("spammer", "buy my stuff", MessageType::UserMessage));
+    h.show(ChatMessage::new("spammer", "waves frantically", MessageType::Action));
+    h.show(ChatMessage::new("spammer", "has landed", MessageType::SystemNotification));
+    h.show(ChatMessage::new("Zorg", "greetings earthling", MessageType::UserMessage));
+    assert!(!h.shows("buy my stuff"));
+    assert!(!h.shows("waves frantically"));
+    assert!(!h.shows("has landed"));
+    assert!(h.shows("greetings earthling"));
+
+    h.expire_toasts();
+    h.submit("/ignore");
+    assert!(h.shows("Ignoring spammer"));
+    h.submit("/unignore spammer");
+    h.show(ChatMessage::new("Spammer", "sorry", MessageType::UserMessage));
+    assert!(h.shows("sorry"));
+    assert!(h.sent().is_empty(), "ignoring is local");
+}
+
+#[test]
+fn reports_go_to_the_server_and_show_up_for_admins() {
+    let mut h = Harness::new();
+    h.submit("/report 12 spam links");
+    assert_eq!(h.sent(), vec!["/report 12 spam links".to_string()]);
+    let notice = "⚑ Report 3: Zorg flagged #12 by spammer in #lobby (\"buy my stuff\"): spam links";
+    h.show(ChatMessage { highlight: true, ..ChatMessage::new("System", notice, MessageType::SystemNotification) });
+    assert!(h.shows("[⚑ Report 3: Zorg flagged #12 by spammer"));
+}
+
+#[test]
+fn secret_commands_are_sent_as_typed() {
+    let mut h = Harness::new();
+    h.submit("/login Tester pass:)word");
+    assert_eq!(h.sent(), vec!["/login Tester pass:)word".to_string()]);
+}
+
+#[test]
+fn private_room_passwords_are_sent_as_typed() {
+    let mut h = Harness::new();
+    h.submit("/create secret --private pass:)word");
+    h.submit("/join secret pass:)word");
+    assert_eq!(h.sent(), vec!["/create secret --private pass:)word".to_string(), "/join secret pass:)word".to_string()]);
+}
+
+#[test]
+fn incoming_messages_appear() {
+    let mut h = Harness::new();
+    let mut msg = ChatMessage::new("Zorg", "greetings earthling", MessageType::UserMessage);
+    msg.verified = true;
+    h.show(msg);
+    assert!(h.shows("Zorg ✔ --> greetings earthling"));
+
+    h.show(ChatMessage::new("Luna", "has landed", MessageType::SystemNotification));
+    assert!(h.shows("[Luna has landed]"));
+
+    h.show(ChatMessage::error(ErrorKind::CommandFailed, "Wrong username or password"));
+    assert!(h.shows("[!! Wrong username or password !!]"));
+}
+
+#[test]
+fn stamps_travel_in_utc_and_show_in_local_time() {
+    let msg = ChatMessage::new("Zorg", "hi", MessageType::UserMessage);
+    let stamped = chrono::DateTime::parse_from_rfc3339(&msg.timestamp).unwrap();
+    assert_eq!(stamped.offset().local_minus_utc(), 0);
+    assert!(msg.timestamp.ends_with('Z'));
+
+    let mut h = Harness::new();
+    let local = stamped.with_timezone(&chrono::Local);
+    h.show(msg);
+    assert!(h.shows(&format!("┌─[today {}]", local.format("%H:%M"))));
+
+    let old = "2025-03-01T22:15:00+00:00";
+    let when = chrono::DateTime::parse_from_rfc3339(old).unwrap().with_timezone(&chrono::Local);
+    assert_eq!(ui::local_time(old, false), when.format("%Y-%m-%d %H:%M").to_string());
+    assert_eq!(ui::local_time(old, true), when.format("%Y-%m-%d %H:%M:%S").to_string());
+    assert_eq!(ui::local_time("10/16/26:12:00:00", false), "10/16/26:12:00:00");
+}
+
+#[test]
+fn days_are_divided_and_stamps_can_be_absolute() {
+    let mut h = Harness::new();
+    let now = chrono::Utc::now();
+    let stamped = |text: &str, at: chrono::DateTime<chrono::Utc>| {
+        let mut msg = ChatMessage::new("Luna", text, MessageType::UserMessage);
+        msg.timestamp = at.to_rfc3339();
+        msg
+    };
+    let yesterday = now - chrono::Duration::days(1);
+    let local = |at: chrono::DateTime<chrono::Utc>| at.with_timezone(&chrono::Local);
+    h.show(stamped("from yesterday", yesterday));
+    h.show(stamped("more from yesterday", yesterday));
+    h.show(stamped("from today", now));
+    let yesterday_divider = format!("──────── {} ────────", local(yesterday).format("%A, %-d %B %Y"));
+    let today_divider = format!("──────── {} ────────", local(now).format("%A, %-d %B %Y"));
+    assert!(h.shows(&format!("┌─[yesterday {}]", local(yesterday).format("%H:%M"))));
+    assert!(h.shows(&yesterday_divider));
+    assert!(h.shows(&today_divider));
+    assert!(h.find(&yesterday_divider).unwrap().y < h.find("from yesterday").unwrap().y);
+    assert!(h.find(&today_divider).unwrap().y > h.find("more from yesterday").unwrap().y);
+
+    h.submit("/set times absolute");
+    let mut in_full = stamped("in full", now);
+    in_full.username = "Zorg".to_string();
+    h.show(in_full);
+    assert!(h.shows(&format!("┌─[{}]", local(now).format("%Y-%m-%d %H:%M:%S"))));
+}
+
+#[test]
+fn messages_in_a_row_share_one_header() {
+    let mut h = Harness::new();
+    let said = |who: &str, text: &str, id: i64| {
+        let mut msg = ChatMessage::new(who, text, MessageType::UserMessage);
+        msg.id = Some(id);
+        msg
+    };
+    h.show(said("Zorg", "first", 1));
+    h.show(said("Zorg", "second", 2));
+    h.show(said("Zorg", "third\nwith more", 3));
+    assert!(h.shows("Zorg --> first"));
+    assert!(h.shows("↳ second #2"));
+    assert!(h.shows("↳ third"));
+    assert!(h.shows("┆ with more #3"));
+    assert_eq!(h.screen_text().matches("Zorg -->").count(), 1);
+
+    // Someone else, a reply or a long pause start a new header
+    h.show(said("Luna", "my turn", 4));
+    h.show(said("Zorg", "back again", 5));
+    let mut reply = said("Zorg", "replying", 6);
+    reply.quote = Some(Quote { id: 4, username: "Luna".to_string(), content: "my turn".to_string() });
+    h.show(reply);
+    let mut later = said("Zorg", "much later", 7);
+    later.timestamp = (chrono::Utc::now() + chrono::Duration::minutes(10)).to_rfc3339();
+    h.show(later);
+    assert!(h.shows("Luna --> my turn"));
+    assert!(h.shows("Zorg --> back again"));
+    assert!(h.shows("Zorg --> replying"));
+    assert!(h.shows("Zorg --> much later"));
+
+    // A follow-up can still be picked on its own
+    let at = h.find("↳ second").unwrap();
+    h.click(at);
+    h.submit("/react :thumbsup:");
+    assert_eq!(h.sent(), vec!["/react 2 👍".to_string()]);
+}
+
+#[test]
+fn frames_from_a_newer_server_degrade_gracefully() {
+    let mut h = Harness::new();
+    let newer = r#"{"username": "pollbot", "content": "Lunch? pizza/tacos", "timestamp": "12:00:00", "message_type": "Poll", "poll": {"options": 2}}"#;
+    let msg: ChatMessage = serde_json::from_str(newer).unwrap();
+    assert_eq!(msg.message_type, MessageType::Unknown);
+    h.show(msg);
+    assert!(h.shows("pollbot sent a kind of message"));
+    assert!(h.shows("Lunch? pizza/tacos"));
+
+    let refused: ChatMessage = serde_json::from_str(r#"{"username": "System", "content": "nope", "timestamp": "", "message_type": "Error", "error": "QuotaExceeded"}"#).unwrap();
+    assert_eq!(refused.error, Some(ErrorKind::Other));
+}
+
+#[test]
+fn reactions_are_summarised() {
+    let mut h = Harness::new();
+    let mut msg = ChatMessage::new("Zorg", "to the moon", MessageType::UserMessage);
+    msg.id = Some(42);
+    msg.reactions = vec![
+        Reaction { emoji: "🚀".to_string(), count: 2 },
+        Reaction { emoji: "😂".to_string(), count: 1 },
+    ];
+    h.show(msg);
+    assert!(h.shows("#42"));
+    assert!(h.shows("🚀 2  😂 1"));
+
+    let mut update = ChatMessage::new("Luna", "reacted 🚀 on #42", MessageType::Reactions);
+    update.id = Some(42);
+    update.reactions = vec![Reaction { emoji: "🚀".to_string(), count: 3 }];
+    h.show(update);
+    assert!(h.shows("[Luna reacted 🚀 on #42 | 🚀 3]"));
+}
+
+#[test]
+fn markup_is_rendered_without_its_markers() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Zorg", "*loud* **louder** _soft_ `x*y_z` snake_case_name 2*3*4", MessageType::UserMessage));
+    assert!(h.shows("Zorg --> loud louder soft x*y_z snake_case_name 2*3*4"));
+
+    h.show(ChatMessage::new("Luna", "> who *goes* there", MessageType::UserMessage));
+    assert!(h.shows("Luna --> │ who goes there"));
+}
+
+#[test]
+fn links_are_numbered_for_open_and_copyurl() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Zorg", "see https://docs.rs/ (or https://w.org/a_(b)).", MessageType::UserMessage));
+    assert!(h.shows("see https://docs.rs/[1] (or https://w.org/a_(b)[2])."));
+
+    // Numbers that were never shown only get the usage
+    h.submit("/open 7");
+    assert!(h.shows("Usage: /open <n>, with n from a link's [n]"));
+    // Copying may not work without a display, either way it is the right link
+    h.submit("/copyurl 2");
+    assert!(h.shows("Copied https://w.org/a_(b)") || h.shows("Could not copy https://w.org/a_(b)"));
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn clicking_a_message_selects_it() {
+    let mut h = Harness::new();
+    let mut msg = ChatMessage::new("Zorg", "pick me", MessageType::UserMessage);
+    msg.id = Some(42);
+    h.show(msg);
+    h.show(ChatMessage::new("Luna", "not me", MessageType::UserMessage));
+
+    let at = h.find("pick me").unwrap();
+    h.click(at);
+    assert!(h.shows("Selected Zorg's message"));
+    // The keyboard is still with the input box
+    h.submit("/react :thumbsup:");
+    assert_eq!(h.sent(), vec!["/react 42 👍".to_string()]);
+
+    // A second click lets go of it
+    h.click(at);
+    h.submit("/react 👍");
+    assert_eq!(h.sent().last().map(String::as_str), Some("/react 👍"));
+    h.submit("/copy");
+    assert!(h.shows("Click a message first to select it"));
+}
+
+#[test]
+fn replies_quote_the_selected_message() {
+    let mut h = Harness::new();
+    let msg = |id, name: &str, text: &str| ChatMessage { id: Some(id), ..ChatMessage::new(name, text, MessageType::UserMessage) };
+    h.show(msg(42, "Zorg", "lunch at noon?\nmy treat"));
+    h.show(msg(43, "Luna", "anyone?"));
+
+    // Clicking one puts it over the input box, what's sent next replies to it
+    h.click(h.find("lunch at noon?").unwrap());
+    assert!(h.shows("> lunch at noon? — Zorg"));
+    h.submit("sounds good :)");
+    assert_eq!(h.sent(), vec!["/reply 42 sounds good 😊".to_string()]);
+    assert!(!h.shows("> lunch at noon? — Zorg"));
+
+    // /quote picks one by id, /unquote drops it
+    h.submit("/quote 43");
+    assert!(h.shows("> anyone? — Luna"));
+    h.submit("/unquote");
+    assert!(!h.shows("> anyone? — Luna"));
+    h.submit("just chatting");
+    assert_eq!(h.sent().last().map(String::as_str), Some("just chatting"));
+    h.submit("/quote 99");
+    assert!(h.shows("There is no message #99 here to quote"));
+
+    // The reply shows what it quotes
+    let quote = Quote { id: 42, username: "Zorg".to_string(), content: "lunch at noon?\nmy treat".to_string() };
+    h.show(ChatMessage { quote: Some(quote), ..msg(44, "Tester", "sounds good") });
+    assert!(h.shows("│  > lunch at noon? — Zorg"));
+}
+
+#[test]
+fn links_are_found_where_they_are_clicked() {
+    let line = "see https://w.org/a_(b)[2]). or not";
+    assert_eq!(links::link_at(line, 4).as_deref(), Some("https://w.org/a_(b)"));
+    assert_eq!(links::link_at(line, 24).as_deref(), Some("https://w.org/a_(b)"), "its number counts too");
+    assert_eq!(links::link_at(line, 2), None);
+    assert_eq!(links::link_at(line, 27), None);
+}
+
+#[test]
+fn wheel_scrolls_the_messages() {
+    let mut h = Harness::new();
+    for i in 0..80 {
+        h.show(ChatMessage::new("Zorg", format!("line {:02}", i), MessageType::UserMessage));
+    }
+    // The newest are in view
+    assert!(h.shows("line 79"));
+    assert!(!h.shows("line 00"));
+
+    let wheel = |h: &mut Harness, event| {
+        for _ in 0..20 {
+            h.runner.on_event(Event::Mouse { offset: Vec2::zero(), position: Vec2::new(60, 20), event });
+        }
+        h.render();
+    };
+    wheel(&mut h, MouseEvent::WheelUp);
+    assert!(!h.shows("line 79"));
+    // Scrolled up, new messages don't pull the view down
+    h.show(ChatMessage::new("Zorg", "line 80", MessageType::UserMessage));
+    assert!(!h.shows("line 80"));
+
+    wheel(&mut h, MouseEvent::WheelDown);
+    h.show(ChatMessage::new("Zorg", "line 81", MessageType::UserMessage));
+    assert!(h.shows("line 81"));
+    assert_eq!(h.input(), "", "the wheel doesn't touch the input box");
+
+    // Clearing while scrolled up follows new messages again
+    wheel(&mut h, MouseEvent::WheelUp);
+    h.submit("/clear");
+    for i in 0..80 {
+        h.show(ChatMessage::new("Luna", format!("again {:02}", i), MessageType::UserMessage));
+    }
+    assert!(h.shows("again 79"));
+}
+
+#[test]
+fn navigation_mode_scrolls_and_searches_the_messages() {
+    let mut h = Harness::new();
+    for i in 0..120 {
+        h.show(ChatMessage::new("Zorg", format!("line {:03}", i), MessageType::UserMessage));
+    }
+    h.type_text("half");
+    h.press(Key::Esc);
+    assert!(h.shows("NAVIGATE"));
+    assert!(h.shows("line 119"), "the messages stay where they were");
+
+    h.type_text("gg");
+    assert!(h.shows("line 000"));
+    assert!(!h.shows("line 119"));
+    h.type_text("jjj");
+    assert!(!h.shows("line 000"));
+    h.press(Event::CtrlChar('u'));
+    assert!(h.shows("line 000"));
+    h.press(Event::CtrlChar('d'));
+    assert!(!h.shows("line 000"));
+    // Scrolled up, new messages don't pull the view down
+    h.show(ChatMessage::new("Zorg", "line 120", MessageType::UserMessage));
+    assert!(!h.shows("line 120"));
+    h.type_text("G");
+    assert!(h.shows("line 120"));
+    h.show(ChatMessage::new("Zorg", "line 121", MessageType::UserMessage));
+    assert!(h.shows("line 121"), "at the bottom it follows new messages again");
+
+    h.type_text("/");
+    assert!(h.shows("Search the messages"));
+    h.submit("LINE 01");
+    assert!(!h.shows("Search the messages"));
+    let top = h.find("line 019").expect("the newest match above comes to the top");
+    assert!(!h.shows("line 121"));
+    h.type_text("n");
+    assert_eq!(h.find("line 018").map(|found| found.y), Some(top.y), "then the next older one");
+    h.type_text("N");
+    assert_eq!(h.find("line 019").map(|found| found.y), Some(top.y));
+    h.type_text("/");
+    h.submit("line 000");
+    h.type_text("n");
+    assert!(h.shows("Nothing older says \"line 000\""));
+
+    h.press('i');
+    assert!(!h.shows("NAVIGATE"));
+    h.type_text(" a thought");
+    assert_eq!(h.input(), "half a thought");
+    h.press(Key::Esc);
+    h.press(Key::Enter);
+    assert!(!h.shows("NAVIGATE"));
+    assert!(h.sent().is_empty(), "Enter went back to the input box, it didn't send");
+    h.press(Event::CtrlChar('q'));
+    assert!(!h.runner.is_running());
+}
+
+#[test]
+fn multi_line_messages_keep_their_indentation() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Zorg", "steps:\n  1. land\n\n    2. greet", MessageType::UserMessage));
+    assert!(h.shows("Zorg --> steps:"));
+    assert!(h.shows("   ┆   1. land"));
+    assert!(h.shows("   ┆     2. greet"));
+
+    // Taller than the pane, it is shown from the top and the view stays there
+    let tall: Vec<String> = (0..90).map(|i| format!("row {:02}", i)).collect();
+    h.show(ChatMessage::new("Luna", tall.join("\n"), MessageType::UserMessage));
+    assert!(h.shows("Luna --> row 00"));
+    assert!(!h.shows("row 89"));
+    h.show(ChatMessage::new("Zorg", "after", MessageType::UserMessage));
+    assert!(h.shows("Luna --> row 00"));
+    assert!(!h.shows("Zorg --> after"));
+}
+
+#[test]
+fn code_blocks_are_boxed() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Zorg", "look:\n```rust\nfn main() {\n\tprintln!(\"*hi*\");\n}\n```", MessageType::UserMessage));
+    assert!(h.shows("Zorg --> look:"));
+    assert!(h.shows("┌─ rust"));
+    assert!(h.shows("│     println!(\"*hi*\");"), "code is left alone, tabs become spaces");
+    assert!(h.shows("└───"));
+}
+
+#[test]
+fn long_code_blocks_collapse_until_expanded() {
+    let mut h = Harness::new();
+    let code: Vec<String> = (1..=20).map(|i| format!("line {:02}", i)).collect();
+    h.show(ChatMessage::new("Zorg", format!("```\n{}\n```", code.join("\n")), MessageType::UserMessage));
+    assert!(h.shows("line 12"));
+    assert!(!h.shows("line 13"));
+    assert!(h.shows("… 8 more lines, /expand 1 to see them"));
+
+    h.submit("/expand 1");
+    assert!(h.shows("line 20"));
+    h.submit("/expand 2");
+    assert!(h.shows("Usage: /expand <n>"));
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn fence_opens_the_code_editor() {
+    let mut h = Harness::new();
+    h.submit("```py");
+    assert!(h.shows("Code block (py)"));
+    assert!(h.sent().is_empty());
+
+    h.type_text("def hi():");
+    h.press(Key::Enter);
+    h.type_text("    return ':rocket:'");
+    h.press(Key::Tab); // over to the buttons
+    h.press(Key::Enter);
+    assert!(!h.shows("Code block (py)"));
+    assert_eq!(h.sent(), vec!["```py\ndef hi():\n    return ':rocket:'\n```".to_string()]);
+    assert_eq!(h.input(), "");
+}
+
+#[test]
+fn raw_shows_messages_as_sent() {
+    let mut h = Harness::new();
+    h.submit("/raw");
+    assert!(h.shows("New messages are shown exactly as sent"));
+    h.show(ChatMessage::new("Zorg", "*loud* > `code`", MessageType::UserMessage));
+    assert!(h.shows("Zorg --> *loud* > `code`"));
+
+    h.submit("/raw");
+    h.show(ChatMessage::new("Zorg", "*again*", MessageType::UserMessage));
+    assert!(h.shows("↳ again"));
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn whispers_show_sender_and_recipient() {
+    let mut h = Harness::new();
+    let mut whisper = ChatMessage::new("Luna", "psst, look at Zorg's hat", MessageType::Whisper);
+    whisper.recipient = Some("Tester".to_string());
+    h.show(whisper);
+    assert!(h.shows("Luna → Tester ~~> psst, look at Zorg's hat"));
+}
+
+#[test]
+fn sealed_whispers_open_at_either_end_only() {
+    let (luna, tester, zorg) = (E2e::generate(), E2e::generate(), E2e::generate());
+    luna.learn("Tester", &tester.public_key());
+    tester.learn("Luna", &luna.public_key());
+    zorg.learn("Luna", &luna.public_key());
+
+    let line = luna.seal_line("/w Tester meet at the airlock");
+    let sealed = line.strip_prefix("/ewhisper Tester ").expect("sealed for Tester");
+    assert!(!sealed.contains("airlock"));
+    assert_eq!(tester.open("Luna", sealed).as_deref(), Some("meet at the airlock"));
+    assert_eq!(luna.open("Tester", sealed).as_deref(), Some("meet at the airlock"), "the sender's own copy");
+    assert_eq!(zorg.open("Luna", sealed), None);
+    // Without a key it goes out as typed
+    assert_eq!(luna.seal_line("/w Zorg hi"), "/w Zorg hi");
+    tester.learn("Luna", "");
+    assert_eq!(tester.seal_line("/msg Luna hi"), "/msg Luna hi");
+
+    // Opened, it shows with a lock
+    let mut h = Harness::new();
+    h.show(ChatMessage { recipient: Some("Tester".to_string()), encrypted: true, ..ChatMessage::new("Luna", "meet at the airlock", MessageType::Whisper) });
+    assert!(h.shows("🔒 whisper"));
+}
+
+#[test]
+fn signed_lines_carry_their_signature_and_flags_show() {
+    let signer = Signer::generate();
+    let line = signer.sign_line("lobby", "  hello there  ");
+    let (sign, text) = line.split_once('\n').unwrap();
+    let stamp: u64 = sign.split(' ').nth(1).unwrap().parse().unwrap();
+    // Signed as the server reads it, trimmed, with the room and the stamp
+    assert_eq!(sign, format!("/sign {} {}", stamp, signer.sign(&signed_text("lobby", stamp, "hello there"))));
+    assert_eq!(text, "  hello there  ");
+    // A block is signed as one message, and every line gets a higher stamp
+    let block = signer.sign_line("lobby", "```rust\nfn main() {}   \n```");
+    let next: u64 = block.split(' ').nth(1).unwrap().parse().unwrap();
+    assert!(next > stamp);
+    assert!(block.starts_with(&format!("/sign {} {}\n", next, signer.sign(&signed_text("lobby", next, "```rust\nfn main() {}\n```")))));
+    assert_ne!(signer.public_key(), Signer::generate().public_key());
+
+    let mut h = Harness::new();
+    h.show(ChatMessage { bad_signature: true, ..ChatMessage::new("Luna", "it's me, honest", MessageType::UserMessage) });
+    assert!(h.shows("Luna ⚠ not signed with their key --> it's me, honest"));
+    h.show(ChatMessage::new("Zorg", "hi", MessageType::UserMessage));
+    assert!(h.shows("Zorg --> hi"));
+}
+
+#[test]
+fn proxy_urls_parse_and_hide_their_password() {
+    assert_eq!(Proxy::parse("socks5://127.0.0.1:9050").unwrap().to_string(), "socks5://127.0.0.1:9050");
+    assert_eq!(Proxy::parse("socks5h://tor:secret@[::1]:9050/").unwrap().to_string(), "socks5://tor@[::1]:9050");
+    assert!(Proxy::parse("http://proxy:8080").is_err());
+    assert!(Proxy::parse("socks5://proxy").is_err());
+    assert!(Proxy::parse("socks5://:1080").is_err());
+}
+
+#[test]
+fn actions_show_as_third_person() {
+    let mut h = Harness::new();
+    let mut action = ChatMessage::new("alice", "waves", MessageType::Action);
+    action.id = Some(7);
+    h.show(action);
+    assert!(h.shows("* alice waves #7"));
+    assert!(!h.shows("alice -->"));
+}
+
+#[test]
+fn two_factor_setup_shows_qr_and_backup_codes() {
+    let mut h = Harness::new();
+    let uri = "otpauth://totp/Retro%20Chat:Tester?secret=JBSWY3DPEHPK3PXP&issuer=Retro%20Chat";
+    h.show(ChatMessage::new("System", uri, MessageType::TwoFactorSetup));
+    assert!(h.shows("Two-factor setup"));
+    assert!(h.shows("▀"), "QR code drawn with half blocks");
+    assert!(h.shows("Enter this key in the app: JBSWY3DPEHPK3PXP"));
+
+    h.show(ChatMessage::new("System", "abcde-fghij\nklmno-pqrst", MessageType::BackupCodes));
+    assert!(h.shows("abcde-fghij"));
+    assert!(h.shows("klmno-pqrst"));
+}
+
+#[test]
+fn multi_line_notices_are_continued() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("System", "Server stats\nUptime: 5s\nOnline: 2 users in 1 room", MessageType::SystemNotification));
+    assert!(h.shows("[System Server stats"));
+    assert!(h.shows("   ┆ Uptime: 5s"));
+    assert!(h.shows("   ┆ Online: 2 users in 1 room]"));
+}
+
+#[test]
+fn ops_events_are_tagged_with_their_kind() {
+    let mut h = Harness::new();
+    let mut event = ChatMessage::ops_event(OpsEvent::Ban, "203.0.113.7 tried to connect but is banned");
+    event.timestamp = "12:00:00".to_string();
+    h.show(event);
+    assert!(h.shows("#server-ops [12:00:00] ban 203.0.113.7 tried to connect but is banned"));
+}
+
+#[test]
+fn session_frames_are_not_shown() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::session("Tester", "secret-token"));
+    assert!(!h.shows("secret-token"));
+}
+
+#[test]
+fn user_count_goes_to_status_bar() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::user_count(3));
+    assert!(h.shows("3 online"));
+    h.show(ChatMessage::user_count(2));
+    assert!(h.shows("2 online"));
+    assert!(!h.shows("3 online"));
+}
+
+#[test]
+fn status_bar_shows_connection_and_room() {
+    let mut h = Harness::new();
+    assert!(h.shows("◌ connecting  #lobby"));
+    ui::set_connection(&mut h.runner, Connection::Connected);
+    h.show(ChatMessage::user_count(4));
+    assert!(h.shows("● connected  #lobby  👥 4 online"));
+
+    ui::set_connection(&mut h.runner, Connection::Reconnecting);
+    h.show(ChatMessage::new("System", "joined", MessageType::RoomJoined).in_room("dev"));
+    assert!(h.shows("◌ reconnecting  #dev  👥 4 online"));
+    ui::set_connection(&mut h.runner, Connection::Disconnected);
+    h.render();
+    assert!(h.shows("○ disconnected"));
+    assert!(h.shows("/help"));
+}
+
+#[test]
+fn rooms_get_tabs_with_unread_counts() {
+    let mut h = Harness::new();
+    assert!(h.shows(" 1 #lobby "));
+    h.show(ChatMessage::new("System", "You are talking in #dev", MessageType::RoomJoined).in_room("dev"));
+    assert!(h.shows(" 1 #lobby   2 #dev "));
+    assert!(h.shows("◌ connecting  #dev"));
+
+    // The lobby's messages wait in its tab while #dev is up
+    h.show(ChatMessage::new("Luna", "over in the lobby", MessageType::UserMessage).in_room("lobby"));
+    h.show(ChatMessage::new("Zorg", "still here", MessageType::UserMessage).in_room("lobby"));
+    assert!(!h.shows("over in the lobby"));
+    assert!(h.shows(" 1 #lobby (2)  2 #dev "));
+    h.show(ChatMessage::new("Luna", "over in dev", MessageType::UserMessage).in_room("dev"));
+    assert!(h.shows("over in dev"));
+
+    // Alt+1 brings the lobby up and moves the server along, what's being typed stays with #dev
+    h.type_text("half a thought");
+    h.press(Event::AltChar('1'));
+    assert!(h.shows("over in the lobby"));
+    assert!(!h.shows("over in dev"));
+    assert!(h.shows(" 1 #lobby   2 #dev "));
+    assert!(h.shows("◌ connecting  #lobby"));
+    assert_eq!(h.sent(), vec!["/join lobby".to_string()]);
+    assert_eq!(h.input(), "");
+    h.press(Event::AltChar('2'));
+    assert_eq!(h.input(), "half a thought");
+
+    // Leaving a room closes its tab
+    h.press(Event::AltChar('9'));
+    assert_eq!(h.sent().len(), 2);
+    h.show(ChatMessage::new("System", "You left #dev", MessageType::RoomLeft).in_room("dev"));
+    assert!(!h.shows("2 #dev"));
+}
+
+#[test]
+fn room_topics_show_in_the_header() {
+    let mut h = Harness::new();
+    let joined = |room: &str, topic: &str| ChatMessage {
+        topic: Some(topic.to_string()),
+        ..ChatMessage::new("System", format!("You are talking in #{}", room), MessageType::RoomJoined).in_room(room)
+    };
+    h.show(joined("dev", "Bugs and builds"));
+    assert!(h.shows("Bugs and builds"));
+
+    // A new topic replaces the old one, and says who changed it
+    h.show(ChatMessage {
+        topic: Some("Release on Friday".to_string()),
+        ..ChatMessage::new("Luna", "set the topic of #dev to: Release on Friday", MessageType::SystemNotification).in_room("dev")
+    });
+    assert!(h.shows("Luna set the topic of #dev to: Release on Friday"));
+    h.expire_toasts();
+    assert!(h.shows("Release on Friday"));
+    assert!(!h.shows("Bugs and builds"));
+
+    // Each tab keeps its own
+    h.show(joined("lobby", ""));
+    assert!(!h.shows("Release on Friday"));
+    h.press(Event::AltChar('2'));
+    assert!(h.shows("Release on Friday"));
+}
+
+#[test]
+fn pinned_messages_fold_into_a_panel_over_the_messages() {
+    let mut h = Harness::new();
+    let pin = |id, name: &str, text: &str| ChatMessage { id: Some(id), ..ChatMessage::new(name, text, MessageType::UserMessage) };
+    assert!(!h.shows("📌"));
+    h.show(ChatMessage::pins("lobby", vec![pin(12, "Luna", "Meeting at noon\nbring snacks"), pin(15, "Zorg", "Rules: be nice")]));
+    assert!(h.shows("📌 2 pinned messages (F3 to show)"));
+    assert!(!h.shows("Meeting at noon"));
+
+    // F3 opens them up, one line each
+    h.press(Key::F3);
+    assert!(h.shows("📌 Pinned (F3 to fold)"));
+    assert!(h.shows("#12 Luna: Meeting at noon"));
+    assert!(h.shows("#15 Zorg: Rules: be nice"));
+    assert!(!h.shows("bring snacks"));
+
+    // A room without pins has no panel, /pins says so
+    h.show(ChatMessage::new("System", "You are talking in #dev", MessageType::RoomJoined).in_room("dev"));
+    assert!(!h.shows("📌"));
+    h.submit("/pins");
+    assert!(h.shows("Nothing is pinned in #dev"));
+    assert!(h.sent().is_empty());
+
+    h.press(Event::AltChar('1'));
+    h.press(Key::F3);
+    h.submit("/pins");
+    assert!(h.shows("=== Pinned in #lobby ==="));
+}
+
+#[test]
+fn picking_a_room_from_the_list_joins_it() {
+    let mut h = Harness::new();
+    let room = |name: &str, members, topic: Option<&str>| RoomInfo { name: name.to_string(), members, topic: topic.map(str::to_string) };
+    h.show(ChatMessage::room_list(vec![room("lobby", 5, None), room("dev", 3, Some("Bugs and builds"))]));
+    assert!(h.shows("Rooms"));
+    assert!(h.shows("#dev"));
+    assert!(h.shows("👥 3"));
+    assert!(h.shows("Bugs and builds"));
+
+    h.press(Key::Down);
+    h.press(Key::Enter);
+    assert_eq!(h.sent(), vec!["/join dev".to_string()]);
+    assert!(!h.shows("Bugs and builds"));
+}
+
+#[test]
+fn drafts_go_out_once_typing_pauses_and_come_in_from_other_devices() {
+    let mut h = Harness::new();
+    h.type_text("hello fr");
+    assert!(ui::due_drafts(&mut h.runner, Instant::now()).is_empty(), "still typing");
+    let lines = ui::due_drafts(&mut h.runner, Instant::now() + ui::DRAFT_DEBOUNCE);
+    assert_eq!(lines, vec!["/draft lobby hello fr".to_string()]);
+    assert!(ui::due_drafts(&mut h.runner, Instant::now() + ui::DRAFT_DEBOUNCE).is_empty(), "sent once");
+
+    // The server passes on the newest draft of any of our devices
+    h.show(ChatMessage::draft("lobby", "hello from the phone"));
+    assert_eq!(h.input(), "hello from the phone");
+    // Unless we typed since, then ours is newer and goes out to replace it
+    h.type_text("!");
+    h.show(ChatMessage::draft("lobby", "something older"));
+    assert_eq!(h.input(), "hello from the phone!");
+
+    // Drafts for other rooms wait for their tab
+    h.show(ChatMessage::draft("dev", "about that bug"));
+    h.show(ChatMessage::new("System", "You are talking in #dev", MessageType::RoomJoined).in_room("dev"));
+    assert_eq!(h.input(), "about that bug");
+
+    // Sending the line clears the draft everywhere
+    h.press(Key::Enter);
+    let mut lines = ui::due_drafts(&mut h.runner, Instant::now() + ui::DRAFT_DEBOUNCE);
+    lines.sort();
+    assert_eq!(lines, vec!["/draft dev ".to_string(), "/draft lobby hello from the phone!".to_string()]);
+    assert_eq!(h.sent(), vec!["about that bug".to_string()]);
+
+    // A room we left takes no more drafts
+    h.type_text("half a thought");
+    h.show(ChatMessage::new("System", "You left #dev", MessageType::RoomLeft).in_room("dev"));
+    assert!(ui::due_drafts(&mut h.runner, Instant::now() + ui::DRAFT_DEBOUNCE).is_empty());
+}
+
+#[test]
+fn where_we_left_off_is_marked_and_reported() {
+    let mut h = Harness::new();
+    let joined = |room: &str, last_read| ChatMessage {
+        last_read: Some(last_read),
+        ..ChatMessage::new("System", format!("You are talking in #{}", room), MessageType::RoomJoined).in_room(room)
+    };
+    let said = |room: &str, id, text: &str| ChatMessage { id: Some(id), ..ChatMessage::new("Luna", text, MessageType::UserMessage).in_room(room) };
+    h.show(joined("lobby", 5));
+    h.show(joined("dev", 11));
+
+    // History up to where we left off comes first, the divider goes before the first message after it
+    for (id, text) in [(10, "old news"), (11, "read that"), (12, "brand new"), (13, "newer still")] {
+        h.show(said("dev", id, text));
+    }
+    let divider = h.find("new since you were last here").expect("divider shown");
+    assert!(h.find("read that").unwrap().y < divider.y);
+    assert!(divider.y < h.find("brand new").unwrap().y);
+    assert_eq!(h.screen_text().matches("new since you were last here").count(), 1);
+
+    // Scrolled down to the newest, the server hears how far we got, once
+    assert_eq!(ui::due_reads(&mut h.runner), vec!["/read dev 13".to_string()]);
+    assert!(ui::due_reads(&mut h.runner).is_empty());
+
+    // A tab that isn't up only counts what we haven't read
+    for id in 4..=6 {
+        h.show(said("lobby", id, "lobby chat"));
+    }
+    assert!(h.shows(" 1 #lobby (1)  2 #dev "));
+
+    // Whisperers hear we saw them, and so do we when they see ours
+    h.show(ChatMessage { recipient: Some("Tester".to_string()), ..ChatMessage::new("Luna", "psst", MessageType::Whisper) });
+    assert_eq!(ui::due_reads(&mut h.runner), vec!["/read @Luna".to_string()]);
+    h.show(ChatMessage::new("Luna", "", MessageType::Seen));
+    assert!(h.shows("✓ Luna saw your whisper"));
+}
+
+#[test]
+fn f2_toggles_the_roster() {
+    let mut h = Harness::new();
+    let member = |name: &str, verified| Member { username: name.to_string(), verified, dnd: false };
+    h.show(ChatMessage::roster(vec![member("Luna", true), member("Zorg", false)]));
+    assert!(!h.shows("Online (2)"));
+
+    h.press(Key::F2);
+    assert!(h.shows("Online (2)"));
+    assert!(h.shows("Luna ✔"));
+    assert!(h.shows("Zorg"));
+    // Kept up to date while it's open, and typing still goes to the input box
+    h.show(ChatMessage::roster(vec![member("Luna", true)]));
+    assert!(h.shows("Online (1)"));
+    assert!(!h.shows("Zorg"));
+    h.type_text("hi");
+    assert_eq!(h.input(), "hi");
+
+    h.press(Key::F2);
+    assert!(!h.shows("Online (1)"));
+}
+
+#[test]
+fn ping_round_trip_shows_in_the_status_bar() {
+    let mut h = Harness::new();
+    h.submit("/ping");
+    let sent = h.sent();
+    assert_eq!(sent.len(), 1);
+    let stamp = sent[0].strip_prefix("/ping ").expect("a ping with its stamp");
+    assert!(stamp.parse::<u128>().is_ok());
+
+    h.show(ChatMessage::new("System", stamp, MessageType::Ping));
+    assert!(h.shows("Pong,"));
+    assert!(h.shows(" ms there and back"));
+    assert!(h.shows("⏱ "));
+    // Pings sent by the client itself don't toast
+    h.expire_toasts();
+    h.show(ChatMessage::new("System", stamp, MessageType::Ping));
+    assert!(!h.shows("Pong,"));
+    assert!(h.shows("⏱ "));
+}
+
+#[test]
+fn toasts_show_and_expire() {
+    let mut h = Harness::new();
+    ui::toast(&mut h.runner, ToastKind::Error, "Send failed: broken pipe");
+    ui::toast(&mut h.runner, ToastKind::Info, "Reconnected");
+    h.render();
+    assert!(h.shows("⚠ Send failed: broken pipe"));
+    assert!(h.shows("ℹ Reconnected"));
+
+    h.expire_toasts();
+    assert!(!h.shows("Send failed"));
+    assert!(!h.shows("Reconnected"));
+}
+
+#[test]
+fn room_notices_are_toasts() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Luna", "joined #dev", MessageType::SystemNotification).in_room("dev"));
+    assert!(h.shows("ℹ Luna joined #dev"));
+
+    // Not kept in the message view once the toast is gone
+    h.expire_toasts();
+    assert!(!h.shows("Luna joined"));
+}
+
+#[test]
+fn read_only_rooms_grey_out_the_input() {
+    let mut h = Harness::new();
+    let mut joined = ChatMessage::new("System", "You are talking in #news", MessageType::RoomJoined).in_room("news");
+    joined.read_only = true;
+    h.show(joined);
+    assert!(h.shows("Read-only room, commands only"));
+
+    // Chat stays in the box, commands still go out
+    h.submit("anyone there?");
+    assert!(h.sent().is_empty());
+    assert_eq!(h.input(), "anyone there?");
+    assert!(h.shows("This room is read-only"));
+    h.runner.call_on_name(ui::INPUT, |view: &mut EditView| view.set_content(""));
+    h.submit("/join lobby");
+    assert_eq!(h.sent(), vec!["/join lobby".to_string()]);
+
+    // Opening the room (or moving to another one) gives the box back
+    h.show(ChatMessage::new("System", "#news is open, everyone can post", MessageType::RoomMode).in_room("news"));
+    assert!(h.shows("Chit Chat"));
+    assert!(h.shows("#news is open, everyone can post"));
+    h.submit("hello");
+    assert_eq!(h.sent().last().map(String::as_str), Some("hello"));
+}
+
+#[test]
+fn help_and_clear_stay_local() {
+    let mut h = Harness::new();
+    h.show(ChatMessage::new("Zorg", "still here", MessageType::UserMessage));
+    h.submit("/clear");
+    assert!(!h.shows("still here"));
+    h.submit("/help");
+    assert!(h.shows("Help"));
+    assert!(h.sent().is_empty());
+}
+
+#[test]
+fn help_lists_client_and_server_commands_keys_and_the_version() {
+    let mut h = Harness::new();
+    h.submit("/help");
+    assert!(h.shows(&format!("retro-chat-project {}", env!("CARGO_PKG_VERSION"))));
+    assert!(h.shows("/clear - Clear the message view"));
+    assert!(h.shows("It hasn't said which commands it has"));
+    h.press(Key::Esc);
+    assert!(!h.shows("This client"));
+    assert!(!h.shows("/clear - Clear"), "help isn't dumped in the chat any more");
+
+    let commands = vec![CommandInfo { usage: "/summon <user>".into(), about: "Call someone over".into() }];
+    h.show(ChatMessage { commands, ..ChatMessage::new("System", "", MessageType::Capabilities) });
+    assert!(!h.shows("Call someone over"), "the frame itself isn't shown");
+    h.submit("/help");
+    assert!(h.shows("/summon <user> - Call someone over"));
+    assert!(!h.shows("It hasn't said"));
+    h.press(Key::Esc);
+    assert!(!h.shows("This client"));
+    assert!(h.runner.is_running());
+}
+
+#[test]
+fn debug_wire_toggles_tracing() {
+    let mut h = Harness::new();
+    h.submit("/debug wire on");
+    assert!(h.ctx.tracer.is_enabled());
+    assert!(h.shows("Wire tracing on"));
+    h.submit("/debug wire off");
+    assert!(!h.ctx.tracer.is_enabled());
+}
+
+#[test]
+fn password_dialog_opens_and_submits() {
+    let mut h = Harness::new();
+    let entered = Arc::new(Mutex::new(None));
+    ui::show_password_dialog(&mut h.runner, {
+        let entered = entered.clone();
+        move |_, password: &str| *entered.lock().unwrap() = Some(password.to_string())
+    });
+    h.render();
+    assert!(h.shows("Server password"));
+
+    h.type_text("hunter2");
+    assert!(!h.shows("hunter2"), "password must be masked");
+    h.press(Key::Enter);
+    assert_eq!(entered.lock().unwrap().as_deref(), Some("hunter2"));
+    assert!(!h.shows("Server password"));
+}
+
+#[test]
+fn connect_dialog_asks_until_username_and_server_look_right() {
+    let mut h = Harness::new();
+    let chosen = Arc::new(Mutex::new(None));
+    ui::show_connect_dialog(&mut h.runner, "", "127.0.0.1:8082", Some("Couldn't connect to nowhere:1: refused"), {
+        let chosen = chosen.clone();
+        move |_, username: &str, server: &str| *chosen.lock().unwrap() = Some((username.to_string(), server.to_string()))
+    });
+    h.render();
+    assert!(h.shows("Connect to a chat server"));
+    assert!(h.shows("Couldn't connect to nowhere:1"));
+
+    h.submit("two words");
+    assert!(h.shows("Pick a username, one word without spaces"));
+    h.runner.call_on_name(ui::CONNECT_USERNAME, |view: &mut EditView| view.set_content("Bobrovsky"));
+    h.runner.call_on_name(ui::CONNECT_SERVER, |view: &mut EditView| view.set_content("chat.example.org"));
+    h.press(Key::Enter);
+    assert!(h.shows("Check the server"));
+    assert!(chosen.lock().unwrap().is_none());
+
+    h.runner.call_on_name(ui::CONNECT_SERVER, |view: &mut EditView| view.set_content("quic://chat.example.org:8443"));
+    h.press(Key::Enter);
+    assert_eq!(*chosen.lock().unwrap(), Some(("Bobrovsky".to_string(), "quic://chat.example.org:8443".to_string())));
+    assert!(!h.shows("Connect to a chat server"));
+
+    assert!(check_addr("unix:/tmp/chat.sock").is_ok() && check_addr("tcp://[::1]:8082").is_ok());
+    assert!(check_addr("unix:").is_err() && check_addr("host:0").is_err() && check_addr(":8082").is_err());
+}
+
+#[test]
+fn saved_servers_are_offered_and_switched_to() {
+    let file = std::env::temp_dir().join(format!("ui-test-servers-{}.toml", std::process::id()));
+    let toml = "username = \"zorg\"\n\n[servers.home]\naddress = \"chat.example.org:8082\"\n\n\
+                [servers.work]\naddress = \"quic://chat.work.example:8443\"\nusername = \"Bobrovsky\"\npassword = \"hunter2\"\nquic_cert = \"/etc/chat/cert.pem\"\n";
+    std::fs::write(&file, toml).unwrap();
+    let settings = SettingsFile::at(file.clone()).load().unwrap();
+    assert_eq!(settings.servers.len(), 2);
+
+    // A password from an older config.toml is found for moving to the keychain, and then taken out of the file
+    let saved = SettingsFile::at(file.clone());
+    assert_eq!(saved.old_passwords(), [("work".to_string(), "hunter2".to_string())]);
+    saved.forget_password("work").unwrap();
+    assert!(saved.old_passwords().is_empty());
+    assert!(!std::fs::read_to_string(&file).unwrap().contains("hunter2"));
+    assert_eq!(saved.load().unwrap().servers, settings.servers);
+    assert_eq!(
+        settings.servers["work"],
+        Profile {
+            address: "quic://chat.work.example:8443".to_string(),
+            username: Some("Bobrovsky".to_string()),
+            quic_cert: Some("/etc/chat/cert.pem".into()),
+            proxy: None,
+        }
+    );
+
+    // /connect on its own lists them, picking one asks the client to go there
+    let mut h = Harness::with_settings(SettingsFile::at(file.clone()));
+    h.submit("/connect");
+    std::fs::remove_file(&file).unwrap();
+    assert!(h.shows("Saved servers"));
+    assert!(h.shows("chat.example.org:8082"));
+    assert!(h.shows("Bobrovsky @ quic://chat.work.example:8443"));
+    assert!(!h.shows("Another server"));
+    h.press(Key::Down);
+    h.press(Key::Enter);
+    assert!(!h.shows("Saved servers"));
+    h.submit("/connect  home ");
+    assert_eq!(h.sent(), ["/connect work", "/connect  home "]);
+    assert_eq!(ui::connect_to("/connect  home "), Some("home"));
+    assert_eq!(ui::connect_to("/connect"), None);
+
+    // Once the client is on the new server the window starts over there
+    h.show(ChatMessage::new("System", "You are talking in #dev", MessageType::RoomJoined).in_room("dev"));
+    h.show(ChatMessage::new("Luna", "from the old server", MessageType::UserMessage).in_room("dev"));
+    ui::switch_server(&mut h.runner, "Bobrovsky", "work");
+    h.render();
+    assert!(h.shows("Chatterer: Bobrovsky"));
+    assert!(h.shows("Connected to work as Bobrovsky"));
+    assert!(h.shows(" 1 #lobby "));
+    assert!(!h.shows("#dev") && !h.shows("from the old server"));
+
+    // At the start there's another server to pick, and nothing to cancel
+    let mut h = Harness::new();
+    let picked = Arc::new(Mutex::new(None));
+    ui::show_server_picker(&mut h.runner, &settings.servers, Some("Couldn't connect to home"), true, {
+        let picked = picked.clone();
+        move |_, name: Option<&str>| *picked.lock().unwrap() = Some(name.map(str::to_string))
+    });
+    h.render();
+    assert!(h.shows("Couldn't connect to home"));
+    assert!(h.shows("Another server...") && h.shows("Quit") && !h.shows("Cancel"));
+    for _ in 0..2 {
+        h.press(Key::Down);
+    }
+    h.press(Key::Enter);
+    assert_eq!(*picked.lock().unwrap(), Some(None));
+}
+
+// The OS keychain stand-in for the session tests: every entry made for the same name sees the same secret
+fn memory_keychain() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| keyring::set_default_credential_builder(Box::new(MemoryKeychain::default())));
+}
+
+#[derive(Default)]
+struct MemoryKeychain(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+struct MemoryEntry {
+    secrets: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    name: String,
+}
+
+impl CredentialBuilderApi for MemoryKeychain {
+    fn build(&self, _target: Option<&str>, service: &str, user: &str) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(MemoryEntry { secrets: self.0.clone(), name: format!("{}/{}", service, user) }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl CredentialApi for MemoryEntry {
+    fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+        self.secrets.lock().unwrap().insert(self.name.clone(), secret.to_vec());
+        Ok(())
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        self.secrets.lock().unwrap().get(&self.name).cloned().ok_or(keyring::Error::NoEntry)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        self.secrets.lock().unwrap().remove(&self.name).map(drop).ok_or(keyring::Error::NoEntry)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[test]
+fn session_tokens_move_from_sessions_json_into_the_keychain() {
+    memory_keychain();
+    let dir = std::env::temp_dir().join(format!("ui-test-sessions-{}", std::process::id()));
+    let file = dir.join("sessions.json");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&file, r#"{"ada@chat.example.org:8082": "old-token", "bob@chat.example.org:8082": "bobs-token"}"#).unwrap();
+
+    // The first load finds it in the file and moves it over, leaving the other user's token where it was
+    let sessions = Sessions::at(file.clone(), true);
+    assert_eq!(sessions.load_token("chat.example.org:8082", "Ada").as_deref(), Some("old-token"));
+    let left: HashMap<String, String> = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(left, HashMap::from([("bob@chat.example.org:8082".to_string(), "bobs-token".to_string())]));
+    let elsewhere = Sessions::at(dir.join("none.json"), true);
+    assert_eq!(elsewhere.load_token("chat.example.org:8082", "ada").as_deref(), Some("old-token"));
+
+    // New tokens only go in the keychain, and so do saved servers' passwords, apart from anyone's token
+    sessions.save_token("chat.example.org:8082", "ada", "new-token").unwrap();
+    sessions.save_password("chat.example.org:8082", "hunter2").unwrap();
+    assert_eq!(elsewhere.load_token("chat.example.org:8082", "ada").as_deref(), Some("new-token"));
+    assert_eq!(elsewhere.load_password("chat.example.org:8082").as_deref(), Some("hunter2"));
+    assert_eq!(elsewhere.load_password("chat.example.org:9000"), None);
+    let text = std::fs::read_to_string(&file).unwrap();
+    assert!(!text.contains("new-token") && !text.contains("hunter2"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn with_no_keyring_session_tokens_stay_in_a_private_sessions_file() {
+    memory_keychain();
+    let dir = std::env::temp_dir().join(format!("ui-test-no-keyring-{}", std::process::id()));
+    let file = dir.join("rust-chat").join("sessions.json");
+    let sessions = Sessions::at(file.clone(), false);
+    assert_eq!(sessions.load_token("chat.example.org:8082", "cleo"), None);
+
+    sessions.save_token("chat.example.org:8082", "Cleo", "file-token").unwrap();
+    assert_eq!(sessions.load_token("chat.example.org:8082", "cleo").as_deref(), Some("file-token"));
+    assert!(std::fs::read_to_string(&file).unwrap().contains("\"cleo@chat.example.org:8082\": \"file-token\""));
+    assert_eq!(Sessions::at(dir.join("none.json"), true).load_token("chat.example.org:8082", "cleo"), None);
+
+    // Only we can read it, also when an older version left it readable by others
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        sessions.save_token("chat.example.org:8082", "cleo", "newer-token").unwrap();
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unsent_messages_are_offered_one_by_one() {
+    let mut h = Harness::new();
+    let choices = Arc::new(Mutex::new(Vec::new()));
+    let offer = |h: &mut Harness| {
+        let choices = choices.clone();
+        ui::offer_unsent(&mut h.runner, vec!["first".to_string(), "second".to_string(), "third".to_string()], move |_, line, choice| {
+            choices.lock().unwrap().push((line.to_string(), choice));
+        });
+        h.render();
+    };
+    offer(&mut h);
+    assert!(h.shows("Unsent message 1 of 3"));
+    assert!(h.shows("first"));
+
+    // Offering again while the first round is up doesn't stack another dialog
+    offer(&mut h);
+    h.press(Key::Enter); // Send
+    assert!(h.shows("Unsent message 2 of 3"));
+    h.press(Key::Right);
+    h.press(Key::Enter); // Edit
+    assert!(h.shows("Unsent message 3 of 3"));
+    h.press(Key::Right);
+    h.press(Key::Right);
+    h.press(Key::Enter); // Discard
+    assert!(!h.shows("Unsent message"));
+
+    assert_eq!(
+        *choices.lock().unwrap(),
+        vec![
+            ("first".to_string(), UnsentChoice::Send),
+            ("second".to_string(), UnsentChoice::Edit),
+            ("third".to_string(), UnsentChoice::Discard),
+        ]
+    );
+    assert_eq!(h.input(), "second");
+}
+
+#[test]
+fn the_writer_reports_lines_that_went_out_and_ones_that_did_not() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        use tokio::io::AsyncBufReadExt;
+
+        let (reports, mut reported) = tokio::sync::mpsc::unbounded_channel();
+        let writer = Writer::spawn(move |report| {
+            let _ = reports.send(report);
+        });
+        let chat = |line: &str| Outgoing::Chat { line: line.to_string(), wire: format!("{} (signed)", line) };
+
+        // Nothing to send on yet: chat and pings fail out loud, quiet lines don't
+        writer.send(Outgoing::Quiet("/draft lobby hel".to_string()));
+        writer.send(chat("hello"));
+        writer.send(Outgoing::Now("/ping 1".to_string()));
+        assert!(matches!(reported.recv().await, Some(Report::Failed(Outgoing::Chat { line, .. }, e)) if line == "hello" && e.kind() == std::io::ErrorKind::NotConnected));
+        assert!(matches!(reported.recv().await, Some(Report::Failed(Outgoing::Now(_), _))));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let client = ChatClient::connect(&addr, Handshake { username: "alice".to_string(), ..Default::default() }).await.unwrap();
+        let (server_side, _) = listener.accept().await.unwrap();
+        let mut lines = tokio::io::BufReader::new(server_side).lines();
+        lines.next_line().await.unwrap(); // the handshake
+
+        writer.connected(client.sender());
+        writer.send(chat("hello again"));
+        assert!(matches!(reported.recv().await, Some(Report::Sent(Outgoing::Chat { line, .. })) if line == "hello again"));
+        assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("hello again (signed)"));
+
+        writer.disconnected();
+        writer.send(chat("gone"));
+        assert!(matches!(reported.recv().await, Some(Report::Failed(..))));
+
+        writer.connected(client.sender());
+        writer.close().await;
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    });
+}
+
+#[test]
+fn timing_out_for_idling_is_not_reconnected_from() {
+    let mut policy = ReconnectPolicy::default();
+    let mut timed_out = ChatMessage::new("System", "You timed out after 30 minutes without activity", MessageType::SystemNotification);
+    timed_out.error = Some(ErrorKind::IdleTimeout);
+    assert_eq!(policy.next_action(Some(&timed_out)), ReconnectAction::GiveUp("timed out for inactivity".to_string()));
+    // A connection that just dropped is tried again
+    assert!(matches!(policy.next_action(None), ReconnectAction::Retry(_)));
+}
+
+#[test]
+fn quit_stops_the_ui() {
+    let mut h = Harness::new();
+    h.submit("/quit");
+    assert!(!h.runner.is_running());
+}